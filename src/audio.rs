@@ -3,7 +3,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-//! Audio file decoding and OpenAL bindings.
+//! Audio file decoding, OpenAL bindings, and audio recording.
 //!
 //! The audio file decoding support is an abstraction over various libraries
 //! (currently [caf], [hound], and dr_mp3), usage of which should be confined to
@@ -14,6 +14,7 @@
 
 mod aac;
 mod ima4;
+pub mod recording;
 
 pub use ima4::decode_ima4;
 use touchHLE_dr_mp3_wrapper as dr_mp3;
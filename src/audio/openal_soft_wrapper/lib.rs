@@ -48,6 +48,7 @@ pub const ALC_FALSE: ALCboolean = 0;
 #[allow(dead_code)]
 pub const ALC_TRUE: ALCboolean = 1;
 
+pub const ALC_FREQUENCY: ALCenum = 0x1007;
 pub const ALC_DEVICE_SPECIFIER: ALCenum = 0x1005;
 
 extern "C" {
@@ -69,6 +70,19 @@ extern "C" {
     pub fn alcGetString(device: *mut ALCdevice, param: ALCenum) -> *const ALCchar;
 }
 
+// === alext.h ===
+
+// ALC_SOFT_loopback
+pub const ALC_FORMAT_CHANNELS_SOFT: ALCenum = 0x1990;
+pub const ALC_FORMAT_TYPE_SOFT: ALCenum = 0x1991;
+pub const ALC_SHORT_SOFT: ALCenum = 0x1402;
+pub const ALC_STEREO_SOFT: ALCenum = 0x1501;
+
+extern "C" {
+    pub fn alcLoopbackOpenDeviceSOFT(deviceName: *const ALCchar) -> *mut ALCdevice;
+    pub fn alcRenderSamplesSOFT(device: *mut ALCdevice, buffer: *mut ALCvoid, samples: ALCsizei);
+}
+
 // === al.h ===
 
 #[allow(dead_code)]
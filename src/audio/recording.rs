@@ -0,0 +1,128 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Recording the app's audio output to a WAV file (see `--record=`).
+//!
+//! While recording, OpenAL devices are opened using OpenAL Soft's loopback
+//! extension (`ALC_SOFT_loopback`) rather than being connected to a real audio
+//! output. Nothing is heard live; instead, the devices only produce samples
+//! when [AudioRecorder::render] asks for them, and these are mixed together
+//! and written to the file. This means the audio advances in lockstep with the
+//! recorded video (see [crate::frame_capture]), rather than in real time.
+
+use super::openal as al;
+use super::openal::alc_types::{ALCcontext, ALCdevice, ALCint};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+pub const SAMPLE_RATE: u32 = 44100;
+const CHANNELS: u16 = 2;
+
+pub struct AudioRecorder {
+    writer: hound::WavWriter<BufWriter<File>>,
+    /// Loopback devices that are currently open, whether they were opened by
+    /// the app or by touchHLE itself. The flag is set once a context has been
+    /// created, which sets the device's output format; until then, rendering
+    /// the device isn't safe.
+    devices: Vec<(*mut ALCdevice, bool)>,
+}
+impl AudioRecorder {
+    pub fn new(path: &Path) -> Result<AudioRecorder, String> {
+        let spec = hound::WavSpec {
+            channels: CHANNELS,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let writer = hound::WavWriter::create(path, spec)
+            .map_err(|e| format!("Couldn't create {}: {}", path.display(), e))?;
+        Ok(AudioRecorder {
+            writer,
+            devices: Vec::new(),
+        })
+    }
+
+    /// Mix `frames` sample frames of output from all the open devices and
+    /// append them to the file. If no device is open, silence is written.
+    pub fn render(&mut self, frames: u32) {
+        let sample_count = frames as usize * CHANNELS as usize;
+        let mut mix = vec![0i16; sample_count];
+        let mut buffer = vec![0i16; sample_count];
+        for &(device, _) in self.devices.iter().filter(|&&(_, has_context)| has_context) {
+            unsafe {
+                al::alcRenderSamplesSOFT(
+                    device,
+                    buffer.as_mut_ptr().cast(),
+                    frames.try_into().unwrap(),
+                )
+            };
+            for (mixed, &sample) in mix.iter_mut().zip(buffer.iter()) {
+                *mixed = mixed.saturating_add(sample);
+            }
+        }
+
+        // The app might exit without running destructors, so the WAV header
+        // needs to be kept up-to-date.
+        let result = mix
+            .into_iter()
+            .try_for_each(|sample| self.writer.write_sample(sample))
+            .and_then(|()| self.writer.flush());
+        if let Err(e) = result {
+            log!("Warning: Couldn't write recorded audio: {}", e);
+        }
+    }
+}
+
+/// Context attributes for loopback devices, see [create_context].
+static LOOPBACK_CONTEXT_ATTRIBUTES: [ALCint; 7] = [
+    al::ALC_FREQUENCY,
+    SAMPLE_RATE as ALCint,
+    al::ALC_FORMAT_CHANNELS_SOFT,
+    al::ALC_STEREO_SOFT,
+    al::ALC_FORMAT_TYPE_SOFT,
+    al::ALC_SHORT_SOFT,
+    0,
+];
+
+/// Open the default OpenAL device, or a loopback device if recording.
+pub fn open_device(recorder: Option<&mut AudioRecorder>) -> *mut ALCdevice {
+    let Some(recorder) = recorder else {
+        return unsafe { al::alcOpenDevice(std::ptr::null()) };
+    };
+    let device = unsafe { al::alcLoopbackOpenDeviceSOFT(std::ptr::null()) };
+    if !device.is_null() {
+        recorder.devices.push((device, false));
+    }
+    device
+}
+
+/// Create a context on a device opened by [open_device].
+pub fn create_context(
+    device: *mut ALCdevice,
+    recorder: Option<&mut AudioRecorder>,
+) -> *mut ALCcontext {
+    let loopback_device = recorder.and_then(|recorder| {
+        recorder
+            .devices
+            .iter_mut()
+            .find(|&&mut (other, _)| other == device)
+    });
+    let Some((_, has_context)) = loopback_device else {
+        return unsafe { al::alcCreateContext(device, std::ptr::null()) };
+    };
+    let context = unsafe { al::alcCreateContext(device, LOOPBACK_CONTEXT_ATTRIBUTES.as_ptr()) };
+    if !context.is_null() {
+        *has_context = true;
+    }
+    context
+}
+
+/// Must be called before closing a device opened by [open_device].
+pub fn forget_device(device: *mut ALCdevice, recorder: Option<&mut AudioRecorder>) {
+    if let Some(recorder) = recorder {
+        recorder.devices.retain(|&(other, _)| other != device);
+    }
+}
@@ -11,7 +11,10 @@
 //! For the moment, only ARMv6 has been tested.
 
 use crate::abi::GuestFunction;
-use crate::mem::{ConstPtr, GuestUSize, Mem, MutPtr, Ptr, SafeRead, SafeWrite};
+use crate::mem::{
+    guest_size_of, AccessKind, ConstPtr, GuestUSize, Mem, MemoryFault, MutPtr, Ptr, SafeRead,
+    SafeWrite,
+};
 
 // Import functions from C++
 use touchHLE_dynarmic_wrapper::*;
@@ -22,13 +25,29 @@ fn touchHLE_cpu_read_impl<T: SafeRead + Default>(
     mem: *mut touchHLE_Mem,
     addr: VAddr,
     error: *mut bool,
+    is_code: bool,
 ) -> T {
-    // If a panic occurs (probably due to a null-pointer access), we can't let
-    // it keep unwinding as it will hit non-Rust stack frames (dynarmic).
-    // Instead we catch the unwind and then tell the C++ code a problem occurred
-    // so it can immediately halt CPU execution and then panic itself, now
-    // with only Rust stack frames to worry about and with CPU state information
-    // available that's useful for debugging.
+    let mem = unsafe { &mut *mem.cast::<Mem>() };
+    // Accesses that violate the page protections (e.g. null-page accesses)
+    // are errors, which make the C++ code halt CPU execution. Failed
+    // instruction fetches are only reported if the instruction is executed.
+    let kind = if is_code {
+        AccessKind::Execute
+    } else {
+        AccessKind::Read
+    };
+    if !mem.check_access(addr, guest_size_of::<T>(), kind) {
+        unsafe {
+            error.write(true);
+        }
+        return T::default();
+    }
+
+    // If a panic occurs, we can't let it keep unwinding as it will hit non-Rust
+    // stack frames (dynarmic). Instead we catch the unwind and then tell the
+    // C++ code a problem occurred so it can immediately halt CPU execution and
+    // then panic itself, now with only Rust stack frames to worry about and
+    // with CPU state information available that's useful for debugging.
     //
     // TODO: Disable this in debug mode? This relies on dynarmic's
     // check_halt_on_memory_access option which surely has a significant
@@ -37,10 +56,18 @@ fn touchHLE_cpu_read_impl<T: SafeRead + Default>(
     // I'm not sure if this actually is unwind-safe, but considering
     // the emulator will crash anyway, maybe this is okay.
     let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-        let mem = unsafe { &mut *mem.cast::<Mem>() };
         let ptr: ConstPtr<T> = Ptr::from_bits(addr);
-        mem.read(ptr)
+        let value = mem.read(ptr);
+        // Instruction fetches happen when code is compiled, not when it's run,
+        // so it wouldn't make sense to check them.
+        if !is_code {
+            mem.check_watchpoints(addr, guest_size_of::<T>(), /* is_write: */ false);
+        }
+        value
     }));
+    if res.is_err() {
+        mem.record_fault(addr, kind);
+    }
     unsafe {
         error.write(res.is_err());
     }
@@ -48,31 +75,46 @@ fn touchHLE_cpu_read_impl<T: SafeRead + Default>(
 }
 
 fn touchHLE_cpu_write_impl<T: SafeWrite>(mem: *mut touchHLE_Mem, addr: VAddr, value: T) -> bool {
+    let mem = unsafe { &mut *mem.cast::<Mem>() };
+    if !mem.check_access(addr, guest_size_of::<T>(), AccessKind::Write) {
+        return true;
+    }
     // See comments above about catch_unwind
     let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-        let mem = unsafe { &mut *mem.cast::<Mem>() };
         let ptr: MutPtr<T> = Ptr::from_bits(addr);
-        mem.write(ptr, value)
+        mem.write(ptr, value);
+        mem.check_watchpoints(addr, guest_size_of::<T>(), /* is_write: */ true);
     }));
+    if res.is_err() {
+        mem.record_fault(addr, AccessKind::Write);
+    }
     res.is_err()
 }
 
 // Export functions for use by C++
 #[no_mangle]
 extern "C" fn touchHLE_cpu_read_u8(mem: *mut touchHLE_Mem, addr: VAddr, error: *mut bool) -> u8 {
-    touchHLE_cpu_read_impl(mem, addr, error)
+    touchHLE_cpu_read_impl(mem, addr, error, /* is_code: */ false)
 }
 #[no_mangle]
 extern "C" fn touchHLE_cpu_read_u16(mem: *mut touchHLE_Mem, addr: VAddr, error: *mut bool) -> u16 {
-    touchHLE_cpu_read_impl(mem, addr, error)
+    touchHLE_cpu_read_impl(mem, addr, error, /* is_code: */ false)
 }
 #[no_mangle]
 extern "C" fn touchHLE_cpu_read_u32(mem: *mut touchHLE_Mem, addr: VAddr, error: *mut bool) -> u32 {
-    touchHLE_cpu_read_impl(mem, addr, error)
+    touchHLE_cpu_read_impl(mem, addr, error, /* is_code: */ false)
 }
 #[no_mangle]
 extern "C" fn touchHLE_cpu_read_u64(mem: *mut touchHLE_Mem, addr: VAddr, error: *mut bool) -> u64 {
-    touchHLE_cpu_read_impl(mem, addr, error)
+    touchHLE_cpu_read_impl(mem, addr, error, /* is_code: */ false)
+}
+#[no_mangle]
+extern "C" fn touchHLE_cpu_read_code_u32(
+    mem: *mut touchHLE_Mem,
+    addr: VAddr,
+    error: *mut bool,
+) -> u32 {
+    touchHLE_cpu_read_impl(mem, addr, error, /* is_code: */ true)
 }
 #[no_mangle]
 extern "C" fn touchHLE_cpu_write_u8(mem: *mut touchHLE_Mem, addr: VAddr, value: u8) -> bool {
@@ -90,6 +132,11 @@ extern "C" fn touchHLE_cpu_write_u32(mem: *mut touchHLE_Mem, addr: VAddr, value:
 extern "C" fn touchHLE_cpu_write_u64(mem: *mut touchHLE_Mem, addr: VAddr, value: u64) -> bool {
     touchHLE_cpu_write_impl(mem, addr, value)
 }
+#[no_mangle]
+extern "C" fn touchHLE_cpu_watchpoint_hit(mem: *mut touchHLE_Mem) -> bool {
+    let mem = unsafe { &*mem.cast::<Mem>() };
+    mem.has_watchpoint_hit()
+}
 
 pub struct Cpu {
     dynarmic_wrapper: *mut touchHLE_DynarmicWrapper,
@@ -136,12 +183,16 @@ pub enum CpuState {
 /// A reason that can cause CPU execution to be interrupted.
 #[derive(Debug)]
 pub enum CpuError {
-    /// Memory error during execution (probably a null page access).
-    MemoryError,
+    /// Memory error during execution, e.g. a null page access or a write to a
+    /// read-only page (see [Mem::set_protection]).
+    MemoryError(MemoryFault),
     /// Undefined instruction (perhaps from a GDB software breakpoint).
     UndefinedInstruction,
     /// Breakpoint (`bkpt` instruction).
     Breakpoint,
+    /// Data watchpoint hit (see [Mem::add_watchpoint]). This happens after the
+    /// access has completed.
+    Watchpoint,
 }
 
 impl Cpu {
@@ -163,7 +214,7 @@ impl Cpu {
     /// is provided, direct memory access is enabled, and the CPU instance
     /// becomes bound to that [Mem] instance (subsequent calls must use the same
     /// one).
-    pub fn new(direct_memory_access: Option<&mut Mem>) -> Cpu {
+    pub fn new(mut direct_memory_access: Option<&mut Mem>) -> Cpu {
         // Null page count is in pages rather than bytes. Mem ensures it is
         // page aligned.
         let null_page_count: usize = direct_memory_access
@@ -176,15 +227,22 @@ impl Cpu {
         // ensure we only execute the CPU while holding a &mut on the Mem object
         // to which that pointer belongs.
         let direct_memory_access_ptr = direct_memory_access
+            .as_mut()
             .map_or(std::ptr::null_mut(), |mem| unsafe {
                 mem.direct_memory_access_ptr()
             });
         let dynarmic_wrapper =
             unsafe { touchHLE_DynarmicWrapper_new(direct_memory_access_ptr, null_page_count) };
-        Cpu {
+        let mut cpu = Cpu {
             dynarmic_wrapper,
             direct_memory_access_ptr,
+        };
+        // Pages that aren't readable and writable (e.g. the app's __TEXT
+        // segment) have to use the slow path so the protection is checked.
+        if let Some(mem) = direct_memory_access {
+            cpu.update_page_slow_paths(mem, 0..=(Mem::PAGE_COUNT - 1));
         }
+        cpu
     }
 
     pub fn regs(&self) -> &[u32; 16] {
@@ -200,6 +258,21 @@ impl Cpu {
         }
     }
 
+    /// The VFP/NEON extension registers. ARMv6 only has VFPv2, which uses the
+    /// first 32 of these (`s0`–`s31`, which alias `d0`–`d15`).
+    pub fn ext_regs(&self) -> &[u32; 64] {
+        unsafe {
+            let ptr = touchHLE_DynarmicWrapper_ext_regs_const(self.dynarmic_wrapper);
+            &*(ptr as *const [u32; 64])
+        }
+    }
+    pub fn ext_regs_mut(&mut self) -> &mut [u32; 64] {
+        unsafe {
+            let ptr = touchHLE_DynarmicWrapper_ext_regs_mut(self.dynarmic_wrapper);
+            &mut *(ptr as *mut [u32; 64])
+        }
+    }
+
     pub fn dump_regs(&self) {
         let regs = self.regs();
         for row in 0..4 {
@@ -228,6 +301,13 @@ impl Cpu {
         unsafe { touchHLE_DynarmicWrapper_set_cpsr(self.dynarmic_wrapper, cpsr) }
     }
 
+    pub fn fpscr(&self) -> u32 {
+        unsafe { touchHLE_DynarmicWrapper_fpscr(self.dynarmic_wrapper) }
+    }
+    pub fn set_fpscr(&mut self, fpscr: u32) {
+        unsafe { touchHLE_DynarmicWrapper_set_fpscr(self.dynarmic_wrapper, fpscr) }
+    }
+
     /// Swap the current state of the CPU (registers etc) with the state stored
     /// in the context object.
     pub fn swap_context(&mut self, context: &mut CpuContext) {
@@ -272,6 +352,24 @@ impl Cpu {
         }
     }
 
+    /// Make memory accesses to a page always go through the memory callbacks
+    /// (the "slow path"), rather than directly accessing memory, or undo that.
+    /// This is needed for watchpoints (see [Mem::add_watchpoint]) and page
+    /// protections (see [Mem::set_protection]). The null segment always uses
+    /// the slow path regardless.
+    fn set_page_slow_path(&mut self, page: VAddr, slow: bool) {
+        unsafe { touchHLE_DynarmicWrapper_set_page_slow_path(self.dynarmic_wrapper, page, slow) }
+    }
+
+    /// Update which of a range of pages use the slow path (see
+    /// [Self::set_page_slow_path]), after their watchpoints or protections
+    /// have changed.
+    pub fn update_page_slow_paths(&mut self, mem: &Mem, pages: std::ops::RangeInclusive<VAddr>) {
+        for page in pages {
+            self.set_page_slow_path(page, mem.page_needs_slow_path(page));
+        }
+    }
+
     /// Start CPU execution.
     ///
     /// If `ticks` is [Some], it is used as an abstract time limit. The value
@@ -298,10 +396,11 @@ impl Cpu {
         };
         match res {
             -1 => CpuState::Normal,
-            -2 => CpuState::Error(CpuError::MemoryError),
+            -2 => CpuState::Error(CpuError::MemoryError(mem.take_fault().unwrap())),
             -3 => CpuState::Error(CpuError::UndefinedInstruction),
             -4 => CpuState::Error(CpuError::Breakpoint),
-            _ if res < -4 => panic!("Unexpected CPU execution result"),
+            -5 => CpuState::Error(CpuError::Watchpoint),
+            _ if res < -5 => panic!("Unexpected CPU execution result"),
             svc => CpuState::Svc(svc as u32),
         }
     }
@@ -21,15 +21,19 @@ std::uint8_t touchHLE_cpu_read_u8(touchHLE_Mem *mem, VAddr addr, bool *error);
 std::uint16_t touchHLE_cpu_read_u16(touchHLE_Mem *mem, VAddr addr, bool *error);
 std::uint32_t touchHLE_cpu_read_u32(touchHLE_Mem *mem, VAddr addr, bool *error);
 std::uint64_t touchHLE_cpu_read_u64(touchHLE_Mem *mem, VAddr addr, bool *error);
+std::uint32_t touchHLE_cpu_read_code_u32(touchHLE_Mem *mem, VAddr addr,
+                                         bool *error);
 bool touchHLE_cpu_write_u8(touchHLE_Mem *mem, VAddr addr, std::uint8_t value);
 bool touchHLE_cpu_write_u16(touchHLE_Mem *mem, VAddr addr, std::uint16_t value);
 bool touchHLE_cpu_write_u32(touchHLE_Mem *mem, VAddr addr, std::uint32_t value);
 bool touchHLE_cpu_write_u64(touchHLE_Mem *mem, VAddr addr, std::uint64_t value);
+bool touchHLE_cpu_watchpoint_hit(touchHLE_Mem *mem);
 }
 
 const auto HaltReasonSvc = Dynarmic::HaltReason::UserDefined1;
 const auto HaltReasonUndefinedInstruction = Dynarmic::HaltReason::UserDefined2;
 const auto HaltReasonBreakpoint = Dynarmic::HaltReason::UserDefined3;
+const auto HaltReasonWatchpoint = Dynarmic::HaltReason::UserDefined4;
 
 class Environment final : public Dynarmic::A32::UserCallbacks {
 public:
@@ -39,12 +43,21 @@ public:
   uint32_t halting_svc;
 
 private:
+  // Memory accesses that go through these callbacks are checked against the
+  // watchpoints (see set_page_slow_path()). The CPU halts after the access.
+  void check_watchpoint() {
+    if (touchHLE_cpu_watchpoint_hit(mem)) {
+      cpu->HaltExecution(HaltReasonWatchpoint);
+    }
+  }
+
   std::uint8_t MemoryRead8(VAddr vaddr) override {
     bool error;
     auto value = touchHLE_cpu_read_u8(mem, vaddr, &error);
     if (error) {
       cpu->HaltExecution(Dynarmic::HaltReason::MemoryAbort);
     }
+    check_watchpoint();
     return value;
   }
   std::uint16_t MemoryRead16(VAddr vaddr) override {
@@ -53,6 +66,7 @@ private:
     if (error) {
       cpu->HaltExecution(Dynarmic::HaltReason::MemoryAbort);
     }
+    check_watchpoint();
     return value;
   }
   std::uint32_t MemoryRead32(VAddr vaddr) override {
@@ -61,6 +75,7 @@ private:
     if (error) {
       cpu->HaltExecution(Dynarmic::HaltReason::MemoryAbort);
     }
+    check_watchpoint();
     return value;
   }
   std::uint64_t MemoryRead64(VAddr vaddr) override {
@@ -69,12 +84,13 @@ private:
     if (error) {
       cpu->HaltExecution(Dynarmic::HaltReason::MemoryAbort);
     }
+    check_watchpoint();
     return value;
   }
 
   std::optional<std::uint32_t> MemoryReadCode(VAddr vaddr) override {
     bool error;
-    auto value = touchHLE_cpu_read_u32(mem, vaddr, &error);
+    auto value = touchHLE_cpu_read_code_u32(mem, vaddr, &error);
     if (error) {
       return std::nullopt;
     } else {
@@ -86,21 +102,25 @@ private:
     if (touchHLE_cpu_write_u8(mem, vaddr, value)) {
       cpu->HaltExecution(Dynarmic::HaltReason::MemoryAbort);
     }
+    check_watchpoint();
   }
   void MemoryWrite16(VAddr vaddr, std::uint16_t value) override {
     if (touchHLE_cpu_write_u16(mem, vaddr, value)) {
       cpu->HaltExecution(Dynarmic::HaltReason::MemoryAbort);
     }
+    check_watchpoint();
   }
   void MemoryWrite32(VAddr vaddr, std::uint32_t value) override {
     if (touchHLE_cpu_write_u32(mem, vaddr, value)) {
       cpu->HaltExecution(Dynarmic::HaltReason::MemoryAbort);
     }
+    check_watchpoint();
   }
   void MemoryWrite64(VAddr vaddr, std::uint64_t value) override {
     if (touchHLE_cpu_write_u64(mem, vaddr, value)) {
       cpu->HaltExecution(Dynarmic::HaltReason::MemoryAbort);
     }
+    check_watchpoint();
   }
 
   void InterpreterFallback(std::uint32_t, size_t) override {
@@ -139,9 +159,13 @@ class DynarmicWrapper {
   std::unique_ptr<Dynarmic::A32::Jit> cpu;
   std::array<std::uint8_t *, Dynarmic::A32::UserConfig::NUM_PAGE_TABLE_ENTRIES>
       page_table;
+  std::uint8_t *direct_memory_access_ptr;
+  size_t null_page_count;
 
 public:
-  DynarmicWrapper(void *direct_memory_access_ptr, size_t null_page_count) {
+  DynarmicWrapper(void *direct_memory_access_ptr, size_t null_page_count)
+      : direct_memory_access_ptr((std::uint8_t *)direct_memory_access_ptr),
+        null_page_count(null_page_count) {
     Dynarmic::A32::UserConfig user_config;
     user_config.callbacks = &env;
     // TODO: only do this in debug builds? it's probably expensive
@@ -174,9 +198,23 @@ public:
   const std::uint32_t *regs() const { return &cpu->Regs().front(); }
   std::uint32_t *regs() { return &cpu->Regs().front(); }
 
+  const std::uint32_t *ext_regs() const { return &cpu->ExtRegs().front(); }
+  std::uint32_t *ext_regs() { return &cpu->ExtRegs().front(); }
+
   std::uint32_t cpsr() const { return cpu->Cpsr(); }
   void set_cpsr(std::uint32_t cpsr) { cpu->SetCpsr(cpsr); }
 
+  std::uint32_t fpscr() const { return cpu->Fpscr(); }
+  void set_fpscr(std::uint32_t fpscr) { cpu->SetFpscr(fpscr); }
+
+  // Pages with a null page table entry fall back to the memory callbacks.
+  void set_page_slow_path(VAddr page, bool slow) {
+    if (!direct_memory_access_ptr || page < null_page_count) {
+      return;
+    }
+    page_table.at(page) = slow ? nullptr : direct_memory_access_ptr;
+  }
+
   void invalidate_cache_range(VAddr start, std::uint32_t size) {
     cpu->InvalidateCacheRange(start, size);
   }
@@ -205,6 +243,8 @@ public:
       res = -3;
     } else if (Dynarmic::Has(hr, HaltReasonBreakpoint)) {
       res = -4;
+    } else if (Dynarmic::Has(hr, HaltReasonWatchpoint)) {
+      res = -5;
     } else if (Dynarmic::Has(hr, HaltReasonSvc)) {
       res = std::int32_t(env.halting_svc);
     } else {
@@ -235,6 +275,14 @@ std::uint32_t *touchHLE_DynarmicWrapper_regs_mut(DynarmicWrapper *cpu) {
   return cpu->regs();
 }
 
+const std::uint32_t *
+touchHLE_DynarmicWrapper_ext_regs_const(const DynarmicWrapper *cpu) {
+  return cpu->ext_regs();
+}
+std::uint32_t *touchHLE_DynarmicWrapper_ext_regs_mut(DynarmicWrapper *cpu) {
+  return cpu->ext_regs();
+}
+
 std::uint32_t touchHLE_DynarmicWrapper_cpsr(const DynarmicWrapper *cpu) {
   return cpu->cpsr();
 }
@@ -243,6 +291,14 @@ void touchHLE_DynarmicWrapper_set_cpsr(DynarmicWrapper *cpu,
   cpu->set_cpsr(cpsr);
 }
 
+std::uint32_t touchHLE_DynarmicWrapper_fpscr(const DynarmicWrapper *cpu) {
+  return cpu->fpscr();
+}
+void touchHLE_DynarmicWrapper_set_fpscr(DynarmicWrapper *cpu,
+                                        std::uint32_t fpscr) {
+  cpu->set_fpscr(fpscr);
+}
+
 void touchHLE_DynarmicWrapper_swap_context(DynarmicWrapper *cpu,
                                            void *context) {
   cpu->swap_context(context);
@@ -254,6 +310,11 @@ void touchHLE_DynarmicWrapper_invalidate_cache_range(DynarmicWrapper *cpu,
   cpu->invalidate_cache_range(start, size);
 }
 
+void touchHLE_DynarmicWrapper_set_page_slow_path(DynarmicWrapper *cpu,
+                                                 VAddr page, bool slow) {
+  cpu->set_page_slow_path(page, slow);
+}
+
 std::int32_t touchHLE_DynarmicWrapper_run_or_step(DynarmicWrapper *cpu,
                                                   touchHLE_Mem *mem,
                                                   std::uint64_t *ticks) {
@@ -0,0 +1,148 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Profiles of the devices touchHLE can pretend to be.
+//!
+//! Apps can find out what device they are running on in various ways (e.g.
+//! `sysctlbyname("hw.machine", ...)`, `uname()` or `-[UIDevice model]`), and
+//! some use that to decide things like graphics quality, so all of these must
+//! use the same [DeviceProfile], which is selected with the `--device=` option.
+
+/// Description of a device model and the OS version it runs.
+#[derive(Debug)]
+pub struct DeviceProfile {
+    /// Model identifier, as in `hw.machine`, e.g. `iPhone1,1`. This is also
+    /// the name used for the `--device=` option.
+    pub machine: &'static str,
+    /// Internal board name, as in `hw.model`, e.g. `M68AP`.
+    pub board: &'static str,
+    /// The name returned by `-[UIDevice model]`, e.g. `iPhone`.
+    pub model: &'static str,
+    /// The iPhone OS version, e.g. `2.0`.
+    pub os_version: &'static str,
+    /// The iPhone OS build number, as in `kern.osversion`, e.g. `5A347`.
+    pub os_build: &'static str,
+    /// The Darwin kernel version, as in `kern.osrelease`, e.g. `9.4.1`.
+    pub darwin_release: &'static str,
+    /// Amount of RAM in bytes.
+    pub memory_size: u64,
+    pub cpu_count: u32,
+    /// CPU clock frequency in Hz.
+    pub cpu_frequency: u64,
+    /// Screen size in points, in portrait orientation.
+    pub screen_size: (u32, u32),
+    /// The value returned by `-[UIDevice uniqueIdentifier]`. Real devices have
+    /// a unique value, but it is the same for everyone using touchHLE.
+    pub unique_identifier: &'static str,
+}
+
+const MIB: u64 = 1024 * 1024;
+const MHZ: u64 = 1000 * 1000;
+
+/// All the supported devices. The first is the default.
+pub const DEVICE_PROFILES: &[DeviceProfile] = &[
+    DeviceProfile {
+        machine: "iPhone1,1",
+        board: "M68AP",
+        model: "iPhone",
+        os_version: "2.0",
+        os_build: "5A347",
+        darwin_release: "9.4.1",
+        memory_size: 128 * MIB,
+        cpu_count: 1,
+        cpu_frequency: 412 * MHZ,
+        screen_size: (320, 480),
+        unique_identifier: "7f6a1f0b3a2c4e0d9b8e5c1a2d3f4b5c6e7d8a90",
+    },
+    DeviceProfile {
+        machine: "iPod1,1",
+        board: "N45AP",
+        model: "iPod touch",
+        os_version: "2.0",
+        os_build: "5A347",
+        darwin_release: "9.4.1",
+        memory_size: 128 * MIB,
+        cpu_count: 1,
+        cpu_frequency: 412 * MHZ,
+        screen_size: (320, 480),
+        unique_identifier: "2c9e4d7a1b3f5e6c8d0a9b2e4f6a8c1d3e5f7a92",
+    },
+    DeviceProfile {
+        machine: "iPhone1,2",
+        board: "N82AP",
+        model: "iPhone",
+        os_version: "2.0",
+        os_build: "5A347",
+        darwin_release: "9.4.1",
+        memory_size: 128 * MIB,
+        cpu_count: 1,
+        cpu_frequency: 412 * MHZ,
+        screen_size: (320, 480),
+        unique_identifier: "5d1b8e3f7a9c2e4d6f8a0b1c3e5d7f9a2b4c6e84",
+    },
+    DeviceProfile {
+        machine: "iPod2,1",
+        board: "N72AP",
+        model: "iPod touch",
+        os_version: "2.1.1",
+        os_build: "5F138",
+        darwin_release: "9.4.1",
+        memory_size: 128 * MIB,
+        cpu_count: 1,
+        cpu_frequency: 533 * MHZ,
+        screen_size: (320, 480),
+        unique_identifier: "9a3c5e7f1d2b4a6c8e0f2d4b6a8c0e1f3d5b7a96",
+    },
+    DeviceProfile {
+        machine: "iPhone2,1",
+        board: "N88AP",
+        model: "iPhone",
+        os_version: "3.0",
+        os_build: "7A341",
+        darwin_release: "10.0.0d3",
+        memory_size: 256 * MIB,
+        cpu_count: 1,
+        cpu_frequency: 600 * MHZ,
+        screen_size: (320, 480),
+        unique_identifier: "c4e6a8b0d2f4a6c8e0b2d4f6a8c0e2b4d6f8a0c2",
+    },
+    DeviceProfile {
+        machine: "iPod3,1",
+        board: "N18AP",
+        model: "iPod touch",
+        os_version: "3.1.1",
+        os_build: "7C145",
+        darwin_release: "10.0.0d3",
+        memory_size: 256 * MIB,
+        cpu_count: 1,
+        cpu_frequency: 600 * MHZ,
+        screen_size: (320, 480),
+        unique_identifier: "e1d3c5b7a9f0e2d4c6b8a0f1e3d5c7b9a1f3e5d7",
+    },
+];
+
+impl DeviceProfile {
+    /// Look up a profile by the name used for the `--device=` option (the
+    /// model identifier). Returns [Err] if the name is not recognized.
+    pub fn from_short_name(name: &str) -> Result<&'static Self, ()> {
+        DEVICE_PROFILES
+            .iter()
+            .find(|profile| profile.machine.eq_ignore_ascii_case(name))
+            .ok_or(())
+    }
+
+    /// The host name, as in `kern.hostname` and `uname()`.
+    pub fn host_name(&self) -> String {
+        self.model.replace(' ', "-")
+    }
+
+    /// The kernel version string, as in `kern.version` and `uname()`.
+    pub fn darwin_version(&self) -> String {
+        format!(
+            "Darwin Kernel Version {}: touchHLE; root:xnu/RELEASE_ARM_{}",
+            self.darwin_release, self.board
+        )
+    }
+}
@@ -29,6 +29,7 @@ use crate::frameworks::foundation::ns_string;
 use crate::mach_o::{MachO, SectionType};
 use crate::mem::{ConstVoidPtr, GuestUSize, Mem, MutPtr, Ptr};
 use crate::objc::{nil, ObjC};
+use crate::save_state::{StateReader, StateWriter};
 use crate::Environment;
 use std::collections::HashMap;
 
@@ -130,6 +131,34 @@ pub enum HostConstant {
 /// See also [FunctionExports], [crate::objc::ClassExports].
 pub type ConstantExports = &'static [(&'static str, HostConstant)];
 
+/// Like [search_lists], but for lists grouped by the library or framework they
+/// belong to, e.g. [function_lists::FUNCTION_LISTS]. The name of the library
+/// or framework is returned too.
+pub fn search_grouped_lists<T>(
+    groups: &'static [(&'static str, &'static [&'static [(&'static str, T)]])],
+    symbol: &str,
+) -> Option<(&'static str, &'static (&'static str, T))> {
+    groups
+        .iter()
+        .find_map(|&(group, lists)| search_lists(lists, symbol).map(|entry| (group, entry)))
+}
+
+/// Get the name of the library or framework a host function belongs to.
+pub fn host_function_framework(symbol: &str) -> Option<&'static str> {
+    search_grouped_lists(function_lists::FUNCTION_LISTS, symbol).map(|(framework, _)| framework)
+}
+
+fn search_function_lists(symbol: &str) -> Option<&'static (&'static str, HostFunction)> {
+    search_grouped_lists(function_lists::FUNCTION_LISTS, symbol).map(|(_, entry)| entry)
+}
+
+/// Like [search_function_lists], but also finds functions that only touchHLE
+/// itself creates guest functions for.
+fn search_all_function_lists(symbol: &str) -> Option<&'static (&'static str, HostFunction)> {
+    search_function_lists(symbol)
+        .or_else(|| search_lists(function_lists::PRIVATE_FUNCTION_LISTS, symbol))
+}
+
 /// Helper for working with symbol lists in the style of [FunctionExports].
 pub fn search_lists<T>(
     lists: &'static [&'static [(&'static str, T)]],
@@ -175,6 +204,7 @@ pub struct Dyld {
     linked_host_functions: Vec<(&'static str, HostFunction)>,
     return_to_host_routine: Option<GuestFunction>,
     thread_exit_routine: Option<GuestFunction>,
+    signal_return_routine: Option<GuestFunction>,
     constants_to_link_later: Vec<(MutPtr<ConstVoidPtr>, &'static HostConstant)>,
     non_lazy_host_functions: HashMap<&'static str, GuestFunction>,
 }
@@ -186,9 +216,12 @@ impl Dyld {
     pub const SVC_THREAD_EXIT: u32 = 1;
     /// We reserve this SVC ID for the special return-to-host routine.
     pub const SVC_RETURN_TO_HOST: u32 = 2;
+    /// We reserve this SVC ID for the routine signal handlers return to (see
+    /// [crate::libc::signal]).
+    pub const SVC_SIGNAL_RETURN: u32 = 3;
     /// The range of SVC IDs `SVC_LINKED_FUNCTIONS_BASE..` is used to reference
     /// [Self::linked_host_functions] entries.
-    pub const SVC_LINKED_FUNCTIONS_BASE: u32 = Self::SVC_RETURN_TO_HOST + 1;
+    pub const SVC_LINKED_FUNCTIONS_BASE: u32 = Self::SVC_SIGNAL_RETURN + 1;
 
     const SYMBOL_STUB_INSTRUCTIONS: [u32; 2] = [0xe59fc000, 0xe59cf000];
     const PIC_SYMBOL_STUB_INSTRUCTIONS: [u32; 3] = [0xe59fc004, 0xe08fc00c, 0xe59cf000];
@@ -198,6 +231,7 @@ impl Dyld {
             linked_host_functions: Vec::new(),
             return_to_host_routine: None,
             thread_exit_routine: None,
+            signal_return_routine: None,
             constants_to_link_later: Vec::new(),
             non_lazy_host_functions: HashMap::new(),
         }
@@ -211,14 +245,114 @@ impl Dyld {
         self.thread_exit_routine.unwrap()
     }
 
+    pub fn signal_return_routine(&self) -> GuestFunction {
+        self.signal_return_routine.unwrap()
+    }
+
+    /// Host functions are saved by name, so loading a state can only work with
+    /// the same version of touchHLE.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        let Dyld {
+            linked_host_functions,
+            return_to_host_routine,
+            thread_exit_routine,
+            signal_return_routine,
+            constants_to_link_later,
+            non_lazy_host_functions,
+        } = self;
+        // Late linking happens before the app runs any code.
+        assert!(constants_to_link_later.is_empty());
+
+        let mut symbol_name = |symbol: &'static str| {
+            if search_all_function_lists(symbol).is_none() {
+                writer.unsupported(format!("host function {}", symbol));
+            }
+            symbol.to_string()
+        };
+        let linked_host_functions: Vec<String> = linked_host_functions
+            .iter()
+            .map(|&(symbol, _)| symbol_name(symbol))
+            .collect();
+        let non_lazy_host_functions: Vec<(String, GuestFunction)> = non_lazy_host_functions
+            .iter()
+            .map(|(&symbol, &function)| (symbol_name(symbol), function))
+            .collect();
+
+        writer.write(&linked_host_functions);
+        writer.write(return_to_host_routine);
+        writer.write(thread_exit_routine);
+        writer.write(signal_return_routine);
+        writer.write(&non_lazy_host_functions);
+    }
+
+    pub fn load_state(reader: &mut StateReader) -> Result<Dyld, String> {
+        let find = |symbol: &str| {
+            search_all_function_lists(symbol)
+                .ok_or_else(|| format!("Save state refers to unknown host function {}", symbol))
+        };
+        let linked_host_functions: Vec<String> = reader.read()?;
+        let linked_host_functions = linked_host_functions
+            .iter()
+            .map(|symbol| find(symbol).copied())
+            .collect::<Result<_, _>>()?;
+        let return_to_host_routine = reader.read()?;
+        let thread_exit_routine = reader.read()?;
+        let signal_return_routine = reader.read()?;
+        let non_lazy_host_functions: Vec<(String, GuestFunction)> = reader.read()?;
+        let non_lazy_host_functions = non_lazy_host_functions
+            .iter()
+            .map(|(symbol, function)| find(symbol).map(|&(symbol, _)| (symbol, *function)))
+            .collect::<Result<_, _>>()?;
+        Ok(Dyld {
+            linked_host_functions,
+            return_to_host_routine,
+            thread_exit_routine,
+            signal_return_routine,
+            constants_to_link_later: Vec::new(),
+            non_lazy_host_functions,
+        })
+    }
+
+    /// Get the guest addresses and symbol names of host functions the app can
+    /// call, sorted by address. These are the lazy-linking stubs (see
+    /// [Self::setup_lazy_linking]) of symbols that have host implementations,
+    /// whether or not they've been linked yet, plus any functions linked
+    /// non-lazily. This is for debugging purposes.
+    pub fn host_function_symbols(&self, bins: &[MachO]) -> Vec<(u32, &'static str)> {
+        let mut symbols = Vec::new();
+        for stubs in bins
+            .iter()
+            .flat_map(|bin| bin.get_section(SectionType::SymbolStubs))
+        {
+            let info = stubs.dyld_indirect_symbol_info.as_ref().unwrap();
+            for (i, symbol) in info.indirect_undef_symbols.iter().enumerate() {
+                let Some(symbol) = symbol.as_deref() else {
+                    continue;
+                };
+                if let Some(&(symbol, _)) = search_function_lists(symbol) {
+                    let i: u32 = i.try_into().unwrap();
+                    symbols.push((stubs.addr + i * info.entry_size, symbol));
+                }
+            }
+        }
+        for (&symbol, &function) in self.non_lazy_host_functions.iter() {
+            symbols.push((function.addr_without_thumb_bit(), symbol));
+        }
+        symbols.sort();
+        symbols
+    }
+
     /// Do linking-related tasks that need doing right after loading the
     /// binaries.
     pub fn do_initial_linking(&mut self, bins: &[MachO], mem: &mut Mem, objc: &mut ObjC) {
         assert!(self.return_to_host_routine.is_none());
         assert!(self.thread_exit_routine.is_none());
+        assert!(self.signal_return_routine.is_none());
         self.return_to_host_routine =
             Some(write_return_to_host_routine(mem, Self::SVC_RETURN_TO_HOST));
         self.thread_exit_routine = Some(write_return_to_host_routine(mem, Self::SVC_THREAD_EXIT));
+        self.signal_return_routine =
+            Some(write_return_to_host_routine(mem, Self::SVC_SIGNAL_RETURN));
 
         // Currently assuming only the app binary contains Objective-C things.
 
@@ -243,9 +377,12 @@ impl Dyld {
     pub fn do_initial_linking_with_no_bins(&mut self, mem: &mut Mem, objc: &mut ObjC) {
         assert!(self.return_to_host_routine.is_none());
         assert!(self.thread_exit_routine.is_none());
+        assert!(self.signal_return_routine.is_none());
         self.return_to_host_routine =
             Some(write_return_to_host_routine(mem, Self::SVC_RETURN_TO_HOST));
         self.thread_exit_routine = Some(write_return_to_host_routine(mem, Self::SVC_THREAD_EXIT));
+        self.signal_return_routine =
+            Some(write_return_to_host_routine(mem, Self::SVC_SIGNAL_RETURN));
 
         objc.register_host_selectors(mem);
     }
@@ -391,7 +528,7 @@ impl Dyld {
                 }
             }
 
-            if let Some((symbol, _)) = search_lists(function_lists::FUNCTION_LISTS, symbol) {
+            if let Some((symbol, _)) = search_function_lists(symbol) {
                 // We want the same symbol name to always point to the same
                 // function. It could point to a specific stub entry, but it's
                 // easier to just create a new function and point all the stub
@@ -452,8 +589,8 @@ impl Dyld {
     }
 
     /// Return a host function that can be called to handle an SVC instruction
-    /// encountered during CPU emulation. If `None` is returned, the execution
-    /// needs to resume at `svc_pc`.
+    /// encountered during CPU emulation, and its symbol name. If `None` is
+    /// returned, the execution needs to resume at `svc_pc`.
     pub fn get_svc_handler(
         &mut self,
         bins: &[MachO],
@@ -461,10 +598,12 @@ impl Dyld {
         cpu: &mut Cpu,
         svc_pc: u32,
         svc: u32,
-    ) -> Option<HostFunction> {
+    ) -> Option<(&'static str, HostFunction)> {
         match svc {
             Self::SVC_LAZY_LINK => self.do_lazy_link(bins, mem, cpu, svc_pc),
-            Self::SVC_THREAD_EXIT | Self::SVC_RETURN_TO_HOST => unreachable!(), // don't handle here
+            Self::SVC_THREAD_EXIT | Self::SVC_RETURN_TO_HOST | Self::SVC_SIGNAL_RETURN => {
+                unreachable!() // don't handle here
+            }
             Self::SVC_LINKED_FUNCTIONS_BASE.. => {
                 let f = self
                     .linked_host_functions
@@ -473,7 +612,7 @@ impl Dyld {
                     panic!("Unexpected SVC #{} at {:#x}", svc, svc_pc);
                 };
                 log_dbg!("Call to host function, already linked: {}", symbol);
-                Some(f)
+                Some((symbol, f))
             }
         }
     }
@@ -484,7 +623,7 @@ impl Dyld {
         mem: &mut Mem,
         cpu: &mut Cpu,
         svc_pc: u32,
-    ) -> Option<HostFunction> {
+    ) -> Option<(&'static str, HostFunction)> {
         // Links by restoring the original stub function, then updating
         // __la_symbol_ptr to the appropriate function.
         fn link_by_restoring_stub(
@@ -560,7 +699,7 @@ impl Dyld {
             return None;
         }
 
-        if let Some(&(symbol, f)) = search_lists(function_lists::FUNCTION_LISTS, symbol) {
+        if let Some(&(symbol, f)) = search_function_lists(symbol) {
             // Allocate an SVC ID for this host function
             let idx: u32 = self.linked_host_functions.len().try_into().unwrap();
             let svc = idx + Self::SVC_LINKED_FUNCTIONS_BASE;
@@ -581,7 +720,7 @@ impl Dyld {
 
             // Return the host function so that we can call it now that we're
             // done.
-            return Some(f);
+            return Some((symbol, f));
         }
 
         for dylib in bins.iter() {
@@ -631,7 +770,7 @@ impl Dyld {
         mem: &mut Mem,
         symbol: &str,
     ) -> Result<GuestFunction, ()> {
-        let &(symbol, f) = search_lists(function_lists::FUNCTION_LISTS, symbol).ok_or(())?;
+        let &(symbol, f) = search_function_lists(symbol).ok_or(())?;
         if let Some(&cached_fn) = self.non_lazy_host_functions.get(symbol) {
             return Ok(cached_fn);
         }
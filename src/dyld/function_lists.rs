@@ -7,79 +7,124 @@
 //! very long and frequently-updated list.
 
 use crate::frameworks::{
-    audio_toolbox, core_foundation, core_graphics, dnssd, foundation, openal, opengles, uikit,
+    audio_toolbox, av_audio, core_foundation, core_graphics, dnssd, foundation, openal, opengles,
+    uikit,
 };
 use crate::libc;
 
-/// All the lists of functions that the linker should search through.
-pub const FUNCTION_LISTS: &[super::FunctionExports] = &[
-    libc::clocale::FUNCTIONS,
-    libc::ctype::FUNCTIONS,
-    libc::cxxabi::FUNCTIONS,
-    libc::dirent::FUNCTIONS,
-    libc::dlfcn::FUNCTIONS,
-    libc::errno::FUNCTIONS,
-    libc::ifaddrs::FUNCTIONS,
-    libc::keymgr::FUNCTIONS,
-    libc::netdb::FUNCTIONS,
-    libc::mach_thread_info::FUNCTIONS,
-    libc::mach_time::FUNCTIONS,
-    libc::math::FUNCTIONS,
-    libc::mmap::FUNCTIONS,
-    libc::net::if_::FUNCTIONS,
-    libc::posix_io::FUNCTIONS,
-    libc::posix_io::stat::FUNCTIONS,
-    libc::pthread::key::FUNCTIONS,
-    libc::pthread::mutex::FUNCTIONS,
-    libc::pthread::once::FUNCTIONS,
-    libc::pthread::thread::FUNCTIONS,
-    libc::sched::FUNCTIONS,
-    libc::semaphore::FUNCTIONS,
-    libc::setjmp::FUNCTIONS,
-    libc::signal::FUNCTIONS,
-    libc::stdio::FUNCTIONS,
-    libc::stdio::printf::FUNCTIONS,
-    libc::stdlib::FUNCTIONS,
-    libc::stdlib::qsort::FUNCTIONS,
-    libc::string::FUNCTIONS,
-    libc::sys::timeb::FUNCTIONS,
-    libc::sys::utsname::FUNCTIONS,
-    libc::sysctl::FUNCTIONS,
-    libc::time::FUNCTIONS,
-    libc::unistd::FUNCTIONS,
-    libc::wchar::FUNCTIONS,
-    crate::objc::FUNCTIONS,
-    audio_toolbox::audio_components::FUNCTIONS,
-    audio_toolbox::audio_file::FUNCTIONS,
-    audio_toolbox::audio_queue::FUNCTIONS,
-    audio_toolbox::audio_services::FUNCTIONS,
-    audio_toolbox::audio_session::FUNCTIONS,
-    audio_toolbox::audio_unit::FUNCTIONS,
-    core_foundation::cf_array::FUNCTIONS,
-    core_foundation::cf_bundle::FUNCTIONS,
-    core_foundation::cf_data::FUNCTIONS,
-    core_foundation::cf_run_loop::FUNCTIONS,
-    core_foundation::cf_run_loop_timer::FUNCTIONS,
-    core_foundation::cf_string::FUNCTIONS,
-    core_foundation::cf_type::FUNCTIONS,
-    core_foundation::cf_url::FUNCTIONS,
-    core_foundation::time::FUNCTIONS,
-    core_graphics::cg_affine_transform::FUNCTIONS,
-    core_graphics::cg_bitmap_context::FUNCTIONS,
-    core_graphics::cg_color::FUNCTIONS,
-    core_graphics::cg_color_space::FUNCTIONS,
-    core_graphics::cg_context::FUNCTIONS,
-    core_graphics::cg_data_provider::FUNCTIONS,
-    core_graphics::cg_geometry::FUNCTIONS,
-    core_graphics::cg_image::FUNCTIONS,
-    dnssd::FUNCTIONS,
-    foundation::FUNCTIONS,
-    foundation::ns_file_manager::FUNCTIONS,
-    foundation::ns_log::FUNCTIONS,
-    foundation::ns_objc_runtime::FUNCTIONS,
-    openal::FUNCTIONS,
-    opengles::FUNCTIONS,
-    uikit::ui_application::FUNCTIONS,
-    uikit::ui_geometry::FUNCTIONS,
-    uikit::ui_graphics::FUNCTIONS,
+/// All the lists of functions that the linker should search through, grouped
+/// by the library or framework they belong to.
+pub const FUNCTION_LISTS: &[(&str, &[super::FunctionExports])] = &[
+    (
+        "libSystem",
+        &[
+            libc::arpa::inet::FUNCTIONS,
+            libc::clocale::FUNCTIONS,
+            libc::ctype::FUNCTIONS,
+            libc::cxxabi::FUNCTIONS,
+            libc::dirent::FUNCTIONS,
+            libc::dlfcn::FUNCTIONS,
+            libc::errno::FUNCTIONS,
+            libc::ifaddrs::FUNCTIONS,
+            libc::keymgr::FUNCTIONS,
+            libc::netdb::FUNCTIONS,
+            libc::mach_thread_info::FUNCTIONS,
+            libc::mach_time::FUNCTIONS,
+            libc::math::FUNCTIONS,
+            libc::mmap::FUNCTIONS,
+            libc::net::if_::FUNCTIONS,
+            libc::poll::FUNCTIONS,
+            libc::posix_io::FUNCTIONS,
+            libc::posix_io::stat::FUNCTIONS,
+            libc::pthread::key::FUNCTIONS,
+            libc::pthread::mutex::FUNCTIONS,
+            libc::pthread::once::FUNCTIONS,
+            libc::pthread::thread::FUNCTIONS,
+            libc::sched::FUNCTIONS,
+            libc::semaphore::FUNCTIONS,
+            libc::setjmp::FUNCTIONS,
+            libc::signal::FUNCTIONS,
+            libc::stdio::FUNCTIONS,
+            libc::stdio::printf::FUNCTIONS,
+            libc::stdlib::FUNCTIONS,
+            libc::stdlib::qsort::FUNCTIONS,
+            libc::string::FUNCTIONS,
+            libc::sys::ioctl::FUNCTIONS,
+            libc::sys::select::FUNCTIONS,
+            libc::sys::socket::FUNCTIONS,
+            libc::sys::timeb::FUNCTIONS,
+            libc::sys::utsname::FUNCTIONS,
+            libc::sysctl::FUNCTIONS,
+            libc::time::FUNCTIONS,
+            libc::unistd::FUNCTIONS,
+            libc::wchar::FUNCTIONS,
+            dnssd::FUNCTIONS,
+        ],
+    ),
+    ("libobjc", &[crate::objc::FUNCTIONS]),
+    (
+        "AudioToolbox",
+        &[
+            audio_toolbox::audio_components::FUNCTIONS,
+            audio_toolbox::audio_file::FUNCTIONS,
+            audio_toolbox::audio_queue::FUNCTIONS,
+            audio_toolbox::audio_services::FUNCTIONS,
+            audio_toolbox::audio_session::FUNCTIONS,
+            audio_toolbox::audio_unit::FUNCTIONS,
+        ],
+    ),
+    (
+        "CoreFoundation",
+        &[
+            core_foundation::cf_array::FUNCTIONS,
+            core_foundation::cf_bundle::FUNCTIONS,
+            core_foundation::cf_data::FUNCTIONS,
+            core_foundation::cf_run_loop::FUNCTIONS,
+            core_foundation::cf_run_loop_timer::FUNCTIONS,
+            core_foundation::cf_string::FUNCTIONS,
+            core_foundation::cf_type::FUNCTIONS,
+            core_foundation::cf_url::FUNCTIONS,
+            core_foundation::time::FUNCTIONS,
+        ],
+    ),
+    (
+        "CoreGraphics",
+        &[
+            core_graphics::cg_affine_transform::FUNCTIONS,
+            core_graphics::cg_bitmap_context::FUNCTIONS,
+            core_graphics::cg_color::FUNCTIONS,
+            core_graphics::cg_color_space::FUNCTIONS,
+            core_graphics::cg_context::FUNCTIONS,
+            core_graphics::cg_data_provider::FUNCTIONS,
+            core_graphics::cg_geometry::FUNCTIONS,
+            core_graphics::cg_image::FUNCTIONS,
+        ],
+    ),
+    (
+        "Foundation",
+        &[
+            foundation::FUNCTIONS,
+            foundation::ns_file_manager::FUNCTIONS,
+            foundation::ns_log::FUNCTIONS,
+            foundation::ns_objc_runtime::FUNCTIONS,
+        ],
+    ),
+    ("OpenAL", &[openal::FUNCTIONS]),
+    ("OpenGLES", &[opengles::FUNCTIONS]),
+    (
+        "UIKit",
+        &[
+            uikit::ui_application::FUNCTIONS,
+            uikit::ui_geometry::FUNCTIONS,
+            uikit::ui_graphics::FUNCTIONS,
+        ],
+    ),
+];
+
+/// Helper functions that touchHLE creates guest functions for with
+/// [super::Dyld::create_guest_function], but which the app can't link to.
+pub const PRIVATE_FUNCTION_LISTS: &[super::FunctionExports] = &[
+    av_audio::av_audio_player::PRIVATE_FUNCTIONS,
+    foundation::ns_thread::PRIVATE_FUNCTIONS,
+    libc::stdlib::PRIVATE_FUNCTIONS,
 ];
@@ -9,13 +9,17 @@
 //! via the re-exports one level up.
 
 mod mutex;
+mod save_state;
 
 use crate::abi::GuestRet;
+use crate::frameworks::dnssd::DNSServiceRef;
+use crate::libc::posix_io::FileDescriptor;
 use crate::libc::semaphore::sem_t;
+use crate::libc::sys::socket::POLL_INTERVAL;
 use crate::mem::{MutPtr, MutVoidPtr};
 use crate::{
-    abi, bundle, cpu, dyld, frameworks, fs, gdb, image, libc, mach_o, mem, objc, options, stack,
-    window,
+    abi, bundle, cpu, dyld, frameworks, fs, gdb, image, libc, mach_o, mem, objc, options, replay,
+    stack, symbolication, trace, window,
 };
 use std::net::TcpListener;
 use std::time::{Duration, Instant};
@@ -64,6 +68,10 @@ pub struct Thread {
     /// Address range of this thread's stack, used to check if addresses are in
     /// range while producing a stack trace.
     stack: Option<std::ops::RangeInclusive<u32>>,
+    /// Set when the thread stops being blocked by [ThreadBlock::Io], until the
+    /// host function that blocked has been called again. See
+    /// [Environment::resumed_io].
+    resumed_io: Option<IoBlock>,
 }
 
 impl Thread {
@@ -94,6 +102,11 @@ pub struct Environment {
     pub framework_state: frameworks::State,
     pub mutex_state: mutex::MutexState,
     pub options: options::Options,
+    /// Present if `--trace=` is used, see [trace].
+    pub tracer: Option<trace::Tracer>,
+    /// Present if `--record-input=` or `--replay-input=` is used, see
+    /// [replay].
+    pub replay: Option<replay::Replay>,
     gdb_server: Option<gdb::GdbServer>,
 }
 
@@ -124,6 +137,36 @@ enum ThreadBlock {
     Joining(ThreadId, MutPtr<MutVoidPtr>),
     // Deferred guest-to-host return
     DeferredReturn,
+    // Thread is waiting for I/O, see [Environment::block_on_io].
+    Io(IoBlock),
+}
+
+/// Something a host function can wait for with [Environment::block_on_io].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoWait {
+    /// A file descriptor becoming readable, see
+    /// [libc::posix_io::readiness].
+    Readable(FileDescriptor),
+    /// A file descriptor becoming writable.
+    Writable(FileDescriptor),
+    /// A `DNSServiceRef` having results for `DNSServiceProcessResult()`.
+    DnsService(DNSServiceRef),
+    /// The request made by
+    /// [frameworks::foundation::ns_url_connection::fetch_synchronously] on
+    /// this thread finishing.
+    UrlFetch,
+}
+
+/// A wait for I/O by a blocked thread, see [Environment::block_on_io].
+#[derive(Debug, Clone, Copy)]
+pub struct IoBlock {
+    pub wait: IoWait,
+    /// When to stop waiting even if the I/O isn't ready, e.g. because of
+    /// `SO_RCVTIMEO`.
+    pub deadline: Option<Instant>,
+    /// How much of the operation was done before blocking, e.g. how many bytes
+    /// `recv()` with `MSG_WAITALL` has already received.
+    pub progress: u32,
 }
 
 impl Environment {
@@ -138,7 +181,7 @@ impl Environment {
     pub fn new(
         bundle: bundle::Bundle,
         fs: fs::Fs,
-        options: options::Options,
+        mut options: options::Options,
         env_for_salvage: Option<Environment>,
     ) -> Result<Environment, String> {
         let startup_time = Instant::now();
@@ -154,7 +197,19 @@ impl Environment {
             None
         };
 
-        let window = if options.headless {
+        let window = if options.headless
+            && (!options.capture_frames.is_empty()
+                || options.record.is_some()
+                || options.replay_input.is_some())
+        {
+            // Capturing frames requires an OpenGL context, so an offscreen
+            // window is needed. Replaying needs one too, since UIKit can't run
+            // without one.
+            Some(window::Window::new_offscreen(
+                &format!("{} (touchHLE)", bundle.display_name()),
+                &options,
+            ))
+        } else if options.headless {
             None
         } else {
             let icon = bundle.load_icon(&fs);
@@ -202,6 +257,9 @@ impl Environment {
         } else {
             mem::Mem::new()
         };
+        if options.debug_heap {
+            mem.enable_debug_heap();
+        }
 
         let executable = mach_o::MachO::load_from_file(bundle.executable_path(), &fs, &mut mem)
             .map_err(|e| format!("Could not load executable: {}", e))?;
@@ -248,6 +306,14 @@ impl Environment {
         let mut dyld = dyld::Dyld::new();
         dyld.do_initial_linking(&bins, &mut mem, &mut objc);
 
+        let tracer = options
+            .trace
+            .take()
+            .map(|filters| trace::Tracer::new(filters, options.trace_file.as_deref()))
+            .transpose()?;
+
+        let replay = replay::Replay::new(&options, bundle.bundle_identifier(), startup_time)?;
+
         let cpu = cpu::Cpu::new(match options.direct_memory_access {
             true => Some(&mut mem),
             false => None,
@@ -261,6 +327,7 @@ impl Environment {
             in_host_function: false,
             context: None,
             stack: Some(mem::Mem::MAIN_THREAD_STACK_LOW_END..=0u32.wrapping_sub(1)),
+            resumed_io: None,
         };
 
         let mut env = Environment {
@@ -279,6 +346,8 @@ impl Environment {
             mutex_state: Default::default(),
             framework_state: Default::default(),
             options,
+            tracer,
+            replay,
             gdb_server: None,
         };
 
@@ -296,6 +365,21 @@ impl Environment {
 
         env.cpu.set_cpsr(cpu::Cpu::CPSR_USER_MODE);
 
+        // Loading a save state replaces everything that has been set up so far,
+        // including where execution starts.
+        let state_loaded = if let Some(path) = env.options.load_state.take() {
+            if env.replay.is_some() {
+                return Err(
+                    "--load-state= can't be combined with --record-input= or --replay-input="
+                        .to_string(),
+                );
+            }
+            env.load_state_from_file(&path)?;
+            true
+        } else {
+            false
+        };
+
         if let Some(addrs) = env.options.gdb_listen_addrs.take() {
             let listener = TcpListener::bind(addrs.as_slice())
                 .map_err(|e| format!("Could not bind to {:?}: {}", addrs, e))?;
@@ -312,13 +396,17 @@ impl Environment {
                 .map_err(|e| format!("Could not accept connection: {}", e))?;
             echo!("Debugger client connected on {}.", client_addr);
             let mut gdb_server = gdb::GdbServer::new(client);
-            let step = gdb_server.wait_for_debugger(None, &mut env.cpu, &mut env.mem);
+            let step = gdb_server.wait_for_debugger(None, &mut env);
             assert!(!step, "Can't step right now!"); // TODO?
             env.gdb_server = Some(gdb_server);
         }
 
         echo!("CPU emulation begins now.");
 
+        if state_loaded {
+            return Ok(env);
+        }
+
         // Static initializers for libraries must be run before the initializer
         // in the app binary.
         // TODO: once we support more libraries, replace this hard-coded order
@@ -343,7 +431,11 @@ impl Environment {
             log_dbg!("Static initialization done");
         }
 
-        env.cpu.branch(entry_point_addr);
+        // The entry point is normally main() itself or a function that calls
+        // exit() after main() returns, but either way, returning exits the app.
+        let main_return_routine = libc::stdlib::main_return_routine(&mut env);
+        env.cpu
+            .branch_with_link(entry_point_addr, main_return_routine);
 
         Ok(env)
     }
@@ -403,6 +495,7 @@ impl Environment {
             in_host_function: false,
             context: None,
             stack: Some(mem::Mem::MAIN_THREAD_STACK_LOW_END..=0u32.wrapping_sub(1)),
+            resumed_io: None,
         };
 
         let mut env = Environment {
@@ -421,6 +514,8 @@ impl Environment {
             mutex_state: Default::default(),
             framework_state: Default::default(),
             options,
+            tracer: None,
+            replay: None,
             gdb_server: None,
         };
 
@@ -460,7 +555,71 @@ impl Environment {
         )
     }
 
-    fn stack_trace(&self) {
+    /// Produce a symbolicated stack trace for a thread, one line per frame.
+    /// See [stack::unwind] and [symbolication].
+    pub fn stack_trace(&mut self, thread: ThreadId) -> Vec<String> {
+        let Some(stack_range) = self.threads[thread].stack.clone() else {
+            return vec!["Thread has no stack.".to_string()];
+        };
+        let (pc, regs) = self.with_thread_cpu(thread, |cpu| (cpu.pc_with_thumb_bit(), *cpu.regs()));
+        let frames = stack::unwind(
+            &self.mem,
+            pc,
+            &regs,
+            stack_range,
+            self.dyld.return_to_host_routine(),
+            self.dyld.thread_exit_routine(),
+        );
+        let symbolicator = symbolication::Symbolicator::new(self);
+        frames
+            .into_iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                let description = match frame {
+                    stack::Frame::Guest(addr) => symbolicator.describe(addr, i != 0),
+                    stack::Frame::HostFunction => "[host function]".to_string(),
+                    stack::Frame::ThreadExit => "[thread exit]".to_string(),
+                };
+                match i {
+                    0 => format!("{:2}. {} (PC)", i, description),
+                    1 => format!("{:2}. {} (LR)", i, description),
+                    _ => format!("{:2}. {}", i, description),
+                }
+            })
+            .collect()
+    }
+
+    /// Print a summary of the allocations that haven't been freed, if heap
+    /// debugging mode (`--debug-heap`) is on. This should be called at exit.
+    pub fn report_heap_leaks(&self) {
+        let Some(leak_sites) = self.mem.debug_heap_leaks() else {
+            return;
+        };
+        // Most apps never free some things, and touchHLE itself makes many
+        // allocations that are never freed, so only the biggest are shown.
+        const MAX_SITES: usize = 50;
+        let count: usize = leak_sites.iter().map(|leak_site| leak_site.count).sum();
+        let size: mem::GuestUSize = leak_sites.iter().map(|leak_site| leak_site.size).sum();
+        echo!(
+            "Heap debugging: {} allocations ({:#x} bytes) were not freed. Largest totals by site:",
+            count,
+            size
+        );
+        let symbolicator = symbolication::Symbolicator::new(self);
+        for &mem::LeakSite { site, count, size } in leak_sites.iter().take(MAX_SITES) {
+            // The site is a return address.
+            let site = match site {
+                0 => "touchHLE".to_string(),
+                _ => symbolicator.describe(site, true),
+            };
+            echo!("{:#10x} bytes in {:6} allocations: {}", size, count, site);
+        }
+        if leak_sites.len() > MAX_SITES {
+            echo!("({} more sites not shown)", leak_sites.len() - MAX_SITES);
+        }
+    }
+
+    fn print_stack_trace(&mut self) {
         if self.current_thread == 0 {
             echo!("Attempting to produce stack trace for main thread:");
         } else {
@@ -469,41 +628,8 @@ impl Environment {
                 self.current_thread
             );
         }
-        let stack_range = self.threads[self.current_thread].stack.clone().unwrap();
-        echo!(
-            " 0. {:#x} (PC)",
-            self.cpu.pc_with_thumb_bit().addr_with_thumb_bit()
-        );
-        let regs = self.cpu.regs();
-        let mut lr = regs[cpu::Cpu::LR];
-        let return_to_host_routine_addr = self.dyld.return_to_host_routine().addr_with_thumb_bit();
-        let thread_exit_routine_addr = self.dyld.thread_exit_routine().addr_with_thumb_bit();
-        if lr == return_to_host_routine_addr {
-            echo!(" 1. [host function] (LR)");
-        } else if lr == thread_exit_routine_addr {
-            echo!(" 1. [thread exit] (LR)");
-            return;
-        } else {
-            echo!(" 1. {:#x} (LR)", lr);
-        }
-        let mut i = 2;
-        let mut fp: mem::ConstPtr<u8> = mem::Ptr::from_bits(regs[abi::FRAME_POINTER]);
-        loop {
-            if !stack_range.contains(&fp.to_bits()) {
-                echo!("Next FP ({:?}) is outside the stack.", fp);
-                break;
-            }
-            lr = self.mem.read((fp + 4).cast());
-            fp = self.mem.read(fp.cast());
-            if lr == return_to_host_routine_addr {
-                echo!("{:2}. [host function]", i);
-            } else if lr == thread_exit_routine_addr {
-                echo!("{:2}. [thread exit]", i);
-                return;
-            } else {
-                echo!("{:2}. {:#x}", i, lr);
-            }
-            i += 1;
+        for line in self.stack_trace(self.current_thread) {
+            echo!("{}", line);
         }
     }
 
@@ -528,6 +654,7 @@ impl Environment {
             in_host_function: false,
             context: Some(cpu::CpuContext::new()),
             stack: Some(stack_alloc.to_bits()..=(stack_high_addr - 1)),
+            resumed_io: None,
         });
         let new_thread_id = self.threads.len() - 1;
 
@@ -564,7 +691,7 @@ impl Environment {
             self.current_thread,
             duration
         );
-        let until = Instant::now().checked_add(duration).unwrap();
+        let until = replay::now(self).checked_add(duration).unwrap();
         self.threads[self.current_thread].blocked_by = ThreadBlock::Sleeping(until);
         // For non tail-call sleeps (such as in NSRunLoop), we want to poll
         // other threads but can't return back to the run loop, since it would
@@ -690,6 +817,69 @@ impl Environment {
         self.threads[self.current_thread].blocked_by = ThreadBlock::Joining(joinee_thread, ptr);
     }
 
+    /// Block the current thread until some I/O is ready or the deadline
+    /// passes, then call the host function that blocked again.
+    ///
+    /// Unlike the other blocking functions, this can be used by host functions
+    /// that can only produce their result once the I/O is ready: the result
+    /// they return after blocking is discarded and the CPU registers for the
+    /// arguments are restored, so they must not have any other side effects
+    /// before blocking. When they're called again, [Self::resumed_io] returns
+    /// the `block`, so they can pick up where they left off.
+    ///
+    /// Also note that like [Self::sleep], this only takes effect after the host
+    /// function returns to the main run loop ([Environment::run]).
+    pub fn block_on_io(&mut self, block: IoBlock) {
+        assert!(matches!(
+            self.threads[self.current_thread].blocked_by,
+            ThreadBlock::NotBlocked
+        ));
+        log_dbg!(
+            "Thread {} waiting for I/O: {:?}.",
+            self.current_thread,
+            block
+        );
+        self.threads[self.current_thread].blocked_by = ThreadBlock::Io(block);
+    }
+
+    /// If the current host function is being called again after the thread
+    /// blocked waiting for `wait` (see [Self::block_on_io]), get what it was
+    /// blocked by.
+    pub fn resumed_io(&self, wait: IoWait) -> Option<IoBlock> {
+        self.threads[self.current_thread]
+            .resumed_io
+            .filter(|block| block.wait == wait)
+    }
+
+    /// Whether the current host function has blocked with
+    /// [Self::block_on_io], meaning its result will be discarded.
+    pub fn is_blocked_on_io(&self) -> bool {
+        matches!(
+            self.threads[self.current_thread].blocked_by,
+            ThreadBlock::Io(_)
+        )
+    }
+
+    /// Whether a thread waiting for I/O can continue.
+    fn io_is_ready(&mut self, thread: ThreadId, wait: IoWait) -> bool {
+        match wait {
+            // A file descriptor that was closed in the meantime is ready too,
+            // since the host function will fail right away.
+            IoWait::Readable(fd) => match libc::posix_io::readiness(self, fd) {
+                Some(readiness) => readiness.readable || readiness.error,
+                None => true,
+            },
+            IoWait::Writable(fd) => match libc::posix_io::readiness(self, fd) {
+                Some(readiness) => readiness.writable || readiness.error,
+                None => true,
+            },
+            IoWait::DnsService(sd_ref) => frameworks::dnssd::readiness(self, sd_ref).readable,
+            IoWait::UrlFetch => {
+                frameworks::foundation::ns_url_connection::fetch_is_finished(self, thread)
+            }
+        }
+    }
+
     /// Run the emulator. This is the main loop and won't return until app exit.
     /// Only `main.rs` should call this.
     pub fn run(&mut self) {
@@ -697,9 +887,10 @@ impl Environment {
         // the emulator will crash anyway, maybe this is okay.
         let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| self.run_inner(true)));
         if let Err(e) = res {
+            replay::flush(self);
             echo!("Register state immediately after panic:");
             self.cpu.dump_regs();
-            self.stack_trace();
+            self.print_stack_trace();
             std::panic::resume_unwind(e);
         }
     }
@@ -776,11 +967,72 @@ impl Environment {
     pub fn enter_debugger(&mut self, reason: Option<cpu::CpuError>) -> bool {
         // GDB doesn't seem to manage to produce a useful stack trace, so
         // let's print our own.
-        self.stack_trace();
-        self.gdb_server
-            .as_mut()
-            .unwrap()
-            .wait_for_debugger(reason, &mut self.cpu, &mut self.mem)
+        self.print_stack_trace();
+        self.wait_for_debugger(reason)
+    }
+
+    fn wait_for_debugger(&mut self, reason: Option<cpu::CpuError>) -> bool {
+        // The server needs access to the whole environment, so it's taken out
+        // temporarily.
+        let mut gdb_server = self.gdb_server.take().unwrap();
+        let step = gdb_server.wait_for_debugger(reason, self);
+        self.gdb_server = Some(gdb_server);
+        step
+    }
+
+    /// Run a closure with access to the CPU state of a particular thread. If
+    /// the thread isn't the current one, its context is temporarily swapped
+    /// into the CPU. This is used by the debugger.
+    pub fn with_thread_cpu<R>(
+        &mut self,
+        thread: ThreadId,
+        f: impl FnOnce(&mut cpu::Cpu) -> R,
+    ) -> R {
+        if thread == self.current_thread {
+            return f(&mut self.cpu);
+        }
+        let mut context = self.threads[thread].context.take().unwrap();
+        self.cpu.swap_context(&mut context);
+        let res = f(&mut self.cpu);
+        self.cpu.swap_context(&mut context);
+        self.threads[thread].context = Some(context);
+        res
+    }
+
+    /// Describe what a thread is currently doing, for the debugger.
+    pub fn describe_thread(&self, thread: ThreadId) -> String {
+        let Thread {
+            active,
+            ref blocked_by,
+            in_host_function,
+            ..
+        } = self.threads[thread];
+        let name = if thread == 0 {
+            "main thread".to_string()
+        } else {
+            format!("thread {}", thread)
+        };
+        let state = match blocked_by {
+            _ if !active => "finished".to_string(),
+            ThreadBlock::NotBlocked if thread == self.current_thread => "running".to_string(),
+            ThreadBlock::NotBlocked => "runnable".to_string(),
+            ThreadBlock::Sleeping(until) => format!(
+                "sleeping for {:?}",
+                until.saturating_duration_since(Instant::now())
+            ),
+            ThreadBlock::Mutex(mutex_id) => format!("waiting for mutex {}", mutex_id),
+            ThreadBlock::Semaphore(sem) => format!("waiting for semaphore {:?}", sem),
+            ThreadBlock::Joining(joinee_thread, _) => {
+                format!("joining thread {}", joinee_thread)
+            }
+            ThreadBlock::DeferredReturn => "returning to host".to_string(),
+            ThreadBlock::Io(IoBlock { wait, .. }) => format!("waiting for {:?}", wait),
+        };
+        let mut description = format!("{}, {}", name, state);
+        if active && in_host_function {
+            description.push_str(", in host function");
+        }
+        description
     }
 
     #[inline(always)]
@@ -850,20 +1102,52 @@ impl Environment {
                             ThreadNextAction::Yield
                         }
                     }
+                    dyld::Dyld::SVC_SIGNAL_RETURN => {
+                        assert!(
+                            svc_pc == self.dyld.signal_return_routine().addr_without_thumb_bit()
+                        );
+                        libc::signal::return_from_handler(self);
+                        ThreadNextAction::Continue
+                    }
                     dyld::Dyld::SVC_LAZY_LINK | dyld::Dyld::SVC_LINKED_FUNCTIONS_BASE.. => {
-                        if let Some(f) = self.dyld.get_svc_handler(
+                        if let Some((symbol, f)) = self.dyld.get_svc_handler(
                             &self.bins,
                             &mut self.mem,
                             &mut self.cpu,
                             svc_pc,
                             svc,
                         ) {
+                            trace::function(self, symbol);
                             let was_in_host_function =
                                 self.threads[self.current_thread].in_host_function;
                             self.threads[self.current_thread].in_host_function = true;
+                            let old_allocation_site =
+                                self.mem.set_allocation_site(self.cpu.regs()[cpu::Cpu::LR]);
+                            let [r0, r1, r2, r3, ..] = *self.cpu.regs();
                             f.call_from_guest(self);
+                            self.mem.set_allocation_site(old_allocation_site);
                             self.threads[self.current_thread].in_host_function =
                                 was_in_host_function;
+                            self.threads[self.current_thread].resumed_io = None;
+                            // A host function that is waiting for I/O gets
+                            // called again once it's ready, with the same
+                            // arguments. See Self::block_on_io().
+                            if let ThreadBlock::Io(_) = self.threads[self.current_thread].blocked_by
+                            {
+                                let regs = self.cpu.regs_mut();
+                                regs[..4].copy_from_slice(&[r0, r1, r2, r3]);
+                                regs[cpu::Cpu::PC] = svc_pc;
+                            }
+                            // See `--save-state-after=`. Only the top-level
+                            // run loop can save states.
+                            if root
+                                && symbol.strip_prefix('_').is_some_and(|name| {
+                                    self.options.save_state_after.as_deref() == Some(name)
+                                })
+                            {
+                                self.options.save_state_after = None;
+                                self.save_state_to_file();
+                            }
                             // Host function might have put the thread to sleep.
                             if let ThreadBlock::NotBlocked =
                                 self.threads[self.current_thread].blocked_by
@@ -890,6 +1174,12 @@ impl Environment {
         assert!(self.threads[initial_thread].context.is_none());
 
         loop {
+            // Signals sent to this thread by another thread, or unblocked
+            // since they were sent, must be delivered before it continues.
+            if !self.threads[self.current_thread].is_blocked() {
+                libc::signal::deliver_pending_signals(self);
+            }
+
             // 100,000 ticks is an arbitrary number. It needs to be reasonably
             // large so we aren't jumping in and out of dynarmic or trying to
             // poll for events too often. At the same time, very large values
@@ -915,17 +1205,26 @@ impl Environment {
                 match self.handle_cpu_state(state, initial_thread, root) {
                     ThreadNextAction::Continue => {
                         if step_and_debug {
-                            step_and_debug = self.gdb_server.as_mut().unwrap().wait_for_debugger(
-                                None,
-                                &mut self.cpu,
-                                &mut self.mem,
-                            );
+                            step_and_debug = self.wait_for_debugger(None);
+                            // The debugger may have sent a signal.
+                            libc::signal::deliver_pending_signals(self);
                         }
                     }
                     ThreadNextAction::Yield => break,
                     ThreadNextAction::ReturnToHost => return,
                     ThreadNextAction::DebugCpuError(e) => {
+                        // The app might have its own handler for the signal
+                        // an invalid memory access causes. When debugging,
+                        // the debugger gets to see the error first though.
+                        if let cpu::CpuError::MemoryError(fault) = e {
+                            if !self.is_debugging_enabled()
+                                && libc::signal::deliver_memory_fault(self, fault)
+                            {
+                                continue;
+                            }
+                        }
                         step_and_debug = self.debug_cpu_error(e);
+                        libc::signal::deliver_pending_signals(self);
                     }
                 }
             }
@@ -939,6 +1238,13 @@ impl Environment {
             if let Some(ref mut window) = self.window {
                 window.poll_for_events(&self.options);
             }
+            if let Some(request) = self
+                .window
+                .as_mut()
+                .and_then(|window| window.take_state_request())
+            {
+                self.handle_state_request(request, root);
+            }
 
             loop {
                 // Try to find a new thread to execute, starting with the thread
@@ -946,6 +1252,25 @@ impl Environment {
                 let mut suitable_thread: Option<ThreadId> = None;
                 let mut next_awakening: Option<Instant> = None;
                 let mut mutex_to_relock: Option<MutexId> = None;
+                let mut waiting_for_io = false;
+                // Threads waiting for I/O are checked often, so the time is
+                // only fetched if some thread needs it, lest every check write
+                // a record when recording input (see replay::now()).
+                let needs_time = self.threads.iter().any(|thread| {
+                    matches!(
+                        thread.blocked_by,
+                        ThreadBlock::Sleeping(_)
+                            | ThreadBlock::Io(IoBlock {
+                                deadline: Some(_),
+                                ..
+                            })
+                    )
+                });
+                let now = if needs_time {
+                    replay::now(self)
+                } else {
+                    Instant::now()
+                };
                 for i in 0..self.threads.len() {
                     let i = (self.current_thread + 1 + i) % self.threads.len();
                     let candidate = &mut self.threads[i];
@@ -955,7 +1280,7 @@ impl Environment {
                     }
                     match candidate.blocked_by {
                         ThreadBlock::Sleeping(sleeping_until) => {
-                            if sleeping_until <= Instant::now() {
+                            if sleeping_until <= now {
                                 log_dbg!("Thread {} finished sleeping.", i);
                                 candidate.blocked_by = ThreadBlock::NotBlocked;
                                 suitable_thread = Some(i);
@@ -1027,6 +1352,23 @@ impl Environment {
                                 return;
                             }
                         }
+                        ThreadBlock::Io(block) => {
+                            let timed_out = block.deadline.is_some_and(|deadline| deadline <= now);
+                            if timed_out || self.io_is_ready(i, block.wait) {
+                                log_dbg!("Thread {} finished waiting for I/O.", i);
+                                self.threads[i].blocked_by = ThreadBlock::NotBlocked;
+                                self.threads[i].resumed_io = Some(block);
+                                suitable_thread = Some(i);
+                                break;
+                            }
+                            waiting_for_io = true;
+                            if let Some(deadline) = block.deadline {
+                                next_awakening = match next_awakening {
+                                    None => Some(deadline),
+                                    Some(other) => Some(other.min(deadline)),
+                                };
+                            }
+                        }
                         ThreadBlock::NotBlocked => {
                             suitable_thread = Some(i);
                             break;
@@ -1036,6 +1378,7 @@ impl Environment {
 
                 // There's a suitable thread we can switch to immediately.
                 if let Some(suitable_thread) = suitable_thread {
+                    replay::schedule_thread(self, suitable_thread);
                     if suitable_thread != self.current_thread {
                         self.switch_thread(suitable_thread);
                     }
@@ -1045,10 +1388,21 @@ impl Environment {
                     break;
                 // All suitable threads are blocked and at least one is asleep.
                 // Sleep until one of them wakes up.
-                } else if let Some(next_awakening) = next_awakening {
-                    let duration = next_awakening.duration_since(Instant::now());
+                } else if next_awakening.is_some() || waiting_for_io {
+                    let mut duration = next_awakening.map_or(POLL_INTERVAL, |next_awakening| {
+                        next_awakening.duration_since(now)
+                    });
+                    // There's no way to be notified when I/O is ready, so it
+                    // has to be checked again periodically.
+                    if waiting_for_io {
+                        duration = duration.min(POLL_INTERVAL);
+                    }
                     log_dbg!("All threads blocked/asleep, sleeping for {:?}.", duration);
-                    std::thread::sleep(duration);
+                    // When replaying, the time comes from the recording, so
+                    // there's no need to actually wait.
+                    if !replay::is_replaying(self) {
+                        std::thread::sleep(duration);
+                    }
                     // Try again, there should be some thread awake now (or
                     // there will be soon, since timing is approximate).
                     continue;
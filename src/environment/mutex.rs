@@ -10,6 +10,7 @@ use std::num::NonZeroU32;
 
 use super::{Environment, ThreadId};
 use crate::libc::errno::{EBUSY, EDEADLK, EPERM};
+use crate::save_state::{impl_SaveState, SaveState, StateReader, StateWriter};
 
 /// Stores and manages mutexes. Note that all the methods for locking and
 /// unlocking mutexes are on [Environment] instead, because they interact with
@@ -25,6 +26,10 @@ pub struct MutexState {
     // lifetime :P
     mutex_count: u64,
 }
+impl_SaveState!(MutexState {
+    mutexes,
+    mutex_count,
+});
 
 /// Unique identifier for mutexes, used for mutexes held by host objects and
 /// guest pthread mutexes.
@@ -37,6 +42,11 @@ struct Mutex {
     /// recursive mutex).
     locked: Option<(ThreadId, NonZeroU32)>,
 }
+impl_SaveState!(Mutex {
+    type_,
+    waiting_count,
+    locked,
+});
 
 #[repr(i32)]
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -59,6 +69,14 @@ impl TryFrom<i32> for MutexType {
         }
     }
 }
+impl SaveState for MutexType {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&(*self as i32));
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        MutexType::try_from(reader.read::<i32>()?).map_err(|e| e.to_string())
+    }
+}
 pub const PTHREAD_MUTEX_DEFAULT: MutexType = MutexType::PTHREAD_MUTEX_NORMAL;
 
 impl MutexState {
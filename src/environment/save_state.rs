@@ -0,0 +1,239 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Saving and loading the whole environment. See [crate::save_state] for the
+//! format.
+//!
+//! Host functions keep some of their state on the host stack, which can't be
+//! saved. States are therefore only saved and loaded between host function
+//! calls in the top-level [Environment::run_inner], where no host function is
+//! running on any thread. Apps that are in their run loop are always inside a
+//! host function, so they can't be saved yet.
+
+use super::{Environment, Thread, ThreadBlock, ThreadId};
+use crate::fs::GuestPath;
+use crate::save_state::{SaveState, StateReader, StateWriter};
+use crate::window::StateRequest;
+use crate::{cpu, dyld, frameworks, libc, mem, paths};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+impl SaveState for ThreadBlock {
+    fn save(&self, writer: &mut StateWriter) {
+        match *self {
+            ThreadBlock::NotBlocked => writer.write(&0u8),
+            ThreadBlock::Sleeping(until) => {
+                writer.write(&1u8);
+                writer.write(&until.saturating_duration_since(Instant::now()));
+            }
+            ThreadBlock::Mutex(mutex_id) => {
+                writer.write(&2u8);
+                writer.write(&mutex_id);
+            }
+            ThreadBlock::Semaphore(sem) => {
+                writer.write(&3u8);
+                writer.write(&sem);
+            }
+            ThreadBlock::Joining(joinee_thread, ptr) => {
+                writer.write(&4u8);
+                writer.write(&joinee_thread);
+                writer.write(&ptr);
+            }
+            // This only happens during a host-to-guest call.
+            ThreadBlock::DeferredReturn => writer.unsupported("deferred returns to host"),
+            // Only sockets, DNS-SD and NSURLConnection block on I/O, and they
+            // can't be saved anyway.
+            ThreadBlock::Io(_) => writer.unsupported("threads waiting for I/O"),
+        }
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        Ok(match reader.read::<u8>()? {
+            0 => ThreadBlock::NotBlocked,
+            1 => ThreadBlock::Sleeping(Instant::now() + reader.read::<Duration>()?),
+            2 => ThreadBlock::Mutex(reader.read()?),
+            3 => ThreadBlock::Semaphore(reader.read()?),
+            4 => ThreadBlock::Joining(reader.read()?, reader.read()?),
+            _ => return Err("Save state has an invalid thread state".to_string()),
+        })
+    }
+}
+
+/// The CPU registers of a thread: the core registers, the VFP registers, CPSR
+/// and FPSCR.
+type Registers = ([u32; 16], [u32; 64], (u32, u32));
+
+impl Environment {
+    /// Save the state of the app's execution. See [crate::save_state].
+    pub fn save_state(&mut self) -> Result<Vec<u8>, String> {
+        let mut writer = StateWriter::new();
+        // A recording or replay is a stream of events from startup, which
+        // can't be resumed from the middle.
+        if self.replay.is_some() {
+            writer.unsupported("recording or replaying input");
+        }
+        writer.write(&self.startup_time.elapsed());
+        self.mem.save_state(&mut writer);
+
+        writer.write(&self.current_thread);
+        writer.write(&self.threads.len());
+        for thread in 0..self.threads.len() {
+            let registers: Registers = self.with_thread_cpu(thread, |cpu| {
+                (*cpu.regs(), *cpu.ext_regs(), (cpu.cpsr(), cpu.fpscr()))
+            });
+            let Thread {
+                active,
+                ref blocked_by,
+                in_start_routine,
+                return_value,
+                in_host_function,
+                context: _,
+                ref stack,
+                resumed_io,
+            } = self.threads[thread];
+            if in_host_function {
+                writer.unsupported("threads running host functions");
+            }
+            if resumed_io.is_some() {
+                writer.unsupported("threads waiting for I/O");
+            }
+            writer.write(&active);
+            writer.write(blocked_by);
+            writer.write(&in_start_routine);
+            writer.write(&return_value);
+            writer.write(&stack.as_ref().map(|stack| (*stack.start(), *stack.end())));
+            writer.write(&registers);
+        }
+        writer.write(&self.mutex_state);
+
+        self.dyld.save_state(&mut writer);
+        self.objc.save_state(&self.mem, &mut writer);
+        // The files themselves aren't saved, but relative paths depend on this.
+        writer.write(&self.fs.working_directory().as_str().to_string());
+        self.libc_state.save_state(&mut writer);
+        self.framework_state.save_state(&mut writer);
+
+        writer.finish(self.bundle.bundle_identifier())
+    }
+
+    /// Replace the state of the app's execution with one from a save state.
+    /// The reader's header must already have been checked (see
+    /// [StateReader::new]). If loading fails after that, the environment is
+    /// left in an inconsistent state and shouldn't be run.
+    pub fn load_state(&mut self, mut reader: StateReader) -> Result<(), String> {
+        let elapsed: Duration = reader.read()?;
+        self.mem.load_state(&mut reader)?;
+
+        let current_thread: ThreadId = reader.read()?;
+        let thread_count: usize = reader.read()?;
+        if current_thread >= thread_count {
+            return Err("Save state has an invalid current thread".to_string());
+        }
+        let mut threads = Vec::with_capacity(thread_count);
+        let mut thread_registers = Vec::with_capacity(thread_count);
+        for thread in 0..thread_count {
+            threads.push(Thread {
+                active: reader.read()?,
+                blocked_by: reader.read()?,
+                in_start_routine: reader.read()?,
+                return_value: reader.read()?,
+                in_host_function: false,
+                // The current thread's state is in the CPU.
+                context: (thread != current_thread).then(cpu::CpuContext::new),
+                stack: reader
+                    .read::<Option<(u32, u32)>>()?
+                    .map(|(start, end)| start..=end),
+                resumed_io: None,
+            });
+            thread_registers.push(reader.read::<Registers>()?);
+        }
+        self.threads = threads;
+        self.current_thread = current_thread;
+        for (thread, (regs, ext_regs, (cpsr, fpscr))) in thread_registers.into_iter().enumerate() {
+            self.with_thread_cpu(thread, |cpu| {
+                *cpu.regs_mut() = regs;
+                *cpu.ext_regs_mut() = ext_regs;
+                cpu.set_cpsr(cpsr);
+                cpu.set_fpscr(fpscr);
+            });
+        }
+        self.mutex_state = reader.read()?;
+
+        self.dyld = dyld::Dyld::load_state(&mut reader)?;
+        self.objc.load_state(&mut reader)?;
+        let working_directory: String = reader.read()?;
+        self.fs
+            .change_working_directory(GuestPath::new(&working_directory))
+            .map_err(|_| format!("Couldn't change to directory {:?}", working_directory))?;
+        self.libc_state = libc::State::load_state(&mut reader, &mut self.fs)?;
+        self.framework_state = frameworks::State::load_state(&mut reader)?;
+        reader.finish()?;
+
+        self.startup_time = Instant::now()
+            .checked_sub(elapsed)
+            .ok_or_else(|| "Save state's timestamp is out of range".to_string())?;
+        // The code and the page protections have changed.
+        self.cpu.invalidate_cache_range(0, u32::MAX);
+        self.cpu
+            .update_page_slow_paths(&self.mem, 0..=(mem::Mem::PAGE_COUNT - 1));
+        Ok(())
+    }
+
+    /// The file that F5 saves to and F9 loads from, see `--save-state-file=`.
+    fn save_state_path(&self) -> PathBuf {
+        self.options.save_state_file.clone().unwrap_or_else(|| {
+            paths::user_data_base_path()
+                .join(paths::SAVE_STATES_DIR)
+                .join(format!("{}.touchHLEstate", self.bundle.bundle_identifier()))
+        })
+    }
+
+    pub(super) fn save_state_to_file(&mut self) {
+        let path = self.save_state_path();
+        let res = self.save_state().and_then(|bytes| {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)
+                    .map_err(|e| format!("Couldn't create {}: {}", dir.display(), e))?;
+            }
+            std::fs::write(&path, bytes)
+                .map_err(|e| format!("Couldn't write {}: {}", path.display(), e))
+        });
+        match res {
+            Ok(()) => echo!("Saved state to {}.", path.display()),
+            Err(e) => echo!("Couldn't save state: {}", e),
+        }
+    }
+
+    /// Load a save state from a file, e.g. for `--load-state=`. Errors are
+    /// returned if the file isn't a valid save state for this app, but a
+    /// failure after that panics, since the environment can't be used anymore.
+    pub(super) fn load_state_from_file(&mut self, path: &Path) -> Result<(), String> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| format!("Couldn't read save state {}: {}", path.display(), e))?;
+        let reader = StateReader::new(&bytes, self.bundle.bundle_identifier())?;
+        if let Err(e) = self.load_state(reader) {
+            panic!("Couldn't load save state {}: {}", path.display(), e);
+        }
+        echo!("Loaded state from {}.", path.display());
+        Ok(())
+    }
+
+    /// Handle a save state hotkey. `root` is the same as for
+    /// [Environment::run_inner], see the module documentation.
+    pub(super) fn handle_state_request(&mut self, request: StateRequest, root: bool) {
+        if !root {
+            echo!("Can't save or load a state while the app is waiting for touchHLE.");
+            return;
+        }
+        match request {
+            StateRequest::Save => self.save_state_to_file(),
+            StateRequest::Load => {
+                let path = self.save_state_path();
+                if let Err(e) = self.load_state_from_file(&path) {
+                    echo!("{}", e);
+                }
+            }
+        }
+    }
+}
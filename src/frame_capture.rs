@@ -0,0 +1,485 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Capturing frames presented by the app, for screenshots and for comparing
+//! the output against reference images.
+//!
+//! Frames are captured from the app's framebuffer, i.e. at the app's internal
+//! resolution (including the scale hack) rather than whatever size the window
+//! is, and without the virtual cursor. They are rotated to match the
+//! [DeviceOrientation], so they look like the app does in the window.
+//!
+//! A screenshot of the next frame can be requested with a hotkey (see
+//! [crate::window]), and `--capture-frames=` lets specific frames be captured
+//! automatically, which is useful for automated testing. Capturing also works
+//! in headless mode, since an offscreen window is created then. The saved
+//! frames can be checked against reference images with
+//! [compare_with_reference], which is exported for the integration tests.
+//!
+//! `--record=` captures every frame and writes them to a YUV4MPEG2 video,
+//! along with the audio (see [crate::audio::recording]). The video has a
+//! constant framerate, so each frame is put in the slot nearest to the time it
+//! was presented: frames are repeated if the app presents frames late, and
+//! dropped if it presents them early.
+
+use crate::audio::recording::{AudioRecorder, SAMPLE_RATE};
+use crate::image::Image;
+use crate::options::Options;
+use crate::paths;
+use crate::window::DeviceOrientation;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+pub struct FrameCapture {
+    screenshot_dir: PathBuf,
+    /// See `--capture-frames=`. Frames are numbered from 1.
+    frames_to_capture: BTreeSet<u64>,
+    screenshot_requested: bool,
+    frame_number: u64,
+    recording: Option<Recording>,
+}
+impl FrameCapture {
+    pub fn new(options: &Options) -> FrameCapture {
+        let recording = match options
+            .record
+            .as_deref()
+            .map(|path| Recording::new(path, options))
+        {
+            Some(Ok(recording)) => Some(recording),
+            Some(Err(e)) => {
+                log!("Warning: Couldn't start recording: {}", e);
+                None
+            }
+            None => None,
+        };
+        FrameCapture {
+            screenshot_dir: options
+                .screenshot_dir
+                .clone()
+                .unwrap_or_else(|| paths::user_data_base_path().join(paths::SCREENSHOTS_DIR)),
+            frames_to_capture: options.capture_frames.iter().copied().collect(),
+            screenshot_requested: false,
+            frame_number: 0,
+            recording,
+        }
+    }
+
+    /// The audio recorder, if recording. This needs to be used when opening
+    /// OpenAL devices (see [crate::audio::recording]).
+    pub fn audio_recorder(&mut self) -> Option<&mut AudioRecorder> {
+        self.recording
+            .as_mut()
+            .map(|recording| &mut recording.audio)
+    }
+
+    /// Save a screenshot of the next frame presented.
+    pub fn request_screenshot(&mut self) {
+        self.screenshot_requested = true;
+    }
+
+    /// Must be called once for each frame presented by the app, before it is
+    /// presented. Returns [true] if the frame should be passed to
+    /// [Self::capture_frame].
+    pub fn begin_frame(&mut self) -> bool {
+        self.frame_number += 1;
+        self.recording.is_some()
+            || self.screenshot_requested
+            || self.frames_to_capture.contains(&self.frame_number)
+    }
+
+    /// Save the current frame. The pixels must be in RGBA8 format, with rows in
+    /// bottom-to-top order (as returned by `glReadPixels()`), and not rotated.
+    pub fn capture_frame(
+        &mut self,
+        pixels: &[u8],
+        size: (u32, u32),
+        orientation: DeviceOrientation,
+    ) {
+        let (pixels, size) = rotate_frame(pixels, size, orientation);
+
+        if let Some(recording) = &mut self.recording {
+            recording.record_frame(&pixels, size);
+        }
+
+        let mut file_names = Vec::new();
+        if std::mem::take(&mut self.screenshot_requested) {
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            file_names.push(format!("screenshot_{}_{}.png", time, self.frame_number));
+        }
+        if self.frames_to_capture.remove(&self.frame_number) {
+            file_names.push(format!("frame_{}.png", self.frame_number));
+        }
+        if file_names.is_empty() {
+            return;
+        }
+
+        let png = Image::from_pixel_vec(pixels, size).to_png();
+
+        if let Err(e) = std::fs::create_dir_all(&self.screenshot_dir) {
+            log!(
+                "Warning: Couldn't create {}: {}",
+                self.screenshot_dir.display(),
+                e
+            );
+            return;
+        }
+        for file_name in file_names {
+            let path = self.screenshot_dir.join(file_name);
+            match std::fs::write(&path, &png) {
+                Ok(()) => echo!("Saved frame {} to {}", self.frame_number, path.display()),
+                Err(e) => log!("Warning: Couldn't write {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+/// Result of [compare_with_reference].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameComparison {
+    /// The dimensions of the frame and of the reference image. The pixels are
+    /// only compared if these are the same.
+    pub dimensions: ((u32, u32), (u32, u32)),
+    /// Number of pixels where some channel differs by more than the tolerance.
+    pub differing_pixels: usize,
+    /// The largest difference in any channel of any pixel.
+    pub max_difference: u8,
+}
+impl FrameComparison {
+    pub fn is_match(&self) -> bool {
+        self.dimensions.0 == self.dimensions.1 && self.differing_pixels == 0
+    }
+}
+
+/// Compare a frame saved by [FrameCapture] against a reference image. Both are
+/// PNG files. A channel may differ by up to `tolerance` without the pixel
+/// counting as different, to allow for differences between GPU drivers. Alpha
+/// is ignored, since captured frames are always opaque.
+pub fn compare_with_reference(
+    frame_png: &[u8],
+    reference_png: &[u8],
+    tolerance: u8,
+) -> Result<FrameComparison, String> {
+    let frame = Image::from_bytes(frame_png).map_err(|e| format!("Bad frame: {}", e))?;
+    let reference =
+        Image::from_bytes(reference_png).map_err(|e| format!("Bad reference image: {}", e))?;
+    let dimensions = (frame.dimensions(), reference.dimensions());
+    let (differing_pixels, max_difference) = if dimensions.0 == dimensions.1 {
+        compare_pixels(frame.pixels(), reference.pixels(), tolerance)
+    } else {
+        (0, 0)
+    };
+    Ok(FrameComparison {
+        dimensions,
+        differing_pixels,
+        max_difference,
+    })
+}
+
+/// Compares two RGBA8 images of the same size. Returns the number of pixels
+/// that differ by more than `tolerance`, and the largest difference.
+fn compare_pixels(a: &[u8], b: &[u8], tolerance: u8) -> (usize, u8) {
+    assert_eq!(a.len(), b.len());
+    let mut differing_pixels = 0;
+    let mut max_difference = 0;
+    for (a, b) in a.chunks_exact(4).zip(b.chunks_exact(4)) {
+        let difference = (0..3).map(|i| a[i].abs_diff(b[i])).max().unwrap();
+        if difference > tolerance {
+            differing_pixels += 1;
+        }
+        max_difference = max_difference.max(difference);
+    }
+    (differing_pixels, max_difference)
+}
+
+/// State for `--record=`.
+struct Recording {
+    video_path: PathBuf,
+    video: BufWriter<File>,
+    audio: AudioRecorder,
+    fps: f64,
+    /// The time the first frame was presented, and its size, which is used as
+    /// the size of the video.
+    start: Option<(Instant, (u32, u32))>,
+    frames_written: u64,
+    audio_frames_written: u64,
+    /// The most recently written frame, in the video's format.
+    last_frame: Vec<u8>,
+}
+impl Recording {
+    fn new(video_path: &Path, options: &Options) -> Result<Recording, String> {
+        let video = File::create(video_path)
+            .map_err(|e| format!("Couldn't create {}: {}", video_path.display(), e))?;
+        let audio = AudioRecorder::new(&video_path.with_extension("wav"))?;
+        echo!(
+            "Recording video to {} and audio to {}",
+            video_path.display(),
+            video_path.with_extension("wav").display()
+        );
+        Ok(Recording {
+            video_path: video_path.to_owned(),
+            video: BufWriter::new(video),
+            audio,
+            fps: options.fps_limit.unwrap_or(60.0),
+            start: None,
+            frames_written: 0,
+            audio_frames_written: 0,
+            last_frame: Vec::new(),
+        })
+    }
+
+    /// Record a frame. The pixels must be in RGBA8 format, with rows in
+    /// top-to-bottom order.
+    fn record_frame(&mut self, pixels: &[u8], size: (u32, u32)) {
+        let now = Instant::now();
+        let (start, video_size) = match self.start {
+            Some(start) => start,
+            None => {
+                let header = y4m_header(size, self.fps);
+                self.write_video(header.as_bytes());
+                self.start = Some((now, size));
+                (now, size)
+            }
+        };
+
+        let slot = ((now - start).as_secs_f64() * self.fps).round() as u64;
+        if slot < self.frames_written {
+            log_dbg!("Dropping frame presented early for slot {}", slot);
+            return;
+        }
+
+        // If the app took too long to present this frame, fill the gap by
+        // repeating the previous frame.
+        let last_frame = std::mem::take(&mut self.last_frame);
+        while self.frames_written < slot {
+            self.write_frame(&last_frame);
+        }
+        let frame = encode_frame(pixels, size, video_size);
+        self.write_frame(&frame);
+        self.last_frame = frame;
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) {
+        self.write_video(b"FRAME\n");
+        self.write_video(frame);
+        // The app might exit without running destructors.
+        if let Err(e) = self.video.flush() {
+            log!(
+                "Warning: Couldn't write {}: {}",
+                self.video_path.display(),
+                e
+            );
+        }
+        self.frames_written += 1;
+
+        // Render exactly as much audio as the video now lasts for, so rounding
+        // errors don't accumulate.
+        let audio_frames =
+            (self.frames_written as f64 * f64::from(SAMPLE_RATE) / self.fps).round() as u64;
+        self.audio.render(
+            (audio_frames - self.audio_frames_written)
+                .try_into()
+                .unwrap(),
+        );
+        self.audio_frames_written = audio_frames;
+    }
+
+    fn write_video(&mut self, data: &[u8]) {
+        if let Err(e) = self.video.write_all(data) {
+            log!(
+                "Warning: Couldn't write {}: {}",
+                self.video_path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// The stream header for a YUV4MPEG2 file.
+fn y4m_header((width, height): (u32, u32), fps: f64) -> String {
+    let (fps_numerator, fps_denominator) = if fps.fract() == 0.0 {
+        (fps as u64, 1)
+    } else {
+        ((fps * 1000.0).round() as u64, 1000)
+    };
+    format!(
+        "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444\n",
+        width, height, fps_numerator, fps_denominator
+    )
+}
+
+/// Convert a frame to the YUV4MPEG2 format used by [Recording] (Y′CbCr 4:4:4,
+/// BT.601 limited range, in separate planes). The pixels must be in RGBA8
+/// format, with rows in top-to-bottom order.
+///
+/// If the frame is not the size of the video (e.g. because the app changed
+/// orientation), it is centered and either cropped or padded with black.
+fn encode_frame(
+    pixels: &[u8],
+    (width, height): (u32, u32),
+    (out_width, out_height): (u32, u32),
+) -> Vec<u8> {
+    assert_eq!(pixels.len(), width as usize * height as usize * 4);
+
+    let plane_size = out_width as usize * out_height as usize;
+    let mut frame = vec![0u8; plane_size * 3];
+    let (y_plane, uv_planes) = frame.split_at_mut(plane_size);
+    let (u_plane, v_plane) = uv_planes.split_at_mut(plane_size);
+
+    // These can be negative, when the frame is smaller than the video.
+    let x_offset = (i64::from(width) - i64::from(out_width)) / 2;
+    let y_offset = (i64::from(height) - i64::from(out_height)) / 2;
+
+    for out_y in 0..i64::from(out_height) {
+        for out_x in 0..i64::from(out_width) {
+            let (x, y) = (out_x + x_offset, out_y + y_offset);
+            let (r, g, b) =
+                if (0..i64::from(width)).contains(&x) && (0..i64::from(height)).contains(&y) {
+                    let i = (y * i64::from(width) + x) as usize * 4;
+                    (
+                        i32::from(pixels[i]),
+                        i32::from(pixels[i + 1]),
+                        i32::from(pixels[i + 2]),
+                    )
+                } else {
+                    (0, 0, 0)
+                };
+            let i = (out_y * i64::from(out_width) + out_x) as usize;
+            y_plane[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            u_plane[i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            v_plane[i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+    }
+    frame
+}
+
+/// Rotates a frame read from an app's framebuffer (see
+/// [FrameCapture::capture_frame]) the same way it is rotated when it is
+/// presented to the window, and converts it to top-to-bottom row order. The
+/// alpha channel is made opaque, since it isn't shown in the window either.
+fn rotate_frame(
+    pixels: &[u8],
+    (in_width, in_height): (u32, u32),
+    orientation: DeviceOrientation,
+) -> (Vec<u8>, (u32, u32)) {
+    let (in_width, in_height) = (in_width as usize, in_height as usize);
+    assert_eq!(pixels.len(), in_width * in_height * 4);
+
+    let (out_width, out_height) = match orientation {
+        DeviceOrientation::Portrait => (in_width, in_height),
+        DeviceOrientation::LandscapeLeft | DeviceOrientation::LandscapeRight => {
+            (in_height, in_width)
+        }
+    };
+
+    let mut rotated = Vec::with_capacity(pixels.len());
+    for out_y in 0..out_height {
+        // The input is bottom-to-top, so flip the y axis to make the maths
+        // match Window::rotation_matrix().
+        let y = out_height - 1 - out_y;
+        for x in 0..out_width {
+            let (in_x, in_y) = match orientation {
+                DeviceOrientation::Portrait => (x, y),
+                DeviceOrientation::LandscapeLeft => (y, in_height - 1 - x),
+                DeviceOrientation::LandscapeRight => (in_width - 1 - y, x),
+            };
+            let i = (in_y * in_width + in_x) * 4;
+            rotated.extend_from_slice(&pixels[i..i + 3]);
+            rotated.push(255);
+        }
+    }
+    (rotated, (out_width as u32, out_height as u32))
+}
+
+#[cfg(test)]
+mod frame_capture_tests {
+    use super::*;
+
+    #[test]
+    fn rotate() {
+        // A 2×3 frame, bottom-to-top, with each pixel's red channel identifying
+        // it. Top-to-bottom, it looks like:
+        //   4 5
+        //   2 3
+        //   0 1
+        let pixels: Vec<u8> = (0..6).flat_map(|i| [i, 0, 0, 0]).collect();
+        let red = |(pixels, size): (Vec<u8>, (u32, u32))| -> (Vec<u8>, (u32, u32)) {
+            (pixels.iter().copied().step_by(4).collect(), size)
+        };
+
+        assert_eq!(
+            red(rotate_frame(&pixels, (2, 3), DeviceOrientation::Portrait)),
+            (vec![4, 5, 2, 3, 0, 1], (2, 3))
+        );
+        assert_eq!(
+            red(rotate_frame(
+                &pixels,
+                (2, 3),
+                DeviceOrientation::LandscapeLeft
+            )),
+            (vec![5, 3, 1, 4, 2, 0], (3, 2))
+        );
+        assert_eq!(
+            red(rotate_frame(
+                &pixels,
+                (2, 3),
+                DeviceOrientation::LandscapeRight
+            )),
+            (vec![0, 2, 4, 1, 3, 5], (3, 2))
+        );
+        assert!(rotate_frame(&pixels, (2, 3), DeviceOrientation::Portrait)
+            .0
+            .iter()
+            .skip(3)
+            .step_by(4)
+            .all(|&alpha| alpha == 255));
+    }
+
+    #[test]
+    fn compare() {
+        let a = [10, 20, 30, 255, 40, 50, 60, 255];
+        assert_eq!(compare_pixels(&a, &a, 0), (0, 0));
+        // Alpha is ignored.
+        let b = [12, 20, 30, 0, 40, 45, 60, 255];
+        assert_eq!(compare_pixels(&a, &b, 5), (0, 5));
+        assert_eq!(compare_pixels(&a, &b, 4), (1, 5));
+        assert_eq!(compare_pixels(&a, &b, 1), (2, 5));
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(
+            y4m_header((320, 480), 60.0),
+            "YUV4MPEG2 W320 H480 F60:1 Ip A1:1 C444\n"
+        );
+        assert_eq!(
+            y4m_header((480, 320), 29.97),
+            "YUV4MPEG2 W480 H320 F29970:1000 Ip A1:1 C444\n"
+        );
+
+        // White and black pixels map to the limits of the range.
+        let pixels = [255, 255, 255, 255, 0, 0, 0, 255];
+        assert_eq!(
+            encode_frame(&pixels, (2, 1), (2, 1)),
+            [235, 16, 128, 128, 128, 128]
+        );
+        // Padding is black and cropping is centered.
+        assert_eq!(
+            encode_frame(&pixels, (2, 1), (2, 3))[..6],
+            [16, 16, 235, 16, 16, 16]
+        );
+        assert_eq!(encode_frame(&pixels, (2, 1), (1, 1)), [235, 128, 128]);
+        // Pure red.
+        assert_eq!(
+            encode_frame(&[255, 0, 0, 255], (1, 1), (1, 1)),
+            [82, 90, 240]
+        );
+    }
+}
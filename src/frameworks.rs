@@ -35,14 +35,54 @@ pub mod opengles;
 pub mod store_kit;
 pub mod uikit;
 
+use crate::save_state::{StateReader, StateWriter};
+
 /// Container for state of various child modules
 #[derive(Default)]
 pub struct State {
     audio_toolbox: audio_toolbox::State,
     core_animation: core_animation::State,
+    dnssd: dnssd::State,
     foundation: foundation::State,
     media_player: media_player::State,
     openal: openal::State,
     opengles: opengles::State,
     uikit: uikit::State,
 }
+impl State {
+    /// Only Foundation's state is saved. UIKit, Core Animation and OpenGL ES
+    /// keep their state in host objects, which report themselves as
+    /// unsupported, so only the other frameworks are checked here.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        let State {
+            audio_toolbox,
+            core_animation: _,
+            dnssd,
+            foundation,
+            media_player,
+            openal,
+            opengles: _,
+            uikit: _,
+        } = self;
+        if audio_toolbox.in_use() {
+            writer.unsupported("Audio Toolbox");
+        }
+        if dnssd.in_use() {
+            writer.unsupported("DNS-SD");
+        }
+        if media_player.in_use() {
+            writer.unsupported("Media Player");
+        }
+        if openal.in_use() {
+            writer.unsupported("OpenAL");
+        }
+        writer.write(foundation);
+    }
+
+    pub fn load_state(reader: &mut StateReader) -> Result<State, String> {
+        Ok(State {
+            foundation: reader.read()?,
+            ..Default::default()
+        })
+    }
+}
@@ -7,6 +7,8 @@
 
 use crate::audio::openal as al;
 use crate::audio::openal::alc_types::{ALCcontext, ALCdevice};
+use crate::audio::recording;
+use crate::Environment;
 
 /// Macro for checking if an argument is null and returning `paramErr` if so.
 /// This seems to be what the real Audio Toolbox does, and some apps rely on it.
@@ -39,26 +41,38 @@ pub struct State {
     al_device_and_context: Option<(*mut ALCdevice, *mut ALCcontext)>,
 }
 impl State {
-    pub fn make_al_context_current(&mut self) -> ContextManager {
-        if self.al_device_and_context.is_none() {
-            let device = unsafe { al::alcOpenDevice(std::ptr::null()) };
-            assert!(!device.is_null());
-            let context = unsafe { al::alcCreateContext(device, std::ptr::null()) };
-            assert!(!context.is_null());
-            log_dbg!(
-                "New internal OpenAL device ({:?}) and context ({:?})",
-                device,
-                context
-            );
-            self.al_device_and_context = Some((device, context));
-        }
-        let (device, context) = self.al_device_and_context.unwrap();
-        assert!(!device.is_null() && !context.is_null());
+    /// Save states don't support Audio Toolbox yet. Audio queues always use
+    /// the OpenAL context.
+    pub(super) fn in_use(&self) -> bool {
+        self.al_device_and_context.is_some()
+            || !self.audio_file.audio_files.is_empty()
+            || !self.audio_components.audio_component_instances.is_empty()
+    }
+}
 
-        // This object will make sure the existing context, which will belong
-        // to the guest app, is restored once we're done.
-        ContextManager::make_active(context)
+/// Make the OpenAL context used internally by Audio Toolbox current, creating
+/// it if necessary.
+pub fn make_al_context_current(env: &mut Environment) -> ContextManager {
+    let state = &mut env.framework_state.audio_toolbox;
+    if state.al_device_and_context.is_none() {
+        let mut recorder = env.window.as_mut().and_then(|w| w.audio_recorder());
+        let device = recording::open_device(recorder.as_deref_mut());
+        assert!(!device.is_null());
+        let context = recording::create_context(device, recorder);
+        assert!(!context.is_null());
+        log_dbg!(
+            "New internal OpenAL device ({:?}) and context ({:?})",
+            device,
+            context
+        );
+        state.al_device_and_context = Some((device, context));
     }
+    let (device, context) = state.al_device_and_context.unwrap();
+    assert!(!device.is_null() && !context.is_null());
+
+    // This object will make sure the existing context, which will belong to
+    // the guest app, is restored once we're done.
+    ContextManager::make_active(context)
 }
 
 #[must_use]
@@ -13,7 +13,7 @@ use crate::audio::decode_ima4;
 use crate::audio::openal as al;
 use crate::audio::openal::al_types::*;
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::frameworks::audio_toolbox::ContextManager;
+use crate::frameworks::audio_toolbox::{make_al_context_current, ContextManager};
 use crate::frameworks::carbon_core::OSStatus;
 use crate::frameworks::core_audio_types::{
     debug_fourcc, fourcc, kAudioFormatAppleIMA4, kAudioFormatFlagIsBigEndian,
@@ -214,7 +214,7 @@ pub fn AudioQueueSetParameter(
 
     host_object.volume = in_value;
     if let Some(al_source) = host_object.al_source {
-        let _context_manager = env.framework_state.audio_toolbox.make_al_context_current();
+        let _context_manager = make_al_context_current(env);
         unsafe {
             al::alSourcef(al_source, al::AL_MAX_GAIN, in_value);
             assert!(al::alGetError() == 0);
@@ -495,8 +495,7 @@ fn prime_audio_queue(
     in_aq: AudioQueueRef,
     context_manager: Option<ContextManager>,
 ) -> ContextManager {
-    let context_manager = context_manager
-        .unwrap_or_else(|| env.framework_state.audio_toolbox.make_al_context_current());
+    let context_manager = context_manager.unwrap_or_else(|| make_al_context_current(env));
 
     let state = State::get(&mut env.framework_state);
     let host_object = state.audio_queues.get_mut(&in_aq).unwrap();
@@ -604,7 +603,7 @@ pub fn handle_audio_queue(env: &mut Environment, in_aq: AudioQueueRef) {
     // Collect used buffers and call the user callback so the app can provide
     // new buffers.
 
-    let context_manager = env.framework_state.audio_toolbox.make_al_context_current();
+    let context_manager = make_al_context_current(env);
 
     let state = State::get(&mut env.framework_state);
 
@@ -750,7 +749,7 @@ pub fn AudioQueueStart(
 pub fn AudioQueuePause(env: &mut Environment, in_aq: AudioQueueRef) -> OSStatus {
     return_if_null!(in_aq);
 
-    let _context_manager = env.framework_state.audio_toolbox.make_al_context_current();
+    let _context_manager = make_al_context_current(env);
 
     let state = State::get(&mut env.framework_state);
 
@@ -784,7 +783,7 @@ pub fn AudioQueueStop(env: &mut Environment, in_aq: AudioQueueRef, in_immediate:
     if in_immediate {
         log_dbg!("Performing immediate AudioQueueStop for {:?}.", in_aq);
 
-        let _context_manager = env.framework_state.audio_toolbox.make_al_context_current();
+        let _context_manager = make_al_context_current(env);
 
         let state = State::get(&mut env.framework_state);
         let host_object = state.audio_queues.get_mut(&in_aq).unwrap();
@@ -814,7 +813,7 @@ pub fn AudioQueueStop(env: &mut Environment, in_aq: AudioQueueRef, in_immediate:
 fn AudioQueueReset(env: &mut Environment, in_aq: AudioQueueRef) -> OSStatus {
     return_if_null!(in_aq);
 
-    let _context_manager = env.framework_state.audio_toolbox.make_al_context_current();
+    let _context_manager = make_al_context_current(env);
 
     let state = State::get(&mut env.framework_state);
 
@@ -901,7 +900,7 @@ pub fn AudioQueueDispose(
     }
 
     if let Some(al_source) = host_object.al_source {
-        let _context_manager = env.framework_state.audio_toolbox.make_al_context_current();
+        let _context_manager = make_al_context_current(env);
 
         unsafe {
             al::alSourceStop(al_source);
@@ -7,7 +7,7 @@
 //!
 //! Implemented using Audio Queue Services based on [the PlayingAudio example](https://developer.apple.com/library/archive/documentation/MusicAudio/Conceptual/AudioQueueProgrammingGuide/AQPlayback/PlayingAudio.html)
 
-use crate::dyld::HostFunction;
+use crate::dyld::{export_c_func, FunctionExports, HostFunction};
 use crate::frameworks::audio_toolbox::audio_file::{
     self, kAudioFilePropertyDataFormat, kAudioFilePropertyPacketSizeUpperBound,
     kAudioFileReadPermission, AudioFileClose, AudioFileGetProperty, AudioFileID, AudioFileOpenURL,
@@ -400,3 +400,9 @@ fn _touchHLE_AVAudioPlayerOutputBufferHelper(
         }
     }
 }
+
+/// Not exported to the app. This is only for finding the helper by name, e.g.
+/// when loading a save state.
+pub const PRIVATE_FUNCTIONS: FunctionExports = &[export_c_func!(
+    _touchHLE_AVAudioPlayerOutputBufferHelper(_, _, _)
+)];
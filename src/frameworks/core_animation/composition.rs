@@ -16,10 +16,11 @@ use crate::frameworks::core_graphics::{
 };
 use crate::gles::gles11_raw as gles11; // constants only
 use crate::gles::gles11_raw::types::*;
-use crate::gles::present::{present_frame, FpsCounter};
+use crate::gles::present::{present_frame, read_pixels, FpsCounter};
 use crate::gles::GLES;
 use crate::mem::Mem;
 use crate::objc::{id, msg, msg_class, nil, ObjC};
+use crate::replay;
 use crate::Environment;
 use std::time::{Duration, Instant};
 
@@ -67,7 +68,7 @@ pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
             .count_frame(format_args!("Core Animation compositor"));
     }
 
-    let now = Instant::now();
+    let now = replay::now(env);
     let interval = 1.0 / 60.0; // 60Hz
     let new_recomposite_next = if let Some(recomposite_next) = env
         .framework_state
@@ -131,6 +132,7 @@ pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
     let opacity = 1.0;
 
     let window = env.window.as_mut().unwrap();
+    let capture_frame = window.should_capture_frame();
     window.make_internal_gl_ctx_current();
     let gles = window.get_internal_gl_ctx();
 
@@ -231,6 +233,12 @@ pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
         assert_eq!(gles.GetError(), 0);
     }
 
+    let captured_pixels = capture_frame.then(|| {
+        let mut pixels = Vec::new();
+        unsafe { read_pixels(gles, fb_width as _, fb_height as _, &mut pixels) };
+        pixels
+    });
+
     // Present our rendered frame (bound to TEXTURE_2D). This copies it to the
     // default framebuffer (0) so we need to unbind our internal framebuffer.
     unsafe {
@@ -245,6 +253,11 @@ pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
     }
     env.window().swap_window();
 
+    if let Some(pixels) = captured_pixels {
+        env.window_mut()
+            .capture_frame(&pixels, (fb_width, fb_height));
+    }
+
     new_recomposite_next
 }
 
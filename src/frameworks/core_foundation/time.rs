@@ -11,6 +11,7 @@ use crate::frameworks::foundation::NSTimeInterval;
 use crate::libc::time::{time_t, timestamp_to_calendar_date};
 use crate::mem::SafeRead;
 use crate::objc::nil;
+use crate::replay;
 use crate::{impl_GuestRet_for_large_struct, Environment};
 use std::ops::Add;
 use std::time::{Duration, SystemTime};
@@ -38,8 +39,8 @@ impl_GuestRet_for_large_struct!(CFGregorianDate);
 
 /// Absolute time is measured in seconds relative to the absolute reference date
 /// of Jan 1 2001 00:00:00 GMT.
-fn CFAbsoluteTimeGetCurrent(_env: &mut Environment) -> CFAbsoluteTime {
-    SystemTime::now()
+fn CFAbsoluteTimeGetCurrent(env: &mut Environment) -> CFAbsoluteTime {
+    replay::system_time(env)
         .duration_since(apple_epoch())
         .unwrap()
         .as_secs_f64()
@@ -3,37 +3,455 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-//! DNS Service Discovery C
+//! DNS Service Discovery C API (`dns_sd.h`).
+//!
+//! This is implemented using touchHLE's own multicast DNS responder and
+//! querier, see [crate::mdns]. Only the `local.` domain is supported.
 
+use crate::abi::{CallFromHost, GuestFunction};
 use crate::dyld::FunctionExports;
 use crate::export_c_func;
-use crate::mem::{ConstPtr, ConstVoidPtr, MutVoidPtr};
-use crate::Environment;
+use crate::libc::posix_io::{self, FileDescriptor};
+use crate::libc::sys::socket::Readiness;
+use crate::mdns::{self, format_name, parse_name, Mdns, Name, ServiceName};
+use crate::mem::{ConstPtr, ConstVoidPtr, MutPtr, MutVoidPtr, SafeRead};
+use crate::{Environment, IoBlock, IoWait};
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::TryRecvError;
+
+#[derive(Default)]
+pub struct State {
+    /// Started when it's first needed.
+    mdns: Option<Mdns>,
+    services: HashMap<DNSServiceRef, DNSServiceHostObject>,
+}
+impl State {
+    fn get(framework_state: &mut crate::frameworks::State) -> &mut Self {
+        &mut framework_state.dnssd
+    }
+    /// Save states don't support DNS-SD yet.
+    pub(super) fn in_use(&self) -> bool {
+        self.mdns.is_some() || !self.services.is_empty()
+    }
+}
+
+/// For use by `NSNetService`: get the mDNS responder and querier, starting it
+/// if necessary.
+pub fn get_mdns(env: &mut Environment) -> &mut Mdns {
+    State::get(&mut env.framework_state)
+        .mdns
+        .get_or_insert_with(Mdns::spawn)
+}
+
+pub struct OpaqueDNSService {
+    _filler: u8,
+}
+unsafe impl SafeRead for OpaqueDNSService {}
+
+pub type DNSServiceRef = MutPtr<OpaqueDNSService>;
+
+type DNSServiceFlags = u32;
+const kDNSServiceFlagsMoreComing: DNSServiceFlags = 0x1;
+const kDNSServiceFlagsAdd: DNSServiceFlags = 0x2;
+const kDNSServiceFlagsNoAutoRename: DNSServiceFlags = 0x8;
 
 type DNSServiceErrorType = i32;
-const kDNSServiceErr_Unsupported: DNSServiceErrorType = -65544;
+const kDNSServiceErr_NoError: DNSServiceErrorType = 0;
+const kDNSServiceErr_BadParam: DNSServiceErrorType = -65540;
+const kDNSServiceErr_BadReference: DNSServiceErrorType = -65541;
+const kDNSServiceErr_NameConflict: DNSServiceErrorType = -65548;
+
+const kDNSServiceInterfaceIndexAny: u32 = 0;
+
+type DNSServiceRegisterReply = GuestFunction;
+type DNSServiceBrowseReply = GuestFunction;
+type DNSServiceResolveReply = GuestFunction;
+
+struct DNSServiceHostObject {
+    operation: mdns::Operation,
+    /// The service being registered or resolved, if this isn't a browse.
+    service: Option<ServiceName>,
+    callback: GuestFunction,
+    context: MutVoidPtr,
+    /// Events that haven't been passed to the callback yet.
+    pending: VecDeque<mdns::Event>,
+}
+
+/// Get the service type from a string like `_mygame._tcp`. Subtypes aren't
+/// supported and are ignored.
+fn read_service_type(env: &Environment, regtype: ConstPtr<u8>) -> Option<Name> {
+    if regtype.is_null() {
+        return None;
+    }
+    let regtype = env.mem.cstr_at_utf8(regtype).ok()?;
+    let (regtype, subtypes) = regtype.split_once(',').unwrap_or((regtype, ""));
+    if !subtypes.is_empty() {
+        log!("TODO: DNS-SD subtypes {:?} (ignored)", subtypes);
+    }
+    let service_type = parse_name(regtype);
+    if service_type.len() != 2 || !service_type.iter().all(|label| label.starts_with('_')) {
+        return None;
+    }
+    Some(service_type)
+}
+
+fn read_domain(env: &Environment, domain: ConstPtr<u8>) -> Name {
+    let local = parse_name("local.");
+    if domain.is_null() {
+        return local;
+    }
+    let domain = env.mem.cstr_at_utf8(domain).unwrap();
+    let parsed = parse_name(domain);
+    if !parsed.is_empty() && parsed != local {
+        log!("TODO: DNS-SD domain {:?}, using local. instead", domain);
+    }
+    local
+}
+
+fn add_service(
+    env: &mut Environment,
+    sd_ref_ptr: MutPtr<DNSServiceRef>,
+    operation: mdns::Operation,
+    service: Option<ServiceName>,
+    callback: GuestFunction,
+    context: MutVoidPtr,
+) {
+    let sd_ref = env.mem.alloc_and_write(OpaqueDNSService { _filler: 0 });
+    State::get(&mut env.framework_state).services.insert(
+        sd_ref,
+        DNSServiceHostObject {
+            operation,
+            service,
+            callback,
+            context,
+            pending: VecDeque::new(),
+        },
+    );
+    env.mem.write(sd_ref_ptr, sd_ref);
+}
+
+fn DNSServiceRegister(
+    env: &mut Environment,
+    sd_ref_ptr: MutPtr<DNSServiceRef>,
+    flags: DNSServiceFlags,
+    _interface_index: u32,
+    name: ConstPtr<u8>,
+    regtype: ConstPtr<u8>,
+    domain: ConstPtr<u8>,
+    host: ConstPtr<u8>,
+    port: u16, // network byte order
+    txt_len: u16,
+    txt_record: ConstVoidPtr,
+    callback: DNSServiceRegisterReply,
+    context: MutVoidPtr,
+) -> DNSServiceErrorType {
+    let Some(service_type) = read_service_type(env, regtype) else {
+        return kDNSServiceErr_BadParam;
+    };
+    let domain = read_domain(env, domain);
+    let name = if name.is_null() {
+        String::new()
+    } else {
+        env.mem.cstr_at_utf8(name).unwrap().to_string()
+    };
+    // Apple uses the device's name by default, see -[UIDevice name].
+    let name = if name.is_empty() {
+        env.options.device.model.to_string()
+    } else {
+        name
+    };
+    if !host.is_null() && env.mem.read(host) != b'\0' {
+        log!(
+            "TODO: DNSServiceRegister() with host {:?} (ignored)",
+            env.mem.cstr_at_utf8(host)
+        );
+    }
+    let port = u16::from_be(port);
+    let txt = if txt_len == 0 {
+        Vec::new()
+    } else {
+        env.mem.bytes_at(txt_record.cast(), txt_len.into()).to_vec()
+    };
+    let auto_rename = flags & kDNSServiceFlagsNoAutoRename == 0;
+
+    let service = ServiceName {
+        name,
+        service_type,
+        domain,
+    };
+    log_dbg!(
+        "DNSServiceRegister() for {:?} on port {}, auto rename: {}",
+        service,
+        port,
+        auto_rename
+    );
+    let operation = get_mdns(env).register(service.clone(), port, txt, auto_rename);
+    add_service(env, sd_ref_ptr, operation, Some(service), callback, context);
+    kDNSServiceErr_NoError
+}
 
-#[allow(clippy::too_many_arguments)]
 fn DNSServiceBrowse(
-    _env: &mut Environment,
-    _sdRef: MutVoidPtr,
-    _flags: u32,
-    _interfaceIndex: u32,
-    _regtype: ConstPtr<u8>,
-    _domain: ConstPtr<u8>,
-    _callBack: ConstVoidPtr,
-    _context: MutVoidPtr,
+    env: &mut Environment,
+    sd_ref_ptr: MutPtr<DNSServiceRef>,
+    _flags: DNSServiceFlags,
+    _interface_index: u32,
+    regtype: ConstPtr<u8>,
+    domain: ConstPtr<u8>,
+    callback: DNSServiceBrowseReply,
+    context: MutVoidPtr,
+) -> DNSServiceErrorType {
+    let Some(mut service_type) = read_service_type(env, regtype) else {
+        return kDNSServiceErr_BadParam;
+    };
+    service_type.extend(read_domain(env, domain));
+    log_dbg!("DNSServiceBrowse() for {}", format_name(&service_type));
+    let operation = get_mdns(env).browse(service_type);
+    add_service(env, sd_ref_ptr, operation, None, callback, context);
+    kDNSServiceErr_NoError
+}
+
+fn DNSServiceResolve(
+    env: &mut Environment,
+    sd_ref_ptr: MutPtr<DNSServiceRef>,
+    _flags: DNSServiceFlags,
+    _interface_index: u32,
+    name: ConstPtr<u8>,
+    regtype: ConstPtr<u8>,
+    domain: ConstPtr<u8>,
+    callback: DNSServiceResolveReply,
+    context: MutVoidPtr,
 ) -> DNSServiceErrorType {
-    // TODO: implement
-    kDNSServiceErr_Unsupported
+    let Some(service_type) = read_service_type(env, regtype) else {
+        return kDNSServiceErr_BadParam;
+    };
+    if name.is_null() {
+        return kDNSServiceErr_BadParam;
+    }
+    let service = ServiceName {
+        name: env.mem.cstr_at_utf8(name).unwrap().to_string(),
+        service_type,
+        domain: read_domain(env, domain),
+    };
+    log_dbg!("DNSServiceResolve() for {:?}", service);
+    let operation = get_mdns(env).resolve(service.clone());
+    add_service(env, sd_ref_ptr, operation, Some(service), callback, context);
+    kDNSServiceErr_NoError
+}
+
+fn DNSServiceRefSockFD(env: &mut Environment, sd_ref: DNSServiceRef) -> FileDescriptor {
+    if !State::get(&mut env.framework_state)
+        .services
+        .contains_key(&sd_ref)
+    {
+        return -1;
+    }
+    posix_io::dns_service_fd(env, sd_ref)
+}
+
+/// Check for new events from the mDNS thread, returning [true] if there are
+/// any waiting to be passed to the callback.
+fn has_pending_events(env: &mut Environment, sd_ref: DNSServiceRef) -> bool {
+    let Some(host_object) = State::get(&mut env.framework_state)
+        .services
+        .get_mut(&sd_ref)
+    else {
+        return false;
+    };
+    loop {
+        match host_object.operation.events.try_recv() {
+            Ok(event) => host_object.pending.push_back(event),
+            // If the mDNS thread couldn't start, there'll never be any events.
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+        }
+    }
+    !host_object.pending.is_empty()
+}
+
+/// For use by `select()`, `poll()` and [Environment::block_on_io]: the socket
+/// for a `DNSServiceRef` is readable when `DNSServiceProcessResult()` won't
+/// block.
+pub fn readiness(env: &mut Environment, sd_ref: DNSServiceRef) -> Readiness {
+    // DNSServiceProcessResult() fails right away if another thread has
+    // deallocated the DNSServiceRef.
+    let deallocated = !State::get(&mut env.framework_state)
+        .services
+        .contains_key(&sd_ref);
+    Readiness {
+        readable: deallocated || has_pending_events(env, sd_ref),
+        writable: false,
+        error: false,
+    }
+}
+
+fn DNSServiceProcessResult(env: &mut Environment, sd_ref: DNSServiceRef) -> DNSServiceErrorType {
+    if !State::get(&mut env.framework_state)
+        .services
+        .contains_key(&sd_ref)
+    {
+        return kDNSServiceErr_BadReference;
+    }
+    // Like Apple's implementation, this blocks until there's a result. It's
+    // called again once there is.
+    if !has_pending_events(env, sd_ref) {
+        env.block_on_io(IoBlock {
+            wait: IoWait::DnsService(sd_ref),
+            deadline: None,
+            progress: 0,
+        });
+        return kDNSServiceErr_NoError;
+    }
+    let host_object = State::get(&mut env.framework_state)
+        .services
+        .get_mut(&sd_ref)
+        .unwrap();
+    let events: Vec<mdns::Event> = host_object.pending.drain(..).collect();
+    let count = events.len();
+    for (i, event) in events.into_iter().enumerate() {
+        let more_coming = if i + 1 < count {
+            kDNSServiceFlagsMoreComing
+        } else {
+            0
+        };
+        call_callback(env, sd_ref, event, more_coming);
+        // The callback might have deallocated the DNSServiceRef.
+        if !State::get(&mut env.framework_state)
+            .services
+            .contains_key(&sd_ref)
+        {
+            break;
+        }
+    }
+    kDNSServiceErr_NoError
+}
+
+fn call_callback(
+    env: &mut Environment,
+    sd_ref: DNSServiceRef,
+    event: mdns::Event,
+    mut flags: DNSServiceFlags,
+) {
+    let host_object = &State::get(&mut env.framework_state).services[&sd_ref];
+    let callback = host_object.callback;
+    let context = host_object.context;
+    let service = host_object.service.clone();
+    if callback.to_ptr().is_null() {
+        return;
+    }
+    log_dbg!("Calling DNS-SD callback {:?} with {:?}", callback, event);
+    let is_found = matches!(event, mdns::Event::Found(_));
+
+    let mut strings = Vec::new();
+    let mut alloc_string = |env: &mut Environment, string: String| -> ConstPtr<u8> {
+        let ptr = env.mem.alloc_and_write_cstr(string.as_bytes());
+        strings.push(ptr);
+        ptr.cast_const()
+    };
+
+    match event {
+        mdns::Event::Registered(service) => {
+            let name = alloc_string(env, service.name);
+            let regtype = alloc_string(env, format_name(&service.service_type));
+            let domain = alloc_string(env, format_name(&service.domain));
+            let args = (
+                sd_ref,
+                flags | kDNSServiceFlagsAdd,
+                kDNSServiceErr_NoError,
+                name,
+                regtype,
+                domain,
+                context,
+            );
+            let () = callback.call_from_host(env, args);
+        }
+        mdns::Event::Found(service) | mdns::Event::Lost(service) => {
+            if is_found {
+                flags |= kDNSServiceFlagsAdd;
+            }
+            let name = alloc_string(env, service.name);
+            let regtype = alloc_string(env, format_name(&service.service_type));
+            let domain = alloc_string(env, format_name(&service.domain));
+            let args = (
+                sd_ref,
+                flags,
+                kDNSServiceInterfaceIndexAny,
+                kDNSServiceErr_NoError,
+                name,
+                regtype,
+                domain,
+                context,
+            );
+            let () = callback.call_from_host(env, args);
+        }
+        mdns::Event::NameConflict => {
+            let service = service.unwrap();
+            let name = alloc_string(env, service.name);
+            let regtype = alloc_string(env, format_name(&service.service_type));
+            let domain = alloc_string(env, format_name(&service.domain));
+            let args = (
+                sd_ref,
+                flags,
+                kDNSServiceErr_NameConflict,
+                name,
+                regtype,
+                domain,
+                context,
+            );
+            let () = callback.call_from_host(env, args);
+        }
+        mdns::Event::Resolved(resolved) => {
+            let full_name = alloc_string(env, format_name(&service.unwrap().full_name()));
+            let host_target = alloc_string(env, format_name(&resolved.host_name));
+            let txt_len: u16 = resolved.txt.len().try_into().unwrap();
+            let txt_record = env.mem.alloc(txt_len.max(1).into());
+            if txt_len != 0 {
+                env.mem
+                    .bytes_at_mut(txt_record.cast(), txt_len.into())
+                    .copy_from_slice(&resolved.txt);
+            }
+            let args = (
+                sd_ref,
+                flags,
+                kDNSServiceInterfaceIndexAny,
+                kDNSServiceErr_NoError,
+                full_name,
+                host_target,
+                resolved.port.to_be(),
+                txt_len,
+                txt_record.cast_const(),
+                context,
+            );
+            let () = callback.call_from_host(env, args);
+            env.mem.free(txt_record);
+        }
+    }
+
+    for string in strings {
+        env.mem.free(string.cast());
+    }
 }
 
-fn DNSServiceRefSockFD(_env: &mut Environment, _sdRef: MutVoidPtr) -> DNSServiceErrorType {
-    // TODO: implement
-    kDNSServiceErr_Unsupported
+fn DNSServiceRefDeallocate(env: &mut Environment, sd_ref: DNSServiceRef) {
+    // Dropping the operation cancels it.
+    if State::get(&mut env.framework_state)
+        .services
+        .remove(&sd_ref)
+        .is_none()
+    {
+        log!(
+            "Warning: DNSServiceRefDeallocate() with unknown {:?}",
+            sd_ref
+        );
+        return;
+    }
+    posix_io::close_dns_service_fd(env, sd_ref);
+    env.mem.free(sd_ref.cast());
 }
 
 pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(DNSServiceRegister(_, _, _, _, _, _, _, _, _, _, _, _)),
     export_c_func!(DNSServiceBrowse(_, _, _, _, _, _, _)),
+    export_c_func!(DNSServiceResolve(_, _, _, _, _, _, _, _)),
     export_c_func!(DNSServiceRefSockFD(_)),
+    export_c_func!(DNSServiceProcessResult(_)),
+    export_c_func!(DNSServiceRefDeallocate(_)),
 ];
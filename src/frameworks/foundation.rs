@@ -13,6 +13,7 @@
 
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::objc::id;
+use crate::save_state::impl_SaveState;
 use crate::Environment;
 
 pub mod ns_array;
@@ -33,6 +34,7 @@ pub mod ns_keyed_unarchiver;
 pub mod ns_locale;
 pub mod ns_lock;
 pub mod ns_log;
+pub mod ns_net_service;
 pub mod ns_notification;
 pub mod ns_notification_center;
 pub mod ns_null;
@@ -49,6 +51,7 @@ pub mod ns_timer;
 pub mod ns_url;
 pub mod ns_url_connection;
 pub mod ns_url_request;
+pub mod ns_url_response;
 pub mod ns_user_defaults;
 pub mod ns_value;
 
@@ -62,8 +65,21 @@ pub struct State {
     ns_null: ns_null::State,
     ns_run_loop: ns_run_loop::State,
     ns_string: ns_string::State,
+    ns_url_connection: ns_url_connection::State,
     ns_user_defaults: ns_user_defaults::State,
 }
+impl_SaveState!(State {
+    ns_autorelease_pool,
+    ns_bundle,
+    ns_file_manager,
+    ns_locale,
+    ns_notification_center,
+    ns_null,
+    ns_run_loop,
+    ns_string,
+    ns_url_connection,
+    ns_user_defaults,
+});
 
 pub type NSInteger = i32;
 pub type NSUInteger = u32;
@@ -11,9 +11,10 @@ use super::{ns_keyed_unarchiver, ns_string, ns_url, NSNotFound, NSUInteger};
 use crate::fs::GuestPath;
 use crate::mem::MutPtr;
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
-    NSZonePtr,
+    autorelease, host_object_loader, id, impl_HostObject_with_save_state, msg, msg_class, nil,
+    objc_classes, release, retain, ClassExports, HostObject, HostObjectLoaders, NSZonePtr,
 };
+use crate::save_state::impl_SaveState;
 use crate::Environment;
 
 struct ObjectEnumeratorHostObject {
@@ -25,7 +26,10 @@ impl HostObject for ObjectEnumeratorHostObject {}
 struct ArrayHostObject {
     array: Vec<id>,
 }
-impl HostObject for ArrayHostObject {}
+impl_HostObject_with_save_state!(ArrayHostObject);
+impl_SaveState!(ArrayHostObject { array });
+
+pub const HOST_OBJECTS: HostObjectLoaders = &[host_object_loader!(ArrayHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -5,7 +5,11 @@
  */
 //! `NSAutoreleasePool`.
 
-use crate::objc::{id, msg, objc_classes, release, ClassExports, HostObject, NSZonePtr};
+use crate::objc::{
+    host_object_loader, id, impl_HostObject_with_save_state, msg, objc_classes, release,
+    ClassExports, HostObjectLoaders, NSZonePtr,
+};
+use crate::save_state::impl_SaveState;
 use crate::{Environment, ThreadId};
 use std::collections::HashMap;
 
@@ -13,6 +17,7 @@ use std::collections::HashMap;
 pub struct State {
     pool_stacks: HashMap<ThreadId, Vec<id>>,
 }
+impl_SaveState!(State { pool_stacks });
 impl State {
     fn get(env: &mut Environment) -> &mut Self {
         &mut env.framework_state.foundation.ns_autorelease_pool
@@ -24,7 +29,13 @@ struct NSAutoreleasePoolHostObject {
     /// This is allowed to contain duplicates, which get released several times!
     objects: Vec<id>,
 }
-impl HostObject for NSAutoreleasePoolHostObject {}
+impl_HostObject_with_save_state!(NSAutoreleasePoolHostObject);
+impl_SaveState!(NSAutoreleasePoolHostObject {
+    original_thread,
+    objects,
+});
+
+pub const HOST_OBJECTS: HostObjectLoaders = &[host_object_loader!(NSAutoreleasePoolHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -16,6 +16,7 @@ use crate::fs::GuestPathBuf;
 use crate::objc::{
     autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
 };
+use crate::save_state::impl_SaveState;
 use crate::Environment;
 use std::collections::{HashMap, HashSet};
 
@@ -40,6 +41,10 @@ pub struct State {
     main_bundle: Option<id>,
     localization_tables: HashMap<id, id>, // NSString* to NSDictionary*
 }
+impl_SaveState!(State {
+    main_bundle,
+    localization_tables,
+});
 
 pub struct NSBundleHostObject {
     /// If this is [None], this is the main bundle's NSBundle instance and the
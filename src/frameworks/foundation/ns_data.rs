@@ -6,19 +6,26 @@
 //! `NSData` and `NSMutableData`.
 
 use super::ns_string::to_rust_string;
+use super::ns_url_connection::fetch_synchronously;
+use super::ns_url_request::to_http_request;
 use super::{NSRange, NSUInteger};
 use crate::fs::GuestPath;
 use crate::mem::{ConstPtr, ConstVoidPtr, MutPtr, MutVoidPtr, Ptr};
 use crate::objc::{
-    autorelease, id, msg, nil, objc_classes, release, retain, ClassExports, HostObject, NSZonePtr,
+    autorelease, host_object_loader, id, impl_HostObject_with_save_state, msg, nil, objc_classes,
+    release, retain, ClassExports, HostObjectLoaders, NSZonePtr,
 };
+use crate::save_state::impl_SaveState;
 use crate::{msg_class, Environment};
 
 struct NSDataHostObject {
     bytes: MutVoidPtr,
     length: NSUInteger,
 }
-impl HostObject for NSDataHostObject {}
+impl_HostObject_with_save_state!(NSDataHostObject);
+impl_SaveState!(NSDataHostObject { bytes, length });
+
+pub const HOST_OBJECTS: HostObjectLoaders = &[host_object_loader!(NSDataHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -65,6 +72,12 @@ pub const CLASSES: ClassExports = objc_classes! {
 + (id)dataWithContentsOfURL:(id)url {
     let new: id = msg![env; this alloc];
     let new: id = msg![env; new initWithContentsOfURL:url];
+    // This will be called again once the response is complete, see
+    // fetch_synchronously().
+    if env.is_blocked_on_io() {
+        release(env, new);
+        return nil;
+    }
     autorelease(env, new)
 }
 
@@ -92,13 +105,27 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (id)initWithContentsOfURL:(id)url { // NSURL *
-    let path: id = msg![env; url absoluteString];
-    let path = to_rust_string(env, path);
-    // TODO: file URL case
-    assert!(path.starts_with("http"));
-    log!("TODO: ignoring [(NSData*){:?} initWithContentsOfURL:{:?}]", this, path);
-    // TODO: actually load data once we have proper network support
-    nil
+    if url == nil {
+        return nil;
+    }
+    if msg![env; url isFileURL] {
+        let path: id = msg![env; url path];
+        return msg![env; this initWithContentsOfFile:path];
+    }
+    let request: id = msg_class![env; NSURLRequest requestWithURL:url];
+    let request = to_http_request(env, request);
+    log_dbg!("[(NSData*){:?} initWithContentsOfURL:{:?}]", this, request.url);
+    let Some(result) = fetch_synchronously(env, request) else {
+        // This will be called again with the same object once the response
+        // is complete.
+        return this;
+    };
+    let Ok((_response, bytes)) = result else {
+        release(env, this);
+        return nil;
+    };
+    set_bytes(env, this, &bytes);
+    this
 }
 
 - (id)initWithContentsOfFile:(id)path {
@@ -111,14 +138,7 @@ pub const CLASSES: ClassExports = objc_classes! {
         release(env, this);
         return nil;
     };
-    let size = bytes.len().try_into().unwrap();
-    let alloc = env.mem.alloc(size);
-    let slice = env.mem.bytes_at_mut(alloc.cast(), size);
-    slice.copy_from_slice(&bytes);
-
-    let host_object = env.objc.borrow_mut::<NSDataHostObject>(this);
-    host_object.bytes = alloc;
-    host_object.length = size;
+    set_bytes(env, this, &bytes);
     this
 }
 
@@ -234,6 +254,27 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 };
 
+/// Copy bytes into a newly-allocated `NSData*` that doesn't have any yet.
+fn set_bytes(env: &mut Environment, data: id, bytes: &[u8]) {
+    let size = bytes.len().try_into().unwrap();
+    let alloc = env.mem.alloc(size);
+    let slice = env.mem.bytes_at_mut(alloc.cast(), size);
+    slice.copy_from_slice(bytes);
+
+    let host_object = env.objc.borrow_mut::<NSDataHostObject>(data);
+    assert!(host_object.bytes.is_null() && host_object.length == 0);
+    host_object.bytes = alloc;
+    host_object.length = size;
+}
+
+/// Shortcut for host code, creates a new `NSData*` (not autoreleased)
+/// containing a copy of some bytes.
+pub fn from_rust_slice(env: &mut Environment, bytes: &[u8]) -> id {
+    let new: id = msg_class![env; NSData alloc];
+    set_bytes(env, new, bytes);
+    new
+}
+
 pub fn to_rust_slice(env: &mut Environment, data: id) -> &[u8] {
     let borrowed_data = env.objc.borrow::<NSDataHostObject>(data);
     assert!(!borrowed_data.bytes.is_null() && borrowed_data.length != 0);
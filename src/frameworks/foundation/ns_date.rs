@@ -8,7 +8,12 @@
 use super::NSTimeInterval;
 use crate::frameworks::core_foundation::time::apple_epoch;
 use crate::msg_class;
-use crate::objc::{autorelease, id, objc_classes, ClassExports, HostObject};
+use crate::objc::{
+    autorelease, host_object_loader, id, impl_HostObject_with_save_state, objc_classes,
+    ClassExports, HostObjectLoaders,
+};
+use crate::replay;
+use crate::save_state::impl_SaveState;
 
 use std::ops::Add;
 use std::time::{Duration, SystemTime};
@@ -16,7 +21,10 @@ use std::time::{Duration, SystemTime};
 struct NSDateHostObject {
     time_interval: NSTimeInterval,
 }
-impl HostObject for NSDateHostObject {}
+impl_HostObject_with_save_state!(NSDateHostObject);
+impl_SaveState!(NSDateHostObject { time_interval });
+
+pub const HOST_OBJECTS: HostObjectLoaders = &[host_object_loader!(NSDateHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -25,7 +33,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 @implementation NSDate: NSObject
 
 + (NSTimeInterval)timeIntervalSinceReferenceDate {
-    SystemTime::now()
+    replay::system_time(env)
         .duration_since(apple_epoch())
         .unwrap()
         .as_secs_f64()
@@ -34,7 +42,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 + (id)date {
     // "Date objects are immutable, representing an invariant time interval
     // relative to an absolute reference date (00:00:00 UTC on 1 January 2001)."
-    let time_interval = SystemTime::now()
+    let time_interval = replay::system_time(env)
         .duration_since(apple_epoch())
         .unwrap()
         .as_secs_f64();
@@ -60,12 +68,11 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (NSTimeInterval)timeIntervalSinceNow {
-    let host_object = env.objc.borrow::<NSDateHostObject>(this);
-    let time_interval = SystemTime::now()
+    let time_interval = replay::system_time(env)
         .duration_since(apple_epoch())
         .unwrap()
         .as_secs_f64();
-    time_interval - host_object.time_interval
+    time_interval - env.objc.borrow::<NSDateHostObject>(this).time_interval
 }
 
 - (NSTimeInterval)timeIntervalSince1970 {
@@ -11,11 +11,14 @@ use crate::abi::VaList;
 use crate::frameworks::foundation::ns_string::{from_rust_string, to_rust_string};
 use crate::fs::GuestPath;
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
-    NSZonePtr,
+    autorelease, host_object_loader, id, impl_HostObject_with_save_state, msg, msg_class, nil,
+    objc_classes, release, retain, ClassExports, HostObjectLoaders, NSZonePtr,
 };
+use crate::save_state::impl_SaveState;
 use crate::Environment;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
 
 /// Alias for the return type of the `hash` method of the `NSObject` protocol.
 type Hash = NSUInteger;
@@ -29,10 +32,17 @@ pub(super) struct DictionaryHostObject {
     /// hash-map, which is not ideally efficient. :)
     /// The keys are the hash values, the values are a list of key-value pairs
     /// where the keys have the same hash value.
-    map: HashMap<Hash, Vec<(id, id)>>,
+    ///
+    /// The hasher has fixed keys, unlike the default one, so that the order of
+    /// enumeration is the same every time touchHLE is run. Otherwise, replays
+    /// (see [crate::replay]) would diverge.
+    map: HashMap<Hash, Vec<(id, id)>, BuildHasherDefault<DefaultHasher>>,
     pub(super) count: NSUInteger,
 }
-impl HostObject for DictionaryHostObject {}
+impl_HostObject_with_save_state!(DictionaryHostObject);
+impl_SaveState!(DictionaryHostObject { map, count });
+
+pub const HOST_OBJECTS: HostObjectLoaders = &[host_object_loader!(DictionaryHostObject)];
 impl DictionaryHostObject {
     pub(super) fn lookup(&self, env: &mut Environment, key: id) -> id {
         let hash: Hash = msg![env; key hash];
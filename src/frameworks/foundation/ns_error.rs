@@ -4,10 +4,18 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use super::ns_dictionary::dict_from_keys_and_objects;
+use super::ns_string::{from_rust_string, get_static_str, to_rust_string};
 use crate::dyld::{ConstantExports, HostConstant};
 use crate::frameworks::foundation::NSInteger;
-use crate::objc::{id, nil, release, retain, ClassExports, HostObject, NSZonePtr};
-use crate::objc_classes;
+use crate::objc::{
+    autorelease, id, msg, msg_class, nil, release, retain, ClassExports, HostObject, NSZonePtr,
+};
+use crate::{http, objc_classes, Environment};
+
+pub const NSLocalizedDescriptionKey: &str = "NSLocalizedDescriptionKey";
+pub const NSURLErrorDomain: &str = "NSURLErrorDomain";
+pub const NSErrorFailingURLStringKey: &str = "NSErrorFailingURLStringKey";
 
 struct ErrorHostObject {
     domain: id,
@@ -32,6 +40,14 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
 
++ (id)errorWithDomain:(id)domain
+                 code:(NSInteger)code
+             userInfo:(id)user_info {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithDomain:domain code:code userInfo:user_info];
+    autorelease(env, new)
+}
+
 - (id)initWithDomain:(id)domain
                 code:(NSInteger)code
             userInfo:(id)user_info {
@@ -52,12 +68,80 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.dealloc_object(this, &mut env.mem);
 }
 
+- (id)domain {
+    env.objc.borrow::<ErrorHostObject>(this).domain
+}
+
+- (NSInteger)code {
+    env.objc.borrow::<ErrorHostObject>(this).code
+}
+
+- (id)userInfo {
+    env.objc.borrow::<ErrorHostObject>(this).user_info
+}
+
+- (id)localizedDescription {
+    let &ErrorHostObject { domain, code, user_info } = env.objc.borrow(this);
+    let key = get_static_str(env, NSLocalizedDescriptionKey);
+    let description: id = msg![env; user_info objectForKey:key];
+    if description != nil {
+        return description;
+    }
+    let description = format!(
+        "The operation couldn’t be completed. ({} error {}.)",
+        to_rust_string(env, domain),
+        code
+    );
+    let description = from_rust_string(env, description);
+    autorelease(env, description)
+}
+
+- (id)description {
+    let &ErrorHostObject { domain, code, .. } = env.objc.borrow(this);
+    let localized_description: id = msg![env; this localizedDescription];
+    let description = format!(
+        "Error Domain={} Code={} \"{}\"",
+        to_rust_string(env, domain),
+        code,
+        to_rust_string(env, localized_description)
+    );
+    let description = from_rust_string(env, description);
+    autorelease(env, description)
+}
 
 @end
 
 };
 
-pub const CONSTANTS: ConstantExports = &[(
-    "_NSLocalizedDescriptionKey",
-    HostConstant::NSString("NSLocalizedDescriptionKey"),
-)];
+/// Shortcut for host code, creates an autoreleased `NSError*` in
+/// `NSURLErrorDomain` for an error from the HTTP client.
+pub fn url_error(env: &mut Environment, error: http::Error, url: &str) -> id {
+    let domain = get_static_str(env, NSURLErrorDomain);
+    let description_key = get_static_str(env, NSLocalizedDescriptionKey);
+    let description = from_rust_string(env, error.description().to_string());
+    let url_key = get_static_str(env, NSErrorFailingURLStringKey);
+    let url = from_rust_string(env, url.to_string());
+    let user_info =
+        dict_from_keys_and_objects(env, &[(description_key, description), (url_key, url)]);
+    release(env, description);
+    release(env, url);
+    let code: NSInteger = error.code();
+    let error: id = msg_class![env; NSError errorWithDomain:domain code:code userInfo:user_info];
+    release(env, user_info);
+    error
+}
+
+pub const CONSTANTS: ConstantExports = &[
+    (
+        "_NSLocalizedDescriptionKey",
+        HostConstant::NSString(NSLocalizedDescriptionKey),
+    ),
+    (
+        "_NSURLErrorDomain",
+        HostConstant::NSString(NSURLErrorDomain),
+    ),
+    (
+        "_NSErrorFailingURLStringKey",
+        HostConstant::NSString(NSErrorFailingURLStringKey),
+    ),
+];
@@ -110,7 +110,7 @@ pub const CLASSES: ClassExports = objc_classes! {
     let fd = env.objc.borrow::<NSFileHandleHostObject>(this).fd;
     let bytes: ConstVoidPtr = msg![env; data bytes];
     let length: NSUInteger = msg![env; data length];
-    if posix_io::write(env, fd, bytes, length) == -1 {
+    if posix_io::write_direct(env, fd, bytes, length) == -1 {
         panic!("writeData: failed")
     }
 }
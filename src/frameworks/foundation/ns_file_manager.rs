@@ -12,6 +12,7 @@ use crate::mem::MutPtr;
 use crate::objc::{
     autorelease, id, msg, msg_class, nil, objc_classes, release, ClassExports, HostObject,
 };
+use crate::save_state::impl_SaveState;
 use crate::Environment;
 
 type NSSearchPathDirectory = NSUInteger;
@@ -59,6 +60,7 @@ pub const FUNCTIONS: FunctionExports = &[
 pub struct State {
     default_manager: Option<id>,
 }
+impl_SaveState!(State { default_manager });
 
 struct NSDirectoryEnumeratorHostObject {
     iterator: std::vec::IntoIter<GuestPathBuf>,
@@ -140,7 +142,7 @@ pub const CLASSES: ClassExports = objc_classes! {
     let path = ns_string::to_rust_string(env, path); // TODO: avoid copy
     match env.fs.remove(GuestPath::new(&path)) {
         Ok(()) => true,
-        Err(()) => {
+        Err(_) => {
             if !error.is_null() {
                 todo!(); // TODO: create an NSError if requested
             }
@@ -9,6 +9,7 @@ use super::{ns_array, ns_string};
 use crate::dyld::{ConstantExports, HostConstant};
 use crate::objc::{id, objc_classes, ClassExports, HostObject};
 use crate::options::Options;
+use crate::save_state::impl_SaveState;
 use crate::Environment;
 use std::ffi::CStr;
 
@@ -24,6 +25,10 @@ pub struct State {
     current_locale: Option<id>,
     preferred_languages: Option<id>,
 }
+impl_SaveState!(State {
+    current_locale,
+    preferred_languages,
+});
 impl State {
     fn get(env: &mut Environment) -> &mut State {
         &mut env.framework_state.foundation.ns_locale
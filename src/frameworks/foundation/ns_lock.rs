@@ -12,14 +12,25 @@ use crate::libc::pthread::mutex::{
 };
 use crate::mem::{guest_size_of, MutPtr};
 use crate::msg;
-use crate::objc::{id, nil, objc_classes, ClassExports, HostObject};
+use crate::objc::{
+    host_object_loader, id, impl_HostObject_with_save_state, nil, objc_classes, ClassExports,
+    HostObjectLoaders,
+};
+use crate::save_state::impl_SaveState;
 
 struct NSLockHostObject {
     pthread_mutex_ptr: MutPtr<pthread_mutex_t>,
     name: id,
     locked_by: Option<ThreadId>,
 }
-impl HostObject for NSLockHostObject {}
+impl_HostObject_with_save_state!(NSLockHostObject);
+impl_SaveState!(NSLockHostObject {
+    pthread_mutex_ptr,
+    name,
+    locked_by,
+});
+
+pub const HOST_OBJECTS: HostObjectLoaders = &[host_object_loader!(NSLockHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -0,0 +1,772 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSNetService` and `NSNetServiceBrowser`.
+//!
+//! These use touchHLE's own multicast DNS implementation (see [crate::mdns]),
+//! and the delegate is sent messages when the run loop polls for results.
+
+use super::ns_dictionary::{dict_from_keys_and_objects, DictionaryHostObject};
+use super::ns_run_loop::NSRunLoopMode;
+use super::ns_string::{from_rust_string, get_static_str, to_rust_string};
+use super::{ns_array, ns_data, ns_run_loop, NSInteger, NSTimeInterval, NSUInteger};
+use crate::dyld::{ConstantExports, HostConstant};
+use crate::frameworks::dnssd::get_mdns;
+use crate::mdns::{self, format_name, parse_name, ServiceName};
+use crate::objc::{
+    autorelease, id, msg, msg_class, msg_send, nil, objc_classes, release, retain, Class,
+    ClassExports, HostObject, NSZonePtr, SEL,
+};
+use crate::{replay, Environment};
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+type NSNetServiceOptions = NSUInteger;
+const NSNetServiceNoAutoRename: NSNetServiceOptions = 1;
+
+const NSNetServicesErrorCode: &str = "NSNetServicesErrorCode";
+const NSNetServicesErrorDomain: &str = "NSNetServicesErrorDomain";
+
+pub const CONSTANTS: ConstantExports = &[
+    (
+        "_NSNetServicesErrorCode",
+        HostConstant::NSString(NSNetServicesErrorCode),
+    ),
+    (
+        "_NSNetServicesErrorDomain",
+        HostConstant::NSString(NSNetServicesErrorDomain),
+    ),
+];
+
+type NSNetServicesError = NSInteger;
+const NSNetServicesCollisionError: NSNetServicesError = -72001;
+const NSNetServicesActivityInProgress: NSNetServicesError = -72003;
+const NSNetServicesBadArgumentError: NSNetServicesError = -72004;
+const NSNetServicesTimeoutError: NSNetServicesError = -72007;
+
+/// `kCFStreamErrorDomainNetServices`
+const NET_SERVICES_ERROR_DOMAIN: NSInteger = 10;
+
+/// How long `resolve` (without a timeout) takes to time out.
+const DEFAULT_RESOLVE_TIMEOUT: NSTimeInterval = 5.0;
+
+struct NSNetServiceHostObject {
+    /// Strong reference to `NSString*`.
+    domain: id,
+    /// Strong reference to `NSString*`.
+    service_type: id,
+    /// Strong reference to `NSString*`.
+    name: id,
+    /// The port to publish on, or the port found by resolving. `-1` if not
+    /// known yet.
+    port: NSInteger,
+    /// Weak reference.
+    delegate: id,
+    /// Strong reference to `NSData*`, or `nil`.
+    txt_record_data: id,
+    /// Strong reference to `NSString*`. Found by resolving.
+    host_name: id,
+    /// Strong reference to `NSArray*` of `NSData*` containing
+    /// `struct sockaddr_in`s, or `nil`. Found by resolving.
+    addresses: id,
+    /// The run loop the service will be or is scheduled in. Weak reference.
+    run_loop: id,
+    /// Publishing or resolving that's in progress.
+    operation: Option<mdns::Operation>,
+    /// When resolving times out.
+    resolve_deadline: Option<Instant>,
+}
+impl HostObject for NSNetServiceHostObject {}
+
+struct NSNetServiceBrowserHostObject {
+    /// Weak reference.
+    delegate: id,
+    /// The run loop the browser will be or is scheduled in. Weak reference.
+    run_loop: id,
+    /// The search, if one is in progress.
+    operation: Option<mdns::Operation>,
+    /// Strong references to the `NSNetService*` objects for services that
+    /// have been found, so the same object can be passed to the delegate when
+    /// one goes away.
+    services: Vec<(ServiceName, id)>,
+}
+impl HostObject for NSNetServiceBrowserHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSNetService: NSObject
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(NSNetServiceHostObject {
+        domain: nil,
+        service_type: nil,
+        name: nil,
+        port: -1,
+        delegate: nil,
+        txt_record_data: nil,
+        host_name: nil,
+        addresses: nil,
+        run_loop: nil,
+        operation: None,
+        resolve_deadline: None,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)dataFromTXTRecordDictionary:(id)dictionary { // NSDictionary*
+    let keys: Vec<id> = env.objc.borrow::<DictionaryHostObject>(dictionary).iter_keys().collect();
+    let mut txt = Vec::new();
+    for key in keys {
+        let value: id = msg![env; dictionary objectForKey:key];
+        let mut entry = to_rust_string(env, key).into_owned().into_bytes();
+        let value_length: NSUInteger = msg![env; value length];
+        if value_length != 0 {
+            entry.push(b'=');
+            entry.extend_from_slice(ns_data::to_rust_slice(env, value));
+        }
+        let Ok(entry_length) = u8::try_from(entry.len()) else {
+            log!("Warning: TXT record entry for {:?} is too long, skipping", key);
+            continue;
+        };
+        txt.push(entry_length);
+        txt.extend_from_slice(&entry);
+    }
+    let data = ns_data::from_rust_slice(env, &txt);
+    autorelease(env, data)
+}
+
++ (id)dictionaryFromTXTRecordData:(id)data { // NSData*
+    let length: NSUInteger = msg![env; data length];
+    let txt = if length == 0 {
+        Vec::new()
+    } else {
+        ns_data::to_rust_slice(env, data).to_vec()
+    };
+    let mut keys_and_objects = Vec::new();
+    let mut rest = &txt[..];
+    while let Some((&entry_length, after)) = rest.split_first() {
+        let Some(entry) = after.get(..entry_length.into()) else {
+            break;
+        };
+        rest = &after[entry.len()..];
+        let (key, value) = match entry.iter().position(|&c| c == b'=') {
+            Some(idx) => (&entry[..idx], &entry[idx + 1..]),
+            None => (entry, &[][..]),
+        };
+        if key.is_empty() {
+            continue;
+        }
+        let key = from_rust_string(env, String::from_utf8_lossy(key).into_owned());
+        let value = ns_data::from_rust_slice(env, value);
+        keys_and_objects.push((key, value));
+    }
+    let dictionary = dict_from_keys_and_objects(env, &keys_and_objects);
+    for (key, value) in keys_and_objects {
+        release(env, key);
+        release(env, value);
+    }
+    autorelease(env, dictionary)
+}
+
+- (id)initWithDomain:(id)domain // NSString*
+                type:(id)service_type // NSString*
+                name:(id)name // NSString*
+                port:(NSInteger)port {
+    let domain: id = msg![env; domain copy];
+    let service_type: id = msg![env; service_type copy];
+    let name: id = msg![env; name copy];
+    let host_object = env.objc.borrow_mut::<NSNetServiceHostObject>(this);
+    host_object.domain = domain;
+    host_object.service_type = service_type;
+    host_object.name = name;
+    host_object.port = port;
+    this
+}
+
+- (id)initWithDomain:(id)domain // NSString*
+                type:(id)service_type // NSString*
+                name:(id)name { // NSString*
+    msg![env; this initWithDomain:domain type:service_type name:name port:-1i32]
+}
+
+- (())dealloc {
+    let &NSNetServiceHostObject {
+        domain,
+        service_type,
+        name,
+        txt_record_data,
+        host_name,
+        addresses,
+        ..
+    } = env.objc.borrow(this);
+    release(env, domain);
+    release(env, service_type);
+    release(env, name);
+    release(env, txt_record_data);
+    release(env, host_name);
+    release(env, addresses);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (id)delegate {
+    env.objc.borrow::<NSNetServiceHostObject>(this).delegate
+}
+- (())setDelegate:(id)delegate {
+    env.objc.borrow_mut::<NSNetServiceHostObject>(this).delegate = delegate;
+}
+
+- (id)domain {
+    env.objc.borrow::<NSNetServiceHostObject>(this).domain
+}
+- (id)type {
+    env.objc.borrow::<NSNetServiceHostObject>(this).service_type
+}
+- (id)name {
+    env.objc.borrow::<NSNetServiceHostObject>(this).name
+}
+- (NSInteger)port {
+    env.objc.borrow::<NSNetServiceHostObject>(this).port
+}
+- (id)hostName {
+    env.objc.borrow::<NSNetServiceHostObject>(this).host_name
+}
+- (id)addresses {
+    env.objc.borrow::<NSNetServiceHostObject>(this).addresses
+}
+
+- (id)TXTRecordData {
+    env.objc.borrow::<NSNetServiceHostObject>(this).txt_record_data
+}
+- (bool)setTXTRecordData:(id)data { // NSData*
+    if env.objc.borrow::<NSNetServiceHostObject>(this).operation.is_some() {
+        log!("TODO: [(NSNetService*){:?} setTXTRecordData:] while publishing", this);
+        return false;
+    }
+    let data: id = msg![env; data copy];
+    let host_object = env.objc.borrow_mut::<NSNetServiceHostObject>(this);
+    let old_data = std::mem::replace(&mut host_object.txt_record_data, data);
+    release(env, old_data);
+    true
+}
+
+- (())scheduleInRunLoop:(id)run_loop // NSRunLoop*
+                forMode:(NSRunLoopMode)_mode {
+    // TODO: handle run loop modes
+    let host_object = env.objc.borrow_mut::<NSNetServiceHostObject>(this);
+    if host_object.operation.is_some() {
+        log!(
+            "TODO: [(NSNetService*){:?} scheduleInRunLoop:{:?} forMode:] while active",
+            this,
+            run_loop,
+        );
+        return;
+    }
+    host_object.run_loop = run_loop;
+}
+- (())removeFromRunLoop:(id)run_loop // NSRunLoop*
+                forMode:(NSRunLoopMode)_mode {
+    let host_object = env.objc.borrow_mut::<NSNetServiceHostObject>(this);
+    if host_object.operation.is_some() {
+        log!(
+            "TODO: [(NSNetService*){:?} removeFromRunLoop:{:?} forMode:] while active",
+            this,
+            run_loop,
+        );
+        return;
+    }
+    if host_object.run_loop == run_loop {
+        host_object.run_loop = nil;
+    }
+}
+
+- (())publish {
+    msg![env; this publishWithOptions:0u32]
+}
+
+- (())publishWithOptions:(NSNetServiceOptions)options {
+    if env.objc.borrow::<NSNetServiceHostObject>(this).operation.is_some() {
+        let selector = "netService:didNotPublish:";
+        send_error_to_delegate(env, this, selector, NSNetServicesActivityInProgress);
+        return;
+    }
+    let port = env.objc.borrow::<NSNetServiceHostObject>(this).port;
+    let (Some(service_name), Ok(port)) = (service_name(env, this), u16::try_from(port)) else {
+        let selector = "netService:didNotPublish:";
+        send_error_to_delegate(env, this, selector, NSNetServicesBadArgumentError);
+        return;
+    };
+    let txt_record_data = env.objc.borrow::<NSNetServiceHostObject>(this).txt_record_data;
+    let txt_length: NSUInteger = msg![env; txt_record_data length];
+    let txt = if txt_length == 0 {
+        Vec::new()
+    } else {
+        ns_data::to_rust_slice(env, txt_record_data).to_vec()
+    };
+    let auto_rename = options & NSNetServiceNoAutoRename == 0;
+
+    log_dbg!("Publishing NSNetService {:?} as {:?} on port {}", this, service_name, port);
+    send_to_delegate(env, this, "netServiceWillPublish:");
+    let operation = get_mdns(env).register(service_name, port, txt, auto_rename);
+    start(env, this, operation, None);
+}
+
+- (())resolve {
+    msg![env; this resolveWithTimeout:DEFAULT_RESOLVE_TIMEOUT]
+}
+
+- (())resolveWithTimeout:(NSTimeInterval)timeout {
+    if env.objc.borrow::<NSNetServiceHostObject>(this).operation.is_some() {
+        let selector = "netService:didNotResolve:";
+        send_error_to_delegate(env, this, selector, NSNetServicesActivityInProgress);
+        return;
+    }
+    let Some(service_name) = service_name(env, this) else {
+        let selector = "netService:didNotResolve:";
+        send_error_to_delegate(env, this, selector, NSNetServicesBadArgumentError);
+        return;
+    };
+
+    log_dbg!("Resolving NSNetService {:?} ({:?}) with timeout {}", this, service_name, timeout);
+    send_to_delegate(env, this, "netServiceWillResolve:");
+    let operation = get_mdns(env).resolve(service_name);
+    let deadline = replay::now(env) + Duration::from_secs_f64(timeout.max(0.0));
+    start(env, this, operation, Some(deadline));
+}
+
+- (())stop {
+    if stop(env, this) {
+        send_to_delegate(env, this, "netServiceDidStop:");
+    }
+}
+
+@end
+
+@implementation NSNetServiceBrowser: NSObject
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(NSNetServiceBrowserHostObject {
+        delegate: nil,
+        run_loop: nil,
+        operation: None,
+        services: Vec::new(),
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
+- (())dealloc {
+    let services = std::mem::take(
+        &mut env.objc.borrow_mut::<NSNetServiceBrowserHostObject>(this).services
+    );
+    for (_, service) in services {
+        release(env, service);
+    }
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (id)delegate {
+    env.objc.borrow::<NSNetServiceBrowserHostObject>(this).delegate
+}
+- (())setDelegate:(id)delegate {
+    env.objc.borrow_mut::<NSNetServiceBrowserHostObject>(this).delegate = delegate;
+}
+
+- (())scheduleInRunLoop:(id)run_loop // NSRunLoop*
+                forMode:(NSRunLoopMode)_mode {
+    // TODO: handle run loop modes
+    let host_object = env.objc.borrow_mut::<NSNetServiceBrowserHostObject>(this);
+    if host_object.operation.is_some() {
+        log!(
+            "TODO: [(NSNetServiceBrowser*){:?} scheduleInRunLoop:{:?} forMode:] while searching",
+            this,
+            run_loop,
+        );
+        return;
+    }
+    host_object.run_loop = run_loop;
+}
+- (())removeFromRunLoop:(id)run_loop // NSRunLoop*
+                forMode:(NSRunLoopMode)_mode {
+    let host_object = env.objc.borrow_mut::<NSNetServiceBrowserHostObject>(this);
+    if host_object.operation.is_some() {
+        log!(
+            "TODO: [(NSNetServiceBrowser*){:?} removeFromRunLoop:{:?} forMode:] while searching",
+            this,
+            run_loop,
+        );
+        return;
+    }
+    if host_object.run_loop == run_loop {
+        host_object.run_loop = nil;
+    }
+}
+
+- (())searchForServicesOfType:(id)service_type // NSString*
+                     inDomain:(id)domain { // NSString*
+    let selector = "netServiceBrowser:didNotSearch:";
+    if env.objc.borrow::<NSNetServiceBrowserHostObject>(this).operation.is_some() {
+        send_error_to_delegate(env, this, selector, NSNetServicesActivityInProgress);
+        return;
+    }
+    let service_type = to_rust_string(env, service_type);
+    let domain = to_rust_string(env, domain);
+    let Some(mut service_type) = parse_service_type(&service_type) else {
+        send_error_to_delegate(env, this, selector, NSNetServicesBadArgumentError);
+        return;
+    };
+    service_type.extend(parse_domain(&domain));
+
+    log_dbg!("NSNetServiceBrowser {:?} searching for {}", this, format_name(&service_type));
+    send_to_delegate(env, this, "netServiceBrowserWillSearch:");
+    let operation = get_mdns(env).browse(service_type);
+    let run_loop = env.objc.borrow::<NSNetServiceBrowserHostObject>(this).run_loop;
+    let run_loop = if run_loop == nil {
+        // TODO: use the current thread's run loop once other threads can have
+        // one
+        let run_loop: id = msg_class![env; NSRunLoop mainRunLoop];
+        env.objc.borrow_mut::<NSNetServiceBrowserHostObject>(this).run_loop = run_loop;
+        run_loop
+    } else {
+        run_loop
+    };
+    env.objc.borrow_mut::<NSNetServiceBrowserHostObject>(this).operation = Some(operation);
+    ns_run_loop::add_net_service(env, run_loop, this);
+}
+
+- (())stop {
+    let host_object = env.objc.borrow_mut::<NSNetServiceBrowserHostObject>(this);
+    // Dropping the operation stops the search.
+    if host_object.operation.take().is_none() {
+        return;
+    }
+    let run_loop = host_object.run_loop;
+    let services = std::mem::take(&mut host_object.services);
+    ns_run_loop::remove_net_service(env, run_loop, this);
+    for (_, service) in services {
+        release(env, service);
+    }
+    send_to_delegate(env, this, "netServiceBrowserDidStopSearch:");
+}
+
+@end
+
+};
+
+/// Get the service type from a string like `_mygame._tcp.`.
+fn parse_service_type(service_type: &str) -> Option<mdns::Name> {
+    let service_type = parse_name(service_type);
+    if service_type.len() != 2 || !service_type.iter().all(|label| label.starts_with('_')) {
+        return None;
+    }
+    Some(service_type)
+}
+
+/// An empty domain means the default one, and only `local.` is supported.
+fn parse_domain(domain: &str) -> mdns::Name {
+    let local = parse_name("local.");
+    let parsed = parse_name(domain);
+    if !parsed.is_empty() && parsed != local {
+        log!("TODO: Bonjour domain {:?}, using local. instead", domain);
+    }
+    local
+}
+
+fn service_name(env: &mut Environment, service: id) -> Option<ServiceName> {
+    let &NSNetServiceHostObject {
+        domain,
+        service_type,
+        name,
+        ..
+    } = env.objc.borrow(service);
+    let service_type = to_rust_string(env, service_type);
+    let service_type = parse_service_type(&service_type)?;
+    let domain = to_rust_string(env, domain);
+    let domain = parse_domain(&domain);
+    let name = to_rust_string(env, name).into_owned();
+    // Apple uses the device's name by default, see -[UIDevice name].
+    let name = if name.is_empty() {
+        env.options.device.model.to_string()
+    } else {
+        name
+    };
+    Some(ServiceName {
+        name,
+        service_type,
+        domain,
+    })
+}
+
+/// Start publishing or resolving.
+fn start(
+    env: &mut Environment,
+    service: id,
+    operation: mdns::Operation,
+    resolve_deadline: Option<Instant>,
+) {
+    let host_object = env.objc.borrow_mut::<NSNetServiceHostObject>(service);
+    host_object.operation = Some(operation);
+    host_object.resolve_deadline = resolve_deadline;
+    let run_loop = if host_object.run_loop == nil {
+        // TODO: use the current thread's run loop once other threads can have
+        // one
+        let run_loop: id = msg_class![env; NSRunLoop mainRunLoop];
+        env.objc
+            .borrow_mut::<NSNetServiceHostObject>(service)
+            .run_loop = run_loop;
+        run_loop
+    } else {
+        host_object.run_loop
+    };
+    ns_run_loop::add_net_service(env, run_loop, service);
+}
+
+/// Stop publishing or resolving, returning [true] if either was in progress.
+fn stop(env: &mut Environment, service: id) -> bool {
+    let host_object = env.objc.borrow_mut::<NSNetServiceHostObject>(service);
+    // Dropping the operation stops it.
+    if host_object.operation.take().is_none() {
+        return false;
+    }
+    host_object.resolve_deadline = None;
+    let run_loop = host_object.run_loop;
+    ns_run_loop::remove_net_service(env, run_loop, service);
+    true
+}
+
+fn get_delegate(env: &mut Environment, object: id) -> id {
+    let browser_class = env
+        .objc
+        .get_known_class("NSNetServiceBrowser", &mut env.mem);
+    let class: Class = msg![env; object class];
+    if env.objc.class_is_subclass_of(class, browser_class) {
+        env.objc
+            .borrow::<NSNetServiceBrowserHostObject>(object)
+            .delegate
+    } else {
+        env.objc.borrow::<NSNetServiceHostObject>(object).delegate
+    }
+}
+
+/// Get the selector for a delegate method, if the delegate of the service or
+/// browser has it.
+fn delegate_selector(env: &mut Environment, object: id, selector: &str) -> Option<(id, SEL)> {
+    let delegate = get_delegate(env, object);
+    if delegate == nil {
+        return None;
+    }
+    let sel: SEL = env
+        .objc
+        .register_host_selector(selector.to_string(), &mut env.mem);
+    let responds: bool = msg![env; delegate respondsToSelector:sel];
+    responds.then_some((delegate, sel))
+}
+
+fn send_to_delegate(env: &mut Environment, object: id, selector: &str) {
+    if let Some((delegate, sel)) = delegate_selector(env, object, selector) {
+        let () = msg_send(env, (delegate, sel, object));
+    }
+}
+
+/// Send a message like `netService:didNotPublish:` with an error dictionary.
+fn send_error_to_delegate(
+    env: &mut Environment,
+    object: id,
+    selector: &str,
+    error: NSNetServicesError,
+) {
+    log!(
+        "Warning: {:?} failed with error {}, sending {} to the delegate",
+        object,
+        error,
+        selector
+    );
+    let Some((delegate, sel)) = delegate_selector(env, object, selector) else {
+        return;
+    };
+    let code_key = get_static_str(env, NSNetServicesErrorCode);
+    let code: id = msg_class![env; NSNumber numberWithLongLong:(i64::from(error))];
+    let domain_key = get_static_str(env, NSNetServicesErrorDomain);
+    let domain: id =
+        msg_class![env; NSNumber numberWithLongLong:(i64::from(NET_SERVICES_ERROR_DOMAIN))];
+    let error_dict = dict_from_keys_and_objects(env, &[(code_key, code), (domain_key, domain)]);
+    autorelease(env, error_dict);
+    let () = msg_send(env, (delegate, sel, object, error_dict));
+}
+
+/// For use by `NSRunLoop`: deliver any new results for a service or browser to
+/// its delegate.
+pub(super) fn handle_net_service(env: &mut Environment, object: id) {
+    let browser_class = env
+        .objc
+        .get_known_class("NSNetServiceBrowser", &mut env.mem);
+    let class: Class = msg![env; object class];
+    // The delegate might release the object.
+    retain(env, object);
+    if env.objc.class_is_subclass_of(class, browser_class) {
+        handle_browser(env, object);
+    } else {
+        handle_service(env, object);
+    }
+    release(env, object);
+}
+
+fn handle_service(env: &mut Environment, service: id) {
+    loop {
+        let host_object = env.objc.borrow::<NSNetServiceHostObject>(service);
+        let Some(operation) = &host_object.operation else {
+            return;
+        };
+        let Ok(event) = operation.events.try_recv() else {
+            break;
+        };
+
+        let pool: id = msg_class![env; NSAutoreleasePool new];
+        match event {
+            mdns::Event::Registered(service_name) => {
+                let name = from_rust_string(env, service_name.name);
+                let host_object = env.objc.borrow_mut::<NSNetServiceHostObject>(service);
+                let old_name = std::mem::replace(&mut host_object.name, name);
+                release(env, old_name);
+                send_to_delegate(env, service, "netServiceDidPublish:");
+            }
+            mdns::Event::NameConflict => {
+                stop(env, service);
+                send_error_to_delegate(
+                    env,
+                    service,
+                    "netService:didNotPublish:",
+                    NSNetServicesCollisionError,
+                );
+            }
+            mdns::Event::Resolved(resolved) => {
+                set_resolved(env, service, resolved);
+                send_to_delegate(env, service, "netServiceDidResolveAddress:");
+            }
+            mdns::Event::Found(_) | mdns::Event::Lost(_) => unreachable!(),
+        }
+        release(env, pool);
+    }
+
+    let Some(deadline) = env
+        .objc
+        .borrow::<NSNetServiceHostObject>(service)
+        .resolve_deadline
+    else {
+        return;
+    };
+    if replay::now(env) < deadline {
+        return;
+    }
+    let resolved = env.objc.borrow::<NSNetServiceHostObject>(service).addresses != nil;
+    stop(env, service);
+    let pool: id = msg_class![env; NSAutoreleasePool new];
+    if resolved {
+        send_to_delegate(env, service, "netServiceDidStop:");
+    } else {
+        send_error_to_delegate(
+            env,
+            service,
+            "netService:didNotResolve:",
+            NSNetServicesTimeoutError,
+        );
+    }
+    release(env, pool);
+}
+
+/// Store the results of resolving a service.
+fn set_resolved(env: &mut Environment, service: id, resolved: mdns::Resolved) {
+    let host_name = from_rust_string(env, format_name(&resolved.host_name));
+    let txt_record_data = ns_data::from_rust_slice(env, &resolved.txt);
+    let addresses = resolved
+        .addresses
+        .iter()
+        .map(|&address| ns_data::from_rust_slice(env, &sockaddr_in_bytes(address, resolved.port)))
+        .collect();
+    let addresses = ns_array::from_vec(env, addresses);
+
+    let host_object = env.objc.borrow_mut::<NSNetServiceHostObject>(service);
+    host_object.port = resolved.port.into();
+    let old_objects = [
+        std::mem::replace(&mut host_object.host_name, host_name),
+        std::mem::replace(&mut host_object.txt_record_data, txt_record_data),
+        std::mem::replace(&mut host_object.addresses, addresses),
+    ];
+    for old_object in old_objects {
+        release(env, old_object);
+    }
+}
+
+/// The bytes of a `struct sockaddr_in`, which is what `addresses` contains.
+fn sockaddr_in_bytes(address: Ipv4Addr, port: u16) -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    bytes[0] = 16; // sin_len
+    bytes[1] = 2; // sin_family (AF_INET)
+    bytes[2..4].copy_from_slice(&port.to_be_bytes());
+    bytes[4..8].copy_from_slice(&address.octets());
+    bytes
+}
+
+fn handle_browser(env: &mut Environment, browser: id) {
+    let host_object = env.objc.borrow::<NSNetServiceBrowserHostObject>(browser);
+    let Some(operation) = &host_object.operation else {
+        return;
+    };
+    let events: Vec<mdns::Event> = operation.events.try_iter().collect();
+    let count = events.len();
+
+    for (i, event) in events.into_iter().enumerate() {
+        // The delegate might have stopped the search.
+        if env
+            .objc
+            .borrow::<NSNetServiceBrowserHostObject>(browser)
+            .operation
+            .is_none()
+        {
+            break;
+        }
+        let more_coming = i + 1 < count;
+
+        let pool: id = msg_class![env; NSAutoreleasePool new];
+        match event {
+            mdns::Event::Found(service_name) => {
+                let domain = from_rust_string(env, format_name(&service_name.domain));
+                let service_type = from_rust_string(env, format_name(&service_name.service_type));
+                let name = from_rust_string(env, service_name.name.clone());
+                let service: id = msg_class![env; NSNetService alloc];
+                let service: id = msg![env; service initWithDomain:domain
+                                                              type:service_type
+                                                              name:name];
+                release(env, domain);
+                release(env, service_type);
+                release(env, name);
+                env.objc
+                    .borrow_mut::<NSNetServiceBrowserHostObject>(browser)
+                    .services
+                    .push((service_name, service));
+                let selector = "netServiceBrowser:didFindService:moreComing:";
+                if let Some((delegate, sel)) = delegate_selector(env, browser, selector) {
+                    let () = msg_send(env, (delegate, sel, browser, service, more_coming));
+                }
+            }
+            mdns::Event::Lost(service_name) => {
+                let services = &mut env
+                    .objc
+                    .borrow_mut::<NSNetServiceBrowserHostObject>(browser)
+                    .services;
+                if let Some(idx) = services.iter().position(|(name, _)| *name == service_name) {
+                    let (_, service) = services.remove(idx);
+                    let selector = "netServiceBrowser:didRemoveService:moreComing:";
+                    if let Some((delegate, sel)) = delegate_selector(env, browser, selector) {
+                        let () = msg_send(env, (delegate, sel, browser, service, more_coming));
+                    }
+                    release(env, service);
+                }
+            }
+            _ => unreachable!(),
+        }
+        release(env, pool);
+    }
+}
@@ -12,6 +12,7 @@ use crate::objc::{
     id, msg, msg_class, msg_send, nil, objc_classes, release, retain, ClassExports, HostObject,
     NSZonePtr, SEL,
 };
+use crate::save_state::impl_SaveState;
 use std::borrow::Cow;
 use std::collections::HashMap;
 
@@ -19,6 +20,7 @@ use std::collections::HashMap;
 pub struct State {
     default_center: Option<id>,
 }
+impl_SaveState!(State { default_center });
 
 #[derive(Clone)]
 struct Observer {
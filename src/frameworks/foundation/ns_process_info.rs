@@ -7,7 +7,7 @@
 
 use super::NSTimeInterval;
 use crate::objc::{objc_classes, ClassExports};
-use std::time::Instant;
+use crate::replay;
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -16,7 +16,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 @implementation NSProcessInfo: NSObject
 
 + (NSTimeInterval)systemUptime {
-    Instant::now().duration_since(env.startup_time).as_secs_f64()
+    replay::now(env).duration_since(env.startup_time).as_secs_f64()
 }
 
 @end
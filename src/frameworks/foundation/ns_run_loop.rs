@@ -8,7 +8,7 @@
 //! Resources:
 //! - Apple's [Threading Programming Guide](https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/Multithreading/Introduction/Introduction.html)
 
-use super::{ns_string, ns_timer};
+use super::{ns_net_service, ns_string, ns_timer, ns_url_connection};
 use crate::dyld::{ConstantExports, HostConstant};
 use crate::frameworks::audio_toolbox::audio_queue::{handle_audio_queue, AudioQueueRef};
 use crate::frameworks::core_foundation::cf_run_loop::{
@@ -16,6 +16,8 @@ use crate::frameworks::core_foundation::cf_run_loop::{
 };
 use crate::frameworks::{core_animation, media_player, uikit};
 use crate::objc::{id, msg, objc_classes, release, retain, ClassExports, HostObject};
+use crate::replay;
+use crate::save_state::impl_SaveState;
 use crate::Environment;
 use std::time::{Duration, Instant};
 
@@ -40,6 +42,9 @@ pub const CONSTANTS: ConstantExports = &[
 pub struct State {
     main_thread_run_loop: Option<id>,
 }
+impl_SaveState!(State {
+    main_thread_run_loop,
+});
 
 struct NSRunLoopHostObject {
     /// Weak reference. Audio queue must remove itself when destroyed (TODO).
@@ -48,6 +53,13 @@ struct NSRunLoopHostObject {
     /// Strong references to `NSTimer*` in no particular order. Timers are owned
     /// by the run loop. The timer must remove itself when invalidated.
     timers: Vec<id>,
+    /// Strong references to loading `NSURLConnection*` in no particular order.
+    /// The connection must remove itself once loading ends.
+    url_connections: Vec<id>,
+    /// Strong references to publishing or resolving `NSNetService*` and
+    /// searching `NSNetServiceBrowser*` in no particular order. They must
+    /// remove themselves once stopped.
+    net_services: Vec<id>,
 }
 impl HostObject for NSRunLoopHostObject {}
 
@@ -64,6 +76,8 @@ pub const CLASSES: ClassExports = objc_classes! {
         let host_object = Box::new(NSRunLoopHostObject {
             audio_queues: Vec::new(),
             timers: Vec::new(),
+            url_connections: Vec::new(),
+            net_services: Vec::new(),
         });
         let new = env.objc.alloc_static_object(this, host_object, &mut env.mem);
         env.framework_state.foundation.ns_run_loop.main_thread_run_loop = Some(new);
@@ -159,6 +173,50 @@ pub(super) fn remove_timer(env: &mut Environment, run_loop: id, timer: id) {
     }
 }
 
+/// For use by NSURLConnection.
+pub(super) fn add_url_connection(env: &mut Environment, run_loop: id, connection: id) {
+    retain(env, connection);
+    env.objc
+        .borrow_mut::<NSRunLoopHostObject>(run_loop)
+        .url_connections
+        .push(connection);
+}
+
+/// For use by NSURLConnection so it can remove itself once loading ends.
+pub(super) fn remove_url_connection(env: &mut Environment, run_loop: id, connection: id) {
+    let connections = &mut env
+        .objc
+        .borrow_mut::<NSRunLoopHostObject>(run_loop)
+        .url_connections;
+    let connection_idx = connections
+        .iter()
+        .position(|&item| item == connection)
+        .unwrap();
+    connections.swap_remove(connection_idx);
+    release(env, connection);
+}
+
+/// For use by NSNetService and NSNetServiceBrowser.
+pub(super) fn add_net_service(env: &mut Environment, run_loop: id, service: id) {
+    retain(env, service);
+    env.objc
+        .borrow_mut::<NSRunLoopHostObject>(run_loop)
+        .net_services
+        .push(service);
+}
+
+/// For use by NSNetService and NSNetServiceBrowser so they can remove
+/// themselves once stopped.
+pub(super) fn remove_net_service(env: &mut Environment, run_loop: id, service: id) {
+    let services = &mut env
+        .objc
+        .borrow_mut::<NSRunLoopHostObject>(run_loop)
+        .net_services;
+    let service_idx = services.iter().position(|&item| item == service).unwrap();
+    services.swap_remove(service_idx);
+    release(env, service);
+}
+
 /// Run the run loop for just a single iteration. This is a special mode just
 /// for the app picker, since we don't have `runMode:beforeDate:` or
 /// `runUntilDate:` yet. (TODO: implement those to replace this.)
@@ -177,6 +235,8 @@ fn run_run_loop(env: &mut Environment, run_loop: id, single_iteration: bool) {
     // environment or to lock the object. Re-used each iteration for efficiency.
     let mut timers_tmp = Vec::new();
     let mut audio_queues_tmp = Vec::new();
+    let mut url_connections_tmp = Vec::new();
+    let mut net_services_tmp = Vec::new();
 
     fn limit_sleep_time(current: &mut Option<Instant>, new: Option<Instant>) {
         if let Some(new) = new {
@@ -219,6 +279,44 @@ fn run_run_loop(env: &mut Environment, run_loop: id, single_iteration: bool) {
 
         media_player::handle_players(env);
 
+        assert!(url_connections_tmp.is_empty());
+        url_connections_tmp.extend_from_slice(
+            &env.objc
+                .borrow::<NSRunLoopHostObject>(run_loop)
+                .url_connections,
+        );
+
+        for connection in url_connections_tmp.drain(..) {
+            // A delegate method might have cancelled this connection.
+            if env
+                .objc
+                .borrow::<NSRunLoopHostObject>(run_loop)
+                .url_connections
+                .contains(&connection)
+            {
+                ns_url_connection::handle_connection(env, connection);
+            }
+        }
+
+        assert!(net_services_tmp.is_empty());
+        net_services_tmp.extend_from_slice(
+            &env.objc
+                .borrow::<NSRunLoopHostObject>(run_loop)
+                .net_services,
+        );
+
+        for service in net_services_tmp.drain(..) {
+            // A delegate method might have stopped this service or browser.
+            if env
+                .objc
+                .borrow::<NSRunLoopHostObject>(run_loop)
+                .net_services
+                .contains(&service)
+            {
+                ns_net_service::handle_net_service(env, service);
+            }
+        }
+
         // Unfortunately, touchHLE has to poll for certain things repeatedly;
         // it can't just wait until the next event appears.
         //
@@ -234,8 +332,9 @@ fn run_run_loop(env: &mut Environment, run_loop: id, single_iteration: bool) {
         // or until the next scheduled event, whichever is sooner. iPhone OS
         // apps can't do more than 60fps so this should be fine.
         let limit = Duration::from_millis(1000 / 60);
+        let now = replay::now(env);
         env.sleep(
-            sleep_until.map_or(limit, |i| i.duration_since(Instant::now()).min(limit)),
+            sleep_until.map_or(limit, |i| i.duration_since(now).min(limit)),
             false,
         );
 
@@ -11,15 +11,20 @@ use super::ns_enumerator::{fast_enumeration_helper, NSFastEnumerationState};
 use super::NSUInteger;
 use crate::mem::MutPtr;
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, retain, ClassExports, HostObject, NSZonePtr,
+    autorelease, host_object_loader, id, impl_HostObject_with_save_state, msg, msg_class, nil,
+    objc_classes, retain, ClassExports, HostObjectLoaders, NSZonePtr,
 };
+use crate::save_state::impl_SaveState;
 
 /// Belongs to _touchHLE_NSSet
 #[derive(Debug, Default)]
 struct SetHostObject {
     dict: DictionaryHostObject,
 }
-impl HostObject for SetHostObject {}
+impl_HostObject_with_save_state!(SetHostObject);
+impl_SaveState!(SetHostObject { dict });
+
+pub const HOST_OBJECTS: HostObjectLoaders = &[host_object_loader!(SetHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -24,9 +24,10 @@ use crate::fs::GuestPath;
 use crate::mach_o::MachO;
 use crate::mem::{guest_size_of, ConstPtr, GuestUSize, Mem, MutPtr, Ptr, SafeRead};
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, retain, Class, ClassExports, HostObject,
-    NSZonePtr, ObjC,
+    autorelease, host_object_loader, id, impl_HostObject_with_save_state, msg, msg_class, nil,
+    objc_classes, retain, Class, ClassExports, HostObjectLoaders, NSZonePtr, ObjC,
 };
+use crate::save_state::{SaveState, StateReader, StateWriter};
 use crate::Environment;
 use std::borrow::Cow;
 use std::collections::HashMap;
@@ -64,6 +65,26 @@ impl State {
         &mut env.framework_state.foundation.ns_string
     }
 }
+impl SaveState for State {
+    fn save(&self, writer: &mut StateWriter) {
+        let pool: Vec<(String, id)> = self
+            .static_str_pool
+            .iter()
+            .map(|(&string, &object)| (string.to_string(), object))
+            .collect();
+        writer.write(&pool);
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        let pool: Vec<(String, id)> = reader.read()?;
+        Ok(State {
+            // Leaking is fine: the strings live as long as the app anyway.
+            static_str_pool: pool
+                .into_iter()
+                .map(|(string, object)| (&*Box::leak(string.into_boxed_str()), object))
+                .collect(),
+        })
+    }
+}
 
 /// Constant strings embedded in the app binary use this struct. The name is
 /// according to Ghidra, the rest is guesswork.
@@ -84,7 +105,28 @@ enum StringHostObject {
     /// Not necessarily well-formed UTF-16: might contain unpaired surrogates.
     Utf16(Utf16String),
 }
-impl HostObject for StringHostObject {}
+impl_HostObject_with_save_state!(StringHostObject);
+impl SaveState for StringHostObject {
+    fn save(&self, writer: &mut StateWriter) {
+        match self {
+            StringHostObject::Utf8(string) => {
+                writer.write(&0u8);
+                writer.write(&string.to_string());
+            }
+            StringHostObject::Utf16(string) => {
+                writer.write(&1u8);
+                writer.write(string);
+            }
+        }
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        match reader.read::<u8>()? {
+            0 => Ok(StringHostObject::Utf8(Cow::Owned(reader.read()?))),
+            1 => Ok(StringHostObject::Utf16(reader.read()?)),
+            _ => Err("Save state has an invalid NSString".to_string()),
+        }
+    }
+}
 impl StringHostObject {
     fn decode(bytes: Cow<[u8]>, encoding: NSStringEncoding) -> StringHostObject {
         if bytes.len() == 0 {
@@ -6,7 +6,7 @@
 //! `NSThread`.
 
 use super::NSTimeInterval;
-use crate::dyld::HostFunction;
+use crate::dyld::{export_c_func, FunctionExports, HostFunction};
 use crate::frameworks::core_foundation::CFTypeRef;
 use crate::libc::pthread::thread::{
     pthread_attr_init, pthread_attr_setdetachstate, pthread_attr_t, pthread_create, pthread_t,
@@ -152,3 +152,8 @@ pub fn _touchHLE_NSThreadInvocationHelper(env: &mut Environment, ns_thread_obj:
 
     // TODO: NSThread exit
 }
+
+/// Not exported to the app. This is only for finding the helper by name, e.g.
+/// when loading a save state.
+pub const PRIVATE_FUNCTIONS: FunctionExports =
+    &[export_c_func!(_touchHLE_NSThreadInvocationHelper(_))];
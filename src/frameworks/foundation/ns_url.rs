@@ -118,6 +118,10 @@ pub const CLASSES: ClassExports = objc_classes! {
     }
 }
 
+- (bool)isFileURL {
+    matches!(env.objc.borrow(this), NSURLHostObject::FileURL { .. })
+}
+
 - (id)path {
     match *env.objc.borrow(this) {
         NSURLHostObject::FileURL { ns_string, .. } => ns_string,
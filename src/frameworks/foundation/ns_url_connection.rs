@@ -4,9 +4,68 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! `NSURLConnection`.
+//!
+//! Requests are made by touchHLE's own HTTP client (see [crate::http]), and
+//! the delegate is sent messages when the run loop polls the connection.
 
-use crate::objc::{id, nil, ClassExports};
-use crate::objc_classes;
+use super::ns_run_loop::NSRunLoopMode;
+use super::ns_string::to_rust_string;
+use super::ns_url_request::to_http_request;
+use super::{ns_data, ns_error, ns_run_loop, ns_url_response};
+use crate::http;
+use crate::mem::MutPtr;
+use crate::objc::{
+    autorelease, id, msg, msg_class, msg_send, nil, objc_classes, release, retain, ClassExports,
+    HostObject, NSZonePtr, SEL,
+};
+use crate::save_state::{SaveState, StateReader, StateWriter};
+use crate::{Environment, IoBlock, IoWait, ThreadId};
+use std::collections::HashMap;
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+#[derive(Default)]
+pub struct State {
+    /// Requests made by [fetch_synchronously] that threads are waiting for.
+    fetches: HashMap<ThreadId, Fetch>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.foundation.ns_url_connection
+    }
+}
+impl SaveState for State {
+    fn save(&self, writer: &mut StateWriter) {
+        if !self.fetches.is_empty() {
+            writer.unsupported("NSURLConnection");
+        }
+    }
+    fn load(_reader: &mut StateReader) -> Result<Self, String> {
+        Ok(State::default())
+    }
+}
+
+struct Fetch {
+    /// Used to check that the same request is being made again after blocking.
+    url: String,
+    events: Receiver<http::Event>,
+    response: Option<http::Response>,
+    bytes: Vec<u8>,
+    /// Set once the request has finished or failed.
+    result: Option<Result<(), http::Error>>,
+}
+
+struct NSURLConnectionHostObject {
+    /// Strong reference to an immutable copy of the `NSURLRequest*`.
+    request: id,
+    /// Strong reference, which is only kept until loading ends, like Apple's.
+    delegate: id,
+    /// The run loop the connection will be or is scheduled in. Weak reference.
+    run_loop: id,
+    started: bool,
+    /// Events from the HTTP client. Present while loading.
+    events: Option<Receiver<http::Event>>,
+}
+impl HostObject for NSURLConnectionHostObject {}
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -14,19 +73,324 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 @implementation NSURLConnection: NSObject
 
-- (id)initWithRequest:(id)request // NSURLRequest *
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(NSURLConnectionHostObject {
+        request: nil,
+        delegate: nil,
+        run_loop: nil,
+        started: false,
+        events: None,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)connectionWithRequest:(id)request // NSURLRequest*
+                   delegate:(id)delegate {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithRequest:request delegate:delegate];
+    autorelease(env, new)
+}
+
++ (id)sendSynchronousRequest:(id)request // NSURLRequest*
+           returningResponse:(MutPtr<id>)response_ptr // NSURLResponse**
+                       error:(MutPtr<id>)error_ptr { // NSError**
+    let request = to_http_request(env, request);
+    let url = request.url.clone();
+    log_dbg!("[NSURLConnection sendSynchronousRequest:{:?} ...]", url);
+    let Some(result) = fetch_synchronously(env, request) else {
+        // This will be called again once the response is complete.
+        return nil;
+    };
+    match result {
+        Ok((response, bytes)) => {
+            if !response_ptr.is_null() {
+                let response = ns_url_response::from_http_response(env, &response);
+                let response = autorelease(env, response);
+                env.mem.write(response_ptr, response);
+            }
+            let data = ns_data::from_rust_slice(env, &bytes);
+            autorelease(env, data)
+        }
+        Err(error) => {
+            if !response_ptr.is_null() {
+                env.mem.write(response_ptr, nil);
+            }
+            if !error_ptr.is_null() {
+                let error = ns_error::url_error(env, error, &url);
+                env.mem.write(error_ptr, error);
+            }
+            nil
+        }
+    }
+}
+
+- (id)initWithRequest:(id)request // NSURLRequest*
+             delegate:(id)delegate {
+    msg![env; this initWithRequest:request delegate:delegate startImmediately:true]
+}
+
+- (id)initWithRequest:(id)request // NSURLRequest*
              delegate:(id)delegate
      startImmediately:(bool)start_immediately {
-    log!(
-        "TODO: [(NSURLConnection *){:?} initWithRequest:{:?} delegate:{:?} startImmediately:{}]",
-        this,
-        request,
-        delegate,
-        start_immediately,
-    );
-    nil
+    let request: id = msg![env; request copy];
+    retain(env, delegate);
+    let host_object = env.objc.borrow_mut::<NSURLConnectionHostObject>(this);
+    host_object.request = request;
+    host_object.delegate = delegate;
+    if start_immediately {
+        () = msg![env; this start];
+    }
+    this
+}
+
+- (())dealloc {
+    let &NSURLConnectionHostObject { request, delegate, .. } = env.objc.borrow(this);
+    release(env, request);
+    release(env, delegate);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (id)originalRequest {
+    env.objc.borrow::<NSURLConnectionHostObject>(this).request
+}
+
+- (())scheduleInRunLoop:(id)run_loop // NSRunLoop*
+                forMode:(NSRunLoopMode)_mode {
+    // TODO: handle run loop modes
+    let host_object = env.objc.borrow_mut::<NSURLConnectionHostObject>(this);
+    if host_object.started {
+        log!("TODO: [(NSURLConnection*){:?} scheduleInRunLoop:{:?} forMode:] after starting", this, run_loop);
+        return;
+    }
+    host_object.run_loop = run_loop;
+}
+
+- (())start {
+    let host_object = env.objc.borrow_mut::<NSURLConnectionHostObject>(this);
+    if host_object.started {
+        return;
+    }
+    host_object.started = true;
+    let request = host_object.request;
+    let run_loop = if host_object.run_loop == nil {
+        // TODO: use the current thread's run loop once other threads can have
+        // one
+        let run_loop: id = msg_class![env; NSRunLoop mainRunLoop];
+        env.objc.borrow_mut::<NSURLConnectionHostObject>(this).run_loop = run_loop;
+        run_loop
+    } else {
+        host_object.run_loop
+    };
+
+    let request = to_http_request(env, request);
+    log_dbg!("Starting NSURLConnection {:?} for {:?}", this, request.url);
+    let events = http::start(request, &env.options);
+    env.objc.borrow_mut::<NSURLConnectionHostObject>(this).events = Some(events);
+    ns_run_loop::add_url_connection(env, run_loop, this);
+}
+
+- (())cancel {
+    log_dbg!("Cancelling NSURLConnection {:?}", this);
+    // The delegate isn't sent any more messages.
+    if let Some(delegate) = stop(env, this) {
+        release(env, delegate);
+    }
 }
 
 @end
 
 };
+
+/// End loading, removing the connection from its run loop and returning the
+/// delegate (if loading hadn't already ended), which the caller must release.
+fn stop(env: &mut Environment, connection: id) -> Option<id> {
+    let host_object = env.objc.borrow_mut::<NSURLConnectionHostObject>(connection);
+    // Dropping the receiver makes the HTTP client give up.
+    host_object.events.take()?;
+    let delegate = std::mem::replace(&mut host_object.delegate, nil);
+    let run_loop = host_object.run_loop;
+    ns_run_loop::remove_url_connection(env, run_loop, connection);
+    Some(delegate)
+}
+
+/// Send a message with the connection and an optional second argument to the
+/// delegate, if it has a method for it.
+fn send_to_delegate(
+    env: &mut Environment,
+    delegate: id,
+    selector: &str,
+    connection: id,
+    arg: Option<id>,
+) {
+    if delegate == nil {
+        return;
+    }
+    let sel: SEL = env
+        .objc
+        .register_host_selector(selector.to_string(), &mut env.mem);
+    let responds: bool = msg![env; delegate respondsToSelector:sel];
+    if !responds {
+        return;
+    }
+    match arg {
+        Some(arg) => msg_send(env, (delegate, sel, connection, arg)),
+        None => msg_send(env, (delegate, sel, connection)),
+    }
+}
+
+/// For use by `NSRunLoop`: deliver any new events from the HTTP client to the
+/// connection's delegate.
+pub(super) fn handle_connection(env: &mut Environment, connection: id) {
+    // The delegate might release the connection.
+    retain(env, connection);
+    loop {
+        let host_object = env.objc.borrow::<NSURLConnectionHostObject>(connection);
+        let Some(events) = &host_object.events else {
+            // Loading has ended or was cancelled.
+            break;
+        };
+        let event = match events.try_recv() {
+            Ok(event) => event,
+            Err(TryRecvError::Empty) => break,
+            Err(TryRecvError::Disconnected) => {
+                http::Event::Failed(http::Error::NetworkConnectionLost)
+            }
+        };
+        let delegate = host_object.delegate;
+
+        let pool: id = msg_class![env; NSAutoreleasePool new];
+        match event {
+            http::Event::Response(response) => {
+                let response = ns_url_response::from_http_response(env, &response);
+                autorelease(env, response);
+                send_to_delegate(
+                    env,
+                    delegate,
+                    "connection:didReceiveResponse:",
+                    connection,
+                    Some(response),
+                );
+            }
+            http::Event::Data(bytes) => {
+                let data = ns_data::from_rust_slice(env, &bytes);
+                autorelease(env, data);
+                send_to_delegate(
+                    env,
+                    delegate,
+                    "connection:didReceiveData:",
+                    connection,
+                    Some(data),
+                );
+            }
+            http::Event::Finished => {
+                log_dbg!("NSURLConnection {:?} finished loading", connection);
+                let delegate = stop(env, connection).unwrap();
+                send_to_delegate(
+                    env,
+                    delegate,
+                    "connectionDidFinishLoading:",
+                    connection,
+                    None,
+                );
+                release(env, delegate);
+            }
+            http::Event::Failed(error) => {
+                log!(
+                    "Warning: NSURLConnection {:?} failed: {:?}",
+                    connection,
+                    error
+                );
+                let request = env
+                    .objc
+                    .borrow::<NSURLConnectionHostObject>(connection)
+                    .request;
+                let url: id = msg![env; request URL];
+                let url: id = msg![env; url absoluteString];
+                let url = to_rust_string(env, url);
+                let error = ns_error::url_error(env, error, &url);
+                let delegate = stop(env, connection).unwrap();
+                send_to_delegate(
+                    env,
+                    delegate,
+                    "connection:didFailWithError:",
+                    connection,
+                    Some(error),
+                );
+                release(env, delegate);
+            }
+        }
+        release(env, pool);
+    }
+    release(env, connection);
+}
+
+/// Shortcut for host code, makes a request and waits for the whole response.
+///
+/// Other guest threads keep running in the meantime: if the response isn't
+/// complete yet, the current thread blocks and [None] is returned. The host
+/// function must then return without any other side effects, and it will be
+/// called again once the response is complete (see
+/// [Environment::block_on_io]).
+pub fn fetch_synchronously(
+    env: &mut Environment,
+    request: http::Request,
+) -> Option<Result<(http::Response, Vec<u8>), http::Error>> {
+    let thread = env.current_thread;
+    let resumed = env.resumed_io(IoWait::UrlFetch).is_some();
+    let state = State::get(env);
+    let started = state
+        .fetches
+        .get(&thread)
+        .is_some_and(|fetch| resumed && fetch.url == request.url);
+    if !started {
+        let url = request.url.clone();
+        let events = http::start(request, &env.options);
+        State::get(env).fetches.insert(
+            thread,
+            Fetch {
+                url,
+                events,
+                response: None,
+                bytes: Vec::new(),
+                result: None,
+            },
+        );
+    }
+
+    if !fetch_is_finished(env, thread) {
+        env.block_on_io(IoBlock {
+            wait: IoWait::UrlFetch,
+            deadline: None,
+            progress: 0,
+        });
+        return None;
+    }
+    let Fetch {
+        response,
+        bytes,
+        result,
+        ..
+    } = State::get(env).fetches.remove(&thread).unwrap();
+    Some(result.unwrap().map(|()| (response.unwrap(), bytes)))
+}
+
+/// For use by [Environment::block_on_io]: check whether the request a thread
+/// made with [fetch_synchronously] has finished.
+pub fn fetch_is_finished(env: &mut Environment, thread: ThreadId) -> bool {
+    let Some(fetch) = State::get(env).fetches.get_mut(&thread) else {
+        return true;
+    };
+    while fetch.result.is_none() {
+        match fetch.events.try_recv() {
+            Ok(http::Event::Response(response)) => fetch.response = Some(response),
+            Ok(http::Event::Data(bytes)) => fetch.bytes.extend_from_slice(&bytes),
+            Ok(http::Event::Finished) => fetch.result = Some(Ok(())),
+            Ok(http::Event::Failed(error)) => fetch.result = Some(Err(error)),
+            Err(TryRecvError::Empty) => break,
+            Err(TryRecvError::Disconnected) => {
+                fetch.result = Some(Err(http::Error::NetworkConnectionLost))
+            }
+        }
+    }
+    fetch.result.is_some()
+}
@@ -5,12 +5,37 @@
  */
 //! `NSURLRequest and NSMutableURLRequest`.
 
-use super::{NSTimeInterval, NSUInteger};
-use crate::frameworks::foundation::ns_string::to_rust_string;
-use crate::msg;
-use crate::objc::{id, nil, objc_classes, ClassExports};
+use super::ns_dictionary::{dict_from_keys_and_objects, DictionaryHostObject};
+use super::ns_string::{from_rust_string, to_rust_string};
+use super::{ns_data, NSTimeInterval, NSUInteger};
+use crate::http;
+use crate::objc::{
+    autorelease, id, msg, nil, objc_classes, release, retain, Class, ClassExports, HostObject,
+    NSZonePtr,
+};
+use crate::Environment;
+use std::time::Duration;
 
 type NSURLRequestCachePolicy = NSUInteger;
+const NSURLRequestUseProtocolCachePolicy: NSURLRequestCachePolicy = 0;
+
+/// The default for `timeoutInterval`, in seconds.
+const DEFAULT_TIMEOUT_INTERVAL: NSTimeInterval = 60.0;
+
+#[derive(Clone)]
+struct NSURLRequestHostObject {
+    /// Strong reference to `NSURL*`.
+    url: id,
+    cache_policy: NSURLRequestCachePolicy,
+    timeout_interval: NSTimeInterval,
+    method: String,
+    /// Header names are case-insensitive, so they keep the case they were
+    /// first set with.
+    headers: Vec<(String, String)>,
+    /// Strong reference to `NSData*`, or `nil`.
+    body: id,
+}
+impl HostObject for NSURLRequestHostObject {}
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -18,26 +43,247 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 @implementation NSURLRequest: NSObject
 
-+ (id)requestWithURL:(id)url
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(NSURLRequestHostObject {
+        url: nil,
+        cache_policy: NSURLRequestUseProtocolCachePolicy,
+        timeout_interval: DEFAULT_TIMEOUT_INTERVAL,
+        method: "GET".to_string(),
+        headers: Vec::new(),
+        body: nil,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)requestWithURL:(id)url { // NSURL*
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithURL:url];
+    autorelease(env, new)
+}
+
++ (id)requestWithURL:(id)url // NSURL*
          cachePolicy:(NSURLRequestCachePolicy)cache_policy
      timeoutInterval:(NSTimeInterval)timeout_interval {
-    if url == nil {
-        return nil;
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithURL:url
+                                cachePolicy:cache_policy
+                            timeoutInterval:timeout_interval];
+    autorelease(env, new)
+}
+
+- (id)initWithURL:(id)url { // NSURL*
+    msg![env; this initWithURL:url
+                   cachePolicy:NSURLRequestUseProtocolCachePolicy
+               timeoutInterval:DEFAULT_TIMEOUT_INTERVAL]
+}
+
+- (id)initWithURL:(id)url // NSURL*
+      cachePolicy:(NSURLRequestCachePolicy)cache_policy
+  timeoutInterval:(NSTimeInterval)timeout_interval {
+    let url: id = msg![env; url copy];
+    let host_object = env.objc.borrow_mut::<NSURLRequestHostObject>(this);
+    host_object.url = url;
+    host_object.cache_policy = cache_policy;
+    host_object.timeout_interval = timeout_interval;
+    this
+}
+
+- (())dealloc {
+    let &NSURLRequestHostObject { url, body, .. } = env.objc.borrow(this);
+    release(env, url);
+    release(env, body);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+// NSCopying implementation
+- (id)copyWithZone:(NSZonePtr)_zone {
+    let class = env.objc.get_known_class("NSURLRequest", &mut env.mem);
+    copy_request(env, this, class)
+}
+
+// NSMutableCopying implementation
+- (id)mutableCopyWithZone:(NSZonePtr)_zone {
+    let class = env.objc.get_known_class("NSMutableURLRequest", &mut env.mem);
+    copy_request(env, this, class)
+}
+
+- (id)URL {
+    env.objc.borrow::<NSURLRequestHostObject>(this).url
+}
+
+- (NSURLRequestCachePolicy)cachePolicy {
+    env.objc.borrow::<NSURLRequestHostObject>(this).cache_policy
+}
+
+- (NSTimeInterval)timeoutInterval {
+    env.objc.borrow::<NSURLRequestHostObject>(this).timeout_interval
+}
+
+- (id)HTTPMethod {
+    let method = env.objc.borrow::<NSURLRequestHostObject>(this).method.clone();
+    let method = from_rust_string(env, method);
+    autorelease(env, method)
+}
+
+- (id)HTTPBody {
+    env.objc.borrow::<NSURLRequestHostObject>(this).body
+}
+
+- (id)allHTTPHeaderFields {
+    let headers = env.objc.borrow::<NSURLRequestHostObject>(this).headers.clone();
+    let headers: Vec<(id, id)> = headers
+        .into_iter()
+        .map(|(name, value)| (from_rust_string(env, name), from_rust_string(env, value)))
+        .collect();
+    let dict = dict_from_keys_and_objects(env, &headers);
+    for (name, value) in headers {
+        release(env, name);
+        release(env, value);
+    }
+    autorelease(env, dict)
+}
+
+- (id)valueForHTTPHeaderField:(id)field { // NSString*
+    let field = to_rust_string(env, field);
+    let value = env
+        .objc
+        .borrow::<NSURLRequestHostObject>(this)
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(&field))
+        .map(|(_, value)| value.clone());
+    match value {
+        Some(value) => {
+            let value = from_rust_string(env, value);
+            autorelease(env, value)
+        }
+        None => nil,
     }
-    let url_str: id = msg![env; url path];
-    log!(
-        "TODO: [NSURLRequest requestWithURL:{} cachePolicy:{} timeoutInterval:{}]",
-        to_rust_string(env, url_str),
-        cache_policy,
-        timeout_interval,
-    );
-    nil
 }
 
 @end
 
 @implementation NSMutableURLRequest: NSURLRequest
-//TODO
+
+- (())setURL:(id)url { // NSURL*
+    let url: id = msg![env; url copy];
+    let host_object = env.objc.borrow_mut::<NSURLRequestHostObject>(this);
+    let old_url = std::mem::replace(&mut host_object.url, url);
+    release(env, old_url);
+}
+
+- (())setCachePolicy:(NSURLRequestCachePolicy)cache_policy {
+    env.objc.borrow_mut::<NSURLRequestHostObject>(this).cache_policy = cache_policy;
+}
+
+- (())setTimeoutInterval:(NSTimeInterval)timeout_interval {
+    env.objc.borrow_mut::<NSURLRequestHostObject>(this).timeout_interval = timeout_interval;
+}
+
+- (())setHTTPMethod:(id)method { // NSString*
+    let method = to_rust_string(env, method).to_ascii_uppercase();
+    env.objc.borrow_mut::<NSURLRequestHostObject>(this).method = method;
+}
+
+- (())setHTTPBody:(id)body { // NSData*
+    let body: id = msg![env; body copy];
+    let host_object = env.objc.borrow_mut::<NSURLRequestHostObject>(this);
+    let old_body = std::mem::replace(&mut host_object.body, body);
+    release(env, old_body);
+}
+
+- (())setValue:(id)value // NSString*
+forHTTPHeaderField:(id)field { // NSString*
+    let field = to_rust_string(env, field).into_owned();
+    let value = (value != nil).then(|| to_rust_string(env, value).into_owned());
+    let headers = &mut env.objc.borrow_mut::<NSURLRequestHostObject>(this).headers;
+    let existing = headers.iter().position(|(name, _)| name.eq_ignore_ascii_case(&field));
+    match (existing, value) {
+        (Some(idx), Some(value)) => headers[idx].1 = value,
+        (Some(idx), None) => {
+            headers.remove(idx);
+        }
+        (None, Some(value)) => headers.push((field, value)),
+        (None, None) => (),
+    }
+}
+
+- (())addValue:(id)value // NSString*
+forHTTPHeaderField:(id)field { // NSString*
+    let field = to_rust_string(env, field).into_owned();
+    let value = to_rust_string(env, value).into_owned();
+    let headers = &mut env.objc.borrow_mut::<NSURLRequestHostObject>(this).headers;
+    match headers.iter_mut().find(|(name, _)| name.eq_ignore_ascii_case(&field)) {
+        Some((_, existing)) => {
+            existing.push(',');
+            existing.push_str(&value);
+        }
+        None => headers.push((field, value)),
+    }
+}
+
+- (())setAllHTTPHeaderFields:(id)fields { // NSDictionary*
+    let keys: Vec<id> = if fields == nil {
+        Vec::new()
+    } else {
+        env.objc.borrow::<DictionaryHostObject>(fields).iter_keys().collect()
+    };
+    let mut headers = Vec::new();
+    for key in keys {
+        let value: id = msg![env; fields objectForKey:key];
+        let name = to_rust_string(env, key).into_owned();
+        let value = to_rust_string(env, value).into_owned();
+        headers.push((name, value));
+    }
+    env.objc.borrow_mut::<NSURLRequestHostObject>(this).headers = headers;
+}
+
 @end
 
 };
+
+fn copy_request(env: &mut Environment, request: id, class: Class) -> id {
+    let host_object = env.objc.borrow::<NSURLRequestHostObject>(request).clone();
+    retain(env, host_object.url);
+    retain(env, host_object.body);
+    env.objc
+        .alloc_object(class, Box::new(host_object), &mut env.mem)
+}
+
+/// Shortcut for host code, gets the parts of a request that the HTTP client
+/// needs.
+pub fn to_http_request(env: &mut Environment, request: id) -> http::Request {
+    let NSURLRequestHostObject {
+        url,
+        timeout_interval,
+        method,
+        headers,
+        body,
+        ..
+    } = env.objc.borrow::<NSURLRequestHostObject>(request).clone();
+
+    let url = if url == nil {
+        String::new()
+    } else {
+        let url: id = msg![env; url absoluteString];
+        to_rust_string(env, url).into_owned()
+    };
+    let body_length: NSUInteger = msg![env; body length];
+    let body = if body_length == 0 {
+        Vec::new()
+    } else {
+        ns_data::to_rust_slice(env, body).to_vec()
+    };
+    let timeout_interval = if timeout_interval > 0.0 {
+        timeout_interval
+    } else {
+        DEFAULT_TIMEOUT_INTERVAL
+    };
+    http::Request {
+        method,
+        url,
+        headers,
+        body,
+        timeout: Duration::from_secs_f64(timeout_interval),
+    }
+}
@@ -0,0 +1,235 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSURLResponse` and `NSHTTPURLResponse`.
+
+use super::ns_dictionary::dict_from_keys_and_objects;
+use super::ns_string::from_rust_string;
+use super::NSInteger;
+use crate::http;
+use crate::objc::{
+    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
+    NSZonePtr,
+};
+use crate::Environment;
+
+/// Value of `expectedContentLength` when the length isn't known.
+const NSURLResponseUnknownLength: i64 = -1;
+
+struct NSURLResponseHostObject {
+    /// Strong reference to `NSURL*`.
+    url: id,
+    /// Strong reference to `NSString*`, or `nil`.
+    mime_type: id,
+    expected_content_length: i64,
+    /// Strong reference to `NSString*`, or `nil`.
+    text_encoding_name: id,
+    /// Only used by `NSHTTPURLResponse`.
+    status_code: NSInteger,
+    /// Strong reference to `NSDictionary*`, or `nil`. Only used by
+    /// `NSHTTPURLResponse`.
+    header_fields: id,
+}
+impl HostObject for NSURLResponseHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSURLResponse: NSObject
+
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(NSURLResponseHostObject {
+        url: nil,
+        mime_type: nil,
+        expected_content_length: NSURLResponseUnknownLength,
+        text_encoding_name: nil,
+        status_code: 0,
+        header_fields: nil,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
+- (id)initWithURL:(id)url // NSURL*
+         MIMEType:(id)mime_type // NSString*
+expectedContentLength:(NSInteger)length
+ textEncodingName:(id)text_encoding_name { // NSString*
+    let url: id = msg![env; url copy];
+    let mime_type: id = msg![env; mime_type copy];
+    let text_encoding_name: id = msg![env; text_encoding_name copy];
+    let host_object = env.objc.borrow_mut::<NSURLResponseHostObject>(this);
+    host_object.url = url;
+    host_object.mime_type = mime_type;
+    host_object.expected_content_length = length.into();
+    host_object.text_encoding_name = text_encoding_name;
+    this
+}
+
+- (())dealloc {
+    let &NSURLResponseHostObject {
+        url,
+        mime_type,
+        text_encoding_name,
+        header_fields,
+        ..
+    } = env.objc.borrow(this);
+    release(env, url);
+    release(env, mime_type);
+    release(env, text_encoding_name);
+    release(env, header_fields);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+// NSCopying implementation
+- (id)copyWithZone:(NSZonePtr)_zone {
+    retain(env, this)
+}
+
+- (id)URL {
+    env.objc.borrow::<NSURLResponseHostObject>(this).url
+}
+
+- (id)MIMEType {
+    env.objc.borrow::<NSURLResponseHostObject>(this).mime_type
+}
+
+- (i64)expectedContentLength {
+    env.objc.borrow::<NSURLResponseHostObject>(this).expected_content_length
+}
+
+- (id)textEncodingName {
+    env.objc.borrow::<NSURLResponseHostObject>(this).text_encoding_name
+}
+
+@end
+
+@implementation NSHTTPURLResponse: NSURLResponse
+
++ (id)localizedStringForStatusCode:(NSInteger)status_code {
+    // These are the strings Apple uses, which aren't quite the usual reason
+    // phrases.
+    let string = match status_code {
+        100 => "continue",
+        101 => "switching protocols",
+        200 => "no error",
+        201 => "created",
+        202 => "accepted",
+        203 => "non-authoritative information",
+        204 => "no content",
+        205 => "reset content",
+        206 => "partial content",
+        300 => "multiple choices",
+        301 => "moved permanently",
+        302 => "found",
+        303 => "see other",
+        304 => "not modified",
+        305 => "needs proxy",
+        307 => "temporarily redirected",
+        400 => "bad request",
+        401 => "unauthorized",
+        402 => "payment required",
+        403 => "forbidden",
+        404 => "not found",
+        405 => "method not allowed",
+        406 => "unacceptable",
+        407 => "proxy authentication required",
+        408 => "request timed out",
+        409 => "conflict",
+        410 => "no longer exists",
+        411 => "length required",
+        412 => "precondition failed",
+        413 => "request too large",
+        414 => "requested URL too long",
+        415 => "unsupported media type",
+        416 => "requested range not satisfiable",
+        417 => "expectation failed",
+        500 => "internal server error",
+        501 => "unimplemented",
+        502 => "bad gateway",
+        503 => "service unavailable",
+        504 => "gateway timed out",
+        505 => "unsupported version",
+        100..=199 => "informational",
+        200..=299 => "success",
+        300..=399 => "redirected",
+        400..=499 => "client error",
+        500..=599 => "server error",
+        _ => "unknown",
+    };
+    let string = from_rust_string(env, string.to_string());
+    autorelease(env, string)
+}
+
+- (NSInteger)statusCode {
+    env.objc.borrow::<NSURLResponseHostObject>(this).status_code
+}
+
+- (id)allHeaderFields {
+    env.objc.borrow::<NSURLResponseHostObject>(this).header_fields
+}
+
+@end
+
+};
+
+/// Shortcut for host code, creates an `NSHTTPURLResponse*` (not autoreleased)
+/// for a response from the HTTP client.
+pub fn from_http_response(env: &mut Environment, response: &http::Response) -> id {
+    let url = from_rust_string(env, response.url.clone());
+    let ns_url: id = msg_class![env; NSURL alloc];
+    let ns_url: id = msg![env; ns_url initWithString:url];
+    release(env, url);
+
+    let mime_type = response
+        .mime_type()
+        .map_or(nil, |mime_type| from_rust_string(env, mime_type));
+    let text_encoding_name = response
+        .text_encoding_name()
+        .map_or(nil, |name| from_rust_string(env, name));
+
+    // Repeated headers are combined into a single comma-separated value.
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for (name, value) in &response.headers {
+        match headers
+            .iter_mut()
+            .find(|(existing, _)| existing.eq_ignore_ascii_case(name))
+        {
+            Some((_, existing)) => {
+                existing.push_str(", ");
+                existing.push_str(value);
+            }
+            None => headers.push((name.clone(), value.clone())),
+        }
+    }
+    let headers: Vec<(id, id)> = headers
+        .into_iter()
+        .map(|(name, value)| (from_rust_string(env, name), from_rust_string(env, value)))
+        .collect();
+    let header_fields = dict_from_keys_and_objects(env, &headers);
+    for (name, value) in headers {
+        release(env, name);
+        release(env, value);
+    }
+
+    let new: id = msg_class![env; NSHTTPURLResponse alloc];
+    *env.objc.borrow_mut(new) = NSURLResponseHostObject {
+        url: ns_url,
+        mime_type,
+        expected_content_length: response
+            .content_length()
+            .and_then(|length| length.try_into().ok())
+            .unwrap_or(NSURLResponseUnknownLength),
+        text_encoding_name,
+        status_code: response.status_code.into(),
+        header_fields,
+    };
+    log_dbg!(
+        "Created {:?} for {} ({})",
+        new,
+        response.url,
+        response.status_code
+    );
+    new
+}
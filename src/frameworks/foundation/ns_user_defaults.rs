@@ -11,6 +11,7 @@
 use super::ns_dictionary::dict_from_keys_and_objects;
 use super::ns_string;
 use crate::objc::{id, msg_class, objc_classes, ClassExports};
+use crate::save_state::impl_SaveState;
 use crate::Environment;
 
 #[derive(Default)]
@@ -18,6 +19,7 @@ pub struct State {
     /// `NSDictionary*`
     standard_defaults: Option<id>,
 }
+impl_SaveState!(State { standard_defaults });
 impl State {
     fn get(env: &mut Environment) -> &mut State {
         &mut env.framework_state.foundation.ns_user_defaults
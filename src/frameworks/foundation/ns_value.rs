@@ -8,9 +8,10 @@
 use super::NSUInteger;
 use crate::frameworks::foundation::ns_string::from_rust_string;
 use crate::objc::{
-    autorelease, id, msg, msg_class, objc_classes, retain, Class, ClassExports, HostObject,
-    NSZonePtr,
+    autorelease, host_object_loader, id, impl_HostObject_with_save_state, msg, msg_class,
+    objc_classes, retain, Class, ClassExports, HostObjectLoaders, NSZonePtr,
 };
+use crate::save_state::{SaveState, StateReader, StateWriter};
 
 enum NSNumberHostObject {
     Bool(bool),
@@ -19,7 +20,45 @@ enum NSNumberHostObject {
     Float(f32),
     Double(f64),
 }
-impl HostObject for NSNumberHostObject {}
+impl_HostObject_with_save_state!(NSNumberHostObject);
+impl SaveState for NSNumberHostObject {
+    fn save(&self, writer: &mut StateWriter) {
+        match *self {
+            NSNumberHostObject::Bool(value) => {
+                writer.write(&0u8);
+                writer.write(&value);
+            }
+            NSNumberHostObject::UnsignedLongLong(value) => {
+                writer.write(&1u8);
+                writer.write(&value);
+            }
+            NSNumberHostObject::LongLong(value) => {
+                writer.write(&2u8);
+                writer.write(&value);
+            }
+            NSNumberHostObject::Float(value) => {
+                writer.write(&3u8);
+                writer.write(&value);
+            }
+            NSNumberHostObject::Double(value) => {
+                writer.write(&4u8);
+                writer.write(&value);
+            }
+        }
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        match reader.read::<u8>()? {
+            0 => Ok(NSNumberHostObject::Bool(reader.read()?)),
+            1 => Ok(NSNumberHostObject::UnsignedLongLong(reader.read()?)),
+            2 => Ok(NSNumberHostObject::LongLong(reader.read()?)),
+            3 => Ok(NSNumberHostObject::Float(reader.read()?)),
+            4 => Ok(NSNumberHostObject::Double(reader.read()?)),
+            _ => Err("Save state has an invalid NSNumber".to_string()),
+        }
+    }
+}
+
+pub const HOST_OBJECTS: HostObjectLoaders = &[host_object_loader!(NSNumberHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -144,7 +183,17 @@ pub const CLASSES: ClassExports = objc_classes! {
     a == b
 }
 
-// TODO: accessors etc
+- (bool)boolValue {
+    match *env.objc.borrow(this) {
+        NSNumberHostObject::Bool(value) => value,
+        NSNumberHostObject::UnsignedLongLong(value) => value != 0,
+        NSNumberHostObject::LongLong(value) => value != 0,
+        NSNumberHostObject::Float(value) => value != 0.0,
+        NSNumberHostObject::Double(value) => value != 0.0,
+    }
+}
+
+// TODO: other accessors etc
 
 @end
 
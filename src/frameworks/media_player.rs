@@ -13,6 +13,12 @@ pub mod music_player;
 pub struct State {
     movie_player: movie_player::State,
 }
+impl State {
+    /// Save states don't support Media Player yet.
+    pub(super) fn in_use(&self) -> bool {
+        self.movie_player.in_use()
+    }
+}
 
 /// For use by `NSRunLoop`: check media players' status, send notifications if
 /// necessary.
@@ -26,6 +26,9 @@ impl State {
     fn get(env: &mut Environment) -> &mut Self {
         &mut env.framework_state.media_player.movie_player
     }
+    pub(super) fn in_use(&self) -> bool {
+        self.active_player.is_some() || !self.pending_notifications.is_empty()
+    }
 }
 
 type MPMovieScalingMode = NSInteger;
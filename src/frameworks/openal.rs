@@ -14,6 +14,7 @@
 use crate::audio::openal as al;
 use crate::audio::openal::al_types::*;
 use crate::audio::openal::alc_types::*;
+use crate::audio::recording;
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::libc::string::strcmp;
 use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, MutPtr, MutVoidPtr, Ptr, SafeWrite};
@@ -31,6 +32,10 @@ impl State {
     fn get(env: &mut Environment) -> &mut Self {
         &mut env.framework_state.openal
     }
+    /// Save states don't support OpenAL yet.
+    pub(super) fn in_use(&self) -> bool {
+        !self.devices.is_empty() || !self.contexts.is_empty()
+    }
 }
 
 /// Opaque type in guest memory standing in for [ALCdevice] in host memory.
@@ -56,7 +61,8 @@ fn alcOpenDevice(env: &mut Environment, devicename: ConstPtr<u8>) -> MutPtr<Gues
         env.mem.free(d_name.cast_mut().cast());
     }
 
-    let res = unsafe { al::alcOpenDevice(std::ptr::null()) };
+    let recorder = env.window.as_mut().and_then(|w| w.audio_recorder());
+    let res = recording::open_device(recorder);
     if res.is_null() {
         log_dbg!("alcOpenDevice(NULL) returned NULL");
         return Ptr::null();
@@ -70,6 +76,8 @@ fn alcOpenDevice(env: &mut Environment, devicename: ConstPtr<u8>) -> MutPtr<Gues
 fn alcCloseDevice(env: &mut Environment, device: MutPtr<GuestALCdevice>) -> bool {
     let host_device = State::get(env).devices.remove(&device).unwrap();
     env.mem.free(device.cast());
+    let recorder = env.window.as_mut().and_then(|w| w.audio_recorder());
+    recording::forget_device(host_device, recorder);
     let res = unsafe { al::alcCloseDevice(host_device) };
     log_dbg!("alcCloseDevice({:?}) => {:?}", device, res,);
     res != al::ALC_FALSE
@@ -106,7 +114,8 @@ fn alcCreateContext(
 
     let &host_device = State::get(env).devices.get(&device).unwrap();
 
-    let res = unsafe { al::alcCreateContext(host_device, std::ptr::null()) };
+    let recorder = env.window.as_mut().and_then(|w| w.audio_recorder());
+    let res = recording::create_context(host_device, recorder);
     if res.is_null() {
         log_dbg!("alcCreateContext({:?}, NULL) returned NULL", device);
         return Ptr::null();
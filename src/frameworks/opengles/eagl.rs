@@ -13,10 +13,11 @@ use crate::frameworks::foundation::ns_string::get_static_str;
 use crate::frameworks::foundation::NSUInteger;
 use crate::gles::gles11_raw as gles11; // constants only
 use crate::gles::gles11_raw::types::*;
-use crate::gles::present::{present_frame, FpsCounter};
+use crate::gles::present::{present_frame, read_pixels, FpsCounter};
 use crate::gles::{create_gles1_ctx, gles1_on_gl2, GLES};
 use crate::objc::{id, msg, nil, objc_classes, release, retain, ClassExports, HostObject};
 use crate::options::Options;
+use crate::replay;
 use crate::window::Window;
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
@@ -55,11 +56,26 @@ const kEAGLRenderingAPIOpenGLES2: EAGLRenderingAPI = 2;
 #[allow(dead_code)]
 const kEAGLRenderingAPIOpenGLES3: EAGLRenderingAPI = 3;
 
+/// Color format requested by the app with `kEAGLDrawablePropertyColorFormat`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum DrawableColorFormat {
+    RGBA8,
+    RGB565,
+}
+
+/// A renderbuffer's `EAGLDrawable` and the drawable properties that were in
+/// effect when its storage was allocated.
+struct DrawableBinding {
+    /// Always `CAEAGLLayer*`. Retained so it won't dangle.
+    drawable: id,
+    color_format: DrawableColorFormat,
+    retained_backing: bool,
+}
+
 pub(super) struct EAGLContextHostObject {
     pub(super) gles_ctx: Option<Box<dyn GLES>>,
-    /// Mapping of OpenGL ES renderbuffer names to `EAGLDrawable` instances
-    /// (always `CAEAGLLayer*`). Retains the instance so it won't dangle.
-    renderbuffer_drawable_bindings: HashMap<GLuint, id>,
+    /// Mapping of OpenGL ES renderbuffer names to `EAGLDrawable` instances.
+    renderbuffer_drawable_bindings: HashMap<GLuint, DrawableBinding>,
     fps_counter: Option<FpsCounter>,
     next_frame_due: Option<Instant>,
 }
@@ -161,8 +177,8 @@ pub const CLASSES: ClassExports = objc_classes! {
 - (())dealloc {
     let host_obj = env.objc.borrow_mut::<EAGLContextHostObject>(this);
     let bindings = std::mem::take(&mut host_obj.renderbuffer_drawable_bindings);
-    for (_renderbuffer, drawable) in bindings {
-        release(env, drawable);
+    for (_renderbuffer, binding) in bindings {
+        release(env, binding.drawable);
     }
     env.objc.dealloc_object(this, &mut env.mem);
 }
@@ -180,6 +196,14 @@ pub const CLASSES: ClassExports = objc_classes! {
     let format_rgb565 = get_static_str(env, kEAGLColorFormatRGB565);
 
     let format: id = msg![env; props objectForKey:format_key];
+    let color_format = if format == nil || msg![env; format isEqualTo:format_rgba8] {
+        DrawableColorFormat::RGBA8
+    } else if msg![env; format isEqualTo:format_rgb565] {
+        DrawableColorFormat::RGB565
+    } else {
+        log!("[renderbufferStorage:{:?} fromDrawable:{:?}] Warning: unhandled format {:?}, using RGBA8", target, drawable, format);
+        DrawableColorFormat::RGBA8
+    };
     // Theoretically this should map formats like:
     // - kColorFormatRGBA8 => RGBA8_OES
     // - kColorFormatRGB565 => RGB565_OES
@@ -187,34 +211,63 @@ pub const CLASSES: ClassExports = objc_classes! {
     // implementation to arbitrarily restrict which formats can be rendered to,
     // and it seems like RGB565 isn't supported, at least on a machine with
     // Intel HD Graphics 615 running macOS Monterey. I don't think RGBA8 is
-    // guaranteed either, but it at least seems to work.
-    if !msg![env; format isEqualTo:format_rgba8] && !msg![env; format isEqualTo:format_rgb565] {
-        log!("[renderbufferStorage:{:?} fromDrawable:{:?}] Warning: unhandled format {:?}, using RGBA8", target, drawable, format);
-    }
+    // guaranteed either, but it at least seems to work. RGB565 is instead
+    // emulated when presenting, see emulate_rgb565().
     let internalformat = gles11::RGBA8_OES;
 
+    let retained_backing_key = get_static_str(env, kEAGLDrawablePropertyRetainedBacking);
+    let retained_backing: id = msg![env; props objectForKey:retained_backing_key];
+    let retained_backing = retained_backing != nil && msg![env; retained_backing boolValue];
+
     let window = env.window.as_mut().expect("OpenGL ES is not supported in headless mode");
 
     // FIXME: get width and height from the layer!
     let (width, height) = window.size_unrotated_scalehacked();
+    let width: GLsizei = width.try_into().unwrap();
+    let height: GLsizei = height.try_into().unwrap();
 
     // Unclear from documentation if this method requires an appropriate context
     // to already be active, but that seems to be the case in practice?
     let gles = super::sync_context(&mut env.framework_state.opengles, &mut env.objc, window, env.current_thread);
-    let renderbuffer: GLuint = unsafe {
-        gles.RenderbufferStorageOES(target, internalformat, width.try_into().unwrap(), height.try_into().unwrap());
+    let (renderbuffer, old_size): (GLuint, _) = unsafe {
         let mut renderbuffer = 0;
         gles.GetIntegerv(gles11::RENDERBUFFER_BINDING_OES, &mut renderbuffer);
-        renderbuffer as _
+        (renderbuffer as _, get_renderbuffer_size(gles))
     };
 
+    // Some apps call this method again whenever their view is laid out. With
+    // retained backing, the app expects its drawing to survive that, but
+    // re-specifying the storage would make the contents undefined, so if
+    // nothing has changed, the existing storage is kept.
+    let keep_storage = retained_backing
+        && old_size == (width, height)
+        && env
+            .objc
+            .borrow::<EAGLContextHostObject>(this)
+            .renderbuffer_drawable_bindings
+            .get(&renderbuffer)
+            .is_some_and(|old| old.drawable == drawable && old.retained_backing);
+    if keep_storage {
+        log_dbg!("Keeping existing storage of renderbuffer {:?} for drawable {:?} with retained backing.", renderbuffer, drawable);
+    } else {
+        // re-borrow
+        let gles = super::sync_context(&mut env.framework_state.opengles, &mut env.objc, env.window.as_mut().unwrap(), env.current_thread);
+        unsafe {
+            gles.RenderbufferStorageOES(target, internalformat, width, height);
+        }
+    }
+
     retain(env, drawable);
     let host_obj = env.objc.borrow_mut::<EAGLContextHostObject>(this);
-    if let Some(old_drawable) = host_obj.renderbuffer_drawable_bindings.insert(
+    if let Some(old_binding) = host_obj.renderbuffer_drawable_bindings.insert(
         renderbuffer,
-        drawable
+        DrawableBinding {
+            drawable,
+            color_format,
+            retained_backing,
+        },
     ) {
-        release(env, old_drawable);
+        release(env, old_binding.drawable);
     }
 
     true
@@ -225,7 +278,8 @@ pub const CLASSES: ClassExports = objc_classes! {
 
     // The presented frame should be displayed ASAP, but the next one must be
     // delayed, so this needs to be checked before returning.
-    let sleep_for = limit_framerate(&mut env.objc.borrow_mut::<EAGLContextHostObject>(this).next_frame_due, &env.options);
+    let now = replay::now(env);
+    let sleep_for = limit_framerate(&mut env.objc.borrow_mut::<EAGLContextHostObject>(this).next_frame_due, now, &env.options);
 
     if env.options.print_fps {
         env
@@ -249,7 +303,7 @@ pub const CLASSES: ClassExports = objc_classes! {
         renderbuffer as _
     };
 
-    let &drawable = env
+    let &DrawableBinding { drawable, color_format, .. } = env
         .objc
         .borrow::<EAGLContextHostObject>(this)
         .renderbuffer_drawable_bindings
@@ -267,7 +321,7 @@ pub const CLASSES: ClassExports = objc_classes! {
         // re-borrow
         let gles = super::sync_context(&mut env.framework_state.opengles, &mut env.objc, env.window.as_mut().unwrap(), env.current_thread);
         unsafe {
-            present_renderbuffer(gles, env.window.as_mut().unwrap());
+            present_renderbuffer(gles, env.window.as_mut().unwrap(), color_format);
         }
     } else {
         if fullscreen_layer != nil {
@@ -301,7 +355,7 @@ pub const CLASSES: ClassExports = objc_classes! {
         // re-borrow
         let gles = super::sync_context(&mut env.framework_state.opengles, &mut env.objc, env.window.as_mut().unwrap(), env.current_thread);
         let (pixels_vec, width, height) = unsafe {
-            read_renderbuffer(gles, pixels_vec)
+            read_renderbuffer(gles, pixels_vec, color_format)
         };
         present_pixels(env, drawable, pixels_vec, width, height);
     }
@@ -335,7 +389,11 @@ pub const CLASSES: ClassExports = objc_classes! {
 /// an interval's worth of accumulated slop. Allowing infinite accumulation of
 /// slop is not desirable, because if the game is running slowly for a long time
 /// and suddenly speeds back up, it will then run too fast for a long time.
-fn limit_framerate(next_frame_due: &mut Option<Instant>, options: &Options) -> Option<Duration> {
+fn limit_framerate(
+    next_frame_due: &mut Option<Instant>,
+    now: Instant,
+    options: &Options,
+) -> Option<Duration> {
     let interval = if let Some(fps) = options.fps_limit {
         1.0 / fps
     } else {
@@ -345,11 +403,10 @@ fn limit_framerate(next_frame_due: &mut Option<Instant>, options: &Options) -> O
 
     let &mut Some(current_frame_due) = next_frame_due else {
         // First frame presented: no delay yet.
-        *next_frame_due = Some(Instant::now() + interval_rust);
+        *next_frame_due = Some(now + interval_rust);
         return None;
     };
 
-    let now = Instant::now();
     *next_frame_due = if now > current_frame_due + interval_rust {
         // Too much slop has accumulated. Make the next frame wait for the next
         // interval.
@@ -436,11 +493,16 @@ unsafe fn get_renderbuffer_size(gles: &mut dyn GLES) -> (GLsizei, GLsizei) {
 /// noticeably modifying OpenGL ES state while doing so.
 ///
 /// This uses `glReadPixels()`, with all the associated performance risks. Any
-/// existing content in the [Vec] will bereplaced. The format is RGBA8.
+/// existing content in the [Vec] will bereplaced. The format is RGBA8, but if
+/// the drawable's format is RGB565, the precision is reduced to match.
 /// The returned values are the [Vec], the width and height.
 ///
 /// The provided context must be current.
-unsafe fn read_renderbuffer(gles: &mut dyn GLES, mut pixel_buffer: Vec<u8>) -> (Vec<u8>, u32, u32) {
+unsafe fn read_renderbuffer(
+    gles: &mut dyn GLES,
+    mut pixel_buffer: Vec<u8>,
+    color_format: DrawableColorFormat,
+) -> (Vec<u8>, u32, u32) {
     let renderbuffer: GLuint = get_int(gles, gles11::RENDERBUFFER_BINDING_OES) as _;
     let (width, height) = get_renderbuffer_size(gles);
     let width_u32: u32 = width.try_into().unwrap();
@@ -461,31 +523,10 @@ unsafe fn read_renderbuffer(gles: &mut dyn GLES, mut pixel_buffer: Vec<u8>) -> (
         renderbuffer,
     );
 
-    // Read the pixels
-    let size = (width_u32 as usize)
-        .checked_mul(height_u32 as usize)
-        .unwrap()
-        .checked_mul(4)
-        .unwrap();
-    pixel_buffer.clear();
-    pixel_buffer.reserve_exact(size);
-    let before = Instant::now();
-    gles.ReadPixels(
-        0,
-        0,
-        width,
-        height,
-        gles11::RGBA,
-        gles11::UNSIGNED_BYTE,
-        pixel_buffer.as_mut_ptr() as *mut _,
-    );
-    log_dbg!(
-        "glReadPixels(0, 0, {}, {}, …) took {:?}",
-        width,
-        height,
-        Instant::now().saturating_duration_since(before)
-    );
-    pixel_buffer.set_len(size);
+    read_pixels(gles, width, height, &mut pixel_buffer);
+    if color_format == DrawableColorFormat::RGB565 {
+        emulate_rgb565(gles, &mut pixel_buffer, width_u32);
+    }
 
     // Clean up the framebuffer object since we no longer need it.
     gles.DeleteFramebuffersOES(1, &src_framebuffer);
@@ -496,13 +537,60 @@ unsafe fn read_renderbuffer(gles: &mut dyn GLES, mut pixel_buffer: Vec<u8>) -> (
     (pixel_buffer, width_u32, height_u32)
 }
 
+/// Reduces the precision of RGBA8 pixels read from a renderbuffer to that of
+/// an RGB565 drawable, dithering them if the app has enabled `GL_DITHER`.
+///
+/// Renderbuffers are always allocated as RGBA8 (see
+/// `renderbufferStorage:fromDrawable:`), so without this, apps that use RGB565
+/// would look smoother than on a real device, where the reduced precision
+/// causes visible banding (or a dithering pattern). This is an approximation:
+/// real hardware quantizes every time a fragment is written, not just once
+/// when presenting, and its exact dithering pattern is not known.
+unsafe fn emulate_rgb565(gles: &mut dyn GLES, pixels: &mut [u8], width: u32) {
+    let dither = gles.IsEnabled(gles11::DITHER) != gles11::FALSE;
+    quantize_to_rgb565(pixels, width, dither);
+}
+
+/// 4×4 ordered dithering (Bayer) matrix.
+const DITHER_MATRIX: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Quantizes RGBA8 pixels to RGB565 precision in-place, optionally with ordered
+/// dithering. The result is still in RGBA8 format, with alpha set to 255.
+fn quantize_to_rgb565(pixels: &mut [u8], width: u32, dither: bool) {
+    let width = width as usize;
+    for (i, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (i % width, i / width);
+        // Fraction of a quantization step (in 256ths) that must be exceeded to
+        // round upwards.
+        let threshold: u32 = if dither {
+            u32::from(DITHER_MATRIX[y % 4][x % 4]) * 16 + 8
+        } else {
+            128
+        };
+        let quantize = |value: u8, bits: u32| -> u8 {
+            let max = (1 << bits) - 1;
+            let value = ((u32::from(value) * max * 256 / 255 + threshold) / 256).min(max);
+            // Expand back to 8 bits by replicating the high bits.
+            ((value << (8 - bits)) | (value >> (2 * bits - 8))) as u8
+        };
+        pixel[0] = quantize(pixel[0], 5);
+        pixel[1] = quantize(pixel[1], 6);
+        pixel[2] = quantize(pixel[2], 5);
+        pixel[3] = 255;
+    }
+}
+
 /// Copies the pixels in a renderbuffer bound to `GL_RENDERBUFFER_BINDING_OES`
 /// (which should be provided by the app) to a texture and presents it with
 /// [present_frame], trying to avoid noticeably modifying OpenGL ES state while
 /// doing so. The front and back buffers are then swapped.
 ///
 /// The provided context must be current.
-unsafe fn present_renderbuffer(gles: &mut dyn GLES, window: &mut Window) {
+unsafe fn present_renderbuffer(
+    gles: &mut dyn GLES,
+    window: &mut Window,
+    color_format: DrawableColorFormat,
+) {
     // We can't directly copy the content of the renderbuffer to the default
     // framebuffer (the window), but if we attach it to a framebuffer object, we
     // can use glCopyTexImage2D() to copy it to a texture, which we can then
@@ -528,20 +616,57 @@ unsafe fn present_renderbuffer(gles: &mut dyn GLES, window: &mut Window) {
         renderbuffer,
     );
 
+    // The pixels only need to be copied to system RAM if the precision has to
+    // be reduced, which must be done in software (so this has the same
+    // performance problems as the slow path in presentRenderbuffer:,
+    // unfortunately), or if the frame is being captured.
+    let capture_frame = window.should_capture_frame();
+    let pixels = if color_format == DrawableColorFormat::RGB565 || capture_frame {
+        let mut pixels = Vec::new();
+        read_pixels(gles, width, height, &mut pixels);
+        if color_format == DrawableColorFormat::RGB565 {
+            emulate_rgb565(gles, &mut pixels, width as u32);
+        }
+        if capture_frame {
+            window.capture_frame(&pixels, (width as u32, height as u32));
+        }
+        Some(pixels)
+    } else {
+        None
+    };
+
     // Create a texture with a copy of the pixels in the framebuffer
     let mut texture: GLuint = 0;
     gles.GenTextures(1, &mut texture);
     gles.BindTexture(gles11::TEXTURE_2D, texture);
-    gles.CopyTexImage2D(
-        gles11::TEXTURE_2D,
-        0,
-        gles11::RGB as _,
-        0,
-        0,
-        width,
-        height,
-        0,
-    );
+    match color_format {
+        DrawableColorFormat::RGBA8 => gles.CopyTexImage2D(
+            gles11::TEXTURE_2D,
+            0,
+            gles11::RGB as _,
+            0,
+            0,
+            width,
+            height,
+            0,
+        ),
+        DrawableColorFormat::RGB565 => {
+            let old_unpack_alignment = get_int(gles, gles11::UNPACK_ALIGNMENT);
+            gles.PixelStorei(gles11::UNPACK_ALIGNMENT, 4);
+            gles.TexImage2D(
+                gles11::TEXTURE_2D,
+                0,
+                gles11::RGB as _,
+                width,
+                height,
+                0,
+                gles11::RGBA,
+                gles11::UNSIGNED_BYTE,
+                pixels.as_ref().unwrap().as_ptr() as *const _,
+            );
+            gles.PixelStorei(gles11::UNPACK_ALIGNMENT, old_unpack_alignment);
+        }
+    }
     // The texture will not have any mip levels so we must ensure the filter
     // does not use them, else rendering will fail.
     gles.TexParameteri(
@@ -702,3 +827,44 @@ unsafe fn present_renderbuffer(gles: &mut dyn GLES, window: &mut Window) {
 
     //{ let err = gl21::GetError(); if err != 0 { panic!("{:#x}", err); } }
 }
+
+#[cfg(test)]
+mod rgb565_tests {
+    use super::*;
+
+    #[test]
+    fn quantize_without_dithering() {
+        let mut pixels = [
+            0, 0, 0, 0, //
+            255, 255, 255, 0, //
+            0x84, 0x82, 0x84, 0x80, // representable exactly
+            0x85, 0x83, 0x85, 0x80, // rounded down
+            0x89, 0x85, 0x89, 0x80, // rounded up
+        ];
+        quantize_to_rgb565(&mut pixels, 5, false);
+        assert_eq!(
+            pixels,
+            [
+                0, 0, 0, 255, //
+                255, 255, 255, 255, //
+                0x84, 0x82, 0x84, 0xff, //
+                0x84, 0x82, 0x84, 0xff, //
+                0x8c, 0x86, 0x8c, 0xff, //
+            ]
+        );
+    }
+
+    #[test]
+    fn dithering_preserves_average() {
+        // A 4×4 block of a color between two RGB565 levels should dither to
+        // a mix of those levels with about the right average.
+        let mut pixels = [0x88, 0x88, 0x88, 0xff].repeat(16);
+        quantize_to_rgb565(&mut pixels, 4, true);
+        for channel in 0..3 {
+            let values: Vec<u8> = pixels.iter().skip(channel).step_by(4).copied().collect();
+            let sum: u32 = values.iter().map(|&v| u32::from(v)).sum();
+            assert!((sum / 16).abs_diff(0x88) <= 1, "{:?}", values);
+            assert!(values.iter().min() != values.iter().max());
+        }
+    }
+}
@@ -9,7 +9,7 @@
 //! likely to use UIKit in very simple and limited ways, so this implementation
 //! will probably take a lot of shortcuts.
 
-use crate::{msg, Environment};
+use crate::{msg, replay, Environment};
 use std::time::Instant;
 
 pub mod ui_accelerometer;
@@ -54,7 +54,7 @@ pub fn handle_events(env: &mut Environment) -> Option<Instant> {
 
     loop {
         // NSRunLoop will never call this function in headless mode.
-        let Some(event) = env.window.as_mut().unwrap().pop_event() else {
+        let Some(event) = replay::pop_event(env) else {
             break;
         };
 
@@ -13,6 +13,7 @@ use crate::objc::{
     autorelease, id, msg, msg_class, nil, objc_classes, release, ClassExports, HostObject,
     NSZonePtr, TrivialHostObject, SEL,
 };
+use crate::replay;
 use crate::Environment;
 use std::time::{Duration, Instant};
 
@@ -123,14 +124,14 @@ pub const CLASSES: ClassExports = objc_classes! {
 ///
 /// Returns the time an accelerometer update is due, if any.
 pub(super) fn handle_accelerometer(env: &mut Environment) -> Option<Instant> {
-    let state = &mut env.framework_state.uikit.ui_accelerometer;
+    let delegate = env.framework_state.uikit.ui_accelerometer.delegate?;
 
-    let delegate = state.delegate?;
+    let now = replay::now(env);
+    let state = &mut env.framework_state.uikit.ui_accelerometer;
 
     let ns_interval = state.update_interval.unwrap_or(DEFAULT_UPDATE_INTERVAL);
     let rust_interval = Duration::from_secs_f64(ns_interval);
 
-    let now = Instant::now();
     let new_due_by = if let Some(due_by) = state.due_by {
         if due_by > now {
             return Some(due_by);
@@ -166,7 +167,7 @@ pub(super) fn handle_accelerometer(env: &mut Environment) -> Option<Instant> {
     // UIKit creates and drains autorelease pools when handling events.
     let pool: id = msg_class![env; NSAutoreleasePool new];
 
-    let (x, y, z) = env.window().get_acceleration(&env.options);
+    let (x, y, z) = replay::acceleration(env);
     let timestamp: NSTimeInterval = msg_class![env; NSProcessInfo systemUptime];
     let acceleration: id = msg_class![env; UIAcceleration alloc];
     *env.objc.borrow_mut(acceleration) = UIAccelerationHostObject {
@@ -65,13 +65,12 @@ pub const CLASSES: ClassExports = objc_classes! {
     log!("TODO: endGeneratingDeviceOrientationNotifications");
 }
 - (id)model {
-    // TODO: Hardcoded to iPhone for now
-    ns_string::get_static_str(env, "iPhone")
+    ns_string::get_static_str(env, env.options.device.model)
 }
 
 - (id)name {
-    // TODO: Hardcoded to iPhone for now
-    ns_string::get_static_str(env, "iPhone")
+    // The user's name for their device. Real devices default to the model.
+    ns_string::get_static_str(env, env.options.device.model)
 }
 
 - (id)systemName {
@@ -80,13 +79,11 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 // NSString
 - (id)systemVersion {
-    ns_string::get_static_str(env, "2.0")
+    ns_string::get_static_str(env, env.options.device.os_version)
 }
 
 - (id)uniqueIdentifier {
-    // Aspen Simulator returns (null) here
-    // TODO: what should be a correct value?
-    ns_string::get_static_str(env, "touchHLEdevice")
+    ns_string::get_static_str(env, env.options.device.unique_identifier)
 }
 
 - (bool)isMultitaskingSupported {
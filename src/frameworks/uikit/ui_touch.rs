@@ -15,8 +15,9 @@ use crate::objc::{
 };
 use crate::window::{Coords, Event, FingerId};
 use crate::Environment;
-use std::collections::hash_map::{Entry, HashMap};
+use std::collections::hash_map::{DefaultHasher, Entry, HashMap};
 use std::collections::HashSet;
+use std::hash::BuildHasherDefault;
 
 pub type UITouchPhase = NSInteger;
 pub const UITouchPhaseBegan: UITouchPhase = 0;
@@ -106,6 +107,19 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 };
 
+/// Map of views to sets of touches. The hasher has fixed keys, unlike the
+/// default one, so that the views are sent messages in the same order every
+/// time touchHLE is run. Otherwise, replays (see [crate::replay]) would
+/// diverge.
+type ViewTouches = HashMap<id, id, BuildHasherDefault<DefaultHasher>>;
+
+/// Sort the touches in an event, for the same reason as [ViewTouches].
+fn sorted_touches(map: HashMap<FingerId, Coords>) -> Vec<(FingerId, Coords)> {
+    let mut touches: Vec<_> = map.into_iter().collect();
+    touches.sort_by_key(|&(finger_id, _)| finger_id);
+    touches
+}
+
 /// [super::handle_events] will forward touch events to this function.
 pub fn handle_event(env: &mut Environment, event: Event) {
     // before processing anything, we mark all current touches as stationary
@@ -147,7 +161,7 @@ fn handle_touches_down(env: &mut Environment, map: HashMap<FingerId, Coords>) {
 
     let touches: id = msg_class![env; NSMutableSet allocWithZone:(MutVoidPtr::null())];
 
-    for (finger_id, coords) in map {
+    for (finger_id, coords) in sorted_touches(map) {
         let current_touches = &mut env.framework_state.uikit.ui_touch.current_touches;
 
         if current_touches.contains_key(&finger_id) {
@@ -206,7 +220,7 @@ fn handle_touches_down(env: &mut Environment, map: HashMap<FingerId, Coords>) {
         .collect();
 
     // view to set of touches for this view
-    let mut view_touches: HashMap<id, id> = HashMap::new();
+    let mut view_touches: ViewTouches = HashMap::default();
 
     let touches_arr: id = msg![env; touches allObjects];
     let touches_count: NSUInteger = msg![env; touches_arr count];
@@ -304,9 +318,9 @@ fn handle_touches_move(env: &mut Environment, map: HashMap<FingerId, Coords>) {
     let touches: id = msg_class![env; NSMutableSet allocWithZone:(MutVoidPtr::null())];
 
     // view to set of touches for this view
-    let mut view_touches: HashMap<id, id> = HashMap::new();
+    let mut view_touches: ViewTouches = HashMap::default();
 
-    for (finger_id, coords) in map {
+    for (finger_id, coords) in sorted_touches(map) {
         let Some(&touch) = env
             .framework_state
             .uikit
@@ -370,9 +384,9 @@ fn handle_touches_up(env: &mut Environment, map: HashMap<FingerId, Coords>) {
     let touches: id = msg_class![env; NSMutableSet allocWithZone:(MutVoidPtr::null())];
 
     // view to set of touches for this view
-    let mut view_touches: HashMap<id, id> = HashMap::new();
+    let mut view_touches: ViewTouches = HashMap::default();
 
-    for (finger_id, coords) in map {
+    for (finger_id, coords) in sorted_touches(map) {
         let Some(&touch) = env
             .framework_state
             .uikit
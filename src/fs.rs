@@ -48,6 +48,26 @@ enum FileLocation {
     ResourceFilePath(String),
 }
 
+/// Why an operation on the guest filesystem failed. These correspond to POSIX
+/// `errno` values, see [crate::libc::errno::errno_for_fs_error].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FsError {
+    /// The path, or a directory it goes through, doesn't exist.
+    NotFound,
+    /// Part of the path that should be a directory isn't one.
+    NotADirectory,
+    /// The path is a directory, but the operation needs a file.
+    IsADirectory,
+    /// There's already a file or directory at the path.
+    AlreadyExists,
+    /// The directory has to be empty, but isn't.
+    DirectoryNotEmpty,
+    /// The file or directory, or the directory containing it, is read-only.
+    PermissionDenied,
+    /// The operation makes no sense, e.g. moving a directory inside itself.
+    InvalidArgument,
+}
+
 #[derive(Debug)]
 enum FsNode {
     File {
@@ -100,6 +120,25 @@ impl FsNode {
         }
     }
 
+    /// Update the host paths of a writeable node and everything in it, after
+    /// its host file or directory has been moved to `new_host_path`.
+    fn relocate(&mut self, new_host_path: &Path) {
+        match self {
+            FsNode::File { location, .. } => {
+                *location = FileLocation::Path(new_host_path.to_owned());
+            }
+            FsNode::Directory {
+                children,
+                writeable,
+            } => {
+                *writeable = Some(new_host_path.to_owned());
+                for (name, child) in children.iter_mut() {
+                    child.relocate(&new_host_path.join(name));
+                }
+            }
+        }
+    }
+
     // Convenience methods for constructing the read-only parts of the initial
     // filesystem layout
 
@@ -286,13 +325,14 @@ pub fn resolve_path<'a>(path: &'a GuestPath, relative_to: Option<&'a GuestPath>)
 }
 
 /// Like [std::fs::OpenOptions] but for the guest filesystem.
-/// TODO: `create_new`.
 #[derive(Debug)]
 pub struct GuestOpenOptions {
     read: bool,
     write: bool,
     append: bool,
     create: bool,
+    create_new: bool,
+    create_read_only: bool,
     truncate: bool,
 }
 impl GuestOpenOptions {
@@ -302,6 +342,8 @@ impl GuestOpenOptions {
             write: false,
             append: false,
             create: false,
+            create_new: false,
+            create_read_only: false,
             truncate: false,
         }
     }
@@ -321,6 +363,17 @@ impl GuestOpenOptions {
         self.create = true;
         self
     }
+    pub fn create_new(&mut self) -> &mut Self {
+        self.create_new = true;
+        self
+    }
+    /// If a new file is created, don't allow opening it for writing again,
+    /// like creating it with a POSIX mode that lacks write permission. This
+    /// only lasts until touchHLE exits.
+    pub fn create_read_only(&mut self) -> &mut Self {
+        self.create_read_only = true;
+        self
+    }
     pub fn truncate(&mut self) -> &mut Self {
         self.truncate = true;
         self
@@ -363,6 +416,16 @@ impl GuestFile {
         GuestFile::ResourceFile(file)
     }
 
+    /// Get a new handle to the same file, if it's a file in the host file
+    /// system. Files in the app bundle and resource files can't be written to,
+    /// so they don't need this.
+    pub fn try_clone_host_file(&self) -> Option<GuestFile> {
+        match self {
+            GuestFile::File(file) => Some(GuestFile::File(file.try_clone().unwrap())),
+            GuestFile::IpaBundleFile(_) | GuestFile::ResourceFile(_) => None,
+        }
+    }
+
     pub fn sync_all(&self) -> std::io::Result<()> {
         match self {
             GuestFile::File(file) => file.sync_all(),
@@ -561,26 +624,34 @@ impl Fs {
     }
 
     /// Attempts to change the working directory.
-    pub fn change_working_directory(&mut self, new_path: &GuestPath) -> Result<&GuestPath, ()> {
+    pub fn change_working_directory(
+        &mut self,
+        new_path: &GuestPath,
+    ) -> Result<&GuestPath, FsError> {
         let resolved = resolve_path(new_path, Some(&self.working_directory));
-        if !matches!(
-            self.lookup_node_inner(&resolved),
-            Some(FsNode::Directory { .. })
-        ) {
-            return Err(());
+        match self.lookup_node_checked(&resolved)? {
+            FsNode::Directory { .. } => (),
+            FsNode::File { .. } => return Err(FsError::NotADirectory),
         }
-        let new_path = if resolved.is_empty() {
+        self.working_directory = self.absolute_path(new_path);
+        Ok(&self.working_directory)
+    }
+
+    /// Resolve a path relative to the working directory (see [resolve_path])
+    /// and turn it back into a path string, e.g. `/foo/bar`.
+    pub fn absolute_path(&self, path: &GuestPath) -> GuestPathBuf {
+        let resolved = resolve_path(path, Some(&self.working_directory));
+        let path = if resolved.is_empty() {
             String::from("/")
         } else {
-            let mut new_path = String::with_capacity(resolved.iter().map(|c| c.len() + 1).sum());
+            let mut path = String::with_capacity(resolved.iter().map(|c| c.len() + 1).sum());
             for component in resolved {
-                new_path.push('/');
-                new_path.push_str(component);
+                path.push('/');
+                path.push_str(component);
             }
-            new_path
+            path
         };
-        self.working_directory = GuestPathBuf::from(new_path);
-        Ok(&self.working_directory)
+        GuestPathBuf::from(path)
     }
 
     /// [Self::lookup_node] with a pre-resolved path.
@@ -599,6 +670,38 @@ impl Fs {
         Some(node)
     }
 
+    /// Like [Self::lookup_node_inner], but says why the lookup failed.
+    fn lookup_node_checked(&self, resolved_path_components: &[&str]) -> Result<&FsNode, FsError> {
+        let mut node = &self.root;
+        for component in resolved_path_components {
+            let FsNode::Directory {
+                children,
+                writeable: _,
+            } = node
+            else {
+                return Err(FsError::NotADirectory);
+            };
+            node = children.get(*component).ok_or(FsError::NotFound)?
+        }
+        Ok(node)
+    }
+
+    /// Mutable version of [Self::lookup_node_inner].
+    fn lookup_node_inner_mut(&mut self, resolved_path_components: &[&str]) -> Option<&mut FsNode> {
+        let mut node = &mut self.root;
+        for component in resolved_path_components {
+            let FsNode::Directory {
+                children,
+                writeable: _,
+            } = node
+            else {
+                return None;
+            };
+            node = children.get_mut(*component)?
+        }
+        Some(node)
+    }
+
     /// Get the node at a given path, if it exists.
     fn lookup_node(&self, path: &GuestPath) -> Option<&FsNode> {
         self.lookup_node_inner(&resolve_path(path, Some(&self.working_directory)))
@@ -608,9 +711,13 @@ impl Fs {
     /// together with the final path component. This is an alternative to
     /// [Self::lookup_node] useful when writing to a file, where it might not
     /// exist yet (but its parent directory does).
-    fn lookup_parent_node(&mut self, path: &GuestPath) -> Option<(&mut FsNode, String)> {
+    ///
+    /// The root directory has no parent, but it's always a directory, so
+    /// [FsError::IsADirectory] is returned for it.
+    fn lookup_parent_node(&mut self, path: &GuestPath) -> Result<(&mut FsNode, String), FsError> {
         let components = resolve_path(path, Some(&self.working_directory));
-        let (&final_component, parent_components) = components.split_last()?;
+        let (&final_component, parent_components) =
+            components.split_last().ok_or(FsError::IsADirectory)?;
 
         let mut parent = &mut self.root;
         for &component in parent_components {
@@ -619,12 +726,12 @@ impl Fs {
                 writeable: _,
             } = parent
             else {
-                return None;
+                return Err(FsError::NotADirectory);
             };
-            parent = children.get_mut(component)?
+            parent = children.get_mut(component).ok_or(FsError::NotFound)?
         }
 
-        Some((parent, final_component.to_string()))
+        Ok((parent, final_component.to_string()))
     }
 
     /// Like [Path::exists] but for the guest filesystem.
@@ -719,7 +826,8 @@ impl Fs {
     pub fn write<P: AsRef<GuestPath>>(&mut self, path: P, data: &[u8]) -> Result<(), ()> {
         let mut options = GuestOpenOptions::new();
         options.write().create().truncate();
-        self.open_with_options(path, options)?
+        self.open_with_options(path, options)
+            .map_err(|_| ())?
             .write_all(data)
             .map_err(|_| ())
     }
@@ -746,32 +854,113 @@ impl Fs {
         }
     }
 
-    pub fn rename<P: AsRef<GuestPath>>(&self, from: P, to: P) -> Result<(), ()> {
-        let from_node = self.lookup_node(from.as_ref()).ok_or(())?;
-        match from_node {
+    /// Like [std::fs::rename] but for the guest filesystem. Files and
+    /// directories can only be moved between writeable directories.
+    pub fn rename<P: AsRef<GuestPath>>(&mut self, from: P, to: P) -> Result<(), FsError> {
+        let working_directory = self.working_directory.clone();
+        let from = resolve_path(from.as_ref(), Some(&working_directory));
+        let to = resolve_path(to.as_ref(), Some(&working_directory));
+
+        let from_node = self.lookup_node_checked(&from)?;
+        let from_is_dir = matches!(from_node, FsNode::Directory { .. });
+        let from_host_path = match from_node {
             FsNode::File {
-                location: from_location,
+                location: FileLocation::Path(host_path),
+                writeable: true,
+            } => host_path.clone(),
+            FsNode::Directory {
+                writeable: Some(host_path),
                 ..
-            } => match from_location {
-                FileLocation::Path(from_host_path) => {
-                    let to_node = self.lookup_node(to.as_ref()).ok_or(())?;
-                    match to_node {
-                        FsNode::File {
-                            location: to_location,
-                            ..
-                        } => match to_location {
-                            FileLocation::Path(to_host_path) => {
-                                fs::rename(from_host_path, to_host_path).map_err(|_| ())
-                            }
-                            _ => unreachable!(),
-                        },
-                        _ => unimplemented!(),
-                    }
+            } => host_path.clone(),
+            _ => return Err(FsError::PermissionDenied),
+        };
+
+        // The root directory has no parent, so it can't be moved or replaced.
+        let (Some((&from_name, from_parent)), Some((&to_name, to_parent))) =
+            (from.split_last(), to.split_last())
+        else {
+            return Err(FsError::IsADirectory);
+        };
+        for parent in [from_parent, to_parent] {
+            match self.lookup_node_checked(parent)? {
+                FsNode::Directory {
+                    writeable: Some(_), ..
+                } => (),
+                FsNode::Directory {
+                    writeable: None, ..
+                } => return Err(FsError::PermissionDenied),
+                FsNode::File { .. } => return Err(FsError::NotADirectory),
+            }
+        }
+        let Some(FsNode::Directory {
+            writeable: Some(to_parent_host_path),
+            ..
+        }) = self.lookup_node_inner(to_parent)
+        else {
+            unreachable!();
+        };
+        let to_host_path = to_parent_host_path.join(to_name);
+
+        if from == to {
+            return Ok(());
+        }
+        if from_is_dir && to.starts_with(&from) {
+            log!(
+                "Warning: attempt to move directory {:?} inside itself",
+                from_host_path
+            );
+            return Err(FsError::InvalidArgument);
+        }
+
+        // The destination is replaced if it exists, but only by the same kind
+        // of node, and only if it's an empty directory.
+        match self.lookup_node_inner(&to) {
+            None => (),
+            Some(FsNode::File { writeable, .. }) => {
+                if from_is_dir {
+                    return Err(FsError::NotADirectory);
                 }
-                _ => unreachable!(),
-            },
-            _ => unimplemented!(),
+                if !writeable {
+                    return Err(FsError::PermissionDenied);
+                }
+            }
+            Some(FsNode::Directory {
+                children,
+                writeable,
+            }) => {
+                if !from_is_dir {
+                    return Err(FsError::IsADirectory);
+                }
+                if !children.is_empty() {
+                    return Err(FsError::DirectoryNotEmpty);
+                }
+                if writeable.is_none() {
+                    return Err(FsError::PermissionDenied);
+                }
+            }
         }
+
+        handle_open_err(fs::rename(&from_host_path, &to_host_path), &from_host_path);
+        log_dbg!(
+            "Moved {:?} to {:?} (host paths: {:?} to {:?})",
+            from,
+            to,
+            from_host_path,
+            to_host_path
+        );
+
+        let Some(FsNode::Directory { children, .. }) = self.lookup_node_inner_mut(from_parent)
+        else {
+            unreachable!();
+        };
+        let mut node = children.remove(from_name).unwrap();
+        node.relocate(&to_host_path);
+        let Some(FsNode::Directory { children, .. }) = self.lookup_node_inner_mut(to_parent) else {
+            unreachable!();
+        };
+        children.insert(to_name.to_string(), node);
+
+        Ok(())
     }
 
     /// Like [File::options] but for the guest filesystem.
@@ -779,30 +968,35 @@ impl Fs {
         &mut self,
         path: P,
         options: GuestOpenOptions,
-    ) -> Result<GuestFile, ()> {
+    ) -> Result<GuestFile, FsError> {
         let GuestOpenOptions {
             read,
             write,
             append,
             create,
+            create_new,
+            create_read_only,
             truncate,
         } = options;
-        assert!((!truncate && !create) || write || append);
+        assert!((!truncate && !create && !create_new) || write || append);
 
         let path = path.as_ref();
 
-        let (parent_node, new_filename) = self.lookup_parent_node(path).ok_or(())?;
+        let (parent_node, new_filename) = self.lookup_parent_node(path)?;
         let FsNode::Directory {
             children,
             writeable: dir_host_path,
         } = parent_node
         else {
-            return Err(());
+            return Err(FsError::NotADirectory);
         };
 
         // Open an existing file if possible
 
         if let Some(existing_file) = children.get(&new_filename) {
+            if create_new {
+                return Err(FsError::AlreadyExists);
+            }
             match existing_file {
                 &FsNode::File {
                     ref location,
@@ -810,7 +1004,7 @@ impl Fs {
                 } => {
                     if !writeable && (append || write) {
                         log!("Warning: attempt to write to read-only file {:?}", path);
-                        return Err(());
+                        return Err(FsError::PermissionDenied);
                     }
                     match location {
                         FileLocation::Path(host_path) => {
@@ -839,15 +1033,15 @@ impl Fs {
                     }
                 }
                 FsNode::Directory { .. } => {
-                    return Err(());
+                    return Err(FsError::IsADirectory);
                 }
             }
         };
 
         // Create a new file otherwise
 
-        if !create {
-            return Err(());
+        if !create && !create_new {
+            return Err(FsError::NotFound);
         }
 
         let Some(dir_host_path) = dir_host_path else {
@@ -855,7 +1049,7 @@ impl Fs {
                 "Warning: attempt to create file at path {:?}, but directory is read-only",
                 path
             );
-            return Err(());
+            return Err(FsError::PermissionDenied);
         };
 
         for c in new_filename.chars() {
@@ -871,7 +1065,7 @@ impl Fs {
                 .read(read)
                 .write(write)
                 .append(append)
-                .create(create)
+                .create(true)
                 .truncate(truncate)
                 .open(&host_path),
             &host_path,
@@ -885,7 +1079,7 @@ impl Fs {
             new_filename,
             FsNode::File {
                 location: FileLocation::Path(host_path),
-                writeable: true,
+                writeable: !create_read_only,
             },
         );
         Ok(GuestFile::File(file))
@@ -893,10 +1087,10 @@ impl Fs {
 
     /// Removes a file or a directory. If the node is a directory, it must be
     /// empty.
-    pub fn remove<P: AsRef<GuestPath>>(&mut self, path: P) -> Result<(), ()> {
+    pub fn remove<P: AsRef<GuestPath>>(&mut self, path: P) -> Result<(), FsError> {
         let path = path.as_ref();
 
-        let (parent_node, node_name) = self.lookup_parent_node(path).ok_or(())?;
+        let (parent_node, node_name) = self.lookup_parent_node(path)?;
 
         // Parent directory is not a directory
         let FsNode::Directory {
@@ -904,33 +1098,28 @@ impl Fs {
             writeable: dir_writeable,
         } = parent_node
         else {
-            return Err(());
-        };
-
-        if !dir_writeable.is_some() {
-            log!("Warning: attempt to delete file or directroy at path {:?}, but parent directory is read-only", path);
-            return Err(());
+            return Err(FsError::NotADirectory);
         };
 
         let Some(node) = children.get(&node_name) else {
             // There is no file/directory with this name
-            return Err(());
+            return Err(FsError::NotFound);
         };
 
-        match node {
-            FsNode::File {
-                location,
-                writeable,
-            } => {
-                // Read-only files can't be removed. (This is probably not
-                // correct, but it is safer for now.)
-                if !writeable {
-                    return Err(());
-                }
+        if !dir_writeable.is_some() {
+            log!("Warning: attempt to delete file or directroy at path {:?}, but parent directory is read-only", path);
+            return Err(FsError::PermissionDenied);
+        };
 
+        match node {
+            FsNode::File { location, .. } => {
+                // Like on a real filesystem, a file that is read-only can
+                // still be removed, since that only modifies its directory.
                 let host_path = match location {
                     FileLocation::Path(host_path) => host_path,
-                    FileLocation::IpaFileRef(_) | FileLocation::ResourceFilePath(_) => panic!(),
+                    FileLocation::IpaFileRef(_) | FileLocation::ResourceFilePath(_) => {
+                        return Err(FsError::PermissionDenied);
+                    }
                 };
 
                 handle_open_err(std::fs::remove_file(host_path), host_path);
@@ -946,12 +1135,12 @@ impl Fs {
             } => {
                 // Directory is not empty
                 if !children.is_empty() {
-                    return Err(());
+                    return Err(FsError::DirectoryNotEmpty);
                 }
                 // Read-only directories can't be removed. (This is probably not
                 // correct, but it is safer for now.)
                 let Some(host_path) = writeable else {
-                    return Err(());
+                    return Err(FsError::PermissionDenied);
                 };
 
                 handle_open_err(std::fs::remove_dir(host_path), host_path);
@@ -969,10 +1158,14 @@ impl Fs {
     }
 
     /// Like [std::fs::create_dir] but for the guest filesystem.
-    pub fn create_dir<P: AsRef<GuestPath>>(&mut self, path: P) -> Result<(), ()> {
+    pub fn create_dir<P: AsRef<GuestPath>>(&mut self, path: P) -> Result<(), FsError> {
         let path = path.as_ref();
 
-        let (parent_node, new_dir_name) = self.lookup_parent_node(path).ok_or(())?;
+        let (parent_node, new_dir_name) = match self.lookup_parent_node(path) {
+            // The root directory always exists.
+            Err(FsError::IsADirectory) => return Err(FsError::AlreadyExists),
+            result => result?,
+        };
 
         // Parent directory is not a directory
         let FsNode::Directory {
@@ -980,17 +1173,17 @@ impl Fs {
             writeable: dir_host_path,
         } = parent_node
         else {
-            return Err(());
+            return Err(FsError::NotADirectory);
         };
 
         // There's already a file/directory with this name
         if children.contains_key(&new_dir_name) {
-            return Err(());
+            return Err(FsError::AlreadyExists);
         }
 
         let Some(dir_host_path) = dir_host_path else {
             log!("Warning: attempt to create directory at path {:?}, but parent directory is read-only", path);
-            return Err(());
+            return Err(FsError::PermissionDenied);
         };
 
         for c in new_dir_name.chars() {
@@ -13,24 +13,282 @@
 //!   - `gdb/arch/arm.h` for ARMv6 register numbers
 
 use crate::cpu::{Cpu, CpuError};
-use crate::mem::{GuestUSize, Mem, Ptr};
+use crate::mem::{GuestUSize, Ptr, Watchpoint, WatchpointKind};
+use crate::{Environment, ThreadId};
 use std::fmt::Write as _;
 use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
 use std::net::TcpStream;
 use std::time::Duration;
 
 /// GDB target description XML.
-const TARGET_XML: &str = r#"
+///
+/// The register numbers match the ones GDB uses for ARM when there's no target
+/// description, where 16–24 are the unused FPA registers. GDB derives the
+/// `s0`–`s31` pseudo-registers from `d0`–`d15` by itself.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
 <target version="1.0">
     <architecture>armv6</architecture>
     <osabi>Darwin</osabi>
+    <feature name="org.gnu.gdb.arm.core">
+        <reg name="r0" bitsize="32"/>
+        <reg name="r1" bitsize="32"/>
+        <reg name="r2" bitsize="32"/>
+        <reg name="r3" bitsize="32"/>
+        <reg name="r4" bitsize="32"/>
+        <reg name="r5" bitsize="32"/>
+        <reg name="r6" bitsize="32"/>
+        <reg name="r7" bitsize="32"/>
+        <reg name="r8" bitsize="32"/>
+        <reg name="r9" bitsize="32"/>
+        <reg name="r10" bitsize="32"/>
+        <reg name="r11" bitsize="32"/>
+        <reg name="r12" bitsize="32"/>
+        <reg name="sp" bitsize="32" type="data_ptr"/>
+        <reg name="lr" bitsize="32"/>
+        <reg name="pc" bitsize="32" type="code_ptr"/>
+        <reg name="cpsr" bitsize="32" regnum="25"/>
+    </feature>
+    <feature name="org.gnu.gdb.arm.vfp">
+        <reg name="d0" bitsize="64" type="ieee_double" regnum="26"/>
+        <reg name="d1" bitsize="64" type="ieee_double"/>
+        <reg name="d2" bitsize="64" type="ieee_double"/>
+        <reg name="d3" bitsize="64" type="ieee_double"/>
+        <reg name="d4" bitsize="64" type="ieee_double"/>
+        <reg name="d5" bitsize="64" type="ieee_double"/>
+        <reg name="d6" bitsize="64" type="ieee_double"/>
+        <reg name="d7" bitsize="64" type="ieee_double"/>
+        <reg name="d8" bitsize="64" type="ieee_double"/>
+        <reg name="d9" bitsize="64" type="ieee_double"/>
+        <reg name="d10" bitsize="64" type="ieee_double"/>
+        <reg name="d11" bitsize="64" type="ieee_double"/>
+        <reg name="d12" bitsize="64" type="ieee_double"/>
+        <reg name="d13" bitsize="64" type="ieee_double"/>
+        <reg name="d14" bitsize="64" type="ieee_double"/>
+        <reg name="d15" bitsize="64" type="ieee_double"/>
+        <reg name="fpscr" bitsize="32" type="int" group="float"/>
+    </feature>
 </target>
 "#;
 
+/// Register number of CPSR in [TARGET_XML].
+const REG_CPSR: usize = 25;
+/// Register number of `d0` in [TARGET_XML]. `d1`–`d15` follow it.
+const REG_D0: usize = 26;
+const REG_D15: usize = REG_D0 + 15;
+/// Register number of FPSCR in [TARGET_XML].
+const REG_FPSCR: usize = REG_D15 + 1;
+
+/// Register numbers in the order they appear in the `g` and `G` packets.
+fn g_packet_regs() -> impl Iterator<Item = usize> {
+    (0..16).chain([REG_CPSR]).chain(REG_D0..=REG_FPSCR)
+}
+
+/// Size of a register in bytes. Returns [None] for an unknown register number.
+fn reg_size(num: usize) -> Option<usize> {
+    match num {
+        0..=15 | REG_CPSR | REG_FPSCR => Some(4),
+        REG_D0..=REG_D15 => Some(8),
+        _ => None,
+    }
+}
+
+/// Get the value of a register as little-endian bytes, which is how GDB
+/// expects it to be sent. Returns [None] for an unknown register number.
+fn read_reg(cpu: &Cpu, num: usize) -> Option<Vec<u8>> {
+    match num {
+        0..=15 => Some(cpu.regs()[num].to_le_bytes().to_vec()),
+        REG_CPSR => Some(cpu.cpsr().to_le_bytes().to_vec()),
+        REG_D0..=REG_D15 => {
+            let i = (num - REG_D0) * 2;
+            let words = &cpu.ext_regs()[i..i + 2];
+            Some(words.iter().flat_map(|word| word.to_le_bytes()).collect())
+        }
+        REG_FPSCR => Some(cpu.fpscr().to_le_bytes().to_vec()),
+        _ => None,
+    }
+}
+
+/// Set the value of a register from little-endian bytes. Returns [false] if
+/// the register number is unknown or the value has the wrong size.
+fn write_reg(cpu: &mut Cpu, num: usize, bytes: &[u8]) -> bool {
+    if reg_size(num) != Some(bytes.len()) {
+        return false;
+    }
+    let word = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap());
+    match num {
+        0..=15 => cpu.regs_mut()[num] = word(bytes),
+        REG_CPSR => cpu.set_cpsr(word(bytes)),
+        REG_D0..=REG_D15 => {
+            let i = (num - REG_D0) * 2;
+            let ext_regs = cpu.ext_regs_mut();
+            ext_regs[i] = word(&bytes[..4]);
+            ext_regs[i + 1] = word(&bytes[4..]);
+        }
+        REG_FPSCR => cpu.set_fpscr(word(bytes)),
+        _ => unreachable!(),
+    }
+    true
+}
+
+fn encode_hex(packet: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        write!(packet, "{:02x}", byte).unwrap();
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2)?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+/// Escape a string for use in an XML attribute value. Non-ASCII characters
+/// are escaped too, so the result is pure ASCII.
+fn escape_xml(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for c in string.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            ' '..='~' => escaped.push(c),
+            _ => write!(escaped, "&#x{:x};", c as u32).unwrap(),
+        }
+    }
+    escaped
+}
+
+/// Shared library list XML for the loaded binaries, including the app binary.
+/// Addresses are where the `__TEXT` segments were loaded, which are always the
+/// preferred addresses, so the debugger won't apply a slide.
+fn library_list_xml(env: &Environment) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<library-list>\n");
+    for bin in &env.bins {
+        let Some(addr) = bin.text_segment_addr else {
+            continue;
+        };
+        let name = bin.path.as_ref().map_or(&*bin.name, |path| path.as_str());
+        writeln!(
+            xml,
+            "    <library name=\"{}\"><segment address=\"{:#x}\"/></library>",
+            escape_xml(name),
+            addr
+        )
+        .unwrap();
+    }
+    xml.push_str("</library-list>\n");
+    xml
+}
+
+/// Reply to a `qXfer:object:read` packet, where `params` is the `offset,length`
+/// part and `data` is the ASCII object being read.
+fn xfer_read_reply(data: &str, params: &str) -> String {
+    let (offset, length) = params.split_once(',').unwrap();
+    let offset = usize::from_str_radix(offset, 16).unwrap();
+    let length = usize::from_str_radix(length, 16).unwrap();
+    let bytes = data.as_bytes();
+    if offset > bytes.len() {
+        // Invalid offset
+        return "E00".to_string();
+    }
+    let bytes = &bytes[offset..];
+    let length_read = length.min(bytes.len());
+    let mut packet = String::with_capacity(1 + length_read);
+    if length_read < length {
+        // Read data, none left
+        packet.push('l');
+    } else {
+        // Read data, more may remain
+        packet.push('m');
+    }
+    // This packet uses the modern style of binary data where most bytes are
+    // unescaped. Only '#', '$', '}' and '*' need escaping.
+    for &byte in &bytes[..length_read] {
+        assert!(byte.is_ascii());
+        if matches!(byte, b'#' | b'$' | b'}' | b'*') {
+            packet.push('}');
+            packet.push((byte ^ 0x20) as char);
+        } else {
+            packet.push(byte as char);
+        }
+    }
+    packet
+}
+
+/// GDB's thread IDs must be positive, so touchHLE's are offset by one.
+fn to_gdb_thread_id(thread: ThreadId) -> usize {
+    thread + 1
+}
+
+/// Parse a GDB thread ID. Returns `Some(None)` if the ID means any thread
+/// (`0`) or all threads (`-1`), and [None] if it's not a valid active thread.
+fn parse_thread_id(id: &str, env: &Environment) -> Option<Option<ThreadId>> {
+    if id == "0" || id == "-1" {
+        return Some(None);
+    }
+    let thread = usize::from_str_radix(id, 16).ok()?.checked_sub(1)?;
+    env.threads
+        .get(thread)
+        .filter(|thread| thread.active)
+        .map(|_| Some(thread))
+}
+
+/// Stop reply packet, which also tells the debugger which thread stopped.
+/// `extra` can contain additional `name:value;` pairs.
+fn stop_reply(env: &Environment, signal: u8, extra: &str) -> String {
+    format!(
+        "T{:02x}{}thread:{:x};",
+        signal,
+        extra,
+        to_gdb_thread_id(env.current_thread)
+    )
+}
+
+/// Set the PC to the address given in a continue or step packet. The CPU stays
+/// in the same mode (Arm or Thumb).
+fn resume_at(env: &mut Environment, addr: &str) {
+    let addr = GuestUSize::from_str_radix(addr, 16).unwrap();
+    env.cpu.regs_mut()[Cpu::PC] = addr;
+}
+
+/// Deliver a signal that the debugger asked to continue or step with. Signal
+/// numbers are GDB's, but the ones up to `SIGUSR2` are the same as the guest's,
+/// except for 29 (`SIGLOST` for GDB, `SIGINFO` for the guest). `stop_reason` is
+/// why execution stopped, so an invalid memory access can be passed on.
+fn deliver_signal(env: &mut Environment, signal: u8, stop_reason: &Option<CpuError>) {
+    match signal {
+        // No signal. SIGTRAP and SIGINT are normally caused by the debugger
+        // itself, so they're not passed on to the app.
+        0x00 | 0x02 | 0x05 => (),
+        0x01..=0x1c | 0x1e | 0x1f => {
+            log!("Debugger delivered signal {:#x}.", signal);
+            let fault = match stop_reason {
+                Some(CpuError::MemoryError(fault)) => Some(*fault),
+                _ => None,
+            };
+            crate::libc::signal::send_from_debugger(env, signal.into(), fault);
+        }
+        _ => log!(
+            "Debugger delivered signal {:#x}, which the app doesn't have, ignoring it.",
+            signal
+        ),
+    }
+}
+
 /// GDB Remote Serial Protocol handler, implementing a server.
 pub struct GdbServer {
     reader: BufReader<TcpStream>,
     first_halt: bool,
+    /// Thread used for register accesses, selected with the `Hg` packet.
+    selected_thread: ThreadId,
 }
 
 impl GdbServer {
@@ -54,6 +312,7 @@ impl GdbServer {
         GdbServer {
             reader: BufReader::with_capacity(4096, connection),
             first_halt: true,
+            selected_thread: 0,
         }
     }
 
@@ -120,6 +379,90 @@ impl GdbServer {
         Some(body)
     }
 
+    /// Pick the action from a `vCont` packet that applies to the current
+    /// thread, and return the signal it passes on (if any) along with whether
+    /// it means stepping. Returns [None] if the action isn't supported.
+    fn parse_vcont(&self, actions: &str, env: &Environment) -> Option<(Option<u8>, bool)> {
+        let actions: Vec<(&str, Option<Option<ThreadId>>)> = actions
+            .split(';')
+            .map(|action| match action.split_once(':') {
+                Some((action, id)) => (action, parse_thread_id(id, env)),
+                None => (action, Some(None)),
+            })
+            .collect();
+        // The leftmost action that applies to a thread is the one used.
+        let action = actions.iter().find(|&&(_, thread)| match thread {
+            Some(Some(thread)) => thread == env.current_thread,
+            Some(None) => true,
+            None => false,
+        });
+        let action = match action {
+            Some(&(action, _)) => action,
+            None => {
+                // touchHLE's scheduler decides which thread runs, and the
+                // others can't be kept stopped.
+                log!(
+                    "Warning: No vCont action for current thread {:x}, using first action.",
+                    to_gdb_thread_id(env.current_thread)
+                );
+                actions[0].0
+            }
+        };
+        let step = match action.as_bytes().first() {
+            Some(b'c' | b'C') => false,
+            Some(b's' | b'S') => true,
+            _ => {
+                log!("Warning: Unsupported vCont action {:?}", action);
+                return None;
+            }
+        };
+        let signal = if action.len() > 1 {
+            Some(u8::from_str_radix(&action[1..], 16).ok()?)
+        } else {
+            None
+        };
+        Some((signal, step))
+    }
+
+    /// Handle a command sent with GDB's "monitor" command, or LLDB's
+    /// "process plugin packet monitor".
+    fn monitor_command(&mut self, command: &str, env: &mut Environment) {
+        let output = match command.split_whitespace().collect::<Vec<_>>()[..] {
+            ["host-functions"] => {
+                // There's no way to send symbols to the debugger directly, so
+                // just list them. The addresses can be used as breakpoints.
+                let mut output = String::new();
+                for (addr, symbol) in env.dyld.host_function_symbols(&env.bins) {
+                    writeln!(output, "{:#010x} {}", addr, symbol).unwrap();
+                }
+                output
+            }
+            ["backtrace" | "bt"] => {
+                // GDB can't unwind the stack without debug info, but touchHLE
+                // can, and can name the app's methods too.
+                let mut output = String::new();
+                for line in env.stack_trace(self.selected_thread) {
+                    writeln!(output, "{}", line).unwrap();
+                }
+                output
+            }
+            _ => "Supported commands:\n\
+                  backtrace         Show a symbolicated stack trace for the \
+                  selected thread\n\
+                  host-functions    List addresses and names of host-implemented \
+                  functions\n"
+                .to_string(),
+        };
+        // Console output packets, then the final reply. The packets are kept
+        // fairly short because of the debugger's packet size limit.
+        for line in output.as_bytes().chunks(1024) {
+            let mut packet = String::from("O");
+            encode_hex(&mut packet, line);
+            self.send_packet(&packet);
+        }
+        self.send_packet("OK");
+    }
+
     fn send_packet(&mut self, body: &str) {
         let checksum = body.bytes().fold(0u8, |a, b| a.wrapping_add(b));
         write!(self.reader.get_mut(), "${}#{:02x}", body, checksum).unwrap();
@@ -133,11 +476,12 @@ impl GdbServer {
     pub fn wait_for_debugger(
         &mut self,
         stop_reason: Option<CpuError>,
-        cpu: &mut Cpu,
-        mem: &mut Mem,
+        env: &mut Environment,
     ) -> bool {
         echo!("Waiting for debugger to continue.");
 
+        self.selected_thread = env.current_thread;
+
         // Send reply to continue/step packet that gdb sent earlier, so it knows
         // why execution was stopped.
         match stop_reason {
@@ -149,7 +493,7 @@ impl GdbServer {
                 } else {
                     // The debugger previously requested stepping and no errors
                     // occurred.
-                    self.send_packet("S05"); // SIGTRAP
+                    self.send_packet(&stop_reply(env, 0x05, "")); // SIGTRAP
                 }
             }
             // GDB uses an undefined instruction for software breakpoints in
@@ -157,10 +501,20 @@ impl GdbServer {
             // It apparently expects SIGTRAP instead of SIGILL even in the
             // former case.
             Some(CpuError::UndefinedInstruction) | Some(CpuError::Breakpoint) => {
-                self.send_packet("S05"); // SIGTRAP
+                self.send_packet(&stop_reply(env, 0x05, "")); // SIGTRAP
             }
-            Some(CpuError::MemoryError) => {
-                self.send_packet("S0b"); // SIGSEGV
+            Some(CpuError::MemoryError(_)) => {
+                self.send_packet(&stop_reply(env, 0x0b, "")); // SIGSEGV
+            }
+            Some(CpuError::Watchpoint) => {
+                let (watchpoint, addr) = env.mem.take_watchpoint_hit().unwrap();
+                let kind = match watchpoint.kind {
+                    WatchpointKind::Write => "watch",
+                    WatchpointKind::Read => "rwatch",
+                    WatchpointKind::Access => "awatch",
+                };
+                let extra = format!("{}:{:x};", kind, addr);
+                self.send_packet(&stop_reply(env, 0x05, &extra)); // SIGTRAP
             }
         }
 
@@ -177,50 +531,43 @@ impl GdbServer {
                 // Query for target halt reason when first connecting
                 b'?' => {
                     assert!(stop_reason.is_none());
-                    self.send_packet("S00"); // no signal
+                    self.send_packet(&stop_reply(env, 0x00, "")); // no signal
                 }
                 // Read general registers
                 b'g' => {
-                    let mut packet = String::with_capacity(16 * 4 * 2);
-                    for reg in cpu.regs() {
-                        // Rust always prints in big-endian, but GDB expects
-                        // little-endian.
-                        let reg = u32::from_be_bytes(reg.to_le_bytes());
-                        write!(packet, "{:08x}", reg).unwrap();
-                    }
+                    let mut packet = String::new();
+                    env.with_thread_cpu(self.selected_thread, |cpu| {
+                        for num in g_packet_regs() {
+                            encode_hex(&mut packet, &read_reg(cpu, num).unwrap());
+                        }
+                    });
                     self.send_packet(&packet);
                 }
                 // Write general registers
                 b'G' => {
-                    let data = &p[1..];
-                    let regs = cpu.regs_mut();
-                    assert!(data.len() == regs.len() * 4 * 2);
-                    for (i, reg) in regs.iter_mut().enumerate() {
-                        let word = &data[i * 4 * 2..][..4 * 2];
-                        let word = u32::from_str_radix(word, 16).unwrap();
-                        // Rust decodes in big-endian, but GDB supplies
-                        // little-endian.
-                        let word = u32::from_le_bytes(word.to_be_bytes());
-                        *reg = word;
-                    }
+                    let mut data = &decode_hex(&p[1..]).unwrap()[..];
+                    env.with_thread_cpu(self.selected_thread, |cpu| {
+                        for num in g_packet_regs() {
+                            let size = reg_size(num).unwrap();
+                            // GDB may omit registers at the end.
+                            if data.len() < size {
+                                break;
+                            }
+                            let (value, rest) = data.split_at(size);
+                            assert!(write_reg(cpu, num, value));
+                            data = rest;
+                        }
+                    });
                     self.send_packet("OK");
                 }
                 // Read single register by number
                 b'p' => {
                     let num = usize::from_str_radix(&p[1..], 16).unwrap();
-                    let reg = if num < 16 {
-                        Some(cpu.regs()[num])
-                    } else if num == 25 {
-                        Some(cpu.cpsr())
-                    // TODO: FPSCR, VFP registers
-                    } else {
-                        None
-                    };
-                    if let Some(reg) = reg {
-                        // Rust always prints in big-endian, but GDB expects
-                        // little-endian.
-                        let reg = u32::from_be_bytes(reg.to_le_bytes());
-                        self.send_packet(&format!("{:08x}", reg));
+                    let value = env.with_thread_cpu(self.selected_thread, |cpu| read_reg(cpu, num));
+                    if let Some(value) = value {
+                        let mut packet = String::new();
+                        encode_hex(&mut packet, &value);
+                        self.send_packet(&packet);
                     } else {
                         // Error 0
                         self.send_packet("E00");
@@ -228,19 +575,12 @@ impl GdbServer {
                 }
                 // Write single register by number
                 b'P' => {
-                    let (num, word) = p[1..].split_once('=').unwrap();
+                    let (num, value) = p[1..].split_once('=').unwrap();
                     let num = usize::from_str_radix(num, 16).unwrap();
-                    let word = u32::from_str_radix(word, 16).unwrap();
-                    // Rust decodes in big-endian, but GDB supplies
-                    // little-endian.
-                    let word = u32::from_le_bytes(word.to_be_bytes());
-                    if num < 16 {
-                        cpu.regs_mut()[num] = word;
-                        self.send_packet("OK");
-                    } else if num == 25 {
-                        cpu.set_cpsr(word);
+                    let value = decode_hex(value).unwrap();
+                    if env.with_thread_cpu(self.selected_thread, |cpu| write_reg(cpu, num, &value))
+                    {
                         self.send_packet("OK");
-                    // TODO: FPSCR, VFP registers
                     } else {
                         // Error 0
                         self.send_packet("E00");
@@ -252,7 +592,7 @@ impl GdbServer {
                     let addr = GuestUSize::from_str_radix(addr, 16).unwrap();
                     let length = GuestUSize::from_str_radix(length, 16).unwrap();
                     let mut packet = String::with_capacity(length as usize * 2);
-                    match mem.get_bytes_fallible(Ptr::from_bits(addr), length) {
+                    match env.mem.get_bytes_fallible(Ptr::from_bits(addr), length) {
                         Some(data) => {
                             for byte in data {
                                 write!(packet, "{:02x}", byte).unwrap();
@@ -273,7 +613,7 @@ impl GdbServer {
                     let length = GuestUSize::from_str_radix(length, 16).unwrap();
                     assert!(data.len() == length as usize * 2);
 
-                    match mem.get_bytes_fallible_mut(Ptr::from_bits(addr), length) {
+                    match env.mem.get_bytes_fallible_mut(Ptr::from_bits(addr), length) {
                         Some(dest) => {
                             for i in 0..(length as usize) {
                                 let byte = &data[i * 2..][..2];
@@ -281,7 +621,7 @@ impl GdbServer {
                                 dest[i] = byte;
                             }
                             // Important for e.g. software breakpoints.
-                            cpu.invalidate_cache_range(addr, length);
+                            env.cpu.invalidate_cache_range(addr, length);
                             self.send_packet("OK");
                         }
                         None => {
@@ -294,19 +634,86 @@ impl GdbServer {
                 b'c' | b's' => {
                     let addr = &p[1..];
                     if !addr.is_empty() {
-                        todo!("TODO: Resume at {}", addr);
+                        resume_at(env, addr);
                     }
                     break p.as_bytes()[0] == b's';
                 }
-                // "Continue with signal" or "Step with signal".
-                // Presumably "with" means "ignoring"?
+                // Continue or Step with signal
                 b'C' | b'S' => {
-                    // Signal is just ignored for now (TODO?)
-                    if let Some((_signal, addr)) = p[1..].split_once(';') {
-                        todo!("TODO: Resume at {}", addr);
+                    let (signal, addr) = match p[1..].split_once(';') {
+                        Some((signal, addr)) => (signal, Some(addr)),
+                        None => (&p[1..], None),
+                    };
+                    if let Some(addr) = addr {
+                        resume_at(env, addr);
                     }
+                    deliver_signal(env, u8::from_str_radix(signal, 16).unwrap(), &stop_reason);
                     break p.as_bytes()[0] == b'S';
                 }
+                // Insert or remove a watchpoint (write, read or access)
+                b'Z' | b'z' if matches!(p.as_bytes().get(1), Some(b'2'..=b'4')) => {
+                    let kind = match p.as_bytes()[1] {
+                        b'2' => WatchpointKind::Write,
+                        b'3' => WatchpointKind::Read,
+                        _ => WatchpointKind::Access,
+                    };
+                    let (addr, size) = p[3..].split_once(',').unwrap();
+                    let watchpoint = Watchpoint {
+                        addr: GuestUSize::from_str_radix(addr, 16).unwrap(),
+                        size: GuestUSize::from_str_radix(size, 16).unwrap(),
+                        kind,
+                    };
+                    let success = if p.as_bytes()[0] == b'Z' {
+                        env.mem.add_watchpoint(watchpoint);
+                        true
+                    } else {
+                        env.mem.remove_watchpoint(watchpoint)
+                    };
+                    // Watchpoints are only checked on the CPU's slow path.
+                    env.cpu.update_page_slow_paths(&env.mem, watchpoint.pages());
+                    if success {
+                        self.send_packet("OK");
+                    } else {
+                        // Error 0
+                        self.send_packet("E00");
+                    }
+                }
+                // Set thread for subsequent operations
+                b'H' if p.len() > 2 => {
+                    let (op, id) = p[1..].split_at(1);
+                    match (op, parse_thread_id(id, env)) {
+                        // Register accesses
+                        ("g", Some(thread)) => {
+                            self.selected_thread = thread.unwrap_or(env.current_thread);
+                            self.send_packet("OK");
+                        }
+                        // Continuing and stepping. touchHLE's scheduler decides
+                        // which thread runs, so this can't really be honored.
+                        ("c", Some(thread)) => {
+                            if thread.is_some_and(|thread| thread != env.current_thread) {
+                                log!(
+                                    "Warning: Can't resume thread {}, only current thread {:x}",
+                                    id,
+                                    to_gdb_thread_id(env.current_thread)
+                                );
+                            }
+                            self.send_packet("OK");
+                        }
+                        _ => {
+                            // Error 0
+                            self.send_packet("E00");
+                        }
+                    }
+                }
+                // Query whether thread is alive
+                b'T' => {
+                    if let Some(Some(_)) = parse_thread_id(&p[1..], env) {
+                        self.send_packet("OK");
+                    } else {
+                        // Error 0
+                        self.send_packet("E00");
+                    }
+                }
                 // Kill
                 b'k' => {
                     panic!("Debugger requested kill.");
@@ -317,44 +724,74 @@ impl GdbServer {
                     if p == "qAttached" {
                         // New process
                         self.send_packet("0");
+                    // Query for list of active threads (first and subsequent
+                    // parts; the whole list is always sent in the first part)
+                    } else if p == "qfThreadInfo" {
+                        let threads: Vec<String> = (0..env.threads.len())
+                            .filter(|&thread| env.threads[thread].active)
+                            .map(|thread| format!("{:x}", to_gdb_thread_id(thread)))
+                            .collect();
+                        self.send_packet(&format!("m{}", threads.join(",")));
+                    // Query for supported vCont actions
+                    } else if p == "vCont?" {
+                        self.send_packet("vCont;c;C;s;S");
+                    // Continue or step, with actions for each thread
+                    } else if let Some(actions) = p.strip_prefix("vCont;") {
+                        if let Some((signal, step)) = self.parse_vcont(actions, env) {
+                            if let Some(signal) = signal {
+                                deliver_signal(env, signal, &stop_reason);
+                            }
+                            break step;
+                        } else {
+                            // Error 0
+                            self.send_packet("E00");
+                        }
+                    } else if p == "qsThreadInfo" {
+                        // End of list
+                        self.send_packet("l");
+                    // Query for current thread
+                    } else if p == "qC" {
+                        self.send_packet(&format!("QC{:x}", to_gdb_thread_id(env.current_thread)));
+                    // Query for thread description, shown by "info threads"
+                    } else if let Some(id) = p.strip_prefix("qThreadExtraInfo,") {
+                        if let Some(Some(thread)) = parse_thread_id(id, env) {
+                            let mut packet = String::new();
+                            encode_hex(&mut packet, env.describe_thread(thread).as_bytes());
+                            self.send_packet(&packet);
+                        } else {
+                            // Error 0
+                            self.send_packet("E00");
+                        }
                     // Query for supported features
                     } else if p == "qSupported" || p.starts_with("qSupported:") {
-                        // Tell GDB we can send it an XML target description.
-                        self.send_packet("qXfer:features:read+");
+                        // Tell GDB we can send it an XML target description and
+                        // a list of loaded binaries.
+                        self.send_packet("qXfer:features:read+;qXfer:libraries:read+");
                     // Read XML target description
                     } else if let Some(params) = p.strip_prefix("qXfer:features:read:") {
                         let (annex, params) = params.split_once(':').unwrap();
-                        let (offset, length) = params.split_once(',').unwrap();
-                        let offset = usize::from_str_radix(offset, 16).unwrap();
-                        let length = usize::from_str_radix(length, 16).unwrap();
-                        let bytes = TARGET_XML.as_bytes();
-                        if annex == "target.xml" && offset <= bytes.len() {
-                            let bytes = &bytes[offset..];
-                            let length_read = length.min(bytes.len());
-                            let mut packet = String::with_capacity(1 + length_read);
-                            if length_read < length {
-                                // Read data, more remains
-                                packet.push('l');
-                            } else {
-                                // Read data, none left
-                                packet.push('m');
-                            }
-                            // This packet uses the modern style of binary
-                            // data where most bytes are unescaped.
-                            // We happen to know none of the bytes in the XML
-                            // need escaping, and that they're all ASCII.
-                            packet.push_str(std::str::from_utf8(&bytes[..length_read]).unwrap());
-                            self.send_packet(&packet);
+                        if annex == "target.xml" {
+                            self.send_packet(&xfer_read_reply(TARGET_XML, params));
                         } else {
-                            // Unsupported annex or invalid offset
+                            // Unsupported annex
                             self.send_packet("E00");
                         }
+                    // Read list of loaded binaries (shared libraries)
+                    } else if let Some(params) = p.strip_prefix("qXfer:libraries:read::") {
+                        self.send_packet(&xfer_read_reply(&library_list_xml(env), params));
+                    // Command sent with "monitor"
+                    } else if let Some(command) = p.strip_prefix("qRcmd,") {
+                        match decode_hex(command).map(String::from_utf8) {
+                            Some(Ok(command)) => self.monitor_command(&command, env),
+                            // Error 0
+                            _ => self.send_packet("E00"),
+                        }
                     } else {
                         log_dbg!("Unhandled packet.");
                         // Tell GDB we don't understand this packet.
                         // In some cases this causes convenient fallbacks:
-                        // Since we don't support 'Z', GDB will implement
-                        // software breakpoints for us with trap instructions.
+                        // Since we don't support 'Z0' and 'Z1', GDB will
+                        // implement breakpoints for us with trap instructions.
                         self.send_packet("");
                     }
                 }
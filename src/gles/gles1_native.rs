@@ -14,13 +14,20 @@
 
 use super::gles11_raw as gles11;
 use super::gles11_raw::types::*;
-use super::util::{try_decode_pvrtc, PalettedTextureFormat};
+use super::util::{
+    has_extension, pvrtc_format_is_2bit, try_decode_pvrtc, DecodedTextureCache,
+    PalettedTextureFormat,
+};
 use super::GLES;
 use crate::window::{GLContext, GLVersion, Window};
 use std::ffi::CStr;
 
 pub struct GLES1Native {
     gl_ctx: GLContext,
+    /// Whether the driver supports `IMG_texture_compression_pvrtc`. This is
+    /// determined lazily because the context must be current to check it.
+    supports_pvrtc: Option<bool>,
+    decoded_texture_cache: DecodedTextureCache,
 }
 impl GLES for GLES1Native {
     fn description() -> &'static str {
@@ -30,6 +37,10 @@ impl GLES for GLES1Native {
     fn new(window: &mut Window) -> Result<Self, String> {
         Ok(Self {
             gl_ctx: window.create_gl_context(GLVersion::GLES11)?,
+            supports_pvrtc: None,
+            decoded_texture_cache: DecodedTextureCache::new(
+                DecodedTextureCache::DEFAULT_SIZE_LIMIT,
+            ),
         })
     }
 
@@ -77,6 +88,9 @@ impl GLES for GLES1Native {
     unsafe fn GetBooleanv(&mut self, pname: GLenum, params: *mut GLboolean) {
         gles11::GetBooleanv(pname, params)
     }
+    unsafe fn GetFixedv(&mut self, pname: GLenum, params: *mut GLfixed) {
+        gles11::GetFixedv(pname, params)
+    }
     unsafe fn GetFloatv(&mut self, pname: GLenum, params: *mut GLfloat) {
         gles11::GetFloatv(pname, params)
     }
@@ -474,12 +488,36 @@ impl GLES for GLES1Native {
     ) {
         let data = unsafe { std::slice::from_raw_parts(data.cast::<u8>(), image_size as usize) };
         // IMG_texture_compression_pvrtc (only on Imagination/Apple GPUs)
-        // TODO: It would be more efficient to use hardware decoding where
-        // available (I just don't have a suitable device to try this on)
-        if try_decode_pvrtc(
-            self,
-            target,
-            level,
+        if pvrtc_format_is_2bit(internalformat).is_some() {
+            let supports_pvrtc = *self.supports_pvrtc.get_or_insert_with(|| {
+                let supported = has_extension(
+                    gles11::GetString(gles11::EXTENSIONS),
+                    "GL_IMG_texture_compression_pvrtc",
+                );
+                if supported {
+                    log!("Driver supports PVRTC, it will be used directly.");
+                } else {
+                    log!("Driver doesn't support PVRTC, it will be decoded in software.");
+                }
+                supported
+            });
+            if supports_pvrtc {
+                log_dbg!("Directly supported texture format: {:#x}", internalformat);
+                gles11::CompressedTexImage2D(
+                    target,
+                    level,
+                    internalformat,
+                    width,
+                    height,
+                    border,
+                    image_size,
+                    data.as_ptr() as *const _,
+                );
+                return;
+            }
+        }
+        if let Some(pixels) = try_decode_pvrtc(
+            &mut self.decoded_texture_cache,
             internalformat,
             width,
             height,
@@ -487,6 +525,17 @@ impl GLES for GLES1Native {
             data,
         ) {
             log_dbg!("Decoded PVRTC");
+            gles11::TexImage2D(
+                target,
+                level,
+                gles11::RGBA as _,
+                width,
+                height,
+                border,
+                gles11::RGBA,
+                gles11::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const _,
+            );
             return;
         }
 
@@ -22,8 +22,8 @@ use super::gl21compat_raw as gl21;
 use super::gl21compat_raw::types::*;
 use super::gles11_raw as gles11; // constants only
 use super::util::{
-    fixed_to_float, matrix_fixed_to_float, try_decode_pvrtc, PalettedTextureFormat, ParamTable,
-    ParamType,
+    fixed_to_float, matrix_fixed_to_float, try_decode_pvrtc, DecodedTextureCache,
+    PalettedTextureFormat, ParamTable, ParamType,
 };
 use super::GLES;
 use crate::window::{GLContext, GLVersion, Window};
@@ -124,6 +124,29 @@ pub const ARRAYS: &[ArrayInfo] = &[
     },
 ];
 
+/// List of compressed texture formats reported by
+/// `glGet(GL_COMPRESSED_TEXTURE_FORMATS)`. OpenGL 2.1 doesn't support any of
+/// these natively, they are decoded in software by `glCompressedTexImage2D`,
+/// and the host's own compressed formats must not be reported to the app.
+pub const COMPRESSED_TEXTURE_FORMATS: &[GLenum] = &[
+    // IMG_texture_compression_pvrtc
+    gles11::COMPRESSED_RGB_PVRTC_4BPPV1_IMG,
+    gles11::COMPRESSED_RGB_PVRTC_2BPPV1_IMG,
+    gles11::COMPRESSED_RGBA_PVRTC_4BPPV1_IMG,
+    gles11::COMPRESSED_RGBA_PVRTC_2BPPV1_IMG,
+    // OES_compressed_paletted_texture
+    gles11::PALETTE4_RGB8_OES,
+    gles11::PALETTE4_RGBA8_OES,
+    gles11::PALETTE4_R5_G6_B5_OES,
+    gles11::PALETTE4_RGBA4_OES,
+    gles11::PALETTE4_RGB5_A1_OES,
+    gles11::PALETTE8_RGB8_OES,
+    gles11::PALETTE8_RGBA8_OES,
+    gles11::PALETTE8_R5_G6_B5_OES,
+    gles11::PALETTE8_RGBA4_OES,
+    gles11::PALETTE8_RGB5_A1_OES,
+];
+
 /// Table of `glGet` parameters shared by OpenGL ES 1.1 and OpenGL 2.1.
 const GET_PARAMS: ParamTable = ParamTable(&[
     (gl21::ACTIVE_TEXTURE, ParamType::Int, 1),
@@ -132,7 +155,7 @@ const GET_PARAMS: ParamTable = ParamTable(&[
     (gl21::ALPHA_BITS, ParamType::Int, 1),
     (gl21::ALPHA_TEST, ParamType::Boolean, 1),
     (gl21::ALPHA_TEST_FUNC, ParamType::Int, 1),
-    // TODO: ALPHA_TEST_REF (has special type conversion behavior)
+    (gl21::ALPHA_TEST_REF, ParamType::FloatNormalized, 1),
     (gl21::ARRAY_BUFFER_BINDING, ParamType::Int, 1),
     (gl21::BLEND, ParamType::Boolean, 1),
     (gl21::BLEND_DST, ParamType::Int, 1),
@@ -146,36 +169,42 @@ const GET_PARAMS: ParamTable = ParamTable(&[
     (gl21::COLOR_ARRAY_SIZE, ParamType::Int, 1),
     (gl21::COLOR_ARRAY_STRIDE, ParamType::Int, 1),
     (gl21::COLOR_ARRAY_TYPE, ParamType::Int, 1),
-    (gl21::COLOR_CLEAR_VALUE, ParamType::FloatSpecial, 4), // TODO correct type
+    (gl21::COLOR_CLEAR_VALUE, ParamType::FloatNormalized, 4),
     (gl21::COLOR_LOGIC_OP, ParamType::Boolean, 1),
     (gl21::COLOR_MATERIAL, ParamType::Boolean, 1),
     (gl21::COLOR_WRITEMASK, ParamType::Boolean, 4),
-    // TODO: COMPRESSED_TEXTURE_FORMATS (need to support PVRTC etc)
+    // Emulated, see COMPRESSED_TEXTURE_FORMATS.
+    (
+        gl21::COMPRESSED_TEXTURE_FORMATS,
+        ParamType::Int,
+        COMPRESSED_TEXTURE_FORMATS.len() as u8,
+    ),
     (gl21::CULL_FACE, ParamType::Boolean, 1),
     (gl21::CULL_FACE_MODE, ParamType::Int, 1),
-    (gl21::CURRENT_COLOR, ParamType::FloatSpecial, 4), // TODO correct type
-    // TODO: CURRENT_NORMAL (has special type conversion behavior)
+    (gl21::CURRENT_COLOR, ParamType::FloatNormalized, 4),
+    (gl21::CURRENT_NORMAL, ParamType::FloatNormalized, 3),
     (gl21::CURRENT_TEXTURE_COORDS, ParamType::Float, 4),
     (gl21::DEPTH_BITS, ParamType::Int, 1),
-    // TODO: DEPTH_CLEAR_VALUE (has special type conversion behavior)
+    (gl21::DEPTH_CLEAR_VALUE, ParamType::FloatNormalized, 1),
     (gl21::DEPTH_FUNC, ParamType::Int, 1),
-    // TODO: DEPTH_RANGE (has special type conversion behavior)
+    (gl21::DEPTH_RANGE, ParamType::FloatNormalized, 2),
     (gl21::DEPTH_TEST, ParamType::Boolean, 1),
     (gl21::DEPTH_WRITEMASK, ParamType::Boolean, 1),
     (gl21::DITHER, ParamType::Boolean, 1),
     (gl21::ELEMENT_ARRAY_BUFFER_BINDING, ParamType::Int, 1),
     (gl21::FOG, ParamType::Boolean, 1),
-    // TODO: FOG_COLOR (has special type conversion behavior)
+    (gl21::FOG_COLOR, ParamType::FloatNormalized, 4),
     (gl21::FOG_HINT, ParamType::Int, 1),
     (gl21::FOG_MODE, ParamType::Int, 1),
     (gl21::FOG_DENSITY, ParamType::Float, 1),
     (gl21::FOG_START, ParamType::Float, 1),
     (gl21::FOG_END, ParamType::Float, 1),
     (gl21::FRONT_FACE, ParamType::Int, 1),
+    (gl21::GENERATE_MIPMAP_HINT, ParamType::Int, 1),
     (gl21::GREEN_BITS, ParamType::Int, 1),
     // TODO: IMPLEMENTATION_COLOR_READ_FORMAT_OES? (not shared)
     // TODO: IMPLEMENTATION_COLOR_READ_TYPE_OES? (not shared)
-    // TODO: LIGHT_MODEL_AMBIENT (has special type conversion behavior)
+    (gl21::LIGHT_MODEL_AMBIENT, ParamType::FloatNormalized, 4),
     (gl21::LIGHT_MODEL_TWO_SIDE, ParamType::Boolean, 1),
     // TODO: arbitrary number of lights?
     (gl21::LIGHT0, ParamType::Boolean, 1),
@@ -200,7 +229,7 @@ const GET_PARAMS: ParamTable = ParamTable(&[
     (gl21::MAX_TEXTURE_SIZE, ParamType::Int, 1),
     (gl21::MAX_TEXTURE_STACK_DEPTH, ParamType::Int, 1),
     (gl21::MAX_TEXTURE_UNITS, ParamType::Int, 1),
-    (gl21::MAX_VIEWPORT_DIMS, ParamType::Int, 1),
+    (gl21::MAX_VIEWPORT_DIMS, ParamType::Int, 2),
     (gl21::MODELVIEW_MATRIX, ParamType::Float, 16),
     (gl21::MODELVIEW_STACK_DEPTH, ParamType::Int, 1),
     (gl21::MULTISAMPLE, ParamType::Boolean, 1),
@@ -209,7 +238,8 @@ const GET_PARAMS: ParamTable = ParamTable(&[
     (gl21::NORMAL_ARRAY_STRIDE, ParamType::Int, 1),
     (gl21::NORMAL_ARRAY_TYPE, ParamType::Int, 1),
     (gl21::NORMALIZE, ParamType::Boolean, 1),
-    // TODO: NUM_COMPRESSED_TEXTURE_FORMATS (need to support PVRTC etc)
+    // Emulated, see COMPRESSED_TEXTURE_FORMATS.
+    (gl21::NUM_COMPRESSED_TEXTURE_FORMATS, ParamType::Int, 1),
     (gl21::PACK_ALIGNMENT, ParamType::Int, 1),
     (gl21::PERSPECTIVE_CORRECTION_HINT, ParamType::Int, 1),
     (gl21::POINT_DISTANCE_ATTENUATION, ParamType::Float, 3),
@@ -219,8 +249,8 @@ const GET_PARAMS: ParamTable = ParamTable(&[
     (gl21::POINT_SIZE_MAX, ParamType::Float, 1),
     (gl21::POINT_SIZE_MIN, ParamType::Float, 1),
     (gl21::POINT_SIZE_RANGE, ParamType::Float, 2),
-    (gl21::POINT_SMOOTH, ParamType::Boolean, 2),
-    (gl21::POINT_SMOOTH_HINT, ParamType::Int, 2),
+    (gl21::POINT_SMOOTH, ParamType::Boolean, 1),
+    (gl21::POINT_SMOOTH_HINT, ParamType::Int, 1),
     (gl21::POINT_SPRITE, ParamType::Boolean, 1),
     (gl21::POLYGON_OFFSET_FACTOR, ParamType::Float, 1),
     (gl21::POLYGON_OFFSET_FILL, ParamType::Boolean, 1),
@@ -295,7 +325,7 @@ const FOG_PARAMS: ParamTable = ParamTable(&[
     (gl21::FOG_DENSITY, ParamType::Float, 1),
     (gl21::FOG_START, ParamType::Float, 1),
     (gl21::FOG_END, ParamType::Float, 1),
-    (gl21::FOG_COLOR, ParamType::FloatSpecial, 4), // TODO correct type
+    (gl21::FOG_COLOR, ParamType::FloatNormalized, 4),
 ]);
 
 /// Table of `glLight` parameters shared by OpenGL ES 1.1 and OpenGL 2.1.
@@ -364,6 +394,7 @@ pub struct GLES1OnGL2 {
     pointer_is_fixed_point: [bool; ARRAYS.len()],
     fixed_point_texture_units: HashSet<GLenum>,
     fixed_point_translation_buffers: [Vec<GLfloat>; ARRAYS.len()],
+    decoded_texture_cache: DecodedTextureCache,
 }
 impl GLES1OnGL2 {
     /// If any arrays with fixed-point data are in use at the time of a draw
@@ -495,6 +526,28 @@ impl GLES1OnGL2 {
         }
         backups
     }
+    /// Shared implementation of `glGetBooleanv`, `glGetFixedv`, `glGetFloatv`
+    /// and `glGetIntegerv`. See [ParamTable::getv].
+    unsafe fn get_param<T>(pname: GLenum, params: *mut T, convert: fn(ParamType, f64) -> T) {
+        GET_PARAMS.getv(
+            |params| gl21::GetBooleanv(pname, params),
+            |params| gl21::GetFloatv(pname, params),
+            |params| match pname {
+                gl21::NUM_COMPRESSED_TEXTURE_FORMATS => {
+                    params.write(COMPRESSED_TEXTURE_FORMATS.len() as GLint)
+                }
+                gl21::COMPRESSED_TEXTURE_FORMATS => {
+                    for (i, &format) in COMPRESSED_TEXTURE_FORMATS.iter().enumerate() {
+                        params.add(i).write(format as GLint)
+                    }
+                }
+                _ => gl21::GetIntegerv(pname, params),
+            },
+            pname,
+            params,
+            convert,
+        )
+    }
     unsafe fn restore_fixed_point_arrays(
         &mut self,
         from_backup: [Option<ArrayStateBackup>; ARRAYS.len()],
@@ -553,6 +606,9 @@ impl GLES for GLES1OnGL2 {
             pointer_is_fixed_point: [false; ARRAYS.len()],
             fixed_point_texture_units: HashSet::new(),
             fixed_point_translation_buffers: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+            decoded_texture_cache: DecodedTextureCache::new(
+                DecodedTextureCache::DEFAULT_SIZE_LIMIT,
+            ),
         })
     }
 
@@ -612,23 +668,16 @@ impl GLES for GLES1OnGL2 {
         gl21::DisableClientState(array);
     }
     unsafe fn GetBooleanv(&mut self, pname: GLenum, params: *mut GLboolean) {
-        let (type_, _count) = GET_PARAMS.get_type_info(pname);
-        // TODO: type conversion
-        assert!(type_ == ParamType::Boolean);
-        gl21::GetBooleanv(pname, params);
+        Self::get_param(pname, params, ParamType::value_to_boolean)
+    }
+    unsafe fn GetFixedv(&mut self, pname: GLenum, params: *mut GLfixed) {
+        Self::get_param(pname, params, ParamType::value_to_fixed)
     }
-    // TODO: GetFixedv
     unsafe fn GetFloatv(&mut self, pname: GLenum, params: *mut GLfloat) {
-        let (type_, _count) = GET_PARAMS.get_type_info(pname);
-        // TODO: type conversion
-        assert!(type_ == ParamType::Float || type_ == ParamType::FloatSpecial);
-        gl21::GetFloatv(pname, params);
+        Self::get_param(pname, params, ParamType::value_to_float)
     }
     unsafe fn GetIntegerv(&mut self, pname: GLenum, params: *mut GLint) {
-        let (type_, _count) = GET_PARAMS.get_type_info(pname);
-        // TODO: type conversion
-        assert!(type_ == ParamType::Int);
-        gl21::GetIntegerv(pname, params);
+        Self::get_param(pname, params, ParamType::value_to_int)
     }
     unsafe fn GetTexEnviv(&mut self, target: GLenum, pname: GLenum, params: *mut GLint) {
         let (type_, _count) = TEX_ENV_PARAMS.get_type_info(pname);
@@ -1341,12 +1390,9 @@ impl GLES for GLES1OnGL2 {
     ) {
         let data = unsafe { std::slice::from_raw_parts(data.cast::<u8>(), image_size as usize) };
         // IMG_texture_compression_pvrtc (only on Imagination/Apple GPUs)
-        // TODO: It would be more efficient to use hardware decoding where
-        // available (I just don't have a suitable device to try this on)
-        if try_decode_pvrtc(
-            self,
-            target,
-            level,
+        // OpenGL 2.1 drivers won't support this, so it must be decoded.
+        if let Some(pixels) = try_decode_pvrtc(
+            &mut self.decoded_texture_cache,
             internalformat,
             width,
             height,
@@ -1354,6 +1400,17 @@ impl GLES for GLES1OnGL2 {
             data,
         ) {
             log_dbg!("Decoded PVRTC");
+            self.TexImage2D(
+                target,
+                level,
+                gl21::RGBA as _,
+                width,
+                height,
+                border,
+                gl21::RGBA,
+                gl21::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const _,
+            )
         // OES_compressed_paletted_texture is only in OpenGL ES, so we'll need
         // to decompress those formats.
         } else if let Some(PalettedTextureFormat {
@@ -1382,42 +1439,62 @@ impl GLES for GLES1OnGL2 {
             };
             let palette_size = palette_entry_size * palette_entry_count;
 
-            let index_count = width as usize * height as usize;
-            let (index_word_size, index_word_count) = match index_is_nibble {
-                true => (1, (index_count + 1) / 2),
-                false => (4, (index_count + 3) / 4),
-            };
-            let indices_size = index_word_size * index_word_count;
+            // A level of 0 means the image contains only the base level. A
+            // negative level -n means it contains the base level plus n
+            // further mip levels, which all share the same palette.
+            assert!(level <= 0);
+            let level_count = 1 + level.unsigned_abs();
 
-            // TODO: support multiple miplevels in one image
-            assert!(level == 0);
-            assert_eq!(data.len(), palette_size + indices_size);
-            let (palette, indices) = data.split_at(palette_size);
+            let (palette, mut indices) = data.split_at(palette_size);
 
-            let mut decoded = Vec::<u8>::with_capacity(palette_entry_size * index_count);
-            for i in 0..index_count {
-                let index = if index_is_nibble {
-                    (indices[i / 2] >> ((1 - (i % 2)) * 4)) & 0xf
-                } else {
-                    indices[i]
-                } as usize;
-                let palette_entry = &palette[index * palette_entry_size..][..palette_entry_size];
-                decoded.extend_from_slice(palette_entry);
+            // The decoded rows aren't necessarily a multiple of 4 bytes long.
+            let mut old_unpack_alignment = 0;
+            gl21::GetIntegerv(gl21::UNPACK_ALIGNMENT, &mut old_unpack_alignment);
+            gl21::PixelStorei(gl21::UNPACK_ALIGNMENT, 1);
+
+            let mut width = width as usize;
+            let mut height = height as usize;
+            for level in 0..level_count {
+                let index_count = width * height;
+                let indices_size = match index_is_nibble {
+                    true => index_count.div_ceil(2),
+                    false => index_count,
+                };
+                let (level_indices, next_indices) = indices.split_at(indices_size);
+                indices = next_indices;
+
+                let mut decoded = Vec::<u8>::with_capacity(palette_entry_size * index_count);
+                for i in 0..index_count {
+                    let index = if index_is_nibble {
+                        (level_indices[i / 2] >> ((1 - (i % 2)) * 4)) & 0xf
+                    } else {
+                        level_indices[i]
+                    } as usize;
+                    let palette_entry =
+                        &palette[index * palette_entry_size..][..palette_entry_size];
+                    decoded.extend_from_slice(palette_entry);
+                }
+                assert!(decoded.len() == palette_entry_size * index_count);
+
+                gl21::TexImage2D(
+                    target,
+                    level as GLint,
+                    palette_entry_format as _,
+                    width as GLsizei,
+                    height as GLsizei,
+                    border,
+                    palette_entry_format,
+                    palette_entry_type,
+                    decoded.as_ptr() as *const _,
+                );
+
+                width = (width / 2).max(1);
+                height = (height / 2).max(1);
             }
-            assert!(decoded.len() == palette_entry_size * index_count);
+            assert!(indices.is_empty());
 
-            log_dbg!("Decoded paletted texture");
-            gl21::TexImage2D(
-                target,
-                level,
-                palette_entry_format as _,
-                width,
-                height,
-                border,
-                palette_entry_format,
-                palette_entry_type,
-                decoded.as_ptr() as *const _,
-            )
+            gl21::PixelStorei(gl21::UNPACK_ALIGNMENT, old_unpack_alignment);
+            log_dbg!("Decoded paletted texture ({} level(s))", level_count);
         } else {
             unimplemented!("CompressedTexImage2D internalformat: {:#x}", internalformat);
         }
@@ -1774,3 +1851,166 @@ impl GLES for GLES1OnGL2 {
         gl21::GenerateMipmapEXT(target)
     }
 }
+
+#[cfg(test)]
+mod get_params_tests {
+    use super::*;
+
+    /// Calls [ParamTable::getv] for each of the four getter types, with a fake
+    /// host implementation that returns `native` (as boolean, float or integer
+    /// depending on the parameter type).
+    fn get_all(pname: GLenum, native: f64) -> (GLboolean, GLfloat, GLint, GLfixed) {
+        let getbv = |params: *mut GLboolean| unsafe { params.write(native as GLboolean) };
+        let getfv = |params: *mut GLfloat| unsafe { params.write(native as GLfloat) };
+        let getiv = |params: *mut GLint| unsafe { params.write(native as GLint) };
+        let mut boolean = 0;
+        let mut float = 0.0;
+        let mut int = 0;
+        let mut fixed = 0;
+        unsafe {
+            GET_PARAMS.getv(
+                getbv,
+                getfv,
+                getiv,
+                pname,
+                &mut boolean,
+                ParamType::value_to_boolean,
+            );
+            GET_PARAMS.getv(
+                getbv,
+                getfv,
+                getiv,
+                pname,
+                &mut float,
+                ParamType::value_to_float,
+            );
+            GET_PARAMS.getv(
+                getbv,
+                getfv,
+                getiv,
+                pname,
+                &mut int,
+                ParamType::value_to_int,
+            );
+            GET_PARAMS.getv(
+                getbv,
+                getfv,
+                getiv,
+                pname,
+                &mut fixed,
+                ParamType::value_to_fixed,
+            );
+        }
+        (boolean, float, int, fixed)
+    }
+
+    #[test]
+    fn get_type_conversion() {
+        // (pname, native value, boolean, float, integer, fixed-point)
+        let cases: &[(GLenum, f64, GLboolean, GLfloat, GLint, GLfixed)] = &[
+            // Boolean state
+            (gl21::BLEND, 0.0, gl21::FALSE, 0.0, 0, 0),
+            (gl21::BLEND, 1.0, gl21::TRUE, 1.0, 1, 0x10000),
+            // Integer state
+            (
+                gl21::MAX_TEXTURE_SIZE,
+                1024.0,
+                gl21::TRUE,
+                1024.0,
+                1024,
+                1024 << 16,
+            ),
+            (gl21::STENCIL_CLEAR_VALUE, 0.0, gl21::FALSE, 0.0, 0, 0),
+            (gl21::STENCIL_REF, -3.0, gl21::TRUE, -3.0, -3, -3 << 16),
+            // Integers too big for fixed-point saturate
+            (
+                gl21::STENCIL_VALUE_MASK,
+                65536.0,
+                gl21::TRUE,
+                65536.0,
+                65536,
+                GLfixed::MAX,
+            ),
+            // Floating-point state is rounded when converted to integer
+            (gl21::LINE_WIDTH, 1.0, gl21::TRUE, 1.0, 1, 0x10000),
+            (gl21::LINE_WIDTH, 2.5, gl21::TRUE, 2.5, 3, 0x28000),
+            (gl21::FOG_START, -1.25, gl21::TRUE, -1.25, -1, -0x14000),
+            (gl21::FOG_DENSITY, 0.0, gl21::FALSE, 0.0, 0, 0),
+            // Colors, normals, depth values etc are mapped linearly to the
+            // whole integer range, but not for fixed-point.
+            (gl21::ALPHA_TEST_REF, 0.0, gl21::FALSE, 0.0, 0, 0),
+            (
+                gl21::ALPHA_TEST_REF,
+                1.0,
+                gl21::TRUE,
+                1.0,
+                GLint::MAX,
+                0x10000,
+            ),
+            (
+                gl21::ALPHA_TEST_REF,
+                0.5,
+                gl21::TRUE,
+                0.5,
+                0x40000000,
+                0x8000,
+            ),
+            (
+                gl21::CURRENT_NORMAL,
+                -1.0,
+                gl21::TRUE,
+                -1.0,
+                GLint::MIN,
+                -0x10000,
+            ),
+            (
+                gl21::DEPTH_CLEAR_VALUE,
+                1.0,
+                gl21::TRUE,
+                1.0,
+                GLint::MAX,
+                0x10000,
+            ),
+            (gl21::FOG_COLOR, 0.25, gl21::TRUE, 0.25, 0x20000000, 0x4000),
+            (
+                gl21::LIGHT_MODEL_AMBIENT,
+                0.2,
+                gl21::TRUE,
+                0.2,
+                429496736,
+                0x3333,
+            ),
+        ];
+        for &(pname, native, boolean, float, int, fixed) in cases {
+            assert_eq!(
+                get_all(pname, native),
+                (boolean, float, int, fixed),
+                "Wrong conversion of {} for {:#x}",
+                native,
+                pname
+            );
+        }
+    }
+
+    #[test]
+    fn get_compressed_texture_formats() {
+        let mut count: GLint = 0;
+        let mut formats = [0 as GLint; 16];
+        unsafe {
+            GLES1OnGL2::get_param(
+                gl21::NUM_COMPRESSED_TEXTURE_FORMATS,
+                &mut count,
+                ParamType::value_to_int,
+            );
+            GLES1OnGL2::get_param(
+                gl21::COMPRESSED_TEXTURE_FORMATS,
+                formats.as_mut_ptr(),
+                ParamType::value_to_int,
+            );
+        }
+        assert_eq!(count as usize, COMPRESSED_TEXTURE_FORMATS.len());
+        for (&format, &expected) in formats.iter().zip(COMPRESSED_TEXTURE_FORMATS) {
+            assert_eq!(format as GLenum, expected);
+        }
+    }
+}
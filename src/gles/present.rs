@@ -38,6 +38,46 @@ impl FpsCounter {
     }
 }
 
+/// Reads the pixels of the currently bound framebuffer into a [Vec] in RGBA8
+/// format, replacing any existing content. Rows are in bottom-to-top order.
+///
+/// This uses `glReadPixels()`, which blocks until rendering finishes, so it
+/// should be avoided where possible.
+///
+/// The provided context must be current.
+pub unsafe fn read_pixels(
+    gles: &mut dyn GLES,
+    width: gles11::types::GLsizei,
+    height: gles11::types::GLsizei,
+    pixel_buffer: &mut Vec<u8>,
+) {
+    let size = usize::try_from(width)
+        .unwrap()
+        .checked_mul(height.try_into().unwrap())
+        .unwrap()
+        .checked_mul(4)
+        .unwrap();
+    pixel_buffer.clear();
+    pixel_buffer.reserve_exact(size);
+    let before = Instant::now();
+    gles.ReadPixels(
+        0,
+        0,
+        width,
+        height,
+        gles11::RGBA,
+        gles11::UNSIGNED_BYTE,
+        pixel_buffer.as_mut_ptr() as *mut _,
+    );
+    log_dbg!(
+        "glReadPixels(0, 0, {}, {}, …) took {:?}",
+        width,
+        height,
+        Instant::now().saturating_duration_since(before)
+    );
+    pixel_buffer.set_len(size);
+}
+
 /// Present the the latest frame (e.g. the app's splash screen or rendering
 /// output), provided as a texture bound to `GL_TEXTURE_2D`, by drawing it on
 /// the window. It may be rotated, scaled and/or letterboxed as necessary. The
@@ -6,8 +6,12 @@
 //! Shared utilities.
 
 use super::gles11_raw as gles11; // constants only
-use super::gles11_raw::types::{GLenum, GLfixed, GLfloat, GLint, GLsizei};
-use super::GLES;
+use super::gles11_raw::types::{GLboolean, GLenum, GLfixed, GLfloat, GLint, GLsizei, GLubyte};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
 
 /// Convert a fixed-point scalar to a floating-point scalar.
 ///
@@ -26,8 +30,14 @@ pub unsafe fn matrix_fixed_to_float(m: *const GLfixed) -> [GLfloat; 16] {
     matrix
 }
 
+/// Convert a floating-point scalar to a fixed-point scalar, rounding to the
+/// nearest representable value and saturating on overflow.
+pub fn float_to_fixed(float: GLfloat) -> GLfixed {
+    (f64::from(float) * ((1 << 16) as f64)).round() as GLfixed
+}
+
 /// Type of a parameter, used in [ParamTable].
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum ParamType {
     /// `GLboolean`
     Boolean,
@@ -35,15 +45,61 @@ pub enum ParamType {
     Float,
     /// `GLint`
     Int,
-    /// Placeholder type for things like colors which are floating-point
-    /// but don't have the usual conversion behavior to/from integers etc.
-    /// [ParamTable] will accept it for floating-point inputs only.
-    /// TODO: Remove this and add proper types for colors etc.
-    FloatSpecial,
+    /// `GLfloat`, but for values like colors, normals, depth values and the
+    /// alpha test reference value, which have a special conversion to integer:
+    /// the range `[-1.0, 1.0]` is mapped linearly onto the full range of
+    /// [GLint], rather than being rounded (OpenGL ES 1.1 spec section 6.1.2).
+    /// Conversion to fixed-point is the same as for [ParamType::Float].
+    FloatNormalized,
     /// Hack to achieve `#[non_exhaustive]`-like behavior within this crate,
     /// since more types might be added in future
     _NonExhaustive,
 }
+impl ParamType {
+    // The conversion functions below take a value of this type that has been
+    // widened to f64, which can exactly represent all possible values of
+    // GLboolean, GLint and GLfloat.
+
+    /// Convert a value of this type for `glGetBooleanv`.
+    pub fn value_to_boolean(self, value: f64) -> GLboolean {
+        if value != 0.0 {
+            gles11::TRUE
+        } else {
+            gles11::FALSE
+        }
+    }
+
+    /// Convert a value of this type for `glGetFloatv`.
+    pub fn value_to_float(self, value: f64) -> GLfloat {
+        value as GLfloat
+    }
+
+    /// Convert a value of this type for `glGetIntegerv`.
+    pub fn value_to_int(self, value: f64) -> GLint {
+        match self {
+            // Rust's float-to-int casts saturate, which is what we want.
+            ParamType::Float => value.round() as GLint,
+            ParamType::FloatNormalized => {
+                // 1.0 maps to the most positive integer and -1.0 maps to the
+                // most negative integer.
+                let value = value.clamp(-1.0, 1.0);
+                ((f64::from(u32::MAX) * value - 1.0) / 2.0).ceil() as GLint
+            }
+            _ => value as GLint,
+        }
+    }
+
+    /// Convert a value of this type for `glGetFixedv`.
+    pub fn value_to_fixed(self, value: f64) -> GLfixed {
+        match self {
+            ParamType::Float | ParamType::FloatNormalized => float_to_fixed(value as GLfloat),
+            // Integers (and booleans, which are 0 or 1) are not scaled when
+            // converting to integer, but they must be when converting to
+            // fixed-point.
+            _ => (value as GLint).saturating_mul(1 << 16),
+        }
+    }
+}
 
 /// Table of parameter names, component types and component counts.
 ///
@@ -98,7 +154,7 @@ impl ParamTable {
         // On the other hand, fixed-to-float/float-to-fixed conversion is always
         // the same even for the weird float-ish values.
         match type_ {
-            ParamType::Float | ParamType::FloatSpecial => setf(fixed_to_float(param)),
+            ParamType::Float | ParamType::FloatNormalized => setf(fixed_to_float(param)),
             _ => seti(param),
         }
     }
@@ -123,7 +179,7 @@ impl ParamTable {
         match type_ {
             // Fixed-to-float/float-to-fixed conversion is always the same even
             // for the weird float-ish values.
-            ParamType::Float | ParamType::FloatSpecial => {
+            ParamType::Float | ParamType::FloatNormalized => {
                 let mut params_float = [0.0; 16]; // probably the max?
                 let params_float = &mut params_float[..usize::from(count)];
                 for (i, param_float) in params_float.iter_mut().enumerate() {
@@ -134,51 +190,235 @@ impl ParamTable {
             _ => setiv(params),
         }
     }
+
+    /// Implements a vector getter for any of the `glGet` types (`bv`, `fv`,
+    /// `iv` or `xv`), by calling the provided boolean (`bv`), floating-point
+    /// (`fv`) or integer (`iv`) vector getter that matches the parameter's
+    /// type, and then converting each component with `convert`, which should
+    /// be one of [ParamType::value_to_boolean], [ParamType::value_to_float],
+    /// [ParamType::value_to_int] or [ParamType::value_to_fixed].
+    ///
+    /// This will panic if the name is not recognized.
+    pub unsafe fn getv<FBV, FFV, FIV, T>(
+        &self,
+        getbv: FBV,
+        getfv: FFV,
+        getiv: FIV,
+        pname: GLenum,
+        params: *mut T,
+        convert: fn(ParamType, f64) -> T,
+    ) where
+        FBV: FnOnce(*mut GLboolean),
+        FFV: FnOnce(*mut GLfloat),
+        FIV: FnOnce(*mut GLint),
+    {
+        let (type_, count) = self.get_type_info(pname);
+        let count = usize::from(count);
+        let mut values = [0f64; 16]; // probably the max?
+        let values = &mut values[..count];
+        match type_ {
+            ParamType::Boolean => {
+                let mut params_boolean = [gles11::FALSE; 16];
+                getbv(params_boolean.as_mut_ptr());
+                for (value, &param) in values.iter_mut().zip(params_boolean.iter()) {
+                    *value = f64::from(param);
+                }
+            }
+            ParamType::Float | ParamType::FloatNormalized => {
+                let mut params_float = [0.0; 16];
+                getfv(params_float.as_mut_ptr());
+                for (value, &param) in values.iter_mut().zip(params_float.iter()) {
+                    *value = f64::from(param);
+                }
+            }
+            ParamType::Int => {
+                let mut params_int = [0; 16];
+                getiv(params_int.as_mut_ptr());
+                for (value, &param) in values.iter_mut().zip(params_int.iter()) {
+                    *value = f64::from(param);
+                }
+            }
+            ParamType::_NonExhaustive => unreachable!(),
+        }
+        for (i, &value) in values.iter().enumerate() {
+            params.add(i).write(convert(type_, value));
+        }
+    }
+}
+
+/// Check whether an OpenGL extension string (`glGetString(GL_EXTENSIONS)`)
+/// contains a particular extension.
+pub unsafe fn has_extension(extensions: *const GLubyte, name: &str) -> bool {
+    if extensions.is_null() {
+        return false;
+    }
+    let extensions = CStr::from_ptr(extensions as *const _);
+    extensions
+        .to_string_lossy()
+        .split_ascii_whitespace()
+        .any(|extension| extension == name)
+}
+
+/// If `internalformat` is one of the `IMG_texture_compression_pvrtc` formats,
+/// returns [Some] with whether it is a 2 bits-per-pixel format.
+pub fn pvrtc_format_is_2bit(internalformat: GLenum) -> Option<bool> {
+    match internalformat {
+        gles11::COMPRESSED_RGB_PVRTC_4BPPV1_IMG | gles11::COMPRESSED_RGBA_PVRTC_4BPPV1_IMG => {
+            Some(false)
+        }
+        gles11::COMPRESSED_RGB_PVRTC_2BPPV1_IMG | gles11::COMPRESSED_RGBA_PVRTC_2BPPV1_IMG => {
+            Some(true)
+        }
+        _ => None,
+    }
 }
 
 /// Helper for implementing `glCompressedTexImage2D`: if `internalformat` is
-/// one of the `IMG_texture_compression_pvrtc` formats, decode it and call
-/// `glTexImage2D`. Returns `true` if this is done.
+/// one of the `IMG_texture_compression_pvrtc` formats, decode it (or fetch a
+/// previous decoding from `cache`) and return [Some] with RGBA8 pixels that
+/// can be passed to `glTexImage2D`.
 ///
 /// Note that this panics rather than create GL errors for invalid use (TODO?)
-#[allow(clippy::too_many_arguments)]
 pub fn try_decode_pvrtc(
-    gles: &mut dyn GLES,
-    target: GLenum,
-    level: GLint,
+    cache: &mut DecodedTextureCache,
     internalformat: GLenum,
     width: GLsizei,
     height: GLsizei,
     border: GLint,
     pvrtc_data: &[u8],
-) -> bool {
-    let is_2bit = match internalformat {
-        gles11::COMPRESSED_RGB_PVRTC_4BPPV1_IMG | gles11::COMPRESSED_RGBA_PVRTC_4BPPV1_IMG => false,
-        gles11::COMPRESSED_RGB_PVRTC_2BPPV1_IMG | gles11::COMPRESSED_RGBA_PVRTC_2BPPV1_IMG => true,
-        _ => return false,
-    };
+) -> Option<Rc<[u32]>> {
+    let is_2bit = pvrtc_format_is_2bit(internalformat)?;
 
     assert!(border == 0);
-    let pixels = crate::image::decode_pvrtc(
-        pvrtc_data,
-        is_2bit,
-        width.try_into().unwrap(),
-        height.try_into().unwrap(),
-    );
-    unsafe {
-        gles.TexImage2D(
-            target,
-            level,
-            gles11::RGBA as _,
+    let width: u32 = width.try_into().unwrap();
+    let height: u32 = height.try_into().unwrap();
+    Some(
+        cache.get_or_decode(internalformat, width, height, pvrtc_data, || {
+            crate::image::decode_pvrtc(pvrtc_data, is_2bit, width, height)
+        }),
+    )
+}
+
+/// Cache of textures that had to be decoded in software (e.g. PVRTC), so that
+/// an app which uploads the same compressed data again (which is common when
+/// a game reloads a level, or recreates its textures after a memory warning)
+/// doesn't pay the decoding cost twice.
+///
+/// The cache is keyed by the compressed data itself (plus its format and
+/// dimensions), so it doesn't care which texture object the data is uploaded
+/// to. Entries are evicted least-recently-used first once the total size of
+/// the decoded data exceeds a limit.
+pub struct DecodedTextureCache {
+    entries: HashMap<DecodedTextureKey, DecodedTexture>,
+    /// Used to determine the least-recently-used entry.
+    use_counter: u64,
+    /// Total size in bytes of the decoded data in `entries`.
+    decoded_size: usize,
+    /// Limit for `decoded_size`.
+    decoded_size_limit: usize,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct DecodedTextureKey {
+    internalformat: GLenum,
+    width: u32,
+    height: u32,
+    data_hash: u64,
+}
+
+struct DecodedTexture {
+    /// The original data is kept so hash collisions can be detected.
+    compressed: Box<[u8]>,
+    decoded: Rc<[u32]>,
+    last_used: u64,
+}
+
+impl DecodedTextureCache {
+    /// Default limit for the size of decoded data kept by the cache. This is
+    /// enough for several dozen 512×512 textures, which is generous given the
+    /// amount of memory an iPhone OS device has.
+    pub const DEFAULT_SIZE_LIMIT: usize = 64 * 1024 * 1024;
+
+    pub fn new(decoded_size_limit: usize) -> Self {
+        DecodedTextureCache {
+            entries: HashMap::new(),
+            use_counter: 0,
+            decoded_size: 0,
+            decoded_size_limit,
+        }
+    }
+
+    /// Look up the decoded (32 bits per pixel) form of `data` in the cache,
+    /// calling `decode` and adding the result to the cache if it's not there.
+    pub fn get_or_decode<F>(
+        &mut self,
+        internalformat: GLenum,
+        width: u32,
+        height: u32,
+        data: &[u8],
+        decode: F,
+    ) -> Rc<[u32]>
+    where
+        F: FnOnce() -> Vec<u32>,
+    {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        let key = DecodedTextureKey {
+            internalformat,
             width,
             height,
-            border,
-            gles11::RGBA,
-            gles11::UNSIGNED_BYTE,
-            pixels.as_ptr() as *const _,
-        )
-    };
-    true
+            data_hash: hasher.finish(),
+        };
+
+        self.use_counter += 1;
+
+        if let Some(entry) = self.entries.get_mut(&key) {
+            if *entry.compressed == *data {
+                log_dbg!(
+                    "Reusing decoded {}x{} texture (format {:#x})",
+                    width,
+                    height,
+                    internalformat
+                );
+                entry.last_used = self.use_counter;
+                return entry.decoded.clone();
+            }
+            // Hash collision. The new data will replace the old.
+            let old = self.entries.remove(&key).unwrap();
+            self.decoded_size -= old.decoded.len() * 4;
+        }
+
+        let decoded: Rc<[u32]> = decode().into();
+        let size = decoded.len() * 4;
+        // Something this big isn't worth caching.
+        if size > self.decoded_size_limit {
+            return decoded;
+        }
+        while self.decoded_size + size > self.decoded_size_limit {
+            self.evict_least_recently_used();
+        }
+        self.decoded_size += size;
+        self.entries.insert(
+            key,
+            DecodedTexture {
+                compressed: data.into(),
+                decoded: decoded.clone(),
+                last_used: self.use_counter,
+            },
+        );
+        decoded
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let key = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone())
+            .unwrap();
+        let entry = self.entries.remove(&key).unwrap();
+        self.decoded_size -= entry.decoded.len() * 4;
+    }
 }
 
 pub struct PalettedTextureFormat {
@@ -250,3 +490,29 @@ impl PalettedTextureFormat {
         }
     }
 }
+
+#[cfg(test)]
+mod decoded_texture_cache_tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        // Room for two 2x2 textures.
+        let mut cache = DecodedTextureCache::new(2 * 2 * 2 * 4);
+        let decode_count = std::cell::Cell::new(0);
+        let mut get = |data: &[u8]| {
+            cache.get_or_decode(gles11::COMPRESSED_RGBA_PVRTC_4BPPV1_IMG, 2, 2, data, || {
+                decode_count.set(decode_count.get() + 1);
+                vec![u32::from(data[0]); 4]
+            })[0]
+        };
+
+        assert_eq!(get(&[1, 1]), 1);
+        assert_eq!(get(&[2, 2]), 2);
+        assert_eq!(get(&[1, 1]), 1); // hit
+        assert_eq!(get(&[3, 3]), 3); // evicts [2, 2]
+        assert_eq!(get(&[1, 1]), 1); // hit
+        assert_eq!(get(&[2, 2]), 2); // evicts [3, 3]
+        assert_eq!(decode_count.get(), 4);
+    }
+}
@@ -0,0 +1,925 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Minimal HTTP/1.1 client, used to implement `NSURLConnection` and friends.
+//!
+//! Each request is handled on its own host thread, and the results are sent
+//! back over a channel, so the guest's run loop can poll for them without
+//! blocking. HTTPS is not supported, but hosts can be redirected to a local
+//! stand-in server that speaks plain HTTP (see `--redirect-host=`), which is
+//! useful for apps whose servers no longer exist. Alternatively, responses for
+//! a host can be served from files (see `--offline-host=`), in which case no
+//! thread is needed and the responses arrive deterministically.
+
+use crate::options::Options;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+/// Redirects are followed until there have been this many.
+const MAX_REDIRECTS: u32 = 10;
+/// Limit on the size of the status line and headers, to avoid running out of
+/// memory if the server is sending something else.
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+/// Body data is sent to the guest in pieces of at most this size.
+const DATA_CHUNK_SIZE: usize = 16 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub timeout: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct Response {
+    /// The URL of the response, which is different from the request's if a
+    /// redirect was followed.
+    pub url: String,
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+}
+impl Response {
+    /// Get the value of a header. Names are case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+    /// Value of the `Content-Length` header, if any.
+    pub fn content_length(&self) -> Option<u64> {
+        self.header("Content-Length")?.trim().parse().ok()
+    }
+    /// The MIME type part of the `Content-Type` header, if any.
+    pub fn mime_type(&self) -> Option<String> {
+        let content_type = self.header("Content-Type")?;
+        let mime_type = content_type.split(';').next().unwrap().trim();
+        (!mime_type.is_empty()).then(|| mime_type.to_ascii_lowercase())
+    }
+    /// The `charset` parameter of the `Content-Type` header, if any.
+    pub fn text_encoding_name(&self) -> Option<String> {
+        let content_type = self.header("Content-Type")?;
+        content_type.split(';').skip(1).find_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case("charset")
+                .then(|| value.trim().trim_matches('"').to_ascii_lowercase())
+        })
+    }
+}
+
+/// Errors, which correspond to `NSURLError*` codes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    Cancelled,
+    BadURL,
+    TimedOut,
+    UnsupportedURL,
+    CannotFindHost,
+    CannotConnectToHost,
+    NetworkConnectionLost,
+    HTTPTooManyRedirects,
+    BadServerResponse,
+}
+impl Error {
+    /// The `NSURLError*` code for this error.
+    pub fn code(self) -> i32 {
+        match self {
+            Error::Cancelled => -999,
+            Error::BadURL => -1000,
+            Error::TimedOut => -1001,
+            Error::UnsupportedURL => -1002,
+            Error::CannotFindHost => -1003,
+            Error::CannotConnectToHost => -1004,
+            Error::NetworkConnectionLost => -1005,
+            Error::HTTPTooManyRedirects => -1007,
+            Error::BadServerResponse => -1011,
+        }
+    }
+    /// The message Apple uses for this error.
+    pub fn description(self) -> &'static str {
+        match self {
+            Error::Cancelled => "cancelled",
+            Error::BadURL => "bad URL",
+            Error::TimedOut => "The request timed out.",
+            Error::UnsupportedURL => "unsupported URL",
+            Error::CannotFindHost => "A server with the specified hostname could not be found.",
+            Error::CannotConnectToHost => "Could not connect to the server.",
+            Error::NetworkConnectionLost => "The network connection was lost.",
+            Error::HTTPTooManyRedirects => "too many HTTP redirects",
+            Error::BadServerResponse => "The server returned an invalid response.",
+        }
+    }
+    fn from_io_error(e: &io::Error) -> Error {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::TimedOut,
+            _ => Error::NetworkConnectionLost,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Event {
+    /// Always the first event, unless the request failed.
+    Response(Response),
+    /// Part of the response body.
+    Data(Vec<u8>),
+    Finished,
+    Failed(Error),
+}
+
+/// The parts of an `http:` or `https:` URL that matter to the client.
+#[derive(Debug, PartialEq, Eq)]
+struct Url {
+    https: bool,
+    host: String,
+    port: u16,
+    /// Path and query, always starting with `/`.
+    path: String,
+}
+
+fn parse_url(url: &str) -> Result<Url, Error> {
+    let (scheme, rest) = url.split_once("://").ok_or(Error::BadURL)?;
+    let https = if scheme.eq_ignore_ascii_case("http") {
+        false
+    } else if scheme.eq_ignore_ascii_case("https") {
+        true
+    } else {
+        return Err(Error::UnsupportedURL);
+    };
+    // The fragment is never sent to the server.
+    let rest = rest.split('#').next().unwrap();
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(idx) => rest.split_at(idx),
+        None => (rest, ""),
+    };
+    let path = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{}", path)
+    };
+    // User info is not supported, but it shouldn't be confused for the host.
+    let authority = authority.rsplit('@').next().unwrap();
+    let default_port = if https { 443 } else { 80 };
+    let (host, port) = split_host_port(authority, default_port).ok_or(Error::BadURL)?;
+    if host.is_empty() {
+        return Err(Error::BadURL);
+    }
+    Ok(Url {
+        https,
+        host: host.to_ascii_lowercase(),
+        port,
+        path,
+    })
+}
+
+/// Split `host:port` (where the port is optional), handling IPv6 literals
+/// like `[::1]:80`.
+fn split_host_port(authority: &str, default_port: u16) -> Option<(&str, u16)> {
+    let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']')?;
+        match rest {
+            "" => (host, None),
+            _ => (host, Some(rest.strip_prefix(':')?)),
+        }
+    } else {
+        match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        }
+    };
+    let port = match port {
+        None | Some("") => default_port,
+        Some(port) => port.parse().ok()?,
+    };
+    Some((host, port))
+}
+
+/// Resolve the target of a redirect relative to the URL that was requested.
+fn resolve_location(base: &str, location: &str) -> String {
+    if location.contains("://") {
+        return location.to_string();
+    }
+    let (scheme, rest) = base.split_once("://").unwrap();
+    if let Some(rest) = location.strip_prefix("//") {
+        return format!("{}://{}", scheme, rest);
+    }
+    let rest = rest.split(['#', '?']).next().unwrap();
+    let (authority, path) = match rest.find('/') {
+        Some(idx) => rest.split_at(idx),
+        None => (rest, "/"),
+    };
+    if location.starts_with('/') {
+        format!("{}://{}{}", scheme, authority, location)
+    } else if location.starts_with('?') {
+        format!("{}://{}{}{}", scheme, authority, path, location)
+    } else {
+        let dir = &path[..=path.rfind('/').unwrap()];
+        format!("{}://{}{}{}", scheme, authority, dir, location)
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Hosts that are handled specially, from the options.
+struct Hosts {
+    /// Host names mapped to the `host:port` of a server that should be
+    /// contacted instead. The `Host` header still contains the original name.
+    redirect: HashMap<String, String>,
+    /// Host names mapped to the directory or file that responses are served
+    /// from.
+    offline: HashMap<String, PathBuf>,
+}
+
+/// Start a request. The request can be cancelled by dropping the receiver.
+///
+/// Requests for hosts served from files are handled immediately, so all the
+/// events are available once this returns. Other requests are handled on a
+/// new thread.
+pub fn start(request: Request, options: &Options) -> Receiver<Event> {
+    let hosts = Hosts {
+        redirect: options.redirect_hosts.clone(),
+        offline: options.offline_hosts.clone(),
+    };
+    let offline = parse_url(&request.url).is_ok_and(|url| hosts.offline.contains_key(&url.host));
+    let (sender, receiver) = channel();
+    let handle = move || {
+        if let Err(error) = run(request, &hosts, &sender) {
+            // This fails if the request was cancelled, which is fine.
+            let _ = sender.send(Event::Failed(error));
+        }
+    };
+    if offline {
+        handle();
+    } else {
+        std::thread::spawn(handle);
+    }
+    receiver
+}
+
+/// Send an event, or return [Error::Cancelled] if nobody is listening anymore.
+fn send(sender: &Sender<Event>, event: Event) -> Result<(), Error> {
+    sender.send(event).map_err(|_| Error::Cancelled)
+}
+
+fn run(mut request: Request, hosts: &Hosts, sender: &Sender<Event>) -> Result<(), Error> {
+    let mut redirects = 0;
+    loop {
+        let url = parse_url(&request.url)?;
+        let (mut reader, response) = match hosts.offline.get(&url.host) {
+            Some(source) => serve_offline(&request, &url, source)?,
+            None => send_request(&request, &url, &hosts.redirect)?,
+        };
+
+        let location = response
+            .header("Location")
+            .filter(|_| matches!(response.status_code, 301 | 302 | 303 | 307 | 308));
+        if let Some(location) = location {
+            redirects += 1;
+            if redirects > MAX_REDIRECTS {
+                return Err(Error::HTTPTooManyRedirects);
+            }
+            let location = resolve_location(&request.url, location);
+            log_dbg!("HTTP: {} redirected to {}", request.url, location);
+            // Like browsers, only 307 and 308 keep the method and body of
+            // a POST request.
+            let keep_method = matches!(response.status_code, 307 | 308)
+                || request.method == "GET"
+                || request.method == "HEAD";
+            if !keep_method {
+                request.method = "GET".to_string();
+                request.body.clear();
+            }
+            request.url = location;
+            continue;
+        }
+
+        let has_body =
+            request.method != "HEAD" && !matches!(response.status_code, 100..=199 | 204 | 304);
+        let chunked = response
+            .header("Transfer-Encoding")
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("chunked"));
+        let content_length = response.content_length();
+
+        send(sender, Event::Response(response))?;
+        if has_body {
+            if chunked {
+                read_chunked_body(&mut reader, sender)?;
+            } else {
+                read_body(&mut reader, content_length, sender)?;
+            }
+        }
+        return send(sender, Event::Finished);
+    }
+}
+
+/// Connect to the server, send the request and read the response headers.
+fn send_request(
+    request: &Request,
+    url: &Url,
+    redirect_hosts: &HashMap<String, String>,
+) -> Result<(Box<dyn BufRead>, Response), Error> {
+    let stream = match redirect_hosts.get(&url.host) {
+        Some(target) => {
+            // The stand-in only needs to speak plain HTTP.
+            let (host, port) = split_host_port(target, 80).ok_or(Error::BadURL)?;
+            log_dbg!("HTTP: redirecting {} to {}:{}", url.host, host, port);
+            connect(host, port, request.timeout)?
+        }
+        None if url.https => {
+            log!(
+                "TODO: HTTPS is not supported, can't fetch {} (consider --redirect-host=)",
+                request.url
+            );
+            return Err(Error::UnsupportedURL);
+        }
+        None => connect(&url.host, url.port, request.timeout)?,
+    };
+
+    let default_port = if url.https { 443 } else { 80 };
+    let host = if url.port == default_port {
+        url.host.clone()
+    } else {
+        format!("{}:{}", url.host, url.port)
+    };
+    let mut head = format!("{} {} HTTP/1.1\r\n", request.method, url.path);
+    if find_header(&request.headers, "Host").is_none() {
+        head.push_str(&format!("Host: {}\r\n", host));
+    }
+    for (name, value) in &request.headers {
+        // These are controlled by the client.
+        if name.eq_ignore_ascii_case("Content-Length")
+            || name.eq_ignore_ascii_case("Connection")
+            || name.eq_ignore_ascii_case("Transfer-Encoding")
+        {
+            continue;
+        }
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !request.body.is_empty() || request.method == "POST" || request.method == "PUT" {
+        head.push_str(&format!("Content-Length: {}\r\n", request.body.len()));
+    }
+    // Keeping connections alive isn't worth the complexity.
+    head.push_str("Connection: close\r\n\r\n");
+
+    log_dbg!("HTTP: {} {}", request.method, request.url);
+    let mut writer = &stream;
+    writer
+        .write_all(head.as_bytes())
+        .and_then(|_| writer.write_all(&request.body))
+        .and_then(|_| writer.flush())
+        .map_err(|e| Error::from_io_error(&e))?;
+
+    let mut reader = BufReader::new(stream);
+    loop {
+        let (status_code, headers) = read_response_head(&mut reader)?;
+        // Informational responses like "100 Continue" are followed by the
+        // real response.
+        if (100..=199).contains(&status_code) && status_code != 101 {
+            continue;
+        }
+        log_dbg!("HTTP: {} => {}", request.url, status_code);
+        let response = Response {
+            url: request.url.clone(),
+            status_code,
+            headers,
+        };
+        return Ok((Box::new(reader), response));
+    }
+}
+
+/// Get a response for a host with `--offline-host=` from files. `source` is
+/// either a file that is the response to every request, or a directory that
+/// is searched for a file matching the URL's path (see [offline_candidates]).
+///
+/// Files ending in `.http` are a complete HTTP response, including the status
+/// line and headers. Other files are sent with status 200 and a `Content-Type`
+/// guessed from their extension. If no file is found, the status is 404.
+fn serve_offline(
+    request: &Request,
+    url: &Url,
+    source: &Path,
+) -> Result<(Box<dyn BufRead>, Response), Error> {
+    let candidates = if source.is_dir() {
+        offline_candidates(source, &url.path)
+    } else if source.is_file() {
+        vec![source.to_path_buf()]
+    } else {
+        log!(
+            "Warning: {} doesn't exist, can't serve {}",
+            source.display(),
+            request.url
+        );
+        return Err(Error::CannotConnectToHost);
+    };
+
+    let Some((path, file)) = candidates
+        .iter()
+        .find_map(|path| Some((path, File::open(path).ok()?)))
+    else {
+        log!(
+            "Warning: no offline response for {} {}, tried {:?}",
+            request.method,
+            request.url,
+            candidates
+        );
+        let response = Response {
+            url: request.url.clone(),
+            status_code: 404,
+            headers: vec![("Content-Length".to_string(), "0".to_string())],
+        };
+        return Ok((Box::new(Cursor::new(Vec::new())), response));
+    };
+    log_dbg!(
+        "HTTP: serving {} {} from {}",
+        request.method,
+        request.url,
+        path.display()
+    );
+
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let mut reader = BufReader::new(file);
+    if extension == "http" {
+        let (status_code, headers) = read_response_head(&mut reader)?;
+        let response = Response {
+            url: request.url.clone(),
+            status_code,
+            headers,
+        };
+        return Ok((Box::new(reader), response));
+    }
+    let length = reader
+        .get_ref()
+        .metadata()
+        .map_err(|e| Error::from_io_error(&e))?
+        .len();
+    let response = Response {
+        url: request.url.clone(),
+        status_code: 200,
+        headers: vec![
+            (
+                "Content-Type".to_string(),
+                mime_type_for_extension(&extension).to_string(),
+            ),
+            ("Content-Length".to_string(), length.to_string()),
+        ],
+    };
+    Ok((Box::new(reader), response))
+}
+
+/// Get the paths of files that could contain the response for a URL path
+/// (which may include a query), in order of preference.
+///
+/// The path is mapped to a path within `dir`, with `index.html` added if it
+/// ends in `/`. If there is a query, a file with `%3F` (an escaped `?`) and
+/// the query appended to its name is preferred. For each of these, a file
+/// with `.http` appended is preferred.
+fn offline_candidates(dir: &Path, path: &str) -> Vec<PathBuf> {
+    let (path, query) = match path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path, None),
+    };
+    let mut file = dir.to_path_buf();
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        // Requests must not be able to escape the directory.
+        match percent_decode(segment) {
+            Some(segment)
+                if segment != "." && segment != ".." && !segment.contains(['/', '\\']) =>
+            {
+                file.push(segment)
+            }
+            _ => return Vec::new(),
+        }
+    }
+    if path.ends_with('/') {
+        file.push("index.html");
+    }
+
+    let mut names: Vec<OsString> = Vec::new();
+    if let Some(query) = query.filter(|query| !query.is_empty()) {
+        let mut name = file.clone().into_os_string();
+        name.push("%3F");
+        // Characters that aren't allowed in file names on some systems.
+        for c in query.chars() {
+            if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') {
+                name.push(format!("%{:02X}", c as u32));
+            } else {
+                name.push(c.encode_utf8(&mut [0; 4]));
+            }
+        }
+        names.push(name);
+    }
+    names.push(file.into_os_string());
+
+    let mut candidates = Vec::new();
+    for name in names {
+        let mut http_name = name.clone();
+        http_name.push(".http");
+        candidates.push(PathBuf::from(http_name));
+        candidates.push(PathBuf::from(name));
+    }
+    candidates
+}
+
+/// Decode `%XX` escapes in a URL path segment.
+fn percent_decode(string: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(string.len());
+    let mut iter = string.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn mime_type_for_extension(extension: &str) -> &'static str {
+    match extension {
+        "json" => "application/json",
+        "xml" => "text/xml",
+        "plist" => "application/x-plist",
+        "html" | "htm" => "text/html",
+        "txt" => "text/plain",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        _ => "application/octet-stream",
+    }
+}
+
+fn connect(host: &str, port: u16, timeout: Duration) -> Result<TcpStream, Error> {
+    let addrs = (host, port).to_socket_addrs().map_err(|e| {
+        log!("Warning: couldn't resolve {:?}: {}", host, e);
+        Error::CannotFindHost
+    })?;
+    let mut error = Error::CannotFindHost;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream
+                    .set_read_timeout(Some(timeout))
+                    .and_then(|_| stream.set_write_timeout(Some(timeout)))
+                    .map_err(|e| Error::from_io_error(&e))?;
+                return Ok(stream);
+            }
+            Err(e) => {
+                log_dbg!("HTTP: couldn't connect to {}: {}", addr, e);
+                error = match e.kind() {
+                    io::ErrorKind::TimedOut => Error::TimedOut,
+                    _ => Error::CannotConnectToHost,
+                };
+            }
+        }
+    }
+    Err(error)
+}
+
+/// Read a line ending in CRLF (or LF), without the line ending.
+fn read_line(reader: &mut impl BufRead, limit: &mut usize) -> Result<String, Error> {
+    let mut line = Vec::new();
+    reader
+        .take(*limit as u64)
+        .read_until(b'\n', &mut line)
+        .map_err(|e| Error::from_io_error(&e))?;
+    if line.last() != Some(&b'\n') {
+        // Either the connection was closed or the limit was reached.
+        return Err(if line.len() == *limit {
+            Error::BadServerResponse
+        } else {
+            Error::NetworkConnectionLost
+        });
+    }
+    *limit -= line.len();
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| Error::BadServerResponse)
+}
+
+/// Read the status line and headers.
+fn read_response_head(reader: &mut impl BufRead) -> Result<(u16, Vec<(String, String)>), Error> {
+    let mut limit = MAX_HEADER_BYTES;
+    let status_line = read_line(reader, &mut limit)?;
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap();
+    if !version.starts_with("HTTP/1.") {
+        log!("Warning: unexpected HTTP status line {:?}", status_line);
+        return Err(Error::BadServerResponse);
+    }
+    let status_code = parts
+        .next()
+        .and_then(|code| code.parse().ok())
+        .filter(|code| (100..=999).contains(code))
+        .ok_or(Error::BadServerResponse)?;
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    loop {
+        let line = read_line(reader, &mut limit)?;
+        if line.is_empty() {
+            break;
+        }
+        // Obsolete line folding: the line continues the previous header.
+        if line.starts_with([' ', '\t']) {
+            let (_, value) = headers.last_mut().ok_or(Error::BadServerResponse)?;
+            value.push(' ');
+            value.push_str(line.trim());
+            continue;
+        }
+        let (name, value) = line.split_once(':').ok_or(Error::BadServerResponse)?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    Ok((status_code, headers))
+}
+
+/// Read a body that is either `length` bytes long, or continues until the
+/// connection is closed.
+fn read_body(
+    reader: &mut impl Read,
+    length: Option<u64>,
+    sender: &Sender<Event>,
+) -> Result<(), Error> {
+    let mut remaining = length;
+    let mut buffer = vec![0; DATA_CHUNK_SIZE];
+    while remaining != Some(0) {
+        let max = remaining.map_or(buffer.len(), |remaining| {
+            buffer.len().min(remaining.try_into().unwrap_or(usize::MAX))
+        });
+        let count = match reader.read(&mut buffer[..max]) {
+            Ok(0) if remaining.is_none() => break,
+            Ok(0) => return Err(Error::NetworkConnectionLost),
+            Ok(count) => count,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(Error::from_io_error(&e)),
+        };
+        if let Some(ref mut remaining) = remaining {
+            *remaining -= count as u64;
+        }
+        send(sender, Event::Data(buffer[..count].to_vec()))?;
+    }
+    Ok(())
+}
+
+/// Read a body with `Transfer-Encoding: chunked`.
+fn read_chunked_body(reader: &mut impl BufRead, sender: &Sender<Event>) -> Result<(), Error> {
+    loop {
+        let mut limit = MAX_HEADER_BYTES;
+        let line = read_line(reader, &mut limit)?;
+        // Chunk extensions are ignored.
+        let size = line.split(';').next().unwrap().trim();
+        let size = u64::from_str_radix(size, 16).map_err(|_| Error::BadServerResponse)?;
+        if size == 0 {
+            break;
+        }
+        read_body(reader, Some(size), sender)?;
+        if !read_line(reader, &mut limit)?.is_empty() {
+            return Err(Error::BadServerResponse);
+        }
+    }
+    // Trailers are ignored.
+    let mut limit = MAX_HEADER_BYTES;
+    while !read_line(reader, &mut limit)?.is_empty() {}
+    Ok(())
+}
+
+#[cfg(test)]
+mod http_tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn urls() {
+        assert_eq!(
+            parse_url("http://Example.com:8080/a/b?c=d#e"),
+            Ok(Url {
+                https: false,
+                host: "example.com".to_string(),
+                port: 8080,
+                path: "/a/b?c=d".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_url("https://user@[::1]?x"),
+            Ok(Url {
+                https: true,
+                host: "::1".to_string(),
+                port: 443,
+                path: "/?x".to_string(),
+            })
+        );
+        assert_eq!(parse_url("ftp://example.com/"), Err(Error::UnsupportedURL));
+        assert_eq!(parse_url("/just/a/path"), Err(Error::BadURL));
+        assert_eq!(parse_url("http://:80/"), Err(Error::BadURL));
+
+        let base = "http://example.com/a/b?c";
+        assert_eq!(resolve_location(base, "/x"), "http://example.com/x");
+        assert_eq!(resolve_location(base, "x"), "http://example.com/a/x");
+        assert_eq!(resolve_location(base, "?y"), "http://example.com/a/b?y");
+        assert_eq!(resolve_location(base, "//other/"), "http://other/");
+        assert_eq!(resolve_location(base, "https://z/"), "https://z/");
+    }
+
+    /// Start a server that sends one canned response per connection, and
+    /// returns the requests it received once `count` have been handled.
+    fn serve(responses: Vec<&'static str>) -> (u16, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request = String::new();
+                while reader.read_line(&mut request).unwrap() > 2 {}
+                if let Some(length) = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                {
+                    let mut body = vec![0; length.parse().unwrap()];
+                    reader.read_exact(&mut body).unwrap();
+                    request.push_str(std::str::from_utf8(&body).unwrap());
+                }
+                requests.push(request);
+                (&stream).write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        (port, handle)
+    }
+
+    fn fetch(request: Request, options: &Options) -> Vec<Event> {
+        start(request, options).iter().collect()
+    }
+
+    fn request(method: &str, url: String) -> Request {
+        Request {
+            method: method.to_string(),
+            url,
+            headers: vec![("X-Test".to_string(), "1".to_string())],
+            body: Vec::new(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    fn body(events: &[Event]) -> Vec<u8> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Data(data) => Some(data.as_slice()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .concat()
+    }
+
+    #[test]
+    fn content_length() {
+        let (port, server) = serve(vec![
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=UTF-8\r\nContent-Length: 5\r\n\r\nhello",
+        ]);
+        let events = fetch(
+            request("GET", format!("http://127.0.0.1:{}/a?b", port)),
+            &Options::default(),
+        );
+        let Event::Response(ref response) = events[0] else {
+            panic!("{:?}", events);
+        };
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.content_length(), Some(5));
+        assert_eq!(response.mime_type().as_deref(), Some("text/plain"));
+        assert_eq!(response.text_encoding_name().as_deref(), Some("utf-8"));
+        assert_eq!(body(&events), b"hello");
+        assert!(matches!(events.last(), Some(Event::Finished)));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /a?b HTTP/1.1\r\n"));
+        assert!(requests[0].contains(&format!("Host: 127.0.0.1:{}\r\n", port)));
+        assert!(requests[0].contains("X-Test: 1\r\n"));
+    }
+
+    #[test]
+    fn chunked_and_redirect() {
+        let (port, server) = serve(vec![
+            "HTTP/1.1 303 See Other\r\nLocation: /next\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n3;x=y\r\nabc\r\n2\r\nde\r\n0\r\nTrailer: 1\r\n\r\n",
+        ]);
+        let mut post = request("POST", format!("http://127.0.0.1:{}/first", port));
+        post.body = b"data".to_vec();
+        let events = fetch(post, &Options::default());
+        let Event::Response(ref response) = events[0] else {
+            panic!("{:?}", events);
+        };
+        assert_eq!(response.status_code, 404);
+        assert_eq!(response.url, format!("http://127.0.0.1:{}/next", port));
+        assert_eq!(body(&events), b"abcde");
+        assert!(matches!(events.last(), Some(Event::Finished)));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /first HTTP/1.1\r\n"));
+        assert!(requests[0].ends_with("\r\n\r\ndata"));
+        assert!(requests[1].starts_with("GET /next HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn redirect_host_and_errors() {
+        let (port, server) = serve(vec!["HTTP/1.0 200 OK\r\n\r\nuntil EOF", "garbage\r\n"]);
+        let mut options = Options::default();
+        options
+            .redirect_hosts
+            .insert("example.com".to_string(), format!("127.0.0.1:{}", port));
+        let events = fetch(request("GET", "https://example.com/".to_string()), &options);
+        assert_eq!(body(&events), b"until EOF");
+        let events = fetch(request("GET", "http://example.com/".to_string()), &options);
+        assert!(matches!(
+            events[..],
+            [Event::Failed(Error::BadServerResponse)]
+        ));
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains("Host: example.com\r\n"));
+
+        let events = fetch(
+            request("GET", "gopher://example.com/".to_string()),
+            &Options::default(),
+        );
+        assert!(matches!(events[..], [Event::Failed(Error::UnsupportedURL)]));
+    }
+
+    #[test]
+    fn offline_host() {
+        let dir = std::env::temp_dir().join(format!("touchHLE_http_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("api")).unwrap();
+        std::fs::write(dir.join("index.html"), "<html>").unwrap();
+        std::fs::write(dir.join("api/news.json"), "{}").unwrap();
+        std::fs::write(dir.join("api/scores%3Fid=1&b=%2F"), "one").unwrap();
+        std::fs::write(dir.join("api/scores"), "any").unwrap();
+        std::fs::write(
+            dir.join("api/gone.http"),
+            "HTTP/1.1 410 Gone\r\nContent-Type: text/plain\r\n\r\ngone",
+        )
+        .unwrap();
+        std::fs::write(dir.join("canned.xml"), "<xml/>").unwrap();
+
+        let mut options = Options::default();
+        options
+            .offline_hosts
+            .insert("example.com".to_string(), dir.clone());
+        options
+            .offline_hosts
+            .insert("example.net".to_string(), dir.join("canned.xml"));
+        let get = |url: &str| -> (u16, Option<String>, Vec<u8>) {
+            // Offline responses must be available immediately.
+            let events: Vec<Event> = start(request("GET", url.to_string()), &options)
+                .try_iter()
+                .collect();
+            assert!(matches!(events.last(), Some(Event::Finished)));
+            let Event::Response(ref response) = events[0] else {
+                panic!("{:?}", events);
+            };
+            (response.status_code, response.mime_type(), body(&events))
+        };
+
+        assert_eq!(
+            get("https://example.com/"),
+            (200, Some("text/html".to_string()), b"<html>".to_vec())
+        );
+        assert_eq!(
+            get("http://example.com/api/news.json?x=y#z"),
+            (200, Some("application/json".to_string()), b"{}".to_vec())
+        );
+        assert_eq!(get("http://example.com/api/scores?id=1&b=/").2, b"one");
+        assert_eq!(get("http://example.com/api/scores?id=2").2, b"any");
+        assert_eq!(
+            get("http://example.com/api/gone"),
+            (410, Some("text/plain".to_string()), b"gone".to_vec())
+        );
+        assert_eq!(get("http://example.com/api/missing").0, 404);
+        assert_eq!(get("http://example.com/api/%2E%2E/index.html").0, 404);
+        assert_eq!(
+            get("http://example.net/anything?at=all"),
+            (200, Some("text/xml".to_string()), b"<xml/>".to_vec())
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
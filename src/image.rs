@@ -7,12 +7,14 @@
 //!
 //! Implemented as a wrapper around the C library stb_image, since it supports
 //! "CgBI" PNG files (an Apple proprietary extension used in iPhone OS apps).
+//! PNG encoding (used for screenshots) is similarly provided by
+//! stb_image_write.
 //!
 //! This module also exposes decompression for Imagination Technologies' PVRTC
 //! format, implementing as a wrapper around their decoder from the PowerVR
 //! SDK.
 
-use std::ffi::{c_int, c_uchar, CStr};
+use std::ffi::{c_int, c_uchar, c_void, CStr};
 
 use touchHLE_pvrt_decompress_wrapper::*;
 use touchHLE_stb_image_wrapper::*;
@@ -112,6 +114,34 @@ impl Image {
         }
     }
 
+    /// Encode the image as a PNG file. The pixel data is written as-is, so
+    /// this is only lossless for opaque images.
+    pub fn to_png(&self) -> Vec<u8> {
+        unsafe extern "C" fn write(context: *mut c_void, data: *mut c_void, size: c_int) {
+            let png = &mut *context.cast::<Vec<u8>>();
+            png.extend_from_slice(std::slice::from_raw_parts(
+                data.cast::<u8>(),
+                size.try_into().unwrap(),
+            ));
+        }
+
+        let (width, height) = self.dimensions;
+        let mut png = Vec::new();
+        let success = unsafe {
+            stbi_write_png_to_func(
+                write,
+                (&mut png as *mut Vec<u8>).cast(),
+                width.try_into().unwrap(),
+                height.try_into().unwrap(),
+                4,
+                self.pixels().as_ptr().cast(),
+                (width * 4).try_into().unwrap(),
+            )
+        };
+        assert!(success != 0);
+        png
+    }
+
     fn pixels_mut(&mut self) -> &mut [u8] {
         match self.pixels {
             PixelStore::Vec(ref mut vec) => vec,
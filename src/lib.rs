@@ -33,23 +33,31 @@ mod audio;
 mod bundle;
 mod cpu;
 mod debug;
+mod device;
 mod dyld;
 mod environment;
 mod font;
+mod frame_capture;
 mod frameworks;
 mod fs;
 mod gdb;
 mod gles;
+mod http;
 mod image;
 mod libc;
 mod licenses;
 mod mach_o;
 mod matrix;
+mod mdns;
 mod mem;
 mod objc;
 mod options;
 mod paths;
+mod replay;
+mod save_state;
 mod stack;
+mod symbolication;
+mod trace;
 mod window;
 
 // Environment is used very frequently used and used to be in this module, so
@@ -57,7 +65,12 @@ mod window;
 // probably shouldn't be, but they need a new home (TODO).
 // Unlike its siblings, this module should be considered private and only used
 // via re-exports.
-use environment::{Environment, MutexId, MutexType, ThreadId, PTHREAD_MUTEX_DEFAULT};
+use environment::{
+    Environment, IoBlock, IoWait, MutexId, MutexType, ThreadId, PTHREAD_MUTEX_DEFAULT,
+};
+
+// Used by the integration tests to check captured frames.
+pub use frame_capture::{compare_with_reference, FrameComparison};
 
 use std::path::PathBuf;
 
@@ -323,5 +336,7 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> Result<(), String> {
 
     let mut env = Environment::new(bundle, fs, options, env_for_salvage)?;
     env.run();
+    replay::flush(&mut env);
+    env.report_heap_leaks();
     Ok(())
 }
@@ -11,6 +11,7 @@
 
 mod generic_char;
 
+pub mod arpa;
 pub mod clocale;
 pub mod ctype;
 pub mod cxxabi;
@@ -25,6 +26,7 @@ pub mod math;
 pub mod mmap;
 pub mod net;
 pub mod netdb;
+pub mod poll;
 pub mod posix_io;
 pub mod pthread;
 pub mod sched;
@@ -40,17 +42,81 @@ pub mod time;
 pub mod unistd;
 pub mod wchar;
 
+use crate::fs::Fs;
+use crate::save_state::{StateReader, StateWriter};
+
 /// Container for state of various child modules
 #[derive(Default)]
 pub struct State {
     dirent: dirent::State,
+    inet: arpa::inet::State,
     keymgr: keymgr::State,
+    mmap: mmap::State,
+    netdb: netdb::State,
     posix_io: posix_io::State,
     pthread: pthread::State,
     pub semaphore: semaphore::State,
+    signal: signal::State,
     stdlib: stdlib::State,
     string: string::State,
     time: time::State,
     errno: errno::State,
     clocale: clocale::State,
+    cxxabi: cxxabi::State,
+}
+impl State {
+    pub fn save_state(&mut self, writer: &mut StateWriter) {
+        let State {
+            dirent,
+            inet,
+            keymgr,
+            mmap,
+            netdb,
+            posix_io,
+            pthread,
+            semaphore,
+            signal,
+            stdlib,
+            string,
+            time,
+            errno,
+            clocale,
+            cxxabi,
+        } = self;
+        writer.write(dirent);
+        writer.write(inet);
+        writer.write(keymgr);
+        mmap.save_state(writer);
+        writer.write(netdb);
+        posix_io.save_state(writer);
+        writer.write(pthread);
+        writer.write(semaphore);
+        writer.write(signal);
+        writer.write(stdlib);
+        writer.write(string);
+        writer.write(time);
+        writer.write(errno);
+        writer.write(clocale);
+        writer.write(cxxabi);
+    }
+
+    pub fn load_state(reader: &mut StateReader, fs: &mut Fs) -> Result<State, String> {
+        Ok(State {
+            dirent: reader.read()?,
+            inet: reader.read()?,
+            keymgr: reader.read()?,
+            mmap: mmap::State::load_state(reader)?,
+            netdb: reader.read()?,
+            posix_io: posix_io::State::load_state(reader, fs)?,
+            pthread: reader.read()?,
+            semaphore: reader.read()?,
+            signal: reader.read()?,
+            stdlib: reader.read()?,
+            string: reader.read()?,
+            time: reader.read()?,
+            errno: reader.read()?,
+            clocale: reader.read()?,
+            cxxabi: reader.read()?,
+        })
+    }
 }
@@ -0,0 +1,170 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `arpa/inet.h`
+//!
+//! Note that `htons()` and friends are macros, so they don't need to be
+//! implemented here.
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{set_errno, EAFNOSUPPORT, ENOSPC};
+use crate::libc::sys::socket::{in_addr_t, socklen_t, AF_INET, AF_INET6};
+use crate::mem::{ConstPtr, ConstVoidPtr, MutPtr, MutVoidPtr};
+use crate::save_state::impl_SaveState;
+use crate::Environment;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Returned by `inet_addr()` on failure. This is also the broadcast address,
+/// unfortunately.
+const INADDR_NONE: in_addr_t = 0xffffffff;
+
+#[derive(Default)]
+pub struct State {
+    /// Static storage for the return value of `inet_ntoa()`.
+    ntoa_buffer: Option<MutPtr<u8>>,
+}
+impl_SaveState!(State { ntoa_buffer });
+
+/// Parse an IPv4 address the way `inet_aton()` does. Unlike [Ipv4Addr]'s
+/// parser, this accepts octal and hexadecimal parts, and addresses with fewer
+/// than four parts, where the last part fills the remaining bytes (e.g.
+/// `127.1` is `127.0.0.1`).
+fn parse_ipv4_addr(string: &[u8]) -> Option<Ipv4Addr> {
+    let string = std::str::from_utf8(string).ok()?;
+    let mut parts = Vec::new();
+    for part in string.split('.') {
+        let (digits, radix) =
+            if let Some(hex) = part.strip_prefix("0x").or_else(|| part.strip_prefix("0X")) {
+                (hex, 16)
+            } else if part.len() > 1 && part.starts_with('0') {
+                (&part[1..], 8)
+            } else {
+                (part, 10)
+            };
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return None;
+        }
+        parts.push(u32::from_str_radix(digits, radix).ok()?);
+    }
+    let (&last, leading) = parts.split_last()?;
+    if leading.len() > 3 || leading.iter().any(|&part| part > 0xff) {
+        return None;
+    }
+    let last_bits = 8 * (4 - leading.len() as u32);
+    if last_bits < 32 && last >> last_bits != 0 {
+        return None;
+    }
+    let addr = leading
+        .iter()
+        .enumerate()
+        .fold(last, |addr, (i, &part)| addr | (part << (24 - 8 * i)));
+    Some(Ipv4Addr::from(addr))
+}
+
+fn inet_addr(env: &mut Environment, string: ConstPtr<u8>) -> in_addr_t {
+    let res = parse_ipv4_addr(env.mem.cstr_at(string));
+    log_dbg!(
+        "inet_addr({:?} {:?}) => {:?}",
+        string,
+        env.mem.cstr_at_utf8(string),
+        res
+    );
+    res.map_or(INADDR_NONE, |addr| u32::from(addr).to_be())
+}
+
+fn inet_aton(env: &mut Environment, string: ConstPtr<u8>, addr: MutPtr<in_addr_t>) -> i32 {
+    match parse_ipv4_addr(env.mem.cstr_at(string)) {
+        Some(parsed) => {
+            if !addr.is_null() {
+                env.mem.write(addr, u32::from(parsed).to_be());
+            }
+            1
+        }
+        None => 0,
+    }
+}
+
+/// `struct in_addr` is passed by value, and it only contains an `in_addr_t`.
+fn inet_ntoa(env: &mut Environment, addr: in_addr_t) -> MutPtr<u8> {
+    let string = Ipv4Addr::from(u32::from_be(addr)).to_string();
+    // Big enough for "255.255.255.255".
+    let buffer = *env
+        .libc_state
+        .inet
+        .ntoa_buffer
+        .get_or_insert_with(|| env.mem.alloc(16).cast());
+    let bytes = env.mem.bytes_at_mut(buffer, string.len() as u32 + 1);
+    bytes[..string.len()].copy_from_slice(string.as_bytes());
+    bytes[string.len()] = b'\0';
+    buffer
+}
+
+fn inet_pton(env: &mut Environment, af: i32, src: ConstPtr<u8>, dst: MutVoidPtr) -> i32 {
+    let string = env.mem.cstr_at_utf8(src).ok();
+    match af {
+        AF_INET => match string.and_then(|string| string.parse::<Ipv4Addr>().ok()) {
+            Some(addr) => {
+                env.mem
+                    .bytes_at_mut(dst.cast(), 4)
+                    .copy_from_slice(&addr.octets());
+                1
+            }
+            None => 0,
+        },
+        AF_INET6 => match string.and_then(|string| string.parse::<Ipv6Addr>().ok()) {
+            Some(addr) => {
+                env.mem
+                    .bytes_at_mut(dst.cast(), 16)
+                    .copy_from_slice(&addr.octets());
+                1
+            }
+            None => 0,
+        },
+        _ => {
+            set_errno(env, EAFNOSUPPORT);
+            -1
+        }
+    }
+}
+
+fn inet_ntop(
+    env: &mut Environment,
+    af: i32,
+    src: ConstVoidPtr,
+    dst: MutPtr<u8>,
+    size: socklen_t,
+) -> ConstPtr<u8> {
+    let string = match af {
+        AF_INET => {
+            let octets: [u8; 4] = env.mem.bytes_at(src.cast(), 4).try_into().unwrap();
+            Ipv4Addr::from(octets).to_string()
+        }
+        AF_INET6 => {
+            let octets: [u8; 16] = env.mem.bytes_at(src.cast(), 16).try_into().unwrap();
+            Ipv6Addr::from(octets).to_string()
+        }
+        _ => {
+            set_errno(env, EAFNOSUPPORT);
+            return ConstPtr::null();
+        }
+    };
+    let len = string.len() as u32;
+    if len >= size {
+        set_errno(env, ENOSPC);
+        return ConstPtr::null();
+    }
+    let bytes = env.mem.bytes_at_mut(dst, len + 1);
+    bytes[..string.len()].copy_from_slice(string.as_bytes());
+    bytes[string.len()] = b'\0';
+    dst.cast_const()
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(inet_addr(_)),
+    export_c_func!(inet_aton(_, _)),
+    export_c_func!(inet_ntoa(_)),
+    export_c_func!(inet_pton(_, _, _)),
+    export_c_func!(inet_ntop(_, _, _, _)),
+];
@@ -5,33 +5,84 @@
  */
 //! `cxxabi.h`
 //!
+//! `__cxa_atexit()` is how compilers register the destructors of static C++
+//! objects. C `atexit()` (see [crate::libc::stdlib]) uses the same list of
+//! handlers, so that they are all run in the reverse order of registration.
+//!
 //! Resources:
 //! - [Itanium C++ ABI specification](https://itanium-cxx-abi.github.io/cxx-abi/abi.html#dso-dtor-runtime-api)
 
-use crate::abi::GuestFunction;
+use crate::abi::{CallFromHost, GuestFunction};
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::mem::MutVoidPtr;
+use crate::mem::{MutVoidPtr, Ptr};
+use crate::save_state::impl_SaveState;
 use crate::Environment;
 
+#[derive(Default)]
+pub struct State {
+    /// Registered handlers, in order of registration.
+    exit_handlers: Vec<ExitHandler>,
+}
+impl_SaveState!(State { exit_handlers });
+
+/// A function registered with `atexit()` or `__cxa_atexit()`.
+struct ExitHandler {
+    func: GuestFunction, // void (*func)(void *)
+    /// The argument for the function. `atexit()` functions take no argument,
+    /// so they get a null pointer they ignore.
+    arg: MutVoidPtr,
+    /// The "DSO handle" of the binary that registered the handler, or null.
+    dso: MutVoidPtr,
+}
+impl_SaveState!(ExitHandler { func, arg, dso });
+
+/// Add a handler to be run when the app exits.
+pub fn register_exit_handler(
+    env: &mut Environment,
+    func: GuestFunction,
+    arg: MutVoidPtr,
+    dso: MutVoidPtr,
+) {
+    env.libc_state
+        .cxxabi
+        .exit_handlers
+        .push(ExitHandler { func, arg, dso });
+}
+
+/// Run all the handlers registered with `atexit()` and `__cxa_atexit()`, most
+/// recently registered first. This is done when the app exits.
+pub fn run_exit_handlers(env: &mut Environment) {
+    __cxa_finalize(env, Ptr::null());
+}
+
 fn __cxa_atexit(
-    _env: &mut Environment,
+    env: &mut Environment,
     func: GuestFunction, // void (*func)(void *)
     p: MutVoidPtr,
     d: MutVoidPtr,
 ) -> i32 {
-    // TODO: when this is implemented, make sure it's properly compatible with
-    // C atexit.
-    log!(
-        "TODO: __cxa_atexit({:?}, {:?}, {:?}) (unimplemented)",
-        func,
-        p,
-        d
-    );
+    log_dbg!("__cxa_atexit({:?}, {:?}, {:?})", func, p, d);
+    register_exit_handler(env, func, p, d);
     0 // success
 }
 
-fn __cxa_finalize(_env: &mut Environment, d: MutVoidPtr) {
-    log!("TODO: __cxa_finalize({:?}) (unimplemented)", d);
+/// Run the handlers registered for the binary with the DSO handle `d`, or all
+/// handlers if `d` is null.
+fn __cxa_finalize(env: &mut Environment, d: MutVoidPtr) {
+    log_dbg!("__cxa_finalize({:?})", d);
+    // Each handler is removed before it's called, so that none is run twice
+    // and any handler registered by another handler is run too.
+    while let Some(idx) = env
+        .libc_state
+        .cxxabi
+        .exit_handlers
+        .iter()
+        .rposition(|handler| d.is_null() || handler.dso == d)
+    {
+        let ExitHandler { func, arg, .. } = env.libc_state.cxxabi.exit_handlers.remove(idx);
+        log_dbg!("Running exit handler {:?} with {:?}", func, arg);
+        () = func.call_from_host(env, (arg,));
+    }
 }
 
 pub const FUNCTIONS: FunctionExports = &[
@@ -7,7 +7,10 @@
 
 use crate::dyld::FunctionExports;
 use crate::fs::GuestPath;
+use crate::libc::errno::{set_errno, EBADF, ENOENT, ENOTDIR};
+use crate::libc::posix_io::read_path;
 use crate::mem::{ConstPtr, MutPtr, Ptr, SafeRead};
+use crate::save_state::impl_SaveState;
 use crate::{export_c_func, impl_GuestRet_for_large_struct, Environment};
 use std::collections::HashMap;
 
@@ -37,11 +40,20 @@ struct dirent {
 unsafe impl SafeRead for dirent {}
 impl_GuestRet_for_large_struct!(dirent);
 
+/// Values for `d_type`.
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+
 #[derive(Default)]
 pub struct State {
-    open_dirs: HashMap<MutPtr<DIR>, Vec<String>>,
+    /// Names and `d_type` values of the entries in each open directory.
+    open_dirs: HashMap<MutPtr<DIR>, Vec<(String, u8)>>,
     read_dirs: HashMap<MutPtr<DIR>, Vec<MutPtr<dirent>>>,
 }
+impl_SaveState!(State {
+    open_dirs,
+    read_dirs,
+});
 impl State {
     fn get_mut(env: &mut Environment) -> &mut Self {
         &mut env.libc_state.dirent
@@ -49,50 +61,76 @@ impl State {
 }
 
 fn opendir(env: &mut Environment, filename: ConstPtr<u8>) -> MutPtr<DIR> {
-    let path_string = env.mem.cstr_at_utf8(filename).unwrap().to_owned();
+    let path_string = match read_path(env, filename) {
+        Ok(path_string) => path_string,
+        Err(errno) => {
+            set_errno(env, errno);
+            return Ptr::null();
+        }
+    };
     log_dbg!("opendir: filename {}", path_string);
     let guest_path = GuestPath::new(&path_string);
-    let is_dir = env.fs.is_dir(guest_path);
-    if is_dir {
-        let dir = env.mem.alloc_and_write(DIR { idx: 0 });
-        log_dbg!("opendir: new DIR ptr: {:?}", dir);
-        let iter = env.fs.enumerate(guest_path).unwrap();
-        let vec = iter.map(|str| str.to_string()).collect();
-        assert!(!State::get_mut(env).open_dirs.contains_key(&dir));
-        State::get_mut(env).open_dirs.insert(dir, vec);
-        assert!(!State::get_mut(env).read_dirs.contains_key(&dir));
-        State::get_mut(env).read_dirs.insert(dir, Vec::new());
-        dir
-    } else {
-        Ptr::null()
+    let Ok(iter) = env.fs.enumerate(guest_path) else {
+        log_dbg!("opendir: {:?} is not a directory", path_string);
+        let errno = if env.fs.exists(guest_path) {
+            ENOTDIR
+        } else {
+            ENOENT
+        };
+        set_errno(env, errno);
+        return Ptr::null();
+    };
+    let names: Vec<String> = iter.map(|str| str.to_string()).collect();
+    // Like on a real filesystem, every directory contains itself and its
+    // parent.
+    let mut vec = vec![(".".to_string(), DT_DIR), ("..".to_string(), DT_DIR)];
+    for name in names {
+        let d_type = if env.fs.is_dir(&guest_path.join(&name)) {
+            DT_DIR
+        } else {
+            DT_REG
+        };
+        vec.push((name, d_type));
     }
+
+    let dir = env.mem.alloc_and_write(DIR { idx: 0 });
+    log_dbg!("opendir: new DIR ptr: {:?}", dir);
+    assert!(!State::get_mut(env).open_dirs.contains_key(&dir));
+    State::get_mut(env).open_dirs.insert(dir, vec);
+    assert!(!State::get_mut(env).read_dirs.contains_key(&dir));
+    State::get_mut(env).read_dirs.insert(dir, Vec::new());
+    dir
 }
 
-// TODO: return '.' and '..' entries as well
 fn readdir(env: &mut Environment, dirp: MutPtr<DIR>) -> MutPtr<dirent> {
+    let Some(vec) = env.libc_state.dirent.open_dirs.get(&dirp) else {
+        log!("Warning: readdir() on unknown DIR {:?}", dirp);
+        set_errno(env, EBADF);
+        return Ptr::null();
+    };
     let mut dir = env.mem.read(dirp);
-    let vec = env.libc_state.dirent.open_dirs.get(&dirp).unwrap();
     log_dbg!(
         "readdir: dirp {:?}, idx {}, entry '{:?}'",
         dirp,
         dir.idx,
         vec.get(dir.idx)
     );
-    if let Some(str) = vec.get(dir.idx) {
+    if let Some((str, d_type)) = vec.get(dir.idx) {
         dir.idx += 1;
-        env.mem.write(dirp, dir);
 
         let len = str.len();
-        // TODO: fill other fields
         let mut dirent = dirent {
-            d_ino: 0,
-            d_seekoff: 0,
-            d_reclen: 0,
+            // This isn't a real inode number, but it mustn't be zero, since
+            // that would mean the entry was deleted.
+            d_ino: dir.idx as u64,
+            d_seekoff: dir.idx as u64,
+            d_reclen: std::mem::size_of::<dirent>() as u16,
             d_namlen: len as u16,
-            d_type: 0,
+            d_type: *d_type,
             d_name: [b'\0'; MAXPATHLEN],
         };
         dirent.d_name[..len].copy_from_slice(str.as_bytes());
+        env.mem.write(dirp, dir);
         let res = env.mem.alloc_and_write(dirent);
         env.libc_state
             .dirent
@@ -116,8 +154,11 @@ fn closedir(env: &mut Environment, dirp: MutPtr<DIR>) -> i32 {
     if env.libc_state.dirent.open_dirs.remove(&dirp).is_some() {
         // this avoid double free if closedir() is called twice
         env.mem.free(dirp.cast());
+        0 // Success
+    } else {
+        set_errno(env, EBADF);
+        -1
     }
-    0 // Success
 }
 
 pub const FUNCTIONS: FunctionExports = &[
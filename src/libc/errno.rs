@@ -7,32 +7,113 @@
 
 use crate::dyld::FunctionExports;
 use crate::export_c_func;
+use crate::fs::FsError;
 use crate::mem::{ConstPtr, MutPtr};
+use crate::save_state::impl_SaveState;
 use crate::Environment;
 use std::io::Write;
 
 pub const EPERM: i32 = 1;
+pub const ENOENT: i32 = 2;
+pub const ESRCH: i32 = 3;
+pub const EIO: i32 = 5;
+pub const EBADF: i32 = 9;
 pub const EDEADLK: i32 = 11;
+pub const ENOMEM: i32 = 12;
+pub const EACCES: i32 = 13;
+pub const EFAULT: i32 = 14;
 pub const EBUSY: i32 = 16;
+pub const EEXIST: i32 = 17;
+pub const ENOTDIR: i32 = 20;
+pub const EISDIR: i32 = 21;
 pub const EINVAL: i32 = 22;
+pub const EMFILE: i32 = 24;
+pub const ENOTTY: i32 = 25;
+pub const ENOSPC: i32 = 28;
+pub const ESPIPE: i32 = 29;
+pub const EPIPE: i32 = 32;
+pub const ERANGE: i32 = 34;
+/// Also known as `EWOULDBLOCK`.
+pub const EAGAIN: i32 = 35;
+pub const EINPROGRESS: i32 = 36;
+pub const EALREADY: i32 = 37;
+pub const ENOTSOCK: i32 = 38;
+pub const EDESTADDRREQ: i32 = 39;
+pub const EPROTOTYPE: i32 = 41;
+pub const EPROTONOSUPPORT: i32 = 43;
+pub const ENOTSUP: i32 = 45;
+pub const EAFNOSUPPORT: i32 = 47;
+pub const EADDRINUSE: i32 = 48;
+pub const EADDRNOTAVAIL: i32 = 49;
+pub const ECONNABORTED: i32 = 53;
+pub const ECONNRESET: i32 = 54;
+pub const EISCONN: i32 = 56;
+pub const ENOTCONN: i32 = 57;
+pub const ETIMEDOUT: i32 = 60;
+pub const ECONNREFUSED: i32 = 61;
+pub const ENOTEMPTY: i32 = 66;
+pub const EOPNOTSUPP: i32 = 102;
 
 #[derive(Default)]
 pub struct State {
     errnos: std::collections::HashMap<crate::ThreadId, MutPtr<i32>>,
 }
+impl_SaveState!(State { errnos });
 impl State {
     fn errno_for_thread(
         &mut self,
         mem: &mut crate::mem::Mem,
         thread: crate::ThreadId,
     ) -> MutPtr<i32> {
-        *self.errnos.entry(thread).or_insert_with(|| {
-            log!(
-                "TODO: errno accessed on thread {} (will always be 0)",
-                thread
-            );
-            mem.alloc_and_write(0i32)
-        })
+        *self
+            .errnos
+            .entry(thread)
+            .or_insert_with(|| mem.alloc_and_write(0i32))
+    }
+}
+
+/// Set `errno` for the current thread.
+///
+/// TODO: Most functions that can fail don't call this yet.
+pub fn set_errno(env: &mut Environment, errno: i32) {
+    let ptr = env
+        .libc_state
+        .errno
+        .errno_for_thread(&mut env.mem, env.current_thread);
+    env.mem.write(ptr, errno);
+}
+
+/// Get the closest `errno` value for a host I/O error.
+pub fn errno_for_io_error(error: &std::io::Error) -> i32 {
+    use std::io::ErrorKind;
+    match error.kind() {
+        ErrorKind::NotFound => ENOENT,
+        ErrorKind::AlreadyExists => EEXIST,
+        ErrorKind::PermissionDenied => EACCES,
+        ErrorKind::ConnectionRefused => ECONNREFUSED,
+        ErrorKind::ConnectionReset => ECONNRESET,
+        ErrorKind::ConnectionAborted => ECONNABORTED,
+        ErrorKind::NotConnected => ENOTCONN,
+        ErrorKind::AddrInUse => EADDRINUSE,
+        ErrorKind::AddrNotAvailable => EADDRNOTAVAIL,
+        ErrorKind::BrokenPipe => EPIPE,
+        ErrorKind::WouldBlock => EAGAIN,
+        ErrorKind::InvalidInput => EINVAL,
+        ErrorKind::TimedOut => ETIMEDOUT,
+        _ => EIO,
+    }
+}
+
+/// Get the `errno` value for a guest filesystem error.
+pub fn errno_for_fs_error(error: FsError) -> i32 {
+    match error {
+        FsError::NotFound => ENOENT,
+        FsError::NotADirectory => ENOTDIR,
+        FsError::IsADirectory => EISDIR,
+        FsError::AlreadyExists => EEXIST,
+        FsError::DirectoryNotEmpty => ENOTEMPTY,
+        FsError::PermissionDenied => EACCES,
+        FsError::InvalidArgument => EINVAL,
     }
 }
 
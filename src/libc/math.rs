@@ -213,12 +213,6 @@ fn fmodf(_env: &mut Environment, arg1: f32, arg2: f32) -> f32 {
 
 // Maximum, minimum and positive difference functions
 // TODO: implement fdim
-fn bind(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
-    arg1.min(arg2)
-}
-fn connect(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
-    arg1.min(arg2)
-}
 fn div(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
     arg1.min(arg2)
 }
@@ -316,9 +310,6 @@ fn glReadPixels(_env: &mut Environment, arg1: f32, arg2: f32) -> f32 {
 fn glPointSizePointerOES(_env: &mut Environment, arg1: f32, arg2: f32) -> f32 {
     arg1.min(arg2)
 }
-fn abort(_env: &mut Environment, arg1: f32, arg2: f32) -> f32 {
-    arg1.min(arg2)
-}
 fn gzopen(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
     arg1.min(arg2)
 }
@@ -340,45 +331,15 @@ fn inflateInit2_(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
 fn inflateEnd(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
     arg1.min(arg2)
 }
-fn mprotect(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
-    arg1.min(arg2)
-}
-fn rename(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
-    arg1.min(arg2)
-}
-fn setsockopt(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
-    arg1.min(arg2)
-}
 fn strcasestr(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
     arg1.min(arg2)
 }
-fn getsockname(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
-    arg1.min(arg2)
-}
-fn socket(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
-    arg1.min(arg2)
-}
-fn ioctl(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
-    arg1.min(arg2)
-}
-fn inet_addr(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
-    arg1.min(arg2)
-}
-fn inet_ntoa(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
-    arg1.min(arg2)
-}
-fn listen(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
-    arg1.min(arg2)
-}
 fn uncompress(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
     arg1.min(arg2)
 }
 fn wcstok(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
     arg1.min(arg2)
 }
-fn _exit(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
-    arg1.min(arg2)
-}
 fn __sprintf_chk(_env: &mut Environment, arg1: f64, arg2: f64) -> f64 {
     arg1.min(arg2)
 }
@@ -588,8 +549,6 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(fmod(_, _)),
     export_c_func!(fmodf(_, _)),
     // Maximum, minimum and positive difference functions
-    export_c_func!(bind(_, _)),
-    export_c_func!(connect(_, _)),
     export_c_func!(div(_, _)),
     export_c_func!(fmax(_, _)),
     export_c_func!(fmaxf(_, _)),
@@ -622,7 +581,6 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(glGetTexParameteriv(_, _)),
     export_c_func!(glReadPixels(_, _)),
     export_c_func!(glPointSizePointerOES(_, _)),
-    export_c_func!(abort(_, _)),
     export_c_func!(gzopen(_, _)),
     export_c_func!(gzread(_, _)),
     export_c_func!(gzclose(_, _)),
@@ -630,19 +588,9 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(inflateInit_(_, _)),
     export_c_func!(inflateInit2_(_, _)),
     export_c_func!(inflateEnd(_, _)),
-    export_c_func!(mprotect(_, _)),
-    export_c_func!(rename(_, _)),
-    export_c_func!(setsockopt(_, _)),
     export_c_func!(strcasestr(_, _)),
-    export_c_func!(getsockname(_, _)),
-    export_c_func!(socket(_, _)),
-    export_c_func!(ioctl(_, _)),
-    export_c_func!(inet_addr(_, _)),
-    export_c_func!(inet_ntoa(_, _)),
-    export_c_func!(listen(_, _)),
     export_c_func!(uncompress(_, _)),
     export_c_func!(wcstok(_, _)),
-    export_c_func!(_exit(_, _)),
     export_c_func!(__sprintf_chk(_, _)),
     export_c_func!(sqlite3_open(_, _)),
     export_c_func!(sqlite3_errcode(_, _)),
@@ -3,38 +3,426 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+//! `sys/mman.h`
+//!
+//! Mappings are made from whole pages of guest memory reserved with the memory
+//! allocator. File mappings are made by reading the file into memory. For
+//! `MAP_SHARED` mappings of files that were opened for writing, changes are
+//! written back to the file by `msync()`, `munmap()` and when the app exits
+//! (but not automatically, and changes to the file aren't seen by the
+//! mapping).
 
 use crate::dyld::FunctionExports;
 use crate::environment::Environment;
 use crate::export_c_func;
+use crate::fs::GuestFile;
+use crate::libc::errno::{set_errno, EBADF, EINVAL, ENOMEM};
 use crate::libc::posix_io;
-use crate::libc::posix_io::{off_t, FileDescriptor, SEEK_SET};
-use crate::mem::{GuestUSize, MutVoidPtr};
+use crate::libc::posix_io::{off_t, FileDescriptor};
+use crate::mem::{ConstPtr, GuestUSize, Mem, MutVoidPtr, Protection};
+use crate::save_state::{StateReader, StateWriter};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{Seek, SeekFrom, Write};
+use std::rc::Rc;
 
-#[allow(dead_code)]
-const MAP_FILE: i32 = 0x0000;
+const PROT_READ: i32 = 0x1;
+const PROT_WRITE: i32 = 0x2;
+const PROT_EXEC: i32 = 0x4;
+
+const MAP_SHARED: i32 = 0x0001;
+const MAP_PRIVATE: i32 = 0x0002;
+const MAP_FIXED: i32 = 0x0010;
 const MAP_ANON: i32 = 0x1000;
 
-/// Our implementation of mmap is really simple: it's just load entirety of
-/// file in memory!
+const MS_ASYNC: i32 = 0x0001;
+const MS_INVALIDATE: i32 = 0x0002;
+const MS_SYNC: i32 = 0x0010;
+
+const MAP_FAILED: MutVoidPtr = MutVoidPtr::from_bits(u32::MAX);
+
+#[derive(Default)]
+pub struct State {
+    /// Current mappings, keyed by base address. The bases and sizes are
+    /// always page-aligned.
+    mappings: BTreeMap<GuestUSize, Mapping>,
+}
+
+struct Mapping {
+    size: GuestUSize,
+    /// Where to write back changes, for `MAP_SHARED` mappings of writable
+    /// files.
+    write_back: Option<WriteBack>,
+}
+
+#[derive(Clone)]
+struct WriteBack {
+    /// This is shared between the parts of a mapping split by `munmap()`.
+    file: Rc<RefCell<GuestFile>>,
+    /// Offset in the file of the start of the mapping.
+    offset: u64,
+}
+
+impl State {
+    /// The mappings' contents are part of guest memory, so only their bounds
+    /// are written here. Mappings that write back to a file aren't supported.
+    pub(super) fn save_state(&self, writer: &mut StateWriter) {
+        let mut mappings = Vec::new();
+        for (&base, mapping) in &self.mappings {
+            if mapping.write_back.is_some() {
+                writer.unsupported("shared file mappings");
+            }
+            mappings.push((base, mapping.size));
+        }
+        writer.write(&mappings);
+    }
+
+    pub(super) fn load_state(reader: &mut StateReader) -> Result<State, String> {
+        let mappings: Vec<(GuestUSize, GuestUSize)> = reader.read()?;
+        let mappings = mappings
+            .into_iter()
+            .map(|(base, size)| {
+                let write_back = None;
+                (base, Mapping { size, write_back })
+            })
+            .collect();
+        Ok(State { mappings })
+    }
+}
+
+/// Set the protection of some pages from `PROT_*` bits, and make sure the CPU
+/// checks it.
+fn set_protection(env: &mut Environment, base: GuestUSize, len: GuestUSize, prot: i32) {
+    env.mem
+        .set_protection(base, len, Protection::from_bits(prot as u32));
+    if len != 0 {
+        env.cpu
+            .update_page_slow_paths(&env.mem, Mem::pages(base, len));
+    }
+}
+
+/// Round a length up to a whole number of pages, if that's possible.
+fn page_align_len(len: GuestUSize) -> Option<GuestUSize> {
+    len.checked_next_multiple_of(Mem::PAGE_SIZE)
+}
+
 fn mmap(
     env: &mut Environment,
     addr: MutVoidPtr,
     len: GuestUSize,
-    _prot: i32,
+    prot: i32,
     flags: i32,
     fd: FileDescriptor,
     offset: off_t,
 ) -> MutVoidPtr {
-    assert!(addr.is_null());
-    assert_eq!(offset, 0);
-    assert_eq!((flags & MAP_ANON), 0);
-    let new_offset = posix_io::lseek(env, fd, offset, SEEK_SET);
-    assert_eq!(new_offset, offset);
-    let ptr = env.mem.alloc(len);
-    let read = posix_io::read(env, fd, ptr, len);
-    assert_eq!(read as u32, len);
-    ptr
-}
-
-pub const FUNCTIONS: FunctionExports = &[export_c_func!(mmap(_, _, _, _, _, _))];
+    let res = mmap_inner(env, addr, len, prot, flags, fd, offset);
+    match res {
+        Ok(ptr) => {
+            log_dbg!(
+                "mmap({:?}, {:#x}, {:#x}, {:#x}, {}, {:#x}) => {:?}",
+                addr,
+                len,
+                prot,
+                flags,
+                fd,
+                offset,
+                ptr
+            );
+            ptr
+        }
+        Err((errno, reason)) => {
+            log!(
+                "Warning: mmap({:?}, {:#x}, {:#x}, {:#x}, {}, {:#x}) failed: {}",
+                addr,
+                len,
+                prot,
+                flags,
+                fd,
+                offset,
+                reason
+            );
+            set_errno(env, errno);
+            MAP_FAILED
+        }
+    }
+}
+
+fn mmap_inner(
+    env: &mut Environment,
+    addr: MutVoidPtr,
+    len: GuestUSize,
+    prot: i32,
+    flags: i32,
+    fd: FileDescriptor,
+    offset: off_t,
+) -> Result<MutVoidPtr, (i32, &'static str)> {
+    let known_flags = MAP_SHARED | MAP_PRIVATE | MAP_FIXED | MAP_ANON;
+    if flags & !known_flags != 0 {
+        log!("TODO: mmap() flags {:#x}", flags & !known_flags);
+    }
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        log!("TODO: mmap() protection {:#x}", prot);
+    }
+
+    let Some(aligned_len) = page_align_len(len).filter(|&len| len != 0) else {
+        return Err((EINVAL, "bad length"));
+    };
+    if offset < 0 || offset % off_t::from(Mem::PAGE_SIZE) != 0 {
+        return Err((EINVAL, "offset isn't page-aligned"));
+    }
+    let is_fixed = flags & MAP_FIXED != 0;
+    if is_fixed && addr.to_bits() % Mem::PAGE_SIZE != 0 {
+        return Err((EINVAL, "fixed address isn't page-aligned"));
+    }
+
+    // Check the file before making any changes to the address space.
+    let write_back = if flags & MAP_ANON != 0 {
+        None
+    } else if !posix_io::is_file(env, fd) {
+        return Err((EBADF, "bad file descriptor"));
+    } else if flags & MAP_SHARED != 0 && prot & PROT_WRITE != 0 {
+        let file = posix_io::try_clone_for_writing(env, fd)
+            .map_err(|errno| (errno, "file isn't open for writing"))?;
+        Some(WriteBack {
+            file: Rc::new(RefCell::new(file)),
+            offset: offset as u64,
+        })
+    } else {
+        None
+    };
+
+    let base = if is_fixed {
+        // A fixed mapping replaces any existing mapping in the range, but
+        // touchHLE can't replace other kinds of memory. This is checked before
+        // unmapping anything, so that a failed mmap() changes nothing.
+        if !can_map_fixed(env, addr.to_bits(), aligned_len) {
+            return Err((ENOMEM, "fixed address range is in use"));
+        }
+        unmap(env, addr.to_bits(), aligned_len);
+        assert!(env.mem.try_reserve(addr.to_bits(), aligned_len));
+        addr
+    } else if !addr.is_null()
+        && addr.to_bits() % Mem::PAGE_SIZE == 0
+        && env.mem.try_reserve(addr.to_bits(), aligned_len)
+    {
+        addr
+    } else {
+        let Some(base) = env.mem.alloc_pages(aligned_len) else {
+            return Err((ENOMEM, "out of address space"));
+        };
+        base
+    };
+
+    // The memory might be from an earlier mapping or allocation that wasn't
+    // zeroed, e.g. after a fixed mapping replaced an old one.
+    env.mem.bytes_at_mut(base.cast(), aligned_len).fill(0);
+    if flags & MAP_ANON == 0 {
+        // Only the part of the mapping that the file covers is filled, and the
+        // rest stays zeroed.
+        if posix_io::read_at(env, fd, offset as u64, base, len).is_none() {
+            env.mem.free_pages(base.to_bits(), aligned_len);
+            return Err((EBADF, "bad file descriptor"));
+        }
+    }
+
+    set_protection(env, base.to_bits(), aligned_len, prot);
+    env.libc_state.mmap.mappings.insert(
+        base.to_bits(),
+        Mapping {
+            size: aligned_len,
+            write_back,
+        },
+    );
+    Ok(base)
+}
+
+/// Find the parts of mappings in a range of addresses. Each part is given as
+/// the base address of its mapping and the start and end of the part.
+fn mapping_parts(
+    env: &Environment,
+    base: GuestUSize,
+    len: GuestUSize,
+) -> Vec<(GuestUSize, GuestUSize, GuestUSize)> {
+    let mappings = &env.libc_state.mmap.mappings;
+    let end = base.saturating_add(len);
+    // The last mapping starting before the range might overlap it.
+    let first = mappings
+        .range(..base)
+        .next_back()
+        .map_or(base, |(&mapping_base, _)| mapping_base);
+    mappings
+        .range(first..end)
+        .filter_map(|(&mapping_base, mapping)| {
+            let start = mapping_base.max(base);
+            let end = (mapping_base + mapping.size).min(end);
+            (start < end).then_some((mapping_base, start, end))
+        })
+        .collect()
+}
+
+/// Check whether a fixed mapping could be made in a range, i.e. whether every
+/// part of it that isn't already mapped is unused memory.
+fn can_map_fixed(env: &Environment, base: GuestUSize, len: GuestUSize) -> bool {
+    let Some(end) = base.checked_add(len) else {
+        return false;
+    };
+    let mut unmapped_start = base;
+    for (_, mapped_start, mapped_end) in mapping_parts(env, base, len) {
+        if unmapped_start < mapped_start
+            && !env
+                .mem
+                .is_unused(unmapped_start, mapped_start - unmapped_start)
+        {
+            return false;
+        }
+        unmapped_start = mapped_end;
+    }
+    unmapped_start == end || env.mem.is_unused(unmapped_start, end - unmapped_start)
+}
+
+/// Write back the changes to a part of a mapping, if it's a shared mapping of
+/// a file.
+fn write_back(env: &mut Environment, mapping_base: GuestUSize, start: GuestUSize, end: GuestUSize) {
+    let Some(write_back) = &env.libc_state.mmap.mappings[&mapping_base].write_back else {
+        return;
+    };
+    let mut file = write_back.file.borrow_mut();
+    let offset = write_back.offset + u64::from(start - mapping_base);
+
+    // The parts of the mapping past the end of the file aren't written back.
+    let file_len = file.seek(SeekFrom::End(0)).unwrap();
+    let len = u64::from(end - start).min(file_len.saturating_sub(offset));
+    if len == 0 {
+        return;
+    }
+    let bytes = env
+        .mem
+        .bytes_at(ConstPtr::from_bits(start), len.try_into().unwrap());
+    if let Err(e) = file
+        .seek(SeekFrom::Start(offset))
+        .and_then(|_| file.write_all(bytes))
+    {
+        log!(
+            "Warning: writing back mapping at {:#x} failed: {:?}",
+            start,
+            e
+        );
+    }
+}
+
+/// Write back and remove any mappings in a range, splitting mappings that are
+/// only partly in the range.
+fn unmap(env: &mut Environment, base: GuestUSize, len: GuestUSize) {
+    for (mapping_base, start, end) in mapping_parts(env, base, len) {
+        write_back(env, mapping_base, start, end);
+
+        let mappings = &mut env.libc_state.mmap.mappings;
+        let mapping = mappings.remove(&mapping_base).unwrap();
+        let mapping_end = mapping_base + mapping.size;
+        if mapping_base < start {
+            mappings.insert(
+                mapping_base,
+                Mapping {
+                    size: start - mapping_base,
+                    write_back: mapping.write_back.clone(),
+                },
+            );
+        }
+        if end < mapping_end {
+            mappings.insert(
+                end,
+                Mapping {
+                    size: mapping_end - end,
+                    write_back: mapping.write_back.map(|write_back| WriteBack {
+                        offset: write_back.offset + u64::from(end - mapping_base),
+                        ..write_back
+                    }),
+                },
+            );
+        }
+
+        set_protection(env, start, end - start, PROT_READ | PROT_WRITE | PROT_EXEC);
+        env.mem.free_pages(start, end - start);
+    }
+}
+
+/// Write back the changes to all shared mappings of files. This is done when
+/// the app exits, since the changes would otherwise be lost.
+pub fn write_back_all(env: &mut Environment) {
+    let mappings: Vec<_> = env
+        .libc_state
+        .mmap
+        .mappings
+        .iter()
+        .map(|(&base, mapping)| (base, base + mapping.size))
+        .collect();
+    for (base, end) in mappings {
+        write_back(env, base, base, end);
+    }
+}
+
+fn munmap(env: &mut Environment, addr: MutVoidPtr, len: GuestUSize) -> i32 {
+    let Some(aligned_len) = page_align_len(len).filter(|&len| len != 0) else {
+        log!("Warning: munmap({:?}, {:#x}) with bad length", addr, len);
+        set_errno(env, EINVAL);
+        return -1;
+    };
+    if addr.to_bits() % Mem::PAGE_SIZE != 0 {
+        log!(
+            "Warning: munmap({:?}, {:#x}) with unaligned address",
+            addr,
+            len
+        );
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    // It's not an error if there's nothing mapped in the range.
+    unmap(env, addr.to_bits(), aligned_len);
+    log_dbg!("munmap({:?}, {:#x}) => 0", addr, len);
+    0
+}
+
+fn msync(env: &mut Environment, addr: MutVoidPtr, len: GuestUSize, flags: i32) -> i32 {
+    if addr.to_bits() % Mem::PAGE_SIZE != 0
+        || flags & !(MS_ASYNC | MS_INVALIDATE | MS_SYNC) != 0
+        || flags & (MS_ASYNC | MS_SYNC) == (MS_ASYNC | MS_SYNC)
+    {
+        log!(
+            "Warning: msync({:?}, {:#x}, {:#x}) with bad arguments",
+            addr,
+            len,
+            flags
+        );
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    // MS_INVALIDATE has nothing to do, because touchHLE's mappings never see
+    // changes made to the file.
+    for (mapping_base, start, end) in mapping_parts(env, addr.to_bits(), len) {
+        write_back(env, mapping_base, start, end);
+    }
+    log_dbg!("msync({:?}, {:#x}, {:#x}) => 0", addr, len, flags);
+    0
+}
+
+fn mprotect(env: &mut Environment, addr: MutVoidPtr, len: GuestUSize, prot: i32) -> i32 {
+    if addr.to_bits() % Mem::PAGE_SIZE != 0 {
+        log!(
+            "Warning: mprotect({:?}, {:#x}, {:#x}) with unaligned address",
+            addr,
+            len,
+            prot
+        );
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    set_protection(env, addr.to_bits(), len, prot);
+    0
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(mmap(_, _, _, _, _, _)),
+    export_c_func!(munmap(_, _)),
+    export_c_func!(msync(_, _, _)),
+    export_c_func!(mprotect(_, _, _)),
+];
@@ -4,11 +4,30 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! `netdb.h`
+//!
+//! Host names are resolved using the host's resolver, which blocks all guest
+//! threads until it's done. Only IPv4 addresses are supported.
 
 use crate::dyld::FunctionExports;
 use crate::export_c_func;
-use crate::mem::{ConstPtr, MutPtr, Ptr};
+use crate::libc::sys::socket::{
+    sockaddr, sockaddr_in, socklen_t, AF_INET, AF_UNSPEC, IPPROTO_TCP, IPPROTO_UDP, SOCK_DGRAM,
+    SOCK_STREAM,
+};
+use crate::mem::{guest_size_of, ConstPtr, MutPtr, Ptr, SafeRead};
+use crate::save_state::impl_SaveState;
 use crate::Environment;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs};
+
+#[derive(Default)]
+pub struct State {
+    /// Cache of strings returned by `gai_strerror()`.
+    gai_strerror_strings: HashMap<i32, ConstPtr<u8>>,
+}
+impl_SaveState!(State {
+    gai_strerror_strings,
+});
 
 // TODO: struct definition
 #[allow(non_camel_case_types)]
@@ -24,4 +43,285 @@ fn gethostbyname(env: &mut Environment, name: ConstPtr<u8>) -> MutPtr<hostent> {
     Ptr::null()
 }
 
-pub const FUNCTIONS: FunctionExports = &[export_c_func!(gethostbyname(_))];
+const AI_PASSIVE: i32 = 0x1;
+const AI_CANONNAME: i32 = 0x2;
+const AI_NUMERICHOST: i32 = 0x4;
+const AI_ALL: i32 = 0x100;
+const AI_ADDRCONFIG: i32 = 0x400;
+const AI_V4MAPPED: i32 = 0x800;
+const AI_NUMERICSERV: i32 = 0x1000;
+
+const EAI_ADDRFAMILY: i32 = 1;
+const EAI_AGAIN: i32 = 2;
+const EAI_BADFLAGS: i32 = 3;
+const EAI_FAIL: i32 = 4;
+const EAI_FAMILY: i32 = 5;
+const EAI_MEMORY: i32 = 6;
+const EAI_NODATA: i32 = 7;
+const EAI_NONAME: i32 = 8;
+const EAI_SERVICE: i32 = 9;
+const EAI_SOCKTYPE: i32 = 10;
+const EAI_SYSTEM: i32 = 11;
+const EAI_BADHINTS: i32 = 12;
+const EAI_PROTOCOL: i32 = 13;
+const EAI_OVERFLOW: i32 = 14;
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct addrinfo {
+    ai_flags: i32,
+    ai_family: i32,
+    ai_socktype: i32,
+    ai_protocol: i32,
+    ai_addrlen: socklen_t,
+    ai_canonname: MutPtr<u8>,
+    ai_addr: MutPtr<sockaddr>,
+    ai_next: MutPtr<addrinfo>,
+}
+unsafe impl SafeRead for addrinfo {}
+
+/// Look up a service name, for the few services an app might plausibly use.
+/// A real implementation would use `/etc/services`.
+fn port_for_service(service: &str) -> Option<u16> {
+    match service {
+        "ftp" => Some(21),
+        "ssh" => Some(22),
+        "telnet" => Some(23),
+        "smtp" => Some(25),
+        "domain" => Some(53),
+        "http" | "www" => Some(80),
+        "https" => Some(443),
+        _ => None,
+    }
+}
+
+/// Resolve a host name, or return an `EAI_*` error.
+fn resolve_node(node: Option<&str>, flags: i32) -> Result<Vec<Ipv4Addr>, i32> {
+    let Some(node) = node else {
+        // With no node, the addresses are for binding or for connecting to
+        // this device.
+        return Ok(vec![if flags & AI_PASSIVE != 0 {
+            Ipv4Addr::UNSPECIFIED
+        } else {
+            Ipv4Addr::LOCALHOST
+        }]);
+    };
+    if let Ok(addr) = node.parse() {
+        return Ok(vec![addr]);
+    }
+    if flags & AI_NUMERICHOST != 0 {
+        return Err(EAI_NONAME);
+    }
+
+    log_dbg!("Resolving {:?} with the host's resolver", node);
+    let addrs = (node, 0).to_socket_addrs().map_err(|e| {
+        log!("Warning: couldn't resolve {:?}: {}", node, e);
+        EAI_NONAME
+    })?;
+    let mut ipv4_addrs = Vec::new();
+    for addr in addrs {
+        if let SocketAddr::V4(addr) = addr {
+            if !ipv4_addrs.contains(addr.ip()) {
+                ipv4_addrs.push(*addr.ip());
+            }
+        }
+    }
+    if ipv4_addrs.is_empty() {
+        log!("Warning: {:?} has no IPv4 addresses", node);
+        return Err(EAI_NODATA);
+    }
+    Ok(ipv4_addrs)
+}
+
+/// The part of `getaddrinfo()` that doesn't touch guest memory. Returns the
+/// address, socket type and protocol of each result, or an `EAI_*` error.
+fn getaddrinfo_inner(
+    node: Option<&str>,
+    service: Option<&str>,
+    flags: i32,
+    family: i32,
+    socktype: i32,
+    protocol: i32,
+) -> Result<Vec<(SocketAddrV4, i32, i32)>, i32> {
+    let known_flags = AI_PASSIVE
+        | AI_CANONNAME
+        | AI_NUMERICHOST
+        | AI_ALL
+        | AI_ADDRCONFIG
+        | AI_V4MAPPED
+        | AI_NUMERICSERV;
+    if flags & !known_flags != 0 {
+        return Err(EAI_BADFLAGS);
+    }
+    if node.is_none() && service.is_none() {
+        return Err(EAI_NONAME);
+    }
+    if family != AF_UNSPEC && family != AF_INET {
+        log!(
+            "TODO: getaddrinfo() family {} (only IPv4 is supported)",
+            family
+        );
+        return Err(EAI_FAMILY);
+    }
+    // Each kind of socket gets its own result.
+    let kinds: &[(i32, i32)] = match (socktype, protocol) {
+        (0, 0) => &[(SOCK_STREAM, IPPROTO_TCP), (SOCK_DGRAM, IPPROTO_UDP)],
+        (0 | SOCK_STREAM, IPPROTO_TCP) | (SOCK_STREAM, 0) => &[(SOCK_STREAM, IPPROTO_TCP)],
+        (0 | SOCK_DGRAM, IPPROTO_UDP) | (SOCK_DGRAM, 0) => &[(SOCK_DGRAM, IPPROTO_UDP)],
+        _ => return Err(EAI_SOCKTYPE),
+    };
+
+    let port = match service {
+        None => 0,
+        Some(service) => match service.parse::<u16>() {
+            Ok(port) => port,
+            Err(_) if flags & AI_NUMERICSERV != 0 => return Err(EAI_NONAME),
+            Err(_) => port_for_service(service).ok_or(EAI_SERVICE)?,
+        },
+    };
+
+    let addrs = resolve_node(node, flags)?;
+    Ok(addrs
+        .into_iter()
+        .flat_map(|addr| {
+            kinds.iter().map(move |&(socktype, protocol)| {
+                (SocketAddrV4::new(addr, port), socktype, protocol)
+            })
+        })
+        .collect())
+}
+
+fn getaddrinfo(
+    env: &mut Environment,
+    node: ConstPtr<u8>,
+    service: ConstPtr<u8>,
+    hints: ConstPtr<addrinfo>,
+    res: MutPtr<MutPtr<addrinfo>>,
+) -> i32 {
+    let node_str = (!node.is_null()).then(|| env.mem.cstr_at_utf8(node).unwrap().to_string());
+    let service_str =
+        (!service.is_null()).then(|| env.mem.cstr_at_utf8(service).unwrap().to_string());
+    let (flags, family, socktype, protocol) = if hints.is_null() {
+        (AI_V4MAPPED | AI_ADDRCONFIG, AF_UNSPEC, 0, 0)
+    } else {
+        let hints = env.mem.read(hints);
+        (
+            hints.ai_flags,
+            hints.ai_family,
+            hints.ai_socktype,
+            hints.ai_protocol,
+        )
+    };
+
+    let results = match getaddrinfo_inner(
+        node_str.as_deref(),
+        service_str.as_deref(),
+        flags,
+        family,
+        socktype,
+        protocol,
+    ) {
+        Ok(results) => results,
+        Err(error) => {
+            log_dbg!(
+                "getaddrinfo({:?}, {:?}, {:?}, {:?}) => {}",
+                node_str,
+                service_str,
+                hints,
+                res,
+                error
+            );
+            return error;
+        }
+    };
+
+    // The list is built from the end, so each entry can point to the next.
+    let mut next = Ptr::null();
+    for (i, &(addr, socktype, protocol)) in results.iter().enumerate().rev() {
+        let ai_addr = env.mem.alloc_and_write(sockaddr_in::from_socket_addr(addr));
+        // Only the first entry has the canonical name. The name isn't
+        // actually canonicalized.
+        let ai_canonname = match node_str {
+            Some(ref node_str) if i == 0 && flags & AI_CANONNAME != 0 => {
+                env.mem.alloc_and_write_cstr(node_str.as_bytes())
+            }
+            _ => Ptr::null(),
+        };
+        next = env.mem.alloc_and_write(addrinfo {
+            ai_flags: flags,
+            ai_family: AF_INET,
+            ai_socktype: socktype,
+            ai_protocol: protocol,
+            ai_addrlen: guest_size_of::<sockaddr_in>(),
+            ai_canonname,
+            ai_addr: ai_addr.cast(),
+            ai_next: next,
+        });
+    }
+    log_dbg!(
+        "getaddrinfo({:?}, {:?}, {:?}, {:?}) => 0 ({:?})",
+        node_str,
+        service_str,
+        hints,
+        res,
+        results
+    );
+    env.mem.write(res, next);
+    0
+}
+
+fn freeaddrinfo(env: &mut Environment, mut ai: MutPtr<addrinfo>) {
+    while !ai.is_null() {
+        let addrinfo {
+            ai_canonname,
+            ai_addr,
+            ai_next,
+            ..
+        } = env.mem.read(ai);
+        if !ai_canonname.is_null() {
+            env.mem.free(ai_canonname.cast());
+        }
+        if !ai_addr.is_null() {
+            env.mem.free(ai_addr.cast());
+        }
+        env.mem.free(ai.cast());
+        ai = ai_next;
+    }
+}
+
+fn gai_strerror(env: &mut Environment, error: i32) -> ConstPtr<u8> {
+    if let Some(&string) = env.libc_state.netdb.gai_strerror_strings.get(&error) {
+        return string;
+    }
+    // Messages from FreeBSD.
+    let message: &[u8] = match error {
+        EAI_ADDRFAMILY => b"Address family for hostname not supported",
+        EAI_AGAIN => b"Temporary failure in name resolution",
+        EAI_BADFLAGS => b"Invalid value for ai_flags",
+        EAI_FAIL => b"Non-recoverable failure in name resolution",
+        EAI_FAMILY => b"ai_family not supported",
+        EAI_MEMORY => b"Memory allocation failure",
+        EAI_NODATA => b"No address associated with hostname",
+        EAI_NONAME => b"hostname nor servname provided, or not known",
+        EAI_SERVICE => b"servname not supported for ai_socktype",
+        EAI_SOCKTYPE => b"ai_socktype not supported",
+        EAI_SYSTEM => b"System error returned in errno",
+        EAI_BADHINTS => b"Invalid value for hints",
+        EAI_PROTOCOL => b"Resolved protocol is unknown",
+        EAI_OVERFLOW => b"Argument buffer overflow",
+        _ => b"Unknown error",
+    };
+    let string = env.mem.alloc_and_write_cstr(message).cast_const();
+    env.libc_state
+        .netdb
+        .gai_strerror_strings
+        .insert(error, string);
+    string
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(gethostbyname(_)),
+    export_c_func!(getaddrinfo(_, _, _, _)),
+    export_c_func!(freeaddrinfo(_)),
+    export_c_func!(gai_strerror(_)),
+];
@@ -0,0 +1,94 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `poll.h`
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{set_errno, EINVAL};
+use crate::libc::posix_io::{self, FileDescriptor};
+use crate::libc::sys::socket::POLL_INTERVAL;
+use crate::mem::{MutPtr, SafeRead};
+use crate::replay;
+use crate::Environment;
+use std::time::Duration;
+
+const POLLIN: i16 = 0x1;
+const POLLPRI: i16 = 0x2;
+const POLLOUT: i16 = 0x4;
+const POLLERR: i16 = 0x8;
+const POLLNVAL: i16 = 0x20;
+const POLLRDNORM: i16 = 0x40;
+const POLLWRNORM: i16 = POLLOUT;
+
+#[allow(non_camel_case_types)]
+type nfds_t = u32;
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+pub struct pollfd {
+    fd: FileDescriptor,
+    events: i16,
+    revents: i16,
+}
+unsafe impl SafeRead for pollfd {}
+
+fn poll(env: &mut Environment, fds: MutPtr<pollfd>, nfds: nfds_t, timeout: i32) -> i32 {
+    if nfds > 1024 || (fds.is_null() && nfds != 0) || timeout < -1 {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    // A negative timeout means waiting forever.
+    let deadline = (timeout >= 0)
+        .then(|| replay::now(env) + Duration::from_millis(timeout.try_into().unwrap()));
+
+    loop {
+        let mut count = 0;
+        for i in 0..nfds {
+            let pollfd { fd, events, .. } = env.mem.read(fds + i);
+            // Negative file descriptors are ignored.
+            let revents = if fd < 0 {
+                0
+            } else if let Some(readiness) = posix_io::readiness(env, fd) {
+                if events & POLLPRI != 0 {
+                    log!("TODO: poll() on out-of-band data");
+                }
+                let mut revents = 0;
+                if readiness.readable {
+                    revents |= events & (POLLIN | POLLRDNORM);
+                }
+                if readiness.writable {
+                    revents |= events & (POLLOUT | POLLWRNORM);
+                }
+                // This is reported even if it wasn't requested.
+                if readiness.error {
+                    revents |= POLLERR;
+                }
+                revents
+            } else {
+                POLLNVAL
+            };
+            env.mem.write(
+                fds + i,
+                pollfd {
+                    fd,
+                    events,
+                    revents,
+                },
+            );
+            if revents != 0 {
+                count += 1;
+            }
+        }
+
+        if count != 0 || deadline.is_some_and(|deadline| replay::now(env) >= deadline) {
+            log_dbg!("poll({:?}, {}, {}) => {}", fds, nfds, timeout, count);
+            return count;
+        }
+
+        env.sleep(POLL_INTERVAL, false);
+    }
+}
+
+pub const FUNCTIONS: FunctionExports = &[export_c_func!(poll(_, _, _))];
@@ -9,30 +9,349 @@ pub mod stat;
 
 use crate::abi::DotDotDot;
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::fs::{GuestFile, GuestOpenOptions, GuestPath};
+use crate::frameworks::dnssd::{self, DNSServiceRef};
+use crate::fs::{Fs, GuestFile, GuestOpenOptions, GuestPath, GuestPathBuf};
+use crate::libc::errno::{
+    errno_for_fs_error, errno_for_io_error, set_errno, EACCES, EAGAIN, EBADF, EEXIST, EFAULT,
+    EINVAL, EIO, EISDIR, ENOENT, ENOTDIR, ENOTSOCK, ENOTSUP, EPERM, EPIPE, ERANGE, ESPIPE,
+};
+use crate::libc::signal;
+use crate::libc::sys::socket::{self, Readiness, Socket, POLL_INTERVAL};
 use crate::mem::{ConstPtr, ConstVoidPtr, GuestISize, GuestUSize, MutPtr, MutVoidPtr, Ptr};
+use crate::save_state::{SaveState, StateReader, StateWriter};
 use crate::Environment;
+use stat::{mode_t, S_IWUSR};
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::io::{Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
 
 #[derive(Default)]
 pub struct State {
     /// File descriptors _other than stdin, stdout, and stderr_
-    files: Vec<Option<PosixFileHostObject>>,
+    files: Vec<Option<Descriptor>>,
 }
 impl State {
+    /// If `fd` was made by `dup()` or `dup2()`, get the file descriptor it
+    /// duplicates, otherwise return `fd` unchanged.
+    fn resolve(&self, fd: FileDescriptor) -> FileDescriptor {
+        if fd >= NORMAL_FILENO_BASE {
+            if let Some(Some(Descriptor::Duplicate(original))) = self.files.get(fd_to_file_idx(fd))
+            {
+                return *original;
+            }
+        }
+        fd
+    }
+
+    /// Get what a file descriptor refers to. Returns [None] if `fd` isn't
+    /// open, or is (a duplicate of) stdin, stdout or stderr.
+    fn descriptor(&mut self, fd: FileDescriptor) -> Option<&mut Descriptor> {
+        let fd = self.resolve(fd);
+        if fd < NORMAL_FILENO_BASE {
+            return None;
+        }
+        self.files.get_mut(fd_to_file_idx(fd))?.as_mut()
+    }
+
+    /// Whether `fd` is (a duplicate of) stdin, stdout or stderr.
+    fn is_standard_stream(&self, fd: FileDescriptor) -> bool {
+        (STDIN_FILENO..NORMAL_FILENO_BASE).contains(&self.resolve(fd))
+    }
+
+    /// Whether `fd` is open, including stdin, stdout and stderr.
+    fn is_open(&mut self, fd: FileDescriptor) -> bool {
+        self.is_standard_stream(fd) || self.descriptor(fd).is_some()
+    }
+
     fn file_for_fd(&mut self, fd: FileDescriptor) -> Option<&mut PosixFileHostObject> {
-        self.files
-            .get_mut(fd_to_file_idx(fd))
-            .and_then(|file_or_none| file_or_none.as_mut())
+        match self.descriptor(fd)? {
+            Descriptor::File(file) => Some(file),
+            _ => None,
+        }
+    }
+
+    /// Get the socket for a file descriptor, or the `errno` value to fail with
+    /// if there isn't one.
+    pub(super) fn socket_for_fd(&mut self, fd: FileDescriptor) -> Result<&mut Socket, i32> {
+        if self.resolve(fd) < NORMAL_FILENO_BASE {
+            return Err(if fd < 0 { EBADF } else { ENOTSOCK });
+        }
+        match self.descriptor(fd) {
+            Some(Descriptor::Socket(socket)) => Ok(socket),
+            Some(_) => Err(ENOTSOCK),
+            None => Err(EBADF),
+        }
+    }
+
+    fn add_descriptor(&mut self, descriptor: Descriptor) -> FileDescriptor {
+        let idx = if let Some(free_idx) = self.files.iter().position(|f| f.is_none()) {
+            self.files[free_idx] = Some(descriptor);
+            free_idx
+        } else {
+            self.files.push(Some(descriptor));
+            self.files.len() - 1
+        };
+        file_idx_to_fd(idx)
+    }
+
+    /// Give a new socket a file descriptor.
+    pub(super) fn add_socket(&mut self, socket: Socket) -> FileDescriptor {
+        self.add_descriptor(Descriptor::Socket(socket))
     }
+
+    /// Free a file descriptor. If it was the last one referring to something,
+    /// that is returned so it can be closed. If it has duplicates, one of them
+    /// takes its place.
+    fn remove_descriptor(&mut self, fd: FileDescriptor) -> Option<Descriptor> {
+        let descriptor = match self.files.get_mut(fd_to_file_idx(fd))?.take()? {
+            Descriptor::Duplicate(_) => return None,
+            descriptor => descriptor,
+        };
+        let Some(new_idx) = self
+            .files
+            .iter()
+            .position(|f| matches!(f, Some(Descriptor::Duplicate(original)) if *original == fd))
+        else {
+            return Some(descriptor);
+        };
+        let new_fd = file_idx_to_fd(new_idx);
+        self.files[new_idx] = Some(descriptor);
+        for file in self.files.iter_mut() {
+            if let Some(Descriptor::Duplicate(original)) = file {
+                if *original == fd {
+                    *original = new_fd;
+                }
+            }
+        }
+        None
+    }
+
+    pub(super) fn save_state(&mut self, writer: &mut StateWriter) {
+        let mut files: Vec<Option<SavedDescriptor>> = Vec::with_capacity(self.files.len());
+        for descriptor in self.files.iter_mut() {
+            files.push(match descriptor {
+                None => None,
+                Some(Descriptor::File(file)) => Some(SavedDescriptor::File {
+                    path: file.path.as_str().to_string(),
+                    reached_eof: file.reached_eof,
+                    flags: file.flags,
+                    lock: file.lock,
+                    position: file.file.stream_position().unwrap(),
+                }),
+                Some(Descriptor::Directory(directory)) => Some(SavedDescriptor::Directory {
+                    path: directory.path.as_str().to_string(),
+                    lock: directory.lock,
+                }),
+                Some(Descriptor::Duplicate(original)) => {
+                    Some(SavedDescriptor::Duplicate(*original))
+                }
+                Some(Descriptor::Pipe(_)) => {
+                    writer.unsupported("pipes");
+                    None
+                }
+                Some(Descriptor::Socket(_)) => {
+                    writer.unsupported("sockets");
+                    None
+                }
+                Some(Descriptor::DnsService(_)) => {
+                    writer.unsupported("DNS-SD");
+                    None
+                }
+            });
+        }
+        writer.write(&files);
+    }
+
+    /// Reopens the files that were open when the state was saved. Their
+    /// contents aren't part of the save state.
+    pub(super) fn load_state(reader: &mut StateReader, fs: &mut Fs) -> Result<State, String> {
+        let files: Vec<Option<SavedDescriptor>> = reader.read()?;
+        let files = files
+            .into_iter()
+            .map(|descriptor| {
+                Ok(Some(match descriptor {
+                    None => return Ok(None),
+                    Some(SavedDescriptor::File {
+                        path,
+                        reached_eof,
+                        flags,
+                        lock,
+                        position,
+                    }) => {
+                        // The file was already created or truncated if
+                        // necessary when it was first opened.
+                        let mut options = GuestOpenOptions::new();
+                        match flags & O_ACCMODE {
+                            O_RDONLY => options.read(),
+                            O_WRONLY => options.write(),
+                            _ => options.read().write(),
+                        };
+                        if (flags & O_APPEND) != 0 {
+                            options.append();
+                        }
+                        let mut file = fs
+                            .open_with_options(GuestPath::new(&path), options)
+                            .map_err(|e| format!("Couldn't reopen {:?}: {:?}", path, e))?;
+                        file.seek(SeekFrom::Start(position))
+                            .map_err(|e| format!("Couldn't seek in {:?}: {}", path, e))?;
+                        Descriptor::File(PosixFileHostObject {
+                            file,
+                            path: GuestPathBuf::from(path),
+                            reached_eof,
+                            flags,
+                            lock,
+                        })
+                    }
+                    Some(SavedDescriptor::Directory { path, lock }) => {
+                        Descriptor::Directory(PosixDirectoryHostObject {
+                            path: GuestPathBuf::from(path),
+                            lock,
+                        })
+                    }
+                    Some(SavedDescriptor::Duplicate(original)) => Descriptor::Duplicate(original),
+                }))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(State { files })
+    }
+}
+
+/// What a file descriptor refers to.
+enum Descriptor {
+    File(PosixFileHostObject),
+    /// A directory opened with `open()`, which can be used with `fchdir()`.
+    Directory(PosixDirectoryHostObject),
+    /// One end of a pipe made by `pipe()`.
+    Pipe(PipeEnd),
+    Socket(Socket),
+    /// The socket for a `DNSServiceRef`, which can only be used to wait for
+    /// results (see [crate::frameworks::dnssd]).
+    DnsService(DNSServiceRef),
+    /// A duplicate made by `dup()` or `dup2()` of another file descriptor,
+    /// which may be stdin, stdout or stderr. They share a file offset, lock
+    /// etc, so all operations use the other file descriptor instead.
+    Duplicate(FileDescriptor),
 }
 
 struct PosixFileHostObject {
     file: GuestFile,
+    /// Absolute path, used to find other file descriptors for the same file
+    /// when locking it, and to reopen it when loading a save state.
+    path: GuestPathBuf,
     reached_eof: bool,
+    /// The access mode and status flags that were passed to `open()`, or set
+    /// with `fcntl()`.
+    flags: OpenFlag,
+    /// Lock taken with `flock()`, if any: [LOCK_SH] or [LOCK_EX].
+    lock: Option<FLockFlag>,
+}
+impl PosixFileHostObject {
+    fn readable(&self) -> bool {
+        self.flags & O_ACCMODE != O_WRONLY
+    }
+    fn writable(&self) -> bool {
+        self.flags & O_ACCMODE != O_RDONLY
+    }
+}
+
+struct PosixDirectoryHostObject {
+    /// Absolute path.
+    path: GuestPathBuf,
+    /// Lock taken with `flock()`, if any: [LOCK_SH] or [LOCK_EX].
+    lock: Option<FLockFlag>,
+}
+
+/// The data in a pipe, shared by both of its ends.
+#[derive(Default)]
+struct PipeBuffer {
+    data: VecDeque<u8>,
+    read_end_open: bool,
+    write_end_open: bool,
+}
+
+/// One end of a pipe. There's no limit on how much data a pipe can hold, so
+/// writing to one never blocks.
+struct PipeEnd {
+    buffer: Rc<RefCell<PipeBuffer>>,
+    is_write_end: bool,
+    /// Set with `fcntl()`.
+    non_blocking: bool,
+}
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        let mut buffer = self.buffer.borrow_mut();
+        if self.is_write_end {
+            buffer.write_end_open = false;
+        } else {
+            buffer.read_end_open = false;
+        }
+    }
+}
+
+/// What a [Descriptor] is saved as in a save state. Pipes and sockets can't be
+/// saved.
+enum SavedDescriptor {
+    File {
+        path: String,
+        reached_eof: bool,
+        flags: OpenFlag,
+        lock: Option<FLockFlag>,
+        position: u64,
+    },
+    Directory {
+        path: String,
+        lock: Option<FLockFlag>,
+    },
+    Duplicate(FileDescriptor),
+}
+impl SaveState for SavedDescriptor {
+    fn save(&self, writer: &mut StateWriter) {
+        match *self {
+            SavedDescriptor::File {
+                ref path,
+                reached_eof,
+                flags,
+                lock,
+                position,
+            } => {
+                writer.write(&0u8);
+                writer.write(path);
+                writer.write(&reached_eof);
+                writer.write(&flags);
+                writer.write(&lock);
+                writer.write(&position);
+            }
+            SavedDescriptor::Directory { ref path, lock } => {
+                writer.write(&1u8);
+                writer.write(path);
+                writer.write(&lock);
+            }
+            SavedDescriptor::Duplicate(original) => {
+                writer.write(&2u8);
+                writer.write(&original);
+            }
+        }
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        Ok(match reader.read::<u8>()? {
+            0 => SavedDescriptor::File {
+                path: reader.read()?,
+                reached_eof: reader.read()?,
+                flags: reader.read()?,
+                lock: reader.read()?,
+                position: reader.read()?,
+            },
+            1 => SavedDescriptor::Directory {
+                path: reader.read()?,
+                lock: reader.read()?,
+            },
+            2 => SavedDescriptor::Duplicate(reader.read()?),
+            _ => return Err("Save state has an invalid file descriptor".to_string()),
+        })
+    }
 }
 
-// TODO: stdin/stdout/stderr handling somehow
 fn file_idx_to_fd(idx: usize) -> FileDescriptor {
     FileDescriptor::try_from(idx)
         .unwrap()
@@ -49,6 +368,8 @@ pub const STDIN_FILENO: FileDescriptor = 0;
 pub const STDOUT_FILENO: FileDescriptor = 1;
 pub const STDERR_FILENO: FileDescriptor = 2;
 const NORMAL_FILENO_BASE: FileDescriptor = STDERR_FILENO + 1;
+/// The highest file descriptor number is one less than this.
+const OPEN_MAX: FileDescriptor = 256;
 
 /// Flags bitfield for `open`. This alias is for readability, POSIX just uses
 /// `int`.
@@ -61,6 +382,7 @@ pub const O_ACCMODE: OpenFlag = O_RDWR | O_WRONLY | O_RDONLY;
 pub const O_NONBLOCK: OpenFlag = 0x4;
 pub const O_APPEND: OpenFlag = 0x8;
 pub const O_SHLOCK: OpenFlag = 0x10;
+pub const O_EXLOCK: OpenFlag = 0x20;
 pub const O_NOFOLLOW: OpenFlag = 0x100;
 pub const O_CREAT: OpenFlag = 0x200;
 pub const O_TRUNC: OpenFlag = 0x400;
@@ -68,119 +390,186 @@ pub const O_EXCL: OpenFlag = 0x800;
 
 pub type FLockFlag = i32;
 pub const LOCK_SH: FLockFlag = 1;
-#[allow(dead_code)]
 pub const LOCK_EX: FLockFlag = 2;
-#[allow(dead_code)]
 pub const LOCK_NB: FLockFlag = 4;
-#[allow(dead_code)]
 pub const LOCK_UN: FLockFlag = 8;
 
-fn open(env: &mut Environment, path: ConstPtr<u8>, flags: i32, _args: DotDotDot) -> FileDescriptor {
-    // TODO: parse variadic arguments and pass them on (file creation mode)
-    self::open_direct(env, path, flags)
+/// Convert a result to a return value for a function that returns -1 and
+/// sets `errno` on failure.
+fn finish<T: From<i8>>(env: &mut Environment, res: Result<T, i32>) -> T {
+    match res {
+        Ok(value) => value,
+        Err(errno) => {
+            set_errno(env, errno);
+            T::from(-1)
+        }
+    }
+}
+
+/// Read a path passed to a function, or get the `errno` value to fail with.
+pub(super) fn read_path(env: &mut Environment, path: ConstPtr<u8>) -> Result<String, i32> {
+    if path.is_null() {
+        return Err(EFAULT);
+    }
+    match env.mem.cstr_at_utf8(path) {
+        Ok(path_str) => Ok(path_str.to_owned()),
+        Err(err) => {
+            log!("Warning: unable to treat {:?} as utf8 str: {:?}", path, err);
+            Err(EINVAL)
+        }
+    }
+}
+
+fn open(env: &mut Environment, path: ConstPtr<u8>, flags: i32, args: DotDotDot) -> FileDescriptor {
+    // The mode is only passed if a file might be created.
+    let mode: mode_t = if flags & O_CREAT != 0 {
+        args.start().next(env)
+    } else {
+        0
+    };
+    let res = open_inner(env, path, flags, mode);
+    log_dbg!("open({:?}, {:#x}, {:#o}) => {:?}", path, flags, mode, res);
+    finish(env, res)
 }
 
-/// Special extension for host code: [open] without the [DotDotDot].
+/// Special extension for host code: [open] without the [DotDotDot]. Files are
+/// created with the mode `0666`, like with `fopen()`.
 pub fn open_direct(env: &mut Environment, path: ConstPtr<u8>, flags: i32) -> FileDescriptor {
+    let res = open_inner(env, path, flags, 0o666);
+    log_dbg!("open({:?}, {:#x}) => {:?}", path, flags, res);
+    finish(env, res)
+}
+
+fn open_inner(
+    env: &mut Environment,
+    path: ConstPtr<u8>,
+    flags: i32,
+    mode: mode_t,
+) -> Result<FileDescriptor, i32> {
     // TODO: support more flags, this list is not complete
-    assert!(
-        flags
-            & !(O_ACCMODE
-                | O_NONBLOCK
-                | O_APPEND
-                | O_SHLOCK
-                | O_NOFOLLOW
-                | O_CREAT
-                | O_TRUNC
-                | O_EXCL)
-            == 0
-    );
-    // TODO: exclusive mode not implemented yet
-    assert!(flags & O_EXCL == 0);
+    let unsupported = flags
+        & !(O_ACCMODE
+            | O_NONBLOCK
+            | O_APPEND
+            | O_SHLOCK
+            | O_EXLOCK
+            | O_NOFOLLOW
+            | O_CREAT
+            | O_TRUNC
+            | O_EXCL);
+    if unsupported != 0 {
+        log!(
+            "TODO: open({:?}, {:#x}) with unsupported flags {:#x}",
+            path,
+            flags,
+            unsupported
+        );
+        return Err(EINVAL);
+    }
+    if flags & (O_SHLOCK | O_EXLOCK) == (O_SHLOCK | O_EXLOCK) {
+        return Err(EINVAL);
+    }
 
-    if path.is_null() {
-        log_dbg!("open({:?}, {:#x}) => -1", path, flags);
-        return -1; // TODO: set errno to EFAULT
+    let path_string = read_path(env, path)?;
+    log_dbg!("Opening {:?}", path_string);
+    let guest_path = GuestPath::new(&path_string);
+    // TODO: symlinks don't exist in the FS yet, so we can't "not follow" them.
+    if flags & O_NOFOLLOW != 0 {
+        log!("Ignoring O_NOFOLLOW when opening {:?}", path_string);
+    }
+    let mut create = flags & O_CREAT != 0;
+    let exclusive = create && flags & O_EXCL != 0;
+
+    if env.fs.is_dir(guest_path) {
+        if exclusive {
+            return Err(EEXIST);
+        }
+        if flags & O_ACCMODE != O_RDONLY || flags & O_TRUNC != 0 {
+            return Err(EISDIR);
+        }
+        let dir = PosixDirectoryHostObject {
+            path: env.fs.absolute_path(guest_path),
+            lock: None,
+        };
+        let fd = env
+            .libc_state
+            .posix_io
+            .add_descriptor(Descriptor::Directory(dir));
+        return lock_on_open(env, fd, flags);
     }
 
-    // TODO: respect the mode (in the variadic arguments) when creating a file
     // Note: NONBLOCK flag is ignored, assumption is all file I/O is fast
     let mut options = GuestOpenOptions::new();
     match flags & O_ACCMODE {
         O_RDONLY => options.read(),
         O_WRONLY => options.write(),
         O_RDWR => options.read().write(),
-        _ => panic!(),
+        _ => return Err(EINVAL),
     };
+    let writable = flags & O_ACCMODE != O_RDONLY;
+    if create && !writable {
+        // The guest filesystem can only create files that are opened for
+        // writing. That's fine, since the file descriptor won't allow writes.
+        if env.fs.exists(guest_path) && !exclusive {
+            create = false;
+        } else {
+            options.write();
+        }
+    }
     if (flags & O_APPEND) != 0 {
         options.append();
     }
-    if (flags & O_CREAT) != 0 {
+    if exclusive {
+        options.create_new();
+    } else if create {
         options.create();
     }
-    if (flags & O_TRUNC) != 0 {
+    if create && mode & S_IWUSR == 0 {
+        options.create_read_only();
+    }
+    // Truncating only makes sense if the file can be written to.
+    if (flags & O_TRUNC) != 0 && writable {
         options.truncate();
     }
 
-    let path_string = match env.mem.cstr_at_utf8(path) {
-        Ok(path_str) => path_str.to_owned(),
-        Err(err) => {
-            log!(
-                "open() error, unable to treat {:?} as utf8 str: {:?}",
-                path,
-                err
-            );
-            // TODO: set errno
-            return -1;
-        }
-    };
-    // TODO: symlinks don't exist in the FS yet, so we can't "not follow" them.
-    if flags & O_NOFOLLOW != 0 {
-        log!("Ignoring O_NOFOLLOW when opening {:?}", path_string);
-    }
-    let res = match env
+    let file = env
         .fs
-        .open_with_options(GuestPath::new(&path_string), options)
-    {
-        Ok(file) => {
-            let host_object = PosixFileHostObject {
-                file,
-                reached_eof: false,
-            };
+        .open_with_options(guest_path, options)
+        .map_err(errno_for_fs_error)?;
+    let host_object = PosixFileHostObject {
+        file,
+        path: env.fs.absolute_path(guest_path),
+        reached_eof: false,
+        flags: flags & (O_ACCMODE | O_APPEND | O_NONBLOCK),
+        lock: None,
+    };
+    let fd = env
+        .libc_state
+        .posix_io
+        .add_descriptor(Descriptor::File(host_object));
+    lock_on_open(env, fd, flags)
+}
 
-            let idx = if let Some(free_idx) = env
-                .libc_state
-                .posix_io
-                .files
-                .iter()
-                .position(|f| f.is_none())
-            {
-                env.libc_state.posix_io.files[free_idx] = Some(host_object);
-                free_idx
-            } else {
-                let idx = env.libc_state.posix_io.files.len();
-                env.libc_state.posix_io.files.push(Some(host_object));
-                idx
-            };
-            file_idx_to_fd(idx)
-        }
-        Err(()) => {
-            // TODO: set errno
-            -1
-        }
+/// Take the lock requested by `O_SHLOCK` or `O_EXLOCK` for a newly opened file
+/// descriptor, closing it if that fails.
+fn lock_on_open(
+    env: &mut Environment,
+    fd: FileDescriptor,
+    flags: i32,
+) -> Result<FileDescriptor, i32> {
+    let operation = if flags & O_SHLOCK != 0 {
+        LOCK_SH
+    } else if flags & O_EXLOCK != 0 {
+        LOCK_EX
+    } else {
+        return Ok(fd);
     };
-    if res != -1 && (flags & O_SHLOCK) != 0 {
-        // TODO: Handle possible errors
-        flock(env, res, LOCK_SH);
+    let non_blocking = if flags & O_NONBLOCK != 0 { LOCK_NB } else { 0 };
+    if let Err(errno) = flock_inner(env, fd, operation | non_blocking) {
+        close(env, fd);
+        return Err(errno);
     }
-    log_dbg!(
-        "open({:?} {:?}, {:#x}) => {:?}",
-        path,
-        path_string,
-        flags,
-        res
-    );
-    res
+    Ok(fd)
 }
 
 pub fn read(
@@ -189,51 +578,104 @@ pub fn read(
     buffer: MutVoidPtr,
     size: GuestUSize,
 ) -> GuestISize {
+    if env.libc_state.posix_io.socket_for_fd(fd).is_ok() {
+        return socket::recv(env, fd, buffer, size, 0);
+    }
+
+    let res = read_inner(env, fd, buffer, size);
+    log_dbg!("read({:?}, {:?}, {:#x}) => {:?}", fd, buffer, size, res);
+    finish(env, res)
+}
+
+fn read_inner(
+    env: &mut Environment,
+    fd: FileDescriptor,
+    buffer: MutVoidPtr,
+    size: GuestUSize,
+) -> Result<GuestISize, i32> {
+    match env.libc_state.posix_io.resolve(fd) {
+        // TODO: stdin never has any input, since there's no way to provide it
+        STDIN_FILENO => return Ok(0),
+        STDOUT_FILENO | STDERR_FILENO => return Err(EBADF),
+        _ => (),
+    }
+    if !env.libc_state.posix_io.is_open(fd) {
+        return Err(EBADF);
+    }
+    if size == 0 {
+        return Ok(0);
+    }
     if buffer.is_null() {
-        // TODO: set errno to EFAULT
-        return -1;
+        return Err(EFAULT);
     }
 
-    // TODO: error handling for unknown fd?
-    let file = env.libc_state.posix_io.file_for_fd(fd).unwrap();
+    loop {
+        // The pipe might have been closed by another thread while waiting.
+        let file = match env.libc_state.posix_io.descriptor(fd) {
+            Some(Descriptor::File(file)) => file,
+            Some(Descriptor::Pipe(pipe)) => {
+                if pipe.is_write_end {
+                    return Err(EBADF);
+                }
+                let mut pipe_buffer = pipe.buffer.borrow_mut();
+                // Reading from an empty pipe waits for data to be written,
+                // unless the write end has been closed.
+                if pipe_buffer.data.is_empty() && pipe_buffer.write_end_open {
+                    if pipe.non_blocking {
+                        return Err(EAGAIN);
+                    }
+                    drop(pipe_buffer);
+                    env.sleep(POLL_INTERVAL, false);
+                    continue;
+                }
+                let count = pipe_buffer.data.len().min(size as usize);
+                let buffer_slice = env.mem.bytes_at_mut(buffer.cast(), count as GuestUSize);
+                for (dst, src) in buffer_slice.iter_mut().zip(pipe_buffer.data.drain(..count)) {
+                    *dst = src;
+                }
+                return Ok(count.try_into().unwrap());
+            }
+            Some(Descriptor::Directory(_)) => return Err(EISDIR),
+            Some(Descriptor::DnsService(_)) => {
+                log!("TODO: read() from DNS-SD socket {:?}", fd);
+                return Err(EINVAL);
+            }
+            Some(Descriptor::Socket(_) | Descriptor::Duplicate(_)) => unreachable!(),
+            None => return Err(EBADF),
+        };
+        if !file.readable() {
+            return Err(EBADF);
+        }
 
-    let buffer_slice = env.mem.bytes_at_mut(buffer.cast(), size);
-    match file.file.read(buffer_slice) {
-        Ok(bytes_read) => {
-            if bytes_read == 0 && size != 0 {
-                // need to set EOF
-                file.reached_eof = true;
+        let buffer_slice = env.mem.bytes_at_mut(buffer.cast(), size);
+        return match file.file.read(buffer_slice) {
+            Ok(bytes_read) => {
+                if bytes_read == 0 {
+                    // need to set EOF
+                    file.reached_eof = true;
+                }
+                if bytes_read < buffer_slice.len() {
+                    log!(
+                        "Warning: read({:?}, {:?}, {:#x}) read only {:#x} bytes",
+                        fd,
+                        buffer,
+                        size,
+                        bytes_read,
+                    );
+                }
+                Ok(bytes_read.try_into().unwrap())
             }
-            if bytes_read < buffer_slice.len() {
+            Err(e) => {
                 log!(
-                    "Warning: read({:?}, {:?}, {:#x}) read only {:#x} bytes",
-                    fd,
-                    buffer,
-                    size,
-                    bytes_read,
-                );
-            } else {
-                log_dbg!(
-                    "read({:?}, {:?}, {:#x}) => {:#x}",
+                    "Warning: read({:?}, {:?}, {:#x}) encountered error {:?}",
                     fd,
                     buffer,
                     size,
-                    bytes_read,
+                    e,
                 );
+                Err(errno_for_io_error(&e))
             }
-            bytes_read.try_into().unwrap()
-        }
-        Err(e) => {
-            // TODO: set errno
-            log!(
-                "Warning: read({:?}, {:?}, {:#x}) encountered error {:?}, returning -1",
-                fd,
-                buffer,
-                size,
-                e,
-            );
-            -1
-        }
+        };
     }
 }
 
@@ -255,27 +697,108 @@ pub(super) fn clearerr(env: &mut Environment, fd: FileDescriptor) {
 
 /// Helper for C `fflush()`.
 pub(super) fn fflush(env: &mut Environment, fd: FileDescriptor) -> i32 {
-    let Some(file) = env.libc_state.posix_io.file_for_fd(fd) else {
-        // TODO: set errno to EBADF
-        return -1;
+    let res = match env.libc_state.posix_io.resolve(fd) {
+        STDOUT_FILENO => std::io::stdout().flush(),
+        STDERR_FILENO => std::io::stderr().flush(),
+        _ => {
+            let Some(file) = env.libc_state.posix_io.file_for_fd(fd) else {
+                set_errno(env, EBADF);
+                return -1;
+            };
+            file.file.flush()
+        }
     };
-    match file.file.flush() {
+    match res {
         Ok(_) => 0,
-        Err(_) => -1,
+        Err(e) => {
+            set_errno(env, errno_for_io_error(&e));
+            -1
+        }
     }
 }
 
-pub fn write(
+fn write(
     env: &mut Environment,
     fd: FileDescriptor,
     buffer: ConstVoidPtr,
     size: GuestUSize,
 ) -> GuestISize {
-    // TODO: error handling for unknown fd?
-    let file = env.libc_state.posix_io.file_for_fd(fd).unwrap();
+    if env.libc_state.posix_io.socket_for_fd(fd).is_ok() {
+        return socket::send(env, fd, buffer, size, 0);
+    }
 
+    let res = write_inner(env, fd, buffer, size);
+    log_dbg!("write({:?}, {:?}, {:#x}) => {:?}", fd, buffer, size, res);
+    // Writing to a pipe that has no reader also sends SIGPIPE, which
+    // terminates the app unless it's ignored or handled.
+    let broken_pipe = res == Err(EPIPE);
+    let res = finish(env, res);
+    if broken_pipe {
+        return signal::send_sigpipe(env, res);
+    }
+    res
+}
+
+/// Special extension for host code: [write] without sending `SIGPIPE`, since
+/// a signal handler can only be set up when the app calls a function directly.
+pub fn write_direct(
+    env: &mut Environment,
+    fd: FileDescriptor,
+    buffer: ConstVoidPtr,
+    size: GuestUSize,
+) -> GuestISize {
+    if env.libc_state.posix_io.socket_for_fd(fd).is_ok() {
+        return socket::send(env, fd, buffer, size, 0);
+    }
+
+    let res = write_inner(env, fd, buffer, size);
+    log_dbg!("write({:?}, {:?}, {:#x}) => {:?}", fd, buffer, size, res);
+    finish(env, res)
+}
+
+fn write_inner(
+    env: &mut Environment,
+    fd: FileDescriptor,
+    buffer: ConstVoidPtr,
+    size: GuestUSize,
+) -> Result<GuestISize, i32> {
+    if !env.libc_state.posix_io.is_open(fd) {
+        return Err(EBADF);
+    }
+    if size == 0 {
+        return Ok(0);
+    }
+    if buffer.is_null() {
+        return Err(EFAULT);
+    }
     let buffer_slice = env.mem.bytes_at(buffer.cast(), size);
-    match file.file.write(buffer_slice) {
+
+    let res = match env.libc_state.posix_io.resolve(fd) {
+        STDIN_FILENO => return Err(EBADF),
+        STDOUT_FILENO => std::io::stdout().write(buffer_slice),
+        STDERR_FILENO => std::io::stderr().write(buffer_slice),
+        _ => match env.libc_state.posix_io.descriptor(fd).unwrap() {
+            Descriptor::File(file) if file.writable() => file.file.write(buffer_slice),
+            Descriptor::File(_) | Descriptor::Directory(_) => return Err(EBADF),
+            Descriptor::Pipe(pipe) => {
+                if !pipe.is_write_end {
+                    return Err(EBADF);
+                }
+                let mut pipe_buffer = pipe.buffer.borrow_mut();
+                if !pipe_buffer.read_end_open {
+                    return Err(EPIPE);
+                }
+                pipe_buffer.data.extend(buffer_slice);
+                Ok(buffer_slice.len())
+            }
+            Descriptor::DnsService(_) => {
+                log!("TODO: write() to DNS-SD socket {:?}", fd);
+                return Err(EINVAL);
+            }
+            Descriptor::Socket(_) | Descriptor::Duplicate(_) => unreachable!(),
+        },
+    };
+    match res {
         Ok(bytes_written) => {
             if bytes_written < buffer_slice.len() {
                 log!(
@@ -285,72 +808,140 @@ pub fn write(
                     size,
                     bytes_written,
                 );
-            } else {
-                log_dbg!(
-                    "write({:?}, {:?}, {:#x}) => {:#x}",
-                    fd,
-                    buffer,
-                    size,
-                    bytes_written,
-                );
             }
-            bytes_written.try_into().unwrap()
+            Ok(bytes_written.try_into().unwrap())
         }
         Err(e) => {
-            // TODO: set errno
             log!(
-                "Warning: write({:?}, {:?}, {:#x}) encountered error {:?}, returning -1",
+                "Warning: write({:?}, {:?}, {:#x}) encountered error {:?}",
                 fd,
                 buffer,
                 size,
                 e,
             );
-            -1
+            Err(errno_for_io_error(&e))
         }
     }
 }
 
+/// Helper for `mmap()`: like `pread()`, read from a file at an offset without
+/// changing the file position. Returns [None] if `fd` isn't an open file.
+pub(super) fn read_at(
+    env: &mut Environment,
+    fd: FileDescriptor,
+    offset: u64,
+    buffer: MutVoidPtr,
+    size: GuestUSize,
+) -> Option<GuestUSize> {
+    let file = env.libc_state.posix_io.file_for_fd(fd)?;
+    let old_pos = file.file.stream_position().unwrap();
+    file.file.seek(SeekFrom::Start(offset)).unwrap();
+    // Read until the buffer is full or the end of the file is reached.
+    let mut buffer_slice = env.mem.bytes_at_mut(buffer.cast(), size);
+    let mut bytes_read = 0;
+    while !buffer_slice.is_empty() {
+        match file.file.read(buffer_slice) {
+            Ok(0) => break,
+            Ok(n) => {
+                bytes_read += n;
+                buffer_slice = &mut buffer_slice[n..];
+            }
+            Err(e) => {
+                log!("Warning: read_at({:?}) encountered error {:?}", fd, e);
+                break;
+            }
+        }
+    }
+    file.file.seek(SeekFrom::Start(old_pos)).unwrap();
+    Some(bytes_read.try_into().unwrap())
+}
+
+/// Helper for `mmap()`: check whether `fd` is an open file.
+pub(super) fn is_file(env: &mut Environment, fd: FileDescriptor) -> bool {
+    env.libc_state.posix_io.file_for_fd(fd).is_some()
+}
+
+/// Helper for `mmap()` with `MAP_SHARED`: get a new handle to the file that
+/// `fd` refers to, which can be used to write back changes after `fd` is
+/// closed. Fails with `EBADF` if `fd` isn't an open file, or `EACCES` if it
+/// can't be written to.
+pub(super) fn try_clone_for_writing(
+    env: &mut Environment,
+    fd: FileDescriptor,
+) -> Result<GuestFile, i32> {
+    let file = env.libc_state.posix_io.file_for_fd(fd).ok_or(EBADF)?;
+    if !file.writable() {
+        return Err(EACCES);
+    }
+    file.file.try_clone_host_file().ok_or(EACCES)
+}
+
 #[allow(non_camel_case_types)]
 pub type off_t = i64;
 pub const SEEK_SET: i32 = 0;
 pub const SEEK_CUR: i32 = 1;
 pub const SEEK_END: i32 = 2;
 pub fn lseek(env: &mut Environment, fd: FileDescriptor, offset: off_t, whence: i32) -> off_t {
-    // TODO: error handling for unknown fd?
-    let file = env.libc_state.posix_io.file_for_fd(fd).unwrap();
+    let res = lseek_inner(env, fd, offset, whence);
+    log_dbg!("lseek({:?}, {:#x}, {}) => {:?}", fd, offset, whence, res);
+    finish(env, res)
+}
+
+fn lseek_inner(
+    env: &mut Environment,
+    fd: FileDescriptor,
+    offset: off_t,
+    whence: i32,
+) -> Result<off_t, i32> {
+    let state = &mut env.libc_state.posix_io;
+    if state.is_standard_stream(fd) {
+        return Err(ESPIPE);
+    }
+    let file = match state.descriptor(fd) {
+        Some(Descriptor::File(file)) => file,
+        Some(Descriptor::Directory(_)) => {
+            log!("TODO: lseek({:?}) on a directory", fd);
+            return Err(EINVAL);
+        }
+        Some(Descriptor::Pipe(_) | Descriptor::Socket(_) | Descriptor::DnsService(_)) => {
+            return Err(ESPIPE)
+        }
+        Some(Descriptor::Duplicate(_)) => unreachable!(),
+        None => return Err(EBADF),
+    };
 
     let from = match whence {
-        // not sure whether offset is treated as signed or unsigned when using
-        // SEEK_SET, so `.try_into()` seems safer.
-        SEEK_SET => SeekFrom::Start(offset.try_into().unwrap()),
+        SEEK_SET => SeekFrom::Start(offset.try_into().map_err(|_| EINVAL)?),
         SEEK_CUR => SeekFrom::Current(offset),
         SEEK_END => SeekFrom::End(offset),
-        _ => panic!("Unsupported \"whence\" parameter to seek(): {}", whence),
+        _ => return Err(EINVAL),
     };
 
-    let res = match file.file.seek(from) {
+    match file.file.seek(from) {
         Ok(new_offset) => {
             // "A successful call to the fseek() function clears
             // the end-of-file indicator for the stream..."
             file.reached_eof = false;
 
-            new_offset.try_into().unwrap()
+            Ok(new_offset.try_into().unwrap())
         }
-        // TODO: set errno
-        Err(_) => -1,
-    };
-    log_dbg!("lseek({:?}, {:#x}, {}) => {}", fd, offset, whence, res);
-    res
+        Err(e) => Err(errno_for_io_error(&e)),
+    }
 }
 
 pub fn close(env: &mut Environment, fd: FileDescriptor) -> i32 {
-    // TODO: error handling for unknown fd?
-    if fd < 0 || matches!(fd, STDOUT_FILENO | STDERR_FILENO) {
+    // TODO: closing stdin, stdout and stderr should have an effect
+    if (STDIN_FILENO..NORMAL_FILENO_BASE).contains(&fd) {
         return 0;
     }
+    if !env.libc_state.posix_io.is_open(fd) {
+        log!("Warning: close({:?}) failed, returning -1", fd);
+        set_errno(env, EBADF);
+        return -1;
+    }
 
-    match env.libc_state.posix_io.files[fd_to_file_idx(fd)].take() {
-        Some(file) => {
+    match env.libc_state.posix_io.remove_descriptor(fd) {
+        Some(Descriptor::File(file)) => {
             // The actual closing of the file happens implicitly when `file`
             // falls out of scope. The return value is about whether flushing
             // succeeds.
@@ -359,40 +950,177 @@ pub fn close(env: &mut Environment, fd: FileDescriptor) -> i32 {
                     log_dbg!("close({:?}) => 0", fd);
                     0
                 }
-                Err(_) => {
-                    // TODO: set errno
+                Err(e) => {
                     log!("Warning: close({:?}) failed, returning -1", fd);
+                    set_errno(env, errno_for_io_error(&e));
                     -1
                 }
             }
         }
-        None => {
-            // TODO: set errno
-            log!("Warning: close({:?}) failed, returning -1", fd);
-            -1
+        // Other things, like host sockets, are closed when they're dropped.
+        // If there's no descriptor, there are other file descriptors for the
+        // same thing, and it stays open.
+        _ => {
+            log_dbg!("close({:?}) => 0", fd);
+            0
+        }
+    }
+}
+
+/// Close all open file descriptors, flushing the files. This is done when the
+/// app exits.
+pub fn close_all(env: &mut Environment) {
+    for idx in 0..env.libc_state.posix_io.files.len() {
+        if env.libc_state.posix_io.files[idx].is_some() {
+            close(env, file_idx_to_fd(idx));
         }
     }
 }
 
+fn dup(env: &mut Environment, fd: FileDescriptor) -> FileDescriptor {
+    let state = &mut env.libc_state.posix_io;
+    let res = if state.is_open(fd) {
+        let original = state.resolve(fd);
+        Ok(state.add_descriptor(Descriptor::Duplicate(original)))
+    } else {
+        Err(EBADF)
+    };
+    log_dbg!("dup({:?}) => {:?}", fd, res);
+    finish(env, res)
+}
+
+fn dup2(env: &mut Environment, fd: FileDescriptor, new_fd: FileDescriptor) -> FileDescriptor {
+    let res = dup2_inner(env, fd, new_fd);
+    log_dbg!("dup2({:?}, {:?}) => {:?}", fd, new_fd, res);
+    finish(env, res)
+}
+
+fn dup2_inner(
+    env: &mut Environment,
+    fd: FileDescriptor,
+    new_fd: FileDescriptor,
+) -> Result<FileDescriptor, i32> {
+    if !env.libc_state.posix_io.is_open(fd) || !(0..OPEN_MAX).contains(&new_fd) {
+        return Err(EBADF);
+    }
+    if fd == new_fd {
+        return Ok(new_fd);
+    }
+    if new_fd < NORMAL_FILENO_BASE {
+        log!(
+            "TODO: dup2({:?}, {:?}) replacing a standard stream",
+            fd,
+            new_fd
+        );
+        return Err(EINVAL);
+    }
+
+    if env.libc_state.posix_io.is_open(new_fd) {
+        close(env, new_fd);
+    }
+    // This has to be done after closing, since the file descriptor being
+    // duplicated might have been a duplicate of the closed one.
+    let state = &mut env.libc_state.posix_io;
+    let original = state.resolve(fd);
+    let idx = fd_to_file_idx(new_fd);
+    if state.files.len() <= idx {
+        state.files.resize_with(idx + 1, || None);
+    }
+    state.files[idx] = Some(Descriptor::Duplicate(original));
+    Ok(new_fd)
+}
+
+fn pipe(env: &mut Environment, fds: MutPtr<FileDescriptor>) -> i32 {
+    if fds.is_null() {
+        set_errno(env, EFAULT);
+        return -1;
+    }
+    let buffer = Rc::new(RefCell::new(PipeBuffer {
+        data: VecDeque::new(),
+        read_end_open: true,
+        write_end_open: true,
+    }));
+    let state = &mut env.libc_state.posix_io;
+    let read_fd = state.add_descriptor(Descriptor::Pipe(PipeEnd {
+        buffer: buffer.clone(),
+        is_write_end: false,
+        non_blocking: false,
+    }));
+    let write_fd = state.add_descriptor(Descriptor::Pipe(PipeEnd {
+        buffer,
+        is_write_end: true,
+        non_blocking: false,
+    }));
+    env.mem.write(fds, read_fd);
+    env.mem.write(fds + 1, write_fd);
+    log_dbg!("pipe({:?}) => 0, fds: {:?}, {:?}", fds, read_fd, write_fd);
+    0
+}
+
+fn fsync(env: &mut Environment, fd: FileDescriptor) -> i32 {
+    let standard_stream = env.libc_state.posix_io.is_standard_stream(fd);
+    let res = match env.libc_state.posix_io.descriptor(fd) {
+        Some(Descriptor::File(file)) => file.file.sync_all().map_err(|_| EIO),
+        Some(Descriptor::Directory(_)) => Ok(()),
+        Some(_) => Err(ENOTSUP),
+        None if standard_stream => Ok(()),
+        None => Err(EBADF),
+    };
+    log_dbg!("fsync({:?}) => {:?}", fd, res);
+    finish(env, res.map(|()| 0))
+}
+
 fn rename(env: &mut Environment, old: ConstPtr<u8>, new: ConstPtr<u8>) -> i32 {
-    let old = env.mem.cstr_at_utf8(old).unwrap();
-    let new = env.mem.cstr_at_utf8(new).unwrap();
+    let res = rename_inner(env, old, new);
+    log_dbg!("rename({:?}, {:?}) => {:?}", old, new, res);
+    finish(env, res)
+}
+
+fn rename_inner(env: &mut Environment, old: ConstPtr<u8>, new: ConstPtr<u8>) -> Result<i32, i32> {
+    let old = read_path(env, old)?;
+    let new = read_path(env, new)?;
     log_dbg!("rename('{}', '{}')", old, new);
-    match env.fs.rename(GuestPath::new(&old), GuestPath::new(&new)) {
-        Ok(_) => 0,
-        Err(_) => -1,
-    }
+    env.fs
+        .rename(GuestPath::new(&old), GuestPath::new(&new))
+        .map_err(errno_for_fs_error)?;
+    Ok(0)
+}
+
+fn unlink(env: &mut Environment, path: ConstPtr<u8>) -> i32 {
+    let res = read_path(env, path).and_then(|path_string| {
+        let guest_path = GuestPath::new(&path_string);
+        // Directories must be removed with rmdir().
+        if env.fs.is_dir(guest_path) {
+            return Err(EPERM);
+        }
+        env.fs.remove(guest_path).map_err(errno_for_fs_error)
+    });
+    log_dbg!("unlink({:?}) => {:?}", path, res);
+    finish(env, res.map(|()| 0))
+}
+
+fn rmdir(env: &mut Environment, path: ConstPtr<u8>) -> i32 {
+    let res = read_path(env, path).and_then(|path_string| {
+        let guest_path = GuestPath::new(&path_string);
+        // Files must be removed with unlink().
+        if env.fs.is_file(guest_path) {
+            return Err(ENOTDIR);
+        }
+        env.fs.remove(guest_path).map_err(errno_for_fs_error)
+    });
+    log_dbg!("rmdir({:?}) => {:?}", path, res);
+    finish(env, res.map(|()| 0))
 }
 
 pub fn getcwd(env: &mut Environment, buf_ptr: MutPtr<u8>, buf_size: GuestUSize) -> MutPtr<u8> {
     let working_directory = env.fs.working_directory();
     if !env.fs.is_dir(working_directory) {
-        // TODO: set errno to ENOENT
         log!(
             "Warning: getcwd({:?}, {:#x}) failed, returning NULL",
             buf_ptr,
             buf_size
         );
+        set_errno(env, ENOENT);
         return Ptr::null();
     }
 
@@ -410,12 +1138,12 @@ pub fn getcwd(env: &mut Environment, buf_ptr: MutPtr<u8>, buf_size: GuestUSize)
     let res_size: GuestUSize = u32::try_from(working_directory.len()).unwrap() + 1;
 
     if buf_size < res_size {
-        // TODO: set errno to EINVAL or ERANGE as appropriate
         log!(
             "Warning: getcwd({:?}, {:#x}) failed, returning NULL",
             buf_ptr,
             buf_size
         );
+        set_errno(env, if buf_size == 0 { EINVAL } else { ERANGE });
         return Ptr::null();
     }
 
@@ -435,8 +1163,14 @@ pub fn getcwd(env: &mut Environment, buf_ptr: MutPtr<u8>, buf_size: GuestUSize)
 }
 
 fn chdir(env: &mut Environment, path_ptr: ConstPtr<u8>) -> i32 {
-    let path = GuestPath::new(env.mem.cstr_at_utf8(path_ptr).unwrap());
-    match env.fs.change_working_directory(path) {
+    let path = match read_path(env, path_ptr) {
+        Ok(path) => path,
+        Err(errno) => {
+            set_errno(env, errno);
+            return -1;
+        }
+    };
+    match env.fs.change_working_directory(GuestPath::new(&path)) {
         Ok(new) => {
             log_dbg!(
                 "chdir({:?}) => 0, new working directory: {:?}",
@@ -445,25 +1179,254 @@ fn chdir(env: &mut Environment, path_ptr: ConstPtr<u8>) -> i32 {
             );
             0
         }
-        Err(()) => {
+        Err(err) => {
             log!("Warning: chdir({:?}) failed, could not change working directory to {:?}, returning -1", path_ptr, path);
-            // TODO: set errno
+            set_errno(env, errno_for_fs_error(err));
             -1
         }
     }
 }
-// TODO: fchdir(), once open() on a directory is supported.
 
-fn flock(_env: &mut Environment, fd: FileDescriptor, operation: FLockFlag) -> i32 {
-    log!("TODO: flock({:?}, {:?})", fd, operation);
-    0
+fn fchdir(env: &mut Environment, fd: FileDescriptor) -> i32 {
+    let standard_stream = env.libc_state.posix_io.is_standard_stream(fd);
+    let res = match env.libc_state.posix_io.descriptor(fd) {
+        Some(Descriptor::Directory(dir)) => env
+            .fs
+            .change_working_directory(&dir.path)
+            .map(|_| 0)
+            .map_err(errno_for_fs_error),
+        Some(_) => Err(ENOTDIR),
+        None if standard_stream => Err(ENOTDIR),
+        None => Err(EBADF),
+    };
+    log_dbg!("fchdir({:?}) => {:?}", fd, res);
+    finish(env, res)
+}
+
+fn flock(env: &mut Environment, fd: FileDescriptor, operation: FLockFlag) -> i32 {
+    let res = flock_inner(env, fd, operation);
+    log_dbg!("flock({:?}, {:#x}) => {:?}", fd, operation, res);
+    finish(env, res)
+}
+
+fn flock_inner(
+    env: &mut Environment,
+    fd: FileDescriptor,
+    operation: FLockFlag,
+) -> Result<i32, i32> {
+    let non_blocking = operation & LOCK_NB != 0;
+    let operation = operation & !LOCK_NB;
+    if !matches!(operation, LOCK_SH | LOCK_EX | LOCK_UN) {
+        return Err(EINVAL);
+    }
+
+    // Locks only matter between different open files, but touchHLE only
+    // simulates a single process, so they're only useful between threads.
+    loop {
+        // The file might have been closed by another thread while waiting.
+        let state = &mut env.libc_state.posix_io;
+        let fd = state.resolve(fd);
+        let standard_stream = state.is_standard_stream(fd);
+        let (path, lock) = match state.descriptor(fd) {
+            Some(Descriptor::File(file)) => (file.path.clone(), file.lock),
+            Some(Descriptor::Directory(dir)) => (dir.path.clone(), dir.lock),
+            Some(_) => return Err(ENOTSUP),
+            None if standard_stream => return Err(ENOTSUP),
+            None => return Err(EBADF),
+        };
+        let new_lock = match operation {
+            LOCK_UN => None,
+            _ => Some(operation),
+        };
+        let conflict = new_lock.is_some()
+            && state.files.iter().enumerate().any(|(idx, other)| {
+                let (other_path, other_lock) = match other {
+                    Some(Descriptor::File(file)) => (&file.path, file.lock),
+                    Some(Descriptor::Directory(dir)) => (&dir.path, dir.lock),
+                    _ => return false,
+                };
+                file_idx_to_fd(idx) != fd
+                    && other_path.as_str() == path.as_str()
+                    && other_lock
+                        .is_some_and(|other_lock| other_lock == LOCK_EX || operation == LOCK_EX)
+            });
+        if !conflict {
+            match state.descriptor(fd) {
+                Some(Descriptor::File(file)) => file.lock = new_lock,
+                Some(Descriptor::Directory(dir)) => dir.lock = new_lock,
+                _ => unreachable!(),
+            }
+            return Ok(0);
+        }
+        if non_blocking {
+            return Err(EAGAIN);
+        }
+        // Converting a lock doesn't happen atomically, so other threads can
+        // take the lock in the meantime.
+        if lock.is_some() {
+            match state.descriptor(fd) {
+                Some(Descriptor::File(file)) => file.lock = None,
+                Some(Descriptor::Directory(dir)) => dir.lock = None,
+                _ => unreachable!(),
+            }
+        }
+        env.sleep(POLL_INTERVAL, false);
+    }
 }
 
 fn ftruncate(env: &mut Environment, fd: FileDescriptor, len: off_t) -> i32 {
-    let file = env.libc_state.posix_io.file_for_fd(fd).unwrap();
-    match file.file.set_len(len as u64) {
-        Ok(()) => 0,
-        Err(_) => -1, // TODO: set errno
+    let standard_stream = env.libc_state.posix_io.is_standard_stream(fd);
+    let res = match env.libc_state.posix_io.descriptor(fd) {
+        Some(Descriptor::File(file)) if file.writable() && len >= 0 => file
+            .file
+            .set_len(len as u64)
+            .map(|()| 0)
+            .map_err(|e| errno_for_io_error(&e)),
+        Some(_) => Err(EINVAL),
+        None if standard_stream => Err(EINVAL),
+        None => Err(EBADF),
+    };
+    log_dbg!("ftruncate({:?}, {:#x}) => {:?}", fd, len, res);
+    finish(env, res)
+}
+
+const F_GETFD: i32 = 1;
+const F_SETFD: i32 = 2;
+const F_GETFL: i32 = 3;
+const F_SETFL: i32 = 4;
+
+fn fcntl(env: &mut Environment, fd: FileDescriptor, cmd: i32, args: DotDotDot) -> i32 {
+    if !env.libc_state.posix_io.is_open(fd) {
+        log_dbg!("fcntl({:?}, {:#x}) => -1", fd, cmd);
+        set_errno(env, EBADF);
+        return -1;
+    }
+    let res = match cmd {
+        // FD_CLOEXEC is irrelevant because there's no exec().
+        F_GETFD => 0,
+        F_SETFD => 0,
+        F_GETFL => match env.libc_state.posix_io.descriptor(fd) {
+            Some(Descriptor::File(file)) => file.flags,
+            Some(Descriptor::Directory(_)) => O_RDONLY,
+            Some(Descriptor::Pipe(pipe)) => {
+                let access_mode = if pipe.is_write_end {
+                    O_WRONLY
+                } else {
+                    O_RDONLY
+                };
+                if pipe.non_blocking {
+                    access_mode | O_NONBLOCK
+                } else {
+                    access_mode
+                }
+            }
+            Some(Descriptor::Socket(socket)) if socket.non_blocking => O_RDWR | O_NONBLOCK,
+            _ => O_RDWR,
+        },
+        F_SETFL => {
+            let flags: i32 = args.start().next(env);
+            let non_blocking = flags & O_NONBLOCK != 0;
+            match env.libc_state.posix_io.descriptor(fd) {
+                // O_NONBLOCK is ignored for files, see open_inner().
+                Some(Descriptor::File(file)) => {
+                    if (file.flags ^ flags) & O_APPEND != 0 {
+                        log!("TODO: changing O_APPEND for file {:?}", fd);
+                    }
+                    file.flags = (file.flags & !O_NONBLOCK) | (flags & O_NONBLOCK);
+                }
+                Some(Descriptor::Pipe(pipe)) => pipe.non_blocking = non_blocking,
+                Some(Descriptor::Socket(socket)) => socket.non_blocking = non_blocking,
+                _ => (),
+            }
+            0
+        }
+        _ => {
+            log!("TODO: fcntl({:?}, {:#x})", fd, cmd);
+            set_errno(env, EINVAL);
+            -1
+        }
+    };
+    log_dbg!("fcntl({:?}, {:#x}) => {:#x}", fd, cmd, res);
+    res
+}
+
+/// Helper for `select()`, `poll()` and [Environment::block_on_io]: check
+/// whether I/O on a file descriptor would block. Returns [None] if `fd` isn't
+/// open.
+pub fn readiness(env: &mut Environment, fd: FileDescriptor) -> Option<Readiness> {
+    let fd = env.libc_state.posix_io.resolve(fd);
+    match fd {
+        // TODO: stdin is never readable, since there's no way to provide input
+        STDIN_FILENO => return Some(Readiness::default()),
+        STDOUT_FILENO | STDERR_FILENO => {
+            return Some(Readiness {
+                writable: true,
+                ..Default::default()
+            })
+        }
+        _ if fd < 0 => return None,
+        _ => (),
+    }
+    match env.libc_state.posix_io.files.get_mut(fd_to_file_idx(fd))? {
+        // File I/O never blocks, see open_inner().
+        Some(Descriptor::File(_) | Descriptor::Directory(_)) => Some(Readiness {
+            readable: true,
+            writable: true,
+            ..Default::default()
+        }),
+        Some(Descriptor::Pipe(pipe)) => {
+            let buffer = pipe.buffer.borrow();
+            Some(if pipe.is_write_end {
+                Readiness {
+                    writable: true,
+                    error: !buffer.read_end_open,
+                    ..Default::default()
+                }
+            } else {
+                Readiness {
+                    readable: !buffer.data.is_empty() || !buffer.write_end_open,
+                    ..Default::default()
+                }
+            })
+        }
+        Some(Descriptor::Socket(socket)) => Some(socket.readiness()),
+        &mut Some(Descriptor::DnsService(service)) => Some(dnssd::readiness(env, service)),
+        Some(Descriptor::Duplicate(_)) => unreachable!(),
+        None => None,
+    }
+}
+
+/// For use by DNS-SD: get the file descriptor for a `DNSServiceRef`, giving it
+/// one if it doesn't have one yet.
+pub fn dns_service_fd(env: &mut Environment, service: DNSServiceRef) -> FileDescriptor {
+    let state = &mut env.libc_state.posix_io;
+    let existing = state
+        .files
+        .iter()
+        .position(|f| matches!(f, Some(Descriptor::DnsService(s)) if *s == service));
+    match existing {
+        Some(idx) => file_idx_to_fd(idx),
+        None => state.add_descriptor(Descriptor::DnsService(service)),
+    }
+}
+
+/// For use by DNS-SD: close the file descriptor for a `DNSServiceRef` that is
+/// being deallocated, if it has one.
+pub fn close_dns_service_fd(env: &mut Environment, service: DNSServiceRef) {
+    let state = &mut env.libc_state.posix_io;
+    while let Some(idx) = state
+        .files
+        .iter()
+        .position(|f| matches!(f, Some(Descriptor::DnsService(s)) if *s == service))
+    {
+        // Duplicates of the file descriptor are closed too.
+        let fd = file_idx_to_fd(idx);
+        state.files[idx] = None;
+        for file in state.files.iter_mut() {
+            if matches!(file, Some(Descriptor::Duplicate(original)) if *original == fd) {
+                *file = None;
+            }
+        }
     }
 }
 
@@ -473,9 +1436,17 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(write(_, _, _)),
     export_c_func!(lseek(_, _, _)),
     export_c_func!(close(_)),
+    export_c_func!(dup(_)),
+    export_c_func!(dup2(_, _)),
+    export_c_func!(pipe(_)),
+    export_c_func!(fsync(_)),
     export_c_func!(rename(_, _)),
+    export_c_func!(unlink(_)),
+    export_c_func!(rmdir(_)),
     export_c_func!(getcwd(_, _)),
     export_c_func!(chdir(_)),
+    export_c_func!(fchdir(_)),
     export_c_func!(flock(_, _)),
     export_c_func!(ftruncate(_, _)),
+    export_c_func!(fcntl(_, _, _)),
 ];
@@ -5,9 +5,10 @@
  */
 //! POSIX `sys/stat.h`
 
-use super::{off_t, FileDescriptor};
+use super::{finish, off_t, read_path, Descriptor, FileDescriptor};
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::fs::GuestPath;
+use crate::libc::errno::{errno_for_fs_error, EBADF};
 use crate::mem::{ConstPtr, MutVoidPtr};
 use crate::Environment;
 use std::io::{Seek, SeekFrom};
@@ -15,44 +16,66 @@ use std::io::{Seek, SeekFrom};
 #[allow(non_camel_case_types)]
 pub type mode_t = u16;
 
+pub const S_IFIFO: mode_t = 0o010000;
+pub const S_IFCHR: mode_t = 0o020000;
+pub const S_IFDIR: mode_t = 0o040000;
+pub const S_IFREG: mode_t = 0o100000;
+pub const S_IFSOCK: mode_t = 0o140000;
+pub const S_IWUSR: mode_t = 0o000200;
+
 fn mkdir(env: &mut Environment, path: ConstPtr<u8>, mode: mode_t) -> i32 {
     // TODO: respect the mode
-    match env
-        .fs
-        .create_dir(GuestPath::new(&env.mem.cstr_at_utf8(path).unwrap()))
-    {
-        Ok(()) => {
-            log_dbg!("mkdir({:?}, {:#x}) => 0", path, mode);
-            0
-        }
-        Err(()) => {
-            // TODO: set errno
-            log!(
-                "Warning: mkdir({:?}, {:#x}) failed, returning -1",
-                path,
-                mode,
-            );
-            -1
-        }
+    let res = read_path(env, path).and_then(|path_string| {
+        env.fs
+            .create_dir(GuestPath::new(&path_string))
+            .map_err(errno_for_fs_error)
+    });
+    match res {
+        Ok(()) => log_dbg!("mkdir({:?}, {:#x}) => 0", path, mode),
+        Err(errno) => log!(
+            "Warning: mkdir({:?}, {:#x}) failed with errno {}, returning -1",
+            path,
+            mode,
+            errno,
+        ),
     }
+    finish(env, res.map(|()| 0))
 }
 
 fn fstat(env: &mut Environment, fd: FileDescriptor, buf: MutVoidPtr) -> i32 {
-    // TODO: error handling for unknown fd?
-    let file = env.libc_state.posix_io.file_for_fd(fd).unwrap();
-
     log!("Warning: fstat() call, this function is mostly unimplemented");
     // FIXME: This implementation is highly incomplete. fstat() returns a huge
     // struct with many kinds of data in it. This code is assuming the caller
-    // only wants the file size.
+    // only wants the file type and size.
+    let st_mode_ptr = (buf + 0x4).cast::<mode_t>();
     let st_size_ptr = (buf + 0x3c).cast::<off_t>();
 
-    // TODO: Use the stream_len() method if that ever gets stabilized.
-    let old_pos = file.file.stream_position().unwrap();
-    let full_size = file.file.seek(SeekFrom::End(0)).unwrap();
-    file.file.seek(SeekFrom::Start(old_pos)).unwrap();
+    let state = &mut env.libc_state.posix_io;
+    let standard_stream = state.is_standard_stream(fd);
+    let (mode, size) = match state.descriptor(fd) {
+        Some(Descriptor::File(file)) => {
+            // TODO: Use the stream_len() method if that ever gets stabilized.
+            let old_pos = file.file.stream_position().unwrap();
+            let full_size = file.file.seek(SeekFrom::End(0)).unwrap();
+            file.file.seek(SeekFrom::Start(old_pos)).unwrap();
+
+            let (_, _, writeable, _) = env.fs.access(&file.path);
+            let permissions = if writeable { 0o644 } else { 0o444 };
+            (S_IFREG | permissions, full_size)
+        }
+        Some(Descriptor::Directory(_)) => (S_IFDIR | 0o755, 0),
+        Some(Descriptor::Pipe(pipe)) => (S_IFIFO | 0o600, pipe.buffer.borrow().data.len() as u64),
+        Some(Descriptor::Socket(_) | Descriptor::DnsService(_)) => (S_IFSOCK | 0o777, 0),
+        Some(Descriptor::Duplicate(_)) => unreachable!(),
+        None if standard_stream => (S_IFCHR | 0o620, 0),
+        None => {
+            log_dbg!("fstat({:?}, {:?}) => -1", fd, buf);
+            return finish(env, Err(EBADF));
+        }
+    };
 
-    env.mem.write(st_size_ptr, full_size.try_into().unwrap());
+    env.mem.write(st_mode_ptr, mode);
+    env.mem.write(st_size_ptr, size.try_into().unwrap());
 
     0 // success
 }
@@ -8,6 +8,7 @@
 use crate::abi::GuestFunction;
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::mem::{ConstVoidPtr, MutPtr, MutVoidPtr, Ptr};
+use crate::save_state::impl_SaveState;
 use crate::{Environment, ThreadId};
 use std::collections::HashMap;
 
@@ -18,6 +19,7 @@ pub struct State {
     /// the destructor pointer.
     keys: Vec<(HashMap<ThreadId, MutVoidPtr>, GuestFunction)>,
 }
+impl_SaveState!(State { keys });
 
 fn get_state(env: &mut Environment) -> &mut State {
     &mut env.libc_state.pthread.key
@@ -8,7 +8,9 @@
 use crate::abi::GuestFunction;
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::libc::errno::{EDEADLK, EINVAL};
+use crate::libc::signal;
 use crate::mem::{ConstPtr, MutPtr, MutVoidPtr, SafeRead};
+use crate::save_state::{impl_SaveState, SaveState, StateReader, StateWriter};
 use crate::{Environment, ThreadId};
 use std::collections::HashMap;
 
@@ -17,6 +19,10 @@ pub struct State {
     threads: HashMap<pthread_t, ThreadHostObject>,
     main_thread_object_created: bool,
 }
+impl_SaveState!(State {
+    threads,
+    main_thread_object_created,
+});
 impl State {
     fn get(env: &mut Environment) -> &mut Self {
         &mut env.libc_state.pthread.thread
@@ -57,6 +63,24 @@ struct ThreadHostObject {
     joined_by: Option<ThreadId>,
     _attr: pthread_attr_t,
 }
+impl SaveState for ThreadHostObject {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&self.thread_id);
+        writer.write(&self.joined_by);
+        // The rest of the attributes are only checked when creating a thread.
+        writer.write(&{ self._attr.detachstate });
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        Ok(ThreadHostObject {
+            thread_id: reader.read()?,
+            joined_by: reader.read()?,
+            _attr: pthread_attr_t {
+                detachstate: reader.read()?,
+                ..DEFAULT_ATTR
+            },
+        })
+    }
+}
 
 /// Arbitrarily-chosen magic number for `pthread_attr_t` (not Apple's).
 const MAGIC_ATTR: u32 = u32::from_be_bytes(*b"ThAt");
@@ -112,6 +136,7 @@ pub fn pthread_create(
     };
 
     let thread_id = env.new_thread(start_routine, user_data);
+    signal::init_new_thread(env, thread_id);
 
     let opaque = env.mem.alloc_and_write(OpaqueThread {
         magic: MAGIC_THREAD,
@@ -226,6 +251,14 @@ fn pthread_testcancel(_env: &mut Environment) {
     log!("TODO: pthread_testcancel()");
 }
 
+/// Get the touchHLE thread ID for a `pthread_t`, if it's valid.
+pub fn thread_id_for_pthread(env: &mut Environment, thread: pthread_t) -> Option<ThreadId> {
+    State::get(env)
+        .threads
+        .get(&thread)
+        .map(|host_object| host_object.thread_id)
+}
+
 type mach_port_t = u32;
 
 /// Undocumented Darwin function that returns a `mach_port_t`, which in practice
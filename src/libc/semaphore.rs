@@ -9,6 +9,7 @@ use crate::dyld::{export_c_func, FunctionExports};
 use crate::libc::posix_io::stat::mode_t;
 use crate::libc::posix_io::{O_CREAT, O_EXCL};
 use crate::mem::{ConstPtr, MutPtr};
+use crate::save_state::{impl_SaveState, SaveState, StateReader, StateWriter};
 use crate::{Environment, ThreadId};
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
@@ -22,6 +23,64 @@ pub struct State {
     named_semaphores: HashMap<String, Rc<RefCell<SemaphoreHostObject>>>,
     pub open_semaphores: HashMap<MutPtr<sem_t>, Rc<RefCell<SemaphoreHostObject>>>,
 }
+impl SaveState for State {
+    fn save(&self, writer: &mut StateWriter) {
+        // Named semaphores can be in both maps, so each semaphore is written
+        // once and the maps refer to it by index.
+        let mut semaphores: Vec<Rc<RefCell<SemaphoreHostObject>>> = Vec::new();
+        let mut index_of = |semaphore: &Rc<RefCell<SemaphoreHostObject>>| -> u32 {
+            let index = semaphores
+                .iter()
+                .position(|other| Rc::ptr_eq(other, semaphore))
+                .unwrap_or_else(|| {
+                    semaphores.push(semaphore.clone());
+                    semaphores.len() - 1
+                });
+            index.try_into().unwrap()
+        };
+        let named_semaphores: Vec<(String, u32)> = self
+            .named_semaphores
+            .iter()
+            .map(|(name, semaphore)| (name.clone(), index_of(semaphore)))
+            .collect();
+        let open_semaphores: Vec<(MutPtr<sem_t>, u32)> = self
+            .open_semaphores
+            .iter()
+            .map(|(&sem, semaphore)| (sem, index_of(semaphore)))
+            .collect();
+
+        writer.write(&semaphores.len());
+        for semaphore in &semaphores {
+            writer.write(&*semaphore.borrow());
+        }
+        writer.write(&named_semaphores);
+        writer.write(&open_semaphores);
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        let count: usize = reader.read()?;
+        let semaphores = (0..count)
+            .map(|_| Ok(Rc::new(RefCell::new(reader.read()?))))
+            .collect::<Result<Vec<_>, String>>()?;
+        let get = |index: u32| {
+            semaphores
+                .get(index as usize)
+                .cloned()
+                .ok_or_else(|| "Save state has an invalid semaphore".to_string())
+        };
+        let named_semaphores: Vec<(String, u32)> = reader.read()?;
+        let open_semaphores: Vec<(MutPtr<sem_t>, u32)> = reader.read()?;
+        Ok(State {
+            named_semaphores: named_semaphores
+                .into_iter()
+                .map(|(name, index)| Ok((name, get(index)?)))
+                .collect::<Result<_, String>>()?,
+            open_semaphores: open_semaphores
+                .into_iter()
+                .map(|(sem, index)| Ok((sem, get(index)?)))
+                .collect::<Result<_, String>>()?,
+        })
+    }
+}
 impl State {
     fn get(env: &Environment) -> &Self {
         &env.libc_state.semaphore
@@ -39,6 +98,11 @@ pub struct SemaphoreHostObject {
     pub waiting: HashSet<ThreadId>,
     guest_sem: Option<MutPtr<sem_t>>,
 }
+impl_SaveState!(SemaphoreHostObject {
+    value,
+    waiting,
+    guest_sem,
+});
 
 fn sem_open(
     env: &mut Environment,
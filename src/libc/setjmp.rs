@@ -5,26 +5,160 @@
  */
 //! `setjmp.h`.
 //!
-//! We don't have a real implementation for this right now. It could be quite
-//! tricky to write one, considering that we would need to unwind through host
-//! code, and somehow do so selectively since we have a mix of stack frames from
-//! different guest threads. For the moment, we simply pray the app never throws
-//! exceptions.
+//! `setjmp()` saves the callee-saved registers, the stack pointer and the
+//! return address in a buffer, and `longjmp()` restores them, so that it
+//! appears to return from `setjmp()` a second time. The buffer has the same
+//! layout as on iPhone OS.
+//!
+//! This works because host functions are called via stubs that return to the
+//! address in LR once the host function is done (see [crate::dyld]), and
+//! because the return value is put in r0, which is also where `setjmp()`'s
+//! caller expects it.
+//!
+//! Jumping out of guest code that was called by host code (e.g. a comparison
+//! function passed to `qsort()`) isn't possible though, because the host code's
+//! state is on the host stack and can't be unwound. touchHLE panics if an app
+//! tries to do that.
 //!
 //! Note that `setjmp` and `longjmp` are defined as macros in the C standard,
-//! but it seems like the implementation of these on iPhone OS uses real
-//! functions, at least for the former.
+//! but the implementation of these on iPhone OS uses real functions.
 
+use crate::abi::FRAME_POINTER;
+use crate::cpu::Cpu;
 use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::signal;
+use crate::mem::{ConstPtr, MutPtr};
 use crate::Environment;
 
-/// The signature of this is incomplete because it's a stub (see module docs).
-fn setjmp(env: &mut Environment) -> i32 {
-    log_dbg!(
-        "TODO: setjmp() at {:#x}",
-        env.cpu.regs()[crate::cpu::Cpu::LR]
-    );
+/// `jmp_buf` and `sigjmp_buf` are arrays of this type.
+type JmpBufWord = u32;
+
+/// Offsets (in words) of the parts of `jmp_buf`, from Apple's `_setjmp.h`.
+/// The saved core registers are at the start, in the order of [SAVED_REGS].
+const JMP_SP: usize = 7;
+const JMP_LR: usize = 8;
+const JMP_VFP: usize = 9;
+const JMP_SIG: usize = 26;
+const JMP_SIGFLAG: usize = 27;
+
+/// The core registers saved by `setjmp()`. r9 isn't callee-saved on iPhone OS.
+const SAVED_REGS: [usize; 9] = [4, 5, 6, 7, 8, 10, 11, Cpu::SP, Cpu::LR];
+
+/// The VFP registers saved by `setjmp()` are d8-d15, which are s16-s31.
+const SAVED_EXT_REGS: std::ops::Range<usize> = 16..32;
+
+fn save_registers(env: &mut Environment, buf: MutPtr<JmpBufWord>) {
+    for (i, &reg) in SAVED_REGS.iter().enumerate() {
+        env.mem.write(buf + i as u32, env.cpu.regs()[reg]);
+    }
+    for (i, ext_reg) in SAVED_EXT_REGS.enumerate() {
+        env.mem
+            .write(buf + (JMP_VFP + i) as u32, env.cpu.ext_regs()[ext_reg]);
+    }
+}
+
+/// Restore the registers saved by [save_registers] and return the value that
+/// `setjmp()` should appear to return.
+fn restore_registers(env: &mut Environment, buf: ConstPtr<JmpBufWord>, val: i32) -> i32 {
+    let new_sp = env.mem.read(buf + JMP_SP as u32);
+    if jump_crosses_host_code(env, new_sp) {
+        panic!(
+            "longjmp() to {:#x} would skip over host code, which isn't supported",
+            env.mem.read(buf + JMP_LR as u32)
+        );
+    }
+
+    for (i, &reg) in SAVED_REGS.iter().enumerate() {
+        env.cpu.regs_mut()[reg] = env.mem.read(buf + i as u32);
+    }
+    for (i, ext_reg) in SAVED_EXT_REGS.enumerate() {
+        env.cpu.ext_regs_mut()[ext_reg] = env.mem.read(buf + (JMP_VFP + i) as u32);
+    }
+
+    // longjmp(buf, 0) makes setjmp() return 1, so it can't be mistaken for
+    // the original return.
+    if val == 0 {
+        1
+    } else {
+        val
+    }
+}
+
+/// Check whether jumping to a stack frame with the stack pointer `new_sp`
+/// would skip over a call from host code to guest code, by following the chain
+/// of frame records that would be discarded.
+fn jump_crosses_host_code(env: &Environment, new_sp: u32) -> bool {
+    let return_to_host_routine = env.dyld.return_to_host_routine().addr_with_thumb_bit();
+    let discarded_stack = env.cpu.regs()[Cpu::SP]..new_sp;
+    let mut fp = env.cpu.regs()[FRAME_POINTER];
+    while fp % 4 == 0 && discarded_stack.contains(&fp) {
+        let next_fp: u32 = env.mem.read(ConstPtr::from_bits(fp));
+        let lr: u32 = env.mem.read(ConstPtr::from_bits(fp + 4));
+        if lr == return_to_host_routine {
+            return true;
+        }
+        if next_fp <= fp {
+            break;
+        }
+        fp = next_fp;
+    }
+    false
+}
+
+fn setjmp(env: &mut Environment, buf: MutPtr<JmpBufWord>) -> i32 {
+    let mask = signal::current_mask(env);
+    env.mem.write(buf + JMP_SIG as u32, mask);
+    save_registers(env, buf);
+    0 // no longjmp() was performed
+}
+
+/// Like [setjmp], but doesn't save the signal mask.
+fn _setjmp(env: &mut Environment, buf: MutPtr<JmpBufWord>) -> i32 {
+    save_registers(env, buf);
     0 // no longjmp() was performed
 }
 
-pub const FUNCTIONS: FunctionExports = &[export_c_func!(setjmp())];
+/// Like [setjmp] if `savemask` is non-zero, otherwise like [_setjmp].
+fn sigsetjmp(env: &mut Environment, buf: MutPtr<JmpBufWord>, savemask: i32) -> i32 {
+    env.mem.write(buf + JMP_SIGFLAG as u32, savemask as u32);
+    if savemask != 0 {
+        setjmp(env, buf)
+    } else {
+        _setjmp(env, buf)
+    }
+}
+
+/// This is `void` in C, but the return value is needed to make the matching
+/// `setjmp()` call return the right value.
+fn longjmp(env: &mut Environment, buf: ConstPtr<JmpBufWord>, val: i32) -> i32 {
+    let result = restore_registers(env, buf, val);
+    let mask = env.mem.read(buf + JMP_SIG as u32);
+    signal::set_current_mask(env, mask);
+    // Signals that were blocked until now, e.g. because the jump is out of a
+    // signal handler, are delivered right away.
+    signal::deliver_pending_from_host(env, result)
+}
+
+/// Like [longjmp], but doesn't restore the signal mask.
+fn _longjmp(env: &mut Environment, buf: ConstPtr<JmpBufWord>, val: i32) -> i32 {
+    restore_registers(env, buf, val)
+}
+
+/// Like [longjmp] if the matching [sigsetjmp] call saved the signal mask,
+/// otherwise like [_longjmp].
+fn siglongjmp(env: &mut Environment, buf: ConstPtr<JmpBufWord>, val: i32) -> i32 {
+    if env.mem.read(buf + JMP_SIGFLAG as u32) != 0 {
+        longjmp(env, buf, val)
+    } else {
+        _longjmp(env, buf, val)
+    }
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(setjmp(_)),
+    export_c_func!(_setjmp(_)),
+    export_c_func!(sigsetjmp(_, _)),
+    export_c_func!(longjmp(_, _)),
+    export_c_func!(_longjmp(_, _)),
+    export_c_func!(siglongjmp(_, _)),
+];
@@ -3,23 +3,923 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+//! `signal.h`
+//!
+//! touchHLE only simulates a single process, so signals can only come from the
+//! app itself (`raise()`, `kill()` with its own PID, `pthread_kill()`,
+//! `abort()`), from the CPU making an invalid memory access, which becomes a
+//! `SIGSEGV` or `SIGBUS` like on a real device, or from a debugger continuing
+//! with a signal (see [crate::gdb]).
+//!
+//! Handlers are run the way the kernel would run them: a signal frame
+//! containing the `siginfo_t`, the `ucontext_t` and the interrupted thread's
+//! registers is pushed onto the thread's stack (or its alternate signal stack),
+//! and the handler is called with LR pointing at
+//! [crate::dyld::Dyld::signal_return_routine], which restores the registers
+//! from the frame. Since no host code is involved, a handler can also leave
+//! with `siglongjmp()`, which is what crash handlers often do.
+//!
+//! When a host function like `raise()` sends a signal to the current thread,
+//! the handler runs as soon as the host function returns, and the frame is set
+//! up so that the handler returns to the host function's caller.
 
-use crate::dyld::FunctionExports;
-use crate::environment::Environment;
-use crate::export_c_func;
-use crate::mem::{ConstVoidPtr, MutVoidPtr, Ptr};
+use crate::abi::GuestFunction;
+use crate::cpu::Cpu;
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{set_errno, EINVAL, ENOMEM, EPERM, ESRCH};
+use crate::libc::pthread::thread::{pthread_t, thread_id_for_pthread};
+use crate::libc::unistd::{getpid, pid_t};
+use crate::mem::{ConstPtr, GuestUSize, MemoryFault, MutPtr, MutVoidPtr, Ptr, SafeRead};
+use crate::save_state::{impl_SaveState, SaveState, StateReader, StateWriter};
+use crate::{Environment, ThreadId};
+use std::collections::HashMap;
 
-fn sigaction(_env: &mut Environment, signum: i32, act: ConstVoidPtr, oldact: MutVoidPtr) -> i32 {
-    log!("TODO: sigaction({:?}, {:?}, {:?})", signum, act, oldact);
-    0
+#[allow(non_camel_case_types)]
+pub type sigset_t = u32;
+
+const NSIG: i32 = 32;
+
+const SIGABRT: i32 = 6;
+const SIGKILL: i32 = 9;
+const SIGBUS: i32 = 10;
+const SIGSEGV: i32 = 11;
+const SIGPIPE: i32 = 13;
+const SIGURG: i32 = 16;
+const SIGSTOP: i32 = 17;
+const SIGTSTP: i32 = 18;
+const SIGCONT: i32 = 19;
+const SIGCHLD: i32 = 20;
+const SIGTTIN: i32 = 21;
+const SIGTTOU: i32 = 22;
+const SIGIO: i32 = 23;
+const SIGWINCH: i32 = 28;
+const SIGINFO: i32 = 29;
+
+/// Names of the signals, for logging. Indexed by signal number.
+#[rustfmt::skip]
+const SIGNAL_NAMES: [&str; NSIG as usize] = [
+    "", "SIGHUP", "SIGINT", "SIGQUIT", "SIGILL", "SIGTRAP", "SIGABRT", "SIGEMT", "SIGFPE",
+    "SIGKILL", "SIGBUS", "SIGSEGV", "SIGSYS", "SIGPIPE", "SIGALRM", "SIGTERM", "SIGURG",
+    "SIGSTOP", "SIGTSTP", "SIGCONT", "SIGCHLD", "SIGTTIN", "SIGTTOU", "SIGIO", "SIGXCPU",
+    "SIGXFSZ", "SIGVTALRM", "SIGPROF", "SIGWINCH", "SIGINFO", "SIGUSR1", "SIGUSR2",
+];
+
+/// Special values of `sa_handler`.
+const SIG_DFL: GuestUSize = 0;
+const SIG_IGN: GuestUSize = 1;
+/// Returned by `signal()` on failure.
+const SIG_ERR: GuestUSize = -1i32 as GuestUSize;
+
+const SA_ONSTACK: i32 = 0x1;
+const SA_RESTART: i32 = 0x2;
+const SA_RESETHAND: i32 = 0x4;
+const SA_NODEFER: i32 = 0x10;
+
+const SIG_BLOCK: i32 = 1;
+const SIG_UNBLOCK: i32 = 2;
+const SIG_SETMASK: i32 = 3;
+
+const SS_ONSTACK: i32 = 0x1;
+const SS_DISABLE: i32 = 0x4;
+const MINSIGSTKSZ: GuestUSize = 32768;
+
+/// `si_code` for signals sent with `kill()` and friends.
+const SI_USER: i32 = 0x10001;
+/// `si_code` for a `SIGSEGV` caused by accessing unmapped memory.
+const SEGV_MAPERR: i32 = 1;
+/// `si_code` for a `SIGBUS` caused by an access the page protection forbids.
+const BUS_ADRERR: i32 = 2;
+
+const fn sig_bit(sig: i32) -> sigset_t {
+    1 << (sig - 1)
+}
+
+/// `SIGKILL` and `SIGSTOP` can't be caught, blocked or ignored.
+const UNBLOCKABLE: sigset_t = sig_bit(SIGKILL) | sig_bit(SIGSTOP);
+
+fn is_valid_signal(sig: i32) -> bool {
+    (1..NSIG).contains(&sig)
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C, packed)]
+struct sigaction {
+    /// `sa_handler`, or `sa_sigaction` if `SA_SIGINFO` is set. Both are called
+    /// with the same arguments anyway.
+    sa_handler: GuestUSize,
+    sa_mask: sigset_t,
+    sa_flags: i32,
+}
+unsafe impl SafeRead for sigaction {}
+// impl_SaveState! can't be used with packed structs.
+impl SaveState for sigaction {
+    fn save(&self, writer: &mut StateWriter) {
+        let sigaction {
+            sa_handler,
+            sa_mask,
+            sa_flags,
+        } = *self;
+        writer.write(&sa_handler);
+        writer.write(&sa_mask);
+        writer.write(&sa_flags);
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        Ok(sigaction {
+            sa_handler: reader.read()?,
+            sa_mask: reader.read()?,
+            sa_flags: reader.read()?,
+        })
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+struct stack_t {
+    ss_sp: MutVoidPtr,
+    ss_size: GuestUSize,
+    ss_flags: i32,
+}
+unsafe impl SafeRead for stack_t {}
+impl SaveState for stack_t {
+    fn save(&self, writer: &mut StateWriter) {
+        let stack_t {
+            ss_sp,
+            ss_size,
+            ss_flags,
+        } = *self;
+        writer.write(&ss_sp);
+        writer.write(&ss_size);
+        writer.write(&ss_flags);
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        Ok(stack_t {
+            ss_sp: reader.read()?,
+            ss_size: reader.read()?,
+            ss_flags: reader.read()?,
+        })
+    }
+}
+impl stack_t {
+    fn contains(&self, sp: u32) -> bool {
+        let base = self.ss_sp.to_bits();
+        base < sp && sp <= base + self.ss_size
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct siginfo_t {
+    si_signo: i32,
+    si_errno: i32,
+    si_code: i32,
+    si_pid: pid_t,
+    si_uid: u32,
+    si_status: i32,
+    si_addr: MutVoidPtr,
+    si_value: u32,
+    si_band: i32,
+    _pad: [u32; 7],
+}
+unsafe impl SafeRead for siginfo_t {}
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct ucontext_t {
+    uc_onstack: i32,
+    uc_sigmask: sigset_t,
+    uc_stack: stack_t,
+    uc_link: MutPtr<ucontext_t>,
+    uc_mcsize: GuestUSize,
+    uc_mcontext: MutPtr<mcontext>,
+}
+unsafe impl SafeRead for ucontext_t {}
+
+/// `struct __darwin_mcontext`: the saved state of the interrupted thread.
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct mcontext {
+    es: arm_exception_state,
+    ss: arm_thread_state,
+    fs: arm_vfp_state,
+}
+unsafe impl SafeRead for mcontext {}
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct arm_exception_state {
+    exception: u32,
+    fsr: u32,
+    /// Fault address.
+    far: u32,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct arm_thread_state {
+    /// r0 to r12.
+    r: [u32; 13],
+    sp: u32,
+    lr: u32,
+    pc: u32,
+    cpsr: u32,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct arm_vfp_state {
+    r: [u32; 64],
+    fpscr: u32,
+}
+
+/// What gets pushed onto the stack when a handler is run. The `ucontext_t` is
+/// at the lowest address, so that [return_from_handler] can find it at the
+/// stack pointer.
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct SignalFrame {
+    ucontext: ucontext_t,
+    mcontext: mcontext,
+    siginfo: siginfo_t,
+}
+unsafe impl SafeRead for SignalFrame {}
+
+const MCONTEXT_OFFSET: GuestUSize = std::mem::size_of::<ucontext_t>() as GuestUSize;
+const SIGINFO_OFFSET: GuestUSize = MCONTEXT_OFFSET + std::mem::size_of::<mcontext>() as GuestUSize;
+
+#[derive(Default)]
+pub struct State {
+    /// Indexed by signal number. Entry 0 is unused.
+    actions: [sigaction; NSIG as usize],
+    /// Signal masks of threads. Threads without an entry block nothing.
+    masks: HashMap<ThreadId, sigset_t>,
+    /// Signals waiting to be delivered to each thread. Threads without an
+    /// entry have none.
+    pending: HashMap<ThreadId, sigset_t>,
+    /// Alternate signal stacks set with `sigaltstack()`.
+    alt_stacks: HashMap<ThreadId, stack_t>,
+    /// Set once `abort()` has run the app's `SIGABRT` handler, since the app
+    /// must be terminated if the handler returns.
+    aborting: bool,
+}
+impl_SaveState!(State {
+    actions,
+    masks,
+    pending,
+    alt_stacks,
+    aborting,
+});
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.libc_state.signal
+    }
+
+    fn mask(&self, thread: ThreadId) -> sigset_t {
+        self.masks.get(&thread).copied().unwrap_or(0)
+    }
+
+    fn set_mask(&mut self, thread: ThreadId, mask: sigset_t) {
+        self.masks.insert(thread, mask & !UNBLOCKABLE);
+    }
+
+    fn add_pending(&mut self, thread: ThreadId, sig: i32) {
+        *self.pending.entry(thread).or_insert(0) |= sig_bit(sig);
+    }
+}
+
+/// Get the signal mask of the current thread, e.g. for `setjmp()`.
+pub fn current_mask(env: &mut Environment) -> sigset_t {
+    let thread = env.current_thread;
+    State::get(env).mask(thread)
+}
+
+/// Set the signal mask of the current thread, e.g. for `longjmp()`.
+pub fn set_current_mask(env: &mut Environment, mask: sigset_t) {
+    let thread = env.current_thread;
+    State::get(env).set_mask(thread, mask);
+}
+
+/// A new thread inherits the signal mask of the thread that created it.
+pub fn init_new_thread(env: &mut Environment, new_thread: ThreadId) {
+    let mask = current_mask(env);
+    State::get(env).set_mask(new_thread, mask);
+}
+
+enum Disposition {
+    Ignore,
+    Terminate,
+    Handler(sigaction),
+}
+
+fn disposition(env: &mut Environment, sig: i32) -> Disposition {
+    let action = State::get(env).actions[sig as usize];
+    match action.sa_handler {
+        SIG_DFL if default_is_ignore(sig) => Disposition::Ignore,
+        SIG_DFL => Disposition::Terminate,
+        SIG_IGN => Disposition::Ignore,
+        _ => Disposition::Handler(action),
+    }
+}
+
+/// Whether the default action for a signal is to do nothing. This includes
+/// the signals that would stop the process, since touchHLE can't do that.
+fn default_is_ignore(sig: i32) -> bool {
+    [
+        SIGURG, SIGCONT, SIGCHLD, SIGIO, SIGWINCH, SIGINFO, SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU,
+    ]
+    .contains(&sig)
+}
+
+/// The default action for most signals: terminate the app.
+fn terminate(sig: i32) -> ! {
+    panic!(
+        "App was terminated by signal {} ({})",
+        sig, SIGNAL_NAMES[sig as usize]
+    );
+}
+
+/// Take the lowest-numbered signal that is pending for the current thread and
+/// not blocked by it, if there is one.
+fn take_deliverable_signal(env: &mut Environment) -> Option<i32> {
+    let thread = env.current_thread;
+    let state = State::get(env);
+    let pending = state.pending.get(&thread).copied().unwrap_or(0);
+    let deliverable = pending & !state.mask(thread);
+    if deliverable == 0 {
+        return None;
+    }
+    let sig = deliverable.trailing_zeros() as i32 + 1;
+    if pending == sig_bit(sig) {
+        state.pending.remove(&thread);
+    } else {
+        state.pending.insert(thread, pending & !sig_bit(sig));
+    }
+    Some(sig)
+}
+
+fn save_context(env: &Environment) -> mcontext {
+    let regs = env.cpu.regs();
+    let mut r = [0; 13];
+    r.copy_from_slice(&regs[..13]);
+    mcontext {
+        es: arm_exception_state {
+            exception: 0,
+            fsr: 0,
+            far: 0,
+        },
+        ss: arm_thread_state {
+            r,
+            sp: regs[Cpu::SP],
+            lr: regs[Cpu::LR],
+            pc: regs[Cpu::PC],
+            cpsr: env.cpu.cpsr(),
+        },
+        fs: arm_vfp_state {
+            r: *env.cpu.ext_regs(),
+            fpscr: env.cpu.fpscr(),
+        },
+    }
+}
+
+fn restore_context(env: &mut Environment, context: mcontext) {
+    let mcontext { ss, fs, .. } = context;
+    let r = ss.r;
+    let regs = env.cpu.regs_mut();
+    regs[..13].copy_from_slice(&r);
+    regs[Cpu::SP] = ss.sp;
+    regs[Cpu::LR] = ss.lr;
+    regs[Cpu::PC] = ss.pc;
+    // The handler may have changed the flags, but not the processor mode.
+    env.cpu
+        .set_cpsr((ss.cpsr & !0x1f) | (env.cpu.cpsr() & 0x1f));
+    *env.cpu.ext_regs_mut() = fs.r;
+    env.cpu.set_fpscr(fs.fpscr);
+}
+
+/// Push a signal frame for `context` onto the current thread's stack and make
+/// the CPU run the handler next.
+fn push_frame(
+    env: &mut Environment,
+    sig: i32,
+    context: mcontext,
+    si_code: i32,
+    si_addr: u32,
+    action: sigaction,
+) {
+    let thread = env.current_thread;
+    let old_mask = State::get(env).mask(thread);
+
+    let sp = context.ss.sp;
+    let alt_stack = State::get(env).alt_stacks.get(&thread).copied();
+    let on_alt_stack = alt_stack.is_some_and(|stack| stack.contains(sp));
+    let (frame_top, uc_stack) = match alt_stack {
+        Some(stack) if action.sa_flags & SA_ONSTACK != 0 && !on_alt_stack => {
+            (stack.ss_sp.to_bits() + stack.ss_size, stack)
+        }
+        _ => (
+            sp,
+            alt_stack.unwrap_or(stack_t {
+                ss_sp: Ptr::null(),
+                ss_size: 0,
+                ss_flags: SS_DISABLE,
+            }),
+        ),
+    };
+    let si_pid = if si_code == SI_USER { getpid(env) } else { 0 };
+    let frame_size = std::mem::size_of::<SignalFrame>() as GuestUSize;
+    let frame_ptr: MutPtr<SignalFrame> = Ptr::from_bits((frame_top - frame_size) & !7);
+    let ucontext_ptr: MutPtr<ucontext_t> = frame_ptr.cast();
+    let mcontext_ptr: MutPtr<mcontext> = Ptr::from_bits(frame_ptr.to_bits() + MCONTEXT_OFFSET);
+    let siginfo_ptr: MutPtr<siginfo_t> = Ptr::from_bits(frame_ptr.to_bits() + SIGINFO_OFFSET);
+
+    env.mem.write(
+        frame_ptr,
+        SignalFrame {
+            ucontext: ucontext_t {
+                uc_onstack: on_alt_stack as i32,
+                uc_sigmask: old_mask,
+                uc_stack,
+                uc_link: Ptr::null(),
+                uc_mcsize: std::mem::size_of::<mcontext>() as GuestUSize,
+                uc_mcontext: mcontext_ptr,
+            },
+            mcontext: context,
+            siginfo: siginfo_t {
+                si_signo: sig,
+                si_errno: 0,
+                si_code,
+                si_pid,
+                si_uid: 0,
+                si_status: 0,
+                si_addr: Ptr::from_bits(si_addr),
+                si_value: 0,
+                si_band: 0,
+                _pad: [0; 7],
+            },
+        },
+    );
+
+    let mut new_mask = old_mask | action.sa_mask;
+    if action.sa_flags & SA_NODEFER == 0 {
+        new_mask |= sig_bit(sig);
+    }
+    State::get(env).set_mask(thread, new_mask);
+    if action.sa_flags & SA_RESETHAND != 0 {
+        State::get(env).actions[sig as usize] = sigaction::default();
+    }
+
+    let handler = GuestFunction::from_addr_with_thumb_bit(action.sa_handler);
+    log_dbg!(
+        "Running handler {:?} for {} on thread {}, frame at {:?}",
+        handler,
+        SIGNAL_NAMES[sig as usize],
+        thread,
+        frame_ptr
+    );
+    let signal_return_routine = env.dyld.signal_return_routine().addr_with_thumb_bit();
+    let regs = env.cpu.regs_mut();
+    regs[0] = sig as u32;
+    regs[1] = siginfo_ptr.to_bits();
+    regs[2] = ucontext_ptr.to_bits();
+    regs[Cpu::SP] = frame_ptr.to_bits();
+    regs[Cpu::LR] = signal_return_routine;
+    env.cpu.branch(handler);
+}
+
+/// Deliver the signals that are pending for the current thread and not
+/// blocked by it, from within a host function called by the app. The return
+/// value is what the host function must return, which is `ret` unless a
+/// handler is going to run (see the module documentation).
+pub fn deliver_pending_from_host(env: &mut Environment, ret: i32) -> i32 {
+    let mut r0 = ret;
+    let mut in_host_function = true;
+    while let Some(sig) = take_deliverable_signal(env) {
+        let action = match disposition(env, sig) {
+            Disposition::Ignore => continue,
+            Disposition::Terminate => terminate(sig),
+            Disposition::Handler(action) => action,
+        };
+        let mut context = save_context(env);
+        if in_host_function {
+            // The first handler returns to where the host function would.
+            let lr = context.ss.lr;
+            let thumb = (lr & 1) * Cpu::CPSR_THUMB;
+            context.ss.pc = lr & !1;
+            context.ss.cpsr = (context.ss.cpsr & !Cpu::CPSR_THUMB) | thumb;
+            context.ss.r[0] = ret as u32;
+            in_host_function = false;
+        }
+        push_frame(env, sig, context, SI_USER, 0, action);
+        r0 = sig;
+    }
+    r0
+}
+
+/// Called by [crate::Environment] before running guest code on the current
+/// thread, to deliver signals sent to it by other threads or unblocked since.
+pub fn deliver_pending_signals(env: &mut Environment) {
+    if State::get(env).pending.is_empty() {
+        return;
+    }
+    while let Some(sig) = take_deliverable_signal(env) {
+        match disposition(env, sig) {
+            Disposition::Ignore => (),
+            Disposition::Terminate => terminate(sig),
+            Disposition::Handler(action) => {
+                let context = save_context(env);
+                push_frame(env, sig, context, SI_USER, 0, action);
+            }
+        }
+    }
+}
+
+/// Called by [crate::Environment] when the CPU makes an invalid memory access.
+/// If the app has a handler for the resulting signal, the handler is set up to
+/// run and [true] is returned. Otherwise, nothing is done and [false] is
+/// returned, since returning to the faulting instruction would only fault
+/// again.
+pub fn deliver_memory_fault(env: &mut Environment, fault: MemoryFault) -> bool {
+    // Like on iPhone OS, accessing unmapped memory is a SIGSEGV, but an access
+    // to mapped memory that its protection forbids is a SIGBUS.
+    let forbidden = !env.mem.protection(fault.addr).allows(fault.kind);
+    let (sig, si_code) = if env.mem.is_mapped(fault.addr) && forbidden {
+        (SIGBUS, BUS_ADRERR)
+    } else {
+        (SIGSEGV, SEGV_MAPERR)
+    };
+
+    let thread = env.current_thread;
+    if State::get(env).mask(thread) & sig_bit(sig) != 0 {
+        return false;
+    }
+    let Disposition::Handler(action) = disposition(env, sig) else {
+        return false;
+    };
+
+    echo!(
+        "{:?} on thread {}, running the app's {} handler.",
+        fault,
+        thread,
+        SIGNAL_NAMES[sig as usize]
+    );
+    let mut context = save_context(env);
+    context.es.far = fault.addr;
+    push_frame(env, sig, context, si_code, fault.addr, action);
+    true
+}
+
+/// Called by [crate::gdb] when the debugger continues or steps with a signal.
+/// If the thread stopped for an invalid memory access, `fault` is that access,
+/// so that the app's handler for a passed-on `SIGSEGV` or `SIGBUS` gets the
+/// same information it would without a debugger. Otherwise, the signal is made
+/// pending, and [crate::Environment] delivers it before running guest code.
+pub fn send_from_debugger(env: &mut Environment, sig: i32, fault: Option<MemoryFault>) {
+    assert!(is_valid_signal(sig));
+    if let Some(fault) = fault {
+        if (sig == SIGSEGV || sig == SIGBUS) && deliver_memory_fault(env, fault) {
+            return;
+        }
+    }
+    let thread = env.current_thread;
+    State::get(env).add_pending(thread, sig);
+}
+
+/// Called by [crate::Environment] when a handler returns to
+/// [crate::dyld::Dyld::signal_return_routine].
+pub fn return_from_handler(env: &mut Environment) {
+    let frame_ptr: ConstPtr<SignalFrame> = Ptr::from_bits(env.cpu.regs()[Cpu::SP]);
+    let ucontext: ucontext_t = env.mem.read(frame_ptr.cast());
+    let siginfo_ptr: ConstPtr<siginfo_t> = Ptr::from_bits(frame_ptr.to_bits() + SIGINFO_OFFSET);
+    let siginfo = env.mem.read(siginfo_ptr);
+    // The handler may have changed the context, and it's the one the
+    // `ucontext_t` points to that counts.
+    let context = env.mem.read(ucontext.uc_mcontext.cast_const());
+    // The frame is in guest memory, so the app may have overwritten it.
+    if !is_valid_signal(siginfo.si_signo) {
+        panic!(
+            "Signal handler returned on thread {} with invalid signal number {} in its frame, terminating app",
+            env.current_thread, siginfo.si_signo
+        );
+    }
+    log_dbg!(
+        "Handler for {} returned on thread {}",
+        SIGNAL_NAMES[siginfo.si_signo as usize],
+        env.current_thread
+    );
+    restore_context(env, context);
+    set_current_mask(env, ucontext.uc_sigmask);
+
+    if siginfo.si_signo == SIGABRT && State::get(env).aborting {
+        terminate(SIGABRT);
+    }
+    // Signals that arrived while the handler ran may have been unblocked.
+    deliver_pending_signals(env);
+}
+
+/// Send a signal to the current thread from a host function called by the
+/// app. See [deliver_pending_from_host] for the return value.
+fn send_to_current_thread(env: &mut Environment, sig: i32, ret: i32) -> i32 {
+    let thread = env.current_thread;
+    State::get(env).add_pending(thread, sig);
+    deliver_pending_from_host(env, ret)
+}
+
+/// Send `SIGPIPE` to the current thread, for a host function called by the app
+/// that is failing with `EPIPE`. See [deliver_pending_from_host] for the
+/// return value.
+pub fn send_sigpipe(env: &mut Environment, ret: i32) -> i32 {
+    send_to_current_thread(env, SIGPIPE, ret)
+}
+
+/// Implementation of `abort()`. If the app has a `SIGABRT` handler, it runs
+/// once `abort()` returns to the app, and the app is terminated if the handler
+/// returns. See [deliver_pending_from_host] for the return value.
+pub fn abort(env: &mut Environment) -> i32 {
+    // abort() works even if SIGABRT is blocked or ignored.
+    let thread = env.current_thread;
+    let mask = State::get(env).mask(thread);
+    State::get(env).set_mask(thread, mask & !sig_bit(SIGABRT));
+    let Disposition::Handler(_) = disposition(env, SIGABRT) else {
+        terminate(SIGABRT);
+    };
+    State::get(env).aborting = true;
+    send_to_current_thread(env, SIGABRT, 0)
+}
+
+/// Shared part of [sigaction] and [signal]: change the action for a signal if
+/// `new` is [Some], and get the previous one. Returns [Err] if the signal
+/// number is invalid or the action can't be changed.
+fn set_action(env: &mut Environment, sig: i32, new: Option<sigaction>) -> Result<sigaction, ()> {
+    if !is_valid_signal(sig) || (new.is_some() && UNBLOCKABLE & sig_bit(sig) != 0) {
+        return Err(());
+    }
+    let state = State::get(env);
+    let old = state.actions[sig as usize];
+    if let Some(new) = new {
+        log_dbg!(
+            "Action for {} changed from {:?} to {:?}",
+            SIGNAL_NAMES[sig as usize],
+            old,
+            new
+        );
+        state.actions[sig as usize] = new;
+        // Pending instances of a signal that is now ignored are discarded.
+        if new.sa_handler == SIG_IGN || (new.sa_handler == SIG_DFL && default_is_ignore(sig)) {
+            state.pending.retain(|_, pending| {
+                *pending &= !sig_bit(sig);
+                *pending != 0
+            });
+        }
+    }
+    Ok(old)
 }
 
-fn signal(_env: &mut Environment, signum: i32, handler: MutVoidPtr) -> MutVoidPtr {
-    log!("TODO: signal({:?}, {:?})", signum, handler);
-    Ptr::null()
+fn sigaction(
+    env: &mut Environment,
+    sig: i32,
+    act: ConstPtr<sigaction>,
+    oldact: MutPtr<sigaction>,
+) -> i32 {
+    let new = (!act.is_null()).then(|| env.mem.read(act));
+    let Ok(old) = set_action(env, sig, new) else {
+        log!("sigaction({}, {:?}, {:?}) => EINVAL", sig, act, oldact);
+        set_errno(env, EINVAL);
+        return -1;
+    };
+    if !oldact.is_null() {
+        env.mem.write(oldact, old);
+    }
+    0 // success
+}
+
+fn signal(env: &mut Environment, sig: i32, handler: GuestUSize) -> GuestUSize {
+    // signal() has BSD semantics on iPhone OS: the handler stays installed,
+    // and interrupted system calls are restarted.
+    let new = sigaction {
+        sa_handler: handler,
+        sa_mask: 0,
+        sa_flags: SA_RESTART,
+    };
+    match set_action(env, sig, Some(new)) {
+        Ok(old) => old.sa_handler,
+        Err(()) => {
+            log!("signal({}, {:#x}) => SIG_ERR", sig, handler);
+            set_errno(env, EINVAL);
+            SIG_ERR
+        }
+    }
+}
+
+fn raise(env: &mut Environment, sig: i32) -> i32 {
+    log_dbg!("raise({})", sig);
+    if !is_valid_signal(sig) {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    send_to_current_thread(env, sig, 0)
+}
+
+fn kill(env: &mut Environment, pid: pid_t, sig: i32) -> i32 {
+    log_dbg!("kill({}, {})", pid, sig);
+    // 0 and -1 mean the process group and all processes respectively, which
+    // only contain this process as far as the app can tell.
+    if pid != getpid(env) && pid != 0 && pid != -1 {
+        log!("kill({}, {}) for another process => ESRCH", pid, sig);
+        set_errno(env, ESRCH);
+        return -1;
+    }
+    // Signal 0 is used to check whether a process exists.
+    if sig == 0 {
+        return 0;
+    }
+    if !is_valid_signal(sig) {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    // Any thread that doesn't block the signal could receive it, but the
+    // current thread is the obvious choice.
+    send_to_current_thread(env, sig, 0)
+}
+
+fn pthread_kill(env: &mut Environment, thread: pthread_t, sig: i32) -> i32 {
+    log_dbg!("pthread_kill({:?}, {})", thread, sig);
+    let Some(thread_id) = thread_id_for_pthread(env, thread) else {
+        return ESRCH;
+    };
+    if !env.threads[thread_id].active {
+        return ESRCH;
+    }
+    if sig == 0 {
+        return 0;
+    }
+    if !is_valid_signal(sig) {
+        return EINVAL;
+    }
+    if thread_id == env.current_thread {
+        send_to_current_thread(env, sig, 0)
+    } else {
+        // It will be delivered when that thread next runs.
+        State::get(env).add_pending(thread_id, sig);
+        0 // success
+    }
+}
+
+/// Shared part of [sigprocmask] and [pthread_sigmask]. Returns an error number
+/// on failure.
+fn change_mask(
+    env: &mut Environment,
+    how: i32,
+    set: ConstPtr<sigset_t>,
+    oldset: MutPtr<sigset_t>,
+) -> Result<(), i32> {
+    let old = current_mask(env);
+    if !set.is_null() {
+        let set = env.mem.read(set);
+        let new = match how {
+            SIG_BLOCK => old | set,
+            SIG_UNBLOCK => old & !set,
+            SIG_SETMASK => set,
+            _ => return Err(EINVAL),
+        };
+        set_current_mask(env, new);
+    }
+    if !oldset.is_null() {
+        env.mem.write(oldset, old);
+    }
+    Ok(())
+}
+
+fn sigprocmask(
+    env: &mut Environment,
+    how: i32,
+    set: ConstPtr<sigset_t>,
+    oldset: MutPtr<sigset_t>,
+) -> i32 {
+    match change_mask(env, how, set, oldset) {
+        // Signals that were unblocked must be delivered before returning.
+        Ok(()) => deliver_pending_from_host(env, 0),
+        Err(err) => {
+            set_errno(env, err);
+            -1
+        }
+    }
+}
+
+fn pthread_sigmask(
+    env: &mut Environment,
+    how: i32,
+    set: ConstPtr<sigset_t>,
+    oldset: MutPtr<sigset_t>,
+) -> i32 {
+    match change_mask(env, how, set, oldset) {
+        Ok(()) => deliver_pending_from_host(env, 0),
+        Err(err) => err,
+    }
+}
+
+fn sigpending(env: &mut Environment, set: MutPtr<sigset_t>) -> i32 {
+    let thread = env.current_thread;
+    let pending = State::get(env).pending.get(&thread).copied().unwrap_or(0);
+    env.mem.write(set, pending);
+    0 // success
+}
+
+fn sigaltstack(env: &mut Environment, ss: ConstPtr<stack_t>, old_ss: MutPtr<stack_t>) -> i32 {
+    let thread = env.current_thread;
+    let sp = env.cpu.regs()[Cpu::SP];
+    let current = State::get(env).alt_stacks.get(&thread).copied();
+    let on_stack = current.is_some_and(|stack| stack.contains(sp));
+
+    if !ss.is_null() {
+        let new = env.mem.read(ss);
+        log_dbg!("sigaltstack({:?}, {:?}): {:?}", ss, old_ss, new);
+        // The stack can't be changed while it's in use.
+        if on_stack {
+            set_errno(env, EPERM);
+            return -1;
+        }
+        if new.ss_flags & SS_DISABLE != 0 {
+            State::get(env).alt_stacks.remove(&thread);
+        } else if new.ss_size < MINSIGSTKSZ {
+            set_errno(env, ENOMEM);
+            return -1;
+        } else {
+            State::get(env)
+                .alt_stacks
+                .insert(thread, stack_t { ss_flags: 0, ..new });
+        }
+    }
+    if !old_ss.is_null() {
+        let old = match current {
+            Some(stack) => stack_t {
+                ss_flags: if on_stack { SS_ONSTACK } else { 0 },
+                ..stack
+            },
+            None => stack_t {
+                ss_sp: Ptr::null(),
+                ss_size: 0,
+                ss_flags: SS_DISABLE,
+            },
+        };
+        env.mem.write(old_ss, old);
+    }
+    0 // success
+}
+
+fn sigemptyset(env: &mut Environment, set: MutPtr<sigset_t>) -> i32 {
+    env.mem.write(set, 0);
+    0
+}
+fn sigfillset(env: &mut Environment, set: MutPtr<sigset_t>) -> i32 {
+    env.mem.write(set, !0);
+    0
+}
+fn sigaddset(env: &mut Environment, set: MutPtr<sigset_t>, sig: i32) -> i32 {
+    if !is_valid_signal(sig) {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    let value = env.mem.read(set);
+    env.mem.write(set, value | sig_bit(sig));
+    0
+}
+fn sigdelset(env: &mut Environment, set: MutPtr<sigset_t>, sig: i32) -> i32 {
+    if !is_valid_signal(sig) {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    let value = env.mem.read(set);
+    env.mem.write(set, value & !sig_bit(sig));
+    0
+}
+fn sigismember(env: &mut Environment, set: ConstPtr<sigset_t>, sig: i32) -> i32 {
+    if !is_valid_signal(sig) {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    (env.mem.read(set) & sig_bit(sig) != 0).into()
 }
 
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(sigaction(_, _, _)),
     export_c_func!(signal(_, _)),
+    export_c_func!(raise(_)),
+    export_c_func!(kill(_, _)),
+    export_c_func!(pthread_kill(_, _)),
+    export_c_func!(sigprocmask(_, _, _)),
+    export_c_func!(pthread_sigmask(_, _, _)),
+    export_c_func!(sigpending(_)),
+    export_c_func!(sigaltstack(_, _)),
+    export_c_func!(sigemptyset(_)),
+    export_c_func!(sigfillset(_)),
+    export_c_func!(sigaddset(_, _)),
+    export_c_func!(sigdelset(_, _)),
+    export_c_func!(sigismember(_, _)),
 ];
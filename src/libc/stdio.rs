@@ -11,6 +11,7 @@ use super::posix_io::{
 };
 use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
 use crate::fs::GuestPath;
+use crate::libc::errno::{errno_for_fs_error, set_errno, EFAULT};
 use crate::libc::string::strlen;
 use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, Mem, MutPtr, MutVoidPtr, Ptr, SafeRead};
 use crate::Environment;
@@ -195,7 +196,7 @@ fn fwrite(
         _ => {
             // The comment about the item_size/n_items split in fread() applies
             // here too.
-            match posix_io::write(env, fd, buffer, total_size) {
+            match posix_io::write_direct(env, fd, buffer, total_size) {
                 // TODO: ferror() support.
                 -1 => 0,
                 bytes_written => {
@@ -298,8 +299,8 @@ fn putchar(_env: &mut Environment, c: u8) -> i32 {
 
 fn remove(env: &mut Environment, path: ConstPtr<u8>) -> i32 {
     if Ptr::is_null(path) {
-        // TODO: set errno
         log!("remove({:?}) => -1, attempted to remove null", path);
+        set_errno(env, EFAULT);
         return -1;
     }
 
@@ -311,9 +312,9 @@ fn remove(env: &mut Environment, path: ConstPtr<u8>) -> i32 {
             log_dbg!("remove({:?}) => 0", path);
             0
         }
-        Err(_) => {
-            // TODO: set errno
+        Err(err) => {
             log!("Warning: remove({:?}) failed, returning -1", path);
+            set_errno(env, errno_for_fs_error(err));
             -1
         }
     }
@@ -6,14 +6,18 @@
 //! `stdlib.h`
 
 use crate::abi::{CallFromHost, GuestFunction};
-use crate::dyld::{export_c_func, export_c_func_aliased, FunctionExports};
+use crate::dyld::{export_c_func, export_c_func_aliased, FunctionExports, HostFunction};
 use crate::fs::{resolve_path, GuestPath};
 use crate::libc::clocale::{setlocale, LC_CTYPE};
 use crate::libc::string::strlen;
 use crate::libc::wchar::wchar_t;
+use crate::libc::{cxxabi, mmap, posix_io, signal};
 use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, MutPtr, MutVoidPtr, Ptr};
+use crate::replay;
+use crate::save_state::impl_SaveState;
 use crate::Environment;
 use std::collections::HashMap;
+use std::io::Write;
 use std::str::FromStr;
 
 pub mod qsort;
@@ -25,6 +29,12 @@ pub struct State {
     arc4random: u32,
     env: HashMap<Vec<u8>, MutPtr<u8>>,
 }
+impl_SaveState!(State {
+    rand,
+    random,
+    arc4random,
+    env,
+});
 
 // Sizes of zero are implementation-defined. macOS will happily give you back
 // an allocation for any of these, so presumably iPhone OS does too.
@@ -139,7 +149,8 @@ fn random(env: &mut Environment) -> i32 {
 
 fn arc4random(env: &mut Environment) -> u32 {
     env.libc_state.stdlib.arc4random = prng(env.libc_state.stdlib.arc4random);
-    env.libc_state.stdlib.arc4random
+    let value = env.libc_state.stdlib.arc4random;
+    replay::random(env, value)
 }
 
 fn getenv(env: &mut Environment, name: ConstPtr<u8>) -> MutPtr<u8> {
@@ -185,11 +196,61 @@ fn setenv(env: &mut Environment, name: ConstPtr<u8>, value: ConstPtr<u8>, overwr
     0 // success
 }
 
-fn exit(_env: &mut Environment, exit_code: i32) {
+fn atexit(env: &mut Environment, func: GuestFunction) -> i32 {
+    log_dbg!("atexit({:?})", func);
+    cxxabi::register_exit_handler(env, func, Ptr::null(), Ptr::null());
+    0 // success
+}
+
+fn exit(env: &mut Environment, exit_code: i32) {
     echo!("App called exit(), exiting.");
+    exit_app(env, exit_code);
+}
+
+/// Create the guest function that the app's entry point returns to. Returning
+/// from `main()` is the same as calling `exit()` with its return value.
+pub fn main_return_routine(env: &mut Environment) -> GuestFunction {
+    let hf: HostFunction = &(_touchHLE_main_returned as fn(&mut Environment, _));
+    env.dyld
+        .create_guest_function(&mut env.mem, "__touchHLE_main_returned", hf)
+}
+
+fn _touchHLE_main_returned(env: &mut Environment, exit_code: i32) {
+    echo!("App returned from main(), exiting.");
+    exit_app(env, exit_code);
+}
+
+/// Exit touchHLE in an orderly way, whether the app called `exit()` or the
+/// user quit it. Handlers registered with `atexit()` and `__cxa_atexit()`,
+/// which include the destructors of static C++ objects, are run first, since
+/// apps might save their data in them.
+pub fn exit_app(env: &mut Environment, exit_code: i32) -> ! {
+    cxxabi::run_exit_handlers(env);
+    exit_app_without_handlers(env, exit_code)
+}
+
+/// Like [exit_app], but without running any handlers, like `_exit()`. Data
+/// the app has written is still saved.
+pub fn exit_app_without_handlers(env: &mut Environment, exit_code: i32) -> ! {
+    mmap::write_back_all(env);
+    posix_io::close_all(env);
+    // Rust's stdout is line-buffered, and the app might not have finished
+    // its last line.
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+    replay::flush(env);
+    env.report_heap_leaks();
     std::process::exit(exit_code);
 }
 
+/// This is `void` in C and doesn't return to the caller, but if the app has a
+/// `SIGABRT` handler, the return value is needed to run it (see
+/// [signal::abort]).
+fn abort(env: &mut Environment) -> i32 {
+    echo!("App called abort().");
+    signal::abort(env)
+}
+
 fn bsearch(
     env: &mut Environment,
     key: ConstVoidPtr,
@@ -364,7 +425,9 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(arc4random()),
     export_c_func!(getenv(_)),
     export_c_func!(setenv(_, _, _)),
+    export_c_func!(atexit(_)),
     export_c_func!(exit(_)),
+    export_c_func!(abort()),
     export_c_func!(bsearch(_, _, _, _, _)),
     export_c_func!(strtof(_, _)),
     export_c_func!(strtoul(_, _, _)),
@@ -375,6 +438,10 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(wcstombs(_, _, _)),
 ];
 
+/// Not exported to the app. This is only for finding the helper by name, e.g.
+/// when loading a save state.
+pub const PRIVATE_FUNCTIONS: FunctionExports = &[export_c_func!(_touchHLE_main_returned(_))];
+
 /// Returns a tuple containing the parsed number and the length of the number in
 /// the string
 pub fn atof_inner(
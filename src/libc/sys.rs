@@ -4,5 +4,8 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub mod ioctl;
+pub mod select;
+pub mod socket;
 pub mod timeb;
 pub mod utsname;
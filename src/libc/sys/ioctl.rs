@@ -0,0 +1,49 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `sys/ioctl.h`
+
+use crate::abi::DotDotDot;
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{set_errno, ENOTSOCK, ENOTTY};
+use crate::libc::posix_io::FileDescriptor;
+use crate::libc::sys::socket;
+use crate::mem::MutPtr;
+use crate::Environment;
+
+/// Set or clear non-blocking I/O, like `O_NONBLOCK`.
+const FIONBIO: u32 = 0x8004667e;
+/// Get the number of bytes that can be read.
+const FIONREAD: u32 = 0x4004667f;
+
+fn ioctl(env: &mut Environment, fd: FileDescriptor, request: u32, args: DotDotDot) -> i32 {
+    let arg: MutPtr<i32> = args.start().next(env);
+    let res = match request {
+        FIONBIO => match env.libc_state.posix_io.socket_for_fd(fd) {
+            Ok(socket) => {
+                socket.non_blocking = env.mem.read(arg) != 0;
+                Ok(())
+            }
+            // This is ignored for files, like O_NONBLOCK.
+            Err(ENOTSOCK) => Ok(()),
+            Err(errno) => Err(errno),
+        },
+        FIONREAD => socket::bytes_available(env, fd).map(|count| env.mem.write(arg, count)),
+        _ => {
+            log!("TODO: ioctl({:?}, {:#x})", fd, request);
+            Err(ENOTTY)
+        }
+    };
+    log_dbg!("ioctl({:?}, {:#x}, {:?}) => {:?}", fd, request, arg, res);
+    match res {
+        Ok(()) => 0,
+        Err(errno) => {
+            set_errno(env, errno);
+            -1
+        }
+    }
+}
+
+pub const FUNCTIONS: FunctionExports = &[export_c_func!(ioctl(_, _, _))];
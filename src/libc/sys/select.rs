@@ -0,0 +1,117 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `sys/select.h`
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{set_errno, EBADF, EINVAL};
+use crate::libc::posix_io::{self, FileDescriptor};
+use crate::libc::sys::socket::POLL_INTERVAL;
+use crate::libc::time::timeval;
+use crate::mem::{MutPtr, SafeRead};
+use crate::replay;
+use crate::Environment;
+
+const FD_SETSIZE: i32 = 1024;
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Default)]
+#[repr(C, packed)]
+pub struct fd_set {
+    fds_bits: [i32; (FD_SETSIZE / 32) as usize],
+}
+unsafe impl SafeRead for fd_set {}
+impl fd_set {
+    fn is_set(&self, fd: FileDescriptor) -> bool {
+        let fds_bits = self.fds_bits;
+        fds_bits[(fd / 32) as usize] & (1 << (fd % 32)) != 0
+    }
+    fn set(&mut self, fd: FileDescriptor) {
+        let mut fds_bits = self.fds_bits;
+        fds_bits[(fd / 32) as usize] |= 1 << (fd % 32);
+        self.fds_bits = fds_bits;
+    }
+}
+
+fn select(
+    env: &mut Environment,
+    nfds: i32,
+    readfds: MutPtr<fd_set>,
+    writefds: MutPtr<fd_set>,
+    errorfds: MutPtr<fd_set>,
+    timeout: MutPtr<timeval>,
+) -> i32 {
+    if !(0..=FD_SETSIZE).contains(&nfds) {
+        set_errno(env, EINVAL);
+        return -1;
+    }
+    // A null timeout means waiting forever.
+    let timeout = if timeout.is_null() {
+        None
+    } else {
+        let Some(timeout) = env.mem.read(timeout).to_duration() else {
+            set_errno(env, EINVAL);
+            return -1;
+        };
+        Some(timeout)
+    };
+    let deadline = timeout.map(|timeout| replay::now(env) + timeout);
+
+    let read_set =
+        |env: &Environment, set: MutPtr<fd_set>| (!set.is_null()).then(|| env.mem.read(set));
+    let (in_read, in_write, in_error) = (
+        read_set(env, readfds),
+        read_set(env, writefds),
+        read_set(env, errorfds),
+    );
+
+    loop {
+        let mut out_read = fd_set::default();
+        let mut out_write = out_read;
+        let mut out_error = out_read;
+        let mut count = 0;
+        for fd in 0..nfds {
+            let wanted = |set: &Option<fd_set>| set.as_ref().is_some_and(|set| set.is_set(fd));
+            let (want_read, want_write, want_error) =
+                (wanted(&in_read), wanted(&in_write), wanted(&in_error));
+            if !(want_read || want_write || want_error) {
+                continue;
+            }
+            let Some(readiness) = posix_io::readiness(env, fd) else {
+                log_dbg!("select(): bad file descriptor {}", fd);
+                set_errno(env, EBADF);
+                return -1;
+            };
+            for (wanted, ready, out) in [
+                (want_read, readiness.readable, &mut out_read),
+                (want_write, readiness.writable, &mut out_write),
+                (want_error, readiness.error, &mut out_error),
+            ] {
+                if wanted && ready {
+                    out.set(fd);
+                    count += 1;
+                }
+            }
+        }
+
+        if count != 0 || deadline.is_some_and(|deadline| replay::now(env) >= deadline) {
+            for (set, out) in [
+                (readfds, out_read),
+                (writefds, out_write),
+                (errorfds, out_error),
+            ] {
+                if !set.is_null() {
+                    env.mem.write(set, out);
+                }
+            }
+            log_dbg!("select({}, ..., {:?}) => {}", nfds, timeout, count);
+            return count;
+        }
+
+        env.sleep(POLL_INTERVAL, false);
+    }
+}
+
+pub const FUNCTIONS: FunctionExports = &[export_c_func!(select(_, _, _, _, _))];
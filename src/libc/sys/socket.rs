@@ -0,0 +1,1018 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `sys/socket.h`, and the parts of `netinet/in.h` needed to use it.
+//!
+//! Sockets are backed by host sockets from [std::net], or from [socket2] for
+//! TCP sockets that are bound but not yet listening or connected, which
+//! [std::net] can't represent. Only IPv4 TCP and UDP sockets are supported.
+//!
+//! The host sockets are always non-blocking. When an operation on a blocking
+//! guest socket would block, the current guest thread blocks until the socket
+//! is ready and then tries again, so that other guest threads can keep running
+//! in the meantime (see [Environment::block_on_io]).
+//!
+//! Note that network traffic isn't recorded by `--record-input=`, so apps that
+//! use the network can't be replayed reliably.
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{
+    errno_for_io_error, set_errno, EAFNOSUPPORT, EAGAIN, EALREADY, EDESTADDRREQ, EFAULT,
+    EINPROGRESS, EINVAL, EISCONN, ENOTCONN, EOPNOTSUPP, EPROTONOSUPPORT, EPROTOTYPE,
+};
+use crate::libc::time::timeval;
+use crate::mem::{guest_size_of, ConstPtr, ConstVoidPtr, GuestISize, GuestUSize, Mem, MutPtr};
+use crate::mem::{MutVoidPtr, SafeRead};
+use crate::replay;
+use crate::{Environment, IoBlock, IoWait};
+use socket2::{Domain, Protocol, SockRef, Type};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, TcpListener, TcpStream, UdpSocket};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::Duration;
+
+#[allow(non_camel_case_types)]
+pub type socklen_t = u32;
+#[allow(non_camel_case_types)]
+pub type sa_family_t = u8;
+#[allow(non_camel_case_types)]
+pub type in_port_t = u16;
+#[allow(non_camel_case_types)]
+pub type in_addr_t = u32;
+
+pub const AF_UNSPEC: i32 = 0;
+pub const AF_INET: i32 = 2;
+pub const AF_INET6: i32 = 30;
+
+pub const SOCK_STREAM: i32 = 1;
+pub const SOCK_DGRAM: i32 = 2;
+
+pub const IPPROTO_TCP: i32 = 6;
+pub const IPPROTO_UDP: i32 = 17;
+
+const SOL_SOCKET: i32 = 0xffff;
+const SO_BROADCAST: i32 = 0x20;
+const SO_SNDTIMEO: i32 = 0x1005;
+const SO_RCVTIMEO: i32 = 0x1006;
+const SO_ERROR: i32 = 0x1007;
+const SO_TYPE: i32 = 0x1008;
+const TCP_NODELAY: i32 = 0x1;
+
+const MSG_PEEK: i32 = 0x2;
+const MSG_WAITALL: i32 = 0x40;
+const MSG_DONTWAIT: i32 = 0x80;
+
+const SHUT_RD: i32 = 0;
+const SHUT_WR: i32 = 1;
+const SHUT_RDWR: i32 = 2;
+
+/// Opaque type for pointers to any kind of socket address. Only
+/// [sockaddr_in] is supported.
+#[allow(non_camel_case_types)]
+pub struct sockaddr {}
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+pub struct sockaddr_in {
+    pub sin_len: u8,
+    pub sin_family: sa_family_t,
+    /// In network byte order (big-endian).
+    pub sin_port: in_port_t,
+    /// In network byte order (big-endian).
+    pub sin_addr: in_addr_t,
+    pub sin_zero: [u8; 8],
+}
+unsafe impl SafeRead for sockaddr_in {}
+impl sockaddr_in {
+    pub fn from_socket_addr(addr: SocketAddrV4) -> sockaddr_in {
+        sockaddr_in {
+            sin_len: guest_size_of::<sockaddr_in>() as u8,
+            sin_family: AF_INET as sa_family_t,
+            sin_port: addr.port().to_be(),
+            sin_addr: u32::from(*addr.ip()).to_be(),
+            sin_zero: [0; 8],
+        }
+    }
+    pub fn to_socket_addr(self) -> SocketAddrV4 {
+        SocketAddrV4::new(
+            Ipv4Addr::from(u32::from_be(self.sin_addr)),
+            u16::from_be(self.sin_port),
+        )
+    }
+}
+
+/// How long to wait before checking again whether a socket a guest thread is
+/// blocked on is ready.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Whether I/O on a file descriptor would block, see
+/// [crate::libc::posix_io::readiness].
+#[derive(Default, Debug)]
+pub struct Readiness {
+    pub readable: bool,
+    pub writable: bool,
+    /// There's a pending error (e.g. a connection attempt failed).
+    pub error: bool,
+}
+
+pub struct Socket {
+    /// [SOCK_STREAM] or [SOCK_DGRAM].
+    kind: i32,
+    /// Set with `fcntl()` or `ioctl()`.
+    pub non_blocking: bool,
+    state: SocketState,
+    /// An error from a failed non-blocking `connect()`, for `SO_ERROR`.
+    pending_error: Option<i32>,
+    receive_timeout: Option<Duration>,
+    send_timeout: Option<Duration>,
+    /// Integer options set with `setsockopt()`, by level and name. Most of
+    /// these have no effect, but they're kept so `getsockopt()` can return
+    /// them.
+    options: HashMap<(i32, i32), i32>,
+}
+
+enum SocketState {
+    /// Not bound or connected yet, or a connection attempt failed.
+    New,
+    /// A TCP socket that has been bound, but isn't listening or connecting.
+    Bound(socket2::Socket),
+    /// A TCP socket that is listening for connections.
+    Listening {
+        listener: TcpListener,
+        /// A connection accepted while checking the readiness, which the next
+        /// `accept()` will return.
+        pending: Option<(TcpStream, SocketAddr)>,
+    },
+    /// A TCP connection being made by a host thread, since [std::net] can't
+    /// connect without blocking.
+    Connecting(Receiver<std::io::Result<TcpStream>>),
+    Connected(TcpStream),
+    /// A bound UDP socket.
+    Datagram(UdpSocket),
+}
+
+impl Socket {
+    fn new(kind: i32, non_blocking: bool, state: SocketState) -> Socket {
+        Socket {
+            kind,
+            non_blocking,
+            state,
+            pending_error: None,
+            receive_timeout: None,
+            send_timeout: None,
+            options: HashMap::new(),
+        }
+    }
+
+    /// Apply the options that the host socket can support, after setting
+    /// them or after creating the host socket.
+    fn apply_options(&mut self) -> std::io::Result<()> {
+        let nodelay = self.options.get(&(IPPROTO_TCP, TCP_NODELAY)).copied();
+        let broadcast = self.options.get(&(SOL_SOCKET, SO_BROADCAST)).copied();
+        match self.state {
+            SocketState::Connected(ref stream) => {
+                if let Some(nodelay) = nodelay {
+                    stream.set_nodelay(nodelay != 0)?;
+                }
+            }
+            SocketState::Datagram(ref socket) => {
+                if let Some(broadcast) = broadcast {
+                    socket.set_broadcast(broadcast != 0)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Create the host socket for a UDP socket that isn't bound yet, like the
+    /// OS implicitly does when sending.
+    fn datagram_socket(&mut self) -> std::io::Result<&UdpSocket> {
+        if let SocketState::New = self.state {
+            let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
+            socket.set_nonblocking(true)?;
+            self.state = SocketState::Datagram(socket);
+            self.apply_options()?;
+        }
+        let SocketState::Datagram(ref socket) = self.state else {
+            unreachable!();
+        };
+        Ok(socket)
+    }
+
+    /// Check if a connection attempt has finished.
+    fn check_connecting(&mut self) {
+        let SocketState::Connecting(ref receiver) = self.state else {
+            return;
+        };
+        let result = match receiver.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => panic!("Connecting thread disappeared"),
+        };
+        match result.and_then(|stream| stream.set_nonblocking(true).map(|_| stream)) {
+            Ok(stream) => {
+                self.state = SocketState::Connected(stream);
+                if let Err(e) = self.apply_options() {
+                    log!("Warning: couldn't set socket options: {}", e);
+                }
+            }
+            Err(e) => {
+                self.state = SocketState::New;
+                self.pending_error = Some(errno_for_io_error(&e));
+            }
+        }
+    }
+
+    pub fn readiness(&mut self) -> Readiness {
+        self.check_connecting();
+        let mut byte = [0u8];
+        let (readable, writable) = match self.state {
+            SocketState::New | SocketState::Bound(_) => (false, false),
+            SocketState::Listening {
+                ref listener,
+                ref mut pending,
+            } => {
+                if pending.is_none() {
+                    *pending = listener.accept().ok();
+                }
+                (pending.is_some(), false)
+            }
+            SocketState::Connecting(_) => (false, false),
+            // Reaching the end of the stream or an error also counts as
+            // readable, since reading won't block.
+            SocketState::Connected(ref stream) => (
+                !matches!(stream.peek(&mut byte), Err(e) if e.kind() == ErrorKind::WouldBlock),
+                true,
+            ),
+            SocketState::Datagram(ref socket) => (
+                !matches!(socket.peek_from(&mut byte), Err(e) if e.kind() == ErrorKind::WouldBlock),
+                true,
+            ),
+        };
+        let error = self.pending_error.is_some();
+        Readiness {
+            readable,
+            // A failed connection attempt makes the socket writable.
+            writable: writable || error,
+            error,
+        }
+    }
+}
+
+/// Create a host TCP socket bound to an address, which can then either listen
+/// or connect.
+fn bind_stream_socket(addr: SocketAddrV4) -> std::io::Result<socket2::Socket> {
+    let socket = socket2::Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP))?;
+    // This matches what std::net does, and means a server can be restarted
+    // without waiting for its old connections to time out.
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    Ok(socket)
+}
+
+/// Perform an operation on a socket. If it fails with [ErrorKind::WouldBlock],
+/// the current thread blocks until the socket is readable (or writable, if
+/// `writing` is set), and the host function is called again once it is (see
+/// [Environment::block_on_io]). [EAGAIN] is returned in the meantime.
+/// `progress` is kept for the call after blocking. If the socket is
+/// non-blocking or the timeout passes, the [ErrorKind::WouldBlock] error is
+/// returned instead. Errors are returned as `errno` values.
+fn retry<T>(
+    env: &mut Environment,
+    fd: i32,
+    writing: bool,
+    non_blocking: bool,
+    timeout: Option<Duration>,
+    progress: GuestUSize,
+    op: impl FnOnce(&mut Socket, &mut Mem) -> std::io::Result<T>,
+) -> Result<T, i32> {
+    let wait = if writing {
+        IoWait::Writable(fd)
+    } else {
+        IoWait::Readable(fd)
+    };
+    let resumed = env.resumed_io(wait);
+    // The socket might have been closed by another thread while waiting.
+    let socket = env.libc_state.posix_io.socket_for_fd(fd)?;
+    let non_blocking = non_blocking || socket.non_blocking;
+    match op(socket, &mut env.mem) {
+        Ok(res) => Ok(res),
+        Err(e) if e.kind() == ErrorKind::WouldBlock && !non_blocking => {
+            // The timeout counts from the first call, not the latest one.
+            let deadline = match resumed {
+                Some(IoBlock {
+                    deadline: Some(deadline),
+                    ..
+                }) if replay::now(env) >= deadline => return Err(errno_for_io_error(&e)),
+                Some(block) => block.deadline,
+                None => timeout.map(|timeout| replay::now(env) + timeout),
+            };
+            env.block_on_io(IoBlock {
+                wait,
+                deadline,
+                progress,
+            });
+            Err(EAGAIN)
+        }
+        Err(e) => Err(errno_for_io_error(&e)),
+    }
+}
+
+/// Convert a result to a return value for a function that returns -1 and
+/// sets `errno` on failure.
+fn finish<T: From<i8>>(env: &mut Environment, name: &str, fd: i32, res: Result<T, i32>) -> T {
+    match res {
+        Ok(value) => value,
+        // The result doesn't matter, the function will be called again.
+        Err(_) if env.is_blocked_on_io() => T::from(-1),
+        Err(errno) => {
+            log_dbg!("{}({:?}) failed with errno {}", name, fd, errno);
+            set_errno(env, errno);
+            T::from(-1)
+        }
+    }
+}
+
+fn read_sockaddr(
+    env: &Environment,
+    addr: ConstPtr<sockaddr>,
+    addr_len: socklen_t,
+) -> Result<SocketAddrV4, i32> {
+    if addr.is_null() {
+        return Err(EFAULT);
+    }
+    if addr_len < guest_size_of::<sockaddr_in>() {
+        return Err(EINVAL);
+    }
+    let addr: sockaddr_in = env.mem.read(addr.cast());
+    if i32::from(addr.sin_family) != AF_INET {
+        log!(
+            "TODO: socket address family {} (only IPv4 is supported)",
+            addr.sin_family
+        );
+        return Err(EAFNOSUPPORT);
+    }
+    Ok(addr.to_socket_addr())
+}
+
+/// Write a socket address for a function that takes a `sockaddr` buffer and a
+/// pointer to its size. Both can be null if the caller doesn't want the
+/// address. As with the real functions, the address is truncated if the buffer
+/// is too small, and the full size is returned.
+fn write_sockaddr(
+    env: &mut Environment,
+    addr: MutPtr<sockaddr>,
+    addr_len: MutPtr<socklen_t>,
+    value: SocketAddr,
+) {
+    if addr.is_null() || addr_len.is_null() {
+        return;
+    }
+    let value = match value {
+        SocketAddr::V4(value) => value,
+        SocketAddr::V6(value) => SocketAddrV4::new(
+            value.ip().to_ipv4_mapped().unwrap_or(Ipv4Addr::UNSPECIFIED),
+            value.port(),
+        ),
+    };
+    let value = sockaddr_in::from_socket_addr(value);
+    let size = guest_size_of::<sockaddr_in>();
+    let buffer_size = env.mem.read(addr_len).min(size);
+    let value_bytes: [u8; 16] = unsafe { std::mem::transmute(value) };
+    env.mem
+        .bytes_at_mut(addr.cast(), buffer_size)
+        .copy_from_slice(&value_bytes[..buffer_size as usize]);
+    env.mem.write(addr_len, size);
+}
+
+fn socket(env: &mut Environment, domain: i32, type_: i32, protocol: i32) -> i32 {
+    let res = if domain != AF_INET {
+        log!("TODO: socket() domain {} (only IPv4 is supported)", domain);
+        Err(EAFNOSUPPORT)
+    } else {
+        match (type_, protocol) {
+            (SOCK_STREAM, 0 | IPPROTO_TCP) | (SOCK_DGRAM, 0 | IPPROTO_UDP) => {
+                let socket = Socket::new(type_, false, SocketState::New);
+                Ok(env.libc_state.posix_io.add_socket(socket))
+            }
+            (SOCK_STREAM | SOCK_DGRAM, _) => Err(EPROTONOSUPPORT),
+            _ => {
+                log!("TODO: socket() type {}", type_);
+                Err(EPROTOTYPE)
+            }
+        }
+    };
+    let res = finish(env, "socket", -1, res);
+    log_dbg!("socket({}, {}, {}) => {:?}", domain, type_, protocol, res);
+    res
+}
+
+fn bind(env: &mut Environment, fd: i32, addr: ConstPtr<sockaddr>, addr_len: socklen_t) -> i32 {
+    let res = read_sockaddr(env, addr, addr_len).and_then(|addr| {
+        let socket = env.libc_state.posix_io.socket_for_fd(fd)?;
+        if !matches!(socket.state, SocketState::New) {
+            return Err(EINVAL);
+        }
+        socket.state = if socket.kind == SOCK_STREAM {
+            // The host socket doesn't accept connections until listen().
+            SocketState::Bound(bind_stream_socket(addr).map_err(|e| errno_for_io_error(&e))?)
+        } else {
+            let socket = UdpSocket::bind(addr).map_err(|e| errno_for_io_error(&e))?;
+            socket
+                .set_nonblocking(true)
+                .map_err(|e| errno_for_io_error(&e))?;
+            SocketState::Datagram(socket)
+        };
+        socket.apply_options().map_err(|e| errno_for_io_error(&e))?;
+        log_dbg!("bind({:?}, {}) => 0", fd, addr);
+        Ok(0)
+    });
+    finish(env, "bind", fd, res)
+}
+
+fn listen(env: &mut Environment, fd: i32, backlog: i32) -> i32 {
+    let res = env
+        .libc_state
+        .posix_io
+        .socket_for_fd(fd)
+        .and_then(|socket| {
+            if socket.kind != SOCK_STREAM {
+                return Err(EOPNOTSUPP);
+            }
+            match socket.state {
+                SocketState::New => {
+                    // Like the real OS, bind to any free port.
+                    let host_socket =
+                        bind_stream_socket(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))
+                            .map_err(|e| errno_for_io_error(&e))?;
+                    socket.state = SocketState::Bound(host_socket);
+                }
+                SocketState::Bound(_) => (),
+                // Listening again only changes the backlog.
+                SocketState::Listening { ref listener, .. } => {
+                    SockRef::from(listener)
+                        .listen(backlog)
+                        .map_err(|e| errno_for_io_error(&e))?;
+                    return Ok(0);
+                }
+                _ => return Err(EINVAL),
+            }
+            let SocketState::Bound(ref host_socket) = socket.state else {
+                unreachable!();
+            };
+            host_socket
+                .listen(backlog)
+                .and_then(|()| host_socket.set_nonblocking(true))
+                .map_err(|e| errno_for_io_error(&e))?;
+            let SocketState::Bound(host_socket) =
+                std::mem::replace(&mut socket.state, SocketState::New)
+            else {
+                unreachable!();
+            };
+            socket.state = SocketState::Listening {
+                listener: TcpListener::from(host_socket),
+                pending: None,
+            };
+            Ok(0)
+        });
+    log_dbg!("listen({:?}, {}) => {:?}", fd, backlog, res);
+    finish(env, "listen", fd, res)
+}
+
+fn accept(
+    env: &mut Environment,
+    fd: i32,
+    addr: MutPtr<sockaddr>,
+    addr_len: MutPtr<socklen_t>,
+) -> i32 {
+    let res = retry(env, fd, false, false, None, 0, |socket, _| {
+        let SocketState::Listening {
+            ref listener,
+            ref mut pending,
+        } = socket.state
+        else {
+            return Err(ErrorKind::InvalidInput.into());
+        };
+        match pending.take() {
+            Some(connection) => Ok(connection),
+            None => listener.accept(),
+        }
+    });
+    let res = res.and_then(|(stream, peer)| {
+        stream
+            .set_nonblocking(true)
+            .map_err(|e| errno_for_io_error(&e))?;
+        // Like on other BSDs, the new socket inherits O_NONBLOCK.
+        let non_blocking = env.libc_state.posix_io.socket_for_fd(fd)?.non_blocking;
+        let socket = Socket::new(SOCK_STREAM, non_blocking, SocketState::Connected(stream));
+        let new_fd = env.libc_state.posix_io.add_socket(socket);
+        write_sockaddr(env, addr, addr_len, peer);
+        log_dbg!("accept({:?}) => {:?} (peer {})", fd, new_fd, peer);
+        Ok(new_fd)
+    });
+    finish(env, "accept", fd, res)
+}
+
+fn connect(env: &mut Environment, fd: i32, addr: ConstPtr<sockaddr>, addr_len: socklen_t) -> i32 {
+    let addr = match read_sockaddr(env, addr, addr_len) {
+        Ok(addr) => addr,
+        Err(errno) => return finish(env, "connect", fd, Err(errno)),
+    };
+    log_dbg!("connect({:?}, {})", fd, addr);
+
+    let resumed = env.resumed_io(IoWait::Writable(fd)).is_some();
+    let res = env
+        .libc_state
+        .posix_io
+        .socket_for_fd(fd)
+        .and_then(|socket| {
+            if socket.kind == SOCK_DGRAM {
+                // This only sets the default destination.
+                let connected = socket
+                    .datagram_socket()
+                    .and_then(|datagram_socket| datagram_socket.connect(addr));
+                return connected.map(|_| true).map_err(|e| errno_for_io_error(&e));
+            }
+            match socket.state {
+                // If this thread blocked waiting for the connection attempt,
+                // it was already started, and might have finished since.
+                _ if resumed => return Ok(false),
+                SocketState::New | SocketState::Bound(_) => (),
+                SocketState::Connecting(_) => return Err(EALREADY),
+                SocketState::Connected(_) => return Err(EISCONN),
+                SocketState::Listening { .. } => return Err(EOPNOTSUPP),
+                SocketState::Datagram(_) => unreachable!(),
+            }
+            let bound_socket = match std::mem::replace(&mut socket.state, SocketState::New) {
+                SocketState::Bound(host_socket) => Some(host_socket),
+                _ => None,
+            };
+            let (sender, receiver) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                let stream = match bound_socket {
+                    // Connect from the address the socket is bound to.
+                    Some(host_socket) => host_socket
+                        .connect(&addr.into())
+                        .map(|()| TcpStream::from(host_socket)),
+                    None => TcpStream::connect(addr),
+                };
+                let _ = sender.send(stream);
+            });
+            socket.state = SocketState::Connecting(receiver);
+            socket.pending_error = None;
+            Ok(false)
+        });
+    let res = match res {
+        Ok(true) => Ok(0),
+        Ok(false) => {
+            // Wait for the connection attempt to finish. The host has its own
+            // timeout for this.
+            retry(env, fd, true, false, None, 0, |socket, _| {
+                socket.check_connecting();
+                match socket.state {
+                    SocketState::Connecting(_) => Err(ErrorKind::WouldBlock.into()),
+                    SocketState::Connected(_) => Ok(Ok(0)),
+                    _ => Ok(Err(socket.pending_error.take().unwrap())),
+                }
+            })
+            .map_err(|errno| {
+                // Non-blocking sockets continue connecting in the background.
+                if errno == EAGAIN {
+                    EINPROGRESS
+                } else {
+                    errno
+                }
+            })
+            .and_then(|res| res)
+        }
+        Err(errno) => Err(errno),
+    };
+    finish(env, "connect", fd, res)
+}
+
+fn shutdown(env: &mut Environment, fd: i32, how: i32) -> i32 {
+    let res = env
+        .libc_state
+        .posix_io
+        .socket_for_fd(fd)
+        .and_then(|socket| {
+            let how = match how {
+                SHUT_RD => Shutdown::Read,
+                SHUT_WR => Shutdown::Write,
+                SHUT_RDWR => Shutdown::Both,
+                _ => return Err(EINVAL),
+            };
+            let SocketState::Connected(ref stream) = socket.state else {
+                return Err(ENOTCONN);
+            };
+            stream.shutdown(how).map_err(|e| errno_for_io_error(&e))?;
+            Ok(0)
+        });
+    log_dbg!("shutdown({:?}, {}) => {:?}", fd, how, res);
+    finish(env, "shutdown", fd, res)
+}
+
+pub fn send(
+    env: &mut Environment,
+    fd: i32,
+    buffer: ConstVoidPtr,
+    length: GuestUSize,
+    flags: i32,
+) -> GuestISize {
+    sendto(env, fd, buffer, length, flags, ConstPtr::null(), 0)
+}
+
+fn sendto(
+    env: &mut Environment,
+    fd: i32,
+    buffer: ConstVoidPtr,
+    length: GuestUSize,
+    flags: i32,
+    dest_addr: ConstPtr<sockaddr>,
+    dest_len: socklen_t,
+) -> GuestISize {
+    if flags & !MSG_DONTWAIT != 0 {
+        log!("TODO: sendto() flags {:#x}", flags);
+    }
+    let dest_addr = if dest_addr.is_null() {
+        None
+    } else {
+        match read_sockaddr(env, dest_addr, dest_len) {
+            Ok(dest_addr) => Some(dest_addr),
+            Err(errno) => return finish(env, "sendto", fd, Err(errno)),
+        }
+    };
+
+    let timeout = env
+        .libc_state
+        .posix_io
+        .socket_for_fd(fd)
+        .ok()
+        .and_then(|socket| socket.send_timeout);
+    let res = retry(
+        env,
+        fd,
+        true,
+        flags & MSG_DONTWAIT != 0,
+        timeout,
+        0,
+        |socket, mem| {
+            let bytes = mem.bytes_at(buffer.cast(), length);
+            if socket.kind == SOCK_STREAM {
+                // The destination is ignored for connected sockets.
+                let SocketState::Connected(ref mut stream) = socket.state else {
+                    return Err(ErrorKind::NotConnected.into());
+                };
+                stream.write(bytes)
+            } else if let Some(dest_addr) = dest_addr {
+                socket.datagram_socket()?.send_to(bytes, dest_addr)
+            } else {
+                match socket.state {
+                    SocketState::Datagram(ref datagram_socket) => datagram_socket.send(bytes),
+                    _ => Err(ErrorKind::NotConnected.into()),
+                }
+            }
+        },
+    );
+    // Sending a datagram without a destination is a different error than
+    // sending on an unconnected stream.
+    let res = res.map_err(|errno| match errno {
+        ENOTCONN if dest_addr.is_none() && length != 0 => {
+            let is_datagram = env
+                .libc_state
+                .posix_io
+                .socket_for_fd(fd)
+                .is_ok_and(|socket| socket.kind == SOCK_DGRAM);
+            if is_datagram {
+                EDESTADDRREQ
+            } else {
+                ENOTCONN
+            }
+        }
+        errno => errno,
+    });
+    let res = res.map(|sent| GuestISize::try_from(sent).unwrap());
+    log_dbg!("sendto({:?}, {:?}, {:#x}) => {:?}", fd, buffer, length, res);
+    finish(env, "sendto", fd, res)
+}
+
+pub fn recv(
+    env: &mut Environment,
+    fd: i32,
+    buffer: MutVoidPtr,
+    length: GuestUSize,
+    flags: i32,
+) -> GuestISize {
+    recvfrom(
+        env,
+        fd,
+        buffer,
+        length,
+        flags,
+        MutPtr::null(),
+        MutPtr::null(),
+    )
+}
+
+fn recvfrom(
+    env: &mut Environment,
+    fd: i32,
+    buffer: MutVoidPtr,
+    length: GuestUSize,
+    flags: i32,
+    addr: MutPtr<sockaddr>,
+    addr_len: MutPtr<socklen_t>,
+) -> GuestISize {
+    if flags & !(MSG_PEEK | MSG_WAITALL | MSG_DONTWAIT) != 0 {
+        log!("TODO: recvfrom() flags {:#x}", flags);
+    }
+    let peek = flags & MSG_PEEK != 0;
+    let wait_all = flags & MSG_WAITALL != 0 && !peek;
+
+    let timeout = env
+        .libc_state
+        .posix_io
+        .socket_for_fd(fd)
+        .ok()
+        .and_then(|socket| socket.receive_timeout);
+    // With MSG_WAITALL, this might be called again after blocking when part of
+    // the data was already received.
+    let mut received: GuestUSize = env
+        .resumed_io(IoWait::Readable(fd))
+        .map_or(0, |block| block.progress);
+    let mut res;
+    loop {
+        res = retry(
+            env,
+            fd,
+            false,
+            flags & MSG_DONTWAIT != 0,
+            timeout,
+            received,
+            |socket, mem| {
+                let bytes = mem.bytes_at_mut(buffer.cast::<u8>() + received, length - received);
+                match socket.state {
+                    SocketState::Connected(ref mut stream) if peek => {
+                        stream.peek(bytes).map(|count| (count, None))
+                    }
+                    SocketState::Connected(ref mut stream) => {
+                        stream.read(bytes).map(|count| (count, None))
+                    }
+                    SocketState::Datagram(ref datagram_socket) if peek => datagram_socket
+                        .peek_from(bytes)
+                        .map(|(count, from)| (count, Some(from))),
+                    SocketState::Datagram(ref datagram_socket) => datagram_socket
+                        .recv_from(bytes)
+                        .map(|(count, from)| (count, Some(from))),
+                    // Receiving on a UDP socket that isn't bound yet waits
+                    // forever, so it might as well be bound.
+                    SocketState::New if socket.kind == SOCK_DGRAM => {
+                        socket.datagram_socket()?;
+                        Err(ErrorKind::WouldBlock.into())
+                    }
+                    _ => Err(ErrorKind::NotConnected.into()),
+                }
+            },
+        );
+        let Ok((count, from)) = res else {
+            break;
+        };
+        if let Some(from) = from {
+            write_sockaddr(env, addr, addr_len, from);
+        }
+        received += GuestUSize::try_from(count).unwrap();
+        // MSG_WAITALL keeps reading until the buffer is full, unless the end
+        // of the stream is reached.
+        if !wait_all || count == 0 || received == length {
+            break;
+        }
+    }
+    let res = match res {
+        Ok(_) => Ok(received),
+        Err(errno) if env.is_blocked_on_io() => Err(errno),
+        // Data that was already received isn't lost if a later read fails.
+        Err(_) if received != 0 => Ok(received),
+        Err(errno) => Err(errno),
+    };
+    let res = res.map(|received| GuestISize::try_from(received).unwrap());
+    log_dbg!(
+        "recvfrom({:?}, {:?}, {:#x}, {:#x}) => {:?}",
+        fd,
+        buffer,
+        length,
+        flags,
+        res
+    );
+    finish(env, "recvfrom", fd, res)
+}
+
+fn setsockopt(
+    env: &mut Environment,
+    fd: i32,
+    level: i32,
+    name: i32,
+    value: ConstVoidPtr,
+    value_len: socklen_t,
+) -> i32 {
+    let res = match (level, name) {
+        (SOL_SOCKET, SO_SNDTIMEO | SO_RCVTIMEO) => {
+            if value.is_null() || value_len < guest_size_of::<timeval>() {
+                Err(EINVAL)
+            } else {
+                let timeout: timeval = env.mem.read(value.cast());
+                // A zero timeout means no timeout.
+                let timeout = timeout.to_duration().filter(|timeout| !timeout.is_zero());
+                env.libc_state
+                    .posix_io
+                    .socket_for_fd(fd)
+                    .map(|socket| match name {
+                        SO_SNDTIMEO => socket.send_timeout = timeout,
+                        _ => socket.receive_timeout = timeout,
+                    })
+            }
+        }
+        _ if value.is_null() || value_len < guest_size_of::<i32>() => {
+            // e.g. SO_LINGER, which takes a struct.
+            log!(
+                "TODO: setsockopt({:?}, {:#x}, {:#x}) with a {} byte value",
+                fd,
+                level,
+                name,
+                value_len
+            );
+            Ok(())
+        }
+        _ => {
+            let int_value: i32 = env.mem.read(value.cast());
+            env.libc_state
+                .posix_io
+                .socket_for_fd(fd)
+                .and_then(|socket| {
+                    socket.options.insert((level, name), int_value);
+                    socket.apply_options().map_err(|e| errno_for_io_error(&e))
+                })
+        }
+    };
+    log_dbg!(
+        "setsockopt({:?}, {:#x}, {:#x}, {:?}, {}) => {:?}",
+        fd,
+        level,
+        name,
+        value,
+        value_len,
+        res
+    );
+    let res = res.map(|()| 0);
+    finish(env, "setsockopt", fd, res)
+}
+
+fn getsockopt(
+    env: &mut Environment,
+    fd: i32,
+    level: i32,
+    name: i32,
+    value: MutVoidPtr,
+    value_len: MutPtr<socklen_t>,
+) -> i32 {
+    if value.is_null() || value_len.is_null() {
+        return finish(env, "getsockopt", fd, Err(EFAULT));
+    }
+    let socket = match env.libc_state.posix_io.socket_for_fd(fd) {
+        Ok(socket) => socket,
+        Err(errno) => return finish(env, "getsockopt", fd, Err(errno)),
+    };
+    let timeval_value = match (level, name) {
+        (SOL_SOCKET, SO_SNDTIMEO) => Some(socket.send_timeout),
+        (SOL_SOCKET, SO_RCVTIMEO) => Some(socket.receive_timeout),
+        _ => None,
+    };
+    if let Some(timeout) = timeval_value {
+        let timeout = timeval::from_duration(timeout.unwrap_or_default());
+        if env.mem.read(value_len) < guest_size_of::<timeval>() {
+            return finish(env, "getsockopt", fd, Err(EINVAL));
+        }
+        env.mem.write(value.cast(), timeout);
+        env.mem.write(value_len, guest_size_of::<timeval>());
+        return 0;
+    }
+
+    let int_value = match (level, name) {
+        (SOL_SOCKET, SO_ERROR) => {
+            socket.check_connecting();
+            socket.pending_error.take().unwrap_or(0)
+        }
+        (SOL_SOCKET, SO_TYPE) => socket.kind,
+        _ => socket.options.get(&(level, name)).copied().unwrap_or(0),
+    };
+    if env.mem.read(value_len) < guest_size_of::<i32>() {
+        return finish(env, "getsockopt", fd, Err(EINVAL));
+    }
+    env.mem.write(value.cast(), int_value);
+    env.mem.write(value_len, guest_size_of::<i32>());
+    log_dbg!(
+        "getsockopt({:?}, {:#x}, {:#x}) => {}",
+        fd,
+        level,
+        name,
+        int_value
+    );
+    0
+}
+
+fn getsockname(
+    env: &mut Environment,
+    fd: i32,
+    addr: MutPtr<sockaddr>,
+    addr_len: MutPtr<socklen_t>,
+) -> i32 {
+    let res = env
+        .libc_state
+        .posix_io
+        .socket_for_fd(fd)
+        .and_then(|socket| {
+            match socket.state {
+                SocketState::New | SocketState::Connecting(_) => {
+                    Ok(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0).into())
+                }
+                SocketState::Bound(ref host_socket) => host_socket
+                    .local_addr()
+                    .map(|local_addr| local_addr.as_socket().unwrap()),
+                SocketState::Listening { ref listener, .. } => listener.local_addr(),
+                SocketState::Connected(ref stream) => stream.local_addr(),
+                SocketState::Datagram(ref socket) => socket.local_addr(),
+            }
+            .map_err(|e| errno_for_io_error(&e))
+        });
+    let res = res.map(|local_addr| {
+        write_sockaddr(env, addr, addr_len, local_addr);
+        0
+    });
+    finish(env, "getsockname", fd, res)
+}
+
+fn getpeername(
+    env: &mut Environment,
+    fd: i32,
+    addr: MutPtr<sockaddr>,
+    addr_len: MutPtr<socklen_t>,
+) -> i32 {
+    let res = env
+        .libc_state
+        .posix_io
+        .socket_for_fd(fd)
+        .and_then(|socket| match socket.state {
+            SocketState::Connected(ref stream) => {
+                stream.peer_addr().map_err(|e| errno_for_io_error(&e))
+            }
+            SocketState::Datagram(ref socket) => {
+                socket.peer_addr().map_err(|e| errno_for_io_error(&e))
+            }
+            _ => Err(ENOTCONN),
+        });
+    let res = res.map(|peer_addr| {
+        write_sockaddr(env, addr, addr_len, peer_addr);
+        0
+    });
+    finish(env, "getpeername", fd, res)
+}
+
+/// Helper for `ioctl()` with `FIONREAD`: the number of bytes that can be read
+/// without blocking.
+pub(in crate::libc) fn bytes_available(env: &mut Environment, fd: i32) -> Result<i32, i32> {
+    let socket = env.libc_state.posix_io.socket_for_fd(fd)?;
+    // There's no way to get this directly, so the data is peeked at instead.
+    let mut buffer = vec![0u8; 0x10000];
+    let res = match socket.state {
+        SocketState::Connected(ref stream) => stream.peek(&mut buffer),
+        SocketState::Datagram(ref socket) => socket.peek_from(&mut buffer).map(|(count, _)| count),
+        _ => Ok(0),
+    };
+    match res {
+        Ok(count) => Ok(count.try_into().unwrap()),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(0),
+        Err(e) => Err(errno_for_io_error(&e)),
+    }
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(socket(_, _, _)),
+    export_c_func!(bind(_, _, _)),
+    export_c_func!(listen(_, _)),
+    export_c_func!(accept(_, _, _)),
+    export_c_func!(connect(_, _, _)),
+    export_c_func!(shutdown(_, _)),
+    export_c_func!(send(_, _, _, _)),
+    export_c_func!(sendto(_, _, _, _, _, _)),
+    export_c_func!(recv(_, _, _, _)),
+    export_c_func!(recvfrom(_, _, _, _, _, _)),
+    export_c_func!(setsockopt(_, _, _, _, _)),
+    export_c_func!(getsockopt(_, _, _, _, _)),
+    export_c_func!(getsockname(_, _, _)),
+    export_c_func!(getpeername(_, _, _)),
+];
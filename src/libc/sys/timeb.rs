@@ -8,6 +8,7 @@
 use crate::dyld::FunctionExports;
 use crate::libc::time::time_t;
 use crate::mem::{MutPtr, SafeRead};
+use crate::replay;
 use crate::{export_c_func, Environment};
 use std::time::SystemTime;
 
@@ -27,7 +28,7 @@ struct timeb {
 unsafe impl SafeRead for timeb {}
 
 fn ftime(env: &mut Environment, tb: MutPtr<timeb>) -> i32 {
-    let epoch_duration = SystemTime::now()
+    let epoch_duration = replay::system_time(env)
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
     let time64 = epoch_duration.as_secs();
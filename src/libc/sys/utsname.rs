@@ -8,16 +8,41 @@
 use crate::dyld::FunctionExports;
 use crate::environment::Environment;
 use crate::export_c_func;
-use crate::mem::MutPtr;
+use crate::mem::{MutPtr, SafeRead};
+
+const _SYS_NAMELEN: usize = 256;
 
-// TODO: struct definition
 #[allow(non_camel_case_types)]
-struct utsname {}
+#[repr(C, packed)]
+struct utsname {
+    sysname: [u8; _SYS_NAMELEN],
+    nodename: [u8; _SYS_NAMELEN],
+    release: [u8; _SYS_NAMELEN],
+    version: [u8; _SYS_NAMELEN],
+    machine: [u8; _SYS_NAMELEN],
+}
+unsafe impl SafeRead for utsname {}
+
+fn uname(env: &mut Environment, name: MutPtr<utsname>) -> i32 {
+    fn field(value: &str) -> [u8; _SYS_NAMELEN] {
+        let mut field = [0u8; _SYS_NAMELEN];
+        // Always leave a null terminator.
+        let len = value.len().min(_SYS_NAMELEN - 1);
+        field[..len].copy_from_slice(&value.as_bytes()[..len]);
+        field
+    }
 
-fn uname(_env: &mut Environment, name: MutPtr<utsname>) -> i32 {
-    log!("TODO: uname({:?}), returning -1", name);
-    // TODO: set errno
-    -1
+    let device = env.options.device;
+    let value = utsname {
+        sysname: field("Darwin"),
+        nodename: field(&device.host_name()),
+        release: field(device.darwin_release),
+        version: field(&device.darwin_version()),
+        machine: field(device.machine),
+    };
+    log_dbg!("uname({:?})", name);
+    env.mem.write(name, value);
+    0 // success
 }
 
 pub const FUNCTIONS: FunctionExports = &[export_c_func!(uname(_))];
@@ -4,11 +4,140 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! `sys/sysctl.h`
+//!
+//! Only the values describing the device are supported. They come from the
+//! [crate::device::DeviceProfile] selected with `--device=`.
 
 use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{set_errno, EINVAL, ENOENT, ENOMEM, EPERM};
 use crate::mem::{ConstPtr, GuestUSize, MutPtr, MutVoidPtr};
 use crate::Environment;
 
+const CTL_KERN: i32 = 1;
+const CTL_HW: i32 = 6;
+
+const KERN_OSTYPE: i32 = 1;
+const KERN_OSRELEASE: i32 = 2;
+const KERN_VERSION: i32 = 4;
+const KERN_HOSTNAME: i32 = 10;
+const KERN_OSVERSION: i32 = 65;
+
+const HW_MACHINE: i32 = 1;
+const HW_MODEL: i32 = 2;
+const HW_NCPU: i32 = 3;
+const HW_BYTEORDER: i32 = 4;
+const HW_PHYSMEM: i32 = 5;
+const HW_USERMEM: i32 = 6;
+const HW_PAGESIZE: i32 = 7;
+const HW_CPU_FREQ: i32 = 15;
+const HW_MEMSIZE: i32 = 24;
+const HW_AVAILCPU: i32 = 25;
+
+/// Names of the supported values and their management information base (MIB)
+/// numbers.
+const NAMES: &[(&str, [i32; 2])] = &[
+    ("kern.ostype", [CTL_KERN, KERN_OSTYPE]),
+    ("kern.osrelease", [CTL_KERN, KERN_OSRELEASE]),
+    ("kern.version", [CTL_KERN, KERN_VERSION]),
+    ("kern.hostname", [CTL_KERN, KERN_HOSTNAME]),
+    ("kern.osversion", [CTL_KERN, KERN_OSVERSION]),
+    ("hw.machine", [CTL_HW, HW_MACHINE]),
+    ("hw.model", [CTL_HW, HW_MODEL]),
+    ("hw.ncpu", [CTL_HW, HW_NCPU]),
+    ("hw.byteorder", [CTL_HW, HW_BYTEORDER]),
+    ("hw.physmem", [CTL_HW, HW_PHYSMEM]),
+    ("hw.usermem", [CTL_HW, HW_USERMEM]),
+    ("hw.pagesize", [CTL_HW, HW_PAGESIZE]),
+    ("hw.cpufrequency", [CTL_HW, HW_CPU_FREQ]),
+    ("hw.memsize", [CTL_HW, HW_MEMSIZE]),
+    ("hw.availcpu", [CTL_HW, HW_AVAILCPU]),
+];
+
+enum Value {
+    Int(i32),
+    Quad(i64),
+    String(String),
+}
+
+fn value_for_mib(env: &Environment, mib: [i32; 2]) -> Option<Value> {
+    let device = env.options.device;
+    Some(match mib {
+        [CTL_KERN, KERN_OSTYPE] => Value::String("Darwin".to_string()),
+        [CTL_KERN, KERN_OSRELEASE] => Value::String(device.darwin_release.to_string()),
+        [CTL_KERN, KERN_VERSION] => Value::String(device.darwin_version()),
+        [CTL_KERN, KERN_HOSTNAME] => Value::String(device.host_name()),
+        [CTL_KERN, KERN_OSVERSION] => Value::String(device.os_build.to_string()),
+        [CTL_HW, HW_MACHINE] => Value::String(device.machine.to_string()),
+        [CTL_HW, HW_MODEL] => Value::String(device.board.to_string()),
+        [CTL_HW, HW_NCPU | HW_AVAILCPU] => Value::Int(device.cpu_count as i32),
+        [CTL_HW, HW_BYTEORDER] => Value::Int(1234),
+        [CTL_HW, HW_PHYSMEM | HW_USERMEM] => Value::Int(device.memory_size as i32),
+        [CTL_HW, HW_PAGESIZE] => Value::Int(0x1000),
+        [CTL_HW, HW_CPU_FREQ] => Value::Int(device.cpu_frequency as i32),
+        [CTL_HW, HW_MEMSIZE] => Value::Quad(device.memory_size as i64),
+        _ => return None,
+    })
+}
+
+/// Shared part of [sysctl] and [sysctlbyname]: copy the value to the buffer
+/// if there is one, and get its size.
+fn read_value(
+    env: &mut Environment,
+    value: Value,
+    oldp: MutVoidPtr,
+    oldlenp: MutPtr<GuestUSize>,
+    newp: MutVoidPtr,
+) -> i32 {
+    // All the supported values are read-only.
+    if !newp.is_null() {
+        set_errno(env, EPERM);
+        return -1;
+    }
+    if oldlenp.is_null() {
+        if oldp.is_null() {
+            return 0;
+        }
+        set_errno(env, EINVAL);
+        return -1;
+    }
+
+    let available = if oldp.is_null() {
+        None
+    } else {
+        Some(env.mem.read(oldlenp))
+    };
+
+    let bytes = match value {
+        // Like on Darwin, an integer can be read as either 32-bit or 64-bit.
+        Value::Int(value) if available == Some(8) => (value as i64).to_le_bytes().to_vec(),
+        Value::Quad(value) if available == Some(4) => (value as i32).to_le_bytes().to_vec(),
+        Value::Int(value) => value.to_le_bytes().to_vec(),
+        Value::Quad(value) => value.to_le_bytes().to_vec(),
+        Value::String(value) => {
+            let mut bytes = value.into_bytes();
+            bytes.push(b'\0');
+            bytes
+        }
+    };
+    let size: GuestUSize = bytes.len().try_into().unwrap();
+
+    let Some(available) = available else {
+        env.mem.write(oldlenp, size);
+        return 0;
+    };
+    // If the buffer is too small, as much as fits is copied.
+    let copied = size.min(available);
+    env.mem
+        .bytes_at_mut(oldp.cast(), copied)
+        .copy_from_slice(&bytes[..copied as usize]);
+    env.mem.write(oldlenp, copied);
+    if copied < size {
+        set_errno(env, ENOMEM);
+        return -1;
+    }
+    0 // success
+}
+
 fn sysctl(
     env: &mut Environment,
     name: MutPtr<i32>,
@@ -18,19 +147,42 @@ fn sysctl(
     newp: MutVoidPtr,
     newlen: GuestUSize,
 ) -> i32 {
-    log!(
-        "TODO: sysctl({:?}, {:#x}, {:?}, {:?}, {:?}, {:x})",
+    let mib: Vec<i32> = (0..name_len).map(|i| env.mem.read(name + i)).collect();
+    let value = <[i32; 2]>::try_from(&mib[..])
+        .ok()
+        .and_then(|mib| value_for_mib(env, mib));
+    let Some(value) = value else {
+        // Apps sometimes use this to check whether a debugger is attached
+        // (KERN_PROC), and treat an error as meaning there is one, so this
+        // pretends to succeed with no data.
+        log!(
+            "TODO: sysctl({:?}, {:#x}, {:?}, {:?}, {:?}, {:x}) for {:?}, returning no data",
+            name,
+            name_len,
+            oldp,
+            oldlenp,
+            newp,
+            newlen,
+            mib
+        );
+        if !oldlenp.is_null() {
+            env.mem.write(oldlenp, 0);
+        }
+        return 0; // success
+    };
+    let result = read_value(env, value, oldp, oldlenp, newp);
+    log_dbg!(
+        "sysctl({:?}, {:#x}, {:?}, {:?}, {:?}, {:x}) for {:?} => {}",
         name,
         name_len,
         oldp,
         oldlenp,
         newp,
-        newlen
+        newlen,
+        mib,
+        result
     );
-    assert!(!oldp.is_null() && !oldlenp.is_null()); // TODO
-    assert!(newp.is_null()); // TODO
-    env.mem.write(oldlenp, 0);
-    0 // success
+    result
 }
 
 fn sysctlbyname(
@@ -41,32 +193,64 @@ fn sysctlbyname(
     newp: MutVoidPtr,
     newlen: GuestUSize,
 ) -> i32 {
-    let name_str = env.mem.cstr_at_utf8(name).unwrap();
+    let name_str = env.mem.cstr_at_utf8(name).unwrap().to_owned();
+    let value = NAMES
+        .iter()
+        .find(|&&(known_name, _)| known_name == name_str)
+        .and_then(|&(_, mib)| value_for_mib(env, mib));
+    let Some(value) = value else {
+        log!(
+            "TODO: sysctlbyname({:?}, {:?}, {:?}, {:?}, {:x}), returning ENOENT",
+            name_str,
+            oldp,
+            oldlenp,
+            newp,
+            newlen
+        );
+        set_errno(env, ENOENT);
+        return -1;
+    };
+    let result = read_value(env, value, oldp, oldlenp, newp);
     log_dbg!(
-        "TODO: sysctlbyname({:?}, {:?}, {:?}, {:?}, {:x})",
+        "sysctlbyname({:?}, {:?}, {:?}, {:?}, {:x}) => {}",
         name_str,
         oldp,
         oldlenp,
         newp,
-        newlen
+        newlen,
+        result
     );
-    assert_eq!(name_str, "hw.machine");
-    if oldp.is_null() && newp.is_null() {
-        // "iPhone1,1"
-        env.mem.write(oldlenp, 10);
-        return 0;
+    result
+}
+
+fn sysctlnametomib(
+    env: &mut Environment,
+    name: ConstPtr<u8>,
+    mibp: MutPtr<i32>,
+    sizep: MutPtr<GuestUSize>,
+) -> i32 {
+    let name_str = env.mem.cstr_at_utf8(name).unwrap();
+    let mib = NAMES
+        .iter()
+        .find(|&&(known_name, _)| known_name == name_str)
+        .map(|&(_, mib)| mib);
+    let Some(mib) = mib else {
+        log!("TODO: sysctlnametomib({:?}), returning ENOENT", name_str);
+        set_errno(env, ENOENT);
+        return -1;
+    };
+    if env.mem.read(sizep) < 2 {
+        set_errno(env, ENOMEM);
+        return -1;
     }
-    assert!(!oldp.is_null() && !oldlenp.is_null());
-    assert!(newp.is_null());
-    let hw_machine_str = env.mem.alloc_and_write_cstr(b"iPhone1,1");
-    assert_eq!(env.mem.read(oldlenp), 10);
-    env.mem
-        .memmove(oldp, hw_machine_str.cast().cast_const(), 10);
-    env.mem.free(hw_machine_str.cast());
+    env.mem.write(mibp, mib[0]);
+    env.mem.write(mibp + 1, mib[1]);
+    env.mem.write(sizep, 2);
     0 // success
 }
 
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(sysctl(_, _, _, _, _, _)),
     export_c_func!(sysctlbyname(_, _, _, _, _)),
+    export_c_func!(sysctlnametomib(_, _, _)),
 ];
@@ -7,8 +7,10 @@
 
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::mem::{guest_size_of, ConstPtr, MutPtr, Ptr, SafeRead};
+use crate::replay;
+use crate::save_state::impl_SaveState;
 use crate::Environment;
-use std::time::{Duration, Instant, SystemTime};
+use std::time::{Duration, SystemTime};
 
 #[derive(Default)]
 pub struct State {
@@ -17,6 +19,10 @@ pub struct State {
     /// `localtime`. The standard allows calls to either to overwrite it.
     gmtime_tmp: Option<MutPtr<tm>>,
 }
+impl_SaveState!(State {
+    y2k38_warned,
+    gmtime_tmp,
+});
 
 // time.h (C)
 
@@ -30,14 +36,14 @@ type clock_t = u64;
 const CLOCKS_PER_SEC: clock_t = 1000000;
 
 fn clock(env: &mut Environment) -> clock_t {
-    Instant::now()
+    replay::now(env)
         .duration_since(env.startup_time)
         .as_secs()
         .wrapping_mul(CLOCKS_PER_SEC)
 }
 
 fn time(env: &mut Environment, out: MutPtr<time_t>) -> time_t {
-    let time64 = SystemTime::now()
+    let time64 = replay::system_time(env)
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
         .as_secs();
@@ -274,13 +280,27 @@ fn localtime(env: &mut Environment, timestamp: ConstPtr<time_t>) -> MutPtr<tm> {
 // sys/time.h (POSIX)
 
 #[allow(non_camel_case_types)]
-type suseconds_t = i32;
+pub type suseconds_t = i32;
 
 #[allow(non_camel_case_types)]
 #[repr(C, packed)]
-struct timeval {
-    tv_sec: time_t,
-    tv_usec: suseconds_t,
+pub struct timeval {
+    pub tv_sec: time_t,
+    pub tv_usec: suseconds_t,
+}
+impl timeval {
+    /// Convert to a [Duration], or [None] if it's negative.
+    pub fn to_duration(&self) -> Option<Duration> {
+        let secs = u64::try_from(self.tv_sec).ok()?;
+        let micros = u64::try_from(self.tv_usec).ok()?;
+        Some(Duration::from_secs(secs) + Duration::from_micros(micros))
+    }
+    pub fn from_duration(duration: Duration) -> timeval {
+        timeval {
+            tv_sec: duration.as_secs().try_into().unwrap_or(time_t::MAX),
+            tv_usec: duration.subsec_micros().try_into().unwrap(),
+        }
+    }
 }
 unsafe impl SafeRead for timeval {}
 
@@ -319,7 +339,7 @@ fn gettimeofday(
         return 0; // success
     }
 
-    let time = SystemTime::now()
+    let time = replay::system_time(env)
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
 
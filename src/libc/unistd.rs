@@ -8,6 +8,7 @@
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::fs::GuestPath;
 use crate::libc::posix_io::{FileDescriptor, STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
+use crate::libc::stdlib;
 use crate::mem::ConstPtr;
 use crate::Environment;
 use std::time::Duration;
@@ -32,9 +33,9 @@ fn usleep(env: &mut Environment, useconds: useconds_t) -> i32 {
 }
 
 #[allow(non_camel_case_types)]
-type pid_t = i32;
+pub type pid_t = i32;
 
-fn getpid(_env: &mut Environment) -> pid_t {
+pub fn getpid(_env: &mut Environment) -> pid_t {
     // Not a real value, since touchHLE only simulates a single process.
     // PID 0 would be init, which is a bit unrealistic, so let's go with 1.
     1
@@ -76,12 +77,9 @@ fn access(env: &mut Environment, path: ConstPtr<u8>, mode: i32) -> i32 {
     }
 }
 
-fn unlink(env: &mut Environment, path: ConstPtr<u8>) -> i32 {
-    log!(
-        "TODO: unlink('{}') => -1",
-        env.mem.cstr_at_utf8(path).unwrap()
-    );
-    -1
+fn _exit(env: &mut Environment, status: i32) {
+    echo!("App called _exit(), exiting.");
+    stdlib::exit_app_without_handlers(env, status);
 }
 
 pub const FUNCTIONS: FunctionExports = &[
@@ -91,5 +89,5 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(getppid()),
     export_c_func!(isatty(_)),
     export_c_func!(access(_, _)),
-    export_c_func!(unlink(_)),
+    export_c_func!(_exit(_)),
 ];
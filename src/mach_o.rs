@@ -19,8 +19,8 @@
 //! - The [source code of the mach_object crate](https://docs.rs/mach_object/latest/src/mach_object/commands.rs.html) has useful comments that don't show up in the generated documentation, e.g. around `DySymTab`.
 
 use crate::abi::GuestFunction;
-use crate::fs::{Fs, GuestPath};
-use crate::mem::{Mem, Ptr};
+use crate::fs::{Fs, GuestPath, GuestPathBuf};
+use crate::mem::{Mem, Protection, Ptr};
 use mach_object::{
     cpu_subtype_t, vm_prot_t, DyLib, LoadCommand, MachCommand, OFile, Symbol, SymbolIter,
     ThreadState, N_ARM_THUMB_DEF, S_LAZY_SYMBOL_POINTERS, S_MOD_INIT_FUNC_POINTERS,
@@ -38,6 +38,12 @@ const VM_PROT_EXECUTE: vm_prot_t = 4;
 pub struct MachO {
     /// Name (for debugging purposes)
     pub name: String,
+    /// Path the binary was loaded from, if it was loaded from a file.
+    pub path: Option<GuestPathBuf>,
+    /// Address of the `__TEXT` segment, which begins with the Mach-O header.
+    /// Binaries are always loaded at their preferred addresses, so there's no
+    /// slide to apply to this or any other address.
+    pub text_segment_addr: Option<u32>,
     /// Paths of dynamic libraries referenced by the binary.
     pub dynamic_libraries: Vec<String>,
     /// Metadata related to sections.
@@ -46,6 +52,10 @@ pub struct MachO {
     /// can look things up quickly. Thumb function symbols always have the Thumb
     /// bit set.
     pub exported_symbols: HashMap<String, u32>,
+    /// All the symbols defined by the binary, including local ones, sorted by
+    /// address. This is for debugging purposes, e.g. stack traces. The Thumb
+    /// bit is never set.
+    pub symbols_by_addr: Vec<(u32, String)>,
     /// List of addresses and names of external relocations for the dynamic
     /// linker to resolve.
     pub external_relocations: Vec<(u32, String)>,
@@ -306,6 +316,7 @@ impl MachO {
         // Info used for the result
         let mut dynamic_libraries = Vec::new();
         let mut exported_symbols = HashMap::new();
+        let mut symbols_by_addr = Vec::new();
         let mut indirect_undef_symbols: Vec<Option<String>> = Vec::new();
         let mut external_relocations: Vec<(u32, String)> = Vec::new();
         let mut entry_point_pc: Option<u32> = None;
@@ -360,6 +371,13 @@ impl MachO {
 
                     if load_me {
                         into_mem.reserve(vmaddr, vmsize);
+                        // The segment's maximum protection (maxprot) isn't
+                        // enforced for mprotect().
+                        into_mem.set_protection(
+                            vmaddr,
+                            vmsize,
+                            Protection::from_bits(initprot as u32),
+                        );
 
                         // If filesize is less than vmsize, the rest of the
                         // segment should be filled with zeroes. We are assuming
@@ -399,13 +417,17 @@ impl MachO {
                             }
                             if let Symbol::Defined {
                                 name: Some(name),
-                                external: true,
+                                external,
                                 entry,
                                 desc,
                                 ..
                             } = symbol
                             {
                                 let entry: u32 = entry.try_into().unwrap();
+                                symbols_by_addr.push((entry, name.to_string()));
+                                if !external {
+                                    continue;
+                                }
                                 let entry = if desc & N_ARM_THUMB_DEF != 0 {
                                     entry | GuestFunction::THUMB_BIT
                                 } else {
@@ -606,11 +628,16 @@ impl MachO {
             })
             .collect();
 
+        symbols_by_addr.sort();
+
         Ok(MachO {
             name,
+            path: None,
+            text_segment_addr: text_segment_base,
             dynamic_libraries,
             sections,
             exported_symbols,
+            symbols_by_addr,
             external_relocations,
             entry_point_pc,
         })
@@ -625,12 +652,14 @@ impl MachO {
         into_mem: &mut Mem,
     ) -> Result<MachO, &'static str> {
         let name = path.as_ref().file_name().unwrap().to_string();
-        Self::load_from_bytes(
+        let mut bin = Self::load_from_bytes(
             &fs.read(path.as_ref())
                 .map_err(|_| "Could not read executable file")?,
             into_mem,
             name,
-        )
+        )?;
+        bin.path = Some(path.as_ref().to_owned());
+        Ok(bin)
     }
 
     /// Get a section by its name (`&str`) or type ([SectionType]).
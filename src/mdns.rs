@@ -0,0 +1,1448 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! A minimal multicast DNS (mDNS) responder and querier, used to implement
+//! Bonjour (DNS Service Discovery) for the guest app.
+//!
+//! Only what's needed to publish, browse for and resolve services over IPv4 is
+//! implemented. This is enough for two touchHLE instances on the same machine
+//! or local network, or touchHLE and a real device, to find each other.
+//!
+//! The networking happens on a host thread, which operations talk to using
+//! channels, so the guest can poll for results like with [crate::http].
+//!
+//! Resources:
+//! - [RFC 6762: Multicast DNS](https://www.rfc-editor.org/rfc/rfc6762)
+//! - [RFC 6763: DNS-Based Service Discovery](https://www.rfc-editor.org/rfc/rfc6763)
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::time::{Duration, Instant};
+
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// In a question, this asks for a unicast response. In a record, this tells
+/// the receiver to flush other cached records with the same name and type.
+const CLASS_TOP_BIT: u16 = 0x8000;
+
+/// TTL for records tied to the host name (`SRV` and `A`), in seconds.
+const HOST_RECORD_TTL: u32 = 120;
+/// TTL for other records (`PTR` and `TXT`), in seconds.
+const OTHER_RECORD_TTL: u32 = 4500;
+/// TTL limit for responses to "legacy" queriers not using port 5353.
+const LEGACY_UNICAST_TTL: u32 = 10;
+
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+const PROBE_COUNT: u8 = 3;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+const ANNOUNCE_COUNT: u8 = 2;
+const FIRST_QUERY_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_QUERY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How long the thread waits for a packet before checking for commands and
+/// timers again.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A domain name as a list of labels, e.g. `["_http", "_tcp", "local"]`.
+/// Labels can contain any characters, including dots.
+pub type Name = Vec<String>;
+
+/// Labels are compared case-insensitively.
+fn names_equal(a: &[String], b: &[String]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+/// Split a dot-separated name like `_http._tcp.local.` into labels. Escapes
+/// aren't supported, which is fine for service types and domains.
+pub fn parse_name(name: &str) -> Name {
+    name.split('.')
+        .filter(|label| !label.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Format a name like `My\.Game._http._tcp.local.`, escaping dots and
+/// backslashes within labels the way Apple's `DNSServiceConstructFullName()`
+/// does.
+pub fn format_name(name: &[String]) -> String {
+    let mut string = String::new();
+    for label in name {
+        for c in label.chars() {
+            match c {
+                '.' | '\\' => {
+                    string.push('\\');
+                    string.push(c);
+                }
+                _ if c.is_ascii_control() => string.push_str(&format!("\\{:03}", c as u32)),
+                _ => string.push(c),
+            }
+        }
+        string.push('.');
+    }
+    string
+}
+
+/// The name of a service instance, e.g. `My Game` of type `_mygame._tcp` in
+/// the domain `local`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceName {
+    pub name: String,
+    /// e.g. `["_mygame", "_tcp"]`.
+    pub service_type: Name,
+    /// e.g. `["local"]`.
+    pub domain: Name,
+}
+impl ServiceName {
+    fn service_type_name(&self) -> Name {
+        [&self.service_type[..], &self.domain[..]].concat()
+    }
+
+    pub fn full_name(&self) -> Name {
+        let mut name = vec![self.name.clone()];
+        name.extend(self.service_type_name());
+        name
+    }
+
+    fn from_full_name(full_name: &[String]) -> Option<ServiceName> {
+        if full_name.len() < 4 {
+            return None;
+        }
+        Some(ServiceName {
+            name: full_name[0].clone(),
+            service_type: full_name[1..3].to_vec(),
+            domain: full_name[3..].to_vec(),
+        })
+    }
+}
+
+/// Information about a service, found by resolving it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resolved {
+    /// e.g. `["touchHLE-1234abcd", "local"]`.
+    pub host_name: Name,
+    pub port: u16,
+    /// The TXT record in its encoded form, a series of length-prefixed
+    /// strings.
+    pub txt: Vec<u8>,
+    pub addresses: Vec<Ipv4Addr>,
+}
+
+#[derive(Debug)]
+pub enum Event {
+    /// The service was registered, possibly with a new name to avoid a
+    /// conflict. This can happen again if a conflict happens later.
+    Registered(ServiceName),
+    /// Another service has the name and renaming wasn't allowed. The
+    /// registration has ended.
+    NameConflict,
+    Found(ServiceName),
+    Lost(ServiceName),
+    /// The service was resolved, or the information about it has changed.
+    Resolved(Resolved),
+}
+
+/// A handle to the mDNS thread, which stops once this and all operations are
+/// dropped.
+pub struct Mdns {
+    commands: Sender<Command>,
+    next_id: u64,
+}
+
+/// A registration, browse or resolve in progress. It's cancelled when this is
+/// dropped.
+pub struct Operation {
+    id: u64,
+    commands: Sender<Command>,
+    pub events: Receiver<Event>,
+}
+impl Drop for Operation {
+    fn drop(&mut self) {
+        let _ = self.commands.send(Command::Cancel(self.id));
+    }
+}
+
+impl Mdns {
+    /// Start the thread. If the socket can't be set up, a warning is logged
+    /// and operations will never have any results.
+    pub fn spawn() -> Mdns {
+        let (commands, command_receiver) = channel();
+        match Engine::new(command_receiver) {
+            Ok(engine) => {
+                std::thread::spawn(move || engine.run());
+            }
+            Err(e) => log!(
+                "Warning: Couldn't set up multicast DNS, Bonjour won't work: {}",
+                e
+            ),
+        }
+        Mdns {
+            commands,
+            next_id: 0,
+        }
+    }
+
+    fn begin(&mut self, make_command: impl FnOnce(u64, Sender<Event>) -> Command) -> Operation {
+        let id = self.next_id;
+        self.next_id += 1;
+        let (event_sender, events) = channel();
+        let _ = self.commands.send(make_command(id, event_sender));
+        Operation {
+            id,
+            commands: self.commands.clone(),
+            events,
+        }
+    }
+
+    /// Publish a service. The TXT record should be in its encoded form. If
+    /// `auto_rename` is [true], a number is added to the name if another
+    /// service already has it.
+    pub fn register(
+        &mut self,
+        service: ServiceName,
+        port: u16,
+        txt: Vec<u8>,
+        auto_rename: bool,
+    ) -> Operation {
+        self.begin(|id, events| Command::Register {
+            id,
+            service,
+            port,
+            txt,
+            auto_rename,
+            events,
+        })
+    }
+
+    /// Look for services of a type, e.g. `["_mygame", "_tcp", "local"]`.
+    pub fn browse(&mut self, service_type: Name) -> Operation {
+        self.begin(|id, events| Command::Browse {
+            id,
+            service_type,
+            events,
+        })
+    }
+
+    /// Find out the host name, port, TXT record and addresses of a service.
+    pub fn resolve(&mut self, service: ServiceName) -> Operation {
+        self.begin(|id, events| Command::Resolve {
+            id,
+            service,
+            events,
+        })
+    }
+}
+
+enum Command {
+    Register {
+        id: u64,
+        service: ServiceName,
+        port: u16,
+        txt: Vec<u8>,
+        auto_rename: bool,
+        events: Sender<Event>,
+    },
+    Browse {
+        id: u64,
+        service_type: Name,
+        events: Sender<Event>,
+    },
+    Resolve {
+        id: u64,
+        service: ServiceName,
+        events: Sender<Event>,
+    },
+    Cancel(u64),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum RecordData {
+    A(Ipv4Addr),
+    Ptr(Name),
+    /// The priority and weight are ignored, since they're meaningless for
+    /// mDNS.
+    Srv {
+        port: u16,
+        target: Name,
+    },
+    Txt(Vec<u8>),
+    Other(Vec<u8>),
+}
+
+#[derive(Clone, Debug)]
+struct Record {
+    name: Name,
+    rtype: u16,
+    cache_flush: bool,
+    ttl: u32,
+    data: RecordData,
+}
+
+#[derive(Clone, Debug)]
+struct Question {
+    name: Name,
+    rtype: u16,
+    unicast_response: bool,
+}
+
+#[derive(Default, Debug)]
+struct Message {
+    id: u16,
+    is_response: bool,
+    questions: Vec<Question>,
+    answers: Vec<Record>,
+    authorities: Vec<Record>,
+    additionals: Vec<Record>,
+}
+
+fn encode_name(out: &mut Vec<u8>, name: &[String]) {
+    for label in name {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        out.push(label.len() as u8);
+        out.extend_from_slice(label);
+    }
+    out.push(0);
+}
+
+/// Read a name that might use compression, returning it and the offset of the
+/// byte after it.
+fn decode_name(bytes: &[u8], mut pos: usize) -> Option<(Name, usize)> {
+    let mut name = Vec::new();
+    let mut end = None;
+    // Limiting the number of steps means a malicious packet can't cause an
+    // infinite loop.
+    for _ in 0..128 {
+        let len = *bytes.get(pos)? as usize;
+        match len & 0xc0 {
+            0x00 if len == 0 => return Some((name, end.unwrap_or(pos + 1))),
+            0x00 => {
+                let label = bytes.get(pos + 1..pos + 1 + len)?;
+                name.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + len;
+            }
+            0xc0 => {
+                let offset = ((len & 0x3f) << 8) | *bytes.get(pos + 1)? as usize;
+                end.get_or_insert(pos + 2);
+                pos = offset;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+impl RecordData {
+    fn rtype(&self) -> u16 {
+        match self {
+            RecordData::A(_) => TYPE_A,
+            RecordData::Ptr(_) => TYPE_PTR,
+            RecordData::Srv { .. } => TYPE_SRV,
+            RecordData::Txt(_) => TYPE_TXT,
+            RecordData::Other(_) => unreachable!(),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            RecordData::A(address) => out.extend_from_slice(&address.octets()),
+            RecordData::Ptr(target) => encode_name(&mut out, target),
+            RecordData::Srv { port, target } => {
+                out.extend_from_slice(&[0, 0, 0, 0]);
+                out.extend_from_slice(&port.to_be_bytes());
+                encode_name(&mut out, target);
+            }
+            // A TXT record can't be empty, so an empty one is written as a
+            // single empty string.
+            RecordData::Txt(txt) if txt.is_empty() => out.push(0),
+            RecordData::Txt(bytes) | RecordData::Other(bytes) => out.extend_from_slice(bytes),
+        }
+        out
+    }
+
+    fn decode(bytes: &[u8], rtype: u16, start: usize, len: usize) -> Option<RecordData> {
+        let data = bytes.get(start..start + len)?;
+        Some(match rtype {
+            TYPE_A if len == 4 => RecordData::A(Ipv4Addr::new(data[0], data[1], data[2], data[3])),
+            TYPE_PTR => RecordData::Ptr(decode_name(bytes, start)?.0),
+            TYPE_SRV if len > 6 => RecordData::Srv {
+                port: u16::from_be_bytes([data[4], data[5]]),
+                target: decode_name(bytes, start + 6)?.0,
+            },
+            TYPE_TXT => RecordData::Txt(data.to_vec()),
+            _ => RecordData::Other(data.to_vec()),
+        })
+    }
+}
+
+impl Record {
+    fn new(name: Name, ttl: u32, data: RecordData) -> Record {
+        let rtype = data.rtype();
+        Record {
+            name,
+            rtype,
+            // Only PTR records are shared between responders.
+            cache_flush: rtype != TYPE_PTR,
+            ttl,
+            data,
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_name(out, &self.name);
+        out.extend_from_slice(&self.rtype.to_be_bytes());
+        let class = CLASS_IN | if self.cache_flush { CLASS_TOP_BIT } else { 0 };
+        out.extend_from_slice(&class.to_be_bytes());
+        out.extend_from_slice(&self.ttl.to_be_bytes());
+        let data = self.data.encode();
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(&data);
+    }
+
+    /// Whether two records are the same apart from their TTL.
+    fn same_as(&self, other: &Record) -> bool {
+        names_equal(&self.name, &other.name) && self.rtype == other.rtype && self.data == other.data
+    }
+}
+
+/// Reads a DNS message.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+impl Reader<'_> {
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes.get(self.pos..self.pos + 2)?;
+        self.pos += 2;
+        Some(u16::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let bytes = self.bytes.get(self.pos..self.pos + 4)?;
+        self.pos += 4;
+        Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn name(&mut self) -> Option<Name> {
+        let (name, end) = decode_name(self.bytes, self.pos)?;
+        self.pos = end;
+        Some(name)
+    }
+
+    /// Records of classes other than `IN` are skipped.
+    fn records(&mut self, count: u16) -> Option<Vec<Record>> {
+        let mut records = Vec::new();
+        for _ in 0..count {
+            let name = self.name()?;
+            let rtype = self.u16()?;
+            let class = self.u16()?;
+            let ttl = self.u32()?;
+            let len: usize = self.u16()?.into();
+            let data = RecordData::decode(self.bytes, rtype, self.pos, len)?;
+            self.pos += len;
+            if class & !CLASS_TOP_BIT == CLASS_IN {
+                records.push(Record {
+                    name,
+                    rtype,
+                    cache_flush: class & CLASS_TOP_BIT != 0,
+                    ttl,
+                    data,
+                });
+            }
+        }
+        Some(records)
+    }
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.id.to_be_bytes());
+        // Responses are always authoritative.
+        let flags: u16 = if self.is_response { 0x8400 } else { 0 };
+        out.extend_from_slice(&flags.to_be_bytes());
+        for count in [
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.additionals.len(),
+        ] {
+            out.extend_from_slice(&(count as u16).to_be_bytes());
+        }
+        for question in &self.questions {
+            encode_name(&mut out, &question.name);
+            out.extend_from_slice(&question.rtype.to_be_bytes());
+            let class = CLASS_IN
+                | if question.unicast_response {
+                    CLASS_TOP_BIT
+                } else {
+                    0
+                };
+            out.extend_from_slice(&class.to_be_bytes());
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+        {
+            record.encode(&mut out);
+        }
+        out
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Message> {
+        let mut reader = Reader { bytes, pos: 0 };
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        // Messages with a non-zero opcode or response code must be ignored.
+        if flags & 0x780f != 0 {
+            return None;
+        }
+        let question_count = reader.u16()?;
+        let answer_count = reader.u16()?;
+        let authority_count = reader.u16()?;
+        let additional_count = reader.u16()?;
+        let mut questions = Vec::new();
+        for _ in 0..question_count {
+            let name = reader.name()?;
+            let rtype = reader.u16()?;
+            let class = reader.u16()?;
+            questions.push(Question {
+                name,
+                rtype,
+                unicast_response: class & CLASS_TOP_BIT != 0,
+            });
+        }
+        Some(Message {
+            id,
+            is_response: flags & 0x8000 != 0,
+            questions,
+            answers: reader.records(answer_count)?,
+            authorities: reader.records(authority_count)?,
+            additionals: reader.records(additional_count)?,
+        })
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum RegistrationState {
+    Probing,
+    Announcing,
+    Announced,
+}
+
+struct Registration {
+    id: u64,
+    service: ServiceName,
+    /// The name the app asked for, which renaming adds a number to.
+    original_name: String,
+    rename_count: u32,
+    port: u16,
+    /// Never empty, see [RecordData::encode].
+    txt: Vec<u8>,
+    auto_rename: bool,
+    events: Sender<Event>,
+    state: RegistrationState,
+    /// How many probes or announcements have been sent in this state.
+    sent: u8,
+    next_time: Instant,
+}
+
+/// Queries are sent with increasing intervals.
+struct QuerySchedule {
+    next_time: Instant,
+    interval: Duration,
+}
+impl QuerySchedule {
+    fn new(now: Instant) -> QuerySchedule {
+        QuerySchedule {
+            next_time: now,
+            interval: FIRST_QUERY_INTERVAL,
+        }
+    }
+
+    fn is_due(&mut self, now: Instant) -> bool {
+        if now < self.next_time {
+            return false;
+        }
+        self.next_time = now + self.interval;
+        self.interval = (self.interval * 2).min(MAX_QUERY_INTERVAL);
+        true
+    }
+}
+
+struct Browse {
+    id: u64,
+    service_type: Name,
+    events: Sender<Event>,
+    found: Vec<ServiceName>,
+    schedule: QuerySchedule,
+}
+
+struct Resolve {
+    id: u64,
+    service: ServiceName,
+    events: Sender<Event>,
+    last_result: Option<Resolved>,
+    schedule: QuerySchedule,
+}
+
+struct CachedRecord {
+    record: Record,
+    received: Instant,
+    expires: Instant,
+}
+
+struct Engine {
+    socket: UdpSocket,
+    commands: Receiver<Command>,
+    /// e.g. `["touchHLE-1234abcd", "local"]`. This is random so that several
+    /// instances on the same machine don't conflict.
+    host_name: Name,
+    address: Ipv4Addr,
+    registrations: Vec<Registration>,
+    browses: Vec<Browse>,
+    resolves: Vec<Resolve>,
+    /// Records from responses, which browsing and resolving use.
+    cache: Vec<CachedRecord>,
+}
+
+impl Engine {
+    fn new(commands: Receiver<Command>) -> std::io::Result<Engine> {
+        let socket = match bind_shared(MDNS_PORT) {
+            Ok(socket) => socket,
+            Err(e) => {
+                // Responses to queries from other ports are sent directly to
+                // the querier, so browsing and resolving still work.
+                log!("Warning: Couldn't bind to the mDNS port ({}), so services published by the app won't be visible.", e);
+                UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?
+            }
+        };
+        socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_multicast_ttl_v4(255)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        let random = RandomState::new().build_hasher().finish() as u32;
+        let host_name = vec![format!("touchHLE-{:08x}", random), "local".to_string()];
+        let address = local_address();
+        log_dbg!(
+            "mDNS host name is {} with address {}",
+            format_name(&host_name),
+            address
+        );
+        Ok(Engine {
+            socket,
+            commands,
+            host_name,
+            address,
+            registrations: Vec::new(),
+            browses: Vec::new(),
+            resolves: Vec::new(),
+            cache: Vec::new(),
+        })
+    }
+
+    fn run(mut self) {
+        let mut buffer = [0u8; 9000];
+        loop {
+            loop {
+                match self.commands.try_recv() {
+                    Ok(command) => self.handle_command(command),
+                    Err(TryRecvError::Empty) => break,
+                    // The emulator is shutting down.
+                    Err(TryRecvError::Disconnected) => {
+                        for registration in &self.registrations {
+                            if registration.state != RegistrationState::Probing {
+                                self.send_goodbye(registration);
+                            }
+                        }
+                        return;
+                    }
+                }
+            }
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, from)) => {
+                    if let Some(message) = Message::decode(&buffer[..len]) {
+                        self.handle_message(message, from);
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+                Err(e) => {
+                    log_dbg!("mDNS receive error: {}", e);
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+            }
+            self.handle_timers(Instant::now());
+        }
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        let now = Instant::now();
+        match command {
+            Command::Register {
+                id,
+                service,
+                port,
+                txt,
+                auto_rename,
+                events,
+            } => {
+                log_dbg!("mDNS: registering {:?} on port {}", service, port);
+                let txt = if txt.is_empty() { vec![0] } else { txt };
+                self.registrations.push(Registration {
+                    id,
+                    original_name: service.name.clone(),
+                    service,
+                    rename_count: 1,
+                    port,
+                    txt,
+                    auto_rename,
+                    events,
+                    state: RegistrationState::Probing,
+                    sent: 0,
+                    next_time: now,
+                });
+            }
+            Command::Browse {
+                id,
+                service_type,
+                events,
+            } => {
+                log_dbg!("mDNS: browsing for {}", format_name(&service_type));
+                self.browses.push(Browse {
+                    id,
+                    service_type,
+                    events,
+                    found: Vec::new(),
+                    schedule: QuerySchedule::new(now),
+                });
+            }
+            Command::Resolve {
+                id,
+                service,
+                events,
+            } => {
+                log_dbg!("mDNS: resolving {:?}", service);
+                self.resolves.push(Resolve {
+                    id,
+                    service,
+                    events,
+                    last_result: None,
+                    schedule: QuerySchedule::new(now),
+                });
+            }
+            Command::Cancel(id) => {
+                if let Some(idx) = self.registrations.iter().position(|r| r.id == id) {
+                    let registration = self.registrations.remove(idx);
+                    if registration.state != RegistrationState::Probing {
+                        self.send_goodbye(&registration);
+                    }
+                }
+                self.browses.retain(|browse| browse.id != id);
+                self.resolves.retain(|resolve| resolve.id != id);
+            }
+        }
+        // Results might already be in the cache.
+        self.update_operations();
+    }
+
+    /// The records a registered service answers queries with.
+    fn records_for(&self, registration: &Registration) -> Vec<Record> {
+        let service = &registration.service;
+        let full_name = service.full_name();
+        let service_type_name = service.service_type_name();
+        let mut services_name = parse_name("_services._dns-sd._udp");
+        services_name.extend_from_slice(&service.domain);
+        vec![
+            Record::new(
+                service_type_name.clone(),
+                OTHER_RECORD_TTL,
+                RecordData::Ptr(full_name.clone()),
+            ),
+            Record::new(
+                full_name.clone(),
+                HOST_RECORD_TTL,
+                RecordData::Srv {
+                    port: registration.port,
+                    target: self.host_name.clone(),
+                },
+            ),
+            Record::new(
+                full_name,
+                OTHER_RECORD_TTL,
+                RecordData::Txt(registration.txt.clone()),
+            ),
+            Record::new(
+                self.host_name.clone(),
+                HOST_RECORD_TTL,
+                RecordData::A(self.address),
+            ),
+            Record::new(
+                services_name,
+                OTHER_RECORD_TTL,
+                RecordData::Ptr(service_type_name),
+            ),
+        ]
+    }
+
+    fn send(&self, message: &Message, to: SocketAddr) {
+        if let Err(e) = self.socket.send_to(&message.encode(), to) {
+            log_dbg!("mDNS send error: {}", e);
+        }
+    }
+
+    fn send_multicast(&self, message: &Message) {
+        self.send(message, SocketAddrV4::new(MDNS_GROUP, MDNS_PORT).into());
+    }
+
+    /// Tell other devices that a service has gone away.
+    fn send_goodbye(&self, registration: &Registration) {
+        let full_name = registration.service.full_name();
+        let mut answers: Vec<Record> = self
+            .records_for(registration)
+            .into_iter()
+            // The host name and service type might be used by other services.
+            .filter(|record| {
+                names_equal(&record.name, &full_name)
+                    || record.data == RecordData::Ptr(full_name.clone())
+            })
+            .collect();
+        for record in &mut answers {
+            record.ttl = 0;
+        }
+        self.send_multicast(&Message {
+            is_response: true,
+            answers,
+            ..Default::default()
+        });
+    }
+
+    fn handle_timers(&mut self, now: Instant) {
+        for idx in 0..self.registrations.len() {
+            let registration = &self.registrations[idx];
+            if registration.state == RegistrationState::Announced || now < registration.next_time {
+                continue;
+            }
+            let records = self.records_for(registration);
+            let registration = &mut self.registrations[idx];
+            let message = match registration.state {
+                RegistrationState::Probing if registration.sent == PROBE_COUNT => {
+                    log_dbg!("mDNS: registered {:?}", registration.service);
+                    let _ = registration
+                        .events
+                        .send(Event::Registered(registration.service.clone()));
+                    registration.state = RegistrationState::Announcing;
+                    registration.sent = 0;
+                    continue;
+                }
+                RegistrationState::Probing => {
+                    registration.next_time = now + PROBE_INTERVAL;
+                    Message {
+                        // Multicast responses are asked for, because with
+                        // several sockets sharing the port, a unicast
+                        // response might go to the wrong one.
+                        questions: vec![Question {
+                            name: registration.service.full_name(),
+                            rtype: TYPE_ANY,
+                            unicast_response: false,
+                        }],
+                        authorities: records
+                            .into_iter()
+                            .filter(|record| matches!(record.rtype, TYPE_SRV | TYPE_TXT))
+                            .collect(),
+                        ..Default::default()
+                    }
+                }
+                RegistrationState::Announcing => {
+                    registration.next_time = now + ANNOUNCE_INTERVAL;
+                    if registration.sent + 1 == ANNOUNCE_COUNT {
+                        registration.state = RegistrationState::Announced;
+                    }
+                    Message {
+                        is_response: true,
+                        answers: records,
+                        ..Default::default()
+                    }
+                }
+                RegistrationState::Announced => unreachable!(),
+            };
+            registration.sent += 1;
+            self.send_multicast(&message);
+        }
+
+        let mut queries = Vec::new();
+        for browse in &mut self.browses {
+            if !browse.schedule.is_due(now) {
+                continue;
+            }
+            // Known answers that won't expire soon don't need to be sent
+            // again.
+            let answers = self
+                .cache
+                .iter()
+                .filter(|cached| {
+                    cached.record.rtype == TYPE_PTR
+                        && names_equal(&cached.record.name, &browse.service_type)
+                        && cached.expires.saturating_duration_since(now)
+                            > Duration::from_secs(cached.record.ttl.into()) / 2
+                })
+                .map(|cached| cached.record.clone())
+                .collect();
+            queries.push(Message {
+                questions: vec![Question {
+                    name: browse.service_type.clone(),
+                    rtype: TYPE_PTR,
+                    unicast_response: false,
+                }],
+                answers,
+                ..Default::default()
+            });
+        }
+        for resolve in &mut self.resolves {
+            if !resolve.schedule.is_due(now) {
+                continue;
+            }
+            let full_name = resolve.service.full_name();
+            let mut questions: Vec<Question> = [TYPE_SRV, TYPE_TXT]
+                .into_iter()
+                .map(|rtype| Question {
+                    name: full_name.clone(),
+                    rtype,
+                    unicast_response: false,
+                })
+                .collect();
+            // The address is also needed once the host name is known.
+            let target = self
+                .cache
+                .iter()
+                .find_map(|cached| match &cached.record.data {
+                    RecordData::Srv { target, .. }
+                        if names_equal(&cached.record.name, &full_name) =>
+                    {
+                        Some(target.clone())
+                    }
+                    _ => None,
+                });
+            if let Some(target) = target {
+                questions.push(Question {
+                    name: target,
+                    rtype: TYPE_A,
+                    unicast_response: false,
+                });
+            }
+            queries.push(Message {
+                questions,
+                ..Default::default()
+            });
+        }
+        for query in queries {
+            self.send_multicast(&query);
+        }
+
+        let cache_len = self.cache.len();
+        self.cache.retain(|cached| cached.expires > now);
+        if self.cache.len() != cache_len {
+            self.update_operations();
+        }
+    }
+
+    fn handle_message(&mut self, message: Message, from: SocketAddr) {
+        if message.is_response {
+            let records: Vec<&Record> =
+                message.answers.iter().chain(&message.additionals).collect();
+            self.check_for_conflicts(&records, /* probe: */ false);
+            let now = Instant::now();
+            for record in records {
+                self.cache_record(record.clone(), now);
+            }
+            self.update_operations();
+        } else {
+            let records: Vec<&Record> = message.authorities.iter().collect();
+            self.check_for_conflicts(&records, /* probe: */ true);
+            self.answer_query(&message, from);
+        }
+    }
+
+    /// Check whether someone else has a service with the same name as one of
+    /// ours. If `probe` is [true], the records are from another responder's
+    /// probe, which only matters if we're also still probing.
+    fn check_for_conflicts(&mut self, records: &[&Record], probe: bool) {
+        let mut conflicts = Vec::new();
+        for registration in &self.registrations {
+            if probe && registration.state != RegistrationState::Probing {
+                continue;
+            }
+            let ours = self.records_for(registration);
+            let ours: Vec<&Record> = ours
+                .iter()
+                .filter(|record| matches!(record.rtype, TYPE_SRV | TYPE_TXT))
+                .collect();
+            let conflict = records.iter().any(|theirs| {
+                let Some(ours) = ours.iter().find(|ours| {
+                    ours.rtype == theirs.rtype && names_equal(&ours.name, &theirs.name)
+                }) else {
+                    return false;
+                };
+                // Our own packets come back to us, so identical data isn't a
+                // conflict. When two responders probe at the same time, the
+                // one with the lexicographically later data wins.
+                if probe {
+                    theirs.data.encode() > ours.data.encode()
+                } else {
+                    theirs.ttl != 0 && theirs.data != ours.data
+                }
+            });
+            if conflict {
+                conflicts.push(registration.id);
+            }
+        }
+        for id in conflicts {
+            self.handle_conflict(id);
+        }
+    }
+
+    fn handle_conflict(&mut self, id: u64) {
+        let idx = self.registrations.iter().position(|r| r.id == id).unwrap();
+        let registration = &self.registrations[idx];
+        log!(
+            "mDNS: Another service on the network is called {:?}",
+            registration.service.name
+        );
+        if registration.state != RegistrationState::Probing {
+            self.send_goodbye(registration);
+        }
+        if !registration.auto_rename {
+            let registration = self.registrations.remove(idx);
+            let _ = registration.events.send(Event::NameConflict);
+            return;
+        }
+        let registration = &mut self.registrations[idx];
+        registration.rename_count += 1;
+        registration.service.name = format!(
+            "{} ({})",
+            registration.original_name, registration.rename_count
+        );
+        registration.state = RegistrationState::Probing;
+        registration.sent = 0;
+        registration.next_time = Instant::now();
+    }
+
+    fn answer_query(&self, query: &Message, from: SocketAddr) {
+        let mut answers: Vec<Record> = Vec::new();
+        let mut additionals: Vec<Record> = Vec::new();
+        for registration in &self.registrations {
+            if registration.state == RegistrationState::Probing {
+                continue;
+            }
+            let records = self.records_for(registration);
+            for question in &query.questions {
+                for record in &records {
+                    if !names_equal(&record.name, &question.name)
+                        || !(question.rtype == record.rtype || question.rtype == TYPE_ANY)
+                    {
+                        continue;
+                    }
+                    // Known-answer suppression
+                    if query
+                        .answers
+                        .iter()
+                        .any(|known| known.same_as(record) && known.ttl >= record.ttl / 2)
+                    {
+                        continue;
+                    }
+                    if !answers.iter().any(|answer| answer.same_as(record)) {
+                        answers.push(record.clone());
+                    }
+                    add_additionals(&records, record, &mut additionals);
+                }
+            }
+        }
+        if answers.is_empty() {
+            return;
+        }
+        additionals.retain(|additional| !answers.iter().any(|answer| answer.same_as(additional)));
+
+        let mut response = Message {
+            is_response: true,
+            answers,
+            additionals,
+            ..Default::default()
+        };
+        if from.port() == MDNS_PORT {
+            self.send_multicast(&response);
+        } else {
+            // A "legacy" querier that isn't using port 5353 gets a unicast
+            // response that looks like a normal DNS response.
+            response.id = query.id;
+            response.questions = query.questions.clone();
+            for record in response
+                .answers
+                .iter_mut()
+                .chain(response.additionals.iter_mut())
+            {
+                record.ttl = record.ttl.min(LEGACY_UNICAST_TTL);
+                record.cache_flush = false;
+            }
+            self.send(&response, from);
+        }
+    }
+
+    fn cache_record(&mut self, record: Record, now: Instant) {
+        if !matches!(record.rtype, TYPE_A | TYPE_PTR | TYPE_SRV | TYPE_TXT) {
+            return;
+        }
+        if record.cache_flush {
+            // Records from the last second are kept, because they might be
+            // part of the same set of records.
+            self.cache.retain(|cached| {
+                !(names_equal(&cached.record.name, &record.name)
+                    && cached.record.rtype == record.rtype
+                    && cached.record.data != record.data
+                    && now.duration_since(cached.received) > Duration::from_secs(1))
+            });
+        }
+        let existing = self
+            .cache
+            .iter()
+            .position(|cached| cached.record.same_as(&record));
+        if record.ttl == 0 {
+            // This is a "goodbye", the record has gone away.
+            if let Some(idx) = existing {
+                self.cache.swap_remove(idx);
+            }
+            return;
+        }
+        let cached = CachedRecord {
+            expires: now + Duration::from_secs(record.ttl.into()),
+            received: now,
+            record,
+        };
+        match existing {
+            Some(idx) => self.cache[idx] = cached,
+            None => self.cache.push(cached),
+        }
+    }
+
+    /// Send events for browses and resolves whose results have changed.
+    fn update_operations(&mut self) {
+        let cache = &self.cache;
+        for browse in &mut self.browses {
+            let current: Vec<ServiceName> = cache
+                .iter()
+                .filter_map(|cached| match &cached.record.data {
+                    RecordData::Ptr(target)
+                        if names_equal(&cached.record.name, &browse.service_type) =>
+                    {
+                        ServiceName::from_full_name(target)
+                    }
+                    _ => None,
+                })
+                .collect();
+            for service in &current {
+                if !browse.found.contains(service) {
+                    let _ = browse.events.send(Event::Found(service.clone()));
+                }
+            }
+            for service in &browse.found {
+                if !current.contains(service) {
+                    let _ = browse.events.send(Event::Lost(service.clone()));
+                }
+            }
+            browse.found = current;
+        }
+
+        for resolve in &mut self.resolves {
+            let full_name = resolve.service.full_name();
+            let find = |rtype| {
+                cache
+                    .iter()
+                    .find(|cached| {
+                        cached.record.rtype == rtype && names_equal(&cached.record.name, &full_name)
+                    })
+                    .map(|cached| &cached.record.data)
+            };
+            let Some(RecordData::Srv { port, target }) = find(TYPE_SRV) else {
+                continue;
+            };
+            let Some(RecordData::Txt(txt)) = find(TYPE_TXT) else {
+                continue;
+            };
+            let addresses: Vec<Ipv4Addr> = cache
+                .iter()
+                .filter_map(|cached| match cached.record.data {
+                    RecordData::A(address) if names_equal(&cached.record.name, target) => {
+                        Some(address)
+                    }
+                    _ => None,
+                })
+                .collect();
+            if addresses.is_empty() {
+                continue;
+            }
+            let result = Resolved {
+                host_name: target.clone(),
+                port: *port,
+                txt: txt.clone(),
+                addresses,
+            };
+            if resolve.last_result.as_ref() != Some(&result) {
+                let _ = resolve.events.send(Event::Resolved(result.clone()));
+                resolve.last_result = Some(result);
+            }
+        }
+    }
+}
+
+/// Add the records that a querier will probably ask for next, e.g. the `SRV`
+/// record for a service found with a `PTR` record.
+fn add_additionals(records: &[Record], record: &Record, additionals: &mut Vec<Record>) {
+    let target = match &record.data {
+        RecordData::Ptr(target) => target,
+        RecordData::Srv { target, .. } => target,
+        _ => return,
+    };
+    for extra in records
+        .iter()
+        .filter(|extra| names_equal(&extra.name, target))
+    {
+        if !additionals.iter().any(|existing| existing.same_as(extra)) {
+            additionals.push(extra.clone());
+            add_additionals(records, extra, additionals);
+        }
+    }
+}
+
+/// Find the address other devices on the local network can reach this one
+/// at, by checking which address the OS would send mDNS packets from.
+fn local_address() -> Ipv4Addr {
+    UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| {
+            socket.connect((MDNS_GROUP, MDNS_PORT))?;
+            socket.local_addr()
+        })
+        .ok()
+        .and_then(|address| match address.ip() {
+            IpAddr::V4(ip) if !ip.is_unspecified() => Some(ip),
+            _ => None,
+        })
+        .unwrap_or(Ipv4Addr::LOCALHOST)
+}
+
+/// Bind a UDP socket to a port on all interfaces, in a way that lets other
+/// sockets (e.g. another touchHLE instance, or the OS's own mDNS responder)
+/// bind to it too. [std::net] can't do this because the socket option has to
+/// be set before binding, so the socket is created using the OS's API.
+#[cfg(unix)]
+fn bind_shared(port: u16) -> std::io::Result<UdpSocket> {
+    use std::ffi::{c_int, c_void};
+    use std::os::unix::io::FromRawFd;
+
+    extern "C" {
+        fn socket(domain: c_int, type_: c_int, protocol: c_int) -> c_int;
+        fn setsockopt(
+            socket: c_int,
+            level: c_int,
+            name: c_int,
+            value: *const c_void,
+            len: u32,
+        ) -> c_int;
+        fn bind(socket: c_int, address: *const c_void, len: u32) -> c_int;
+    }
+
+    const AF_INET: c_int = 2;
+    const SOCK_DGRAM: c_int = 2;
+    // SOL_SOCKET and SO_REUSEADDR, plus SO_REUSEPORT on Apple platforms.
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    const REUSE_OPTIONS: (c_int, &[c_int]) = (0xffff, &[0x4, 0x200]);
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    const REUSE_OPTIONS: (c_int, &[c_int]) = (1, &[2]);
+
+    // struct sockaddr_in with the address INADDR_ANY
+    let mut address = [0u8; 16];
+    if cfg!(any(target_os = "macos", target_os = "ios")) {
+        address[0] = address.len() as u8;
+        address[1] = AF_INET as u8;
+    } else {
+        address[..2].copy_from_slice(&(AF_INET as u16).to_ne_bytes());
+    }
+    address[2..4].copy_from_slice(&port.to_be_bytes());
+
+    unsafe {
+        let fd = socket(AF_INET, SOCK_DGRAM, 0);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // Taking ownership now means the socket is closed if anything fails.
+        let udp_socket = UdpSocket::from_raw_fd(fd);
+        let (level, names) = REUSE_OPTIONS;
+        let one: c_int = 1;
+        for &name in names {
+            let value: *const c_int = &one;
+            if setsockopt(fd, level, name, value.cast(), 4) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        if bind(fd, address.as_ptr().cast(), address.len() as u32) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(udp_socket)
+    }
+}
+
+/// Windows version of the above.
+#[cfg(windows)]
+fn bind_shared(port: u16) -> std::io::Result<UdpSocket> {
+    use std::ffi::{c_int, c_void};
+    use std::os::windows::io::{FromRawSocket, RawSocket};
+
+    #[link(name = "ws2_32")]
+    extern "system" {
+        fn socket(af: c_int, type_: c_int, protocol: c_int) -> usize;
+        fn setsockopt(
+            socket: usize,
+            level: c_int,
+            name: c_int,
+            value: *const c_void,
+            len: c_int,
+        ) -> c_int;
+        fn bind(socket: usize, address: *const c_void, len: c_int) -> c_int;
+    }
+
+    const INVALID_SOCKET: usize = !0;
+    const AF_INET: c_int = 2;
+    const SOCK_DGRAM: c_int = 2;
+    const SOL_SOCKET: c_int = 0xffff;
+    const SO_REUSEADDR: c_int = 4;
+
+    // std initializes Winsock the first time it creates a socket.
+    drop(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)));
+
+    // struct sockaddr_in with the address INADDR_ANY
+    let mut address = [0u8; 16];
+    address[..2].copy_from_slice(&(AF_INET as u16).to_ne_bytes());
+    address[2..4].copy_from_slice(&port.to_be_bytes());
+
+    unsafe {
+        let raw_socket = socket(AF_INET, SOCK_DGRAM, 0);
+        if raw_socket == INVALID_SOCKET {
+            return Err(std::io::Error::last_os_error());
+        }
+        // Taking ownership now means the socket is closed if anything fails.
+        let udp_socket = UdpSocket::from_raw_socket(raw_socket as RawSocket);
+        let one: c_int = 1;
+        let value: *const c_int = &one;
+        if setsockopt(raw_socket, SOL_SOCKET, SO_REUSEADDR, value.cast(), 4) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if bind(raw_socket, address.as_ptr().cast(), address.len() as c_int) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(udp_socket)
+    }
+}
+
+#[cfg(test)]
+mod mdns_tests {
+    use super::*;
+
+    #[test]
+    fn message_round_trip() {
+        let service = ServiceName {
+            name: "My.Game".to_string(),
+            service_type: parse_name("_mygame._tcp"),
+            domain: parse_name("local."),
+        };
+        let message = Message {
+            id: 0,
+            is_response: true,
+            questions: Vec::new(),
+            answers: vec![
+                Record::new(
+                    service.service_type_name(),
+                    OTHER_RECORD_TTL,
+                    RecordData::Ptr(service.full_name()),
+                ),
+                Record::new(
+                    service.full_name(),
+                    HOST_RECORD_TTL,
+                    RecordData::Srv {
+                        port: 1234,
+                        target: parse_name("host.local"),
+                    },
+                ),
+                Record::new(
+                    service.full_name(),
+                    OTHER_RECORD_TTL,
+                    RecordData::Txt(vec![]),
+                ),
+            ],
+            authorities: Vec::new(),
+            additionals: vec![Record::new(
+                parse_name("host.local"),
+                HOST_RECORD_TTL,
+                RecordData::A(Ipv4Addr::new(192, 168, 0, 2)),
+            )],
+        };
+        let decoded = Message::decode(&message.encode()).unwrap();
+        assert!(decoded.is_response);
+        assert_eq!(decoded.answers.len(), 3);
+        assert!(decoded.answers[0].same_as(&message.answers[0]));
+        assert!(!decoded.answers[0].cache_flush);
+        assert!(decoded.answers[1].same_as(&message.answers[1]));
+        assert!(decoded.answers[1].cache_flush);
+        assert_eq!(decoded.answers[1].ttl, HOST_RECORD_TTL);
+        // An empty TXT record is written as a single empty string.
+        assert_eq!(decoded.answers[2].data, RecordData::Txt(vec![0]));
+        assert!(decoded.additionals[0].same_as(&message.additionals[0]));
+        assert_eq!(
+            ServiceName::from_full_name(&service.full_name()),
+            Some(service)
+        );
+    }
+
+    #[test]
+    fn name_compression() {
+        // A query for "_http._tcp.local" PTR, then an answer whose name points
+        // back to it and whose data points to part of itself.
+        let mut bytes = vec![0, 0, 0x84, 0, 0, 1, 0, 1, 0, 0, 0, 0];
+        encode_name(&mut bytes, &parse_name("_http._tcp.local"));
+        bytes.extend_from_slice(&[0, 12, 0, 1]);
+        bytes.extend_from_slice(&[0xc0, 12, 0, 12, 0, 1, 0, 0, 0, 10, 0, 7]);
+        bytes.extend_from_slice(&[4, b'T', b'e', b's', b't', 0xc0, 12]);
+        let message = Message::decode(&bytes).unwrap();
+        assert_eq!(message.questions[0].name, parse_name("_http._tcp.local"));
+        assert_eq!(message.answers[0].name, parse_name("_http._tcp.local"));
+        assert_eq!(
+            message.answers[0].data,
+            RecordData::Ptr(parse_name("Test._http._tcp.local"))
+        );
+
+        // A pointer loop must not hang.
+        let mut bytes = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        bytes.extend_from_slice(&[0xc0, 12, 0, 12, 0, 1]);
+        assert!(Message::decode(&bytes).is_none());
+    }
+
+    #[test]
+    fn full_name_escaping() {
+        let name = vec![
+            "My.Game\\".to_string(),
+            "_mygame".to_string(),
+            "_tcp".to_string(),
+            "local".to_string(),
+        ];
+        assert_eq!(format_name(&name), "My\\.Game\\\\._mygame._tcp.local.");
+    }
+}
@@ -15,8 +15,12 @@
 //! * [Memory Usage Performance Guidelines](https://developer.apple.com/library/archive/documentation/Performance/Conceptual/ManagingMemory/ManagingMemory.html)
 
 use crate::libc::wchar::wchar_t;
+use crate::save_state::{StateReader, StateWriter};
 
 mod allocator;
+mod debug_heap;
+
+pub use debug_heap::LeakSite;
 
 /// Equivalent of `usize` for guest memory.
 pub type GuestUSize = u32;
@@ -229,11 +233,97 @@ pub struct Mem {
     /// The size of the __PAGE_ZERO segment, where pointer accesses are trapped
     /// to prevent null pointer derefrences.
     ///
-    /// We don't have full memory protection, but we can check accesses in that
-    /// range.
+    /// Unlike the other page protections, this is also enforced for accesses
+    /// made by host code.
     null_segment_size: VAddr,
 
+    /// Protection of each page, see [Self::set_protection].
+    protections: Box<[Protection]>,
+    /// The most recent access made by the CPU that violated the page
+    /// protections, if it hasn't been taken with [Self::take_fault] yet.
+    fault: Option<MemoryFault>,
+
     allocator: allocator::Allocator,
+    /// State for heap debugging mode, if it's on. See
+    /// [Self::enable_debug_heap].
+    debug_heap: Option<debug_heap::DebugHeap>,
+
+    /// Data watchpoints set by the debugger, see [Self::add_watchpoint].
+    watchpoints: Vec<Watchpoint>,
+    /// The watchpoint that was most recently hit and the address of the access
+    /// that hit it, if the debugger hasn't been told about it yet.
+    watchpoint_hit: Option<(Watchpoint, VAddr)>,
+}
+
+/// Protection of a page of memory. The bits have the same values as
+/// `VM_PROT_*` in Mach-O load commands and `PROT_*` for `mmap()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Protection(u8);
+impl Protection {
+    pub const NONE: Protection = Protection(0);
+    pub const READ: Protection = Protection(1);
+    pub const WRITE: Protection = Protection(2);
+    pub const EXECUTE: Protection = Protection(4);
+    pub const ALL: Protection = Protection(7);
+
+    /// Convert from `VM_PROT_*` or `PROT_*` bits. Unknown bits are ignored.
+    pub fn from_bits(bits: u32) -> Protection {
+        Protection((bits & Self::ALL.0 as u32) as u8)
+    }
+
+    pub fn allows(self, kind: AccessKind) -> bool {
+        let needed = match kind {
+            AccessKind::Read => Self::READ,
+            AccessKind::Write => Self::WRITE,
+            AccessKind::Execute => Self::EXECUTE,
+        };
+        self.0 & needed.0 != 0
+    }
+}
+
+/// Kind of memory access made by the CPU.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    /// Instruction fetch.
+    Execute,
+}
+
+/// An access made by the CPU that violated the page protections, see
+/// [Mem::check_access].
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct MemoryFault {
+    pub addr: VAddr,
+    pub kind: AccessKind,
+}
+impl std::fmt::Debug for MemoryFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} access at {:#x}", self.kind, self.addr)
+    }
+}
+
+/// Kind of memory access that triggers a [Watchpoint].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchpointKind {
+    Write,
+    Read,
+    /// Reads and writes.
+    Access,
+}
+
+/// A data watchpoint, see [Mem::add_watchpoint].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: VAddr,
+    pub size: GuestUSize,
+    pub kind: WatchpointKind,
+}
+impl Watchpoint {
+    /// The numbers of the pages this watchpoint covers.
+    pub fn pages(&self) -> std::ops::RangeInclusive<VAddr> {
+        Mem::pages(self.addr, self.size)
+    }
 }
 
 impl Drop for Mem {
@@ -259,6 +349,13 @@ impl Mem {
     /// iPhone OS secondary thread stack size.
     pub const SECONDARY_THREAD_STACK_SIZE: GuestUSize = 512 * 1024;
 
+    /// The size of a page, which is the granularity of things like the null
+    /// segment, page protections and watchpoint checks.
+    pub const PAGE_SIZE: GuestUSize = 0x1000;
+
+    /// The number of pages in the address space.
+    pub const PAGE_COUNT: VAddr = ((1u64 << 32) / Self::PAGE_SIZE as u64) as VAddr;
+
     /// Create a fresh instance of guest memory.
     pub fn new() -> Mem {
         // This will hopefully get the host OS to lazily allocate the memory.
@@ -270,7 +367,12 @@ impl Mem {
         Mem {
             bytes,
             null_segment_size: 0,
+            protections: vec![Protection::ALL; Self::PAGE_COUNT as usize].into_boxed_slice(),
+            fault: None,
             allocator,
+            debug_heap: None,
+            watchpoints: Vec::new(),
+            watchpoint_hit: None,
         }
     }
 
@@ -283,8 +385,18 @@ impl Mem {
         let Mem {
             bytes: _,
             null_segment_size: _,
+            ref mut protections,
+            ref mut fault,
             ref mut allocator,
+            ref mut debug_heap,
+            ref mut watchpoints,
+            ref mut watchpoint_hit,
         } = mem;
+        protections.fill(Protection::ALL);
+        *fault = None;
+        *debug_heap = None;
+        watchpoints.clear();
+        *watchpoint_hit = None;
         let used_chunks = allocator.reset_and_drain_used_chunks();
         for allocator::Chunk { base, size } in used_chunks {
             mem.bytes_mut()[base as usize..][..size.get() as usize].fill(0);
@@ -297,15 +409,11 @@ impl Mem {
     /// this outside of binary loading, and it won't be respected even if you
     /// do. The size must not have been set already, and must be page aligned.
     pub fn set_null_segment_size(&mut self, new_null_segment_size: VAddr) {
-        // TODO?: Maybe this should be replaced with a per-page rwx/callback
-        //        setting? Currently we don't properly follow segment
-        //        protections, which means that applications can write into
-        //        segments they shouldn't be able to. Adding that would fix
-        //        this, along with removing this special case.
         assert!(self.null_segment_size == 0);
-        assert!(new_null_segment_size % 0x1000 == 0);
+        assert!(new_null_segment_size % Self::PAGE_SIZE == 0);
         self.allocator
             .reserve(allocator::Chunk::new(0, new_null_segment_size));
+        self.set_protection(0, new_null_segment_size, Protection::NONE);
         self.null_segment_size = new_null_segment_size;
     }
 
@@ -480,7 +588,11 @@ impl Mem {
 
     /// Allocate `size` bytes.
     pub fn alloc(&mut self, size: GuestUSize) -> MutVoidPtr {
-        let ptr = Ptr::from_bits(self.allocator.alloc(size));
+        let ptr = Ptr::from_bits(if self.debug_heap.is_some() {
+            self.debug_alloc(size)
+        } else {
+            self.allocator.alloc(size)
+        });
         log_dbg!("Allocated {:?} ({:#x} bytes)", ptr, size);
         ptr
     }
@@ -489,6 +601,9 @@ impl Mem {
         if old_ptr.is_null() {
             return self.alloc(size);
         }
+        if self.debug_heap.is_some() {
+            return Ptr::from_bits(self.debug_realloc(old_ptr.to_bits(), size));
+        }
         // TODO: for a moment we always assume that we do not have enough size
         //       to realloc inplace
         let old_size = self.allocator.find_allocated_size(old_ptr.to_bits());
@@ -503,6 +618,11 @@ impl Mem {
 
     /// Free an allocation made with one of the `alloc` methods on this type.
     pub fn free(&mut self, ptr: MutVoidPtr) {
+        if self.debug_heap.is_some() {
+            self.debug_free(ptr.to_bits());
+            log_dbg!("Freed {:?}", ptr);
+            return;
+        }
         let size = self.allocator.free(ptr.to_bits());
         self.bytes_at_mut(ptr.cast(), size).fill(0);
         log_dbg!("Freed {:?} ({:#x} bytes)", ptr, size);
@@ -559,9 +679,232 @@ impl Mem {
         String::from_iter(iter)
     }
 
+    /// Add a data watchpoint. Accesses made by the CPU that overlap with the
+    /// watchpoint will make it halt with [crate::cpu::CpuError::Watchpoint].
+    ///
+    /// Only accesses made through the CPU's memory callbacks are checked, so
+    /// the CPU must be told to use these for the watchpoint's pages (see
+    /// [crate::cpu::Cpu::update_page_slow_paths]). Accesses made by host code,
+    /// e.g. a host implementation of `memcpy()`, are not caught.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+    /// Remove a data watchpoint. Returns [false] if there was no such
+    /// watchpoint.
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let Some(i) = self.watchpoints.iter().position(|&w| w == watchpoint) else {
+            return false;
+        };
+        self.watchpoints.remove(i);
+        true
+    }
+    /// Check whether any watchpoint covers a particular page.
+    pub fn is_page_watched(&self, page: VAddr) -> bool {
+        self.watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.pages().contains(&page))
+    }
+
+    /// Check an access made by the CPU against the watchpoints. Only for use by
+    /// the CPU's memory callbacks.
+    pub fn check_watchpoints(&mut self, addr: VAddr, size: GuestUSize, is_write: bool) {
+        if self.watchpoints.is_empty() {
+            return;
+        }
+        let access_end = addr.saturating_add(size);
+        let hit = self.watchpoints.iter().find(|watchpoint| {
+            let kind_matches = match watchpoint.kind {
+                WatchpointKind::Write => is_write,
+                WatchpointKind::Read => !is_write,
+                WatchpointKind::Access => true,
+            };
+            let watchpoint_end = watchpoint.addr.saturating_add(watchpoint.size);
+            kind_matches && addr < watchpoint_end && watchpoint.addr < access_end
+        });
+        if let Some(&watchpoint) = hit {
+            self.watchpoint_hit = Some((watchpoint, addr.max(watchpoint.addr)));
+        }
+    }
+    /// Returns [true] if [Self::check_watchpoints] found a hit that hasn't been
+    /// taken with [Self::take_watchpoint_hit] yet.
+    pub fn has_watchpoint_hit(&self) -> bool {
+        self.watchpoint_hit.is_some()
+    }
+    /// Get the watchpoint that was hit and the address that was accessed.
+    pub fn take_watchpoint_hit(&mut self) -> Option<(Watchpoint, VAddr)> {
+        self.watchpoint_hit.take()
+    }
+
+    /// Set the protection of the pages covering a range of addresses. New pages
+    /// are readable, writable and executable.
+    ///
+    /// Only accesses made by the CPU are checked (see [Self::check_access]).
+    /// Accesses made by host code, e.g. the dynamic linker writing to a
+    /// read-only segment, are always allowed. The CPU can only check accesses
+    /// that use its memory callbacks, so it must be updated afterwards (see
+    /// [crate::cpu::Cpu::update_page_slow_paths]).
+    pub fn set_protection(&mut self, base: VAddr, size: GuestUSize, protection: Protection) {
+        if size == 0 {
+            return;
+        }
+        for page in Self::pages(base, size) {
+            self.protections[page as usize] = protection;
+        }
+    }
+
+    /// Get the protection of the page containing an address.
+    pub fn protection(&self, addr: VAddr) -> Protection {
+        self.protections[(addr / Self::PAGE_SIZE) as usize]
+    }
+
+    /// The numbers of the pages covering a (non-empty) range of addresses.
+    pub fn pages(base: VAddr, size: GuestUSize) -> std::ops::RangeInclusive<VAddr> {
+        let last_byte = base.saturating_add(size.max(1) - 1);
+        (base / Self::PAGE_SIZE)..=(last_byte / Self::PAGE_SIZE)
+    }
+
+    /// Check whether the CPU must access a page via its memory callbacks,
+    /// because it has watchpoints or isn't readable and writable.
+    pub fn page_needs_slow_path(&self, page: VAddr) -> bool {
+        let protection = self.protections[page as usize];
+        !protection.allows(AccessKind::Read)
+            || !protection.allows(AccessKind::Write)
+            || self.is_page_watched(page)
+    }
+
+    /// Check an access made by the CPU against the page protections. If it's
+    /// not allowed, [false] is returned and the fault is recorded for
+    /// [Self::take_fault]. Only for use by the CPU's memory callbacks.
+    pub fn check_access(&mut self, addr: VAddr, size: GuestUSize, kind: AccessKind) -> bool {
+        // An unaligned access can span two pages.
+        let allowed =
+            Self::pages(addr, size).all(|page| self.protections[page as usize].allows(kind));
+        if !allowed {
+            self.fault = Some(MemoryFault { addr, kind });
+        }
+        allowed
+    }
+    /// Record a fault for an access made by the CPU that failed for some other
+    /// reason than the page protections. Only for use by the CPU's memory
+    /// callbacks.
+    pub fn record_fault(&mut self, addr: VAddr, kind: AccessKind) {
+        self.fault = Some(MemoryFault { addr, kind });
+    }
+    /// Get the fault recorded by [Self::check_access], if any.
+    pub fn take_fault(&mut self) -> Option<MemoryFault> {
+        self.fault.take()
+    }
+
     /// Permanently mark a region of address space as being unusable to the
     /// memory allocator.
     pub fn reserve(&mut self, base: VAddr, size: GuestUSize) {
         self.allocator.reserve(allocator::Chunk::new(base, size));
     }
+
+    /// Write the contents of memory and the allocator's state to a save state.
+    /// Only the allocated chunks' contents are written, because everything
+    /// else should be zero.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        let Mem {
+            bytes: _,
+            null_segment_size,
+            ref protections,
+            fault: _,
+            ref allocator,
+            ref debug_heap,
+            // Watchpoints belong to the debugger, not the app.
+            watchpoints: _,
+            watchpoint_hit: _,
+        } = *self;
+
+        writer.write(&null_segment_size);
+        allocator.save_state(writer);
+        for allocator::Chunk { base, size } in allocator.used_chunks() {
+            writer.write_bytes(&self.bytes()[base as usize..][..size.get() as usize]);
+        }
+        // Protections are run-length encoded, since most pages have the same
+        // protection as their neighbours.
+        let mut runs: Vec<(u32, u8)> = Vec::new();
+        for &Protection(bits) in protections.iter() {
+            match runs.last_mut() {
+                Some((count, run_bits)) if *run_bits == bits => *count += 1,
+                _ => runs.push((1, bits)),
+            }
+        }
+        writer.write(&runs);
+        writer.write(debug_heap);
+    }
+
+    /// Replace the contents of memory and the allocator's state with those
+    /// from a save state. The CPU must be told about the new page protections
+    /// afterwards (see [crate::cpu::Cpu::update_page_slow_paths]), and its
+    /// instruction cache must be invalidated.
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        let null_segment_size = reader.read()?;
+        let allocator = allocator::Allocator::load_state(reader)?;
+
+        let used_chunks = self.allocator.reset_and_drain_used_chunks();
+        for allocator::Chunk { base, size } in used_chunks {
+            self.bytes_mut()[base as usize..][..size.get() as usize].fill(0);
+        }
+        let used_chunks: Vec<_> = allocator.used_chunks().collect();
+        self.allocator = allocator;
+        for allocator::Chunk { base, size } in used_chunks {
+            let bytes = reader.read_bytes(size.get() as usize)?;
+            self.bytes_mut()[base as usize..][..bytes.len()].copy_from_slice(bytes);
+        }
+
+        let runs: Vec<(u32, u8)> = reader.read()?;
+        if runs.iter().map(|&(count, _)| u64::from(count)).sum::<u64>() != Self::PAGE_COUNT.into() {
+            return Err("Save state has the wrong number of pages".to_string());
+        }
+        let mut page = 0;
+        for (count, bits) in runs {
+            self.protections[page..][..count as usize].fill(Protection::from_bits(bits.into()));
+            page += count as usize;
+        }
+
+        self.null_segment_size = null_segment_size;
+        self.debug_heap = reader.read()?;
+        self.fault = None;
+        Ok(())
+    }
+
+    /// Like [Self::reserve], but returns [false] rather than panicking if any
+    /// part of the region is already in use. The region can be freed with
+    /// [Self::free_pages].
+    pub fn try_reserve(&mut self, base: VAddr, size: GuestUSize) -> bool {
+        self.allocator
+            .try_reserve(allocator::Chunk::new(base, size))
+    }
+
+    /// Check whether a region could be reserved with [Self::try_reserve],
+    /// without reserving it.
+    pub fn is_unused(&self, base: VAddr, size: GuestUSize) -> bool {
+        self.allocator.is_unused(allocator::Chunk::new(base, size))
+    }
+
+    /// Check whether an address is in allocated memory, i.e. not in the null
+    /// segment and not free for the allocator to use.
+    pub fn is_mapped(&self, addr: VAddr) -> bool {
+        addr >= self.null_segment_size && !self.is_unused(addr, 1)
+    }
+
+    /// Allocate `size` bytes (a multiple of [Self::PAGE_SIZE]) of page-aligned
+    /// memory, for use by `mmap()`. Returns [None] if there's no space.
+    pub fn alloc_pages(&mut self, size: GuestUSize) -> Option<MutVoidPtr> {
+        let ptr = Ptr::from_bits(self.allocator.alloc_pages(size)?);
+        log_dbg!("Allocated pages {:?} ({:#x} bytes)", ptr, size);
+        Some(ptr)
+    }
+
+    /// Free and zero some pages allocated with [Self::alloc_pages] or
+    /// [Self::try_reserve]. Unlike [Self::free], this can free part of an
+    /// allocation, as `munmap()` can.
+    pub fn free_pages(&mut self, base: VAddr, size: GuestUSize) {
+        assert!(base % Self::PAGE_SIZE == 0 && size % Self::PAGE_SIZE == 0);
+        assert!(self.allocator.release(allocator::Chunk::new(base, size)));
+        self.bytes_mut()[base as usize..][..size as usize].fill(0);
+        log_dbg!("Freed pages {:#x} ({:#x} bytes)", base, size);
+    }
 }
@@ -4,6 +4,7 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 use super::{GuestUSize, Mem, VAddr};
+use crate::save_state::{SaveState, StateReader, StateWriter};
 use std::collections::BTreeMap;
 use std::num::NonZeroU32;
 
@@ -58,6 +59,19 @@ impl Chunk {
     }
 }
 
+impl SaveState for Chunk {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&self.base);
+        writer.write(&self.size);
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        Ok(Chunk {
+            base: reader.read()?,
+            size: reader.read()?,
+        })
+    }
+}
+
 impl std::fmt::Debug for Chunk {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -124,6 +138,13 @@ mod collections {
             }
             Some(self.remove_with_base(chunk.base).unwrap())
         }
+        /// Find the chunk containing an address, if any.
+        #[inline(always)]
+        pub fn get_containing(&self, addr: VAddr) -> Option<Chunk> {
+            let (&base, &size) = self.chunks.range(..=addr).next_back()?;
+            let chunk = Chunk { base, size };
+            chunk.contains(addr).then_some(chunk)
+        }
         #[inline(always)]
         pub fn drain(self) -> impl Iterator<Item = Chunk> {
             self.chunks
@@ -134,6 +155,11 @@ mod collections {
         pub fn get_size_with_base(&self, base: VAddr) -> Option<NonZeroU32> {
             self.chunks.get(&base).copied()
         }
+        pub fn iter(&self) -> impl Iterator<Item = Chunk> + '_ {
+            self.chunks
+                .iter()
+                .map(|(&base, &size)| Chunk { base, size })
+        }
     }
 
     #[derive(Default, Debug)]
@@ -276,6 +302,14 @@ impl Allocator {
     }
 
     pub fn reserve(&mut self, chunk: Chunk) {
+        if !self.try_reserve(chunk) {
+            panic!("Could not reserve chunk {:?}!", chunk);
+        }
+    }
+
+    /// Like [Self::reserve], but returns [false] rather than panicking if any
+    /// part of the chunk is already in use.
+    pub fn try_reserve(&mut self, chunk: Chunk) -> bool {
         let mut to_trisect = None;
         for unused_chunk in self.unused_chunks.iter() {
             if unused_chunk.trisect_by(chunk).is_some() {
@@ -285,7 +319,7 @@ impl Allocator {
         }
 
         let Some(to_trisect) = to_trisect else {
-            panic!("Could not reserve chunk {:?}!", chunk);
+            return false;
         };
 
         let (before, after) = to_trisect.trisect_by(chunk).unwrap();
@@ -297,6 +331,15 @@ impl Allocator {
             self.unused_chunks.insert(after);
         }
         self.used_chunks.insert(chunk);
+        true
+    }
+
+    /// Check whether [Self::try_reserve] would succeed for a chunk, without
+    /// reserving it.
+    pub fn is_unused(&self, chunk: Chunk) -> bool {
+        self.unused_chunks
+            .iter()
+            .any(|unused_chunk| unused_chunk.trisect_by(chunk).is_some())
     }
 
     pub fn alloc(&mut self, size: GuestUSize) -> VAddr {
@@ -318,8 +361,59 @@ impl Allocator {
         alloc.base
     }
 
+    /// Allocate `size` bytes (which must be a multiple of the page size) at a
+    /// page-aligned address, for use by `mmap()`. Unlike [Self::alloc], this
+    /// returns [None] if there's no space.
+    pub fn alloc_pages(&mut self, size: GuestUSize) -> Option<VAddr> {
+        assert!(size != 0 && size % Mem::PAGE_SIZE == 0);
+
+        // The smallest chunk that fits is picked, to avoid fragmentation.
+        let mut best_fit: Option<(Chunk, VAddr)> = None;
+        for unused_chunk in self.unused_chunks.iter() {
+            let Some(base) = unused_chunk.base.checked_next_multiple_of(Mem::PAGE_SIZE) else {
+                continue;
+            };
+            let fits = base
+                .checked_add(size - 1)
+                .is_some_and(|last_byte| last_byte <= unused_chunk.last_byte());
+            let smaller = match best_fit {
+                Some((best, _)) => unused_chunk.size < best.size,
+                None => true,
+            };
+            if fits && smaller {
+                best_fit = Some((unused_chunk, base));
+            }
+        }
+
+        let (_, base) = best_fit?;
+        assert!(self.try_reserve(Chunk::new(base, size)));
+        Some(base)
+    }
+
+    /// Stop using part of a used chunk, for use by `munmap()`. The part is
+    /// returned to the unused memory and the rest stays in use. Returns
+    /// [false] if the part isn't entirely inside a used chunk.
+    pub fn release(&mut self, part: Chunk) -> bool {
+        let Some(used_chunk) = self.used_chunks.get_containing(part.base) else {
+            return false;
+        };
+        let Some((before, after)) = used_chunk.trisect_by(part) else {
+            return false;
+        };
+
+        self.used_chunks.remove_with_base(used_chunk.base);
+        if let Some(before) = before {
+            self.used_chunks.insert(before);
+        }
+        if let Some(after) = after {
+            self.used_chunks.insert(after);
+        }
+        self.insert_unused(part);
+        true
+    }
+
     /// This is used for realloc
-    pub fn find_allocated_size(&mut self, base: VAddr) -> GuestUSize {
+    pub fn find_allocated_size(&self, base: VAddr) -> GuestUSize {
         let Some(size) = self.used_chunks.get_size_with_base(base) else {
             panic!("Can't find {:#x}, unknown allocation!", base);
         };
@@ -334,21 +428,53 @@ impl Allocator {
             return 0;
         };
 
-        if let Some(adjacent) = self
+        self.insert_unused(freed);
+
+        freed.size.get()
+    }
+
+    /// Write the allocator's state to a save state. The unused chunks are
+    /// written in the order [Self::alloc] searches them, so that a loaded
+    /// allocator makes the same allocations.
+    pub(super) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write(&self.used_chunks.iter().collect::<Vec<_>>());
+        writer.write(&self.unused_chunks.iter().collect::<Vec<_>>());
+    }
+
+    pub(super) fn load_state(reader: &mut StateReader) -> Result<Allocator, String> {
+        let mut used_chunks: ChunkMap = Default::default();
+        for chunk in reader.read::<Vec<Chunk>>()? {
+            used_chunks.insert(chunk);
+        }
+        let mut unused_chunks: SizeBucketedChunkMap = Default::default();
+        for chunk in reader.read::<Vec<Chunk>>()? {
+            unused_chunks.insert(chunk);
+        }
+        Ok(Allocator {
+            used_chunks,
+            unused_chunks,
+        })
+    }
+
+    /// Iterate over the chunks that are in use.
+    pub(super) fn used_chunks(&self) -> impl Iterator<Item = Chunk> + '_ {
+        self.used_chunks.iter()
+    }
+
+    /// Add a chunk to the unused memory, combining it with any adjacent unused
+    /// chunks.
+    fn insert_unused(&mut self, chunk: Chunk) {
+        let mut combined = chunk;
+        if let Some(after) = self
             .unused_chunks
-            .remove_with_base(freed.last_byte() + 1)
-            .or_else(|| self.unused_chunks.remove_with_end(freed.base))
+            .remove_with_base(combined.last_byte().wrapping_add(1))
         {
-            let combined = Chunk::new(
-                freed.base.min(adjacent.base),
-                freed.size.get() + adjacent.size.get(),
-            );
-            self.unused_chunks.insert(combined);
-        } else {
-            self.unused_chunks.insert(freed);
+            combined = Chunk::new(combined.base, combined.size.get() + after.size.get());
         }
-
-        freed.size.get()
+        if let Some(before) = self.unused_chunks.remove_with_end(combined.base) {
+            combined = Chunk::new(before.base, before.size.get() + combined.size.get());
+        }
+        self.unused_chunks.insert(combined);
     }
 
     pub(super) fn reset_and_drain_used_chunks(&mut self) -> impl Iterator<Item = Chunk> {
@@ -357,3 +483,29 @@ impl Allocator {
         chunks.drain()
     }
 }
+
+#[cfg(test)]
+mod allocator_tests {
+    use super::*;
+    #[test]
+    fn pages() {
+        let mut allocator = Allocator::new();
+        let small = allocator.alloc(100);
+        let pages = allocator.alloc_pages(Mem::PAGE_SIZE * 3).unwrap();
+        assert_eq!(pages % Mem::PAGE_SIZE, 0);
+        assert!(pages >= small + 100);
+
+        // Releasing part of an allocation leaves the rest in use.
+        assert!(allocator.release(Chunk::new(pages + Mem::PAGE_SIZE, Mem::PAGE_SIZE)));
+        assert!(!allocator.try_reserve(Chunk::new(pages, Mem::PAGE_SIZE)));
+        assert!(allocator.try_reserve(Chunk::new(pages + Mem::PAGE_SIZE, Mem::PAGE_SIZE)));
+        assert!(!allocator.release(Chunk::new(pages, Mem::PAGE_SIZE * 3)));
+
+        // Everything is merged again after it's all released.
+        for i in 0..3 {
+            assert!(allocator.release(Chunk::new(pages + Mem::PAGE_SIZE * i, Mem::PAGE_SIZE)));
+        }
+        let _ = allocator.free(small);
+        assert_eq!(allocator.alloc(Mem::MAIN_THREAD_STACK_LOW_END), 0);
+    }
+}
@@ -0,0 +1,408 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Heap debugging mode (see `--debug-heap`).
+//!
+//! In this mode, each allocation made with [Mem::alloc] is surrounded by
+//! redzones filled with a poison value. Freed allocations are filled with a
+//! different poison value and kept in a quarantine for a while before their
+//! memory is reused. The poison values are checked when an allocation is
+//! freed and when it leaves the quarantine, so writes outside an allocation or
+//! after it was freed can be detected, though only some time after they
+//! happen. Double frees are detected too.
+//!
+//! The return address of the call from guest code that made each allocation
+//! is recorded (see [Mem::set_allocation_site]), so problems can be blamed on
+//! the app's code, and the allocations remaining at exit can be summarized.
+//!
+//! Problems are logged rather than causing a panic, because apps can have
+//! memory bugs that are harmless on a real device.
+
+use super::{GuestUSize, Mem, VAddr};
+use crate::save_state::impl_SaveState;
+use std::collections::{HashMap, VecDeque};
+
+/// Size of the redzones before and after each allocation. This is a multiple
+/// of the allocator's alignment, so allocations remain aligned.
+const REDZONE_SIZE: GuestUSize = 16;
+/// Value redzones are filled with.
+const REDZONE_POISON: u8 = 0xfd;
+/// Value freed allocations are filled with.
+const FREED_POISON: u8 = 0xdd;
+/// Maximum total size of the allocations in the quarantine.
+const QUARANTINE_SIZE: GuestUSize = 4 * 1024 * 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) struct Allocation {
+    size: GuestUSize,
+    /// See [Mem::set_allocation_site].
+    site: VAddr,
+}
+impl_SaveState!(Allocation { size, site });
+
+#[derive(Default)]
+pub struct DebugHeap {
+    /// Allocations that haven't been freed yet, by address (not including the
+    /// redzone before them).
+    allocations: HashMap<VAddr, Allocation>,
+    /// Freed allocations, oldest first.
+    quarantine: VecDeque<(VAddr, Allocation)>,
+    /// Total size of the allocations in the quarantine.
+    quarantine_size: GuestUSize,
+    /// See [Mem::set_allocation_site].
+    site: VAddr,
+}
+impl_SaveState!(DebugHeap {
+    allocations,
+    quarantine,
+    quarantine_size,
+    site,
+});
+
+/// Allocations made from a particular site that haven't been freed, see
+/// [Mem::debug_heap_leaks].
+pub struct LeakSite {
+    pub site: VAddr,
+    pub count: usize,
+    pub size: GuestUSize,
+}
+
+/// Describe a site for a log message.
+fn site_str(site: VAddr) -> String {
+    if site == 0 {
+        "touchHLE".to_string()
+    } else {
+        format!("{:#x}", site)
+    }
+}
+
+/// A problem detected by heap debugging mode. `by` is the site that was
+/// freeing memory when the problem was detected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum HeapError {
+    DoubleFree {
+        addr: VAddr,
+        by: VAddr,
+    },
+    UnknownFree {
+        addr: VAddr,
+        by: VAddr,
+    },
+    /// A redzone of an allocation was written to.
+    OutOfBounds {
+        write: VAddr,
+        addr: VAddr,
+        allocation: Allocation,
+        by: VAddr,
+    },
+    /// An allocation in the quarantine was written to.
+    UseAfterFree {
+        write: VAddr,
+        addr: VAddr,
+        allocation: Allocation,
+    },
+}
+impl std::fmt::Display for HeapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            HeapError::DoubleFree { addr, by } => {
+                write!(f, "Double free {:#x} by {}", addr, site_str(by))
+            }
+            HeapError::UnknownFree { addr, by } => {
+                write!(f, "Free of unknown pointer {:#x} by {}", addr, site_str(by))
+            }
+            HeapError::OutOfBounds {
+                write,
+                addr,
+                allocation,
+                by,
+            } => write!(
+                f,
+                "Write to {:#x}, outside allocation {:#x} ({:#x} bytes, allocated by {}), detected when it was freed by {}",
+                write,
+                addr,
+                allocation.size,
+                site_str(allocation.site),
+                site_str(by)
+            ),
+            HeapError::UseAfterFree {
+                write,
+                addr,
+                allocation,
+            } => write!(
+                f,
+                "Write to {:#x} after allocation {:#x} ({:#x} bytes, allocated by {}) was freed",
+                write,
+                addr,
+                allocation.size,
+                site_str(allocation.site)
+            ),
+        }
+    }
+}
+
+impl Mem {
+    /// Turn on heap debugging mode. This should be done before anything is
+    /// allocated.
+    pub fn enable_debug_heap(&mut self) {
+        self.debug_heap = Some(DebugHeap::default());
+    }
+
+    /// Set the address of the guest code responsible for subsequent
+    /// allocations, for heap debugging. This should be the return address of
+    /// the call from guest code to the host function that is running, or 0 if
+    /// there is none. The previous value is returned so it can be restored.
+    pub fn set_allocation_site(&mut self, site: VAddr) -> VAddr {
+        match self.debug_heap {
+            Some(ref mut heap) => std::mem::replace(&mut heap.site, site),
+            None => 0,
+        }
+    }
+
+    pub(super) fn debug_alloc(&mut self, size: GuestUSize) -> VAddr {
+        let base = self
+            .allocator
+            .alloc(size.checked_add(REDZONE_SIZE * 2).unwrap());
+        // The allocator may have rounded up the size, and the extra space is
+        // treated as part of the redzone after the allocation.
+        let chunk_size = self.allocator.find_allocated_size(base);
+        let chunk = &mut self.bytes_mut()[base as usize..][..chunk_size as usize];
+        let (redzone_before, rest) = chunk.split_at_mut(REDZONE_SIZE as usize);
+        let (allocation, redzone_after) = rest.split_at_mut(size as usize);
+        redzone_before.fill(REDZONE_POISON);
+        allocation.fill(0);
+        redzone_after.fill(REDZONE_POISON);
+
+        let addr = base + REDZONE_SIZE;
+        let heap = self.debug_heap.as_mut().unwrap();
+        let site = heap.site;
+        heap.allocations.insert(addr, Allocation { size, site });
+        addr
+    }
+
+    /// Free an allocation. Any problems detected are logged and returned.
+    pub(super) fn debug_free(&mut self, addr: VAddr) -> Vec<HeapError> {
+        let mut errors = Vec::new();
+        let heap = self.debug_heap.as_mut().unwrap();
+        let by = heap.site;
+        let Some(allocation) = heap.allocations.remove(&addr) else {
+            let error = if heap.quarantine.iter().any(|&(freed, _)| freed == addr) {
+                HeapError::DoubleFree { addr, by }
+            } else {
+                HeapError::UnknownFree { addr, by }
+            };
+            log!("Heap error: {}", error);
+            errors.push(error);
+            return errors;
+        };
+
+        if let Some(write) = self.check_redzones(addr, allocation) {
+            let error = HeapError::OutOfBounds {
+                write,
+                addr,
+                allocation,
+                by,
+            };
+            log!("Heap error: {}", error);
+            errors.push(error);
+        }
+        self.bytes_mut()[addr as usize..][..allocation.size as usize].fill(FREED_POISON);
+
+        let heap = self.debug_heap.as_mut().unwrap();
+        heap.quarantine.push_back((addr, allocation));
+        heap.quarantine_size += allocation.size;
+        loop {
+            let heap = self.debug_heap.as_mut().unwrap();
+            if heap.quarantine_size <= QUARANTINE_SIZE {
+                break;
+            }
+            let (addr, allocation) = heap.quarantine.pop_front().unwrap();
+            heap.quarantine_size -= allocation.size;
+            let bytes = &self.bytes()[addr as usize..][..allocation.size as usize];
+            if let Some(offset) = bytes.iter().position(|&b| b != FREED_POISON) {
+                let error = HeapError::UseAfterFree {
+                    write: addr + offset as GuestUSize,
+                    addr,
+                    allocation,
+                };
+                log!("Heap error: {}", error);
+                errors.push(error);
+            }
+            let base = addr - REDZONE_SIZE;
+            let chunk_size = self.allocator.free(base);
+            self.bytes_mut()[base as usize..][..chunk_size as usize].fill(0);
+        }
+        errors
+    }
+
+    pub(super) fn debug_realloc(&mut self, old_addr: VAddr, size: GuestUSize) -> VAddr {
+        let old_size = self
+            .debug_heap
+            .as_ref()
+            .unwrap()
+            .allocations
+            .get(&old_addr)
+            .map(|allocation| allocation.size);
+        let new_addr = self.debug_alloc(size);
+        // The allocation is always moved, so that pointers to the old one
+        // become pointers to freed memory.
+        if let Some(old_size) = old_size {
+            let size = old_size.min(size) as usize;
+            self.bytes_mut().copy_within(
+                old_addr as usize..old_addr as usize + size,
+                new_addr as usize,
+            );
+        }
+        self.debug_free(old_addr);
+        new_addr
+    }
+
+    /// Returns the address of the first write to a redzone of an allocation,
+    /// if there was one.
+    fn check_redzones(&self, addr: VAddr, allocation: Allocation) -> Option<VAddr> {
+        let base = addr - REDZONE_SIZE;
+        let chunk_size = self.allocator.find_allocated_size(base);
+        let chunk = &self.bytes()[base as usize..][..chunk_size as usize];
+        let redzone_before = &chunk[..REDZONE_SIZE as usize];
+        let redzone_after = &chunk[(REDZONE_SIZE + allocation.size) as usize..];
+        if let Some(i) = redzone_before.iter().position(|&b| b != REDZONE_POISON) {
+            Some(base + i as GuestUSize)
+        } else {
+            redzone_after
+                .iter()
+                .position(|&b| b != REDZONE_POISON)
+                .map(|i| addr + allocation.size + i as GuestUSize)
+        }
+    }
+
+    /// Summarize the allocations that haven't been freed, grouped by site and
+    /// sorted by total size, largest first. Returns [None] if heap debugging
+    /// mode is off.
+    pub fn debug_heap_leaks(&self) -> Option<Vec<LeakSite>> {
+        let heap = self.debug_heap.as_ref()?;
+        let mut sites: HashMap<VAddr, LeakSite> = HashMap::new();
+        for allocation in heap.allocations.values() {
+            let leak_site = sites.entry(allocation.site).or_insert(LeakSite {
+                site: allocation.site,
+                count: 0,
+                size: 0,
+            });
+            leak_site.count += 1;
+            leak_site.size += allocation.size;
+        }
+        let mut sites: Vec<LeakSite> = sites.into_values().collect();
+        sites.sort_by_key(|leak_site| (std::cmp::Reverse(leak_site.size), leak_site.site));
+        Some(sites)
+    }
+}
+
+#[cfg(test)]
+mod debug_heap_tests {
+    use super::*;
+
+    fn new_mem() -> Mem {
+        let mut mem = Mem::new();
+        mem.enable_debug_heap();
+        mem
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let mut mem = new_mem();
+        let addr = mem.alloc(10).to_bits();
+        assert_eq!(mem.bytes()[addr as usize - 1], REDZONE_POISON);
+        assert_eq!(mem.bytes()[addr as usize + 10], REDZONE_POISON);
+
+        // Writing to the allocation itself is fine.
+        mem.bytes_mut()[addr as usize..][..10].fill(1);
+        mem.bytes_mut()[addr as usize + 10] = 1;
+        assert_eq!(
+            mem.debug_free(addr),
+            [HeapError::OutOfBounds {
+                write: addr + 10,
+                addr,
+                allocation: Allocation { size: 10, site: 0 },
+                by: 0,
+            }]
+        );
+
+        let addr = mem.alloc(10).to_bits();
+        mem.bytes_mut()[addr as usize - 2] = 1;
+        mem.set_allocation_site(0x1000);
+        assert_eq!(
+            mem.debug_free(addr),
+            [HeapError::OutOfBounds {
+                write: addr - 2,
+                addr,
+                allocation: Allocation { size: 10, site: 0 },
+                by: 0x1000,
+            }]
+        );
+    }
+
+    #[test]
+    fn double_free() {
+        let mut mem = new_mem();
+        let addr = mem.alloc(10).to_bits();
+        assert!(mem.debug_free(addr).is_empty());
+        assert_eq!(
+            mem.debug_free(addr),
+            [HeapError::DoubleFree { addr, by: 0 }]
+        );
+        assert_eq!(
+            mem.debug_free(addr + 4),
+            [HeapError::UnknownFree {
+                addr: addr + 4,
+                by: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn use_after_free() {
+        let mut mem = new_mem();
+        let addr = mem.alloc(10).to_bits();
+        mem.bytes_mut()[addr as usize..][..10].fill(1);
+        assert!(mem.debug_free(addr).is_empty());
+        assert!(mem.bytes()[addr as usize..][..10]
+            .iter()
+            .all(|&b| b == FREED_POISON));
+
+        mem.bytes_mut()[addr as usize + 3] = 1;
+        // The write is only detected once the allocation leaves the
+        // quarantine, which this pushes it out of.
+        let big = mem.alloc(QUARANTINE_SIZE).to_bits();
+        assert_eq!(
+            mem.debug_free(big),
+            [HeapError::UseAfterFree {
+                write: addr + 3,
+                addr,
+                allocation: Allocation { size: 10, site: 0 },
+            }]
+        );
+    }
+
+    #[test]
+    fn leaks() {
+        let mut mem = new_mem();
+        mem.set_allocation_site(0x1000);
+        mem.alloc(8);
+        mem.alloc(8);
+        mem.set_allocation_site(0x2000);
+        mem.alloc(100);
+        let freed = mem.alloc(1000);
+        assert!(mem.debug_free(freed.to_bits()).is_empty());
+
+        let leaks: Vec<_> = mem
+            .debug_heap_leaks()
+            .unwrap()
+            .into_iter()
+            .map(|leak_site| (leak_site.site, leak_site.count, leak_site.size))
+            .collect();
+        assert_eq!(leaks, [(0x2000, 1, 100), (0x1000, 2, 16)]);
+
+        assert!(Mem::new().debug_heap_leaks().is_none());
+    }
+}
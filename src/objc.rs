@@ -19,6 +19,8 @@
 //! categories and dynamic class editing).
 
 use crate::dyld::{export_c_func, FunctionExports};
+use crate::mem::Mem;
+use crate::save_state::{StateReader, StateWriter};
 use crate::MutexId;
 use std::collections::HashMap;
 
@@ -36,7 +38,9 @@ pub use messages::{
 };
 pub use methods::{HostIMP, IMP};
 pub use objects::{
-    id, impl_HostObject_with_superclass, nil, AnyHostObject, HostObject, TrivialHostObject,
+    host_object_loader, id, impl_HostObject_with_save_state, impl_HostObject_with_superclass,
+    load_host_object, nil, AnyHostObject, HostObject, HostObjectLoader, HostObjectLoaders,
+    TrivialHostObject,
 };
 pub use selectors::{selector, SEL};
 
@@ -90,6 +94,33 @@ impl ObjC {
             message_type_info: None,
         }
     }
+
+    /// Write the runtime's state, including all objects, to a save state.
+    pub fn save_state(&self, mem: &Mem, writer: &mut StateWriter) {
+        let ObjC {
+            ref selectors,
+            objects: _, // see below
+            ref classes,
+            ref sync_mutexes,
+            message_type_info: _,
+        } = *self;
+        writer.write(selectors);
+        writer.write(classes);
+        writer.write(sync_mutexes);
+        self.save_objects(mem, writer);
+    }
+
+    /// Replace the runtime's state with one from a save state.
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        *self = ObjC {
+            selectors: reader.read()?,
+            classes: reader.read()?,
+            sync_mutexes: reader.read()?,
+            objects: Self::load_objects(reader)?,
+            message_type_info: None,
+        };
+        Ok(())
+    }
 }
 
 pub const FUNCTIONS: FunctionExports = &[
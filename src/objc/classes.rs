@@ -14,10 +14,12 @@ mod class_lists;
 pub(super) use class_lists::CLASS_LISTS;
 
 use super::{
-    id, method_list_t, nil, objc_object, AnyHostObject, HostIMP, HostObject, ObjC, IMP, SEL,
+    host_object_loader, id, impl_HostObject_with_save_state, method_list_t, nil, objc_object,
+    AnyHostObject, HostIMP, HostObjectLoaders, ObjC, IMP, SEL,
 };
 use crate::mach_o::MachO;
 use crate::mem::{guest_size_of, ConstPtr, ConstVoidPtr, GuestUSize, Mem, Ptr, SafeRead};
+use crate::save_state::impl_SaveState;
 use std::collections::HashMap;
 
 /// Generic pointer to an Objective-C class or metaclass.
@@ -46,7 +48,15 @@ pub(super) struct ClassHostObject {
     /// This is always >= the value in the superclass.
     pub(super) instance_size: GuestUSize,
 }
-impl HostObject for ClassHostObject {}
+impl_HostObject_with_save_state!(ClassHostObject);
+impl_SaveState!(ClassHostObject {
+    name,
+    is_metaclass,
+    superclass,
+    methods,
+    _instance_start,
+    instance_size,
+});
 
 /// Placeholder object for classes and metaclasses referenced by the app that
 /// we don't have an implementation for.
@@ -57,7 +67,8 @@ pub(super) struct UnimplementedClass {
     pub(super) name: String,
     pub(super) is_metaclass: bool,
 }
-impl HostObject for UnimplementedClass {}
+impl_HostObject_with_save_state!(UnimplementedClass);
+impl_SaveState!(UnimplementedClass { name, is_metaclass });
 
 /// Substitute object for classes and metaclasses from the guest app that we do
 /// not want to support (see [substitute_classes]).
@@ -67,7 +78,14 @@ pub(super) struct FakeClass {
     pub(super) name: String,
     pub(super) is_metaclass: bool,
 }
-impl HostObject for FakeClass {}
+impl_HostObject_with_save_state!(FakeClass);
+impl_SaveState!(FakeClass { name, is_metaclass });
+
+pub(super) const HOST_OBJECTS: HostObjectLoaders = &[
+    host_object_loader!(ClassHostObject),
+    host_object_loader!(UnimplementedClass),
+    host_object_loader!(FakeClass),
+];
 
 /// The layout of a class in an app binary.
 ///
@@ -460,7 +478,57 @@ impl ObjC {
     }
 
     fn find_template(name: &str) -> Option<&'static ClassTemplate> {
-        crate::dyld::search_lists(CLASS_LISTS, name).map(|&(_name, ref template)| template)
+        crate::dyld::search_grouped_lists(CLASS_LISTS, name)
+            .map(|(_framework, &(_name, ref template))| template)
+    }
+
+    /// Find which class template a host method implementation comes from, so
+    /// it can be referred to in a save state. Returns the class name, whether
+    /// it's a class method, and the selector.
+    pub(super) fn find_host_imp(
+        host_imp: &'static dyn HostIMP,
+    ) -> Option<(&'static str, bool, &'static str)> {
+        let host_imp: *const dyn HostIMP = host_imp;
+        for &class_list in CLASS_LISTS.iter().flat_map(|&(_, lists)| lists) {
+            for &(class_name, ref template) in class_list {
+                for (is_class_method, method_list) in [
+                    (true, template.class_methods),
+                    (false, template.instance_methods),
+                ] {
+                    for &(selector, other_host_imp) in method_list {
+                        let other_host_imp: *const dyn HostIMP = other_host_imp;
+                        if host_imp.cast::<()>() == other_host_imp.cast::<()>() {
+                            return Some((class_name, is_class_method, selector));
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// The reverse of [Self::find_host_imp].
+    pub(super) fn get_host_imp(
+        class_name: &str,
+        is_class_method: bool,
+        selector: &str,
+    ) -> Option<&'static dyn HostIMP> {
+        let template = Self::find_template(class_name)?;
+        let method_list = if is_class_method {
+            template.class_methods
+        } else {
+            template.instance_methods
+        };
+        method_list
+            .iter()
+            .find(|&&(other_selector, _)| other_selector == selector)
+            .map(|&(_, host_imp)| host_imp)
+    }
+
+    /// Get the name of the framework a host class belongs to, or [None] if
+    /// the class isn't a host class.
+    pub fn host_class_framework(name: &str) -> Option<&'static str> {
+        crate::dyld::search_grouped_lists(CLASS_LISTS, name).map(|(framework, _)| framework)
     }
 
     /// For use by [crate::dyld]: get the class or metaclass referenced by an
@@ -704,6 +772,33 @@ impl ObjC {
         }
     }
 
+    /// Get the addresses of all the guest method implementations, with names
+    /// like `-[NSObject init]`. This is for debugging purposes, e.g. stack
+    /// traces.
+    pub fn guest_method_symbols(&self, mem: &Mem) -> Vec<(u32, String)> {
+        let mut symbols = Vec::new();
+        for &class in self.classes.values() {
+            for (class, kind) in [(class, '-'), (Self::read_isa(class, mem), '+')] {
+                let Some(ClassHostObject { name, methods, .. }) = self
+                    .get_host_object(class)
+                    .and_then(|host_object| host_object.as_any().downcast_ref())
+                else {
+                    // Unimplemented or fake class, which has no methods.
+                    break;
+                };
+                for (selector, imp) in methods {
+                    if let IMP::Guest(imp) = imp {
+                        symbols.push((
+                            imp.addr_without_thumb_bit(),
+                            format!("{}[{} {}]", kind, name, selector.as_str(mem)),
+                        ));
+                    }
+                }
+            }
+        }
+        symbols
+    }
+
     pub fn get_class_name(&self, class: Class) -> &str {
         let host_object = self.get_host_object(class).unwrap();
         if let Some(ClassHostObject { name, .. }) = host_object.as_any().downcast_ref() {
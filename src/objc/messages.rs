@@ -14,6 +14,7 @@
 use super::{id, nil, Class, ObjC, IMP, SEL};
 use crate::abi::{CallFromHost, GuestRet};
 use crate::mem::{ConstPtr, MutVoidPtr, SafeRead};
+use crate::trace::{self, RawWord};
 use crate::Environment;
 use std::any::TypeId;
 
@@ -36,6 +37,13 @@ fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2
     if receiver == nil {
         // https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/ObjectiveC/Chapters/ocObjectsClasses.html#//apple_ref/doc/uid/TP30001163-CH11-SW7
         log_dbg!("[nil {}]", selector.as_str(&env.mem));
+        if env.tracer.is_some() {
+            let selector = selector.as_str(&env.mem).to_string();
+            let call = trace::message(env, receiver, None, false, selector, None);
+            trace::set_pending(env, call);
+            let call = trace::begin_call(env, &[]);
+            trace::end_call(env, call, &RawWord(0));
+        }
         env.cpu.regs_mut()[0..2].fill(0);
         return;
     }
@@ -88,7 +96,12 @@ fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2
                 continue;
             }
 
-            if let Some(imp) = methods.get(&selector) {
+            if let Some(&imp) = methods.get(&selector) {
+                let traced_call = if env.tracer.is_some() {
+                    trace_message(env, receiver, selector, orig_class, class, imp)
+                } else {
+                    None
+                };
                 match imp {
                     IMP::Host(host_imp) => {
                         // TODO: do type checks when calling GuestIMPs too.
@@ -112,11 +125,23 @@ Type mismatch when sending message {} to {:?}!
                                 );
                             }
                         }
+                        trace::set_pending(env, traced_call);
                         host_imp.call_from_guest(env)
                     }
-                    // We can't create a new stack frame, because that would
-                    // interfere with pass-through of stack arguments.
-                    IMP::Guest(guest_imp) => guest_imp.call_without_pushing_stack_frame(env),
+                    IMP::Guest(guest_imp) => {
+                        // The argument types aren't known, so the registers
+                        // that might contain arguments are traced instead.
+                        trace::set_pending(env, traced_call);
+                        let regs = env.cpu.regs();
+                        let args = [0, 1, 2, 3].map(|i| RawWord(regs[i]));
+                        let traced_call =
+                            trace::begin_call(env, &[&args[0], &args[1], &args[2], &args[3]]);
+                        // We can't create a new stack frame, because that would
+                        // interfere with pass-through of stack arguments.
+                        guest_imp.call_without_pushing_stack_frame(env);
+                        let retval = RawWord(env.cpu.regs()[0]);
+                        trace::end_call(env, traced_call, &retval);
+                    }
                 }
                 return;
             } else {
@@ -157,6 +182,36 @@ Type mismatch when sending message {} to {:?}!
     }
 }
 
+/// Check whether a message should be traced (see [crate::trace]). `class` is
+/// the class the method implementation was found on.
+fn trace_message(
+    env: &mut Environment,
+    receiver: id,
+    selector: SEL,
+    orig_class: Class,
+    class: Class,
+    imp: IMP,
+) -> Option<trace::Call> {
+    let &super::ClassHostObject {
+        ref name,
+        is_metaclass,
+        ..
+    } = env
+        .objc
+        .get_host_object(orig_class)
+        .unwrap()
+        .as_any()
+        .downcast_ref()
+        .unwrap();
+    let name = name.clone();
+    let framework = match imp {
+        IMP::Host(_) => ObjC::host_class_framework(env.objc.get_class_name(class)),
+        IMP::Guest(_) => Some("app"),
+    };
+    let selector = selector.as_str(&env.mem).to_string();
+    trace::message(env, receiver, Some(name), is_metaclass, selector, framework)
+}
+
 /// Standard variant of `objc_msgSend`. See [objc_msgSend_inner].
 #[allow(non_snake_case)]
 pub(super) fn objc_msgSend(env: &mut Environment, receiver: id, selector: SEL) {
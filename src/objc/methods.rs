@@ -13,6 +13,7 @@ use super::{
 };
 use crate::abi::{CallFromGuest, DotDotDot, GuestArg, GuestFunction, GuestRet};
 use crate::mem::{guest_size_of, ConstPtr, GuestUSize, Mem, Ptr, SafeRead};
+use crate::save_state::{SaveState, StateReader, StateWriter};
 use crate::Environment;
 use std::any::TypeId;
 
@@ -24,11 +25,54 @@ use std::any::TypeId;
 /// "guest methods" (functions in the guest app). Either way, the function needs
 /// to conform to the same ABI: [id] and [SEL] must be its first two parameters.
 #[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone)]
 pub enum IMP {
     Host(&'static dyn HostIMP),
     Guest(GuestIMP),
 }
 
+/// Host method implementations are written to save states as a reference to
+/// the class template they come from.
+impl SaveState for IMP {
+    fn save(&self, writer: &mut StateWriter) {
+        match *self {
+            IMP::Host(host_imp) => {
+                writer.write(&true);
+                let Some((class_name, is_class_method, selector)) = ObjC::find_host_imp(host_imp)
+                else {
+                    writer.unsupported("host methods that aren't from a class template");
+                    return;
+                };
+                writer.write(&class_name.to_string());
+                writer.write(&is_class_method);
+                writer.write(&selector.to_string());
+            }
+            IMP::Guest(guest_imp) => {
+                writer.write(&false);
+                writer.write(&guest_imp);
+            }
+        }
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        if !reader.read::<bool>()? {
+            return Ok(IMP::Guest(reader.read()?));
+        }
+        let class_name: String = reader.read()?;
+        let is_class_method: bool = reader.read()?;
+        let selector: String = reader.read()?;
+        ObjC::get_host_imp(&class_name, is_class_method, &selector)
+            .map(IMP::Host)
+            .ok_or_else(|| {
+                format!(
+                    "Save state refers to unknown host method {}[{} {}]",
+                    if is_class_method { '+' } else { '-' },
+                    class_name,
+                    selector
+                )
+            })
+    }
+}
+
 /// Type for any host function implementing a method (see also [IMP]).
 pub trait HostIMP: CallFromGuest {
     /// See [MsgSendSignature::type_info].
@@ -20,9 +20,14 @@
 //!
 //! See also: [crate::frameworks::foundation::ns_object].
 
+mod host_object_lists;
+
 use super::{Class, ClassHostObject};
 use crate::mem::{guest_size_of, GuestUSize, Mem, MutPtr, Ptr, SafeRead};
+use crate::save_state::{SaveState, StateReader, StateWriter};
+use host_object_lists::HOST_OBJECT_LISTS;
 use std::any::Any;
+use std::collections::HashMap;
 use std::num::NonZeroU32;
 
 /// Memory layout of a minimal Objective-C object. See [id].
@@ -85,6 +90,15 @@ pub trait HostObject: Any + 'static {
     fn as_superclass_mut<'a>(&'a mut self) -> Option<&'a mut (dyn AnyHostObject + 'static)> {
         None
     }
+    /// Write the host object to a save state (see [crate::save_state]) and
+    /// return the name its loader is listed under in a [HostObjectLoaders]
+    /// list, or return [None] if this type of host object can't be saved.
+    ///
+    /// Use [impl_HostObject_with_save_state] rather than implementing this
+    /// yourself.
+    fn save_state(&self, _writer: &mut StateWriter) -> Option<&'static str> {
+        None
+    }
 }
 
 /// Convenience macro for implementing [HostObject] where the host object type
@@ -122,6 +136,64 @@ macro_rules! impl_HostObject_with_superclass {
 }
 pub use crate::impl_HostObject_with_superclass; // #[macro_export] is weird...
 
+/// Convenience macro for implementing [HostObject] for a host object type that
+/// can be written to save states, because it implements [SaveState]. The type
+/// must also be listed in a [HostObjectLoaders] list with [host_object_loader],
+/// and that list must be in [HOST_OBJECT_LISTS].
+///
+/// ```ignore
+/// struct FooHostObject {
+///     bar: id,
+/// }
+/// impl_HostObject_with_save_state!(FooHostObject);
+/// impl_SaveState!(FooHostObject { bar });
+///
+/// pub const HOST_OBJECTS: HostObjectLoaders = &[host_object_loader!(FooHostObject)];
+/// ```
+#[macro_export]
+macro_rules! impl_HostObject_with_save_state {
+    ( $ty:ident ) => {
+        impl $crate::objc::HostObject for $ty {
+            fn save_state(
+                &self,
+                writer: &mut $crate::save_state::StateWriter,
+            ) -> Option<&'static str> {
+                writer.write(self);
+                Some(stringify!($ty))
+            }
+        }
+    };
+}
+pub use crate::impl_HostObject_with_save_state; // #[macro_export] is weird...
+
+/// Type for functions that load a host object from a save state, see
+/// [HostObject::save_state].
+pub type HostObjectLoader = fn(&mut StateReader) -> Result<Box<dyn AnyHostObject>, String>;
+
+/// Type for lists of host object loaders. Each module with host objects that
+/// can be written to save states should export a constant using this type,
+/// see [impl_HostObject_with_save_state].
+pub type HostObjectLoaders = &'static [(&'static str, HostObjectLoader)];
+
+/// Generic [HostObjectLoader], use [host_object_loader] rather than this.
+pub fn load_host_object<T: AnyHostObject + SaveState>(
+    reader: &mut StateReader,
+) -> Result<Box<dyn AnyHostObject>, String> {
+    Ok(Box::new(T::load(reader)?))
+}
+
+/// Macro for creating an entry in a [HostObjectLoaders] list.
+#[macro_export]
+macro_rules! host_object_loader {
+    ( $ty:ident ) => {
+        (
+            stringify!($ty),
+            $crate::objc::load_host_object::<$ty> as $crate::objc::HostObjectLoader,
+        )
+    };
+}
+pub use crate::host_object_loader; // #[macro_export] is weird...
+
 /// Trait wrapping [HostObject] with a blanket implementation to make
 /// downcasting work. Don't implement it yourself.
 ///
@@ -142,7 +214,15 @@ impl<T: HostObject> AnyHostObject for T {
 
 /// Empty host object used by `[NSObject alloc]`.
 pub struct TrivialHostObject;
-impl HostObject for TrivialHostObject {}
+impl_HostObject_with_save_state!(TrivialHostObject);
+impl SaveState for TrivialHostObject {
+    fn save(&self, _writer: &mut StateWriter) {}
+    fn load(_reader: &mut StateReader) -> Result<Self, String> {
+        Ok(TrivialHostObject)
+    }
+}
+
+const HOST_OBJECTS: HostObjectLoaders = &[host_object_loader!(TrivialHostObject)];
 
 impl super::ObjC {
     /// Read the all-important `isa`.
@@ -348,4 +428,52 @@ impl super::ObjC {
 
         mem.free(object.cast());
     }
+
+    /// Write all the objects and their host objects to a save state. Objects
+    /// whose host objects can't be saved are reported as unsupported, by their
+    /// class name.
+    pub(super) fn save_objects(&self, mem: &Mem, writer: &mut StateWriter) {
+        writer.write(&self.objects.len());
+        for (&object, entry) in self.objects.iter() {
+            let HostObjectEntry {
+                ref host_object,
+                refcount,
+            } = *entry;
+            writer.write(&object);
+            writer.write(&refcount);
+            let mut host_object_writer = StateWriter::new();
+            if let Some(kind) = host_object.save_state(&mut host_object_writer) {
+                writer.write(&kind.to_string());
+                writer.append(host_object_writer);
+            } else {
+                let class = Self::read_isa(object, mem);
+                writer.unsupported(format!("{} objects", self.get_class_name(class)));
+            }
+        }
+    }
+
+    /// Read the objects written by [Self::save_objects].
+    pub(super) fn load_objects(
+        reader: &mut StateReader,
+    ) -> Result<HashMap<id, HostObjectEntry>, String> {
+        let count: usize = reader.read()?;
+        let mut objects = HashMap::with_capacity(count);
+        for _ in 0..count {
+            let object: id = reader.read()?;
+            let refcount = reader.read()?;
+            let kind: String = reader.read()?;
+            let Some(&(_, loader)) = crate::dyld::search_lists(HOST_OBJECT_LISTS, &kind) else {
+                return Err(format!("Save state has unknown host object type {}", kind));
+            };
+            let host_object = loader(reader)?;
+            objects.insert(
+                object,
+                HostObjectEntry {
+                    host_object,
+                    refcount,
+                },
+            );
+        }
+        Ok(objects)
+    }
 }
@@ -0,0 +1,26 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Separate module just for the host object loader lists, since this should
+//! eventually cover every kind of host object.
+
+use crate::frameworks::foundation;
+
+/// All the lists of host object loaders that loading a save state should search
+/// through. See [super::HostObject::save_state].
+pub const HOST_OBJECT_LISTS: &[super::HostObjectLoaders] = &[
+    // Not a framework! The runtime's own host objects.
+    super::HOST_OBJECTS,
+    crate::objc::classes::HOST_OBJECTS,
+    foundation::ns_array::HOST_OBJECTS,
+    foundation::ns_autorelease_pool::HOST_OBJECTS,
+    foundation::ns_data::HOST_OBJECTS,
+    foundation::ns_date::HOST_OBJECTS,
+    foundation::ns_dictionary::HOST_OBJECTS,
+    foundation::ns_lock::HOST_OBJECTS,
+    foundation::ns_set::HOST_OBJECTS,
+    foundation::ns_string::HOST_OBJECTS,
+    foundation::ns_value::HOST_OBJECTS,
+];
@@ -18,6 +18,7 @@ use super::ObjC;
 use crate::abi::{GuestArg, GuestRet};
 use crate::mach_o::MachO;
 use crate::mem::{ConstPtr, Mem, MutPtr, Ptr};
+use crate::save_state::{SaveState, StateReader, StateWriter};
 use crate::Environment;
 
 /// Create a string literal for a selector from Objective-C message syntax
@@ -57,6 +58,15 @@ impl GuestRet for SEL {
     }
 }
 
+impl SaveState for SEL {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&self.0)
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        Ok(SEL(reader.read()?))
+    }
+}
+
 impl SEL {
     pub fn as_str(self, mem: &Mem) -> &str {
         // selectors are probably always UTF-8 but this hasn't been verified
@@ -92,7 +102,7 @@ impl ObjC {
     /// [ObjC::register_bin_selectors], so that selector strings in the app
     /// binary can be re-used. [crate::dyld] calls both of these.
     pub fn register_host_selectors(&mut self, mem: &mut Mem) {
-        for &class_list in super::CLASS_LISTS {
+        for &class_list in super::CLASS_LISTS.iter().flat_map(|&(_, lists)| lists) {
             for (_name, template) in class_list {
                 for method_list in [template.class_methods, template.instance_methods] {
                     for &(name, _imp) in method_list {
@@ -5,18 +5,22 @@
  */
 //! Parsing and management of user-configurable options, e.g. for input methods.
 
+use crate::device::{DeviceProfile, DEVICE_PROFILES};
 use crate::gles::GLESImplementation;
+use crate::paths;
+use crate::trace;
 use crate::window::DeviceOrientation;
 use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Read};
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::num::NonZeroU32;
+use std::path::PathBuf;
 
 pub const OPTIONS_HELP: &str =
     include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/OPTIONS_HELP.txt"));
 
 /// Game controller button for `--button-to-touch=` option.
-#[derive(Copy, Clone, Hash, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum Button {
     DPadLeft,
     DPadUp,
@@ -46,9 +50,23 @@ pub struct Options {
     pub direct_memory_access: bool,
     pub gdb_listen_addrs: Option<Vec<SocketAddr>>,
     pub preferred_languages: Option<Vec<String>>,
+    pub device: &'static DeviceProfile,
     pub headless: bool,
     pub print_fps: bool,
     pub fps_limit: Option<f64>,
+    pub screenshot_dir: Option<PathBuf>,
+    pub capture_frames: Vec<u64>,
+    pub record: Option<PathBuf>,
+    pub trace: Option<Vec<trace::Filter>>,
+    pub trace_file: Option<PathBuf>,
+    pub debug_heap: bool,
+    pub load_state: Option<PathBuf>,
+    pub save_state_file: Option<PathBuf>,
+    pub save_state_after: Option<String>,
+    pub record_input: Option<PathBuf>,
+    pub replay_input: Option<PathBuf>,
+    pub redirect_hosts: HashMap<String, String>,
+    pub offline_hosts: HashMap<String, PathBuf>,
 }
 
 impl Default for Options {
@@ -68,9 +86,23 @@ impl Default for Options {
             direct_memory_access: true,
             gdb_listen_addrs: None,
             preferred_languages: None,
+            device: &DEVICE_PROFILES[0],
             headless: false,
             print_fps: false,
             fps_limit: Some(60.0), // Original iPhone is 60Hz and uses v-sync
+            screenshot_dir: None,
+            capture_frames: Vec::new(),
+            record: None,
+            trace: None,
+            trace_file: None,
+            debug_heap: false,
+            load_state: None,
+            save_state_file: None,
+            save_state_after: None,
+            record_input: None,
+            replay_input: None,
+            redirect_hosts: HashMap::new(),
+            offline_hosts: HashMap::new(),
         }
     }
 }
@@ -171,6 +203,9 @@ impl Options {
             self.gdb_listen_addrs = Some(addrs);
         } else if let Some(value) = arg.strip_prefix("--preferred-languages=") {
             self.preferred_languages = Some(value.split(',').map(ToOwned::to_owned).collect());
+        } else if let Some(value) = arg.strip_prefix("--device=") {
+            self.device = DeviceProfile::from_short_name(value)
+                .map_err(|_| "Unrecognized --device= value".to_string())?;
         } else if arg == "--headless" {
             self.headless = true;
         } else if arg == "--print-fps" {
@@ -186,6 +221,55 @@ impl Options {
                     .ok_or_else(|| "Invalid value for --fps-limit=".to_string())?;
                 self.fps_limit = Some(limit);
             }
+        } else if let Some(value) = arg.strip_prefix("--screenshot-dir=") {
+            self.screenshot_dir = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--capture-frames=") {
+            self.capture_frames = value
+                .split(',')
+                .map(|frame| frame.parse().ok().filter(|&frame| frame >= 1))
+                .collect::<Option<_>>()
+                .ok_or_else(|| "Invalid frame number for --capture-frames=".to_string())?;
+        } else if let Some(value) = arg.strip_prefix("--record=") {
+            self.record = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--trace=") {
+            self.trace = Some(
+                value
+                    .split(',')
+                    .map(trace::Filter::parse)
+                    .collect::<Result<_, _>>()?,
+            );
+        } else if let Some(value) = arg.strip_prefix("--trace-file=") {
+            self.trace_file = Some(PathBuf::from(value));
+        } else if arg == "--debug-heap" {
+            self.debug_heap = true;
+        } else if let Some(value) = arg.strip_prefix("--load-state=") {
+            self.load_state = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--save-state-file=") {
+            self.save_state_file = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--save-state-after=") {
+            self.save_state_after = Some(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("--record-input=") {
+            self.record_input = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--replay-input=") {
+            self.replay_input = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--redirect-host=") {
+            let (host, target) = value
+                .split_once('=')
+                .filter(|(host, target)| !host.is_empty() && !target.is_empty())
+                .ok_or_else(|| "--redirect-host= requires a host and a target".to_string())?;
+            self.redirect_hosts
+                .insert(host.to_ascii_lowercase(), target.to_string());
+        } else if let Some(value) = arg.strip_prefix("--offline-host=") {
+            let (host, path) = value
+                .split_once('=')
+                .filter(|(host, path)| !host.is_empty() && !path.is_empty())
+                .ok_or_else(|| "--offline-host= requires a host and a path".to_string())?;
+            // Relative paths are relative to the directory with the options
+            // file, which is not the current directory on Android.
+            self.offline_hosts.insert(
+                host.to_ascii_lowercase(),
+                paths::user_data_base_path().join(path),
+            );
         } else {
             return Ok(false);
         };
@@ -15,8 +15,8 @@
 //!   [USER_OPTIONS_FILE]. These are ordinary files and are found in
 //!   [user_data_base_path].
 //! * Files that touchHLE will create and modify, and the user may modify if
-//!   they want to: [SANDBOX_DIR]. These are ordinary files and are found in
-//!   [user_data_base_path].
+//!   they want to: [SANDBOX_DIR], [SCREENSHOTS_DIR], [SAVE_STATES_DIR]. These
+//!   are ordinary files and are found in [user_data_base_path].
 //!
 //! See also [crate::fs], which provides a virtual filesystem for the guest app
 //! and defines path types.
@@ -80,6 +80,12 @@ pub const USER_OPTIONS_FILE: &str = "touchHLE_options.txt";
 /// the `Documents` directory.
 pub const SANDBOX_DIR: &str = "touchHLE_sandbox";
 
+/// Name of the default directory where touchHLE will save screenshots.
+pub const SCREENSHOTS_DIR: &str = "touchHLE_screenshots";
+
+/// Name of the default directory where touchHLE will save save states.
+pub const SAVE_STATES_DIR: &str = "touchHLE_save_states";
+
 /// Get a platform-specific base path needed for accessing touchHLE's
 /// user-modifiable files. This is empty on platforms other than Android.
 pub fn user_data_base_path() -> &'static Path {
@@ -0,0 +1,606 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Deterministic recording and replaying of runs (see `--record-input=` and
+//! `--replay-input=`).
+//!
+//! Emulation is deterministic apart from a few inputs from the outside world:
+//! events from the window, the accelerometer, the time and `arc4random()`.
+//! While recording, each of these is written to a file at the moment it is
+//! observed. While replaying, they are read back from the file instead, so the
+//! app gets exactly the same inputs in the same order, and behaves the same
+//! way. Host code that needs one of these inputs must get it through this
+//! module if the result can affect the app, e.g. by using [now] rather than
+//! [Instant::now].
+//!
+//! Which thread is scheduled is also recorded. This should be deterministic
+//! if everything else is, so while replaying it's only checked, so that a
+//! divergence from the recording is noticed soon after it happens. Divergence
+//! is possible because some things aren't recorded, e.g. the progress of audio
+//! playback, and it causes a panic, since continuing would be meaningless.
+//!
+//! The file is a header followed by a sequence of records, each a tag byte
+//! followed by the record's data. Integers are little-endian, except for
+//! variable-length integers, which are LEB128.
+
+use crate::options::{Button, Options};
+use crate::window::{Coords, Event, FingerId, TextInputEvent};
+use crate::{Environment, ThreadId};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::time::{Duration, Instant, SystemTime};
+
+const MAGIC: &[u8] = b"touchHLE input recording\0";
+const FORMAT_VERSION: u32 = 1;
+
+const TAG_TIME: u8 = 0;
+const TAG_EVENT: u8 = 1;
+const TAG_NO_EVENT: u8 = 2;
+const TAG_ACCELERATION: u8 = 3;
+const TAG_RANDOM: u8 = 4;
+const TAG_THREAD: u8 = 5;
+
+/// Order of the buttons in the file format.
+const BUTTONS: [Button; 10] = [
+    Button::DPadLeft,
+    Button::DPadUp,
+    Button::DPadRight,
+    Button::DPadDown,
+    Button::Start,
+    Button::A,
+    Button::B,
+    Button::X,
+    Button::Y,
+    Button::LeftShoulder,
+];
+
+#[derive(Debug)]
+enum Record {
+    /// Time elapsed since the previous time record (or since startup).
+    Time(Duration),
+    /// Result of popping an event from the window's queue.
+    Event(Option<Event>),
+    Acceleration((f32, f32, f32)),
+    Random(u32),
+    Thread(ThreadId),
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, string: &str) {
+    write_varint(out, string.len() as u64);
+    out.extend_from_slice(string.as_bytes());
+}
+
+fn write_touches(out: &mut Vec<u8>, touches: &HashMap<FingerId, Coords>) {
+    write_varint(out, touches.len() as u64);
+    for (&finger_id, &(x, y)) in touches {
+        match finger_id {
+            FingerId::Mouse => out.push(0),
+            FingerId::Touch(id) => {
+                out.push(1);
+                out.extend_from_slice(&id.to_le_bytes());
+            }
+            FingerId::VirtualCursor => out.push(2),
+            FingerId::ButtonToTouch(button) => {
+                out.push(3);
+                out.push(BUTTONS.iter().position(|&b| b == button).unwrap() as u8);
+            }
+        }
+        out.extend_from_slice(&x.to_le_bytes());
+        out.extend_from_slice(&y.to_le_bytes());
+    }
+}
+
+fn write_event(out: &mut Vec<u8>, event: &Event) {
+    match event {
+        Event::Quit => out.push(0),
+        Event::AppWillResignActive => out.push(1),
+        Event::AppWillTerminate => out.push(2),
+        Event::TouchesDown(touches) => {
+            out.push(3);
+            write_touches(out, touches);
+        }
+        Event::TouchesMove(touches) => {
+            out.push(4);
+            write_touches(out, touches);
+        }
+        Event::TouchesUp(touches) => {
+            out.push(5);
+            write_touches(out, touches);
+        }
+        Event::EnterDebugger => out.push(6),
+        Event::TextInput(TextInputEvent::Text(text)) => {
+            out.push(7);
+            write_string(out, text);
+        }
+        Event::TextInput(TextInputEvent::Backspace) => out.push(8),
+        Event::TextInput(TextInputEvent::Return) => out.push(9),
+    }
+}
+
+fn write_record(out: &mut Vec<u8>, record: &Record) {
+    match *record {
+        Record::Time(delta) => {
+            out.push(TAG_TIME);
+            write_varint(out, delta.as_nanos().try_into().unwrap());
+        }
+        Record::Event(Some(ref event)) => {
+            out.push(TAG_EVENT);
+            write_event(out, event);
+        }
+        Record::Event(None) => out.push(TAG_NO_EVENT),
+        Record::Acceleration((x, y, z)) => {
+            out.push(TAG_ACCELERATION);
+            for value in [x, y, z] {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        Record::Random(value) => {
+            out.push(TAG_RANDOM);
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        Record::Thread(thread) => {
+            out.push(TAG_THREAD);
+            write_varint(out, thread as u64);
+        }
+    }
+}
+
+fn invalid_data(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid {}", what))
+}
+
+fn read_bytes<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u8(input: &mut impl Read) -> io::Result<u8> {
+    read_bytes::<1>(input).map(|[byte]| byte)
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    read_bytes(input).map(u32::from_le_bytes)
+}
+
+fn read_f32(input: &mut impl Read) -> io::Result<f32> {
+    read_bytes(input).map(f32::from_le_bytes)
+}
+
+fn read_varint(input: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_u8(input)?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("variable-length integer"))
+}
+
+fn read_string(input: &mut impl Read) -> io::Result<String> {
+    let len: usize = read_varint(input)?
+        .try_into()
+        .map_err(|_| invalid_data("string length"))?;
+    let mut bytes = vec![0u8; len];
+    input.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| invalid_data("string"))
+}
+
+fn read_touches(input: &mut impl Read) -> io::Result<HashMap<FingerId, Coords>> {
+    let count = read_varint(input)?;
+    let mut touches = HashMap::new();
+    for _ in 0..count {
+        let finger_id = match read_u8(input)? {
+            0 => FingerId::Mouse,
+            1 => FingerId::Touch(i64::from_le_bytes(read_bytes(input)?)),
+            2 => FingerId::VirtualCursor,
+            3 => FingerId::ButtonToTouch(
+                *BUTTONS
+                    .get(usize::from(read_u8(input)?))
+                    .ok_or_else(|| invalid_data("button"))?,
+            ),
+            _ => return Err(invalid_data("finger ID")),
+        };
+        let coords = (read_f32(input)?, read_f32(input)?);
+        touches.insert(finger_id, coords);
+    }
+    Ok(touches)
+}
+
+fn read_event(input: &mut impl Read) -> io::Result<Event> {
+    Ok(match read_u8(input)? {
+        0 => Event::Quit,
+        1 => Event::AppWillResignActive,
+        2 => Event::AppWillTerminate,
+        3 => Event::TouchesDown(read_touches(input)?),
+        4 => Event::TouchesMove(read_touches(input)?),
+        5 => Event::TouchesUp(read_touches(input)?),
+        6 => Event::EnterDebugger,
+        7 => Event::TextInput(TextInputEvent::Text(read_string(input)?)),
+        8 => Event::TextInput(TextInputEvent::Backspace),
+        9 => Event::TextInput(TextInputEvent::Return),
+        _ => return Err(invalid_data("event")),
+    })
+}
+
+/// Read the next record, or return [None] at the end of the file.
+fn read_record(input: &mut impl Read) -> io::Result<Option<Record>> {
+    let mut tag = [0u8];
+    if input.read(&mut tag)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(match tag[0] {
+        TAG_TIME => Record::Time(Duration::from_nanos(read_varint(input)?)),
+        TAG_EVENT => Record::Event(Some(read_event(input)?)),
+        TAG_NO_EVENT => Record::Event(None),
+        TAG_ACCELERATION => {
+            Record::Acceleration((read_f32(input)?, read_f32(input)?, read_f32(input)?))
+        }
+        TAG_RANDOM => Record::Random(read_u32(input)?),
+        TAG_THREAD => Record::Thread(
+            read_varint(input)?
+                .try_into()
+                .map_err(|_| invalid_data("thread ID"))?,
+        ),
+        _ => return Err(invalid_data("record tag")),
+    }))
+}
+
+enum Mode {
+    Recording(BufWriter<File>),
+    Replaying(BufReader<File>),
+    /// Recording failed, or the end of the replay was reached and live input
+    /// is being used instead.
+    Live,
+}
+
+pub struct Replay {
+    mode: Mode,
+    /// The time of the startup according to the system clock, so that
+    /// [system_time] can be derived from [now].
+    system_time_base: SystemTime,
+    /// Time elapsed since startup as of the last call to [now].
+    elapsed: Duration,
+    /// Added to the time elapsed since startup once the end of a replay is
+    /// reached, so that time doesn't go backwards. Replays run faster than
+    /// real time, since there's no need to wait for anything.
+    live_offset: Duration,
+    /// Number of records read or written, for error messages.
+    record_count: u64,
+}
+impl Replay {
+    /// Start recording or replaying if the options ask for it.
+    pub fn new(
+        options: &Options,
+        bundle_id: &str,
+        startup_time: Instant,
+    ) -> Result<Option<Replay>, String> {
+        let (mode, system_time_base) = match (&options.record_input, &options.replay_input) {
+            (None, None) => return Ok(None),
+            (Some(_), Some(_)) => {
+                return Err("--record-input= and --replay-input= can't be used together".to_string())
+            }
+            (Some(path), None) => {
+                let system_time_base = SystemTime::now() - startup_time.elapsed();
+                let mut header = MAGIC.to_vec();
+                header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+                write_string(&mut header, crate::VERSION);
+                write_string(&mut header, bundle_id);
+                let base_nanos = system_time_base
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_nanos();
+                write_varint(&mut header, base_nanos.try_into().unwrap());
+
+                let mut file = File::create(path)
+                    .map(BufWriter::new)
+                    .map_err(|e| format!("Couldn't create {}: {}", path.display(), e))?;
+                file.write_all(&header)
+                    .map_err(|e| format!("Couldn't write to {}: {}", path.display(), e))?;
+                echo!("Recording input to {}.", path.display());
+                (Mode::Recording(file), system_time_base)
+            }
+            (None, Some(path)) => {
+                let mut file = File::open(path)
+                    .map(BufReader::new)
+                    .map_err(|e| format!("Couldn't open {}: {}", path.display(), e))?;
+                let read_header = |file: &mut BufReader<File>| {
+                    let mut magic = vec![0u8; MAGIC.len()];
+                    file.read_exact(&mut magic)?;
+                    if magic != MAGIC || read_u32(file)? != FORMAT_VERSION {
+                        return Err(invalid_data("header"));
+                    }
+                    let version = read_string(file)?;
+                    let recorded_bundle_id = read_string(file)?;
+                    let base_nanos = read_varint(file)?;
+                    Ok((version, recorded_bundle_id, base_nanos))
+                };
+                let (version, recorded_bundle_id, base_nanos) = read_header(&mut file)
+                    .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+                if recorded_bundle_id != bundle_id {
+                    return Err(format!(
+                        "{} is a recording of {}, not {}",
+                        path.display(),
+                        recorded_bundle_id,
+                        bundle_id
+                    ));
+                }
+                if version != crate::VERSION {
+                    log!(
+                        "Warning: {} was recorded with touchHLE {}, which may not replay the same way in this version ({}).",
+                        path.display(),
+                        version,
+                        crate::VERSION
+                    );
+                }
+                echo!("Replaying input from {}.", path.display());
+                (
+                    Mode::Replaying(file),
+                    SystemTime::UNIX_EPOCH + Duration::from_nanos(base_nanos),
+                )
+            }
+        };
+        Ok(Some(Replay {
+            mode,
+            system_time_base,
+            elapsed: Duration::ZERO,
+            live_offset: Duration::ZERO,
+            record_count: 0,
+        }))
+    }
+
+    fn write(&mut self, record: &Record) {
+        let Mode::Recording(ref mut file) = self.mode else {
+            return;
+        };
+        let mut bytes = Vec::new();
+        write_record(&mut bytes, record);
+        if let Err(e) = file.write_all(&bytes) {
+            log!(
+                "Warning: Couldn't write to input recording, stopping recording: {}",
+                e
+            );
+            self.mode = Mode::Live;
+            return;
+        }
+        self.record_count += 1;
+    }
+}
+
+/// Check whether touchHLE is replaying a recording (and hasn't reached the end
+/// of it yet).
+pub fn is_replaying(env: &Environment) -> bool {
+    matches!(
+        env.replay,
+        Some(Replay {
+            mode: Mode::Replaying(_),
+            ..
+        })
+    )
+}
+
+/// Read the next record while replaying. Returns [None] if touchHLE isn't
+/// replaying, including if the end of the replay was just reached.
+fn read(env: &mut Environment) -> Option<Record> {
+    let replay = env.replay.as_mut()?;
+    let Mode::Replaying(ref mut file) = replay.mode else {
+        return None;
+    };
+    match read_record(file) {
+        Ok(Some(record)) => {
+            replay.record_count += 1;
+            return Some(record);
+        }
+        Ok(None) => (),
+        Err(e) => panic!(
+            "Couldn't read record {} of replay: {}",
+            replay.record_count + 1,
+            e
+        ),
+    }
+
+    let record_count = replay.record_count;
+    if env.options.headless {
+        echo!(
+            "Reached the end of the replay after {} records, exiting.",
+            record_count
+        );
+        env.report_heap_leaks();
+        std::process::exit(0);
+    }
+    echo!(
+        "Reached the end of the replay after {} records, continuing with live input.",
+        record_count
+    );
+    let replay = env.replay.as_mut().unwrap();
+    replay.live_offset = replay.elapsed.saturating_sub(env.startup_time.elapsed());
+    replay.mode = Mode::Live;
+    None
+}
+
+/// Panic because the replay has diverged from the recording.
+fn diverged(env: &Environment, expected: &str, record: &Record) -> ! {
+    panic!(
+        "Replay diverged from the recording at record {}: expected {}, but found {:?}",
+        env.replay.as_ref().unwrap().record_count,
+        expected,
+        record
+    );
+}
+
+/// Get the current time. This should be used instead of [Instant::now]
+/// whenever the result could affect the app.
+pub fn now(env: &mut Environment) -> Instant {
+    if env.replay.is_none() {
+        return Instant::now();
+    }
+    let elapsed = match read(env) {
+        Some(Record::Time(delta)) => env.replay.as_ref().unwrap().elapsed + delta,
+        Some(record) => diverged(env, "a time", &record),
+        None => {
+            let replay = env.replay.as_mut().unwrap();
+            let elapsed = (env.startup_time.elapsed() + replay.live_offset).max(replay.elapsed);
+            let delta = elapsed - replay.elapsed;
+            replay.write(&Record::Time(delta));
+            elapsed
+        }
+    };
+    env.replay.as_mut().unwrap().elapsed = elapsed;
+    env.startup_time + elapsed
+}
+
+/// Get the current time according to the system clock. This should be used
+/// instead of [SystemTime::now] whenever the result could affect the app.
+///
+/// When recording or replaying, this is derived from [now], so adjustments to
+/// the system clock while touchHLE is running aren't seen by the app.
+pub fn system_time(env: &mut Environment) -> SystemTime {
+    let Some(ref replay) = env.replay else {
+        return SystemTime::now();
+    };
+    let system_time_base = replay.system_time_base;
+    system_time_base + now(env).duration_since(env.startup_time)
+}
+
+/// Get the next event from the window, like [crate::window::Window::pop_event].
+pub fn pop_event(env: &mut Environment) -> Option<Event> {
+    if is_replaying(env) {
+        // Live input is ignored while replaying, except for requests to quit or
+        // to enter the debugger, which can't affect the replay.
+        while let Some(event) = env.window_mut().pop_event() {
+            if matches!(event, Event::Quit | Event::EnterDebugger) {
+                return Some(event);
+            }
+        }
+        match read(env) {
+            Some(Record::Event(event)) => return event,
+            Some(record) => diverged(env, "an event", &record),
+            None => (),
+        }
+    }
+    let event = env.window_mut().pop_event();
+    // Entering the debugger isn't recorded, since it doesn't affect the app
+    // and would be unwelcome during a replay.
+    if matches!(event, Some(Event::EnterDebugger)) {
+        return event;
+    }
+    let record = Record::Event(event);
+    if let Some(ref mut replay) = env.replay {
+        replay.write(&record);
+    }
+    let Record::Event(event) = record else {
+        unreachable!();
+    };
+    event
+}
+
+/// Get the accelerometer's current reading, like
+/// [crate::window::Window::get_acceleration].
+pub fn acceleration(env: &mut Environment) -> (f32, f32, f32) {
+    match read(env) {
+        Some(Record::Acceleration(acceleration)) => return acceleration,
+        Some(record) => diverged(env, "an accelerometer reading", &record),
+        None => (),
+    }
+    let acceleration = env.window().get_acceleration(&env.options);
+    if let Some(ref mut replay) = env.replay {
+        replay.write(&Record::Acceleration(acceleration));
+    }
+    acceleration
+}
+
+/// Record a random number the app will get, or replace it with the recorded
+/// one.
+pub fn random(env: &mut Environment, value: u32) -> u32 {
+    match read(env) {
+        Some(Record::Random(value)) => return value,
+        Some(record) => diverged(env, "a random number", &record),
+        None => (),
+    }
+    if let Some(ref mut replay) = env.replay {
+        replay.write(&Record::Random(value));
+    }
+    value
+}
+
+/// Record that a thread has been chosen to run next, or check that this
+/// matches the recording.
+pub fn schedule_thread(env: &mut Environment, thread: ThreadId) {
+    match read(env) {
+        Some(Record::Thread(recorded)) if recorded == thread => return,
+        Some(record) => diverged(env, &format!("thread {} to be scheduled", thread), &record),
+        None => (),
+    }
+    if let Some(ref mut replay) = env.replay {
+        replay.write(&Record::Thread(thread));
+    }
+}
+
+/// Make sure everything recorded so far has been written to the file. This
+/// must be done before touchHLE exits or crashes.
+pub fn flush(env: &mut Environment) {
+    let Some(Replay {
+        mode: Mode::Recording(ref mut file),
+        ..
+    }) = env.replay
+    else {
+        return;
+    };
+    if let Err(e) = file.flush() {
+        log!("Warning: Couldn't write to input recording: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod replay_tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let records = [
+            Record::Time(Duration::from_nanos(16_666_667)),
+            Record::Event(Some(Event::TouchesDown(HashMap::from([(
+                FingerId::Touch(-1),
+                (1.5, 2.0),
+            )])))),
+            Record::Event(Some(Event::TouchesUp(HashMap::from([(
+                FingerId::ButtonToTouch(Button::LeftShoulder),
+                (470.0, 310.0),
+            )])))),
+            Record::Event(Some(Event::TextInput(TextInputEvent::Text(
+                "héllo".to_string(),
+            )))),
+            Record::Event(None),
+            Record::Acceleration((0.0, -1.0, 0.25)),
+            Record::Random(0xdeadbeef),
+            Record::Thread(300),
+        ];
+        let mut bytes = Vec::new();
+        for record in &records {
+            write_record(&mut bytes, record);
+        }
+        let mut input = &bytes[..];
+        for record in &records {
+            let read = read_record(&mut input).unwrap().unwrap();
+            assert_eq!(format!("{:?}", read), format!("{:?}", record));
+        }
+        assert!(read_record(&mut input).unwrap().is_none());
+    }
+}
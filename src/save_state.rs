@@ -0,0 +1,436 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Save states: snapshots of the whole emulated environment that can be written
+//! to a file and loaded later (see `--load-state=` and the F5 and F9 hotkeys).
+//!
+//! The file format is a simple binary one: a header identifying touchHLE and
+//! the app, followed by the values written with [StateWriter], in the order
+//! [crate::Environment::save_state] writes them. There's no schema, so a save
+//! state can only be loaded by the same version of touchHLE that wrote it.
+//!
+//! Anything that isn't plain data has to be re-created when loading. Host
+//! objects and framework state that touchHLE can't save yet are reported with
+//! [StateWriter::unsupported], and saving fails rather than producing a save
+//! state that would be broken when loaded. See `dev-docs/save-states.md` for
+//! what is and isn't supported.
+
+use crate::abi::GuestFunction;
+use crate::mem::Ptr;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+const MAGIC: &[u8] = b"touchHLE save state\0";
+
+/// Trait for things that can be written to a save state and read back.
+pub trait SaveState: Sized {
+    fn save(&self, writer: &mut StateWriter);
+    fn load(reader: &mut StateReader) -> Result<Self, String>;
+}
+
+/// Convenience macro for implementing [SaveState] for a struct by saving each
+/// of its fields in order. All the fields must be listed, so that adding a
+/// field without deciding how to save it is a compile error.
+///
+/// ```ignore
+/// struct Foo {
+///     bar: u32,
+///     baz: Vec<id>,
+/// }
+/// impl_SaveState!(Foo { bar, baz });
+/// ```
+#[macro_export]
+macro_rules! impl_SaveState {
+    ( $ty:ident { $($field:ident),* $(,)? } ) => {
+        impl $crate::save_state::SaveState for $ty {
+            fn save(&self, writer: &mut $crate::save_state::StateWriter) {
+                let $ty { $(ref $field),* } = *self;
+                $(writer.write($field);)*
+            }
+            fn load(
+                reader: &mut $crate::save_state::StateReader,
+            ) -> Result<Self, String> {
+                Ok($ty { $($field: reader.read()?),* })
+            }
+        }
+    };
+}
+pub use crate::impl_SaveState; // #[macro_export] is weird...
+
+/// Builds up the contents of a save state.
+#[derive(Default)]
+pub struct StateWriter {
+    bytes: Vec<u8>,
+    /// Descriptions of things that couldn't be saved, see [Self::unsupported].
+    unsupported: Vec<String>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter {
+            bytes: Vec::new(),
+            unsupported: Vec::new(),
+        }
+    }
+
+    pub fn write<T: SaveState>(&mut self, value: &T) {
+        value.save(self)
+    }
+
+    /// Write bytes without a length prefix. The reader must know how many
+    /// bytes to expect, see [StateReader::read_bytes].
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes)
+    }
+
+    /// Record that something can't be saved yet, e.g. `"EAGLContext objects"`.
+    /// Writing can continue, so that everything unsupported can be reported at
+    /// once, but [Self::finish] will fail.
+    pub fn unsupported(&mut self, what: impl Into<String>) {
+        let what = what.into();
+        if !self.unsupported.contains(&what) {
+            self.unsupported.push(what);
+        }
+    }
+
+    /// Write the data from another writer, e.g. one used to find out whether a
+    /// host object can be saved before committing to writing it.
+    pub fn append(&mut self, other: StateWriter) {
+        let StateWriter { bytes, unsupported } = other;
+        self.bytes.extend_from_slice(&bytes);
+        for what in unsupported {
+            self.unsupported(what);
+        }
+    }
+
+    /// Get the complete save state, with the header, or a description of what
+    /// couldn't be saved.
+    pub fn finish(self, app_id: &str) -> Result<Vec<u8>, String> {
+        if !self.unsupported.is_empty() {
+            return Err(format!(
+                "touchHLE can't save these yet: {}",
+                self.unsupported.join(", ")
+            ));
+        }
+        let mut header = StateWriter::new();
+        header.write_bytes(MAGIC);
+        header.write(&crate::VERSION.to_string());
+        header.write(&app_id.to_string());
+        header.write_bytes(&self.bytes);
+        Ok(header.bytes)
+    }
+}
+
+/// Reads back the contents of a save state written by [StateWriter].
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Check the header of a save state and return a reader for its contents.
+    pub fn new(bytes: &'a [u8], app_id: &str) -> Result<StateReader<'a>, String> {
+        let mut reader = StateReader { bytes };
+        if reader.read_bytes(MAGIC.len()).ok() != Some(MAGIC) {
+            return Err("Not a touchHLE save state".to_string());
+        }
+        let version: String = reader.read()?;
+        if version != crate::VERSION {
+            return Err(format!(
+                "Save state is from touchHLE {}, but this is touchHLE {}",
+                version,
+                crate::VERSION
+            ));
+        }
+        let state_app_id: String = reader.read()?;
+        if state_app_id != app_id {
+            return Err(format!(
+                "Save state is for app {:?}, not {:?}",
+                state_app_id, app_id
+            ));
+        }
+        Ok(reader)
+    }
+
+    pub fn read<T: SaveState>(&mut self) -> Result<T, String> {
+        T::load(self)
+    }
+
+    /// Read bytes written with [StateWriter::write_bytes].
+    pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < count {
+            return Err("Save state is truncated".to_string());
+        }
+        let (bytes, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    /// Check that everything has been read.
+    pub fn finish(self) -> Result<(), String> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err("Save state has unexpected data at the end".to_string())
+        }
+    }
+}
+
+macro_rules! impl_SaveState_for_int {
+    ( $($ty:ty),* ) => {
+        $(
+            impl SaveState for $ty {
+                fn save(&self, writer: &mut StateWriter) {
+                    writer.write_bytes(&self.to_le_bytes())
+                }
+                fn load(reader: &mut StateReader) -> Result<Self, String> {
+                    let bytes = reader.read_bytes(std::mem::size_of::<$ty>())?;
+                    Ok(<$ty>::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+impl_SaveState_for_int!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl SaveState for usize {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&u64::try_from(*self).unwrap())
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        let value: u64 = reader.read()?;
+        value
+            .try_into()
+            .map_err(|_| "Save state has an out-of-range size".to_string())
+    }
+}
+
+impl SaveState for bool {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&u8::from(*self))
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        match reader.read::<u8>()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err("Save state has an invalid boolean".to_string()),
+        }
+    }
+}
+
+impl SaveState for NonZeroU32 {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&self.get())
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        NonZeroU32::new(reader.read()?).ok_or_else(|| "Save state has an invalid zero".to_string())
+    }
+}
+
+impl SaveState for Duration {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&self.as_secs());
+        writer.write(&self.subsec_nanos());
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        Ok(Duration::new(reader.read()?, reader.read()?))
+    }
+}
+
+impl SaveState for String {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&self.len());
+        writer.write_bytes(self.as_bytes());
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        let len = reader.read()?;
+        let bytes = reader.read_bytes(len)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| "Save state has an invalid string".to_string())
+    }
+}
+
+impl<T: SaveState> SaveState for Option<T> {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&self.is_some());
+        if let Some(value) = self {
+            writer.write(value);
+        }
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        Ok(if reader.read()? {
+            Some(reader.read()?)
+        } else {
+            None
+        })
+    }
+}
+
+impl<T: SaveState> SaveState for Box<T> {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&**self)
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        Ok(Box::new(reader.read()?))
+    }
+}
+
+impl<A: SaveState, B: SaveState> SaveState for (A, B) {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&self.0);
+        writer.write(&self.1);
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        Ok((reader.read()?, reader.read()?))
+    }
+}
+
+impl<A: SaveState, B: SaveState, C: SaveState> SaveState for (A, B, C) {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&self.0);
+        writer.write(&self.1);
+        writer.write(&self.2);
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        Ok((reader.read()?, reader.read()?, reader.read()?))
+    }
+}
+
+impl<T: SaveState, const N: usize> SaveState for [T; N] {
+    fn save(&self, writer: &mut StateWriter) {
+        for item in self {
+            writer.write(item);
+        }
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        let items: Vec<T> = (0..N).map(|_| reader.read()).collect::<Result<_, _>>()?;
+        Ok(items.try_into().ok().unwrap())
+    }
+}
+
+/// Helper for collections: write the length, then the items.
+fn save_items<'a, T: SaveState + 'a>(
+    writer: &mut StateWriter,
+    len: usize,
+    items: impl Iterator<Item = &'a T>,
+) {
+    writer.write(&len);
+    for item in items {
+        writer.write(item);
+    }
+}
+
+/// Helper for collections: read the items written by [save_items].
+fn load_items<T: SaveState, C: FromIterator<T>>(reader: &mut StateReader) -> Result<C, String> {
+    let len: usize = reader.read()?;
+    (0..len).map(|_| reader.read()).collect()
+}
+
+impl<T: SaveState> SaveState for Vec<T> {
+    fn save(&self, writer: &mut StateWriter) {
+        save_items(writer, self.len(), self.iter())
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        load_items(reader)
+    }
+}
+
+impl<T: SaveState> SaveState for VecDeque<T> {
+    fn save(&self, writer: &mut StateWriter) {
+        save_items(writer, self.len(), self.iter())
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        load_items(reader)
+    }
+}
+
+impl<T: SaveState + Eq + Hash> SaveState for HashSet<T> {
+    fn save(&self, writer: &mut StateWriter) {
+        save_items(writer, self.len(), self.iter())
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        load_items(reader)
+    }
+}
+
+impl<K: SaveState + Eq + Hash, V: SaveState> SaveState for HashMap<K, V> {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&self.len());
+        for (key, value) in self {
+            writer.write(key);
+            writer.write(value);
+        }
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        load_items::<(K, V), _>(reader)
+    }
+}
+
+impl<T, const MUT: bool> SaveState for Ptr<T, MUT> {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&self.to_bits())
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        Ok(Ptr::from_bits(reader.read()?))
+    }
+}
+
+impl SaveState for GuestFunction {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write(&self.addr_with_thumb_bit())
+    }
+    fn load(reader: &mut StateReader) -> Result<Self, String> {
+        Ok(GuestFunction::from_addr_with_thumb_bit(reader.read()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), vec![Some(1u32), None]);
+        map.insert("b".to_string(), Vec::new());
+        let value = (
+            map,
+            (-2i32, 0.5f64, true),
+            Duration::from_nanos(1_000_000_123),
+        );
+
+        let mut writer = StateWriter::new();
+        writer.write(&value);
+        let bytes = writer.finish("com.example.app").unwrap();
+
+        let mut reader = StateReader::new(&bytes, "com.example.app").unwrap();
+        assert!(reader.read::<(_, _, _)>().unwrap() == value);
+        reader.finish().unwrap();
+    }
+
+    #[test]
+    fn rejects_other_app_and_truncation() {
+        let mut writer = StateWriter::new();
+        writer.write(&123u32);
+        let bytes = writer.finish("com.example.app").unwrap();
+
+        assert!(StateReader::new(&bytes, "com.example.other").is_err());
+        let mut reader = StateReader::new(&bytes[..bytes.len() - 1], "com.example.app").unwrap();
+        assert!(reader.read::<u32>().is_err());
+    }
+
+    #[test]
+    fn unsupported() {
+        let mut writer = StateWriter::new();
+        writer.unsupported("Foo objects");
+        writer.unsupported("Bar objects");
+        writer.unsupported("Foo objects");
+        assert_eq!(
+            writer.finish("com.example.app"),
+            Err("touchHLE can't save these yet: Foo objects, Bar objects".to_string())
+        );
+    }
+}
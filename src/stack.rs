@@ -5,8 +5,10 @@
  */
 //! Utilities related to the stack.
 
+use crate::abi::{GuestFunction, FRAME_POINTER};
 use crate::cpu::Cpu;
-use crate::mem::{GuestUSize, Mem, MutPtr, Ptr};
+use crate::mem::{ConstPtr, GuestUSize, Mem, MutPtr, Ptr};
+use std::ops::RangeInclusive;
 
 /// Set up the stack for the main thread, ready to execute the entry-point
 /// of the application (aka `start`).
@@ -99,3 +101,71 @@ pub fn prep_stack_for_start(
 
     cpu.regs_mut()[Cpu::SP] = stack_ptr.to_bits();
 }
+
+/// Frame in a stack trace produced by [unwind].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// Address in guest code, with the Thumb bit set if it's Thumb code. For
+    /// every frame but the first, this is a return address.
+    Guest(u32),
+    /// Guest code was called by a host function here.
+    HostFunction,
+    /// The thread's start routine returns here.
+    ThreadExit,
+}
+
+/// Produce a stack trace for a thread, given its PC, registers and the range
+/// of its stack. The first frame is the PC and the second is the LR.
+///
+/// Apple's ABI uses r7 as the frame pointer in both Arm and Thumb code, and it
+/// points to a frame record containing the caller's r7 followed by the return
+/// address, so the trace can be produced by following the chain of records.
+/// Functions that don't create a frame record (e.g. some leaf functions) are
+/// only seen if the LR points into them.
+pub fn unwind(
+    mem: &Mem,
+    pc: GuestFunction,
+    regs: &[u32; 16],
+    stack_range: RangeInclusive<u32>,
+    return_to_host_routine: GuestFunction,
+    thread_exit_routine: GuestFunction,
+) -> Vec<Frame> {
+    let classify = |addr| {
+        if addr == return_to_host_routine.addr_with_thumb_bit() {
+            Frame::HostFunction
+        } else if addr == thread_exit_routine.addr_with_thumb_bit() {
+            Frame::ThreadExit
+        } else {
+            Frame::Guest(addr)
+        }
+    };
+
+    let mut frames = vec![
+        Frame::Guest(pc.addr_with_thumb_bit()),
+        classify(regs[Cpu::LR]),
+    ];
+    let mut fp = regs[FRAME_POINTER];
+    // If the current function has already pushed its frame record, that
+    // record's return address is the LR, which shouldn't be listed twice.
+    let mut skip_lr = true;
+    while frames.last() != Some(&Frame::ThreadExit) {
+        // Frame records are word-aligned and must be within the stack. The
+        // stack grows downwards, so each record must also be above the last
+        // one, which guarantees this terminates even if the stack is corrupt.
+        if fp % 4 != 0 || !stack_range.contains(&fp) || !stack_range.contains(&fp.wrapping_add(7)) {
+            break;
+        }
+        let next_fp: u32 = mem.read(ConstPtr::from_bits(fp));
+        let lr: u32 = mem.read(ConstPtr::from_bits(fp + 4));
+        let frame = classify(lr);
+        if !(skip_lr && lr == regs[Cpu::LR]) {
+            frames.push(frame);
+        }
+        skip_lr = false;
+        if next_fp <= fp {
+            break;
+        }
+        fp = next_fp;
+    }
+    frames
+}
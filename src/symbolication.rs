@@ -0,0 +1,154 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Symbolication of guest code addresses, for stack traces.
+//!
+//! Names come from three places:
+//! - The symbol tables of the loaded binaries (see [crate::mach_o]). Apps are
+//!   usually stripped, so this is mostly useful for the bundled dylibs.
+//! - The Objective-C runtime's method lists, which name the app's methods even
+//!   in stripped binaries.
+//! - The dynamic linker's stubs for host functions (see [crate::dyld]).
+
+use crate::abi::GuestFunction;
+use crate::Environment;
+
+struct Symbol {
+    addr: u32,
+    /// End of the range of addresses this symbol could cover, i.e. the end of
+    /// the section it's in. The next symbol takes precedence if it's earlier.
+    end: u32,
+    name: String,
+    /// Name of the binary containing the symbol.
+    location: String,
+}
+
+pub struct Symbolicator {
+    /// Sorted by address, with no duplicate addresses.
+    symbols: Vec<Symbol>,
+}
+impl Symbolicator {
+    pub fn new(env: &Environment) -> Symbolicator {
+        let mut symbols = Vec::new();
+
+        let guest_symbols = env
+            .bins
+            .iter()
+            .flat_map(|bin| bin.symbols_by_addr.iter().cloned())
+            .chain(env.objc.guest_method_symbols(&env.mem));
+        for (addr, name) in guest_symbols {
+            let section = env.bins.iter().find_map(|bin| {
+                bin.sections
+                    .iter()
+                    .find(|section| (section.addr..section.addr + section.size).contains(&addr))
+                    .map(|section| (bin, section))
+            });
+            let Some((bin, section)) = section else {
+                continue;
+            };
+            symbols.push(Symbol {
+                addr,
+                end: section.addr + section.size,
+                name,
+                location: bin.name.clone(),
+            });
+        }
+
+        for (addr, name) in env.dyld.host_function_symbols(&env.bins) {
+            symbols.push(Symbol {
+                addr,
+                // Stubs are at most 16 bytes, and the routines created by
+                // Dyld::create_proc_address are 8 bytes.
+                end: addr + 16,
+                name: name.to_string(),
+                location: "touchHLE".to_string(),
+            });
+        }
+
+        // The sort is stable, so if there are several names for an address,
+        // the one from the binary's symbol table wins.
+        symbols.sort_by_key(|symbol| symbol.addr);
+        symbols.dedup_by_key(|symbol| symbol.addr);
+        Symbolicator { symbols }
+    }
+
+    /// Describe a code address (without the Thumb bit), e.g.
+    /// `-[Foo bar] + 0x12 (in Foo)`. Returns [None] if no symbol covers it.
+    ///
+    /// Return addresses point to the instruction after the call, which could
+    /// be the start of a different function, so `is_return_address` should be
+    /// set for them.
+    pub fn symbolicate(&self, addr: u32, is_return_address: bool) -> Option<String> {
+        let lookup_addr = if is_return_address {
+            addr.checked_sub(1)?
+        } else {
+            addr
+        };
+        let i = self
+            .symbols
+            .partition_point(|symbol| symbol.addr <= lookup_addr);
+        let symbol = self.symbols[..i].last()?;
+        if lookup_addr >= symbol.end {
+            return None;
+        }
+        // C symbols have an extra leading underscore.
+        let name = symbol.name.strip_prefix('_').unwrap_or(&symbol.name);
+        let offset = addr - symbol.addr;
+        Some(if offset == 0 {
+            format!("{} (in {})", name, symbol.location)
+        } else {
+            format!("{} + {:#x} (in {})", name, offset, symbol.location)
+        })
+    }
+
+    /// Like [Self::symbolicate], but for an address that may have the Thumb
+    /// bit set, and the address itself is always included, e.g.
+    /// `0x1235 -[Foo bar] + 0x4 (in Foo)` or just `0x1235`.
+    pub fn describe(&self, addr_with_thumb_bit: u32, is_return_address: bool) -> String {
+        let addr = addr_with_thumb_bit & !GuestFunction::THUMB_BIT;
+        match self.symbolicate(addr, is_return_address) {
+            Some(symbol) => format!("{:#x} {}", addr_with_thumb_bit, symbol),
+            None => format!("{:#x}", addr_with_thumb_bit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod symbolication_tests {
+    use super::*;
+
+    #[test]
+    fn symbolicate() {
+        let symbol = |addr, end, name: &str| Symbol {
+            addr,
+            end,
+            name: name.to_string(),
+            location: "App".to_string(),
+        };
+        let symbolicator = Symbolicator {
+            symbols: vec![
+                symbol(0x1000, 0x2000, "_main"),
+                symbol(0x1100, 0x2000, "-[Foo bar]"),
+                symbol(0x3000, 0x3010, "_fopen"),
+            ],
+        };
+        assert_eq!(symbolicator.symbolicate(0xfff, false), None);
+        assert_eq!(
+            symbolicator.symbolicate(0x1000, false).as_deref(),
+            Some("main (in App)")
+        );
+        assert_eq!(
+            symbolicator.symbolicate(0x1104, false).as_deref(),
+            Some("-[Foo bar] + 0x4 (in App)")
+        );
+        // A call at the end of main() returns to the start of -[Foo bar].
+        assert_eq!(
+            symbolicator.symbolicate(0x1100, true).as_deref(),
+            Some("main + 0x100 (in App)")
+        );
+        assert_eq!(symbolicator.symbolicate(0x2000, false), None);
+        assert_eq!(symbolicator.symbolicate(0x3010, false), None);
+    }
+}
@@ -0,0 +1,349 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Tracing of calls from the app into touchHLE (see `--trace=`).
+//!
+//! Two kinds of call are traced: calls to host functions, which are dispatched
+//! by [crate::dyld], and Objective-C messages, which are dispatched by
+//! [crate::objc]'s `objc_msgSend`. Host functions and host methods have their
+//! arguments and return values decoded using their Rust types, via
+//! [crate::abi::CallFromGuest]. Guest methods have no such type information, so
+//! only the raw register values are shown for them.
+//!
+//! A call is traced if it matches any of the [Filter]s. The trace is either
+//! logged in a human-readable format, or written to a file (`--trace-file=`) in
+//! a tab-separated format that is easier to process with other tools.
+
+use crate::objc::id;
+use crate::{Environment, ThreadId};
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Which property of a call a [Filter] is matched against.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum FilterKind {
+    /// Match any property.
+    Any,
+    Framework,
+    Function,
+    Class,
+    Selector,
+}
+
+/// Parsed filter from `--trace=`, e.g. `class:NS*`.
+#[derive(Debug)]
+pub struct Filter {
+    kind: FilterKind,
+    glob: String,
+}
+impl Filter {
+    pub fn parse(filter: &str) -> Result<Filter, String> {
+        let (kind, glob) = match filter.split_once(':') {
+            Some(("framework", glob)) => (FilterKind::Framework, glob),
+            Some(("function", glob)) => (FilterKind::Function, glob),
+            Some(("class", glob)) => (FilterKind::Class, glob),
+            Some(("selector", glob)) => (FilterKind::Selector, glob),
+            // Selectors often contain colons, so this isn't an error.
+            _ => (FilterKind::Any, filter),
+        };
+        if glob.is_empty() {
+            return Err(format!("Empty trace filter {:?}", filter));
+        }
+        Ok(Filter {
+            kind,
+            glob: glob.to_string(),
+        })
+    }
+
+    fn matches(&self, call: &Call) -> bool {
+        let properties = [
+            (FilterKind::Framework, call.framework),
+            (FilterKind::Function, call.function),
+            (FilterKind::Class, call.class.as_deref()),
+            (FilterKind::Selector, call.selector.as_deref()),
+        ];
+        properties.into_iter().any(|(kind, property)| {
+            (self.kind == FilterKind::Any || self.kind == kind)
+                && property.is_some_and(|property| glob_matches(&self.glob, property))
+        })
+    }
+}
+
+/// Match a string against a glob pattern, where `*` matches any sequence of
+/// characters and `?` matches any single character.
+fn glob_matches(glob: &str, string: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let string: Vec<char> = string.chars().collect();
+    let (mut g, mut s) = (0, 0);
+    // Position of the last `*` in the glob, and the position in the string it
+    // was matched at, for backtracking.
+    let mut backtrack = None;
+    while s < string.len() {
+        match glob.get(g) {
+            Some('*') => {
+                backtrack = Some((g, s));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == string[s] => {
+                g += 1;
+                s += 1;
+            }
+            _ => {
+                let Some((star_g, star_s)) = backtrack else {
+                    return false;
+                };
+                // Let the `*` match one more character.
+                backtrack = Some((star_g, star_s + 1));
+                g = star_g + 1;
+                s = star_s + 1;
+            }
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// A call that is being traced.
+pub struct Call {
+    /// Library or framework the function or method belongs to. For guest
+    /// methods, this is `app`.
+    framework: Option<&'static str>,
+    /// Symbol name of a host function, without the leading underscore.
+    function: Option<&'static str>,
+    /// Receiver class name and selector, for messages.
+    class: Option<String>,
+    selector: Option<String>,
+    is_class_method: bool,
+    receiver: Option<id>,
+}
+impl Call {
+    /// The name to show in the trace, e.g. `fopen` or `-[NSString length]`.
+    fn name(&self) -> String {
+        if let Some(function) = self.function {
+            return function.to_string();
+        }
+        format!(
+            "{}[{} {}]",
+            if self.is_class_method { '+' } else { '-' },
+            self.class.as_deref().unwrap_or("nil"),
+            self.selector.as_deref().unwrap()
+        )
+    }
+}
+
+/// Raw register value, shown in hexadecimal.
+pub struct RawWord(pub u32);
+impl Debug for RawWord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+pub struct Tracer {
+    filters: Vec<Filter>,
+    /// See `--trace-file=`. If this is absent, the trace is logged instead.
+    file: Option<BufWriter<File>>,
+    /// Call that has been matched but not yet dispatched, see [set_pending].
+    pending: Option<Call>,
+    /// Nesting depth of traced calls for each thread.
+    depths: Vec<usize>,
+}
+impl Tracer {
+    pub fn new(filters: Vec<Filter>, file: Option<&Path>) -> Result<Tracer, String> {
+        let file = file
+            .map(|path| {
+                File::create(path)
+                    .map(BufWriter::new)
+                    .map_err(|e| format!("Couldn't create {}: {}", path.display(), e))
+            })
+            .transpose()?;
+        Ok(Tracer {
+            filters,
+            file,
+            pending: None,
+            depths: Vec::new(),
+        })
+    }
+
+    fn matches(&self, call: &Call) -> bool {
+        self.filters.iter().any(|filter| filter.matches(call))
+    }
+
+    fn depth(&mut self, thread: ThreadId) -> &mut usize {
+        if self.depths.len() <= thread {
+            self.depths.resize(thread + 1, 0);
+        }
+        &mut self.depths[thread]
+    }
+
+    fn write_line(&mut self, line: std::fmt::Arguments) {
+        let Some(file) = &mut self.file else {
+            log!("{}", line);
+            return;
+        };
+        // Flushed every time so nothing is lost if touchHLE crashes, which is
+        // when a trace is most useful.
+        let result = file
+            .write_fmt(line)
+            .and_then(|()| file.write_all(b"\n"))
+            .and_then(|()| file.flush());
+        if let Err(e) = result {
+            log!("Warning: Couldn't write to trace file: {}", e);
+        }
+    }
+}
+
+/// Check whether a call to a host function should be traced, and if so, make
+/// it the pending call. The `symbol` is the mangled name.
+pub fn function(env: &mut Environment, symbol: &'static str) {
+    let Some(tracer) = &mut env.tracer else {
+        return;
+    };
+    // These are traced as messages instead.
+    if symbol.starts_with("_objc_msgSend") {
+        return;
+    }
+    let call = Call {
+        framework: crate::dyld::host_function_framework(symbol),
+        function: Some(symbol.strip_prefix('_').unwrap_or(symbol)),
+        class: None,
+        selector: None,
+        is_class_method: false,
+        receiver: None,
+    };
+    if tracer.matches(&call) {
+        tracer.pending = Some(call);
+    }
+}
+
+/// Check whether an Objective-C message should be traced. `framework` is
+/// [None] if the receiver is `nil`.
+pub fn message(
+    env: &mut Environment,
+    receiver: id,
+    class: Option<String>,
+    is_class_method: bool,
+    selector: String,
+    framework: Option<&'static str>,
+) -> Option<Call> {
+    let tracer = env.tracer.as_ref()?;
+    let call = Call {
+        framework,
+        function: None,
+        class,
+        selector: Some(selector),
+        is_class_method,
+        receiver: Some(receiver),
+    };
+    tracer.matches(&call).then_some(call)
+}
+
+/// Make a call the pending call, i.e. the next one that [begin_call] will log.
+/// This is how [crate::abi::CallFromGuest] finds out it should log a call.
+pub fn set_pending(env: &mut Environment, call: Option<Call>) {
+    if let Some(tracer) = &mut env.tracer {
+        tracer.pending = call;
+    }
+}
+
+/// Log the pending call, if there is one, with its decoded arguments. For
+/// messages, the receiver and selector arguments are skipped. The call is
+/// returned so it can be passed to [end_call].
+pub fn begin_call(env: &mut Environment, args: &[&dyn Debug]) -> Option<Call> {
+    let thread = env.current_thread;
+    let tracer = env.tracer.as_mut()?;
+    let call = tracer.pending.take()?;
+
+    let args = if call.receiver.is_some() && call.function.is_none() {
+        args.get(2..).unwrap_or(&[])
+    } else {
+        args
+    };
+    let depth = *tracer.depth(thread);
+    *tracer.depth(thread) += 1;
+    let name = call.name();
+    if tracer.file.is_some() {
+        let mut line = format!(
+            ">\t{}\t{}\t{}\t{}\t",
+            thread,
+            depth,
+            call.framework.unwrap_or(""),
+            name
+        );
+        if let Some(receiver) = call.receiver {
+            line.push_str(&format!("{:?}", receiver));
+        }
+        for arg in args {
+            line.push_str(&format!("\t{:?}", arg));
+        }
+        tracer.write_line(format_args!("{}", line));
+    } else {
+        let mut line = format!("{:indent$}{}", "", name, indent = depth * 2);
+        if let Some(receiver) = call.receiver {
+            line.push_str(&format!(" on {:?}", receiver));
+        }
+        line.push('(');
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                line.push_str(", ");
+            }
+            line.push_str(&format!("{:?}", arg));
+        }
+        line.push(')');
+        if let Some(framework) = call.framework {
+            line.push_str(&format!(" [{}]", framework));
+        }
+        tracer.write_line(format_args!("Thread {}: {}", thread, line));
+    }
+    Some(call)
+}
+
+/// Log the return value of a call logged by [begin_call].
+pub fn end_call(env: &mut Environment, call: Option<Call>, retval: &dyn Debug) {
+    let Some(call) = call else {
+        return;
+    };
+    let thread = env.current_thread;
+    let tracer = env.tracer.as_mut().unwrap();
+    *tracer.depth(thread) -= 1;
+    let depth = *tracer.depth(thread);
+    // Functions that return nothing have the return type `()`.
+    let retval = format!("{:?}", retval);
+    let retval = if retval == "()" { "" } else { &retval };
+    let name = call.name();
+    if tracer.file.is_some() {
+        tracer.write_line(format_args!(
+            "<\t{}\t{}\t{}\t{}",
+            thread, depth, name, retval
+        ));
+    } else if !retval.is_empty() {
+        tracer.write_line(format_args!(
+            "Thread {}: {:indent$}{} => {}",
+            thread,
+            "",
+            name,
+            retval,
+            indent = depth * 2
+        ));
+    }
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("init*", "initWithFrame:"));
+        assert!(!glob_matches("init*", "dealloc"));
+        assert!(glob_matches("*With*:", "initWithFrame:"));
+        assert!(!glob_matches("*With*:", "initWithFrame"));
+        assert!(glob_matches("NS?tring", "NSString"));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(!glob_matches("a*b*c", "aXbYbZ"));
+    }
+}
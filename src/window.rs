@@ -12,6 +12,8 @@
 //! window system interaction in general, because it is assumed only one window
 //! will be needed for the runtime of the app.
 
+use crate::audio::recording::AudioRecorder;
+use crate::frame_capture::FrameCapture;
 use crate::gles::present::present_frame;
 use crate::gles::{create_gles1_ctx, GLES};
 use crate::image::Image;
@@ -32,12 +34,17 @@ pub enum DeviceOrientation {
     LandscapeLeft,
     LandscapeRight,
 }
-fn size_for_orientation(orientation: DeviceOrientation, scale_hack: NonZeroU32) -> (u32, u32) {
+fn size_for_orientation(
+    orientation: DeviceOrientation,
+    device_screen_size: (u32, u32),
+    scale_hack: NonZeroU32,
+) -> (u32, u32) {
     let scale_hack = scale_hack.get();
+    let (width, height) = device_screen_size;
     match orientation {
-        DeviceOrientation::Portrait => (320 * scale_hack, 480 * scale_hack),
-        DeviceOrientation::LandscapeLeft => (480 * scale_hack, 320 * scale_hack),
-        DeviceOrientation::LandscapeRight => (480 * scale_hack, 320 * scale_hack),
+        DeviceOrientation::Portrait => (width * scale_hack, height * scale_hack),
+        DeviceOrientation::LandscapeLeft => (height * scale_hack, width * scale_hack),
+        DeviceOrientation::LandscapeRight => (height * scale_hack, width * scale_hack),
     }
 }
 fn rotate_fullscreen_size(orientation: DeviceOrientation, screen_size: (u32, u32)) -> (u32, u32) {
@@ -68,7 +75,7 @@ fn set_sdl2_orientation(orientation: DeviceOrientation) {
     );
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub enum FingerId {
     Mouse,
     Touch(i64),
@@ -103,6 +110,16 @@ pub enum Event {
     TextInput(TextInputEvent),
 }
 
+/// Save state operation requested with a hotkey, see
+/// [Window::take_state_request].
+#[derive(Debug, Copy, Clone)]
+pub enum StateRequest {
+    /// User pressed F5.
+    Save,
+    /// User pressed F9.
+    Load,
+}
+
 pub enum GLVersion {
     /// OpenGL ES 1.1
     GLES11,
@@ -151,6 +168,9 @@ pub struct Window {
     /// Copy of `fullscreen` on [Options]. Note that this is meaningless when
     /// [Self::rotatable_fullscreen] returns [true].
     fullscreen: bool,
+    /// Copy of the `screen_size` of the [crate::device::DeviceProfile] on
+    /// [Options].
+    device_screen_size: (u32, u32),
     scale_hack: NonZeroU32,
     internal_gl_ctx: Option<Box<dyn GLES>>,
     splash_image: Option<Image>,
@@ -162,6 +182,8 @@ pub struct Window {
     accelerometer: Option<sdl2::sensor::Sensor>,
     virtual_cursor_last: Option<(f32, f32, bool, bool)>,
     virtual_cursor_last_unsticky: Option<(f32, f32, Instant)>,
+    frame_capture: FrameCapture,
+    state_request: Option<StateRequest>,
 }
 impl Window {
     /// Returns [true] if touchHLE is running on a device where we should always
@@ -176,6 +198,32 @@ impl Window {
         icon: Option<Image>,
         launch_image: Option<Image>,
         options: &Options,
+    ) -> Window {
+        Self::new_inner(
+            title,
+            icon,
+            launch_image,
+            options,
+            /* offscreen: */ false,
+        )
+    }
+
+    /// Create a hidden window, which is only used to provide OpenGL contexts.
+    /// This makes it possible to capture the app's output in headless mode
+    /// (see [crate::frame_capture]).
+    ///
+    /// On a system without a display, it may be necessary to set the
+    /// `SDL_VIDEODRIVER` environment variable to `offscreen` for this to work.
+    pub fn new_offscreen(title: &str, options: &Options) -> Window {
+        Self::new_inner(title, None, None, options, /* offscreen: */ true)
+    }
+
+    fn new_inner(
+        title: &str,
+        icon: Option<Image>,
+        launch_image: Option<Image>,
+        options: &Options,
+        offscreen: bool,
     ) -> Window {
         let sdl_ctx = sdl2::init().unwrap();
         let video_ctx = sdl_ctx.video().unwrap();
@@ -206,42 +254,50 @@ impl Window {
         // here, and then the app can disable it if it wants to.
         video_ctx.enable_screen_saver();
 
+        let device_screen_size = options.device.screen_size;
         let scale_hack = options.scale_hack;
         // TODO: some apps specify their orientation in Info.plist, we could use
         // that here.
         let device_orientation = options.initial_orientation;
-        let fullscreen = options.fullscreen;
+        let fullscreen = options.fullscreen && !offscreen;
 
-        let mut window = if Self::rotatable_fullscreen() {
+        let mut window = if offscreen {
+            let (width, height) =
+                size_for_orientation(device_orientation, device_screen_size, scale_hack);
+            video_ctx
+                .window(title, width, height)
+                .hidden()
+                .opengl()
+                .build()
+                .unwrap()
+        } else if Self::rotatable_fullscreen() {
             // Without this, SDL will force fullscreen mode to be portrait.
             set_sdl2_orientation(device_orientation);
             let screen_size = video_ctx.display_bounds(0).unwrap().size();
             let (width, height) = rotate_fullscreen_size(device_orientation, screen_size);
-            let window = video_ctx
+            video_ctx
                 .window(title, width, height)
                 .fullscreen()
                 .opengl()
                 .build()
-                .unwrap();
-            window
+                .unwrap()
         } else if fullscreen {
             let (width, height) = video_ctx.display_bounds(0).unwrap().size();
-            let window = video_ctx
+            video_ctx
                 .window(title, width, height)
                 .fullscreen_desktop()
                 .opengl()
                 .build()
-                .unwrap();
-            window
+                .unwrap()
         } else {
-            let (width, height) = size_for_orientation(device_orientation, scale_hack);
-            let window = video_ctx
+            let (width, height) =
+                size_for_orientation(device_orientation, device_screen_size, scale_hack);
+            video_ctx
                 .window(title, width, height)
                 .position_centered()
                 .opengl()
                 .build()
-                .unwrap();
-            window
+                .unwrap()
         };
 
         if env::consts::OS == "android" {
@@ -290,6 +346,7 @@ impl Window {
             #[cfg(target_os = "macos")]
             viewport_y_offset: 0,
             fullscreen,
+            device_screen_size,
             scale_hack,
             internal_gl_ctx: None,
             splash_image: launch_image,
@@ -301,6 +358,8 @@ impl Window {
             accelerometer,
             virtual_cursor_last: None,
             virtual_cursor_last_unsticky: None,
+            frame_capture: FrameCapture::new(options),
+            state_request: None,
         };
 
         // Set up OpenGL ES context used for splash screen and app UI rendering
@@ -340,8 +399,11 @@ impl Window {
             independent_of_viewport: bool,
         ) -> (f32, f32) {
             let (vx, vy, vw, vh) = if independent_of_viewport {
-                let (width, height) =
-                    size_for_orientation(window.device_orientation, NonZeroU32::new(1).unwrap());
+                let (width, height) = size_for_orientation(
+                    window.device_orientation,
+                    window.device_screen_size,
+                    NonZeroU32::new(1).unwrap(),
+                );
                 (0, 0, width, height)
             } else {
                 window.viewport()
@@ -583,6 +645,30 @@ impl Window {
                     echo!("F12 pressed, EnterDebugger event queued.");
                     Event::EnterDebugger
                 }
+                E::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F10),
+                    ..
+                } => {
+                    echo!("F10 pressed, a screenshot will be taken of the next frame.");
+                    self.frame_capture.request_screenshot();
+                    continue;
+                }
+                E::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F5),
+                    ..
+                } => {
+                    echo!("F5 pressed, a state will be saved.");
+                    self.state_request = Some(StateRequest::Save);
+                    continue;
+                }
+                E::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F9),
+                    ..
+                } => {
+                    echo!("F9 pressed, a state will be loaded.");
+                    self.state_request = Some(StateRequest::Load);
+                    continue;
+                }
                 E::KeyDown {
                     keycode: Some(sdl2::keyboard::Keycode::Backspace),
                     ..
@@ -635,6 +721,13 @@ impl Window {
             .or_else(|| self.event_queue.pop_front())
     }
 
+    /// Get the save state operation requested since this was last called, if
+    /// any. Unlike [Event]s, this is handled by touchHLE itself (see
+    /// [crate::Environment]), not the app.
+    pub fn take_state_request(&mut self) -> Option<StateRequest> {
+        self.state_request.take()
+    }
+
     fn controller_added(&mut self, joystick_idx: u32) {
         let Ok(controller) = self.controller_ctx.open(joystick_idx) else {
             log!("Warning: A new controller was connected, but it couldn't be accessed!");
@@ -999,6 +1092,27 @@ impl Window {
         // onto image so we can rotate later if necessary
     }
 
+    /// Must be called once for each frame presented by the app, before it is
+    /// presented. Returns [true] if the frame should be passed to
+    /// [Self::capture_frame]. See [crate::frame_capture].
+    pub fn should_capture_frame(&mut self) -> bool {
+        self.frame_capture.begin_frame()
+    }
+
+    /// Save the current frame. The pixels must be in RGBA8 format, with rows in
+    /// bottom-to-top order (as returned by `glReadPixels()`), and not rotated.
+    /// See [crate::frame_capture].
+    pub fn capture_frame(&mut self, pixels: &[u8], size: (u32, u32)) {
+        self.frame_capture
+            .capture_frame(pixels, size, self.device_orientation);
+    }
+
+    /// The audio recorder, if `--record=` is in use. See
+    /// [crate::audio::recording].
+    pub fn audio_recorder(&mut self) -> Option<&mut AudioRecorder> {
+        self.frame_capture.audio_recorder()
+    }
+
     /// Swap front-buffer and back-buffer so the result of OpenGL rendering is
     /// presented.
     pub fn swap_window(&self) {
@@ -1020,7 +1134,7 @@ impl Window {
                 set_sdl2_orientation(new_orientation);
                 rotate_fullscreen_size(new_orientation, self.window.size())
             } else {
-                size_for_orientation(new_orientation, self.scale_hack)
+                size_for_orientation(new_orientation, self.device_screen_size, self.scale_hack)
             };
 
             // macOS quirk: when resizing the window, the new framebuffer's size
@@ -1078,7 +1192,11 @@ impl Window {
     /// The aspect ratio, scale and orientation reflect the guest app's view of
     /// the world.
     pub fn size_unrotated_unscaled(&self) -> (u32, u32) {
-        size_for_orientation(DeviceOrientation::Portrait, NonZeroU32::new(1).unwrap())
+        size_for_orientation(
+            DeviceOrientation::Portrait,
+            self.device_screen_size,
+            NonZeroU32::new(1).unwrap(),
+        )
     }
 
     /// Get the size in pixels of the window without rotation but with the
@@ -1087,7 +1205,11 @@ impl Window {
     /// Only the aspect ratio and orientation reflect the guest app's view of
     /// the world.
     pub fn size_unrotated_scalehacked(&self) -> (u32, u32) {
-        size_for_orientation(DeviceOrientation::Portrait, self.scale_hack)
+        size_for_orientation(
+            DeviceOrientation::Portrait,
+            self.device_screen_size,
+            self.scale_hack,
+        )
     }
 
     /// Get the region of the on-screen window (x, y, width, height) used to
@@ -1096,8 +1218,11 @@ impl Window {
     /// The aspect ratio of this region always reflects the guest app's view of
     /// the world, but the scale and orientation might not.
     pub fn viewport(&self) -> (u32, u32, u32, u32) {
-        let (app_width, app_height) =
-            size_for_orientation(self.device_orientation, self.scale_hack);
+        let (app_width, app_height) = size_for_orientation(
+            self.device_orientation,
+            self.device_screen_size,
+            self.scale_hack,
+        );
         if !self.fullscreen && !Self::rotatable_fullscreen() {
             return (0, 0, app_width, app_height);
         }
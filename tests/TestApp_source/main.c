@@ -27,6 +27,19 @@ typedef int wchar_t;
 // <errno.h>
 int *__error(void);
 #define errno (*__error())
+#define EPERM 1
+#define ENOENT 2
+#define EBADF 9
+#define EACCES 13
+#define EFAULT 14
+#define EEXIST 17
+#define ENOTDIR 20
+#define EISDIR 21
+#define EINVAL 22
+#define ESPIPE 29
+#define EPIPE 32
+#define ENOTSUP 45
+#define ENOTEMPTY 66
 
 // <stdarg.h>
 typedef __builtin_va_list va_list;
@@ -38,6 +51,7 @@ typedef __builtin_va_list va_list;
 typedef struct FILE FILE;
 FILE *fopen(const char *, const char *);
 int fclose(FILE *);
+int rename(const char *, const char *);
 int sscanf(const char *, const char *, ...);
 int printf(const char *, ...);
 int vsnprintf(char *, size_t, const char *, va_list);
@@ -48,6 +62,7 @@ size_t fwrite(const void *, size_t, size_t, FILE *);
 #define EXIT_SUCCESS 0
 #define EXIT_FAILURE 1
 void exit(int);
+int atexit(void (*)(void));
 void free(void *);
 void *malloc(size_t);
 void qsort(void *, size_t, size_t, int (*)(const void *, const void *));
@@ -75,14 +90,78 @@ int strncmp(const char *, const char *, size_t);
 size_t strcspn(const char *, const char *);
 
 // <unistd.h>
+#define SEEK_SET 0
+#define SEEK_CUR 1
+#define SEEK_END 2
 typedef unsigned int __uint32_t;
 typedef __uint32_t useconds_t;
+typedef long ssize_t;
+typedef long long off_t;
 int chdir(const char *);
+int fchdir(int);
 char *getcwd(char *, size_t);
 int usleep(useconds_t);
+int close(int);
+void _exit(int);
+ssize_t read(int, void *, size_t);
+ssize_t write(int, const void *, size_t);
+off_t lseek(int, off_t, int);
+int ftruncate(int, off_t);
+int fsync(int);
+int dup(int);
+int dup2(int, int);
+int pipe(int[2]);
+int unlink(const char *);
+int rmdir(const char *);
+
+// <cxxabi.h>
+int __cxa_atexit(void (*)(void *), void *, void *);
 
 // <fcntl.h>
+#define O_RDONLY 0x00000000
+#define O_WRONLY 0x00000001
+#define O_RDWR 0x00000002
+#define O_NONBLOCK 0x00000004
+#define O_SHLOCK 0x00000010
+#define O_EXLOCK 0x00000020
 #define O_CREAT 0x00000200
+#define O_TRUNC 0x00000400
+#define O_EXCL 0x00000800
+#define F_GETFL 3
+#define F_SETFL 4
+int open(const char *, int, ...);
+int fcntl(int, int, ...);
+
+// <sys/file.h>
+#define LOCK_SH 1
+#define LOCK_EX 2
+#define LOCK_NB 4
+#define LOCK_UN 8
+int flock(int, int);
+
+// <sys/stat.h>
+#define S_IFMT 0170000
+#define S_IFDIR 0040000
+#define S_IFREG 0100000
+struct stat {
+  int st_dev;
+  unsigned short st_mode;
+  unsigned short st_nlink;
+  unsigned long long st_ino;
+  unsigned int st_uid;
+  unsigned int st_gid;
+  int st_rdev;
+  long st_timespecs[8];
+  long long st_size;
+  long long st_blocks;
+  int st_blksize;
+  unsigned int st_flags;
+  unsigned int st_gen;
+  int st_lspare;
+  long long st_qspare[2];
+};
+int mkdir(const char *, unsigned short);
+int fstat(int, struct stat *);
 
 // <pthread.h>
 typedef struct opaque_pthread_t opaque_pthread_t;
@@ -118,8 +197,14 @@ char *setlocale(int category, const char *locale);
 typedef struct {
   int _unused;
 } DIR;
+#define DT_DIR 4
+#define DT_REG 8
 struct dirent {
-  char _unused[21]; // TODO
+  unsigned long long d_ino;
+  unsigned long long d_seekoff;
+  unsigned short d_reclen;
+  unsigned short d_namlen;
+  unsigned char d_type;
   char d_name[1024];
 };
 DIR *opendir(const char *);
@@ -129,6 +214,123 @@ int closedir(DIR *);
 // <wchar.h>
 int swscanf(const wchar_t *, const wchar_t *, ...);
 
+// <sys/mman.h>
+#define PROT_NONE 0x00
+#define PROT_READ 0x01
+#define PROT_WRITE 0x02
+#define MAP_PRIVATE 0x0002
+#define MAP_FIXED 0x0010
+#define MAP_ANON 0x1000
+#define MAP_FAILED ((void *)-1)
+void *mmap(void *, size_t, int, int, int, off_t);
+int munmap(void *, size_t);
+int mprotect(void *, size_t, int);
+
+// <setjmp.h>
+typedef int jmp_buf[10 + 16 + 2];
+typedef int sigjmp_buf[10 + 16 + 2 + 1];
+int setjmp(jmp_buf);
+void longjmp(jmp_buf, int);
+int _setjmp(jmp_buf);
+void _longjmp(jmp_buf, int);
+int sigsetjmp(sigjmp_buf, int);
+void siglongjmp(sigjmp_buf, int);
+
+// <signal.h>
+#define SIGBUS 10
+#define SIGSEGV 11
+#define SIGPIPE 13
+#define SIGUSR1 30
+#define SIG_DFL ((void (*)(int))0)
+#define SIG_IGN ((void (*)(int))1)
+#define SIG_BLOCK 1
+#define SIG_UNBLOCK 2
+#define SA_SIGINFO 0x40
+#define SI_USER 0x10001
+#define SEGV_MAPERR 1
+#define BUS_ADRERR 2
+typedef unsigned int sigset_t;
+typedef struct {
+  int si_signo;
+  int si_errno;
+  int si_code;
+  int si_pid;
+  unsigned int si_uid;
+  int si_status;
+  void *si_addr;
+  int si_value;
+  long si_band;
+  unsigned long __pad[7];
+} siginfo_t;
+struct sigaction {
+  void (*sa_sigaction)(int, siginfo_t *, void *);
+  sigset_t sa_mask;
+  int sa_flags;
+};
+int sigaction(int, const struct sigaction *, struct sigaction *);
+void (*signal(int, void (*)(int)))(int);
+int raise(int);
+int sigemptyset(sigset_t *);
+int sigaddset(sigset_t *, int);
+int sigismember(const sigset_t *, int);
+int sigprocmask(int, const sigset_t *, sigset_t *);
+int sigpending(sigset_t *);
+
+// <sys/socket.h>, <netinet/in.h> and <arpa/inet.h>
+#define AF_INET 2
+#define SOCK_STREAM 1
+#define SOCK_DGRAM 2
+#define EAGAIN 35
+#define EINPROGRESS 36
+#define ECONNREFUSED 61
+#define MSG_WAITALL 0x40
+typedef unsigned int socklen_t;
+struct sockaddr;
+struct sockaddr_in {
+  unsigned char sin_len;
+  unsigned char sin_family;
+  unsigned short sin_port;
+  unsigned int sin_addr;
+  char sin_zero[8];
+};
+int socket(int, int, int);
+int bind(int, const struct sockaddr *, socklen_t);
+int listen(int, int);
+int accept(int, struct sockaddr *, socklen_t *);
+int connect(int, const struct sockaddr *, socklen_t);
+int getsockname(int, struct sockaddr *, socklen_t *);
+ssize_t send(int, const void *, size_t, int);
+ssize_t recv(int, void *, size_t, int);
+ssize_t sendto(int, const void *, size_t, int, const struct sockaddr *,
+               socklen_t);
+ssize_t recvfrom(int, void *, size_t, int, struct sockaddr *, socklen_t *);
+unsigned int inet_addr(const char *);
+
+// <poll.h>
+#define POLLIN 0x0001
+#define POLLOUT 0x0004
+struct pollfd {
+  int fd;
+  short events;
+  short revents;
+};
+int poll(struct pollfd *, unsigned int, int);
+
+// <sys/sysctl.h> and <sys/utsname.h>
+#define ENOMEM 12
+#define CTL_HW 6
+#define HW_MACHINE 1
+int sysctl(int *, unsigned int, void *, size_t *, void *, size_t);
+int sysctlbyname(const char *, void *, size_t *, void *, size_t);
+struct utsname {
+  char sysname[256];
+  char nodename[256];
+  char release[256];
+  char version[256];
+  char machine[256];
+};
+int uname(struct utsname *);
+
 // `CFBase.h`
 
 typedef const struct _CFAllocator *CFAllocatorRef;
@@ -158,6 +360,36 @@ CFComparisonResult CFStringCompare(CFStringRef a, CFStringRef b,
 CFRange CFStringFind(CFStringRef theString, CFStringRef stringToFind,
                      CFOptionFlags compareOptions);
 
+// `objc/objc.h`, `objc/message.h`
+typedef struct objc_object *id;
+typedef struct objc_selector *SEL;
+id objc_msgSend(id, SEL, ...);
+SEL sel_registerName(const char *);
+
+// `NSObjCRuntime.h`
+id NSClassFromString(CFStringRef);
+
+// `EAGL.h`
+#define kEAGLRenderingAPIOpenGLES1 1
+
+// `OpenGLES/ES1/gl.h`, `OpenGLES/ES1/glext.h`
+typedef unsigned int GLenum;
+typedef unsigned int GLbitfield;
+typedef unsigned int GLuint;
+typedef int GLsizei;
+typedef float GLclampf;
+#define GL_COLOR_BUFFER_BIT 0x00004000
+#define GL_FRAMEBUFFER_OES 0x8D40
+#define GL_RENDERBUFFER_OES 0x8D41
+#define GL_COLOR_ATTACHMENT0_OES 0x8CE0
+void glClearColor(GLclampf, GLclampf, GLclampf, GLclampf);
+void glClear(GLbitfield);
+void glGenFramebuffersOES(GLsizei, GLuint *);
+void glBindFramebufferOES(GLenum, GLuint);
+void glGenRenderbuffersOES(GLsizei, GLuint *);
+void glBindRenderbufferOES(GLenum, GLuint);
+void glFramebufferRenderbufferOES(GLenum, GLenum, GLenum, GLuint);
+
 // === Main code ===
 
 int int_compar(const void *a, const void *b) { return *(int *)a - *(int *)b; }
@@ -836,6 +1068,13 @@ const char *path_test_app = "/var/mobile/Applications/"
                             "00000000-0000-0000-0000-000000000000/TestApp.app";
 #endif
 
+#ifdef DEFINE_ME_WHEN_BUILDING_ON_MACOS
+const char *path_documents = "/tmp";
+#else
+const char *path_documents = "/var/mobile/Applications/"
+                             "00000000-0000-0000-0000-000000000000/Documents";
+#endif
+
 int test_dirent() {
   struct dirent *dp;
   DIR *dirp = opendir(path_test_app);
@@ -862,6 +1101,266 @@ int test_dirent() {
   return 0;
 }
 
+// Removes anything test_posix_io() might have left behind, ignoring errors.
+void test_posix_io_cleanup() {
+  unlink("posix_io_dir/f");
+  rmdir("posix_io_dir");
+  rmdir("posix_io_empty");
+  rmdir("posix_io_empty2");
+  unlink("posix_io_file");
+  unlink("posix_io_moved");
+  unlink("posix_io_read_only");
+}
+
+enum {
+  FD_BAD,
+  FD_FILE,
+  FD_READ_ONLY,
+  FD_FILE_AGAIN,
+  FD_DIR,
+  FD_PIPE_READ,
+  FD_PIPE_WRITE,
+  FD_BROKEN_PIPE_WRITE,
+  FD_COUNT
+};
+
+enum {
+  OP_OPEN,
+  OP_READ,
+  OP_WRITE,
+  OP_LSEEK,
+  OP_FSTAT,
+  OP_FTRUNCATE,
+  OP_MKDIR,
+  OP_RMDIR,
+  OP_RENAME,
+  OP_UNLINK,
+  OP_FSYNC,
+  OP_DUP,
+  OP_DUP2,
+  OP_FCHDIR,
+  OP_FLOCK,
+  OP_CLOSE
+};
+
+struct posix_io_error_case {
+  int op;
+  const char *path;
+  const char *path2;
+  int fd;
+  long long arg;
+  int arg2;
+  int expected_errno;
+};
+
+int test_posix_io_errors_run(const struct posix_io_error_case *c, int *fds) {
+  char buf[16];
+  struct stat st;
+  int fd = fds[c->fd];
+  switch (c->op) {
+  case OP_OPEN:
+    return open(c->path, (int)c->arg, 0644);
+  case OP_READ:
+    return read(fd, buf, sizeof buf);
+  case OP_WRITE:
+    return write(fd, "x", 1);
+  case OP_LSEEK:
+    return lseek(fd, c->arg, c->arg2);
+  case OP_FSTAT:
+    return fstat(fd, &st);
+  case OP_FTRUNCATE:
+    return ftruncate(fd, c->arg);
+  case OP_MKDIR:
+    return mkdir(c->path, 0755);
+  case OP_RMDIR:
+    return rmdir(c->path);
+  case OP_RENAME:
+    return rename(c->path, c->path2);
+  case OP_UNLINK:
+    return unlink(c->path);
+  case OP_FSYNC:
+    return fsync(fd);
+  case OP_DUP:
+    return dup(fd);
+  case OP_DUP2:
+    return dup2(fd, 60);
+  case OP_FCHDIR:
+    return fchdir(fd);
+  case OP_FLOCK:
+    return flock(fd, (int)c->arg);
+  case OP_CLOSE:
+    return close(fd);
+  default:
+    return 0;
+  }
+}
+
+int test_posix_io_errors() {
+  char *old_cwd = getcwd(NULL, 0);
+  if (chdir(path_documents))
+    return -1;
+  test_posix_io_cleanup();
+  // Writing to a pipe with no reader raises SIGPIPE before failing.
+  signal(SIGPIPE, SIG_IGN);
+
+  int fds[FD_COUNT];
+  int pipe_fds[2], broken_pipe_fds[2];
+  fds[FD_BAD] = 200;
+  fds[FD_FILE] = open("posix_io_file", O_RDWR | O_CREAT | O_EXCL, 0644);
+  int fd = open("posix_io_read_only", O_WRONLY | O_CREAT, 0444);
+  if (fds[FD_FILE] == -1 || fd == -1 || close(fd))
+    return -2;
+  fds[FD_READ_ONLY] = open("posix_io_read_only", O_RDONLY);
+  fds[FD_FILE_AGAIN] = open("posix_io_file", O_RDONLY);
+  if (mkdir("posix_io_dir", 0755) || mkdir("posix_io_empty", 0755))
+    return -3;
+  fd = open("posix_io_dir/f", O_WRONLY | O_CREAT | O_TRUNC, 0644);
+  if (fd == -1 || close(fd))
+    return -4;
+  fds[FD_DIR] = open("posix_io_dir", O_RDONLY);
+  if (fds[FD_READ_ONLY] == -1 || fds[FD_FILE_AGAIN] == -1 ||
+      fds[FD_DIR] == -1 || pipe(pipe_fds) || pipe(broken_pipe_fds) ||
+      close(broken_pipe_fds[0]))
+    return -5;
+  fds[FD_PIPE_READ] = pipe_fds[0];
+  fds[FD_PIPE_WRITE] = pipe_fds[1];
+  fds[FD_BROKEN_PIPE_WRITE] = broken_pipe_fds[1];
+  if (flock(fds[FD_FILE], LOCK_EX))
+    return -6;
+
+  char bundle_file[256];
+  strlcpy(bundle_file, path_test_app, sizeof bundle_file);
+  strncat(bundle_file, "/Info.plist", sizeof bundle_file - strlen(bundle_file));
+
+  struct posix_io_error_case cases[] = {
+      {OP_OPEN, "posix_io_nonexistent", NULL, 0, O_RDONLY, 0, ENOENT},
+      {OP_OPEN, "posix_io_file", NULL, 0, O_WRONLY | O_CREAT | O_EXCL, 0,
+       EEXIST},
+      {OP_OPEN, "posix_io_dir", NULL, 0, O_WRONLY, 0, EISDIR},
+      {OP_OPEN, "posix_io_file/x", NULL, 0, O_RDONLY, 0, ENOTDIR},
+      {OP_OPEN, NULL, NULL, 0, O_RDONLY, 0, EFAULT},
+      {OP_OPEN, "posix_io_read_only", NULL, 0, O_WRONLY, 0, EACCES},
+      {OP_OPEN, bundle_file, NULL, 0, O_WRONLY, 0, EACCES},
+      {OP_OPEN, "posix_io_file", NULL, 0, O_RDONLY | O_SHLOCK | O_NONBLOCK, 0,
+       EAGAIN},
+      {OP_OPEN, "posix_io_file", NULL, 0, O_RDONLY | O_EXLOCK | O_NONBLOCK, 0,
+       EAGAIN},
+      {OP_READ, NULL, NULL, FD_BAD, 0, 0, EBADF},
+      {OP_READ, NULL, NULL, FD_DIR, 0, 0, EISDIR},
+      {OP_READ, NULL, NULL, FD_PIPE_WRITE, 0, 0, EBADF},
+      {OP_WRITE, NULL, NULL, FD_BAD, 0, 0, EBADF},
+      {OP_WRITE, NULL, NULL, FD_READ_ONLY, 0, 0, EBADF},
+      {OP_WRITE, NULL, NULL, FD_PIPE_READ, 0, 0, EBADF},
+      {OP_WRITE, NULL, NULL, FD_BROKEN_PIPE_WRITE, 0, 0, EPIPE},
+      {OP_LSEEK, NULL, NULL, FD_BAD, 0, SEEK_SET, EBADF},
+      {OP_LSEEK, NULL, NULL, FD_PIPE_READ, 0, SEEK_SET, ESPIPE},
+      {OP_LSEEK, NULL, NULL, FD_FILE, 0, 42, EINVAL},
+      {OP_LSEEK, NULL, NULL, FD_FILE, -1, SEEK_SET, EINVAL},
+      {OP_FSTAT, NULL, NULL, FD_BAD, 0, 0, EBADF},
+      {OP_FTRUNCATE, NULL, NULL, FD_BAD, 0, 0, EBADF},
+      {OP_FTRUNCATE, NULL, NULL, FD_READ_ONLY, 0, 0, EINVAL},
+      {OP_FTRUNCATE, NULL, NULL, FD_FILE, -1, 0, EINVAL},
+      {OP_MKDIR, "posix_io_dir", NULL, 0, 0, 0, EEXIST},
+      {OP_MKDIR, "posix_io_file", NULL, 0, 0, 0, EEXIST},
+      {OP_MKDIR, "posix_io_nonexistent/x", NULL, 0, 0, 0, ENOENT},
+      {OP_MKDIR, "posix_io_file/x", NULL, 0, 0, 0, ENOTDIR},
+      {OP_RMDIR, "posix_io_dir", NULL, 0, 0, 0, ENOTEMPTY},
+      {OP_RMDIR, "posix_io_file", NULL, 0, 0, 0, ENOTDIR},
+      {OP_RMDIR, "posix_io_nonexistent", NULL, 0, 0, 0, ENOENT},
+      {OP_RENAME, "posix_io_nonexistent", "posix_io_x", 0, 0, 0, ENOENT},
+      {OP_RENAME, "posix_io_dir", "posix_io_file", 0, 0, 0, ENOTDIR},
+      {OP_RENAME, "posix_io_file", "posix_io_empty", 0, 0, 0, EISDIR},
+      {OP_RENAME, "posix_io_empty", "posix_io_dir", 0, 0, 0, ENOTEMPTY},
+      {OP_RENAME, "posix_io_dir", "posix_io_dir/x", 0, 0, 0, EINVAL},
+      {OP_RENAME, bundle_file, "posix_io_x", 0, 0, 0, EACCES},
+      {OP_UNLINK, "posix_io_nonexistent", NULL, 0, 0, 0, ENOENT},
+      {OP_UNLINK, "posix_io_dir", NULL, 0, 0, 0, EPERM},
+      {OP_FSYNC, NULL, NULL, FD_BAD, 0, 0, EBADF},
+      {OP_FSYNC, NULL, NULL, FD_PIPE_WRITE, 0, 0, ENOTSUP},
+      {OP_DUP, NULL, NULL, FD_BAD, 0, 0, EBADF},
+      {OP_DUP2, NULL, NULL, FD_BAD, 0, 0, EBADF},
+      {OP_FCHDIR, NULL, NULL, FD_BAD, 0, 0, EBADF},
+      {OP_FCHDIR, NULL, NULL, FD_FILE, 0, 0, ENOTDIR},
+      {OP_FLOCK, NULL, NULL, FD_BAD, LOCK_SH, 0, EBADF},
+      {OP_FLOCK, NULL, NULL, FD_PIPE_READ, LOCK_SH, 0, ENOTSUP},
+      {OP_FLOCK, NULL, NULL, FD_FILE, 0, 0, EINVAL},
+      {OP_FLOCK, NULL, NULL, FD_FILE_AGAIN, LOCK_SH | LOCK_NB, 0, EAGAIN},
+      {OP_CLOSE, NULL, NULL, FD_BAD, 0, 0, EBADF},
+  };
+  int n = sizeof(cases) / sizeof(cases[0]);
+  for (int i = 0; i < n; i++) {
+    errno = 0;
+    if (test_posix_io_errors_run(&cases[i], fds) != -1 ||
+        errno != cases[i].expected_errno) {
+      return -100 - i;
+    }
+  }
+
+  // The same calls succeed when used properly.
+  char buf[8];
+  if (write(fds[FD_PIPE_WRITE], "abc", 3) != 3 ||
+      read(fds[FD_PIPE_READ], buf, sizeof buf) != 3 || memcmp(buf, "abc", 3))
+    return -7;
+  if (close(fds[FD_PIPE_WRITE]) || read(fds[FD_PIPE_READ], buf, 1) != 0)
+    return -8;
+  // Duplicated descriptors share their file offset.
+  int dup_fd = dup(fds[FD_FILE]);
+  if (write(fds[FD_FILE], "hello", 5) != 5 ||
+      lseek(dup_fd, 0, SEEK_SET) != 0 ||
+      lseek(fds[FD_FILE], 0, SEEK_CUR) != 0 ||
+      read(fds[FD_FILE], buf, 5) != 5 || memcmp(buf, "hello", 5))
+    return -9;
+  if (dup2(fds[FD_FILE], 50) != 50 || close(50) || close(dup_fd))
+    return -10;
+  struct stat st;
+  if (fstat(fds[FD_FILE], &st) || (st.st_mode & S_IFMT) != S_IFREG ||
+      st.st_size != 5 || fstat(fds[FD_DIR], &st) ||
+      (st.st_mode & S_IFMT) != S_IFDIR)
+    return -11;
+  if (flock(fds[FD_FILE], LOCK_UN) ||
+      flock(fds[FD_FILE_AGAIN], LOCK_SH | LOCK_NB))
+    return -12;
+  char *cwd = NULL;
+  if (fchdir(fds[FD_DIR]) || !(cwd = getcwd(NULL, 0)) ||
+      strcmp(strrchr(cwd, '/'), "/posix_io_dir") || chdir(".."))
+    return -13;
+  free(cwd);
+
+  // Every directory lists itself and its parent.
+  int found = 0;
+  DIR *dirp = opendir("posix_io_dir");
+  struct dirent *dp;
+  while (dirp && (dp = readdir(dirp)) != NULL) {
+    if ((!strcmp(dp->d_name, ".") || !strcmp(dp->d_name, "..")) &&
+        dp->d_type == DT_DIR)
+      found++;
+    else if (!strcmp(dp->d_name, "f") && dp->d_type == DT_REG)
+      found++;
+    else
+      return -14;
+  }
+  if (!dirp || closedir(dirp) || found != 3)
+    return -15;
+
+  if (rename("posix_io_file", "posix_io_moved") ||
+      rename("posix_io_empty", "posix_io_empty2") ||
+      open("posix_io_file", O_RDONLY) != -1 || errno != ENOENT)
+    return -16;
+  for (int i = FD_FILE; i < FD_COUNT; i++) {
+    if (i != FD_PIPE_WRITE && close(fds[i]))
+      return -17;
+  }
+  if (unlink("posix_io_moved") || unlink("posix_io_read_only") ||
+      rmdir("posix_io_empty2") || unlink("posix_io_dir/f") ||
+      rmdir("posix_io_dir"))
+    return -18;
+
+  signal(SIGPIPE, SIG_DFL);
+  chdir(old_cwd);
+  free(old_cwd);
+  return 0;
+}
+
 int test_strchr() {
   char *src = "abc";
   if (strchr(src, 'a')[0] != 'a' || strrchr(src, 'a')[0] != 'a')
@@ -1011,6 +1510,391 @@ int test_fwrite() {
   return 0;
 }
 
+int test_mmap() {
+  size_t page = 0x1000;
+  char *p = mmap(NULL, 3 * page, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANON,
+                 -1, 0);
+  if (p == MAP_FAILED || (unsigned long)p % page != 0)
+    return -1;
+  for (size_t i = 0; i < 3 * page; i++) {
+    if (p[i] != 0)
+      return -2;
+  }
+  p[0] = 1;
+  p[page] = 2;
+  p[2 * page] = 3;
+
+  // Replace the middle page with a new one.
+  if (munmap(p + page, page))
+    return -3;
+  char *p2 = mmap(p + page, page, PROT_READ | PROT_WRITE,
+                  MAP_PRIVATE | MAP_ANON | MAP_FIXED, -1, 0);
+  if (p2 != p + page || p[0] != 1 || p[page] != 0 || p[2 * page] != 3)
+    return -4;
+
+  // Lengths that aren't a multiple of the page size are rounded up.
+  if (munmap(p, 3 * page - 1))
+    return -5;
+  // Unaligned addresses aren't allowed.
+  if (munmap(p + 1, page) != -1 || errno != EINVAL)
+    return -6;
+  // Fixed mappings can't replace memory that wasn't mapped with mmap().
+  void *code = (void *)((unsigned long)&test_mmap & ~(page - 1));
+  if (mmap(code, page, PROT_READ, MAP_PRIVATE | MAP_ANON | MAP_FIXED, -1, 0) !=
+          MAP_FAILED ||
+      errno != ENOMEM)
+    return -7;
+  return 0;
+}
+
+jmp_buf test_setjmp_buf;
+sigjmp_buf test_setjmp_sigbuf;
+
+void test_setjmp_jump(int val) {
+  // Clobber some callee-saved registers, so that they must be restored.
+  volatile double d = val * 2.5;
+  (void)d;
+  longjmp(test_setjmp_buf, val);
+}
+
+int test_setjmp() {
+  volatile int calls = 0;
+  int res = setjmp(test_setjmp_buf);
+  calls++;
+  if (calls == 1) {
+    if (res != 0)
+      return -1;
+    test_setjmp_jump(42);
+    return -2; // unreachable
+  } else if (calls == 2) {
+    if (res != 42)
+      return -3;
+    // longjmp(buf, 0) must make setjmp() return 1
+    test_setjmp_jump(0);
+    return -4; // unreachable
+  } else if (calls == 3) {
+    if (res != 1)
+      return -5;
+  } else {
+    return -6;
+  }
+
+  volatile int sigcalls = 0;
+  res = sigsetjmp(test_setjmp_sigbuf, 1);
+  sigcalls++;
+  if (sigcalls == 1) {
+    if (res != 0)
+      return -7;
+    siglongjmp(test_setjmp_sigbuf, 7);
+  } else if (sigcalls != 2 || res != 7) {
+    return -8;
+  }
+
+  volatile int _calls = 0;
+  res = _setjmp(test_setjmp_buf);
+  _calls++;
+  if (_calls == 1) {
+    if (res != 0)
+      return -9;
+    _longjmp(test_setjmp_buf, 3);
+  } else if (_calls != 2 || res != 3) {
+    return -10;
+  }
+  return 0;
+}
+
+volatile int test_signals_count;
+volatile siginfo_t test_signals_info;
+sigjmp_buf test_signals_buf;
+
+void test_signals_handler(int sig, siginfo_t *info, void *context) {
+  test_signals_count++;
+  test_signals_info = *info;
+}
+
+void test_signals_segv_handler(int sig, siginfo_t *info, void *context) {
+  test_signals_info = *info;
+  siglongjmp(test_signals_buf, 1);
+}
+
+int test_signals() {
+  struct sigaction action = {0}, old_action;
+  action.sa_sigaction = test_signals_handler;
+  action.sa_flags = SA_SIGINFO;
+  sigemptyset(&action.sa_mask);
+  if (sigaction(SIGUSR1, &action, &old_action) != 0 ||
+      old_action.sa_sigaction != 0)
+    return -1;
+  if (raise(SIGUSR1) != 0 || test_signals_count != 1 ||
+      test_signals_info.si_signo != SIGUSR1 ||
+      test_signals_info.si_code != SI_USER)
+    return -2;
+
+  // A blocked signal stays pending until it's unblocked.
+  sigset_t set, pending;
+  sigemptyset(&set);
+  sigaddset(&set, SIGUSR1);
+  if (sigprocmask(SIG_BLOCK, &set, NULL) != 0 || raise(SIGUSR1) != 0 ||
+      test_signals_count != 1)
+    return -3;
+  if (sigpending(&pending) != 0 || !sigismember(&pending, SIGUSR1))
+    return -4;
+  if (sigprocmask(SIG_UNBLOCK, &set, NULL) != 0 || test_signals_count != 2)
+    return -5;
+  if (signal(SIGUSR1, SIG_DFL) != (void (*)(int))test_signals_handler)
+    return -6;
+
+  // An invalid memory access runs the handler, which can jump out of it.
+  // Accessing mapped memory that the protection forbids is a SIGBUS.
+  size_t page = 0x1000;
+  char *p = mmap(NULL, page, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANON,
+                 -1, 0);
+  if (p == MAP_FAILED || mprotect(p, page, PROT_NONE) != 0)
+    return -7;
+  struct sigaction old_segv_action;
+  action.sa_sigaction = test_signals_segv_handler;
+  if (sigaction(SIGBUS, &action, &old_action) != 0 ||
+      sigaction(SIGSEGV, &action, &old_segv_action) != 0)
+    return -8;
+  volatile int faulted = 0;
+  if (sigsetjmp(test_signals_buf, 1) == 0) {
+    (void)*(volatile char *)(p + 4);
+  } else {
+    faulted = 1;
+  }
+  munmap(p, page);
+  if (!faulted || test_signals_info.si_signo != SIGBUS ||
+      test_signals_info.si_code != BUS_ADRERR ||
+      test_signals_info.si_addr != p + 4)
+    return -9;
+  // siglongjmp() restored the signal mask, so SIGBUS isn't blocked anymore.
+  if (sigprocmask(SIG_BLOCK, NULL, &set) != 0 || sigismember(&set, SIGBUS))
+    return -10;
+
+  // Accessing unmapped memory, like the null page, is a SIGSEGV.
+  faulted = 0;
+  if (sigsetjmp(test_signals_buf, 1) == 0) {
+    (void)*(volatile char *)8;
+  } else {
+    faulted = 1;
+  }
+  sigaction(SIGBUS, &old_action, NULL);
+  sigaction(SIGSEGV, &old_segv_action, NULL);
+  if (!faulted || test_signals_info.si_signo != SIGSEGV ||
+      test_signals_info.si_code != SEGV_MAPERR ||
+      test_signals_info.si_addr != (void *)8)
+    return -11;
+  return 0;
+}
+
+// The handlers run when main() returns, so a failure is reported with _exit()
+// and a message instead of the test's return value. integration.rs checks that
+// they ran at all.
+char test_atexit_order[5];
+int test_atexit_arg = 42;
+
+void test_atexit_check(void) {
+  if (strcmp(test_atexit_order, "cdba") != 0) {
+    printf("test_atexit: handlers ran in the wrong order (%s)\n",
+           test_atexit_order);
+    _exit(1);
+  }
+  printf("Exit handlers ran\n");
+}
+void test_atexit_a(void) { strncat(test_atexit_order, "a", 1); }
+void test_atexit_b(void *arg) {
+  if (arg == &test_atexit_arg && *(int *)arg == 42)
+    strncat(test_atexit_order, "b", 1);
+}
+void test_atexit_d(void) { strncat(test_atexit_order, "d", 1); }
+void test_atexit_c(void) {
+  strncat(test_atexit_order, "c", 1);
+  // A handler registered while handlers are running is run next.
+  atexit(test_atexit_d);
+}
+
+int test_atexit() {
+  // Handlers are run in the reverse order of registration, whether they were
+  // registered with atexit() or __cxa_atexit().
+  if (atexit(test_atexit_check) != 0 || atexit(test_atexit_a) != 0 ||
+      __cxa_atexit(test_atexit_b, &test_atexit_arg, NULL) != 0 ||
+      atexit(test_atexit_c) != 0)
+    return -1;
+  return 0;
+}
+
+int test_sockets() {
+  // A UDP socket sending to itself.
+  int udp = socket(AF_INET, SOCK_DGRAM, 0);
+  if (udp < 0)
+    return -1;
+  struct sockaddr_in addr;
+  memset(&addr, 0, sizeof(addr));
+  addr.sin_len = sizeof(addr);
+  addr.sin_family = AF_INET;
+  addr.sin_addr = inet_addr("127.0.0.1");
+  socklen_t addr_len = sizeof(addr);
+  if (bind(udp, (struct sockaddr *)&addr, sizeof(addr)) ||
+      getsockname(udp, (struct sockaddr *)&addr, &addr_len) ||
+      addr.sin_port == 0)
+    return -2;
+  if (sendto(udp, "ping", 4, 0, (struct sockaddr *)&addr, sizeof(addr)) != 4)
+    return -3;
+  char buf[8];
+  if (recvfrom(udp, buf, sizeof(buf), 0, NULL, NULL) != 4 ||
+      memcmp(buf, "ping", 4))
+    return -4;
+  close(udp);
+
+  // A TCP connection to a listening socket.
+  int server = socket(AF_INET, SOCK_STREAM, 0);
+  addr.sin_port = 0;
+  addr_len = sizeof(addr);
+  if (server < 0 || bind(server, (struct sockaddr *)&addr, sizeof(addr)) ||
+      listen(server, 1) ||
+      getsockname(server, (struct sockaddr *)&addr, &addr_len))
+    return -5;
+  int client = socket(AF_INET, SOCK_STREAM, 0);
+  fcntl(client, F_SETFL, fcntl(client, F_GETFL) | O_NONBLOCK);
+  if (connect(client, (struct sockaddr *)&addr, sizeof(addr)) != -1 ||
+      errno != EINPROGRESS)
+    return -6;
+  int conn = accept(server, NULL, NULL);
+  if (conn < 0)
+    return -7;
+  struct pollfd pfd = {client, POLLOUT, 0};
+  if (poll(&pfd, 1, 1000) != 1 || pfd.revents != POLLOUT)
+    return -8;
+  if (send(client, "hello", 5, 0) != 5 || recv(conn, buf, 5, 0) != 5 ||
+      memcmp(buf, "hello", 5))
+    return -9;
+  // There's nothing more to read, so this would block.
+  if (recv(client, buf, 5, 0) != -1 || errno != EAGAIN)
+    return -10;
+  close(client);
+  // The other end sees the connection being closed.
+  if (recv(conn, buf, 5, 0) != 0)
+    return -11;
+  close(conn);
+
+  // A socket can be bound before connecting, and doesn't accept connections
+  // unless it's listening.
+  struct sockaddr_in bound_addr = addr;
+  bound_addr.sin_port = 0;
+  addr_len = sizeof(bound_addr);
+  int bound = socket(AF_INET, SOCK_STREAM, 0);
+  if (bound < 0 ||
+      bind(bound, (struct sockaddr *)&bound_addr, sizeof(bound_addr)) ||
+      getsockname(bound, (struct sockaddr *)&bound_addr, &addr_len) ||
+      bound_addr.sin_port == 0)
+    return -12;
+  client = socket(AF_INET, SOCK_STREAM, 0);
+  if (connect(client, (struct sockaddr *)&bound_addr, sizeof(bound_addr)) !=
+          -1 ||
+      errno != ECONNREFUSED)
+    return -13;
+  close(client);
+  struct sockaddr_in peer_addr;
+  addr_len = sizeof(peer_addr);
+  if (connect(bound, (struct sockaddr *)&addr, sizeof(addr)) ||
+      (conn = accept(server, (struct sockaddr *)&peer_addr, &addr_len)) < 0 ||
+      peer_addr.sin_port != bound_addr.sin_port)
+    return -14;
+  close(conn);
+  close(bound);
+  close(server);
+  return 0;
+}
+
+// Used by test_socket_threads().
+struct sockaddr_in socket_thread_addr;
+int socket_thread_result = 0;
+
+void *socket_thread_func(void *arg) {
+  char buf[4];
+  int client = socket(AF_INET, SOCK_STREAM, 0);
+  // The main thread is blocked in accept() until this connects.
+  if (client < 0 ||
+      connect(client, (struct sockaddr *)&socket_thread_addr,
+              sizeof(socket_thread_addr)) ||
+      send(client, "ping", 4, 0) != 4)
+    socket_thread_result = -1;
+  // This blocks until the main thread has sent both halves of the reply.
+  else if (recv(client, buf, 4, MSG_WAITALL) != 4 || memcmp(buf, "pong", 4))
+    socket_thread_result = -2;
+  else
+    socket_thread_result = 1;
+  close(client);
+  return NULL;
+}
+
+int test_socket_threads() {
+  int server = socket(AF_INET, SOCK_STREAM, 0);
+  memset(&socket_thread_addr, 0, sizeof(socket_thread_addr));
+  socket_thread_addr.sin_len = sizeof(socket_thread_addr);
+  socket_thread_addr.sin_family = AF_INET;
+  socket_thread_addr.sin_addr = inet_addr("127.0.0.1");
+  socklen_t addr_len = sizeof(socket_thread_addr);
+  if (server < 0 ||
+      bind(server, (struct sockaddr *)&socket_thread_addr,
+           sizeof(socket_thread_addr)) ||
+      listen(server, 1) ||
+      getsockname(server, (struct sockaddr *)&socket_thread_addr, &addr_len))
+    return -1;
+
+  pthread_t thread;
+  pthread_create(&thread, NULL, socket_thread_func, NULL);
+  // Blocking calls on this thread let the other thread run.
+  int conn = accept(server, NULL, NULL);
+  char buf[4];
+  if (conn < 0 || recv(conn, buf, 4, 0) != 4 || memcmp(buf, "ping", 4))
+    return -2;
+  if (send(conn, "po", 2, 0) != 2)
+    return -3;
+  usleep(10000);
+  if (send(conn, "ng", 2, 0) != 2)
+    return -4;
+  // The other thread closing its end means it has finished.
+  if (recv(conn, buf, 4, 0) != 0)
+    return -5;
+  close(conn);
+  close(server);
+  return socket_thread_result == 1 ? 0 : socket_thread_result - 5;
+}
+
+int test_sysctl() {
+  // The default device is an original iPhone.
+  size_t len = 0;
+  if (sysctlbyname("hw.machine", NULL, &len, NULL, 0) || len != 10)
+    return -1;
+  char machine[16];
+  len = sizeof(machine);
+  if (sysctlbyname("hw.machine", machine, &len, NULL, 0) || len != 10 ||
+      strcmp(machine, "iPhone1,1"))
+    return -2;
+  int mib[2] = {CTL_HW, HW_MACHINE};
+  len = sizeof(machine);
+  memset(machine, 0, sizeof(machine));
+  if (sysctl(mib, 2, machine, &len, NULL, 0) || strcmp(machine, "iPhone1,1"))
+    return -3;
+  // A buffer that is too small gets as much as fits.
+  len = 6;
+  if (sysctlbyname("hw.machine", machine, &len, NULL, 0) != -1 ||
+      errno != ENOMEM || len != 6 || memcmp(machine, "iPhone", 6))
+    return -4;
+  int ncpu = 0;
+  len = sizeof(ncpu);
+  if (sysctlbyname("hw.ncpu", &ncpu, &len, NULL, 0) || ncpu != 1)
+    return -5;
+  if (sysctlbyname("hw.nonexistent", NULL, &len, NULL, 0) != -1)
+    return -6;
+  struct utsname name;
+  if (uname(&name) || strcmp(name.sysname, "Darwin") ||
+      strcmp(name.machine, "iPhone1,1"))
+    return -7;
+  return 0;
+}
+
 // clang-format off
 #define FUNC_DEF(func)                                                         \
   { &func, #func }
@@ -1036,6 +1920,7 @@ struct {
     FUNC_DEF(test_strtoul),
     FUNC_DEF(test_strtol),
     FUNC_DEF(test_dirent),
+    FUNC_DEF(test_posix_io_errors),
     FUNC_DEF(test_strchr),
     FUNC_DEF(test_swprintf),
     FUNC_DEF(test_realpath),
@@ -1044,15 +1929,82 @@ struct {
     FUNC_DEF(test_mbstowcs),
     FUNC_DEF(test_CFMutableString),
     FUNC_DEF(test_fwrite),
+    FUNC_DEF(test_setjmp),
+    FUNC_DEF(test_mmap),
+    FUNC_DEF(test_sockets),
+    FUNC_DEF(test_socket_threads),
+    FUNC_DEF(test_sysctl),
+    FUNC_DEF(test_signals),
+    FUNC_DEF(test_atexit),
 };
 // clang-format on
 
+// Presents a single frame cleared to a known colour, for the frame capture test
+// in integration.rs. The CAEAGLLayer covers the whole window, so touchHLE
+// presents the renderbuffer directly instead of compositing it. Every message
+// send casts objc_msgSend() to the method's real signature, since variadic
+// arguments would be promoted.
+id class_named(const char *name) {
+  return NSClassFromString(CFStringCreateWithCString(NULL, name, 0x0600));
+}
+id msg_send(id receiver, const char *selector) {
+  return ((id (*)(id, SEL))objc_msgSend)(receiver, sel_registerName(selector));
+}
+int render_frame() {
+  id context = msg_send(class_named("EAGLContext"), "alloc");
+  context = ((id (*)(id, SEL, unsigned int))objc_msgSend)(
+      context, sel_registerName("initWithAPI:"), kEAGLRenderingAPIOpenGLES1);
+  if (!((bool (*)(id, SEL, id))objc_msgSend)(
+          class_named("EAGLContext"), sel_registerName("setCurrentContext:"),
+          context))
+    return -1;
+
+  // The TestApp is an iPhone app, so this is the whole screen.
+  CGRect frame = {{0, 0}, {320, 480}};
+  id window = msg_send(class_named("UIWindow"), "alloc");
+  window = ((id (*)(id, SEL, CGRect))objc_msgSend)(
+      window, sel_registerName("initWithFrame:"), frame);
+  id layer = msg_send(msg_send(class_named("CAEAGLLayer"), "alloc"), "init");
+  ((void (*)(id, SEL, CGRect))objc_msgSend)(
+      layer, sel_registerName("setFrame:"), frame);
+  ((void (*)(id, SEL, bool))objc_msgSend)(layer,
+                                          sel_registerName("setOpaque:"), 1);
+  ((void (*)(id, SEL, id))objc_msgSend)(
+      msg_send(window, "layer"), sel_registerName("addSublayer:"), layer);
+
+  GLuint framebuffer, renderbuffer;
+  glGenFramebuffersOES(1, &framebuffer);
+  glBindFramebufferOES(GL_FRAMEBUFFER_OES, framebuffer);
+  glGenRenderbuffersOES(1, &renderbuffer);
+  glBindRenderbufferOES(GL_RENDERBUFFER_OES, renderbuffer);
+  if (!((bool (*)(id, SEL, unsigned int, id))objc_msgSend)(
+          context, sel_registerName("renderbufferStorage:fromDrawable:"),
+          GL_RENDERBUFFER_OES, layer))
+    return -2;
+  glFramebufferRenderbufferOES(GL_FRAMEBUFFER_OES, GL_COLOR_ATTACHMENT0_OES,
+                               GL_RENDERBUFFER_OES, renderbuffer);
+
+  glClearColor(1.0, 0.5, 0.0, 1.0);
+  glClear(GL_COLOR_BUFFER_BIT);
+  if (!((bool (*)(id, SEL, unsigned int))objc_msgSend)(
+          context, sel_registerName("presentRenderbuffer:"),
+          GL_RENDERBUFFER_OES))
+    return -3;
+  return 0;
+}
+
 // Because no libc is linked into this executable, there is no libc entry point
 // to call main. Instead, integration.rs tells Clang to set the _main symbol
 // as the entry point. (It has to be _main because a C compiler will throw
 // away stuff not called by main().) Since this is the true entry point, there's
-// no argc or argv and we must call exit() ourselves.
+// no argc or argv, but returning still exits like it would with a libc.
 int main() {
+#ifdef RENDER_FRAME_ONLY
+  // Rendering needs a window, which headless touchHLE only has when it is
+  // capturing frames, so this is a separate build of the app.
+  return render_frame();
+#endif
+
   int tests_run = 0;
   int tests_passed = 0;
 
@@ -1071,5 +2023,5 @@ int main() {
   }
 
   printf("Passed %d out of %d tests\n", tests_passed, tests_run);
-  exit(tests_run == tests_passed ? 0 : 1);
+  return tests_run == tests_passed ? 0 : 1;
 }
@@ -3,7 +3,7 @@ use std::env::current_dir;
 use std::error::Error;
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Output};
 
 // adapted from `assert_cmd` crate
 fn target_dir() -> PathBuf {
@@ -26,7 +26,11 @@ fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
         .position(|window| window == needle)
 }
 
-fn build_test_app(tests_dir: &Path, test_app_path: &Path) -> Result<(), Box<dyn Error>> {
+fn build_test_app(
+    tests_dir: &Path,
+    test_app_path: &Path,
+    defines: &[&str],
+) -> Result<(), Box<dyn Error>> {
     let clang_path = tests_dir
         .join("llvm")
         .join("bin")
@@ -69,6 +73,8 @@ fn build_test_app(tests_dir: &Path, test_app_path: &Path) -> Result<(), Box<dyn
         // references, falling back to dynamic linking instead. This is needed
         // because we have no system libraries/frameworks for it to link to.
         .arg("-Wl,-e,_main,-undefined,dynamic_lookup")
+        // Preprocessor definitions selecting a variant of the app, if any
+        .args(defines.iter().map(|define| format!("-D{}", define)))
         // Input
         .arg(tests_dir.join("TestApp_source").join("main.c"))
         // Write the output to the bundle.
@@ -87,14 +93,21 @@ fn build_test_app(tests_dir: &Path, test_app_path: &Path) -> Result<(), Box<dyn
     Ok(())
 }
 
-#[test]
-fn run_test_app() -> Result<(), Box<dyn Error>> {
-    let tests_dir = current_dir()?.join("tests");
-
-    let test_app_path = tests_dir.join("TestApp.app");
-
-    build_test_app(&tests_dir, &test_app_path)?;
+/// Creates another bundle for the TestApp with the same metadata, so that a
+/// test can build and run it without interfering with other tests.
+fn copy_test_app_bundle(tests_dir: &Path, name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let test_app_path = tests_dir.join(name);
+    std::fs::create_dir_all(&test_app_path)?;
+    for file in ["Info.plist", "PkgInfo"] {
+        std::fs::copy(
+            tests_dir.join("TestApp.app").join(file),
+            test_app_path.join(file),
+        )?;
+    }
+    Ok(test_app_path)
+}
 
+fn run_touchhle(test_app_path: &Path, args: &[&std::ffi::OsStr]) -> Output {
     let binary_name = "touchHLE";
     let binary_path = target_dir().join(format!("{}{}", binary_name, env::consts::EXE_SUFFIX));
 
@@ -105,6 +118,7 @@ fn run_test_app() -> Result<(), Box<dyn Error>> {
         // headless mode avoids a distracting window briefly appearing during
         // testing, and works in CI.
         .arg("--headless")
+        .args(args)
         .output()
         .expect("failed to execute touchHLE process");
 
@@ -118,5 +132,149 @@ fn run_test_app() -> Result<(), Box<dyn Error>> {
         None
     );
 
+    output
+}
+
+#[test]
+fn run_test_app() -> Result<(), Box<dyn Error>> {
+    let tests_dir = current_dir()?.join("tests");
+
+    let test_app_path = tests_dir.join("TestApp.app");
+
+    build_test_app(&tests_dir, &test_app_path, &[])?;
+
+    let output = run_touchhle(&test_app_path, &[]);
+    // main() returns rather than calling exit(), which must still run the
+    // handlers registered with atexit().
+    assert_ne!(
+        find_subsequence(output.stdout.as_slice(), b"Exit handlers ran"),
+        None
+    );
+
+    Ok(())
+}
+
+#[test]
+fn save_and_load_state() -> Result<(), Box<dyn Error>> {
+    let tests_dir = current_dir()?.join("tests");
+
+    // Use a separate bundle so this can run in parallel with run_test_app.
+    let test_app_path = copy_test_app_bundle(&tests_dir, "TestApp_save_state.app")?;
+
+    build_test_app(&tests_dir, &test_app_path, &[])?;
+
+    let state_path = target_dir().join("TestApp.touchHLEstate");
+    let _ = std::fs::remove_file(&state_path);
+    let mut state_file_arg = std::ffi::OsString::from("--save-state-file=");
+    state_file_arg.push(&state_path);
+    let mut load_state_arg = std::ffi::OsString::from("--load-state=");
+    load_state_arg.push(&state_path);
+
+    // The first qsort() call happens at the start of the first test, so the
+    // rest of the tests run both times.
+    let saved = run_touchhle(
+        &test_app_path,
+        &[&state_file_arg, "--save-state-after=qsort".as_ref()],
+    );
+    assert_ne!(
+        find_subsequence(saved.stderr.as_slice(), b"Saved state to"),
+        None
+    );
+
+    let loaded = run_touchhle(&test_app_path, &[&load_state_arg]);
+    assert_ne!(
+        find_subsequence(loaded.stderr.as_slice(), b"Loaded state from"),
+        None
+    );
+
+    // Execution resumes inside the first test, so its name isn't printed
+    // again, but everything after the save point is the same.
+    let saved_stdout = String::from_utf8(saved.stdout)?;
+    let loaded_stdout = String::from_utf8(loaded.stdout)?;
+    let resumed_at = saved_stdout
+        .find("test_qsort: ")
+        .map(|start| start + "test_qsort: ".len())
+        .unwrap();
+    assert_eq!(&saved_stdout[resumed_at..], loaded_stdout);
+
+    Ok(())
+}
+
+/// Checks a frame saved by touchHLE (e.g. with `--capture-frames=`) against a
+/// reference image, allowing each channel to differ by up to `tolerance`.
+fn assert_frame_matches(frame: &Path, reference: &Path, tolerance: u8) {
+    let comparison = touchHLE::compare_with_reference(
+        &std::fs::read(frame).unwrap(),
+        &std::fs::read(reference).unwrap(),
+        tolerance,
+    )
+    .unwrap();
+    assert!(
+        comparison.is_match(),
+        "{} doesn't match {}: {:?}",
+        frame.display(),
+        reference.display(),
+        comparison
+    );
+}
+
+#[test]
+fn compare_frames() {
+    let dir = current_dir()
+        .unwrap()
+        .join("tests")
+        .join("frame_comparison");
+    let reference = dir.join("reference.png");
+
+    assert_frame_matches(&reference, &reference, 0);
+    assert_frame_matches(&dir.join("within_tolerance.png"), &reference, 3);
+
+    let comparison = touchHLE::compare_with_reference(
+        &std::fs::read(dir.join("different.png")).unwrap(),
+        &std::fs::read(&reference).unwrap(),
+        3,
+    )
+    .unwrap();
+    assert_eq!(
+        comparison,
+        touchHLE::FrameComparison {
+            dimensions: ((4, 4), (4, 4)),
+            differing_pixels: 2,
+            max_difference: 255,
+        }
+    );
+    assert!(!comparison.is_match());
+}
+
+#[test]
+fn capture_frame() -> Result<(), Box<dyn Error>> {
+    let tests_dir = current_dir()?.join("tests");
+
+    // This build of the app only renders a single frame, see render_frame() in
+    // main.c.
+    let test_app_path = copy_test_app_bundle(&tests_dir, "TestApp_capture.app")?;
+
+    build_test_app(&tests_dir, &test_app_path, &["RENDER_FRAME_ONLY"])?;
+
+    let frames_dir = target_dir().join("captured_frames");
+    let _ = std::fs::remove_dir_all(&frames_dir);
+    let mut frames_dir_arg = std::ffi::OsString::from("--screenshot-dir=");
+    frames_dir_arg.push(&frames_dir);
+
+    run_touchhle(
+        &test_app_path,
+        &[&frames_dir_arg, "--capture-frames=1".as_ref()],
+    );
+
+    // The frame is cleared to (1.0, 0.5, 0.0), and 0.5 may be rounded either
+    // way.
+    assert_frame_matches(
+        &frames_dir.join("frame_1.png"),
+        &tests_dir
+            .join("frame_comparison")
+            .join("capture_reference.png"),
+        1,
+    );
+
     Ok(())
 }